@@ -194,6 +194,9 @@ pub fn audio_node_create(audio_processor_name: String) -> Result<u32> {
         "pan" => Ok(NodeType::Simple(Box::<
             audio_processor_utility::pan::PanProcessor<f32>,
         >::default())),
+        "waveshaper" => Ok(NodeType::Simple(Box::<
+            audio_processor_waveshaper::WaveshaperProcessor,
+        >::default())),
         _ => Err(anyhow::Error::msg("Failed to create processor")),
     };
     let processor = processor?;
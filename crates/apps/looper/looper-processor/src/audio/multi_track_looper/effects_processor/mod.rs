@@ -27,7 +27,9 @@ use basedrop::{Shared, SharedCell};
 
 use audio_garbage_collector::{make_shared, make_shared_cell};
 use audio_processor_bitcrusher::BitCrusherProcessor;
+use audio_processor_convolution::{ConvolutionProcessor, ImpulseResponse, ImpulseResponseError};
 use audio_processor_graph::{AudioProcessorGraph, AudioProcessorGraphHandle, NodeIndex, NodeType};
+use audio_processor_time::mod_reverb::ModReverbProcessor;
 use audio_processor_time::FreeverbProcessor;
 use audio_processor_time::MonoDelayProcessor;
 use audio_processor_traits::parameters::{AudioProcessorHandleProvider, AudioProcessorHandleRef};
@@ -35,6 +37,7 @@ use audio_processor_traits::simple_processor::MonoCopyProcessor;
 use audio_processor_traits::{
     simple_processor, AudioBuffer, AudioContext, AudioProcessor, AudioProcessorSettings,
 };
+use audio_processor_waveshaper::WaveshaperProcessor;
 use augmented_dsp_filters::rbj::{FilterProcessor, FilterType};
 
 type SomeEffectProcessor = Box<dyn AudioProcessor<SampleType = f32> + Send + 'static>;
@@ -48,6 +51,9 @@ pub enum EffectType {
     EffectTypeDelay = 1,
     EffectTypeFilter = 2,
     EffectTypeBitCrusher = 3,
+    EffectTypeModReverb = 4,
+    EffectTypeWaveshaper = 5,
+    EffectTypeConvolution = 6,
 }
 
 #[derive(Clone)]
@@ -63,9 +69,15 @@ pub struct EffectsProcessorHandle {
     settings: SharedCell<AudioProcessorSettings>,
 }
 
+/// Impulse response for convolution effects added before an IR file is loaded, it passes the
+/// signal through unchanged
+pub fn unit_impulse_response() -> ImpulseResponse {
+    ImpulseResponse::new(44100.0, vec![vec![1.0]]).expect("Unit impulse response is valid")
+}
+
 impl EffectsProcessorHandle {
     pub fn add_effect(&self, effect: EffectType) {
-        let (mut processor, handle): (SomeEffectProcessor, AudioProcessorHandleRef) = {
+        let (processor, handle): (SomeEffectProcessor, AudioProcessorHandleRef) = {
             use EffectType::*;
 
             let (effect, handle): (SomeEffectProcessor, SomeHandle) = match effect {
@@ -95,11 +107,38 @@ impl EffectsProcessorHandle {
                     let handle = AudioProcessorHandleProvider::generic_handle(&processor);
                     (Box::new(processor), handle)
                 }
+                EffectTypeModReverb => {
+                    let processor = ModReverbProcessor::default();
+                    let handle = processor.generic_handle();
+                    (Box::new(processor), handle)
+                }
+                EffectTypeWaveshaper => {
+                    let processor = WaveshaperProcessor::default();
+                    let handle = processor.generic_handle();
+                    (Box::new(processor), handle)
+                }
+                EffectTypeConvolution => {
+                    let processor = ConvolutionProcessor::new(unit_impulse_response());
+                    let handle = processor.generic_handle();
+                    (Box::new(processor), handle)
+                }
             };
 
             (effect, handle)
         };
 
+        self.add_node(processor, handle);
+    }
+
+    /// Read a room or cabinet impulse response file and add a convolution effect playing it
+    pub fn add_impulse_response_file(&self, path: &str) -> Result<(), ImpulseResponseError> {
+        let processor = ConvolutionProcessor::new(ImpulseResponse::from_path(path)?);
+        let handle = processor.generic_handle();
+        self.add_node(Box::new(processor), handle);
+        Ok(())
+    }
+
+    fn add_node(&self, mut processor: SomeEffectProcessor, handle: AudioProcessorHandleRef) {
         let settings = *self.settings.get().deref();
         let mut context = AudioContext::from(settings);
         processor.prepare(&mut context);
@@ -187,3 +226,46 @@ impl AudioProcessor for EffectsProcessor {
         self.graph.process(context, data)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_impulse_response_file() {
+        let directory = tempdir::TempDir::new("looper_processor__effects").unwrap();
+        let path = directory.path().join("room.wav");
+        let mut writer = hound::WavWriter::create(
+            &path,
+            hound::WavSpec {
+                channels: 1,
+                sample_rate: 44100,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            },
+        )
+        .unwrap();
+        for sample in [1.0, 0.5, 0.25] {
+            writer.write_sample(sample as f32).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let processor = EffectsProcessor::new();
+        let handle = processor.handle();
+        handle
+            .add_impulse_response_file(path.to_str().unwrap())
+            .unwrap();
+        assert_eq!(handle.effects.get().len(), 1);
+        assert_eq!(handle.effects.get()[0].handle.name(), "Convolution");
+    }
+
+    #[test]
+    fn test_add_missing_impulse_response_file() {
+        let processor = EffectsProcessor::new();
+        let handle = processor.handle();
+        assert!(handle
+            .add_impulse_response_file("/missing/impulse_response.wav")
+            .is_err());
+        assert!(handle.effects.get().is_empty());
+    }
+}
@@ -29,7 +29,7 @@ use audio_garbage_collector::make_shared;
 use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
 
 pub struct EnvelopeHandle {
-    pub adsr_envelope: augmented_adsr_envelope::Envelope,
+    pub adsr_envelope: augmented_adsr_envelope::MultiStageEnvelope,
     pub enabled: AtomicBool,
 }
 
@@ -39,7 +39,7 @@ pub struct EnvelopeProcessor {
 
 impl Default for EnvelopeProcessor {
     fn default() -> Self {
-        let envelope = augmented_adsr_envelope::Envelope::new();
+        let envelope = augmented_adsr_envelope::MultiStageEnvelope::default();
         envelope.set_attack(Duration::from_secs_f32(0.0));
         envelope.set_decay(Duration::from_secs_f32(0.0));
         envelope.set_sustain(1.0);
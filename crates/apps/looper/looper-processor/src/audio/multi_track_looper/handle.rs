@@ -29,6 +29,7 @@ use num::ToPrimitive;
 
 use audio_garbage_collector::{make_shared, make_shared_cell, Shared};
 use audio_processor_analysis::running_rms_processor::RunningRMSProcessorHandle;
+use audio_processor_analysis::transient_detection::stft::markers::AudioFileMarker;
 use audio_processor_metronome::MetronomeProcessorHandle;
 use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
 use augmented_atomics::{AtomicF32, AtomicValue};
@@ -37,7 +38,7 @@ use crate::audio::multi_track_looper::midi_store::MidiStoreHandle;
 use crate::audio::multi_track_looper::scene_state::SceneHandle;
 use crate::audio::multi_track_looper::track_events_worker::TrackEventsBus;
 use crate::audio::processor::handle::{LooperHandleThread, LooperState, ToggleRecordingResult};
-use crate::parameters::LFOMode;
+use crate::parameters::{LFODivision, LFOMode, LFORetriggerMode};
 use crate::{QuantizeMode, TimeInfoProvider, TimeInfoProviderImpl};
 
 use super::looper_voice::LooperVoice;
@@ -378,6 +379,31 @@ impl MultiTrackLooperHandle {
         }
     }
 
+    pub fn set_lfo_division(&self, looper_id: LooperId, lfo: usize, division: LFODivision) {
+        if let Some(voice) = self.voices.get(looper_id.0) {
+            Self::update_parameter_table(
+                voice,
+                ParameterId::ParameterIdLFO(lfo, LFOParameter::LFOParameterDivision),
+                ParameterValue::Enum(AtomicUsize::new(division.into())),
+            )
+        }
+    }
+
+    pub fn set_lfo_retrigger_mode(
+        &self,
+        looper_id: LooperId,
+        lfo: usize,
+        retrigger_mode: LFORetriggerMode,
+    ) {
+        if let Some(voice) = self.voices.get(looper_id.0) {
+            Self::update_parameter_table(
+                voice,
+                ParameterId::ParameterIdLFO(lfo, LFOParameter::LFOParameterRetrigger),
+                ParameterValue::Enum(AtomicUsize::new(retrigger_mode.into())),
+            )
+        }
+    }
+
     pub fn set_quantization_mode(&self, looper_id: LooperId, mode: CQuantizeMode) {
         if let Some(voice) = self.voices.get(looper_id.0) {
             voice.looper().quantize_options().set_mode(match mode {
@@ -474,6 +500,17 @@ impl MultiTrackLooperHandle {
         self.slice_worker.result(looper_id.0)
     }
 
+    /// Slice the looper at these positions in samples, instead of at detected transients
+    pub fn set_looper_slices(&self, looper_id: LooperId, positions: &[usize]) {
+        let markers = positions
+            .iter()
+            .map(|position| AudioFileMarker {
+                position_samples: *position,
+            })
+            .collect();
+        self.slice_worker.set_result(looper_id.0, markers);
+    }
+
     #[allow(clippy::single_match, clippy::collapsible_match)]
     pub fn set_boolean_parameter(
         &self,
@@ -487,6 +524,9 @@ impl MultiTrackLooperHandle {
                     SourceParameter::LoopEnabled => {
                         voice.looper().set_loop_enabled(value);
                     }
+                    SourceParameter::TimeStretchEnabled => {
+                        voice.looper().set_time_stretch_enabled(value);
+                    }
                     _ => {}
                 },
                 ParameterId::ParameterIdEnvelope(parameter) => match parameter {
@@ -25,7 +25,9 @@ use serde::{Deserialize, Serialize};
 
 use augmented_atomics::AtomicF32;
 
-use crate::parameters::{build_default_parameters, build_parameter_indexes, ParameterId};
+use crate::parameters::{
+    build_default_parameters, build_parameter_indexes, smooth_step, LFOMode, ParameterId,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LFOHandleMap {
@@ -98,11 +100,110 @@ impl LFOHandle {
     }
 }
 
+/// Audio-thread state of a looper LFO.
+///
+/// The phase offset shifts the whole waveform, retriggering moves the phase back to the offset.
+/// Random shapes draw a new value every time the phase wraps.
+pub struct LFOOscillator {
+    mode: LFOMode,
+    sample_rate: f32,
+    frequency: f32,
+    phase: f32,
+    phase_offset: f32,
+    random_seed: u32,
+    previous_random: f32,
+    current_random: f32,
+}
+
+impl LFOOscillator {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut lfo = Self {
+            mode: LFOMode::LFOModeSine,
+            sample_rate,
+            frequency: 1.0,
+            phase: 0.0,
+            phase_offset: 0.0,
+            random_seed: 0x9E37_79B9,
+            previous_random: 0.0,
+            current_random: 0.0,
+        };
+        lfo.next_random();
+        lfo.next_random();
+        lfo
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    #[cfg(test)]
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_mode(&mut self, mode: LFOMode) {
+        self.mode = mode;
+    }
+
+    #[cfg(test)]
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Set the phase offset as a fraction of a cycle, the current phase moves with it
+    pub fn set_phase_offset(&mut self, phase_offset: f32) {
+        let phase_offset = phase_offset.rem_euclid(1.0);
+        if (phase_offset - self.phase_offset).abs() < f32::EPSILON {
+            return;
+        }
+        self.phase = (self.phase - self.phase_offset + phase_offset).rem_euclid(1.0);
+        self.phase_offset = phase_offset;
+    }
+
+    /// Restart the cycle from the phase offset
+    pub fn retrigger(&mut self) {
+        self.phase = self.phase_offset;
+    }
+
+    pub fn get(&self) -> f32 {
+        match self.mode {
+            LFOMode::LFOModeSampleAndHold => self.current_random,
+            LFOMode::LFOModeSmoothRandom => {
+                smooth_step(self.previous_random, self.current_random, self.phase)
+            }
+            _ => (self.mode.generator_fn())(self.phase),
+        }
+    }
+
+    pub fn tick_n(&mut self, num_samples: f32) {
+        self.phase += num_samples * self.frequency / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            self.next_random();
+        }
+    }
+
+    /// xorshift32, good enough for modulation and allocation free
+    fn next_random(&mut self) {
+        let mut x = self.random_seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_seed = x;
+        self.previous_random = self.current_random;
+        self.current_random = (x as f32 / u32::MAX as f32) * 2.0 - 1.0;
+    }
+}
+
 #[cfg(test)]
 mod test {
     use audio_processor_testing_helpers::assert_f_eq;
 
-    use crate::parameters::SourceParameter;
+    use crate::parameters::{LFODivision, SourceParameter};
 
     use super::*;
 
@@ -149,4 +250,75 @@ mod test {
         let amount = handle.modulation_amount(&SourceParameter::Start.into());
         assert_f_eq!(amount, 0.0);
     }
+
+    #[test]
+    fn test_lfo_oscillator_phase_offset_shifts_waveform() {
+        let mut lfo = LFOOscillator::new(100.0);
+        lfo.set_mode(LFOMode::LFOModeTriangle);
+        assert_f_eq!(lfo.get(), -1.0);
+        lfo.set_phase_offset(0.5);
+        assert_f_eq!(lfo.get(), 1.0);
+        lfo.tick_n(25.0);
+        assert_f_eq!(lfo.phase(), 0.75);
+        assert_f_eq!(lfo.get(), 0.0);
+    }
+
+    #[test]
+    fn test_lfo_oscillator_retrigger_resets_to_offset() {
+        let mut lfo = LFOOscillator::new(100.0);
+        lfo.set_mode(LFOMode::LFOModeSawUp);
+        lfo.set_phase_offset(0.25);
+        lfo.tick_n(40.0);
+        assert_f_eq!(lfo.phase(), 0.65);
+        lfo.retrigger();
+        assert_f_eq!(lfo.phase(), 0.25);
+        assert_f_eq!(lfo.get(), -0.5);
+    }
+
+    #[test]
+    fn test_lfo_oscillator_sample_and_hold_changes_once_per_cycle() {
+        let mut lfo = LFOOscillator::new(100.0);
+        lfo.set_mode(LFOMode::LFOModeSampleAndHold);
+        let first = lfo.get();
+        assert!((-1.0..=1.0).contains(&first));
+        for _ in 0..9 {
+            lfo.tick_n(10.0);
+            assert_f_eq!(lfo.get(), first);
+        }
+        lfo.tick_n(10.0);
+        assert!((lfo.get() - first).abs() > f32::EPSILON);
+    }
+
+    #[test]
+    fn test_lfo_oscillator_smooth_random_is_continuous() {
+        let mut lfo = LFOOscillator::new(100.0);
+        lfo.set_mode(LFOMode::LFOModeSmoothRandom);
+        let mut previous = lfo.get();
+        for _ in 0..1000 {
+            lfo.tick_n(1.0);
+            let value = lfo.get();
+            assert!((-1.0..=1.0).contains(&value));
+            assert!((value - previous).abs() < 0.1);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn test_division_frequency() {
+        assert!(LFODivision::LFODivisionFree.frequency(120.0).is_none());
+        assert_f_eq!(
+            LFODivision::LFODivisionQuarter.frequency(120.0).unwrap(),
+            2.0
+        );
+        assert_f_eq!(
+            LFODivision::LFODivisionOneBar.frequency(120.0).unwrap(),
+            0.5
+        );
+        assert_f_eq!(
+            LFODivision::LFODivisionTripletEighth
+                .frequency(120.0)
+                .unwrap(),
+            6.0
+        );
+    }
 }
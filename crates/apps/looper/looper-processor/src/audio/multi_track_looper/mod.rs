@@ -31,20 +31,19 @@ use rustc_hash::FxHashMap as HashMap;
 
 use audio_garbage_collector::{make_shared, Shared};
 use audio_processor_analysis::running_rms_processor::RunningRMSProcessor;
-use audio_processor_graph::{AudioProcessorGraph, NodeType};
+use audio_processor_graph::{AudioProcessorGraph, ConnectionIndex, NodeType};
 use audio_processor_metronome::MetronomeProcessor;
 use audio_processor_traits::{
     AudioBuffer, AudioContext, AudioProcessor, MidiEventHandler, MidiMessageLike,
 };
 use augmented_atomics::AtomicValue;
-use augmented_oscillator::Oscillator;
 
 use crate::audio::time_info_provider::TimeInfoMetronomePlayhead;
-use crate::parameters::LFOMode;
+use crate::parameters::{LFODivision, LFOMode, LFORetriggerMode};
 use crate::{LooperOptions, TimeInfoProvider, TimeInfoProviderImpl};
 
 pub use self::handle::MultiTrackLooperHandle;
-use self::lfo_processor::LFOHandle;
+use self::lfo_processor::{LFOHandle, LFOOscillator};
 use self::looper_voice::{LooperVoice, VoiceProcessors};
 use self::metrics::audio_processor_metrics::AudioProcessorMetrics;
 use self::midi_button::{MIDIButton, MIDIButtonEvent};
@@ -108,9 +107,13 @@ type ParametersScratchIndexes = HashMap<ParameterId, usize>;
 /// ```
 pub struct MultiTrackLooper {
     graph: AudioProcessorGraph,
+    /// Connection from each voice's effects into the output, used to read stems
+    voice_outputs: Vec<ConnectionIndex>,
     handle: Shared<MultiTrackLooperHandle>,
     step_trackers: Vec<StepTracker>,
-    lfos: Vec<(Oscillator<f32>, Oscillator<f32>)>,
+    lfos: Vec<(LFOOscillator, LFOOscillator)>,
+    /// Last looper playhead per voice, used to detect the loop wrapping around
+    lfo_playheads: Vec<usize>,
     metrics: AudioProcessorMetrics,
     parameters_scratch: ParametersScratch,
     parameter_scratch_indexes: ParametersScratchIndexes,
@@ -156,18 +159,22 @@ impl MultiTrackLooper {
         let step_trackers = processors.iter().map(|_| StepTracker::default()).collect();
         let lfos = processors
             .iter()
-            .map(|_| (Oscillator::sine(44100.0), Oscillator::sine(44100.0)))
+            .map(|_| (LFOOscillator::new(44100.0), LFOOscillator::new(44100.0)))
             .collect();
+        let lfo_playheads = processors.iter().map(|_| 0).collect();
 
-        let graph = Self::build_audio_graph(input_meter_processor, processors, metronome);
+        let (graph, voice_outputs) =
+            Self::build_audio_graph(input_meter_processor, processors, metronome);
 
         Self {
             graph,
+            voice_outputs,
             handle,
             step_trackers,
             parameters_scratch,
             parameter_scratch_indexes,
             lfos,
+            lfo_playheads,
             metrics,
             record_midi_button: MIDIButton::new(),
         }
@@ -218,15 +225,19 @@ impl MultiTrackLooper {
         let step_trackers = processors.iter().map(|_| StepTracker::default()).collect();
         let lfos = processors
             .iter()
-            .map(|_| (Oscillator::sine(44100.0), Oscillator::sine(44100.0)))
+            .map(|_| (LFOOscillator::new(44100.0), LFOOscillator::new(44100.0)))
             .collect();
-        let graph = Self::build_audio_graph(input_meter_processor, processors, metronome);
+        let lfo_playheads = processors.iter().map(|_| 0).collect();
+        let (graph, voice_outputs) =
+            Self::build_audio_graph(input_meter_processor, processors, metronome);
 
         Self {
             graph,
+            voice_outputs,
             handle,
             step_trackers,
             lfos,
+            lfo_playheads,
             metrics,
             parameters_scratch,
             parameter_scratch_indexes,
@@ -238,6 +249,15 @@ impl MultiTrackLooper {
         &self.handle
     }
 
+    /// Copy the processed output of a track, after its envelope & effects, from the last
+    /// `process` call into `output`. Returns `false` if the track doesn't exist.
+    pub fn copy_voice_output(&self, looper_id: LooperId, output: &mut AudioBuffer<f32>) -> bool {
+        self.voice_outputs
+            .get(looper_id.0)
+            .map(|connection| self.graph.copy_connection_buffer(*connection, output))
+            .unwrap_or(false)
+    }
+
     fn build_voices(
         options: &LooperOptions,
         num_voices: usize,
@@ -266,7 +286,7 @@ impl MultiTrackLooper {
         input_meter: RunningRMSProcessor,
         processors: Vec<VoiceProcessors>,
         metronome: MetronomeProcessor<TimeInfoMetronomePlayhead>,
-    ) -> AudioProcessorGraph {
+    ) -> (AudioProcessorGraph, Vec<ConnectionIndex>) {
         let mut graph = AudioProcessorGraph::default();
         let metronome_idx = graph.add_node(NodeType::Simple(Box::new(metronome)));
         let input_meter_node_idx = graph.add_node(NodeType::Simple(Box::new(input_meter)));
@@ -280,6 +300,7 @@ impl MultiTrackLooper {
             .add_connection(metronome_idx, graph.output())
             .expect("Shouldn't produce loop");
 
+        let mut voice_outputs = Vec::with_capacity(processors.len());
         for VoiceProcessors {
             looper,
             pitch_shifter,
@@ -304,12 +325,13 @@ impl MultiTrackLooper {
             graph
                 .add_connection(envelope_idx, effects_idx)
                 .expect("Shouldn't produce loop");
-            graph
+            let output_connection = graph
                 .add_connection(effects_idx, graph.output())
                 .expect("Shouldn't produce loop");
+            voice_outputs.push(output_connection);
         }
 
-        graph
+        (graph, voice_outputs)
     }
 }
 
@@ -326,14 +348,28 @@ impl MultiTrackLooper {
             let parameters_scratch = &mut self.parameters_scratch;
             let parameters_scratch_indexes = &self.parameter_scratch_indexes;
 
-            for (voice, step_tracker) in self.handle.voices().iter().zip(step_trackers) {
-                Self::process_triggers_for_voice(
+            for ((voice, step_tracker), (lfo1, lfo2)) in self
+                .handle
+                .voices()
+                .iter()
+                .zip(step_trackers)
+                .zip(self.lfos.iter_mut())
+            {
+                let triggered = Self::process_triggers_for_voice(
                     parameters_scratch_indexes,
                     parameters_scratch,
                     voice,
                     step_tracker,
                     position_beats,
-                )
+                );
+                if triggered {
+                    Self::retrigger_lfos(
+                        parameters_scratch_indexes,
+                        &parameters_scratch[voice.id],
+                        [lfo1, lfo2],
+                        LFORetriggerMode::LFORetriggerModeTrigger,
+                    );
+                }
             }
         }
     }
@@ -344,13 +380,14 @@ impl MultiTrackLooper {
         voice: &LooperVoice,
         step_tracker: &mut StepTracker,
         position_beats: f64,
-    ) {
+    ) -> bool {
         let triggers = voice.trigger_model();
 
         let triggers_vec = triggers.triggers();
-        if let Some(_trigger) =
+        let triggered =
             find_current_beat_trigger(triggers, &triggers_vec, step_tracker, position_beats)
-        {
+                .is_some();
+        if triggered {
             voice.looper().trigger();
             voice.envelope().adsr_envelope.note_on();
         }
@@ -371,29 +408,83 @@ impl MultiTrackLooper {
         if !has_triggers {
             voice.envelope().adsr_envelope.note_off();
         }
+
+        triggered
+    }
+}
+
+// Envelope handling
+impl MultiTrackLooper {
+    /// Envelopes may have segments with durations in beats, so they follow the global tempo
+    fn sync_envelope_tempo(&self) {
+        let time_info = self.handle.time_info_provider().get_time_info();
+        if let Some(tempo) = time_info.tempo() {
+            for voice in self.handle.voices() {
+                voice.envelope().adsr_envelope.set_tempo(tempo as f32);
+            }
+        }
     }
 }
 
 // LFOs handling
 impl MultiTrackLooper {
     fn process_lfos(&mut self) {
+        let tempo = self.handle.time_info_provider().get_time_info().tempo();
         let parameters_scratch = &mut self.parameters_scratch;
         let parameters_scratch_indexes = &self.parameter_scratch_indexes;
-        for ((lfo1, lfo2), voice) in self.lfos.iter_mut().zip(self.handle.voices().iter()) {
+        for (((lfo1, lfo2), last_playhead), voice) in self
+            .lfos
+            .iter_mut()
+            .zip(self.lfo_playheads.iter_mut())
+            .zip(self.handle.voices().iter())
+        {
+            let playhead = voice.looper().playhead();
+            let looped = voice.looper().is_playing_back() && playhead < *last_playhead;
+            *last_playhead = playhead;
+            if looped {
+                Self::retrigger_lfos(
+                    parameters_scratch_indexes,
+                    &parameters_scratch[voice.id],
+                    [&mut *lfo1, &mut *lfo2],
+                    LFORetriggerMode::LFORetriggerModeLoopStart,
+                );
+            }
+
             Self::process_lfos_for_voice(
                 parameters_scratch_indexes,
                 parameters_scratch,
                 &mut [(lfo1, voice.lfo1()), (lfo2, voice.lfo2())],
                 &voice,
-            )
+                tempo,
+            );
+        }
+    }
+
+    /// Reset the LFOs of a voice whose retrigger mode matches `event`
+    fn retrigger_lfos(
+        parameter_scratch_indexes: &ParametersScratchIndexes,
+        scratch: &[ParameterValue],
+        mut lfos: [&mut LFOOscillator; 2],
+        event: LFORetriggerMode,
+    ) {
+        for (lfo_index, lfo) in lfos.iter_mut().enumerate() {
+            let retrigger_idx =
+                ParameterId::ParameterIdLFO(lfo_index, LFOParameter::LFOParameterRetrigger);
+            let retrigger_idx = parameter_scratch_indexes[&retrigger_idx];
+            let retrigger_mode = LFORetriggerMode::try_from(scratch[retrigger_idx].as_enum())
+                .unwrap_or(LFORetriggerMode::LFORetriggerModeFree);
+            if retrigger_mode == event {
+                lfo.retrigger();
+            }
         }
     }
 
     fn process_lfos_for_voice(
         parameter_scratch_indexes: &ParametersScratchIndexes,
         parameters_scratch: &mut ParametersScratch,
-        lfos: &mut [(&mut Oscillator<f32>, &LFOHandle)],
+        lfos: &mut [(&mut LFOOscillator, &LFOHandle)],
         voice: &&LooperVoice,
+        tempo: Option<f64>,
     ) {
         for (lfo_index, (lfo_osc, lfo_handle)) in lfos.iter_mut().enumerate() {
             let freq_idx =
@@ -405,14 +496,24 @@ impl MultiTrackLooper {
             let lfo_mode_idx =
                 ParameterId::ParameterIdLFO(lfo_index, LFOParameter::LFOParameterMode);
             let lfo_mode_idx = parameter_scratch_indexes[&lfo_mode_idx];
+            let division_idx =
+                ParameterId::ParameterIdLFO(lfo_index, LFOParameter::LFOParameterDivision);
+            let division_idx = parameter_scratch_indexes[&division_idx];
+            let phase_idx = ParameterId::ParameterIdLFO(lfo_index, LFOParameter::LFOParameterPhase);
+            let phase_idx = parameter_scratch_indexes[&phase_idx];
 
             let scratch = &mut parameters_scratch[voice.id];
 
-            let freq = scratch[freq_idx].as_float();
+            let division = LFODivision::try_from(scratch[division_idx].as_enum())
+                .unwrap_or(LFODivision::LFODivisionFree);
+            let freq = tempo
+                .and_then(|tempo| division.frequency(tempo))
+                .unwrap_or_else(|| scratch[freq_idx].as_float());
             lfo_osc.set_frequency(freq);
             let lfo_mode: LFOMode =
                 LFOMode::try_from(scratch[lfo_mode_idx].as_enum()).unwrap_or(LFOMode::LFOModeSine);
-            lfo_osc.set_generator(lfo_mode.generator_fn());
+            lfo_osc.set_mode(lfo_mode);
+            lfo_osc.set_phase_offset(scratch[phase_idx].as_float());
 
             let global_amount = scratch[amount_idx].as_float();
 
@@ -563,6 +664,7 @@ impl AudioProcessor for MultiTrackLooper {
             self.process_triggers();
             self.process_lfos();
             self.flush_parameters();
+            self.sync_envelope_tempo();
 
             self.graph.process(context, data);
 
@@ -658,6 +760,18 @@ mod test {
         assert_f_eq!(looper.handle.voices()[0].looper().speed(), 2.0);
     }
 
+    #[test]
+    fn test_time_stretch_parameter_is_respected() {
+        let looper = MultiTrackLooper::default();
+        assert!(!looper.handle.voices()[0].looper().time_stretch_enabled());
+        looper.handle.set_boolean_parameter(
+            LooperId(0),
+            SourceParameter::TimeStretchEnabled.into(),
+            true,
+        );
+        assert!(looper.handle.voices()[0].looper().time_stretch_enabled());
+    }
+
     #[test]
     fn test_scenes_are_respected() {
         let mut looper = MultiTrackLooper::default();
@@ -771,6 +885,43 @@ mod test {
         assert_eq!(buffer.channel(0), [1.0, 2.0, 3.0, 4.0])
     }
 
+    #[test]
+    fn test_set_looper_slices_moves_the_start_with_the_slice_id() {
+        let mut processor = MultiTrackLooper::new(Default::default(), 1);
+        let mut settings = AudioProcessorSettings::default();
+        settings.sample_rate = 100.0;
+        settings.input_channels = 1;
+        settings.output_channels = 1;
+        let mut context = AudioContext::from(settings);
+        processor.prepare(&mut context);
+
+        let handle = processor.handle().clone();
+        let voice = &handle.voices()[0];
+        voice
+            .looper()
+            .set_looper_buffer(&AudioBuffer::from_interleaved(
+                1,
+                &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0],
+            ));
+        handle.set_looper_slices(LooperId(0), &[0, 2, 6]);
+        let slices = handle.get_looper_slices(LooperId(0)).unwrap();
+        assert_eq!(slices.markers().len(), 3);
+
+        voice
+            .user_parameters()
+            .set(SourceParameter::SliceEnabled, true);
+        handle.set_int_parameter(LooperId(0), SourceParameter::SliceId.into(), 2);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize_with(1, 4, || 0.0);
+        processor.process(&mut context, &mut buffer);
+
+        voice.looper().play();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize_with(1, 4, || 0.0);
+        processor.process(&mut context, &mut buffer);
+        assert_eq!(buffer.channel(0), [7.0, 8.0, 7.0, 8.0])
+    }
+
     #[test]
     fn test_we_can_set_start_on_a_looper() {
         let mut processor = MultiTrackLooper::default();
@@ -1037,6 +1188,53 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_lfo_rate_follows_tempo_division() {
+        let mut looper = MultiTrackLooper::new(Default::default(), 1);
+        looper.handle().set_tempo(120.0);
+        looper
+            .handle()
+            .set_lfo_division(LooperId(0), 0, LFODivision::LFODivisionQuarter);
+
+        looper.process_scenes();
+        looper.process_lfos();
+        assert_f_eq!(looper.lfos[0].0.frequency(), 2.0);
+        assert_f_eq!(looper.lfos[0].1.frequency(), 1.0);
+
+        looper.handle().set_tempo(90.0);
+        looper.process_scenes();
+        looper.process_lfos();
+        assert_f_eq!(looper.lfos[0].0.frequency(), 1.5);
+    }
+
+    #[test]
+    fn test_lfo_retrigger_resets_matching_lfos() {
+        let mut looper = MultiTrackLooper::new(Default::default(), 1);
+        looper.handle().set_lfo_retrigger_mode(
+            LooperId(0),
+            1,
+            LFORetriggerMode::LFORetriggerModeTrigger,
+        );
+        looper
+            .handle()
+            .set_lfo_parameter(LooperId(0), 1, LFOParameter::LFOParameterPhase, 0.25);
+        looper.process_scenes();
+        looper.process_lfos();
+        looper.tick_lfos(11025.0);
+        assert_f_eq!(looper.lfos[0].0.phase(), 0.25);
+        assert_f_eq!(looper.lfos[0].1.phase(), 0.5);
+
+        let (lfo1, lfo2) = &mut looper.lfos[0];
+        MultiTrackLooper::retrigger_lfos(
+            &looper.parameter_scratch_indexes,
+            &looper.parameters_scratch[0],
+            [lfo1, lfo2],
+            LFORetriggerMode::LFORetriggerModeTrigger,
+        );
+        assert_f_eq!(looper.lfos[0].0.phase(), 0.25);
+        assert_f_eq!(looper.lfos[0].1.phase(), 0.25);
+    }
+
     #[test]
     fn test_starts_empty() {
         let looper = MultiTrackLooper::new(Default::default(), 8);
@@ -133,6 +133,8 @@ pub enum SourceParameter {
     SliceId = 7,
     #[strum(props(type = "bool", default = "false"))]
     SliceEnabled = 8,
+    #[strum(props(type = "bool", default = "false"))]
+    TimeStretchEnabled = 9,
 }
 
 #[repr(C)]
@@ -243,6 +245,14 @@ pub enum LFOParameter {
     LFOParameterAmount = 1,
     #[strum(props(type = "enum", default = "0"))]
     LFOParameterMode = 2,
+    /// Musical division the rate is synced to, `LFODivisionFree` uses the frequency parameter
+    #[strum(props(type = "enum", default = "0"))]
+    LFOParameterDivision = 3,
+    /// Phase offset as a fraction of a cycle (0-1)
+    #[strum(props(type = "float", default = "0.0"))]
+    LFOParameterPhase = 4,
+    #[strum(props(type = "enum", default = "0"))]
+    LFOParameterRetrigger = 5,
 }
 
 #[repr(C)]
@@ -252,17 +262,127 @@ pub enum LFOParameter {
 pub enum LFOMode {
     LFOModeSine = 0,
     LFOModeSquare = 1,
+    /// Saw going down
     LFOModeSaw = 2,
+    LFOModeTriangle = 3,
+    LFOModeSawUp = 4,
+    /// Holds a new random value every cycle
+    LFOModeSampleAndHold = 5,
+    /// Glides between a new random value every cycle
+    LFOModeSmoothRandom = 6,
 }
 
 impl LFOMode {
+    /// Function from phase to value for this shape.
+    ///
+    /// Random shapes depend on state kept by `LFOOscillator`, for them this returns a fixed
+    /// sequence which is useful for drawing.
     pub fn generator_fn(&self) -> fn(f32) -> f32 {
         match self {
             LFOMode::LFOModeSine => augmented_oscillator::generators::sine_generator,
             LFOMode::LFOModeSquare => augmented_oscillator::generators::square_generator,
             LFOMode::LFOModeSaw => augmented_oscillator::generators::saw_generator,
+            LFOMode::LFOModeTriangle => augmented_oscillator::generators::triangle_generator,
+            LFOMode::LFOModeSawUp => augmented_oscillator::generators::saw_up_generator,
+            LFOMode::LFOModeSampleAndHold => sample_and_hold_preview,
+            LFOMode::LFOModeSmoothRandom => smooth_random_preview,
+        }
+    }
+
+    pub fn is_random(&self) -> bool {
+        matches!(
+            self,
+            LFOMode::LFOModeSampleAndHold | LFOMode::LFOModeSmoothRandom
+        )
+    }
+}
+
+static RANDOM_PREVIEW_STEPS: [f32; 4] = [0.6, -0.2, 0.9, -0.7];
+
+fn sample_and_hold_preview(phase: f32) -> f32 {
+    let step = ((phase % 1.0) * RANDOM_PREVIEW_STEPS.len() as f32) as usize;
+    RANDOM_PREVIEW_STEPS[step % RANDOM_PREVIEW_STEPS.len()]
+}
+
+fn smooth_random_preview(phase: f32) -> f32 {
+    let position = (phase % 1.0) * RANDOM_PREVIEW_STEPS.len() as f32;
+    let step = position as usize % RANDOM_PREVIEW_STEPS.len();
+    let next = (step + 1) % RANDOM_PREVIEW_STEPS.len();
+    smooth_step(
+        RANDOM_PREVIEW_STEPS[step],
+        RANDOM_PREVIEW_STEPS[next],
+        position.fract(),
+    )
+}
+
+/// Interpolate between `from` and `to` with an S-curve, so the slope is zero at both ends
+pub fn smooth_step(from: f32, to: f32, t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    from + (to - from) * t * t * (3.0 - 2.0 * t)
+}
+
+/// Musical note length an LFO cycle is synced to. Bars assume 4/4.
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Serialize, Deserialize, FromPrimitive, ToPrimitive)]
+#[serde(into = "usize")]
+#[serde(try_from = "usize")]
+pub enum LFODivision {
+    LFODivisionFree = 0,
+    LFODivisionFourBars = 1,
+    LFODivisionTwoBars = 2,
+    LFODivisionOneBar = 3,
+    LFODivisionHalf = 4,
+    LFODivisionQuarter = 5,
+    LFODivisionEighth = 6,
+    LFODivisionSixteenth = 7,
+    LFODivisionThirtySecond = 8,
+    LFODivisionDottedQuarter = 9,
+    LFODivisionDottedEighth = 10,
+    LFODivisionTripletQuarter = 11,
+    LFODivisionTripletEighth = 12,
+    LFODivisionTripletSixteenth = 13,
+}
+
+impl LFODivision {
+    /// Length of one cycle in beats (quarter notes), `None` if the LFO is free running
+    pub fn beats(&self) -> Option<f64> {
+        use LFODivision::*;
+        match self {
+            LFODivisionFree => None,
+            LFODivisionFourBars => Some(16.0),
+            LFODivisionTwoBars => Some(8.0),
+            LFODivisionOneBar => Some(4.0),
+            LFODivisionHalf => Some(2.0),
+            LFODivisionQuarter => Some(1.0),
+            LFODivisionEighth => Some(0.5),
+            LFODivisionSixteenth => Some(0.25),
+            LFODivisionThirtySecond => Some(0.125),
+            LFODivisionDottedQuarter => Some(1.5),
+            LFODivisionDottedEighth => Some(0.75),
+            LFODivisionTripletQuarter => Some(2.0 / 3.0),
+            LFODivisionTripletEighth => Some(1.0 / 3.0),
+            LFODivisionTripletSixteenth => Some(1.0 / 6.0),
         }
     }
+
+    /// Rate in Hz for this division at `tempo` BPM
+    pub fn frequency(&self, tempo: f64) -> Option<f32> {
+        self.beats().map(|beats| (tempo / 60.0 / beats) as f32)
+    }
+}
+
+/// When the LFO phase is reset back to its phase offset
+#[repr(C)]
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Serialize, Deserialize, FromPrimitive, ToPrimitive)]
+#[serde(into = "usize")]
+#[serde(try_from = "usize")]
+pub enum LFORetriggerMode {
+    /// Never reset, the LFO runs freely
+    LFORetriggerModeFree = 0,
+    /// Reset whenever the looper wraps back to the start of the loop
+    LFORetriggerModeLoopStart = 1,
+    /// Reset whenever a sequencer step triggers the looper
+    LFORetriggerModeTrigger = 2,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -499,3 +619,5 @@ usize_conversion!(QuantizationParameter);
 usize_conversion!(EnvelopeParameter);
 usize_conversion!(LFOParameter);
 usize_conversion!(LFOMode);
+usize_conversion!(LFODivision);
+usize_conversion!(LFORetriggerMode);
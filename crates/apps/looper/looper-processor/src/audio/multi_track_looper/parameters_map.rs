@@ -80,12 +80,16 @@ impl ParametersMap {
         }
     }
 
-    /// Returns true if the parameter has been set after the default
+    /// Returns true if the parameter has been set after the default.
+    ///
+    /// Maps loaded from older projects may not know about newer parameters, those are never set.
     #[inline]
     pub fn has_value(&self, id: impl Into<ParameterId>) -> bool {
         let id: ParameterId = id.into();
-        let index: usize = self.indexes[&id];
-        self.has_value[index].get()
+        self.indexes
+            .get(&id)
+            .map(|index| self.has_value[*index].get())
+            .unwrap_or(false)
     }
 
     #[inline]
@@ -180,6 +180,17 @@ impl SliceWorker {
         self.results.get(&id).map(|entry| entry.val().clone())
     }
 
+    /// Store markers that are already known, such as the cue markers of a loaded file, as the
+    /// result for `id`
+    pub fn set_result(&self, id: usize, markers: Vec<AudioFileMarker>) {
+        self.results.insert(
+            id,
+            SliceResult {
+                result: make_shared(markers),
+            },
+        );
+    }
+
     pub fn stop(&mut self) {
         self.is_running.store(false, Ordering::Relaxed);
     }
@@ -21,6 +21,8 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::path::PathBuf;
+
 use atomic_refcell::AtomicRefCell;
 use basedrop::Shared;
 
@@ -40,6 +42,11 @@ pub enum TrackEventsMessage {
     ClearedBuffer {
         looper_id: LooperId,
     },
+    LoadedClip {
+        looper_id: LooperId,
+        path: PathBuf,
+        looper_clip: Shared<AtomicRefCell<AudioBuffer<AtomicF32>>>,
+    },
 }
 
 pub struct TrackEventsBus {
@@ -81,4 +88,18 @@ impl TrackEventsBus {
             settings,
         });
     }
+
+    /// Called off the audio thread, after the file at `path` is loaded into a looper
+    pub fn on_loaded_clip(
+        &self,
+        looper_id: LooperId,
+        path: PathBuf,
+        looper_clip: Shared<AtomicRefCell<AudioBuffer<AtomicF32>>>,
+    ) {
+        let _ = self.tx.push(TrackEventsMessage::LoadedClip {
+            looper_id,
+            path,
+            looper_clip,
+        });
+    }
 }
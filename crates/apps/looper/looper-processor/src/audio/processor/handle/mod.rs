@@ -31,12 +31,14 @@ use basedrop::{Shared, SharedCell};
 use num_derive::{FromPrimitive, ToPrimitive};
 
 use audio_garbage_collector::{make_shared, make_shared_cell};
+use audio_processor_pitch_shifter::time_stretch::{WsolaOptions, WsolaTimeStretcher};
 use audio_processor_traits::{AtomicF32, AudioBuffer, AudioProcessorSettings};
 use augmented_atomics::{AtomicEnum, AtomicValue};
 pub use quantize_mode::{QuantizeMode, QuantizeOptions};
 use utils::CopyLoopClipParams;
 
 use crate::audio::processor::handle::scratch_pad::ScratchPad;
+use crate::audio::processor::handle::time_stretch::{LoopRegionSource, OverdubStretcher};
 use crate::audio::{
     loop_quantization::{LoopQuantizer, QuantizeInput},
     time_info_provider::{HostCallback, TimeInfoProvider, TimeInfoProviderImpl},
@@ -44,6 +46,7 @@ use crate::audio::{
 
 mod quantize_mode;
 mod scratch_pad;
+mod time_stretch;
 mod utils;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -139,6 +142,11 @@ pub struct LooperHandle {
     speed: AtomicF32,
     /// Defaults to true, if false the loop will not repeat until triggered.
     loop_enabled: AtomicBool,
+    /// If true, speed and tempo changes don't change the pitch of the loop and the loop follows
+    /// the tempo it was recorded at
+    time_stretch_enabled: AtomicBool,
+    /// Tempo the clip was recorded at, 0 if unknown
+    clip_tempo: AtomicF32,
 
     /// This looper's playback state
     state: AtomicEnum<LooperState>,
@@ -153,6 +161,15 @@ pub struct LooperHandle {
     looper_clip1: LooperClip,
     /// Where playback is within the looped clip buffer
     cursor: AtomicF32,
+    /// Renders playback when time-stretching is enabled
+    time_stretcher: AtomicRefCell<WsolaTimeStretcher>,
+    /// Set when the cursor jumps, so the time-stretcher restarts from it
+    time_stretch_reset: AtomicBool,
+    /// Writes overdubs when time-stretching is enabled
+    overdub_stretcher: AtomicRefCell<OverdubStretcher>,
+    /// Set when overdubbing starts, so the overdub stretcher restarts from the cursor, or when
+    /// the clip is replaced, so nothing left from the last overdub is written into it
+    overdub_reset: AtomicBool,
     /// Provides time information
     time_info_provider: Shared<TimeInfoProviderImpl>,
     tick_time: AtomicBool,
@@ -193,6 +210,8 @@ impl LooperHandle {
             fade_end: AtomicF32::new(0.0),
             speed: AtomicF32::new(1.0),
             loop_enabled: AtomicBool::new(true),
+            time_stretch_enabled: AtomicBool::new(false),
+            clip_tempo: AtomicF32::new(0.0),
 
             state: AtomicEnum::new(LooperState::Empty),
             start_cursor: AtomicUsize::new(0),
@@ -202,6 +221,10 @@ impl LooperHandle {
             looper_clip1: make_shared_cell(AtomicRefCell::new(AudioBuffer::empty())),
             scheduled_playback: AtomicUsize::new(0),
             cursor: AtomicF32::new(0.0),
+            time_stretcher: AtomicRefCell::new(WsolaTimeStretcher::default()),
+            time_stretch_reset: AtomicBool::new(true),
+            overdub_stretcher: AtomicRefCell::new(OverdubStretcher::default()),
+            overdub_reset: AtomicBool::new(true),
             time_info_provider,
             tick_time: AtomicBool::new(true),
             options,
@@ -272,11 +295,39 @@ impl LooperHandle {
         self.loop_enabled.store(value, Ordering::Relaxed);
     }
 
+    pub fn time_stretch_enabled(&self) -> bool {
+        self.time_stretch_enabled.load(Ordering::Relaxed)
+    }
+
+    /// When enabled, speed changes will not change pitch and the loop will follow the global
+    /// tempo, relative to the tempo it was recorded at
+    pub fn set_time_stretch_enabled(&self, value: bool) {
+        self.time_stretch_reset.store(true, Ordering::Relaxed);
+        self.time_stretch_enabled.store(value, Ordering::Relaxed);
+    }
+
+    /// Tempo the clip was recorded at, `None` if it isn't known yet
+    pub fn clip_tempo(&self) -> Option<f32> {
+        let tempo = self.clip_tempo.get();
+        if tempo > 0.0 {
+            Some(tempo)
+        } else {
+            None
+        }
+    }
+
+    /// Override the tempo the clip was recorded at. Clips without a tempo adopt the global tempo
+    /// once they're played back.
+    pub fn set_clip_tempo(&self, tempo: Option<f32>) {
+        self.clip_tempo.set(tempo.unwrap_or(0.0));
+    }
+
     pub fn set_tick_time(&self, value: bool) {
         self.tick_time.set(value)
     }
 
     pub fn trigger(&self) {
+        self.time_stretch_reset.store(true, Ordering::Relaxed);
         self.cursor.set(self.get_start_samples());
     }
 
@@ -343,6 +394,29 @@ impl LooperHandle {
         self.end_offset.get() * self.length.get() as f32
     }
 
+    fn global_tempo(&self) -> Option<f32> {
+        self.time_info_provider
+            .get_time_info()
+            .tempo()
+            .map(|tempo| tempo as f32)
+    }
+
+    /// How many clip samples playback moves per output sample. When time-stretching, this
+    /// includes the ratio between the global tempo and the clip tempo.
+    fn playback_rate(&self) -> f32 {
+        let speed = self.speed.get();
+        if !self.time_stretch_enabled() {
+            return speed;
+        }
+
+        let tempo_ratio = self
+            .clip_tempo()
+            .zip(self.global_tempo())
+            .map(|(clip_tempo, global_tempo)| global_tempo / clip_tempo)
+            .unwrap_or(1.0);
+        speed * tempo_ratio
+    }
+
     pub fn toggle_recording(&self, thread: LooperHandleThread) -> ToggleRecordingResult {
         let old_state = self.state.get();
         if old_state == LooperState::Recording || old_state == LooperState::Overdubbing {
@@ -412,6 +486,10 @@ impl LooperHandle {
             if self.tick_time.get() {
                 self.time_info_provider.play();
             }
+            if old_state == LooperState::Paused {
+                self.time_stretch_reset.store(true, Ordering::Relaxed);
+            }
+            self.overdub_reset.store(true, Ordering::Relaxed);
             self.state.set(LooperState::Overdubbing);
         }
 
@@ -420,6 +498,8 @@ impl LooperHandle {
 
     pub fn clear(&self) {
         self.state.set(LooperState::Empty);
+        self.clip_tempo.set(0.0);
+        self.overdub_reset.store(true, Ordering::Relaxed);
         // Clear the looper clip in case playback re-starts
         let clip = self.looper_clip.get();
         let clip = clip.deref().borrow();
@@ -438,6 +518,7 @@ impl LooperHandle {
         self.state.set(LooperState::Playing);
         // TODO - This should be a parameter, or there should be a "Stopped" state
         self.cursor.set(self.get_start_samples());
+        self.time_stretch_reset.store(true, Ordering::Relaxed);
     }
 
     pub fn stop(&self) {
@@ -494,8 +575,10 @@ impl LooperHandle {
                 if self.tick_time.get() {
                     self.time_info_provider.play();
                 }
+                self.clip_tempo.set(self.global_tempo().unwrap_or(0.0));
                 self.state.set(LooperState::Playing);
                 self.cursor.set(0.0);
+                self.time_stretch_reset.store(true, Ordering::Relaxed);
             }
         } else if old_state == LooperState::Overdubbing {
             self.state.set(LooperState::Playing);
@@ -539,7 +622,9 @@ impl LooperHandle {
                 if self.tick_time.get() {
                     self.time_info_provider.play();
                 }
+                self.clip_tempo.set(self.global_tempo().unwrap_or(0.0));
                 self.cursor.set(0.0);
+                self.time_stretch_reset.store(true, Ordering::Relaxed);
                 self.state.set(LooperState::Playing);
             }
             self.looper_clip1.set(self.looper_clip.get());
@@ -570,6 +655,8 @@ impl LooperHandle {
         let new_length = new_buffer.num_samples();
         self.looper_clip.set(make_shared(new_buffer.into()));
         self.length.set(new_length);
+        self.clip_tempo.set(0.0);
+        self.overdub_reset.store(true, Ordering::Relaxed);
         self.state.set(LooperState::Paused);
         self.cursor.set(self.get_start_samples());
     }
@@ -606,6 +693,13 @@ impl LooperHandle {
                 ))));
         }
 
+        *self.time_stretcher.borrow_mut() =
+            WsolaTimeStretcher::new(WsolaOptions::from_sample_rate(settings.sample_rate()));
+        self.time_stretch_reset.store(true, Ordering::Relaxed);
+        *self.overdub_stretcher.borrow_mut() =
+            OverdubStretcher::new(WsolaOptions::from_sample_rate(settings.sample_rate()));
+        self.overdub_reset.store(true, Ordering::Relaxed);
+
         self.time_info_provider
             .set_sample_rate(settings.sample_rate());
 
@@ -616,6 +710,11 @@ impl LooperHandle {
         self.state.get()
     }
 
+    /// Put the looper back in a previous `state`, such as after rendering it offline
+    pub(crate) fn restore_state(&self, state: LooperState) {
+        self.state.set(state);
+    }
+
     #[inline]
     pub(crate) fn process(&self, channel: usize, sample: f32) -> f32 {
         let scratch_pad = self.scratch_pad.borrow();
@@ -623,6 +722,18 @@ impl LooperHandle {
 
         let state = self.state.get();
         let out = match state {
+            // Overdubs are written by `after_process_overdub` when time-stretching
+            LooperState::Playing | LooperState::Overdubbing if self.time_stretch_enabled() => {
+                let clip = self.looper_clip.get();
+                let clip = clip.deref().borrow();
+                let mut time_stretcher = self.time_stretcher.borrow_mut();
+                if self.time_stretch_reset.swap(false, Ordering::Relaxed) {
+                    time_stretcher.reset(self.cursor.get(), self.playback_rate());
+                }
+
+                let source = self.loop_region_source(&clip);
+                self.apply_wet_volume(time_stretcher.sample(&source, channel))
+            }
             LooperState::Playing => {
                 let clip = self.looper_clip.get();
                 let clip = clip.deref().borrow();
@@ -653,6 +764,14 @@ impl LooperHandle {
         self.dry_volume.get() * sample + out
     }
 
+    fn loop_region_source<'a>(&self, clip: &'a AudioBuffer<AtomicF32>) -> LoopRegionSource<'a> {
+        LoopRegionSource {
+            clip,
+            start: self.get_start_samples() as i64,
+            end: self.get_end_samples().ceil() as i64,
+        }
+    }
+
     #[inline]
     fn apply_wet_volume(&self, out: f32) -> f32 {
         let fade_in_volume = self.get_fade_in_volume(self.cursor.get());
@@ -688,8 +807,16 @@ impl LooperHandle {
             // Recording, incrementing the length
             LooperState::Recording => self.after_process_recording(&scratch_pad),
             // Playing states, moving/resetting the cursor
-            LooperState::Playing => self.after_process_playing(),
-            LooperState::Overdubbing => self.after_process_playing(),
+            LooperState::Playing => {
+                self.after_process_overdub(&scratch_pad, false);
+                self.after_process_playing();
+                self.after_process_time_stretch();
+            }
+            LooperState::Overdubbing => {
+                self.after_process_overdub(&scratch_pad, true);
+                self.after_process_playing();
+                self.after_process_time_stretch();
+            }
             _ => {}
         }
     }
@@ -732,9 +859,51 @@ impl LooperHandle {
         }
     }
 
+    /// Record the clip tempo if it's unknown and move the time-stretcher along with the cursor
+    fn after_process_time_stretch(&self) {
+        // Clips recorded without a tempo take the first known global tempo as their own
+        if self.clip_tempo().is_none() {
+            if let Some(tempo) = self.global_tempo() {
+                self.clip_tempo.set(tempo);
+            }
+        }
+
+        if !self.time_stretch_enabled() {
+            return;
+        }
+
+        let clip = self.looper_clip.get();
+        let clip = clip.deref().borrow();
+        let source = self.loop_region_source(&clip);
+        self.time_stretcher
+            .borrow_mut()
+            .advance(&source, self.cursor.get(), self.playback_rate());
+    }
+
+    /// Write the input into the clip while overdubbing a time-stretched clip, and finish writing
+    /// the last of it once overdubbing stops
+    fn after_process_overdub(&self, scratch_pad: &ScratchPad, is_overdubbing: bool) {
+        if !self.time_stretch_enabled()
+            || (!is_overdubbing && self.overdub_reset.load(Ordering::Relaxed))
+        {
+            return;
+        }
+
+        let clip = self.looper_clip.get();
+        let clip = clip.deref().borrow();
+        let source = self.loop_region_source(&clip);
+        let mut overdub_stretcher = self.overdub_stretcher.borrow_mut();
+        if is_overdubbing && self.overdub_reset.swap(false, Ordering::Relaxed) {
+            // The scratch-pad cursor has already moved past the frame just recorded
+            let input_position = scratch_pad.cursor() as f32 - 1.0;
+            overdub_stretcher.reset(input_position, self.cursor.get());
+        }
+        overdub_stretcher.process(scratch_pad, &source, self.playback_rate(), is_overdubbing);
+    }
+
     // If we're in `Playing` or `Overdubbing` states (the looper is reproducing audio).
     //
-    // * Increment the `cursor` by `speed`
+    // * Increment the `cursor` by `speed`, or by the playback rate if time-stretching
     // * If "loop mode" is enabled, reset the cursor to the start of the playback on each tick
     //   - Start of playback depends on the direction the loop is moving to
     //     * If the loop is playing forwards, we'll go to the start offset parameter position
@@ -747,7 +916,7 @@ impl LooperHandle {
 
         let cursor = self.cursor.get();
         let length = self.length.get() as f32;
-        let speed = self.speed.get();
+        let speed = self.playback_rate();
 
         if !self.loop_enabled.load(Ordering::Relaxed) && (cursor + speed) >= end_samples {
             return;
@@ -0,0 +1,219 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use audio_processor_pitch_shifter::time_stretch::{
+    TimeStretchSource, WsolaOptions, WsolaTimeStretcher,
+};
+use audio_processor_traits::{AtomicF32, AudioBuffer};
+
+use super::scratch_pad::ScratchPad;
+
+/// Reads a looper clip for the time-stretcher, wrapping around the start/end region of the loop
+pub struct LoopRegionSource<'a> {
+    pub clip: &'a AudioBuffer<AtomicF32>,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl<'a> LoopRegionSource<'a> {
+    /// Index of `position` in the clip, once wrapped around the region
+    #[inline]
+    fn index(&self, position: i64) -> Option<usize> {
+        let num_samples = self.clip.num_samples();
+        let length = self.end - self.start;
+        if length <= 0 || num_samples == 0 {
+            return None;
+        }
+
+        let index = self.start + (position - self.start).rem_euclid(length);
+        Some(index as usize % num_samples)
+    }
+
+    /// Wrap a fractional `position` around the region
+    fn wrap(&self, position: f32) -> f32 {
+        let length = (self.end - self.start) as f32;
+        if length <= 0.0 {
+            return position;
+        }
+        self.start as f32 + (position - self.start as f32).rem_euclid(length)
+    }
+
+    /// Mix `value` into the clip at `position`
+    #[inline]
+    pub fn add(&self, channel: usize, position: i64, value: f32) {
+        if let Some(index) = self.index(position) {
+            let sample = self.clip.get(channel, index);
+            sample.set(sample.get() + value);
+        }
+    }
+}
+
+impl<'a> TimeStretchSource for LoopRegionSource<'a> {
+    fn num_channels(&self) -> usize {
+        self.clip.num_channels()
+    }
+
+    #[inline]
+    fn sample(&self, channel: usize, position: i64) -> f32 {
+        self.index(position)
+            .map(|index| self.clip.get(channel, index).get())
+            .unwrap_or(0.0)
+    }
+}
+
+/// Reads the input history for the time-stretcher, wrapping around the circular buffer
+impl TimeStretchSource for ScratchPad {
+    fn num_channels(&self) -> usize {
+        self.buffer().num_channels()
+    }
+
+    #[inline]
+    fn sample(&self, channel: usize, position: i64) -> f32 {
+        let num_samples = self.max_len() as i64;
+        if num_samples == 0 {
+            return 0.0;
+        }
+        self.buffer()
+            .get(channel, position.rem_euclid(num_samples) as usize)
+            .get()
+    }
+}
+
+/// Writes overdubs into a time-stretched clip.
+///
+/// When the clip is playing at a rate other than 1, the input is stretched by the inverse of that
+/// rate before it's mixed in, so the overdub plays back in time with the clip and at its original
+/// pitch. The stretcher needs to see a grain of input past what it's reading, so overdubs are
+/// written a fixed latency behind the input and keep being written for that long after
+/// overdubbing stops.
+pub struct OverdubStretcher {
+    stretcher: WsolaTimeStretcher,
+    /// Input frames the stretcher reads behind the most recent input
+    latency: usize,
+    /// Input frames recorded but not written into the clip yet
+    pending: usize,
+    /// Position on the scratch-pad of the next input frame to write
+    input_position: f32,
+    /// Position on the clip the next input frame was heard at
+    write_position: f32,
+}
+
+impl Default for OverdubStretcher {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl OverdubStretcher {
+    pub fn new(options: WsolaOptions) -> Self {
+        let latency = options.grain_size + options.search_range;
+        Self {
+            stretcher: WsolaTimeStretcher::new(options),
+            latency,
+            pending: 0,
+            input_position: 0.0,
+            write_position: 0.0,
+        }
+    }
+
+    /// Start a new overdub. `input_position` is the scratch-pad position of the frame just
+    /// recorded and `write_position` where the clip was when it was heard.
+    pub fn reset(&mut self, input_position: f32, write_position: f32) {
+        self.stretcher.reset(input_position, 1.0);
+        self.pending = 0;
+        self.input_position = input_position;
+        self.write_position = write_position;
+    }
+
+    /// Called once per frame. While `is_recording` another input frame is queued, then the
+    /// oldest queued frame is written once it's far enough behind the input.
+    pub fn process(
+        &mut self,
+        input: &ScratchPad,
+        clip: &LoopRegionSource,
+        rate: f32,
+        is_recording: bool,
+    ) {
+        let latency = if is_recording {
+            self.pending += 1;
+            self.latency
+        } else {
+            0
+        };
+
+        if self.pending > latency {
+            self.write_frame(input, clip, rate);
+            self.pending -= 1;
+        }
+    }
+
+    /// Write one input frame, which covers `rate` samples of the clip
+    fn write_frame(&mut self, input: &ScratchPad, clip: &LoopRegionSource, rate: f32) {
+        let num_channels = input.num_channels().min(clip.num_channels());
+        let start = self.write_position;
+        let end = start + rate;
+        let (mut position, step) = if rate >= 0.0 {
+            (start.ceil() as i64, 1)
+        } else {
+            (start.floor() as i64, -1)
+        };
+
+        while (rate >= 0.0 && (position as f32) < end) || (rate < 0.0 && (position as f32) > end) {
+            for channel in 0..num_channels {
+                clip.add(channel, position, self.stretcher.sample(input, channel));
+            }
+            position += step;
+            let input_position = self.input_position + (position as f32 - start) / rate;
+            self.stretcher
+                .advance(input, input_position, 1.0 / rate.abs());
+        }
+
+        self.input_position = (self.input_position + 1.0).rem_euclid(input.max_len() as f32);
+        self.write_position = clip.wrap(end);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loop_region_source_wraps_around_the_region() {
+        let mut clip = AudioBuffer::empty();
+        clip.resize_with(1, 10, || AtomicF32::new(0.0));
+        for (i, sample) in clip.channel(0).iter().enumerate() {
+            sample.set(i as f32);
+        }
+
+        let source = LoopRegionSource {
+            clip: &clip,
+            start: 2,
+            end: 6,
+        };
+        let samples: Vec<f32> = (0..10).map(|i| source.sample(0, i)).collect();
+        assert_eq!(
+            samples,
+            vec![4.0, 5.0, 2.0, 3.0, 4.0, 5.0, 2.0, 3.0, 4.0, 5.0]
+        );
+        assert_eq!(source.sample(0, -1), 3.0);
+    }
+}
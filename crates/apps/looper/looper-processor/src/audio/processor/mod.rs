@@ -624,6 +624,185 @@ mod test {
         );
     }
 
+    /// Estimate the frequency of a sine from its rising zero-crossings
+    fn estimate_frequency(signal: &[f32], sample_rate: f32) -> f32 {
+        let crossings = signal
+            .windows(2)
+            .positions(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .collect_vec();
+        let periods = (crossings.len() - 1) as f32;
+        let duration = (crossings[crossings.len() - 1] - crossings[0]) as f32;
+        periods * sample_rate / duration
+    }
+
+    fn make_time_stretch_looper(clip_tempo: f32, tempo: f32) -> (LooperProcessor, AudioContext) {
+        let settings = AudioProcessorSettings::new(22050.0, 1, 1, 512);
+        let mut context = AudioContext::from(settings);
+        let mut looper = LooperProcessor::default();
+        looper.prepare(&mut context);
+
+        let clip = sine_buffer(settings.sample_rate(), 440.0, Duration::from_secs(1));
+        looper
+            .handle
+            .set_looper_buffer(&AudioBuffer::from_interleaved(1, &clip));
+        looper.handle.set_clip_tempo(Some(clip_tempo));
+        looper.handle.set_tempo(tempo);
+        looper.handle.set_time_stretch_enabled(true);
+        looper.handle.play();
+
+        (looper, context)
+    }
+
+    #[test]
+    fn test_time_stretched_looper_follows_the_tempo_at_the_same_pitch() {
+        for (tempo, expected_playhead) in [(60.0, 5512), (180.0, 16537)] {
+            let (mut looper, mut context) = make_time_stretch_looper(120.0, tempo);
+
+            let mut output_buffer = AudioBuffer::empty();
+            output_buffer.resize(1, 11025);
+            looper.process(&mut context, &mut output_buffer);
+
+            assert!((looper.handle.playhead() as i32 - expected_playhead).abs() <= 1);
+            let frequency = estimate_frequency(output_buffer.channel(0), 22050.0);
+            assert!(
+                (frequency - 440.0).abs() < 5.0,
+                "Tempo {} changed the frequency to {}",
+                tempo,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn test_time_stretched_looper_at_the_clip_tempo_plays_the_clip() {
+        let (mut looper, mut context) = make_time_stretch_looper(120.0, 120.0);
+        let clip = looper_clip_copy(&looper);
+
+        let mut output_buffer = AudioBuffer::empty();
+        output_buffer.resize(1, 1000);
+        looper.process(&mut context, &mut output_buffer);
+        for (actual, expected) in output_buffer.channel(0).iter().zip(&clip) {
+            assert!((actual - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_time_stretched_looper_does_not_allocate() {
+        let (mut looper, mut context) = make_time_stretch_looper(120.0, 100.0);
+        looper.handle.set_speed(-1.5);
+
+        let mut output_buffer = AudioBuffer::empty();
+        output_buffer.resize(1, 512);
+        assert_no_alloc(|| {
+            for _ in 0..50 {
+                looper.process(&mut context, &mut output_buffer);
+            }
+        });
+        looper.handle.start_recording();
+        assert_no_alloc(|| {
+            for _ in 0..50 {
+                looper.process(&mut context, &mut output_buffer);
+            }
+        });
+    }
+
+    #[test]
+    fn test_time_stretched_overdub_at_the_clip_tempo_adds_the_input() {
+        let (mut looper, mut context) = make_time_stretch_looper(120.0, 120.0);
+        let clip = looper_clip_copy(&looper);
+
+        let input = sine_buffer(22050.0, 220.0, Duration::from_millis(200));
+        looper.handle.start_recording();
+        let mut buffer = AudioBuffer::from_interleaved(1, &input);
+        looper.process(&mut context, &mut buffer);
+        looper
+            .handle
+            .stop_recording(LooperHandleThread::OtherThread);
+        // Overdubs are written behind the input, let the rest of it through
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 2000);
+        looper.process(&mut context, &mut buffer);
+
+        let result = looper_clip_copy(&looper);
+        for (i, input) in input.iter().enumerate() {
+            assert!(
+                (result[i] - (clip[i] + input)).abs() < 1e-4,
+                "Sample {} is {}, expected {}",
+                i,
+                result[i],
+                clip[i] + input
+            );
+        }
+        assert_eq!(result[input.len()..], clip[input.len()..]);
+    }
+
+    #[test]
+    fn test_time_stretched_overdub_keeps_its_pitch_and_timing() {
+        let settings = AudioProcessorSettings::new(22050.0, 1, 1, 512);
+        let mut context = AudioContext::from(settings);
+        let mut looper = LooperProcessor::default();
+        looper.prepare(&mut context);
+        let mut clip = AudioBuffer::empty();
+        clip.resize(1, 22050);
+        looper.handle.set_looper_buffer(&clip);
+        looper.handle.set_clip_tempo(Some(120.0));
+        looper.handle.set_tempo(240.0);
+        looper.handle.set_time_stretch_enabled(true);
+        looper.handle.play();
+
+        // At twice the clip tempo, 250ms of input covers 500ms of the clip
+        let input = sine_buffer(22050.0, 440.0, Duration::from_millis(250));
+        looper.handle.start_recording();
+        let mut buffer = AudioBuffer::from_interleaved(1, &input);
+        looper.process(&mut context, &mut buffer);
+        looper
+            .handle
+            .stop_recording(LooperHandleThread::OtherThread);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 2000);
+        looper.process(&mut context, &mut buffer);
+
+        let result = looper_clip_copy(&looper);
+        let frequency = estimate_frequency(&result[1000..10000], 22050.0);
+        assert!(
+            (frequency - 440.0).abs() < 5.0,
+            "Overdub frequency changed to {}",
+            frequency
+        );
+        let overdub_length = input.len() * 2;
+        assert!(result[overdub_length - 1000..overdub_length]
+            .iter()
+            .any(|sample| sample.abs() > 0.1));
+        assert!(result[overdub_length + 1000..]
+            .iter()
+            .all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn test_clip_tempo_is_recorded_when_recording_stops() {
+        let mut looper = LooperProcessor::default();
+        let mut context = AudioContext::from(test_settings());
+        looper.prepare(&mut context);
+        looper.handle.set_tempo(90.0);
+
+        let mut buffer = AudioBuffer::from_interleaved(1, &[1.0, 2.0, 3.0, 4.0]);
+        looper.handle.start_recording();
+        looper.process(&mut context, &mut buffer);
+        looper
+            .handle
+            .stop_recording(LooperHandleThread::OtherThread);
+        assert_eq!(looper.handle.clip_tempo(), Some(90.0));
+
+        looper.handle.clear();
+        assert_eq!(looper.handle.clip_tempo(), None);
+    }
+
+    fn looper_clip_copy(looper: &LooperProcessor) -> Vec<f32> {
+        let clip = looper.handle.looper_clip();
+        let clip = clip.borrow();
+        clip.channel(0).iter().map(|f| f.get()).collect_vec()
+    }
+
     fn get_position_beats(looper: &mut LooperProcessor) -> f64 {
         looper
             .handle
@@ -21,7 +21,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 
 use crate::c_api::into_ptr;
@@ -203,3 +203,29 @@ pub unsafe extern "C" fn looper_engine__add_effect(
 
     handle.voices()[looper_id].effects().add_effect(effect_type);
 }
+
+/// Read the room or cabinet impulse response file at `path` and add a convolution effect playing
+/// it into the track with `LooperId`. Returns false if the file couldn't be read.
+#[no_mangle]
+pub unsafe extern "C" fn looper_engine__add_impulse_response_effect(
+    engine: *const LooperEngine,
+    looper_id: usize,
+    path: *const c_char,
+) -> bool {
+    let handle = (*engine).handle();
+    let path = CStr::from_ptr(path).to_str().unwrap_or("");
+    match handle.voices()[looper_id]
+        .effects()
+        .add_impulse_response_file(path)
+    {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!(
+                "Failed to load impulse response path={} error={}",
+                path,
+                err
+            );
+            false
+        }
+    }
+}
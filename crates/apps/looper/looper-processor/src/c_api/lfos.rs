@@ -21,7 +21,7 @@ use crate::{LooperEngine, LooperId};
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-use crate::parameters::{LFOMode, LFOParameter, ParameterId};
+use crate::parameters::{LFODivision, LFOMode, LFOParameter, LFORetriggerMode, ParameterId};
 
 /// Change LFO parameters on a given track and LFO path
 #[no_mangle]
@@ -51,6 +51,34 @@ pub unsafe extern "C" fn looper_engine__set_lfo_mode(
         .set_lfo_mode(LooperId(looper_id), lfo_id, mode);
 }
 
+/// Sync an LFO rate to a musical division, `LFODivisionFree` goes back to the frequency parameter
+#[no_mangle]
+pub unsafe extern "C" fn looper_engine__set_lfo_division(
+    engine: *const LooperEngine,
+    looper_id: usize,
+    lfo_id: usize,
+    division: LFODivision,
+) {
+    let engine = &(*engine);
+    engine
+        .handle()
+        .set_lfo_division(LooperId(looper_id), lfo_id, division);
+}
+
+/// Choose when the LFO phase goes back to its offset
+#[no_mangle]
+pub unsafe extern "C" fn looper_engine__set_lfo_retrigger_mode(
+    engine: *const LooperEngine,
+    looper_id: usize,
+    lfo_id: usize,
+    retrigger_mode: LFORetriggerMode,
+) {
+    let engine = &(*engine);
+    engine
+        .handle()
+        .set_lfo_retrigger_mode(LooperId(looper_id), lfo_id, retrigger_mode);
+}
+
 /// Map an LFO to another parameter
 #[no_mangle]
 pub unsafe extern "C" fn looper_engine__add_lfo_mapping(
@@ -81,9 +109,5 @@ pub unsafe extern "C" fn looper_engine__remove_lfo_mapping(
 #[no_mangle]
 pub unsafe extern "C" fn looper_engine__get_lfo_sample(mode: LFOMode, phase: f32) -> f32 {
     let phase = phase / (std::f32::consts::PI * 2.0);
-    match mode {
-        LFOMode::LFOModeSine => augmented_oscillator::generators::sine_generator(phase),
-        LFOMode::LFOModeSquare => augmented_oscillator::generators::square_generator(phase),
-        LFOMode::LFOModeSaw => augmented_oscillator::generators::saw_generator(phase),
-    }
+    (mode.generator_fn())(phase)
 }
@@ -28,9 +28,13 @@ use cpal::traits::{DeviceTrait, HostTrait};
 use audio_processor_standalone::standalone_cpal::AudioIOMode;
 use audio_processor_standalone::standalone_processor::StandaloneOptions;
 use audio_processor_standalone::{StandaloneHandles, StandaloneProcessorImpl};
+use audio_processor_traits::{AudioContext, AudioProcessor};
 
 use crate::audio::time_info_provider::HostCallback;
 use crate::services::defaults_service;
+use crate::services::stem_export::{
+    export_stems, StemExportError, StemExportOptions, StemExportResult,
+};
 use crate::{MultiTrackLooper, MultiTrackLooperHandle};
 
 enum AudioState {
@@ -104,11 +108,14 @@ impl AudioStateController {
     }
 
     fn get_options(&mut self) -> Option<StandaloneOptions> {
-        let options = self.state.as_ref().and_then(|state| match state {
-            AudioState::Standalone { options, .. } => Some(options.clone()),
-            _ => None,
-        });
-        options
+        self.state.as_ref().and_then(standalone_options)
+    }
+}
+
+fn standalone_options(state: &AudioState) -> Option<StandaloneOptions> {
+    match state {
+        AudioState::Standalone { options, .. } => Some(options.clone()),
+        _ => None,
     }
 }
 
@@ -180,6 +187,61 @@ impl Handler<GetOptions> for AudioStateController {
     }
 }
 
+/// Bounce per-track stems & a master mix into a directory. The audio-thread is stopped while
+/// rendering & restarted with the same options afterwards, if it was running.
+#[derive(actix::Message)]
+#[rtype(result = "Result<StemExportResult, StemExportError>")]
+pub struct ExportStems {
+    pub options: StemExportOptions,
+}
+
+impl Handler<ExportStems> for AudioStateController {
+    type Result = Result<StemExportResult, StemExportError>;
+
+    fn handle(&mut self, msg: ExportStems, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(AudioState::Hosted(_)) = self.state {
+            return Err(StemExportError::Hosted);
+        }
+
+        let handle = self.handle.clone();
+        let num_voices = handle.num_voices();
+        with_audio_thread_stopped(
+            &mut self.state,
+            |state| standalone_options(state).unwrap_or_default(),
+            |options| {
+                let processor =
+                    MultiTrackLooper::from_handle(Default::default(), num_voices, handle.clone());
+                let state = setup_audio_state(options, processor);
+                log::info!("=== Restarted audio-thread after stem export ======\n");
+                state
+            },
+            || {
+                let mut processor =
+                    MultiTrackLooper::from_handle(Default::default(), num_voices, handle.clone());
+                let mut context = AudioContext::from(*handle.settings());
+                processor.prepare(&mut context);
+                export_stems(&mut processor, &msg.options)
+            },
+        )
+    }
+}
+
+/// Run `f` while the audio-thread is stopped, by dropping `state`. If it was running, it is
+/// restarted afterwards through `restart`, with the options read from the previous state.
+fn with_audio_thread_stopped<S, O, T>(
+    state: &mut Option<S>,
+    options: impl FnOnce(&S) -> O,
+    restart: impl FnOnce(O) -> S,
+    f: impl FnOnce() -> T,
+) -> T {
+    let previous_options = state.take().map(|previous| options(&previous));
+    let result = f();
+    if let Some(previous_options) = previous_options {
+        *state = Some(restart(previous_options));
+    }
+    result
+}
+
 pub struct AudioDevice {
     pub name: String,
 }
@@ -216,3 +278,75 @@ fn build_domain_model(
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    struct MockState<'a> {
+        options: &'static str,
+        events: &'a RefCell<Vec<String>>,
+    }
+
+    impl Drop for MockState<'_> {
+        fn drop(&mut self) {
+            self.events
+                .borrow_mut()
+                .push(format!("stop {}", self.options));
+        }
+    }
+
+    #[test]
+    fn test_with_audio_thread_stopped_restarts_a_running_state() {
+        let events = RefCell::new(vec![]);
+        let mut state = Some(MockState {
+            options: "previous",
+            events: &events,
+        });
+
+        let result = with_audio_thread_stopped(
+            &mut state,
+            |state| state.options,
+            |options| {
+                events.borrow_mut().push(format!("start {}", options));
+                MockState {
+                    options: "restarted",
+                    events: &events,
+                }
+            },
+            || {
+                events.borrow_mut().push("export".to_string());
+                10
+            },
+        );
+
+        assert_eq!(result, 10);
+        assert_eq!(state.as_ref().unwrap().options, "restarted");
+        assert_eq!(
+            *events.borrow(),
+            vec!["stop previous", "export", "start previous"]
+        );
+    }
+
+    #[test]
+    fn test_with_audio_thread_stopped_does_not_start_a_stopped_state() {
+        let events = RefCell::new(vec![]);
+        let mut state: Option<MockState> = None;
+
+        let result = with_audio_thread_stopped(
+            &mut state,
+            |state| state.options,
+            |_| panic!("The audio-thread should not be started"),
+            || {
+                events.borrow_mut().push("export".to_string());
+                10
+            },
+        );
+
+        assert_eq!(result, 10);
+        assert!(state.is_none());
+        assert_eq!(*events.borrow(), vec!["export"]);
+    }
+}
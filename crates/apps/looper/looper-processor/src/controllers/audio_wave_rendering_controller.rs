@@ -23,6 +23,7 @@
 
 use std::collections::HashMap;
 
+use actix::Addr;
 use basedrop::Shared;
 use foreign_types_shared::{ForeignType, ForeignTypeRef};
 use metal::{CAMetalLayer, CommandQueue, Device, MTLPixelFormat, MetalLayer};
@@ -32,10 +33,14 @@ use skia_safe::{
     Surface,
 };
 
+use actix_system_threads::ActorSystem;
 use atomic_queue::Queue;
 use audio_processor_traits::AudioBuffer;
-use augmented_audio_wave::spawn_audio_drawer;
+use augmented_audio_wave::{draw_peaks, spawn_audio_drawer};
 
+use crate::audio::processor::handle::LooperClipRef;
+use crate::services::sample_library::peaks::PeakFile;
+use crate::services::sample_library::{GetSamplePeaksMessage, SampleLibrary};
 use crate::{
     audio::multi_track_looper::track_events_worker::TrackEventsMessage, LooperId,
     MultiTrackLooperHandle,
@@ -69,8 +74,11 @@ pub struct AudioWaveRenderingControllerImpl<AWPP: AudioWavePlayheadProvider> {
     // Events / info providers
     handle: AWPP,
     track_events: Shared<Queue<TrackEventsMessage>>,
+    sample_library: Addr<SampleLibrary>,
     // Internal state
     drawers: HashMap<LooperId, augmented_audio_wave::PathRendererHandle>,
+    /// Cached peaks of clips loaded from the sample library, drawn instead of their samples
+    peaks: HashMap<LooperId, PeakFile>,
     surfaces: HashMap<LooperId, Surface>,
     // Metal handle references
     device: Device,
@@ -81,7 +89,11 @@ pub struct AudioWaveRenderingControllerImpl<AWPP: AudioWavePlayheadProvider> {
 }
 
 impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
-    pub fn new(handle: AWPP, track_events: Shared<Queue<TrackEventsMessage>>) -> Option<Self> {
+    pub fn new(
+        handle: AWPP,
+        track_events: Shared<Queue<TrackEventsMessage>>,
+        sample_library: Addr<SampleLibrary>,
+    ) -> Option<Self> {
         let device = Device::system_default()?;
         let queue = device.new_command_queue();
         let backend = unsafe {
@@ -96,6 +108,7 @@ impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
 
         Some(Self {
             drawers: Default::default(),
+            peaks: Default::default(),
             surfaces: Default::default(),
             handle,
             device,
@@ -103,6 +116,7 @@ impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
             context,
             _backend: backend,
             track_events,
+            sample_library,
             recording_context,
         })
     }
@@ -146,7 +160,15 @@ impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
             .expect("Surface was not present");
         let partial_canvas = partial_surface.canvas();
 
-        if let Some(drawer) = self.drawers.get_mut(&looper_id) {
+        if let Some(peaks) = self.peaks.get(&looper_id) {
+            partial_canvas.clear(Color4f::new(0.0, 0.0, 0.0, 1.0));
+            draw_peaks(
+                partial_canvas,
+                &peaks.peaks(drawable_size.width as usize),
+                (drawable_size.width, drawable_size.height),
+            );
+            partial_surface.flush_and_submit();
+        } else if let Some(drawer) = self.drawers.get_mut(&looper_id) {
             drawer.draw(partial_canvas, (drawable_size.width, drawable_size.height));
             partial_surface.flush_and_submit();
         }
@@ -190,17 +212,34 @@ impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
                     looper_clip,
                     ..
                 } => {
-                    let looper_clip = looper_clip.borrow();
-                    let looper_clip_copy: Vec<Vec<f32>> = looper_clip
-                        .channels()
-                        .iter()
-                        .map(|sample| sample.iter().map(|s| s.get()).collect())
-                        .collect();
-                    let looper_clip_copy = AudioBuffer::new(looper_clip_copy);
-                    self.drawers
-                        .insert(looper_id, spawn_audio_drawer(looper_clip_copy));
+                    self.spawn_clip_drawer(looper_id, &looper_clip);
+                }
+                TrackEventsMessage::LoadedClip {
+                    looper_id,
+                    path,
+                    looper_clip,
+                } => {
+                    let sample_library = self.sample_library.clone();
+                    let peaks = ActorSystem::current().spawn_result(async move {
+                        sample_library.send(GetSamplePeaksMessage { path }).await
+                    });
+
+                    match peaks {
+                        Ok(Ok(Some(peaks))) => {
+                            self.drawers.remove(&looper_id);
+                            self.peaks.insert(looper_id, peaks);
+                        }
+                        result => {
+                            if let Ok(Err(err)) = result {
+                                log::error!("Failed to read sample peaks: {}", err);
+                            }
+                            // The file isn't indexed (yet), fallback to drawing its samples
+                            self.spawn_clip_drawer(looper_id, &looper_clip);
+                        }
+                    }
                 }
                 TrackEventsMessage::ClearedBuffer { looper_id } => {
+                    self.peaks.remove(&looper_id);
                     let partial_surface = self
                         .surfaces
                         .get_mut(&looper_id)
@@ -211,6 +250,19 @@ impl<AWPP: AudioWavePlayheadProvider> AudioWaveRenderingControllerImpl<AWPP> {
             }
         }
     }
+
+    fn spawn_clip_drawer(&mut self, looper_id: LooperId, looper_clip: &LooperClipRef) {
+        let looper_clip = looper_clip.borrow();
+        let looper_clip_copy: Vec<Vec<f32>> = looper_clip
+            .channels()
+            .iter()
+            .map(|sample| sample.iter().map(|s| s.get()).collect())
+            .collect();
+        let looper_clip_copy = AudioBuffer::new(looper_clip_copy);
+        self.peaks.remove(&looper_id);
+        self.drawers
+            .insert(looper_id, spawn_audio_drawer(looper_clip_copy));
+    }
 }
 
 fn get_drawable_surface<'a>(
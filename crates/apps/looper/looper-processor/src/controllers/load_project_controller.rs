@@ -24,7 +24,9 @@ use actix::Addr;
 use basedrop::Shared;
 
 use crate::audio::multi_track_looper::looper_voice::LooperVoice;
-use crate::audio::multi_track_looper::parameters::{build_default_parameters, ParameterId};
+use crate::audio::multi_track_looper::parameters::{
+    build_default_parameters, LooperId, ParameterId,
+};
 use crate::audio::multi_track_looper::ParametersMap;
 use crate::controllers::events_controller::{ApplicationEvent, BroadcastMessage, EventsController};
 use crate::services::audio_clip_manager::{AudioClipManager, AudioClipModelRef, LoadClipMessage};
@@ -138,6 +140,23 @@ async fn copy_clips(
                 buffer.num_samples()
             );
             destination_voice.looper().set_looper_buffer(buffer);
+            destination.track_events_worker().on_loaded_clip(
+                LooperId(destination_voice.id),
+                path.to_path_buf(),
+                destination_voice.looper().looper_clip(),
+            );
+            // Prefer the tempo the clip was played at over the one tagged on its file
+            let clip_tempo = latest_project
+                .voices
+                .iter()
+                .find(|voice| voice.id == destination_voice.id)
+                .and_then(|voice| voice.clip_tempo)
+                .or_else(|| clip.tempo());
+            destination_voice.looper().set_clip_tempo(clip_tempo);
+            let slice_positions = clip.slice_positions();
+            if !slice_positions.is_empty() {
+                destination.set_looper_slices(LooperId(destination_voice.id), &slice_positions);
+            }
             events_controller
                 .send(BroadcastMessage(
                     ApplicationEvent::ApplicationEventLooperClipUpdated {
@@ -40,6 +40,7 @@ use crate::controllers::load_project_controller;
 use crate::controllers::load_project_controller::LoadContext;
 use crate::services::audio_clip_manager::AudioClipManager;
 use crate::services::project_manager::ProjectManager;
+use crate::services::sample_library::SampleLibrary;
 #[cfg(any(target_os = "ios", target_os = "macos"))]
 use crate::services::{
     analytics::AnalyticsService,
@@ -78,6 +79,7 @@ pub struct LooperEngine {
     metrics_actor: Mutex<AudioProcessorMetricsActor>,
     audio_clip_manager: Addr<AudioClipManager>,
     project_manager: Addr<ProjectManager>,
+    sample_library: Addr<SampleLibrary>,
     events_controller: Addr<EventsController>,
     #[cfg(any(target_os = "ios", target_os = "macos"))]
     analytics_service: Addr<AnalyticsService>,
@@ -117,7 +119,8 @@ impl LooperEngine {
         setup_osc_server(handle.clone());
 
         let events_controller = ActorSystem::start(EventsController::default());
-        let audio_clip_manager = ActorSystem::start(AudioClipManager::default());
+        let sample_library = ActorSystem::start(SampleLibrary::default());
+        let audio_clip_manager = ActorSystem::start(AudioClipManager::new(sample_library.clone()));
         let project_manager = ActorSystem::start(ProjectManager::default());
 
         let autosave_controller = {
@@ -162,13 +165,14 @@ impl LooperEngine {
         let queue = handle.track_events_worker().queue();
         #[cfg(any(target_os = "ios", target_os = "macos"))]
         let audio_wave_rendering_controller =
-            AudioWaveRenderingController::new(handle.clone(), queue);
+            AudioWaveRenderingController::new(handle.clone(), queue, sample_library.clone());
 
         LooperEngine {
             handle,
             metrics_actor,
             audio_clip_manager,
             project_manager,
+            sample_library,
             events_controller,
             audio_state_controller,
             #[cfg(any(target_os = "ios", target_os = "macos"))]
@@ -227,6 +231,10 @@ impl LooperEngine {
     pub fn project_manager(&self) -> &Addr<ProjectManager> {
         &self.project_manager
     }
+
+    pub fn sample_library(&self) -> &Addr<SampleLibrary> {
+        &self.sample_library
+    }
 }
 
 pub async fn save_project(
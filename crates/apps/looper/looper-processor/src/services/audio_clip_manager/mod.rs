@@ -23,16 +23,18 @@
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use actix::{Actor, Handler};
+use actix::{Actor, ActorFutureExt, Addr, Handler, ResponseActFuture, WrapFuture};
 use basedrop::Shared;
 use bytesize::ByteSize;
 
 use audio_garbage_collector::make_shared;
 use audio_processor_file::file_io::AudioFileError;
-use audio_processor_file::OutputAudioFileProcessor;
+use audio_processor_file::metadata::{read_metadata, AudioFileMetadata};
+use audio_processor_file::{OutputAudioFileProcessor, OutputFileError};
 use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
 
 use crate::audio::processor::handle::{looper_clip_copy_to_vec_buffer, LooperClipRef};
+use crate::services::sample_library::{GetSampleEntryMessage, SampleEntry, SampleLibrary};
 
 pub struct AudioClipId(usize);
 
@@ -42,12 +44,52 @@ pub struct AudioClipModel {
     #[allow(unused)]
     path: PathBuf,
     contents: AudioBuffer<f32>,
+    metadata: Option<AudioFileMetadata>,
+    sample_entry: Option<SampleEntry>,
+    sample_rate: f32,
 }
 
 impl AudioClipModel {
     pub fn contents(&self) -> &AudioBuffer<f32> {
         &self.contents
     }
+
+    pub fn metadata(&self) -> Option<&AudioFileMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The file's sample library entry, if it is indexed
+    pub fn sample_entry(&self) -> Option<&SampleEntry> {
+        self.sample_entry.as_ref()
+    }
+
+    /// Tempo of the loop, if the file has ACID or BPM tags or the sample library found one in its
+    /// name
+    pub fn tempo(&self) -> Option<f32> {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.tempo())
+            .or_else(|| self.sample_entry.as_ref()?.tempo)
+    }
+
+    /// Slice positions from the file's cue markers or beats, converted to frames of the
+    /// (resampled) clip contents
+    pub fn slice_positions(&self) -> Vec<usize> {
+        let metadata = match &self.metadata {
+            Some(metadata) => metadata,
+            None => return vec![],
+        };
+        let ratio = metadata
+            .sample_rate
+            .map(|file_sample_rate| self.sample_rate as f64 / file_sample_rate as f64)
+            .unwrap_or(1.0);
+        metadata
+            .slice_positions()
+            .into_iter()
+            .map(|position| (position as f64 * ratio).round() as usize)
+            .filter(|position| *position < self.contents.num_samples())
+            .collect()
+    }
 }
 
 pub type AudioClipModelRef = Shared<AudioClipModel>;
@@ -58,10 +100,28 @@ pub struct AudioClipManager {
     settings: AudioProcessorSettings,
     #[allow(dead_code)]
     audio_clips: Vec<AudioClipModelRef>,
+    sample_library: Option<Addr<SampleLibrary>>,
 }
 
 impl AudioClipManager {
+    /// Clips loaded through [`LoadClipMessage`] take their levels & tempo from the
+    /// `sample_library` index rather than analyzing the file again
+    pub fn new(sample_library: Addr<SampleLibrary>) -> Self {
+        Self {
+            sample_library: Some(sample_library),
+            ..Self::default()
+        }
+    }
+
     pub fn load_at_path(&mut self, path: &Path) -> Result<AudioClipModelRef, AudioFileError> {
+        self.load_clip(path, None)
+    }
+
+    fn load_clip(
+        &mut self,
+        path: &Path,
+        sample_entry: Option<SampleEntry>,
+    ) -> Result<AudioClipModelRef, AudioFileError> {
         log::info!("Reading file at path {:?}", path);
         let mut audio_file =
             audio_processor_file::InMemoryAudioFile::from_path(path.to_str().unwrap())?;
@@ -74,18 +134,28 @@ impl AudioClipManager {
             byte_size,
             duration
         );
-        let mut sum: f32 = 0.0;
-        for sample_index in 0..audio_file.num_samples() {
-            let mono_sample = audio_file.get_mono(sample_index);
-            sum += mono_sample.abs();
+        if let Some(entry) = &sample_entry {
+            log::info!(
+                "Levels loudness_db={} peak_db={}",
+                entry.loudness_db,
+                entry.peak_db
+            );
+        }
+
+        let metadata = read_metadata(path)
+            .map_err(|err| log::warn!("Failed to read metadata of {:?}: {}", path, err))
+            .ok();
+        if let Some(tempo) = metadata.as_ref().and_then(|metadata| metadata.tempo()) {
+            log::info!("File tempo={}", tempo);
         }
-        let rms = sum / audio_file.num_samples() as f32;
-        log::info!("RMS level rms={}", rms);
 
         let clip_model = make_shared(AudioClipModel {
             id: AudioClipId(self.audio_clips.len()),
             path: path.into(),
             contents: audio_file,
+            metadata,
+            sample_entry,
+            sample_rate: self.settings.sample_rate(),
         });
         self.audio_clips.push(clip_model.clone());
         Ok(clip_model)
@@ -103,24 +173,41 @@ pub(crate) struct LoadClipMessage {
 }
 
 impl Handler<LoadClipMessage> for AudioClipManager {
-    type Result = Result<AudioClipModelRef, AudioFileError>;
+    type Result = ResponseActFuture<Self, Result<AudioClipModelRef, AudioFileError>>;
 
     fn handle(&mut self, msg: LoadClipMessage, _ctx: &mut Self::Context) -> Self::Result {
-        self.load_at_path(&msg.path)
+        let sample_library = self.sample_library.clone();
+        let path = msg.path.clone();
+        let sample_entry_fut = async move {
+            let sample_library = sample_library?;
+            sample_library
+                .send(GetSampleEntryMessage { path })
+                .await
+                .ok()
+                .flatten()
+        };
+        let result_fut = sample_entry_fut
+            .into_actor(self)
+            .map(move |sample_entry, act, _ctx| act.load_clip(&msg.path, sample_entry));
+
+        Box::pin(result_fut)
     }
 }
 
-pub fn write_looper_clip(settings: AudioProcessorSettings, clip_path: &Path, clip: &LooperClipRef) {
+pub fn write_looper_clip(
+    settings: AudioProcessorSettings,
+    clip_path: &Path,
+    clip: &LooperClipRef,
+) -> Result<(), OutputFileError> {
     log::info!("Writing audio into {:?}", clip_path);
 
     let mut output_processor =
         OutputAudioFileProcessor::from_path(settings, clip_path.to_str().unwrap());
-    output_processor.prepare(settings);
+    output_processor.prepare(settings)?;
 
     let mut clip_buffer = looper_clip_copy_to_vec_buffer(clip);
-    if let Err(err) = output_processor.process(&mut clip_buffer) {
-        log::error!("Failed to write file: {}", err);
-    }
+    output_processor.process(&mut clip_buffer)?;
+    output_processor.finalize()
 }
 
 fn estimate_file_size<SampleType>(audio_file: &AudioBuffer<SampleType>) -> ByteSize {
@@ -135,6 +222,7 @@ mod test {
     use audio_processor_testing_helpers::{relative_path, rms_level};
 
     use actix_system_threads::ActorSystem;
+    use audio_processor_file::{CueMarker, OutputFileSettings};
     use audio_processor_traits::{AudioContext, AudioProcessor};
 
     use crate::audio::multi_track_looper::looper_voice::LooperVoice;
@@ -144,6 +232,7 @@ mod test {
     use crate::services::project_manager::{
         LoadLatestProjectMessage, ProjectManager, SaveProjectMessage,
     };
+    use crate::services::sample_library::ScanSampleLibraryMessage;
     use crate::{controllers, LooperOptions, MultiTrackLooper};
 
     use super::*;
@@ -161,6 +250,73 @@ mod test {
         assert!(level > 0.1);
     }
 
+    #[test]
+    fn test_load_file_reads_slice_markers() {
+        wisual_logger::init_from_env();
+        let data_path = tempdir::TempDir::new("looper_processor__audio_clip_manager").unwrap();
+        let file_path = data_path.path().join("loop.wav");
+
+        let mut file_settings = AudioProcessorSettings::default();
+        file_settings.set_sample_rate(22050.0);
+        let mut output = OutputAudioFileProcessor::new(
+            file_settings,
+            OutputFileSettings {
+                markers: vec![CueMarker::new(0, "1"), CueMarker::new(11025, "2")],
+                ..OutputFileSettings::new(file_path.to_str().unwrap())
+            },
+        );
+        output.prepare(file_settings).unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 22050);
+        output.process(&mut buffer).unwrap();
+        output.finalize().unwrap();
+
+        let mut manager = AudioClipManager::default();
+        let clip = manager.load_at_path(&file_path).unwrap();
+        assert_eq!(clip.metadata().unwrap().markers.len(), 2);
+        assert_eq!(clip.slice_positions(), vec![0, 22050]);
+        assert_eq!(clip.tempo(), None);
+    }
+
+    #[test]
+    fn test_load_clip_message_uses_the_sample_library_entry() {
+        wisual_logger::init_from_env();
+        let data_path = tempdir::TempDir::new("looper_processor__audio_clip_manager").unwrap();
+        let root = data_path.path().join("samples");
+        std::fs::create_dir_all(&root).unwrap();
+        let file_path = root.join("loop 96bpm.wav");
+
+        let settings = AudioProcessorSettings::default();
+        let mut output = OutputAudioFileProcessor::from_path(settings, file_path.to_str().unwrap());
+        output.prepare(settings).unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 4800);
+        output.process(&mut buffer).unwrap();
+        output.finalize().unwrap();
+
+        let sample_library =
+            ActorSystem::start(SampleLibrary::new(&root, data_path.path().join("library")));
+        ActorSystem::current()
+            .spawn_result({
+                let sample_library = sample_library.clone();
+                async move { sample_library.send(ScanSampleLibraryMessage).await }
+            })
+            .unwrap()
+            .unwrap();
+
+        let audio_clip_manager = ActorSystem::start(AudioClipManager::new(sample_library));
+        let clip = ActorSystem::current()
+            .spawn_result(async move {
+                audio_clip_manager
+                    .send(LoadClipMessage { path: file_path })
+                    .await
+            })
+            .unwrap()
+            .unwrap();
+        assert!(clip.sample_entry().is_some());
+        assert_eq!(clip.tempo(), Some(96.0));
+    }
+
     #[test]
     fn test_roundtrip_to_file() {
         wisual_logger::init_from_env();
@@ -183,6 +339,7 @@ mod test {
         let mut looper = MultiTrackLooper::new(LooperOptions::default(), 1);
         let voice: &LooperVoice = &looper.handle().voices()[0];
         voice.looper().set_looper_buffer(&input_buffer);
+        voice.looper().set_clip_tempo(Some(100.0));
 
         // Save its project
         let handle = looper.handle().clone();
@@ -220,6 +377,7 @@ mod test {
         assert_eq!(buffer.num_channels(), input_buffer.num_channels());
         assert_eq!(buffer, input_buffer);
         assert_eq!(voice.looper().state(), LooperState::Paused);
+        assert_eq!(voice.looper().clip_tempo(), Some(100.0));
 
         // ======================================================================
         // Playback tests
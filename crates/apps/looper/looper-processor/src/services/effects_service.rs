@@ -21,13 +21,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 use audio_processor_bitcrusher::BitCrusherProcessor;
+use audio_processor_convolution::ConvolutionProcessor;
+use audio_processor_time::mod_reverb::ModReverbProcessor;
 use audio_processor_time::{FreeverbProcessor, MonoDelayProcessor};
 use audio_processor_traits::parameters::{
     AudioProcessorHandleProvider, AudioProcessorHandleRef, ParameterSpec,
 };
+use audio_processor_waveshaper::WaveshaperProcessor;
 use augmented_dsp_filters::rbj::FilterProcessor;
 
-use crate::audio::multi_track_looper::effects_processor::EffectType;
+use crate::audio::multi_track_looper::effects_processor::{unit_impulse_response, EffectType};
 
 fn build_parameters_model(ty: EffectType, handle: AudioProcessorHandleRef) -> EffectDefinition {
     let name = handle.name();
@@ -82,6 +85,18 @@ impl EffectsService {
                 EffectType::EffectTypeFilter,
                 FilterProcessor::default().generic_handle(),
             ),
+            (
+                EffectType::EffectTypeModReverb,
+                ModReverbProcessor::default().generic_handle(),
+            ),
+            (
+                EffectType::EffectTypeWaveshaper,
+                WaveshaperProcessor::default().generic_handle(),
+            ),
+            (
+                EffectType::EffectTypeConvolution,
+                ConvolutionProcessor::new(unit_impulse_response()).generic_handle(),
+            ),
         ]
         .map(|(ty, handle)| build_parameters_model(ty, handle))
         .to_vec()
@@ -99,6 +114,9 @@ mod test {
         assert_eq!(effects[1].name, "Delay");
         assert_eq!(effects[2].name, "Bit-crusher");
         assert_eq!(effects[3].name, "Filter");
+        assert_eq!(effects[4].name, "Mod Reverb");
+        assert_eq!(effects[5].name, "Waveshaper");
+        assert_eq!(effects[6].name, "Convolution");
     }
 
     #[test]
@@ -29,3 +29,5 @@ pub mod defaults_service;
 pub mod effects_service;
 pub mod osc_server;
 pub mod project_manager;
+pub mod sample_library;
+pub mod stem_export;
@@ -55,12 +55,17 @@ pub struct ProjectManager {
 
 pub const PROJECT_MANAGER_DATA_PATH_KEY: &str = "CONTINUOUS_DATA_PATH";
 
+/// The app's data directory, overridden by the `CONTINUOUS_DATA_PATH` environment variable
+pub fn default_data_path() -> PathBuf {
+    std::env::var(PROJECT_MANAGER_DATA_PATH_KEY)
+        .ok()
+        .map(PathBuf::from)
+        .unwrap_or_else(data_path)
+}
+
 impl Default for ProjectManager {
     fn default() -> Self {
-        let data_path = std::env::var(PROJECT_MANAGER_DATA_PATH_KEY)
-            .ok()
-            .map(PathBuf::from)
-            .unwrap_or_else(data_path);
+        let data_path = default_data_path();
         log::info!("Data-path: {:?}", data_path);
         Self::new(data_path)
     }
@@ -211,7 +216,10 @@ fn persist_handle_clips(
             let clip_path = project_path.join(format!("looper_{}.wav", voice.id));
             let clip = voice.looper().looper_clip();
 
-            write_looper_clip(settings, &clip_path, &clip);
+            if let Err(err) = write_looper_clip(settings, &clip_path, &clip) {
+                log::error!("Failed to write looper clip {:?}: {}", clip_path, err);
+                return None;
+            }
 
             Some(clip_path)
         })
@@ -266,6 +274,60 @@ mod test {
         assert!(!latest_project.voices.is_empty());
     }
 
+    #[test]
+    fn test_project_persists_lfo_settings() {
+        use crate::parameters::{
+            LFODivision, LFOParameter, LFORetriggerMode, LooperId, ParameterId,
+        };
+
+        let looper = MultiTrackLooper::default();
+        let handle = looper.handle();
+        handle.set_lfo_division(LooperId(0), 1, LFODivision::LFODivisionEighth);
+        handle.set_lfo_retrigger_mode(LooperId(0), 1, LFORetriggerMode::LFORetriggerModeLoopStart);
+        handle.set_lfo_parameter(LooperId(0), 1, LFOParameter::LFOParameterPhase, 0.25);
+
+        let project = project_from_handle(handle, vec![]);
+        let buffer = rmp_serde::to_vec(&project).unwrap();
+        let project: Project = rmp_serde::from_slice(&buffer).unwrap();
+
+        let values = &project.voices[0].parameter_values;
+        let division = values.get(ParameterId::ParameterIdLFO(
+            1,
+            LFOParameter::LFOParameterDivision,
+        ));
+        assert_eq!(
+            division.as_enum(),
+            usize::from(LFODivision::LFODivisionEighth)
+        );
+        let retrigger = values.get(ParameterId::ParameterIdLFO(
+            1,
+            LFOParameter::LFOParameterRetrigger,
+        ));
+        assert_eq!(
+            retrigger.as_enum(),
+            usize::from(LFORetriggerMode::LFORetriggerModeLoopStart)
+        );
+        let phase = values.get(ParameterId::ParameterIdLFO(
+            1,
+            LFOParameter::LFOParameterPhase,
+        ));
+        assert!((phase.as_float() - 0.25).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_project_persists_clip_tempo() {
+        let looper = MultiTrackLooper::default();
+        let handle = looper.handle();
+        handle.voices()[1].looper().set_clip_tempo(Some(95.0));
+
+        let project = project_from_handle(handle, vec![]);
+        let buffer = rmp_serde::to_vec(&project).unwrap();
+        let project: Project = rmp_serde::from_slice(&buffer).unwrap();
+
+        assert_eq!(project.voices[0].clip_tempo, None);
+        assert_eq!(project.voices[1].clip_tempo, Some(95.0));
+    }
+
     #[actix::test]
     async fn test_actor_load_latest_project() {
         wisual_logger::init_from_env();
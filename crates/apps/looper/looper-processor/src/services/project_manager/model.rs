@@ -55,6 +55,10 @@ pub struct LooperVoicePersist {
     pub triggers: TrackTriggerModelPersist,
     pub lfo1: LFOHandleMap,
     pub lfo2: LFOHandleMap,
+    /// Tempo the clip was recorded at, so time-stretching picks up where it was. Projects saved
+    /// before this was stored default to `None`.
+    #[serde(default)]
+    pub clip_tempo: Option<f32>,
 }
 
 impl From<&LooperVoice> for LooperVoicePersist {
@@ -65,6 +69,7 @@ impl From<&LooperVoice> for LooperVoicePersist {
             triggers: TrackTriggerModelPersist::from(voice.trigger_model().deref()),
             lfo1: voice.lfo1().map().clone(),
             lfo2: voice.lfo2().map().clone(),
+            clip_tempo: voice.looper().clip_tempo(),
         }
     }
 }
@@ -0,0 +1,699 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Indexes a folder of audio files, caching their duration, sample rate, loudness, tempo &
+//! waveform peaks, so the UI never has to decode a sample to list or draw it.
+//!
+//! The index & peak files live in the library's data path. [`SampleLibrary`] is an actor that
+//! watches the folder & re-indexes files as they change.
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use actix::{Actor, Addr, AsyncContext, Handler, Message};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use audio_processor_file::file_io::{find_audio_files, is_audio_file, AudioFileError};
+use audio_processor_file::levels::measure_levels;
+use audio_processor_file::metadata::read_metadata;
+use audio_processor_file::InMemoryAudioFile;
+use audio_processor_traits::AudioProcessorSettings;
+
+use crate::services::project_manager::default_data_path;
+
+use self::peaks::PeakFile;
+
+pub mod peaks;
+
+/// Bumped when the index or the contents of peak files change, so they are rebuilt
+const INDEX_VERSION: u32 = 2;
+const INDEX_FILE_NAME: &str = "index.msgpack";
+const LIBRARY_DIRECTORY: &str = "SampleLibrary";
+const PEAKS_DIRECTORY: &str = "peaks";
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SampleLibraryError {
+    #[error("IO error {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to read audio file {0}")]
+    AudioFile(#[from] AudioFileError),
+    #[error("Decode index error {0}")]
+    DecodeIndex(#[from] rmp_serde::decode::Error),
+    #[error("Encode index error {0}")]
+    EncodeIndex(#[from] rmp_serde::encode::Error),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampleEntry {
+    pub path: PathBuf,
+    pub file_size: u64,
+    /// Modification time in milliseconds since the epoch, used to detect changes
+    pub modified_ms: u64,
+    pub duration_secs: f32,
+    pub sample_rate: u32,
+    pub num_channels: usize,
+    /// RMS level over the whole file, in dBFS
+    pub loudness_db: f32,
+    pub peak_db: f32,
+    /// From the file's tags, or a `120bpm` style file name
+    pub tempo: Option<f32>,
+    /// Name of the peaks file in the library's peaks directory
+    pub peaks_file: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SampleLibraryIndex {
+    pub version: u32,
+    pub entries: BTreeMap<PathBuf, SampleEntry>,
+}
+
+impl Default for SampleLibraryIndex {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+impl ScanSummary {
+    fn has_changes(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
+}
+
+pub struct SampleLibrary {
+    root: PathBuf,
+    data_path: PathBuf,
+    index: SampleLibraryIndex,
+    // This needs to be kept around otherwise the watcher will stop when dropped
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl Default for SampleLibrary {
+    /// Index the app's data directory, which holds the clips of saved projects, keeping the index
+    /// in its `SampleLibrary` sub-directory
+    fn default() -> Self {
+        let data_path = default_data_path();
+        Self::new(&data_path, data_path.join(LIBRARY_DIRECTORY))
+    }
+}
+
+impl SampleLibrary {
+    /// Index the files under `root`, keeping the index & peak files in `data_path`. An existing
+    /// index in `data_path` is loaded, so only changed files are analyzed on the next scan.
+    pub fn new(root: impl Into<PathBuf>, data_path: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let data_path = data_path.into();
+        let index = load_index(&data_path.join(INDEX_FILE_NAME))
+            .map_err(|err| log::warn!("Failed to load sample library index, re-creating: {}", err))
+            .unwrap_or_default();
+        log::info!(
+            "Sample library root={:?} data_path={:?} entries={}",
+            root,
+            data_path,
+            index.entries.len()
+        );
+
+        Self {
+            root,
+            data_path,
+            index,
+            watcher: None,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &SampleEntry> {
+        self.index.entries.values()
+    }
+
+    pub fn entry(&self, path: &Path) -> Option<&SampleEntry> {
+        self.index.entries.get(path)
+    }
+
+    /// Re-index every supported file under the root, analyzing only new & modified files
+    pub fn scan(&mut self) -> Result<ScanSummary, SampleLibraryError> {
+        let mut paths = vec![];
+        find_audio_files(&self.root, &mut paths)?;
+        let found: HashSet<&PathBuf> = paths.iter().collect();
+        let removed: Vec<PathBuf> = self
+            .index
+            .entries
+            .keys()
+            .filter(|path| !found.contains(path))
+            .cloned()
+            .collect();
+
+        let mut summary = ScanSummary::default();
+        for path in paths.iter().chain(removed.iter()) {
+            self.update_path(path, &mut summary);
+        }
+        log::info!("Sample library scan finished {:?}", summary);
+
+        if summary.has_changes() {
+            self.save_index()?;
+        }
+        Ok(summary)
+    }
+
+    /// Re-index only `paths`, such as after a file-system event
+    pub fn update_paths(&mut self, paths: &[PathBuf]) -> Result<ScanSummary, SampleLibraryError> {
+        let mut summary = ScanSummary::default();
+        for path in paths {
+            if !path.starts_with(&self.root) {
+                continue;
+            }
+
+            if path.is_dir() {
+                // A directory moved into the library may only be reported by its own path
+                let mut found = vec![];
+                find_audio_files(path, &mut found)?;
+                for found_path in found {
+                    self.update_path(&found_path, &mut summary);
+                }
+            } else if path.exists() {
+                if is_audio_file(path) {
+                    self.update_path(path, &mut summary);
+                }
+            } else {
+                // Either a file or a whole directory was removed, drop every entry under it
+                let removed: Vec<PathBuf> = self
+                    .index
+                    .entries
+                    .keys()
+                    .filter(|entry_path| entry_path.starts_with(path))
+                    .cloned()
+                    .collect();
+                for entry_path in removed {
+                    self.update_path(&entry_path, &mut summary);
+                }
+            }
+        }
+        if summary.has_changes() {
+            self.save_index()?;
+        }
+        Ok(summary)
+    }
+
+    /// Read the cached peaks for the sample at `path`
+    pub fn peaks(&self, path: &Path) -> Result<Option<PeakFile>, SampleLibraryError> {
+        match self.entry(path) {
+            Some(entry) => {
+                let contents = std::fs::read(self.peaks_path(&entry.peaks_file))?;
+                Ok(Some(rmp_serde::from_slice(&contents)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn update_path(&mut self, path: &Path, summary: &mut ScanSummary) {
+        let file_metadata = match std::fs::metadata(path) {
+            Ok(file_metadata) if file_metadata.is_file() => file_metadata,
+            _ => {
+                if let Some(entry) = self.index.entries.remove(path) {
+                    let _ = std::fs::remove_file(self.peaks_path(&entry.peaks_file));
+                    summary.removed += 1;
+                }
+                return;
+            }
+        };
+        let file_size = file_metadata.len();
+        let modified_ms = file_metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_millis() as u64)
+            .unwrap_or(0);
+
+        let existing = self.index.entries.get(path);
+        if let Some(entry) = existing {
+            if entry.file_size == file_size
+                && entry.modified_ms == modified_ms
+                && self.peaks_path(&entry.peaks_file).exists()
+            {
+                summary.unchanged += 1;
+                return;
+            }
+        }
+        let is_update = existing.is_some();
+        let peaks_file = existing
+            .map(|entry| entry.peaks_file.clone())
+            .unwrap_or_else(|| format!("{}.peaks", uuid::Uuid::new_v4()));
+
+        match self.analyze(path, file_size, modified_ms, peaks_file) {
+            Ok(entry) => {
+                if is_update {
+                    summary.updated += 1;
+                } else {
+                    summary.added += 1;
+                }
+                self.index.entries.insert(path.into(), entry);
+            }
+            Err(err) => {
+                log::warn!("Failed to index {:?}: {}", path, err);
+                self.index.entries.remove(path);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    fn analyze(
+        &self,
+        path: &Path,
+        file_size: u64,
+        modified_ms: u64,
+        peaks_file: String,
+    ) -> Result<SampleEntry, SampleLibraryError> {
+        log::debug!("Analyzing {:?}", path);
+        let metadata = read_metadata(path)?;
+
+        // Read the file at its own rate & channel count
+        let mut settings = AudioProcessorSettings::default();
+        if let Some(sample_rate) = metadata.sample_rate {
+            settings.set_sample_rate(sample_rate as f32);
+        }
+        if let Some(num_channels) = metadata.num_channels {
+            settings.set_output_channels(num_channels);
+        }
+        let mut audio_file = InMemoryAudioFile::from_path(&path.to_string_lossy())?;
+        let buffer = audio_file.read_into_vec_audio_buffer(&settings)?;
+
+        let peaks = PeakFile::from_buffer(&buffer);
+        std::fs::create_dir_all(self.data_path.join(PEAKS_DIRECTORY))?;
+        std::fs::write(self.peaks_path(&peaks_file), rmp_serde::to_vec(&peaks)?)?;
+
+        let levels = measure_levels(buffer.channels());
+        Ok(SampleEntry {
+            path: path.into(),
+            file_size,
+            modified_ms,
+            duration_secs: buffer.num_samples() as f32 / settings.sample_rate(),
+            sample_rate: settings.sample_rate() as u32,
+            num_channels: buffer.num_channels(),
+            loudness_db: levels.rms_db,
+            peak_db: levels.peak_db,
+            tempo: metadata.tempo().or_else(|| tempo_from_file_name(path)),
+            peaks_file,
+        })
+    }
+
+    fn peaks_path(&self, peaks_file: &str) -> PathBuf {
+        self.data_path.join(PEAKS_DIRECTORY).join(peaks_file)
+    }
+
+    fn save_index(&self) -> Result<(), SampleLibraryError> {
+        std::fs::create_dir_all(&self.data_path)?;
+        let contents = rmp_serde::to_vec(&self.index)?;
+        std::fs::write(self.data_path.join(INDEX_FILE_NAME), contents)?;
+        Ok(())
+    }
+
+    fn start_watcher(&mut self, address: Addr<Self>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = match watcher(tx, WATCH_DEBOUNCE) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                log::error!("Failed to create sample library watcher: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = watcher.watch(&self.root, RecursiveMode::Recursive) {
+            log::error!("Failed to watch sample library {:?}: {}", self.root, err);
+            return;
+        }
+
+        std::thread::Builder::new()
+            .name(String::from("sample-library-watcher"))
+            .spawn(move || {
+                // Finishes when the watcher is dropped
+                while let Ok(event) = rx.recv() {
+                    match event {
+                        DebouncedEvent::Rescan => address.do_send(ScanSampleLibraryMessage),
+                        event => {
+                            let paths = event_paths(event);
+                            if !paths.is_empty() {
+                                address.do_send(FilesChangedMessage { paths });
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("Failed to spawn sample library watcher thread");
+        self.watcher = Some(watcher);
+    }
+}
+
+impl Actor for SampleLibrary {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Err(err) = std::fs::create_dir_all(&self.root) {
+            log::error!("Failed to create sample library {:?}: {}", self.root, err);
+        }
+        self.start_watcher(ctx.address());
+        ctx.address().do_send(ScanSampleLibraryMessage);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<ScanSummary, SampleLibraryError>")]
+pub struct ScanSampleLibraryMessage;
+
+impl Handler<ScanSampleLibraryMessage> for SampleLibrary {
+    type Result = Result<ScanSummary, SampleLibraryError>;
+
+    fn handle(&mut self, _msg: ScanSampleLibraryMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.scan()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<ScanSummary, SampleLibraryError>")]
+pub struct FilesChangedMessage {
+    pub paths: Vec<PathBuf>,
+}
+
+impl Handler<FilesChangedMessage> for SampleLibrary {
+    type Result = Result<ScanSummary, SampleLibraryError>;
+
+    fn handle(&mut self, msg: FilesChangedMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.update_paths(&msg.paths)
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<SampleEntry>")]
+pub struct GetSampleEntriesMessage;
+
+impl Handler<GetSampleEntriesMessage> for SampleLibrary {
+    type Result = Vec<SampleEntry>;
+
+    fn handle(&mut self, _msg: GetSampleEntriesMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.entries().cloned().collect()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<SampleEntry>")]
+pub struct GetSampleEntryMessage {
+    pub path: PathBuf,
+}
+
+impl Handler<GetSampleEntryMessage> for SampleLibrary {
+    type Result = Option<SampleEntry>;
+
+    fn handle(&mut self, msg: GetSampleEntryMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.entry(&msg.path).cloned()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Option<PeakFile>, SampleLibraryError>")]
+pub struct GetSamplePeaksMessage {
+    pub path: PathBuf,
+}
+
+impl Handler<GetSamplePeaksMessage> for SampleLibrary {
+    type Result = Result<Option<PeakFile>, SampleLibraryError>;
+
+    fn handle(&mut self, msg: GetSamplePeaksMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.peaks(&msg.path)
+    }
+}
+
+fn load_index(index_path: &Path) -> Result<SampleLibraryIndex, SampleLibraryError> {
+    if !index_path.exists() {
+        return Ok(SampleLibraryIndex::default());
+    }
+    let index: SampleLibraryIndex = rmp_serde::from_slice(&std::fs::read(index_path)?)?;
+    if index.version != INDEX_VERSION {
+        log::warn!("Ignoring sample library index version={}", index.version);
+        return Ok(SampleLibraryIndex::default());
+    }
+    Ok(index)
+}
+
+fn event_paths(event: DebouncedEvent) -> Vec<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path) => {
+            vec![path]
+        }
+        DebouncedEvent::Rename(from, to) => vec![from, to],
+        _ => vec![],
+    }
+}
+
+/// Parse tempos from names such as `drums_120bpm.wav` or `Bass 92.5 BPM.wav`
+fn tempo_from_file_name(path: &Path) -> Option<f32> {
+    let name = path.file_stem()?.to_str()?.to_lowercase();
+    let position = name.find("bpm")?;
+    let before = name[..position].trim_end_matches([' ', '_', '-']);
+    let start = before
+        .rfind(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|index| index + 1)
+        .unwrap_or(0);
+    let tempo: f32 = before[start..].parse().ok()?;
+    Some(tempo).filter(|tempo| (20.0..=400.0).contains(tempo))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_system_threads::ActorSystem;
+    use audio_processor_file::{OutputAudioFileProcessor, OutputFileSettings, OutputSampleFormat};
+    use audio_processor_traits::AudioBuffer;
+
+    use super::*;
+
+    fn write_sine(path: &Path, num_frames: usize, amplitude: f32) {
+        let mut settings = AudioProcessorSettings::default();
+        settings.set_sample_rate(48000.0);
+        let mut output = OutputAudioFileProcessor::new(
+            settings,
+            OutputFileSettings {
+                format: OutputSampleFormat::Float32,
+                ..OutputFileSettings::new(path.to_str().unwrap())
+            },
+        );
+        output.prepare(settings).unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, num_frames);
+        for frame in 0..num_frames {
+            let value =
+                amplitude * (frame as f32 / 48000.0 * 440.0 * 2.0 * std::f32::consts::PI).sin();
+            buffer.set(0, frame, value);
+            buffer.set(1, frame, value);
+        }
+        output.process(&mut buffer).unwrap();
+        output.finalize().unwrap();
+    }
+
+    struct Setup {
+        _directory: tempdir::TempDir,
+        root: PathBuf,
+        data_path: PathBuf,
+    }
+
+    fn setup() -> Setup {
+        wisual_logger::init_from_env();
+        let directory = tempdir::TempDir::new("looper_processor__sample_library").unwrap();
+        let root = directory.path().join("samples");
+        std::fs::create_dir_all(root.join("drums")).unwrap();
+        write_sine(&root.join("drums/loop_120bpm.wav"), 48000, 1.0);
+        write_sine(&root.join("pad.wav"), 24000, 0.5);
+        std::fs::write(root.join("notes.txt"), "not audio").unwrap();
+
+        Setup {
+            root,
+            data_path: directory.path().join("data"),
+            _directory: directory,
+        }
+    }
+
+    #[test]
+    fn test_scan_indexes_audio_files() {
+        let setup = setup();
+        let mut library = SampleLibrary::new(&setup.root, &setup.data_path);
+        let summary = library.scan().unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                added: 2,
+                ..ScanSummary::default()
+            }
+        );
+
+        let entry = library
+            .entry(&setup.root.join("drums/loop_120bpm.wav"))
+            .unwrap();
+        assert_eq!(entry.sample_rate, 48000);
+        assert_eq!(entry.num_channels, 2);
+        assert!((entry.duration_secs - 1.0).abs() < 0.01);
+        assert_eq!(entry.tempo, Some(120.0));
+        // A full-scale sine is at -3dB RMS
+        assert!(
+            (entry.loudness_db - -3.0).abs() < 0.1,
+            "{}",
+            entry.loudness_db
+        );
+        assert!(entry.peak_db.abs() < 0.1);
+
+        let entry = library.entry(&setup.root.join("pad.wav")).unwrap();
+        assert_eq!(entry.tempo, None);
+        assert!(
+            (entry.loudness_db - -9.0).abs() < 0.1,
+            "{}",
+            entry.loudness_db
+        );
+
+        let peaks = library.peaks(&setup.root.join("pad.wav")).unwrap().unwrap();
+        assert!((peaks.num_frames as i64 - 24000).abs() <= 1);
+        assert!(peaks
+            .peaks(100)
+            .iter()
+            .all(|(min, max)| *max > 0.45 && *min < -0.45));
+    }
+
+    #[test]
+    fn test_rescans_are_incremental_and_persisted() {
+        let setup = setup();
+        let mut library = SampleLibrary::new(&setup.root, &setup.data_path);
+        library.scan().unwrap();
+
+        // A new library instance picks up the persisted index
+        let mut library = SampleLibrary::new(&setup.root, &setup.data_path);
+        assert_eq!(library.entries().count(), 2);
+        assert_eq!(library.scan().unwrap().unchanged, 2);
+
+        write_sine(&setup.root.join("pad.wav"), 48000, 0.5);
+        std::fs::remove_file(setup.root.join("drums/loop_120bpm.wav")).unwrap();
+        let summary = library.scan().unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                updated: 1,
+                removed: 1,
+                ..ScanSummary::default()
+            }
+        );
+        let entry = library.entry(&setup.root.join("pad.wav")).unwrap();
+        assert!((entry.duration_secs - 1.0).abs() < 0.01);
+        assert_eq!(
+            std::fs::read_dir(setup.data_path.join(PEAKS_DIRECTORY))
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_update_paths_handles_directories() {
+        let setup = setup();
+        let mut library = SampleLibrary::new(&setup.root, &setup.data_path);
+        library.scan().unwrap();
+
+        // A removed directory is reported by its own path only
+        let drums = setup.root.join("drums");
+        std::fs::remove_dir_all(&drums).unwrap();
+        let summary = library.update_paths(&[drums.clone()]).unwrap();
+        assert_eq!(summary.removed, 1);
+        assert_eq!(library.entries().count(), 1);
+        assert!(library.entry(&drums.join("loop_120bpm.wav")).is_none());
+
+        // So is a directory moved into the library
+        let moved_in = setup.root.join("moved");
+        std::fs::create_dir_all(moved_in.join("nested")).unwrap();
+        write_sine(&moved_in.join("nested/hit.wav"), 4800, 1.0);
+        let summary = library.update_paths(&[moved_in.clone()]).unwrap();
+        assert_eq!(summary.added, 1);
+        assert!(library.entry(&moved_in.join("nested/hit.wav")).is_some());
+    }
+
+    #[test]
+    fn test_changed_files_are_reindexed_by_the_actor() {
+        let setup = setup();
+        let library = ActorSystem::start(SampleLibrary::new(&setup.root, &setup.data_path));
+        // Wait for the initial scan
+        ActorSystem::current()
+            .spawn_result({
+                let library = library.clone();
+                async move { library.send(ScanSampleLibraryMessage).await }
+            })
+            .unwrap()
+            .unwrap();
+
+        let new_file = setup.root.join("drums/fill 90 BPM.wav");
+        write_sine(&new_file, 4800, 1.0);
+        let summary = ActorSystem::current()
+            .spawn_result({
+                let library = library.clone();
+                let paths = vec![new_file.clone(), setup.root.join("notes.txt")];
+                async move { library.send(FilesChangedMessage { paths }).await }
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.added, 1);
+
+        let entries = ActorSystem::current()
+            .spawn_result(async move { library.send(GetSampleEntriesMessage).await })
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        let entry = entries.iter().find(|entry| entry.path == new_file).unwrap();
+        assert_eq!(entry.tempo, Some(90.0));
+    }
+
+    #[test]
+    fn test_tempo_from_file_name() {
+        let tempo = |name: &str| tempo_from_file_name(Path::new(name));
+        assert_eq!(tempo("drums_120bpm.wav"), Some(120.0));
+        assert_eq!(tempo("Bass 92.5 BPM.wav"), Some(92.5));
+        assert_eq!(tempo("174-bpm-break.wav"), Some(174.0));
+        assert_eq!(tempo("bpm.wav"), None);
+        assert_eq!(tempo("kick.wav"), None);
+        assert_eq!(tempo("loop_9999bpm.wav"), None);
+    }
+
+    #[test]
+    fn test_event_paths() {
+        assert_eq!(
+            event_paths(DebouncedEvent::Rename("a.wav".into(), "b.wav".into())),
+            vec![PathBuf::from("a.wav"), PathBuf::from("b.wav")]
+        );
+        assert!(event_paths(DebouncedEvent::Chmod("a.wav".into())).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Multi-resolution min/max peaks, so waveforms can be drawn at any zoom level without reading
+//! the audio file.
+use serde::{Deserialize, Serialize};
+
+use audio_processor_traits::AudioBuffer;
+
+/// Frames per bucket of the finest level
+const BASE_FRAMES_PER_BUCKET: usize = 64;
+/// Each level has this many times fewer buckets than the previous one
+const LEVEL_FACTOR: usize = 4;
+/// Levels stop being generated once they'd have fewer buckets than this
+const MIN_BUCKETS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeakLevel {
+    pub frames_per_bucket: usize,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+impl PeakLevel {
+    pub fn len(&self) -> usize {
+        self.min.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.is_empty()
+    }
+
+    /// Divide the resolution of this level by `LEVEL_FACTOR`
+    fn downsample(&self) -> PeakLevel {
+        let min = self
+            .min
+            .chunks(LEVEL_FACTOR)
+            .map(|chunk| chunk.iter().copied().fold(f32::MAX, f32::min))
+            .collect();
+        let max = self
+            .max
+            .chunks(LEVEL_FACTOR)
+            .map(|chunk| chunk.iter().copied().fold(f32::MIN, f32::max))
+            .collect();
+        PeakLevel {
+            frames_per_bucket: self.frames_per_bucket * LEVEL_FACTOR,
+            min,
+            max,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeakFile {
+    pub num_frames: usize,
+    /// Finest level first
+    pub levels: Vec<PeakLevel>,
+}
+
+impl PeakFile {
+    /// Build the peaks of all channels of `buffer`, the min/max of each bucket is taken across
+    /// channels
+    pub fn from_buffer(buffer: &AudioBuffer<f32>) -> Self {
+        let num_frames = buffer.num_samples();
+        let num_buckets = num_frames.div_ceil(BASE_FRAMES_PER_BUCKET);
+        let mut base = PeakLevel {
+            frames_per_bucket: BASE_FRAMES_PER_BUCKET,
+            min: vec![f32::MAX; num_buckets],
+            max: vec![f32::MIN; num_buckets],
+        };
+        for channel in buffer.channels() {
+            for (bucket, samples) in channel.chunks(BASE_FRAMES_PER_BUCKET).enumerate() {
+                base.min[bucket] = samples.iter().copied().fold(base.min[bucket], f32::min);
+                base.max[bucket] = samples.iter().copied().fold(base.max[bucket], f32::max);
+            }
+        }
+
+        let mut levels = vec![base];
+        while levels[levels.len() - 1].len() / LEVEL_FACTOR >= MIN_BUCKETS {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+
+        Self { num_frames, levels }
+    }
+
+    /// The coarsest level that still has at least `num_buckets` buckets
+    pub fn level_for(&self, num_buckets: usize) -> Option<&PeakLevel> {
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| level.len() >= num_buckets)
+            .or_else(|| self.levels.first())
+    }
+
+    /// Exactly `num_buckets` `(min, max)` pairs covering the whole file, e.g. one per pixel
+    pub fn peaks(&self, num_buckets: usize) -> Vec<(f32, f32)> {
+        let level = match self.level_for(num_buckets) {
+            Some(level) if !level.is_empty() && num_buckets > 0 => level,
+            _ => return vec![],
+        };
+
+        (0..num_buckets)
+            .map(|bucket| {
+                let start = bucket * level.len() / num_buckets;
+                let end = ((bucket + 1) * level.len() / num_buckets).max(start + 1);
+                let end = end.min(level.len());
+                let min = level.min[start..end]
+                    .iter()
+                    .copied()
+                    .fold(f32::MAX, f32::min);
+                let max = level.max[start..end]
+                    .iter()
+                    .copied()
+                    .fold(f32::MIN, f32::max);
+                (min, max)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ramp(num_frames: usize) -> AudioBuffer<f32> {
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, num_frames);
+        for frame in 0..num_frames {
+            let value = frame as f32 / num_frames as f32;
+            buffer.set(0, frame, value);
+            buffer.set(1, frame, -value);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_levels_are_generated_down_to_the_minimum_resolution() {
+        let peaks = PeakFile::from_buffer(&ramp(64 * 1024));
+        let frames_per_bucket: Vec<usize> = peaks
+            .levels
+            .iter()
+            .map(|level| level.frames_per_bucket)
+            .collect();
+        assert_eq!(frames_per_bucket, vec![64, 256, 1024]);
+        assert_eq!(peaks.levels[0].len(), 1024);
+        assert_eq!(peaks.levels[2].len(), 64);
+    }
+
+    #[test]
+    fn test_buckets_hold_the_min_and_max_across_channels() {
+        let peaks = PeakFile::from_buffer(&ramp(64 * 1024));
+        let level = &peaks.levels[0];
+        assert_eq!(level.max[1], 127.0 / (64.0 * 1024.0));
+        assert_eq!(level.min[1], -127.0 / (64.0 * 1024.0));
+        assert_eq!(peaks.levels[1].max[0], level.max[3]);
+    }
+
+    #[test]
+    fn test_buckets_that_do_not_cross_zero() {
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 1024);
+        for frame in 0..1024 {
+            buffer.set(0, frame, 0.25 + frame as f32 / 4096.0);
+        }
+        let peaks = PeakFile::from_buffer(&buffer);
+        let level = &peaks.levels[0];
+        assert_eq!(level.min[0], 0.25);
+        assert_eq!(level.max[0], 0.25 + 63.0 / 4096.0);
+        assert_eq!(level.min[1], 0.25 + 64.0 / 4096.0);
+        assert!(peaks.peaks(4).iter().all(|(min, _)| *min >= 0.25));
+
+        for sample in buffer.slice_mut() {
+            *sample = -*sample;
+        }
+        let peaks = PeakFile::from_buffer(&buffer);
+        assert_eq!(peaks.levels[0].max[0], -0.25);
+        assert!(peaks.peaks(4).iter().all(|(_, max)| *max <= -0.25));
+    }
+
+    #[test]
+    fn test_peaks_for_a_width() {
+        let peaks = PeakFile::from_buffer(&ramp(64 * 1024));
+        assert_eq!(peaks.level_for(100).unwrap().frames_per_bucket, 256);
+        assert_eq!(peaks.level_for(10).unwrap().frames_per_bucket, 1024);
+
+        let pixels = peaks.peaks(100);
+        assert_eq!(pixels.len(), 100);
+        assert!(pixels[99].1 > 0.99);
+        assert!(pixels.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    #[test]
+    fn test_short_and_empty_buffers() {
+        let peaks = PeakFile::from_buffer(&ramp(100));
+        assert_eq!(peaks.levels.len(), 1);
+        assert_eq!(peaks.peaks(10).len(), 10);
+
+        let peaks = PeakFile::from_buffer(&AudioBuffer::empty());
+        assert!(peaks.peaks(10).is_empty());
+    }
+}
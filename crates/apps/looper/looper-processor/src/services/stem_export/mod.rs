@@ -0,0 +1,357 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Bounces the processed output of each looper track, and of the full mix, into WAV files.
+//!
+//! [`export_stems`] runs a [`MultiTrackLooper`] offline through its regular `process` path, so
+//! scenes, sequencer triggers & parameter locks, LFOs, envelopes and effects apply exactly as they
+//! would during real-time playback. The transport is rewound before rendering.
+use std::path::{Path, PathBuf};
+
+use audio_processor_file::{OutputAudioFileProcessor, OutputFileError};
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor, AudioProcessorSettings};
+
+use crate::audio::processor::handle::LooperState;
+use crate::{LooperId, MultiTrackLooper, MultiTrackLooperHandle, TimeInfoProvider};
+
+pub const MASTER_FILE_NAME: &str = "master.wav";
+const DEFAULT_BEATS_PER_BAR: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StemExportError {
+    #[error("IO error {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to write stem {0}")]
+    OutputFile(#[from] OutputFileError),
+    #[error("Stems can only be exported once the tempo is set")]
+    MissingTempo,
+    #[error("Stems can't be exported while hosted, the host controls the transport")]
+    Hosted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemExportOptions {
+    /// Directory stems are written into
+    pub output_path: PathBuf,
+    /// Length of the export
+    pub bars: usize,
+    pub beats_per_bar: usize,
+}
+
+impl StemExportOptions {
+    pub fn new(output_path: impl Into<PathBuf>, bars: usize) -> Self {
+        Self {
+            output_path: output_path.into(),
+            bars,
+            beats_per_bar: DEFAULT_BEATS_PER_BAR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemExportResult {
+    /// One file per non-empty track
+    pub stems: Vec<(LooperId, PathBuf)>,
+    /// Sum of all stems, the metronome isn't included
+    pub master: PathBuf,
+    pub num_samples: usize,
+}
+
+struct StemWriter {
+    looper_id: LooperId,
+    path: PathBuf,
+    output: OutputAudioFileProcessor,
+}
+
+/// Render `bars` at the current tempo into `track_N.wav` stems & a `master.wav` mix.
+///
+/// `looper` must be prepared and mustn't be running on the audio thread, since this moves the
+/// transport of its handle. The transport & every track are left stopped or playing as they were
+/// found, with the transport & playing tracks rewound to the start.
+pub fn export_stems(
+    looper: &mut MultiTrackLooper,
+    options: &StemExportOptions,
+) -> Result<StemExportResult, StemExportError> {
+    let handle = looper.handle().clone();
+    let settings = *handle.settings();
+    let time_info = handle.time_info_provider().get_time_info();
+    let tempo = time_info.tempo().ok_or(StemExportError::MissingTempo)?;
+    let num_beats = options.bars * options.beats_per_bar;
+    let num_samples =
+        (num_beats as f64 * 60.0 / tempo * settings.sample_rate() as f64).round() as usize;
+    log::info!(
+        "Exporting stems bars={} tempo={} num_samples={} into {:?}",
+        options.bars,
+        tempo,
+        num_samples,
+        options.output_path
+    );
+
+    std::fs::create_dir_all(&options.output_path)?;
+    let mut stems = handle
+        .voices()
+        .iter()
+        .filter(|voice| !voice.looper().is_empty())
+        .map(|voice| {
+            let path = options
+                .output_path
+                .join(format!("track_{}.wav", voice.id + 1));
+            Ok(StemWriter {
+                looper_id: LooperId(voice.id),
+                output: create_output(settings, &path)?,
+                path,
+            })
+        })
+        .collect::<Result<Vec<_>, StemExportError>>()?;
+    let master_path = options.output_path.join(MASTER_FILE_NAME);
+    let mut master_output = create_output(settings, &master_path)?;
+
+    let was_playing = time_info.is_playing();
+    let voice_states = rewind(&handle);
+
+    let mut context = AudioContext::from(settings);
+    let mut buffer = AudioBuffer::empty();
+    let mut stem_buffer = AudioBuffer::empty();
+    let mut master_buffer = AudioBuffer::empty();
+    let mut rendered_samples = 0;
+    while rendered_samples < num_samples {
+        let block_size = settings.block_size().min(num_samples - rendered_samples);
+        buffer.resize(settings.output_channels(), block_size);
+        master_buffer.resize(settings.output_channels(), block_size);
+        for sample in buffer.slice_mut() {
+            *sample = 0.0;
+        }
+        for sample in master_buffer.slice_mut() {
+            *sample = 0.0;
+        }
+
+        looper.process(&mut context, &mut buffer);
+
+        for stem in stems.iter_mut() {
+            looper.copy_voice_output(stem.looper_id, &mut stem_buffer);
+            master_buffer.add(&stem_buffer);
+            stem.output.process(&mut stem_buffer)?;
+        }
+        master_output.process(&mut master_buffer)?;
+        rendered_samples += block_size;
+    }
+
+    for stem in stems.iter_mut() {
+        stem.output.finalize()?;
+    }
+    master_output.finalize()?;
+
+    handle.stop();
+    restore_voice_states(&handle, &voice_states);
+    if was_playing {
+        handle.play();
+    }
+
+    Ok(StemExportResult {
+        stems: stems
+            .into_iter()
+            .map(|stem| (stem.looper_id, stem.path))
+            .collect(),
+        master: master_path,
+        num_samples,
+    })
+}
+
+fn create_output(
+    settings: AudioProcessorSettings,
+    path: &Path,
+) -> Result<OutputAudioFileProcessor, StemExportError> {
+    let mut output = OutputAudioFileProcessor::from_path(settings, &path.to_string_lossy());
+    output.prepare(settings)?;
+    Ok(output)
+}
+
+/// Start the transport & the playing tracks from the beginning, returning the state of each track
+fn rewind(handle: &MultiTrackLooperHandle) -> Vec<LooperState> {
+    handle.stop();
+    let voice_states: Vec<LooperState> = handle
+        .voices()
+        .iter()
+        .map(|voice| voice.looper().state())
+        .collect();
+    for voice in handle.voices() {
+        if voice.looper().state() == LooperState::Playing {
+            voice.looper().trigger();
+        }
+    }
+    handle.play();
+    voice_states
+}
+
+/// Undo state changes made while rendering, such as by scheduled recordings, rewinding the tracks
+/// that were playing
+fn restore_voice_states(handle: &MultiTrackLooperHandle, voice_states: &[LooperState]) {
+    for (voice, state) in handle.voices().iter().zip(voice_states) {
+        voice.looper().restore_state(*state);
+        if *state == LooperState::Playing {
+            voice.looper().trigger();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use crate::audio::processor::handle::LooperState;
+
+    use super::*;
+
+    fn read_wav(path: &Path) -> Vec<f32> {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        reader
+            .samples::<f32>()
+            .map(|sample| sample.unwrap())
+            .collect()
+    }
+
+    fn setup() -> MultiTrackLooper {
+        let mut looper = MultiTrackLooper::new(Default::default(), 3);
+        let settings = AudioProcessorSettings {
+            sample_rate: 1000.0,
+            input_channels: 1,
+            output_channels: 1,
+            block_size: 64,
+        };
+        let mut context = AudioContext::from(settings);
+        looper.prepare(&mut context);
+        looper
+    }
+
+    fn set_clip(looper: &MultiTrackLooper, looper_id: LooperId, value: f32) {
+        let voice = &looper.handle().voices()[looper_id.0];
+        let mut clip = AudioBuffer::empty();
+        clip.resize(1, 500);
+        for sample in clip.slice_mut() {
+            *sample = value;
+        }
+        voice.looper().set_looper_buffer(&clip);
+        voice.looper().play();
+    }
+
+    #[test]
+    fn test_export_stems_writes_tracks_and_master() {
+        wisual_logger::init_from_env();
+        let directory = tempdir::TempDir::new("looper_processor__stem_export").unwrap();
+        let mut looper = setup();
+        set_clip(&looper, LooperId(0), 0.5);
+        set_clip(&looper, LooperId(2), 0.25);
+        looper.handle().set_volume(LooperId(2), 0.5);
+        looper.handle().set_tempo(60.0);
+
+        let options = StemExportOptions::new(directory.path().join("stems"), 1);
+        let result = export_stems(&mut looper, &options).unwrap();
+
+        // 1 bar of 4 beats at 60bpm
+        assert_eq!(result.num_samples, 4000);
+        assert_eq!(
+            result.stems,
+            vec![
+                (LooperId(0), options.output_path.join("track_1.wav")),
+                (LooperId(2), options.output_path.join("track_3.wav")),
+            ]
+        );
+        assert!(!options.output_path.join("track_2.wav").exists());
+
+        let track1 = read_wav(&result.stems[0].1);
+        let track3 = read_wav(&result.stems[1].1);
+        let master = read_wav(&result.master);
+        assert_eq!(track1.len(), 4000);
+        assert_eq!(master.len(), 4000);
+        assert!(track1.iter().all(|sample| *sample == 0.5));
+        assert!(track3.iter().all(|sample| *sample == 0.125));
+        assert!(master.iter().all(|sample| *sample == 0.625));
+    }
+
+    #[test]
+    fn test_export_stems_restores_transport() {
+        let directory = tempdir::TempDir::new("looper_processor__stem_export").unwrap();
+        let mut looper = setup();
+        set_clip(&looper, LooperId(0), 0.5);
+        looper.handle().set_tempo(120.0);
+        looper.handle().stop();
+
+        let options = StemExportOptions::new(directory.path(), 2);
+        let result = export_stems(&mut looper, &options).unwrap();
+        assert_eq!(result.num_samples, 4000);
+
+        let time_info = looper.handle().time_info_provider().get_time_info();
+        assert!(!time_info.is_playing());
+        assert_eq!(time_info.position_beats(), Some(0.0));
+        assert_eq!(
+            looper.handle().voices()[0].looper().state(),
+            LooperState::Playing
+        );
+    }
+
+    #[test]
+    fn test_export_stems_only_rewinds_playing_tracks() {
+        let directory = tempdir::TempDir::new("looper_processor__stem_export").unwrap();
+        let mut looper = setup();
+        set_clip(&looper, LooperId(0), 0.5);
+        set_clip(&looper, LooperId(1), 0.25);
+        looper.handle().set_tempo(60.0);
+
+        // Pause the second track half-way through its loop
+        let mut context = AudioContext::from(*looper.handle().settings());
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 64);
+        for _ in 0..4 {
+            looper.process(&mut context, &mut buffer);
+        }
+        let paused = looper.handle().voices()[1].looper();
+        paused.pause();
+        let paused_playhead = paused.playhead();
+        assert!(paused_playhead > 0);
+
+        let options = StemExportOptions::new(directory.path(), 1);
+        let result = export_stems(&mut looper, &options).unwrap();
+        assert!(read_wav(&result.stems[0].1)
+            .iter()
+            .all(|sample| *sample == 0.5));
+        assert!(read_wav(&result.stems[1].1)
+            .iter()
+            .all(|sample| *sample == 0.0));
+
+        let voices = looper.handle().voices();
+        assert_eq!(voices[0].looper().state(), LooperState::Playing);
+        assert_eq!(voices[0].looper().playhead(), 0);
+        assert_eq!(voices[1].looper().state(), LooperState::Paused);
+        assert_eq!(voices[1].looper().playhead(), paused_playhead);
+    }
+
+    #[test]
+    fn test_export_stems_requires_tempo() {
+        let directory = tempdir::TempDir::new("looper_processor__stem_export").unwrap();
+        let mut looper = setup();
+        set_clip(&looper, LooperId(0), 0.5);
+
+        let options = StemExportOptions::new(directory.path(), 1);
+        let result = export_stems(&mut looper, &options);
+        assert!(matches!(result, Err(StemExportError::MissingTempo)));
+    }
+}
@@ -31,7 +31,9 @@ use crate::audio_io::cpal_vst_buffer_handler::CpalVstBufferHandler;
 use crate::audio_io::AudioHostPluginLoadError;
 use crate::processors::audio_file_processor::file_io::AudioFileError;
 use crate::processors::audio_file_processor::AudioFileProcessor;
-use crate::processors::output_file_processor::OutputAudioFileProcessor;
+use crate::processors::output_file_processor::{
+    DitherMode, OutputAudioFileProcessor, OutputFileError, OutputFileSettings, OutputSampleFormat,
+};
 use crate::processors::test_host_processor::flush_vst_output;
 use crate::TestPluginHost;
 
@@ -41,12 +43,16 @@ pub enum OfflineRenderError {
     AudioFileError(#[from] AudioFileError),
     #[error("Failed to load plug-in")]
     AudioHostPluginLoadError(#[from] AudioHostPluginLoadError),
+    #[error("Failed to write the output file")]
+    OutputFileError(#[from] OutputFileError),
 }
 
 pub struct OfflineRenderer {
     audio_settings: AudioProcessorSettings,
     input_file_path: String,
     output_file_path: String,
+    output_format: OutputSampleFormat,
+    dither: DitherMode,
     plugin_path: String,
 }
 
@@ -61,10 +67,18 @@ impl OfflineRenderer {
             audio_settings,
             input_file_path: String::from(input_file_path),
             output_file_path: String::from(output_file_path),
+            output_format: OutputSampleFormat::default(),
+            dither: DitherMode::default(),
             plugin_path: String::from(plugin_path),
         }
     }
 
+    /// Set the sample format of the rendered file & the dither used for integer formats
+    pub fn set_output_format(&mut self, output_format: OutputSampleFormat, dither: DitherMode) {
+        self.output_format = output_format;
+        self.dither = dither;
+    }
+
     pub fn run(&self) -> Result<OfflineRenderDiagnostics, OfflineRenderError> {
         let garbage_collector = audio_garbage_collector::GarbageCollector::default();
 
@@ -75,14 +89,20 @@ impl OfflineRenderer {
             &self.input_file_path,
         )?;
         let mut plugin = TestPluginHost::load_vst_plugin(self.plugin_path.as_ref())?;
-        let mut output_file_processor =
-            OutputAudioFileProcessor::from_path(self.audio_settings, &self.output_file_path);
+        let mut output_file_processor = OutputAudioFileProcessor::new(
+            self.audio_settings,
+            OutputFileSettings {
+                format: self.output_format,
+                dither: self.dither,
+                ..OutputFileSettings::new(&self.output_file_path)
+            },
+        );
 
         plugin.set_sample_rate(self.audio_settings.sample_rate());
         plugin.set_block_size(self.audio_settings.block_size() as i64);
         let mut context = AudioContext::from(self.audio_settings);
         audio_file_processor.prepare(&mut context);
-        output_file_processor.prepare(self.audio_settings);
+        output_file_processor.prepare(self.audio_settings)?;
 
         let audio_file_buffer = audio_file_processor.buffer();
         let audio_file_total_samples = audio_file_buffer[0].len();
@@ -136,11 +156,10 @@ impl OfflineRenderer {
             plugin_conversions_time += start.elapsed();
 
             let start = Instant::now();
-            output_file_processor
-                .process(&mut buffer)
-                .expect("Failed to write to file");
+            output_file_processor.process(&mut buffer)?;
             audio_output_time += start.elapsed();
         }
+        output_file_processor.finalize()?;
         let total_runtime = start.elapsed().as_millis();
 
         log::info!(
@@ -24,6 +24,7 @@ use std::ops::Deref;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
+use std::str::FromStr;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::thread::JoinHandle;
@@ -50,6 +51,8 @@ use crate::audio_io::offline_renderer::OfflineRenderer;
 use crate::audio_io::test_plugin_host::TestPluginHost;
 use crate::audio_io::WaitMessage;
 use crate::commands::options::RunOptions;
+use crate::processors::output_file_processor::batch::{plan_batch, run_batch, BatchInput};
+use crate::processors::output_file_processor::{DitherMode, OutputSampleFormat};
 use crate::processors::shared_processor::SharedProcessor;
 
 mod file_watch;
@@ -263,16 +266,91 @@ fn run_initialize_file_watch_thread(
 /// Start the offline rendering command, rendering to an output file
 fn run_offline_rendering(run_options: RunOptions) {
     log::info!("Running offline rendering");
+    let input_path = run_options
+        .input_audio()
+        .clone()
+        .expect("The \"--input\" flag is required for offline rendering");
     let output_file_path = run_options.output_audio().clone().unwrap();
+    let batch_input = BatchInput::parse(&input_path);
+    if Path::new(&input_path).is_dir() || matches!(batch_input, BatchInput::Glob(_)) {
+        run_batch_offline_rendering(&run_options, batch_input, Path::new(&output_file_path));
+        return;
+    }
+
     let (audio_settings, _) = get_audio_options(&run_options);
-    let offline_renderer = OfflineRenderer::new(
+    let (output_format, dither) = get_output_format(&run_options);
+    let mut offline_renderer = OfflineRenderer::new(
         audio_settings,
-        &run_options
-            .input_audio()
-            .clone()
-            .expect("The \"--input\" flag is required for offline rendering"),
+        &input_path,
         &output_file_path,
         run_options.plugin_path(),
     );
+    offline_renderer.set_output_format(output_format, dither);
     offline_renderer.run().expect("Failed to render audio");
 }
+
+fn get_output_format(run_options: &RunOptions) -> (OutputSampleFormat, DitherMode) {
+    let output_format = parse_format_option(run_options.output_format(), "output format");
+    let dither = parse_format_option(run_options.dither(), "dither mode");
+    (output_format, dither)
+}
+
+/// Parse an optional flag, logging the error & exiting if it's invalid
+fn parse_format_option<T: FromStr<Err = String> + Default>(
+    value: &Option<String>,
+    name: &str,
+) -> T {
+    value
+        .as_ref()
+        .map(|value| {
+            value.parse().unwrap_or_else(|err| {
+                log::error!("Invalid {}: {}", name, err);
+                exit(1);
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// Render every file in a directory or glob through the plug-in, in parallel, mirroring the folder
+/// structure into `output_directory` & writing a JSON report
+fn run_batch_offline_rendering(
+    run_options: &RunOptions,
+    input: BatchInput,
+    output_directory: &Path,
+) {
+    let (audio_settings, _) = get_audio_options(run_options);
+    let (output_format, dither) = get_output_format(run_options);
+    let jobs = plan_batch(&input, output_directory, "wav").unwrap_or_else(|err| {
+        log::error!("Failed to find batch input files: {}", err);
+        exit(1);
+    });
+
+    let report = run_batch(jobs, run_options.jobs(), |job| {
+        let mut offline_renderer = OfflineRenderer::new(
+            audio_settings,
+            &job.input.to_string_lossy(),
+            &job.output.to_string_lossy(),
+            run_options.plugin_path(),
+        );
+        offline_renderer.set_output_format(output_format, dither);
+        offline_renderer.run().map(|_| ())
+    })
+    .unwrap_or_else(|err| {
+        log::error!("Batch rendering failed: {}", err);
+        exit(1);
+    });
+
+    report.log_summary();
+    let report_path = run_options
+        .report()
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| output_directory.join("report.json"));
+    if let Err(err) = report.write_json(&report_path) {
+        log::error!("Failed to write batch report: {}", err);
+        exit(1);
+    }
+    if report.num_failed() > 0 {
+        exit(1);
+    }
+}
@@ -27,6 +27,10 @@ pub struct RunOptions {
     plugin_path: String,
     input_audio: Option<String>,
     output_audio: Option<String>,
+    output_format: Option<String>,
+    dither: Option<String>,
+    jobs: Option<usize>,
+    report: Option<String>,
     open_editor: bool,
     watch: bool,
     audio_host_id: Option<String>,
@@ -51,6 +55,22 @@ impl RunOptions {
         &self.output_audio
     }
 
+    pub fn output_format(&self) -> &Option<String> {
+        &self.output_format
+    }
+
+    pub fn dither(&self) -> &Option<String> {
+        &self.dither
+    }
+
+    pub fn jobs(&self) -> Option<usize> {
+        self.jobs
+    }
+
+    pub fn report(&self) -> &Option<String> {
+        &self.report
+    }
+
     pub fn open_editor(&self) -> bool {
         self.open_editor
     }
@@ -96,10 +116,22 @@ pub fn build_run_command<'a, 'b>() -> App<'a, 'b> {
             "-p, --plugin=<PLUGIN_PATH> 'An audio-plugin to load'",
         ))
         .arg(clap::Arg::from_usage(
-            "-i, --input=[INPUT_PATH] 'An audio file to process'",
+            "-i, --input=[INPUT_PATH] 'An audio file to process, or a directory/glob of files to render offline'",
         ))
         .arg(clap::Arg::from_usage(
-            "-o, --output=[OUTPUT_PATH] 'If specified, will render offline into file'",
+            "-o, --output=[OUTPUT_PATH] 'If specified, will render offline into file (or directory, for batch inputs)'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--output-format=[FORMAT] 'Offline render sample format, one of 16, 24, 32 or 32f (default)'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--dither=[DITHER] 'Offline render dither for 16/24-bit output, one of none, tpdf (default) or noise-shaped'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--jobs=[JOBS] 'Number of files to render at once for batch inputs, defaults to the number of CPUs'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--report=[REPORT_PATH] 'Where to write the batch JSON report, defaults to report.json in the output directory'",
         ))
         .arg(clap::Arg::from_usage(
             "-e, --editor 'Open the editor window'",
@@ -135,6 +167,14 @@ pub fn parse_run_options(matches: &ArgMatches) -> Option<RunOptions> {
     let plugin_path = matches.value_of("plugin")?.to_string();
     let input_audio = matches.value_of("input").map(|i| i.to_string());
     let output_audio = matches.value_of("output").map(|value| value.to_string());
+    let output_format = matches
+        .value_of("output-format")
+        .map(|value| value.to_string());
+    let dither = matches.value_of("dither").map(|value| value.to_string());
+    let jobs = matches
+        .value_of("jobs")
+        .map(|value| value.parse().expect("Invalid number of jobs"));
+    let report = matches.value_of("report").map(|value| value.to_string());
     let open_editor = matches.is_present("editor");
     let watch = matches.is_present("watch");
 
@@ -161,6 +201,10 @@ pub fn parse_run_options(matches: &ArgMatches) -> Option<RunOptions> {
         plugin_path,
         input_audio,
         output_audio,
+        output_format,
+        dither,
+        jobs,
+        report,
         open_editor,
         watch,
         audio_host_id,
@@ -203,6 +247,10 @@ mod test {
             "something.dylib",
             "--input=input.mp3",
             "--output=output.mp3",
+            "--output-format=24",
+            "--dither=noise-shaped",
+            "--jobs=4",
+            "--report=report.json",
             "--watch",
             "--editor",
             "--host-id=CoreAudio",
@@ -217,6 +265,10 @@ mod test {
         assert_eq!(options.plugin_path(), "something.dylib");
         assert_eq!(options.input_audio().as_ref().unwrap(), "input.mp3");
         assert_eq!(options.output_audio().as_ref().unwrap(), "output.mp3");
+        assert_eq!(options.output_format().as_ref().unwrap(), "24");
+        assert_eq!(options.dither().as_ref().unwrap(), "noise-shaped");
+        assert_eq!(options.jobs(), Some(4));
+        assert_eq!(options.report().as_ref().unwrap(), "report.json");
         assert_eq!(options.input_device_id().as_ref().unwrap(), "InputDevice");
         assert_eq!(options.output_device_id().as_ref().unwrap(), "OutputDevice");
         assert_eq!(options.watch(), true);
@@ -0,0 +1,301 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::convert::TryInto;
+
+use audio_garbage_collector::Shared;
+use audio_processor_traits::parameters::{
+    AudioProcessorHandle, FloatType, ParameterSpec, ParameterType, ParameterValue,
+};
+use num_traits::FromPrimitive;
+
+use crate::modulation::{MAX_MODULATION_SLOTS, NUM_LFOS};
+use crate::parameters::{SynthesizerHandle, MAX_UNISON, MAX_VOICES};
+
+/// Name, range & step of the parameters before the LFOs and modulation slots
+const GLOBAL_PARAMETERS: [(&str, (f32, f32), Option<f32>); 21] = [
+    ("Volume", (0.0, 1.0), None),
+    ("Polyphony", (1.0, MAX_VOICES as f32), Some(1.0)),
+    ("Voice mode", (0.0, 2.0), Some(1.0)),
+    ("Voice stealing", (0.0, 1.0), Some(1.0)),
+    ("Glide", (0.0, 2.0), None),
+    ("Waveform", (0.0, 2.0), Some(1.0)),
+    ("Pulse width", (0.01, 0.99), None),
+    ("Unison voices", (1.0, MAX_UNISON as f32), Some(1.0)),
+    ("Unison detune", (0.0, 100.0), None),
+    ("Unison spread", (0.0, 1.0), None),
+    ("Voice pan spread", (0.0, 1.0), None),
+    ("Filter cutoff", (20.0, 20000.0), None),
+    ("Filter resonance", (0.1, 10.0), None),
+    ("Amp attack", (0.0, 5.0), None),
+    ("Amp decay", (0.0, 5.0), None),
+    ("Amp sustain", (0.0, 1.0), None),
+    ("Amp release", (0.0, 5.0), None),
+    ("Filter attack", (0.0, 5.0), None),
+    ("Filter decay", (0.0, 5.0), None),
+    ("Filter sustain", (0.0, 1.0), None),
+    ("Filter release", (0.0, 5.0), None),
+];
+const LFO_PARAMETERS: [(&str, (f32, f32), Option<f32>); 2] = [
+    ("rate", (0.0, 20.0), None),
+    ("shape", (0.0, 3.0), Some(1.0)),
+];
+const SLOT_PARAMETERS: [(&str, (f32, f32), Option<f32>); 5] = [
+    ("source", (0.0, 9.0), Some(1.0)),
+    ("destination", (0.0, 5.0), Some(1.0)),
+    ("depth", (-1.0, 1.0), None),
+    ("curve", (0.0, 3.0), Some(1.0)),
+    ("CC", (0.0, 127.0), Some(1.0)),
+];
+
+const LFOS_START: usize = GLOBAL_PARAMETERS.len();
+const SLOTS_START: usize = LFOS_START + NUM_LFOS * LFO_PARAMETERS.len();
+const PARAMETER_COUNT: usize = SLOTS_START + MAX_MODULATION_SLOTS * SLOT_PARAMETERS.len();
+
+/// Exposes the synthesizer parameters, LFOs & modulation matrix slots.
+///
+/// Enum parameters (voice mode, waveform, LFO shapes & slot source, destination & curve) are
+/// set with their discriminant.
+pub struct GenericHandle(pub Shared<SynthesizerHandle>);
+
+fn float_spec(name: String, range: (f32, f32), step: Option<f32>) -> ParameterSpec {
+    ParameterSpec::new(name, ParameterType::Float(FloatType { range, step }))
+}
+
+fn enum_value<T: FromPrimitive>(value: f32) -> Option<T> {
+    T::from_usize(value.round().max(0.0) as usize)
+}
+
+impl AudioProcessorHandle for GenericHandle {
+    fn name(&self) -> String {
+        "Synthesizer".to_string()
+    }
+
+    fn parameter_count(&self) -> usize {
+        PARAMETER_COUNT
+    }
+
+    fn get_parameter_spec(&self, index: usize) -> ParameterSpec {
+        if index < LFOS_START {
+            let (name, range, step) = GLOBAL_PARAMETERS[index];
+            float_spec(name.to_string(), range, step)
+        } else if index < SLOTS_START {
+            let index = index - LFOS_START;
+            let (name, range, step) = LFO_PARAMETERS[index % LFO_PARAMETERS.len()];
+            let lfo = index / LFO_PARAMETERS.len();
+            float_spec(format!("LFO {} {}", lfo + 1, name), range, step)
+        } else {
+            let index = index - SLOTS_START;
+            let (name, range, step) = SLOT_PARAMETERS[index % SLOT_PARAMETERS.len()];
+            let slot = index / SLOT_PARAMETERS.len();
+            float_spec(format!("Mod {} {}", slot + 1, name), range, step)
+        }
+    }
+
+    fn get_parameter(&self, index: usize) -> Option<ParameterValue> {
+        let handle = &self.0;
+        let value = match index {
+            0 => handle.volume(),
+            1 => handle.polyphony() as f32,
+            2 => handle.voice_mode() as usize as f32,
+            3 => handle.voice_stealing_mode() as usize as f32,
+            4 => handle.glide(),
+            5 => handle.waveform() as usize as f32,
+            6 => handle.pulse_width(),
+            7 => handle.unison_voices() as f32,
+            8 => handle.unison_detune(),
+            9 => handle.unison_spread(),
+            10 => handle.voice_pan_spread(),
+            11 => handle.filter_cutoff(),
+            12 => handle.filter_resonance(),
+            13 => handle.amp_envelope().attack(),
+            14 => handle.amp_envelope().decay(),
+            15 => handle.amp_envelope().sustain(),
+            16 => handle.amp_envelope().release(),
+            17 => handle.filter_envelope().attack(),
+            18 => handle.filter_envelope().decay(),
+            19 => handle.filter_envelope().sustain(),
+            20 => handle.filter_envelope().release(),
+            _ if (LFOS_START..SLOTS_START).contains(&index) => {
+                let index = index - LFOS_START;
+                let lfo = handle.modulation_matrix().lfo(index / LFO_PARAMETERS.len());
+                match index % LFO_PARAMETERS.len() {
+                    0 => lfo.rate(),
+                    _ => lfo.shape() as usize as f32,
+                }
+            }
+            _ if (SLOTS_START..PARAMETER_COUNT).contains(&index) => {
+                let index = index - SLOTS_START;
+                let slot = handle
+                    .modulation_matrix()
+                    .slot(index / SLOT_PARAMETERS.len());
+                match index % SLOT_PARAMETERS.len() {
+                    0 => slot.source() as usize as f32,
+                    1 => slot.destination() as usize as f32,
+                    2 => slot.depth(),
+                    3 => slot.curve() as usize as f32,
+                    _ => slot.controller() as f32,
+                }
+            }
+            _ => return None,
+        };
+        Some(value.into())
+    }
+
+    fn set_parameter(&self, index: usize, request: ParameterValue) {
+        let handle = &self.0;
+        if let Ok(value) = request.try_into() {
+            let value: f32 = value;
+            match index {
+                0 => handle.set_volume(value),
+                1 => handle.set_polyphony(value.round().max(0.0) as usize),
+                2 => {
+                    if let Some(mode) = enum_value(value) {
+                        handle.set_voice_mode(mode);
+                    }
+                }
+                3 => {
+                    if let Some(mode) = enum_value(value) {
+                        handle.set_voice_stealing_mode(mode);
+                    }
+                }
+                4 => handle.set_glide(value),
+                5 => {
+                    if let Some(waveform) = enum_value(value) {
+                        handle.set_waveform(waveform);
+                    }
+                }
+                6 => handle.set_pulse_width(value),
+                7 => handle.set_unison_voices(value.round().max(0.0) as usize),
+                8 => handle.set_unison_detune(value),
+                9 => handle.set_unison_spread(value),
+                10 => handle.set_voice_pan_spread(value),
+                11 => handle.set_filter_cutoff(value),
+                12 => handle.set_filter_resonance(value),
+                13 => handle.amp_envelope().set_attack(value),
+                14 => handle.amp_envelope().set_decay(value),
+                15 => handle.amp_envelope().set_sustain(value),
+                16 => handle.amp_envelope().set_release(value),
+                17 => handle.filter_envelope().set_attack(value),
+                18 => handle.filter_envelope().set_decay(value),
+                19 => handle.filter_envelope().set_sustain(value),
+                20 => handle.filter_envelope().set_release(value),
+                _ if (LFOS_START..SLOTS_START).contains(&index) => {
+                    let index = index - LFOS_START;
+                    let lfo = handle.modulation_matrix().lfo(index / LFO_PARAMETERS.len());
+                    match index % LFO_PARAMETERS.len() {
+                        0 => lfo.set_rate(value),
+                        _ => {
+                            if let Some(shape) = enum_value(value) {
+                                lfo.set_shape(shape);
+                            }
+                        }
+                    }
+                }
+                _ if (SLOTS_START..PARAMETER_COUNT).contains(&index) => {
+                    let index = index - SLOTS_START;
+                    let slot = handle
+                        .modulation_matrix()
+                        .slot(index / SLOT_PARAMETERS.len());
+                    match index % SLOT_PARAMETERS.len() {
+                        0 => {
+                            if let Some(source) = enum_value(value) {
+                                slot.set_source(source);
+                            }
+                        }
+                        1 => {
+                            if let Some(destination) = enum_value(value) {
+                                slot.set_destination(destination);
+                            }
+                        }
+                        2 => slot.set_depth(value),
+                        3 => {
+                            if let Some(curve) = enum_value(value) {
+                                slot.set_curve(curve);
+                            }
+                        }
+                        _ => slot.set_controller(value.round().max(0.0) as usize),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_garbage_collector::make_shared;
+
+    use crate::modulation::{ModulationDestination, ModulationSource};
+
+    use super::*;
+
+    #[test]
+    fn test_parameter_specs() {
+        let handle = GenericHandle(make_shared(SynthesizerHandle::default()));
+        assert_eq!(handle.parameter_count(), 21 + 2 * 2 + 8 * 5);
+        assert_eq!(handle.get_parameter_spec(0).name(), "Volume");
+        assert_eq!(
+            handle.get_parameter_spec(LFOS_START + 3).name(),
+            "LFO 2 shape"
+        );
+        assert_eq!(
+            handle.get_parameter_spec(SLOTS_START + 5 + 2).name(),
+            "Mod 2 depth"
+        );
+        for index in 0..handle.parameter_count() {
+            let spec = handle.get_parameter_spec(index);
+            let value: f32 = handle.get_parameter(index).unwrap().try_into().unwrap();
+            let (min, max) = spec.ty().float().unwrap().range;
+            assert!(value >= min && value <= max, "{} = {}", spec.name(), value);
+        }
+        assert_eq!(handle.get_parameter(PARAMETER_COUNT), None);
+    }
+
+    #[test]
+    fn test_set_modulation_slot() {
+        let handle = GenericHandle(make_shared(SynthesizerHandle::default()));
+        let slot_start = SLOTS_START + 7 * SLOT_PARAMETERS.len();
+        handle.set_parameter(slot_start, (ModulationSource::Lfo2 as usize as f32).into());
+        handle.set_parameter(
+            slot_start + 1,
+            (ModulationDestination::PulseWidth as usize as f32).into(),
+        );
+        handle.set_parameter(slot_start + 2, (-0.5).into());
+        handle.set_parameter(slot_start + 4, 300.0.into());
+
+        let routing = handle.0.modulation_matrix().slot(7).routing();
+        assert_eq!(routing.source, ModulationSource::Lfo2);
+        assert_eq!(routing.destination, ModulationDestination::PulseWidth);
+        assert_eq!(routing.depth, -0.5);
+        assert_eq!(routing.controller, 127);
+        assert_eq!(handle.get_parameter(slot_start + 2), Some((-0.5).into()));
+
+        // Out of range enum values are ignored
+        handle.set_parameter(slot_start, 100.0.into());
+        assert_eq!(
+            handle.0.modulation_matrix().slot(7).source(),
+            ModulationSource::Lfo2
+        );
+    }
+}
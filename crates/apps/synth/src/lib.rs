@@ -20,23 +20,87 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-use num::FromPrimitive;
-use rimd::Status;
+//! A polyphonic subtractive synthesizer.
+//!
+//! Each voice stacks band-limited unison oscillators, goes through a per-voice low-pass filter
+//! with its own envelope & is panned across the stereo field. Parameters live on a
+//! [`SynthesizerHandle`] shared with the audio-thread, which includes a per-voice modulation
+//! matrix (see [`ModulationMatrixHandle`]).
 
-use audio_processor_traits::simple_processor::MultiChannel;
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::parameters::{
+    make_handle_ref, AudioProcessorHandleProvider, AudioProcessorHandleRef,
+};
 use audio_processor_traits::{
     AudioBuffer, AudioContext, AudioProcessor, AudioProcessorSettings, MidiEventHandler,
     MidiMessageLike,
 };
-use augmented_dsp_filters::rbj::{FilterProcessor, FilterType};
-use voice::Voice;
+use augmented_midi::{parse_midi_event, MIDIMessage, MIDIMessageNote, ParserState};
+use voice::{NoteTrigger, Voice};
+
+pub use generic_handle::GenericHandle;
+pub use modulation::*;
+pub use parameters::*;
 
+mod generic_handle;
+mod modulation;
+mod parameters;
 mod voice;
 
+/// Maximum number of held notes remembered on mono & legato modes
+const MAX_HELD_NOTES: usize = 16;
+
+/// Stack of held notes, so mono & legato modes go back to the previous note when the last one
+/// is released
+struct HeldNotes {
+    notes: [u8; MAX_HELD_NOTES],
+    len: usize,
+}
+
+impl Default for HeldNotes {
+    fn default() -> Self {
+        Self {
+            notes: [0; MAX_HELD_NOTES],
+            len: 0,
+        }
+    }
+}
+
+impl HeldNotes {
+    fn push(&mut self, note: u8) {
+        self.remove(note);
+        if self.len == MAX_HELD_NOTES {
+            self.notes.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.notes[self.len] = note;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, note: u8) {
+        if let Some(index) = self.notes[..self.len].iter().position(|n| *n == note) {
+            self.notes.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+
+    fn last(&self) -> Option<u8> {
+        self.notes[..self.len].last().copied()
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
 pub struct Synthesizer {
-    current_voice: usize,
-    voices: [Voice; 4],
-    filter: MultiChannel<FilterProcessor<f32>>,
+    handle: Shared<SynthesizerHandle>,
+    voices: Vec<Voice>,
+    held_notes: HeldNotes,
+    note_counter: u64,
+    /// Last value of every CC, normalized to 0-1
+    controllers: [f32; 128],
+    midi_parser_state: ParserState,
 }
 
 impl Default for Synthesizer {
@@ -49,14 +113,161 @@ impl Default for Synthesizer {
 impl Synthesizer {
     pub fn new(sample_rate: f32) -> Self {
         Synthesizer {
-            current_voice: 0,
-            voices: [
-                Voice::new(sample_rate),
-                Voice::new(sample_rate),
-                Voice::new(sample_rate),
-                Voice::new(sample_rate),
-            ],
-            filter: MultiChannel::new(|| FilterProcessor::new(FilterType::LowPass)),
+            handle: make_shared(SynthesizerHandle::default()),
+            voices: (0..MAX_VOICES)
+                .map(|index| {
+                    // Spread voice pan positions evenly without any pattern across voices
+                    let pan = (index as f32 * 0.618_034).fract() * 2.0 - 1.0;
+                    Voice::new(sample_rate, pan)
+                })
+                .collect(),
+            held_notes: HeldNotes::default(),
+            note_counter: 0,
+            controllers: [0.0; 128],
+            midi_parser_state: ParserState::default(),
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<SynthesizerHandle> {
+        &self.handle
+    }
+
+    /// Number of voices currently producing sound
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.is_active()).count()
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if velocity == 0 {
+            self.note_off(note);
+            return;
+        }
+
+        self.note_counter += 1;
+        match self.handle.voice_mode() {
+            VoiceMode::Poly => {
+                self.held_notes.clear();
+                let voice_index = self.find_voice(note);
+                self.voices[voice_index].note_on(
+                    &self.handle,
+                    note,
+                    velocity,
+                    NoteTrigger::Retrigger,
+                    self.note_counter,
+                );
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                let trigger = if self.handle.voice_mode() == VoiceMode::Legato
+                    && self.held_notes.last().is_some()
+                {
+                    NoteTrigger::Legato
+                } else {
+                    NoteTrigger::GlideRetrigger
+                };
+                self.held_notes.push(note);
+                self.voices[0].note_on(&self.handle, note, velocity, trigger, self.note_counter);
+            }
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        match self.handle.voice_mode() {
+            VoiceMode::Poly => {
+                for voice in &mut self.voices {
+                    if voice.is_held() && voice.current_note() == Some(note) {
+                        voice.note_off();
+                    }
+                }
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                self.held_notes.remove(note);
+                let voice = &mut self.voices[0];
+                if voice.current_note() != Some(note) {
+                    return;
+                }
+
+                if let Some(previous_note) = self.held_notes.last() {
+                    // Going back to a held note never retriggers, so the velocity is unused
+                    voice.note_on(
+                        &self.handle,
+                        previous_note,
+                        0,
+                        NoteTrigger::Legato,
+                        self.note_counter,
+                    );
+                } else {
+                    voice.note_off();
+                }
+            }
+        }
+    }
+
+    /// Set aftertouch pressure on the voice playing `note` or on all voices for channel pressure
+    pub fn aftertouch(&mut self, note: Option<u8>, pressure: u8) {
+        for voice in &mut self.voices {
+            if note.is_none() || voice.current_note() == note {
+                voice.set_pressure(pressure);
+            }
+        }
+    }
+
+    /// Set the value of a CC, between 0 and 127, which may be routed on the modulation matrix
+    pub fn set_controller(&mut self, controller_number: u8, value: u8) {
+        if let Some(controller) = self.controllers.get_mut(controller_number as usize) {
+            *controller = value as f32 / 127.0;
+        }
+    }
+
+    /// Find the voice a new note should go to
+    fn find_voice(&self, note: u8) -> usize {
+        let polyphony = self.handle.polyphony().min(self.voices.len());
+        let voices = &self.voices[..polyphony];
+
+        if let Some(index) = voices
+            .iter()
+            .position(|voice| voice.is_active() && voice.current_note() == Some(note))
+        {
+            return index;
+        }
+        if let Some(index) = voices.iter().position(|voice| !voice.is_active()) {
+            return index;
+        }
+
+        let has_released = voices.iter().any(|voice| !voice.is_held());
+        let candidates = voices
+            .iter()
+            .enumerate()
+            .filter(|(_, voice)| !has_released || !voice.is_held());
+        let stolen = match self.handle.voice_stealing_mode() {
+            VoiceStealingMode::Oldest => candidates.min_by_key(|(_, voice)| voice.started_at()),
+            VoiceStealingMode::Quietest => candidates
+                .min_by(|(_, voice1), (_, voice2)| voice1.level().total_cmp(&voice2.level())),
+        };
+        stolen.map(|(index, _)| index).unwrap_or(0)
+    }
+
+    fn handle_midi_message(&mut self, message: &MIDIMessage<&[u8]>) {
+        match message {
+            MIDIMessage::NoteOn(MIDIMessageNote { note, velocity, .. }) => {
+                self.note_on(*note, *velocity);
+            }
+            MIDIMessage::NoteOff(MIDIMessageNote { note, .. }) => {
+                self.note_off(*note);
+            }
+            MIDIMessage::PolyphonicKeyPressure { note, pressure, .. } => {
+                self.aftertouch(Some(*note), *pressure);
+            }
+            MIDIMessage::ChannelPressure { pressure, .. } => {
+                self.aftertouch(None, *pressure);
+            }
+            MIDIMessage::ControlChange {
+                controller_number,
+                value,
+                ..
+            } => {
+                self.set_controller(*controller_number, *value);
+            }
+            _ => {}
         }
     }
 }
@@ -66,94 +277,277 @@ impl AudioProcessor for Synthesizer {
 
     fn prepare(&mut self, context: &mut AudioContext) {
         for voice in &mut self.voices {
-            voice.prepare(context);
+            voice.prepare(context.settings.sample_rate());
         }
-        self.filter.prepare(context);
     }
 
-    fn process(&mut self, context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
         // Silence the input
         for sample in data.slice_mut() {
             *sample = 0.0;
         }
 
-        // Produce output for 4 voices
         for voice in &mut self.voices {
-            voice.process(context, data);
+            if voice.is_active() {
+                voice.process(&self.handle, &self.controllers, data);
+            }
         }
+    }
+}
 
-        self.filter.process(context, data);
+impl AudioProcessorHandleProvider for Synthesizer {
+    fn generic_handle(&self) -> AudioProcessorHandleRef {
+        make_handle_ref(GenericHandle(self.handle.clone()))
     }
 }
 
 impl MidiEventHandler for Synthesizer {
     fn process_midi_events<Message: MidiMessageLike>(&mut self, midi_messages: &[Message]) {
         for message in midi_messages {
-            let maybe_bytes = message.bytes();
-            // TODO Write a better MIDI parser
-            // (rimd allocates on parse, so we can't parse the whole message; only the status byte)
-            let maybe_status = maybe_bytes.and_then(|b| rimd::Status::from_u8(b[0]));
-            if let Some((status, bytes)) = maybe_status.zip(maybe_bytes) {
-                self.handle_midi_message(status, bytes);
+            let maybe_message = message.bytes().and_then(|bytes| {
+                parse_midi_event::<&[u8]>(bytes, &mut self.midi_parser_state).ok()
+            });
+            if let Some((_, message)) = maybe_message {
+                self.handle_midi_message(&message);
             }
         }
     }
 }
 
-impl Synthesizer {
-    fn handle_midi_message(&mut self, status: rimd::Status, bytes: &[u8]) {
-        match status {
-            Status::NoteOn => {
-                println!("Note on {}/{}", bytes[1], bytes[2]);
-                self.note(false, bytes);
-            }
-            Status::NoteOff => {
-                // println!("Note off {}", bytes[1]);
-                self.note(true, bytes);
-            }
-            Status::ControlChange => {
-                if bytes[1] == 21 {
-                    self.filter.for_each(|filter| {
-                        filter.set_cutoff(22000.0 * (bytes[2] as f32 / 127.0));
-                    })
-                }
-                if bytes[1] == 22 {
-                    self.filter.for_each(|filter| {
-                        filter.set_cutoff(22000.0 * (bytes[2] as f32 / 127.0));
-                    })
-                }
-            }
-            _ => {}
+#[cfg(test)]
+mod test {
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    struct TestMessage(Vec<u8>);
+
+    impl MidiMessageLike for TestMessage {
+        fn is_midi(&self) -> bool {
+            true
+        }
+
+        fn bytes(&self) -> Option<&[u8]> {
+            Some(&self.0)
         }
     }
 
-    fn note(&mut self, is_off: bool, bytes: &[u8]) {
-        let note = bytes[1];
-        let velocity = bytes[2];
-        if velocity == 0 || is_off {
-            let voice = self.voices.iter_mut().find(|voice| {
-                voice.current_note().is_some() && voice.current_note().unwrap() == note
-            });
-            if let Some(voice) = voice {
-                voice.note_off();
-            }
-        } else {
-            let voice = self
-                .voices
-                .iter_mut()
-                .find(|voice| voice.current_note().is_none());
-            if let Some(voice) = voice {
-                voice.note_on(note, velocity);
-            } else {
-                self.current_voice = (self.current_voice + 1) % self.voices.len();
-                self.voices[self.current_voice].note_on(note, velocity);
+    fn setup() -> (Synthesizer, AudioContext, AudioBuffer<f32>) {
+        let settings = AudioProcessorSettings::new(1000.0, 2, 2, 64);
+        let mut context = AudioContext::from(settings);
+        let mut synth = Synthesizer::new(1000.0);
+        synth.prepare(&mut context);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 64);
+        (synth, context, buffer)
+    }
+
+    fn rms(buffer: &AudioBuffer<f32>, channel: usize) -> f32 {
+        let channel = buffer.channel(channel);
+        (channel.iter().map(|s| s * s).sum::<f32>() / channel.len() as f32).sqrt()
+    }
+
+    fn playing_notes(synth: &Synthesizer) -> Vec<u8> {
+        let mut notes: Vec<u8> = synth
+            .voices
+            .iter()
+            .filter(|voice| voice.is_held())
+            .filter_map(|voice| voice.current_note())
+            .collect();
+        notes.sort_unstable();
+        notes
+    }
+
+    #[test]
+    fn test_held_notes_stack() {
+        let mut held_notes = HeldNotes::default();
+        held_notes.push(60);
+        held_notes.push(62);
+        held_notes.push(64);
+        held_notes.remove(64);
+        assert_eq!(held_notes.last(), Some(62));
+        held_notes.remove(60);
+        assert_eq!(held_notes.last(), Some(62));
+        held_notes.remove(62);
+        assert_eq!(held_notes.last(), None);
+    }
+
+    #[test]
+    fn test_midi_note_on_and_off_are_parsed() {
+        let (mut synth, mut context, mut buffer) = setup();
+        synth.process_midi_events(&[TestMessage(vec![0x90, 60, 100])]);
+        synth.process(&mut context, &mut buffer);
+        assert_eq!(playing_notes(&synth), vec![60]);
+        assert!(rms(&buffer, 0) > 0.0);
+
+        // Note on with velocity 0 is a note off
+        synth.process_midi_events(&[TestMessage(vec![0x90, 60, 0])]);
+        assert_eq!(playing_notes(&synth), Vec::<u8>::new());
+        for _ in 0..100 {
+            synth.process(&mut context, &mut buffer);
+        }
+        assert_eq!(synth.active_voices(), 0);
+        assert_eq!(rms(&buffer, 0), 0.0);
+    }
+
+    #[test]
+    fn test_polyphony_steals_oldest_voice() {
+        let (mut synth, _context, _buffer) = setup();
+        synth.handle().set_polyphony(2);
+        synth.note_on(60, 100);
+        synth.note_on(62, 100);
+        synth.note_on(64, 100);
+        assert_eq!(playing_notes(&synth), vec![62, 64]);
+    }
+
+    #[test]
+    fn test_polyphony_steals_quietest_voice() {
+        let (mut synth, _context, _buffer) = setup();
+        synth.handle().set_polyphony(2);
+        synth
+            .handle()
+            .set_voice_stealing_mode(VoiceStealingMode::Quietest);
+        synth.handle().amp_envelope().set_attack(0.0);
+        synth.note_on(60, 127);
+        synth.note_on(62, 10);
+        synth.note_on(64, 100);
+        assert_eq!(playing_notes(&synth), vec![60, 64]);
+    }
+
+    #[test]
+    fn test_released_voices_are_stolen_first() {
+        let (mut synth, _context, _buffer) = setup();
+        synth.handle().set_polyphony(2);
+        synth.note_on(60, 100);
+        synth.note_on(62, 100);
+        synth.note_off(62);
+        synth.note_on(64, 100);
+        assert_eq!(playing_notes(&synth), vec![60, 64]);
+    }
+
+    #[test]
+    fn test_mono_mode_returns_to_held_note() {
+        let (mut synth, _context, _buffer) = setup();
+        synth.handle().set_voice_mode(VoiceMode::Mono);
+        synth.note_on(60, 100);
+        synth.note_on(64, 100);
+        assert_eq!(playing_notes(&synth), vec![64]);
+        synth.note_off(64);
+        assert_eq!(playing_notes(&synth), vec![60]);
+        synth.note_off(60);
+        assert_eq!(playing_notes(&synth), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_legato_does_not_retrigger_envelope() {
+        for (mode, expect_retrigger) in [(VoiceMode::Mono, true), (VoiceMode::Legato, false)] {
+            let (mut synth, mut context, mut buffer) = setup();
+            synth.handle().set_voice_mode(mode);
+            synth.handle().amp_envelope().set_attack(0.01);
+            synth.handle().amp_envelope().set_decay(0.01);
+            synth.handle().amp_envelope().set_sustain(0.5);
+            synth.note_on(60, 100);
+            for _ in 0..4 {
+                synth.process(&mut context, &mut buffer);
             }
+            let sustain_level = synth.voices[0].level();
+
+            synth.note_on(64, 100);
+            let mut short_buffer = AudioBuffer::empty();
+            short_buffer.resize(2, 5);
+            synth.process(&mut context, &mut short_buffer);
+            let level = synth.voices[0].level();
+            assert_eq!(
+                level > sustain_level,
+                expect_retrigger,
+                "{:?} {} {}",
+                mode,
+                sustain_level,
+                level
+            );
         }
     }
-}
 
-#[cfg(test)]
-mod test {
     #[test]
-    fn test_compiles() {}
+    fn test_velocity_scales_output() {
+        let (mut synth, mut context, mut buffer) = setup();
+        synth.note_on(60, 127);
+        synth.process(&mut context, &mut buffer);
+        let loud = rms(&buffer, 0);
+
+        let (mut synth, mut context, mut buffer) = setup();
+        synth.note_on(60, 32);
+        synth.process(&mut context, &mut buffer);
+        let quiet = rms(&buffer, 0);
+        assert!(quiet < loud * 0.5, "quiet={} loud={}", quiet, loud);
+    }
+
+    #[test]
+    fn test_aftertouch_routed_to_amp() {
+        let (mut synth, mut context, mut buffer) = setup();
+        synth
+            .handle()
+            .modulation_matrix()
+            .set_routings(&[ModulationRouting {
+                source: ModulationSource::Aftertouch,
+                destination: ModulationDestination::Amp,
+                depth: 1.0,
+                ..ModulationRouting::default()
+            }]);
+        synth.note_on(60, 100);
+        synth.process(&mut context, &mut buffer);
+        assert_eq!(rms(&buffer, 0), 0.0);
+        synth.process_midi_events(&[TestMessage(vec![0xD0, 127])]);
+        synth.process(&mut context, &mut buffer);
+        synth.process(&mut context, &mut buffer);
+        assert!(rms(&buffer, 0) > 0.0);
+    }
+
+    #[test]
+    fn test_controller_routed_to_pitch() {
+        let (mut synth, mut context, _buffer) = setup();
+        synth.handle().set_unison_voices(1);
+        synth
+            .handle()
+            .modulation_matrix()
+            .set_routings(&[ModulationRouting {
+                source: ModulationSource::Controller,
+                destination: ModulationDestination::Pitch,
+                depth: 1.0,
+                controller: 74,
+                ..ModulationRouting::default()
+            }]);
+        synth.handle().set_filter_cutoff(20000.0);
+        synth.note_on(57, 127);
+
+        let crossings = |buffer: &AudioBuffer<f32>| {
+            let channel = buffer.channel(0);
+            channel
+                .windows(2)
+                .filter(|w| w[0] <= 0.0 && w[1] > 0.0)
+                .count()
+        };
+        let mut long_buffer = AudioBuffer::empty();
+        long_buffer.resize(2, 1000);
+        synth.process(&mut context, &mut long_buffer);
+        let before = crossings(&long_buffer);
+        // Full CC value shifts pitch up an octave
+        synth.process_midi_events(&[TestMessage(vec![0xB0, 74, 127])]);
+        synth.process(&mut context, &mut long_buffer);
+        let after = crossings(&long_buffer);
+        assert!((219..=221).contains(&before), "{}", before);
+        assert!((439..=441).contains(&after), "{}", after);
+    }
+
+    #[test]
+    fn test_voice_panning() {
+        let (mut synth, mut context, mut buffer) = setup();
+        synth.handle().set_unison_voices(1);
+        synth.handle().set_voice_pan_spread(1.0);
+        // The first voice is panned hard left
+        synth.note_on(60, 100);
+        synth.process(&mut context, &mut buffer);
+        assert!(rms(&buffer, 0) > 0.0);
+        assert!(rms(&buffer, 1) < rms(&buffer, 0) * 0.01);
+    }
 }
@@ -0,0 +1,502 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Per-voice modulation matrix.
+//!
+//! Each slot routes a [`ModulationSource`] to a [`ModulationDestination`] with a depth and a
+//! [`ModulationCurve`]. Slots are evaluated on every voice at control-rate, so voice sources like
+//! envelopes, velocity or the per-voice LFOs modulate each voice independently.
+
+use std::sync::atomic::AtomicUsize;
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use augmented_atomics::{AtomicEnum, AtomicF32, AtomicValue};
+use augmented_oscillator::generators;
+
+/// Number of routings on the matrix
+pub const MAX_MODULATION_SLOTS: usize = 8;
+/// Number of LFOs on each voice
+pub const NUM_LFOS: usize = 2;
+
+/// Pitch modulation range in semitones, at full depth
+pub const PITCH_MODULATION_RANGE: f32 = 12.0;
+/// Filter cut-off modulation range in octaves, at full depth
+pub const CUTOFF_MODULATION_RANGE: f32 = 5.0;
+/// Filter resonance modulation range in octaves of Q, at full depth
+pub const RESONANCE_MODULATION_RANGE: f32 = 2.0;
+/// Pulse width modulation range, at full depth
+pub const PULSE_WIDTH_MODULATION_RANGE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum ModulationSource {
+    None = 0,
+    /// Per-voice LFO, between -1 and 1
+    Lfo1 = 1,
+    /// Per-voice LFO, between -1 and 1
+    Lfo2 = 2,
+    /// Between 0 and 1
+    AmpEnvelope = 3,
+    /// Between 0 and 1
+    FilterEnvelope = 4,
+    /// Between 0 and 1
+    Velocity = 5,
+    /// Distance from middle C, one unit per 60 semi-tones
+    KeyTracking = 6,
+    /// CC 1, between 0 and 1
+    ModWheel = 7,
+    /// Polyphonic or channel pressure, between 0 and 1
+    Aftertouch = 8,
+    /// The CC number set on the slot, between 0 and 1
+    Controller = 9,
+}
+
+/// Destinations of a slot. The depth is scaled by the range of each destination, for example a
+/// depth of `1.0` on [`ModulationDestination::Pitch`] is [`PITCH_MODULATION_RANGE`] semi-tones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum ModulationDestination {
+    None = 0,
+    Pitch = 1,
+    PulseWidth = 2,
+    FilterCutoff = 3,
+    FilterResonance = 4,
+    /// Scales the voice volume. Unlike other destinations, amp slots multiply the volume by
+    /// `1 + depth * (value - 1)` so a depth of `1.0` makes the volume follow the source.
+    Amp = 5,
+}
+
+/// Shaping applied to the source value before it is scaled by the depth. Curves are symmetric,
+/// so bipolar sources keep their sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum ModulationCurve {
+    Linear = 0,
+    Exponential = 1,
+    Logarithmic = 2,
+    SCurve = 3,
+}
+
+impl ModulationCurve {
+    pub fn apply(&self, value: f32) -> f32 {
+        let magnitude = value.abs().min(1.0);
+        let shaped = match self {
+            ModulationCurve::Linear => return value,
+            ModulationCurve::Exponential => magnitude * magnitude,
+            ModulationCurve::Logarithmic => magnitude.sqrt(),
+            ModulationCurve::SCurve => magnitude * magnitude * (3.0 - 2.0 * magnitude),
+        };
+        shaped.copysign(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum LfoShape {
+    Sine = 0,
+    Triangle = 1,
+    Saw = 2,
+    Square = 3,
+}
+
+impl LfoShape {
+    pub(crate) fn generator(&self) -> fn(f32) -> f32 {
+        match self {
+            LfoShape::Sine => generators::sine_generator,
+            LfoShape::Triangle => generators::triangle_generator,
+            LfoShape::Saw => generators::saw_generator,
+            LfoShape::Square => generators::square_generator,
+        }
+    }
+}
+
+pub struct LfoHandle {
+    rate: AtomicF32,
+    shape: AtomicEnum<LfoShape>,
+}
+
+impl LfoHandle {
+    fn new(rate: f32, shape: LfoShape) -> Self {
+        Self {
+            rate: rate.into(),
+            shape: shape.into(),
+        }
+    }
+
+    /// LFO rate in Hz
+    pub fn rate(&self) -> f32 {
+        self.rate.get()
+    }
+
+    pub fn set_rate(&self, value: f32) {
+        self.rate.set(value.max(0.0));
+    }
+
+    pub fn shape(&self) -> LfoShape {
+        self.shape.get()
+    }
+
+    pub fn set_shape(&self, value: LfoShape) {
+        self.shape.set(value);
+    }
+}
+
+/// A single routing, this is what should be stored when saving the matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModulationRouting {
+    pub source: ModulationSource,
+    pub destination: ModulationDestination,
+    /// Between -1 and 1
+    pub depth: f32,
+    pub curve: ModulationCurve,
+    /// CC number read by [`ModulationSource::Controller`]
+    pub controller: usize,
+}
+
+impl Default for ModulationRouting {
+    fn default() -> Self {
+        Self {
+            source: ModulationSource::None,
+            destination: ModulationDestination::None,
+            depth: 0.0,
+            curve: ModulationCurve::Linear,
+            controller: 0,
+        }
+    }
+}
+
+impl ModulationRouting {
+    fn new(source: ModulationSource, destination: ModulationDestination, depth: f32) -> Self {
+        Self {
+            source,
+            destination,
+            depth,
+            ..Self::default()
+        }
+    }
+}
+
+pub struct ModulationSlotHandle {
+    source: AtomicEnum<ModulationSource>,
+    destination: AtomicEnum<ModulationDestination>,
+    depth: AtomicF32,
+    curve: AtomicEnum<ModulationCurve>,
+    controller: AtomicUsize,
+}
+
+impl From<ModulationRouting> for ModulationSlotHandle {
+    fn from(routing: ModulationRouting) -> Self {
+        Self {
+            source: routing.source.into(),
+            destination: routing.destination.into(),
+            depth: routing.depth.into(),
+            curve: routing.curve.into(),
+            controller: AtomicUsize::new(routing.controller),
+        }
+    }
+}
+
+impl ModulationSlotHandle {
+    pub fn source(&self) -> ModulationSource {
+        self.source.get()
+    }
+
+    pub fn set_source(&self, value: ModulationSource) {
+        self.source.set(value);
+    }
+
+    pub fn destination(&self) -> ModulationDestination {
+        self.destination.get()
+    }
+
+    pub fn set_destination(&self, value: ModulationDestination) {
+        self.destination.set(value);
+    }
+
+    pub fn depth(&self) -> f32 {
+        self.depth.get()
+    }
+
+    pub fn set_depth(&self, value: f32) {
+        self.depth.set(value.clamp(-1.0, 1.0));
+    }
+
+    pub fn curve(&self) -> ModulationCurve {
+        self.curve.get()
+    }
+
+    pub fn set_curve(&self, value: ModulationCurve) {
+        self.curve.set(value);
+    }
+
+    pub fn controller(&self) -> usize {
+        self.controller.get()
+    }
+
+    /// Set the CC number, clamped between 0 and 127
+    pub fn set_controller(&self, value: usize) {
+        self.controller.set(value.min(127));
+    }
+
+    pub fn routing(&self) -> ModulationRouting {
+        ModulationRouting {
+            source: self.source(),
+            destination: self.destination(),
+            depth: self.depth(),
+            curve: self.curve(),
+            controller: self.controller(),
+        }
+    }
+
+    pub fn set_routing(&self, routing: ModulationRouting) {
+        self.set_source(routing.source);
+        self.set_destination(routing.destination);
+        self.set_depth(routing.depth);
+        self.set_curve(routing.curve);
+        self.set_controller(routing.controller);
+    }
+}
+
+/// Values of all modulation sources for one voice
+pub struct ModulationSourceValues<'a> {
+    pub lfos: [f32; NUM_LFOS],
+    pub amp_envelope: f32,
+    pub filter_envelope: f32,
+    pub velocity: f32,
+    pub key_tracking: f32,
+    pub aftertouch: f32,
+    /// Every CC value, normalized to 0-1
+    pub controllers: &'a [f32; 128],
+}
+
+impl<'a> ModulationSourceValues<'a> {
+    fn get(&self, source: ModulationSource, controller: usize) -> f32 {
+        match source {
+            ModulationSource::None => 0.0,
+            ModulationSource::Lfo1 => self.lfos[0],
+            ModulationSource::Lfo2 => self.lfos[1],
+            ModulationSource::AmpEnvelope => self.amp_envelope,
+            ModulationSource::FilterEnvelope => self.filter_envelope,
+            ModulationSource::Velocity => self.velocity,
+            ModulationSource::KeyTracking => self.key_tracking,
+            ModulationSource::ModWheel => self.controllers[1],
+            ModulationSource::Aftertouch => self.aftertouch,
+            ModulationSource::Controller => self.controllers[controller.min(127)],
+        }
+    }
+}
+
+/// Sum of all the slots targeting each destination, before scaling by the destination range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModulationValues {
+    pub pitch: f32,
+    pub pulse_width: f32,
+    pub filter_cutoff: f32,
+    pub filter_resonance: f32,
+    /// Product of all the amp slots
+    pub amp: f32,
+}
+
+impl Default for ModulationValues {
+    fn default() -> Self {
+        Self {
+            pitch: 0.0,
+            pulse_width: 0.0,
+            filter_cutoff: 0.0,
+            filter_resonance: 0.0,
+            amp: 1.0,
+        }
+    }
+}
+
+pub struct ModulationMatrixHandle {
+    slots: [ModulationSlotHandle; MAX_MODULATION_SLOTS],
+    lfos: [LfoHandle; NUM_LFOS],
+}
+
+impl Default for ModulationMatrixHandle {
+    fn default() -> Self {
+        use ModulationDestination::*;
+        use ModulationSource::*;
+
+        let mut routings = [ModulationRouting::default(); MAX_MODULATION_SLOTS];
+        routings[0] = ModulationRouting::new(FilterEnvelope, FilterCutoff, 0.4);
+        routings[1] = ModulationRouting::new(Velocity, FilterCutoff, 0.2);
+        routings[2] = ModulationRouting::new(Velocity, Amp, 1.0);
+        routings[3] = ModulationRouting::new(Aftertouch, FilterCutoff, 0.2);
+        routings[4] = ModulationRouting {
+            controller: 21,
+            ..ModulationRouting::new(Controller, FilterCutoff, 1.0)
+        };
+        routings[5] = ModulationRouting {
+            controller: 22,
+            ..ModulationRouting::new(Controller, FilterResonance, 1.0)
+        };
+
+        Self {
+            slots: routings.map(ModulationSlotHandle::from),
+            lfos: [
+                LfoHandle::new(5.0, LfoShape::Sine),
+                LfoHandle::new(0.5, LfoShape::Triangle),
+            ],
+        }
+    }
+}
+
+impl ModulationMatrixHandle {
+    pub fn slots(&self) -> &[ModulationSlotHandle; MAX_MODULATION_SLOTS] {
+        &self.slots
+    }
+
+    pub fn slot(&self, index: usize) -> &ModulationSlotHandle {
+        &self.slots[index]
+    }
+
+    pub fn lfos(&self) -> &[LfoHandle; NUM_LFOS] {
+        &self.lfos
+    }
+
+    pub fn lfo(&self, index: usize) -> &LfoHandle {
+        &self.lfos[index]
+    }
+
+    /// Snapshot of all routings
+    pub fn routings(&self) -> [ModulationRouting; MAX_MODULATION_SLOTS] {
+        let mut result = [ModulationRouting::default(); MAX_MODULATION_SLOTS];
+        for (routing, slot) in result.iter_mut().zip(&self.slots) {
+            *routing = slot.routing();
+        }
+        result
+    }
+
+    /// Replace all routings, slots missing from `routings` are cleared
+    pub fn set_routings(&self, routings: &[ModulationRouting]) {
+        for (index, slot) in self.slots.iter().enumerate() {
+            slot.set_routing(routings.get(index).copied().unwrap_or_default());
+        }
+    }
+
+    pub fn evaluate(&self, sources: &ModulationSourceValues) -> ModulationValues {
+        let mut values = ModulationValues::default();
+        for slot in &self.slots {
+            let destination = slot.destination();
+            let source = slot.source();
+            if destination == ModulationDestination::None || source == ModulationSource::None {
+                continue;
+            }
+
+            let value = slot.curve().apply(sources.get(source, slot.controller()));
+            let depth = slot.depth();
+            match destination {
+                ModulationDestination::None => {}
+                ModulationDestination::Pitch => values.pitch += value * depth,
+                ModulationDestination::PulseWidth => values.pulse_width += value * depth,
+                ModulationDestination::FilterCutoff => values.filter_cutoff += value * depth,
+                ModulationDestination::FilterResonance => values.filter_resonance += value * depth,
+                ModulationDestination::Amp => {
+                    values.amp *= (1.0 + depth * (value - 1.0)).max(0.0);
+                }
+            }
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sources(controllers: &[f32; 128]) -> ModulationSourceValues<'_> {
+        ModulationSourceValues {
+            lfos: [0.5, -1.0],
+            amp_envelope: 1.0,
+            filter_envelope: 0.5,
+            velocity: 0.25,
+            key_tracking: 0.0,
+            aftertouch: 0.0,
+            controllers,
+        }
+    }
+
+    #[test]
+    fn test_curves_keep_range_and_sign() {
+        for curve in [
+            ModulationCurve::Linear,
+            ModulationCurve::Exponential,
+            ModulationCurve::Logarithmic,
+            ModulationCurve::SCurve,
+        ] {
+            assert_eq!(curve.apply(0.0), 0.0);
+            assert!((curve.apply(1.0) - 1.0).abs() < 1e-6);
+            assert!((curve.apply(-1.0) + 1.0).abs() < 1e-6);
+        }
+        assert!((ModulationCurve::Exponential.apply(0.5) - 0.25).abs() < 1e-6);
+        assert!((ModulationCurve::Logarithmic.apply(-0.25) + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_evaluate_sums_slots_per_destination() {
+        let matrix = ModulationMatrixHandle::default();
+        matrix.set_routings(&[
+            ModulationRouting::new(ModulationSource::Lfo1, ModulationDestination::Pitch, 0.5),
+            ModulationRouting::new(ModulationSource::Lfo2, ModulationDestination::Pitch, 0.5),
+            ModulationRouting {
+                controller: 74,
+                ..ModulationRouting::new(
+                    ModulationSource::Controller,
+                    ModulationDestination::FilterCutoff,
+                    -1.0,
+                )
+            },
+        ]);
+        let mut controllers = [0.0; 128];
+        controllers[74] = 0.5;
+
+        let values = matrix.evaluate(&sources(&controllers));
+        assert!((values.pitch - -0.25).abs() < 1e-6);
+        assert!((values.filter_cutoff - -0.5).abs() < 1e-6);
+        assert_eq!(values.amp, 1.0);
+    }
+
+    #[test]
+    fn test_amp_slots_scale_volume() {
+        let matrix = ModulationMatrixHandle::default();
+        matrix.set_routings(&[ModulationRouting::new(
+            ModulationSource::Velocity,
+            ModulationDestination::Amp,
+            1.0,
+        )]);
+        let controllers = [0.0; 128];
+        assert!((matrix.evaluate(&sources(&controllers)).amp - 0.25).abs() < 1e-6);
+
+        matrix.slot(0).set_depth(0.5);
+        assert!((matrix.evaluate(&sources(&controllers)).amp - 0.625).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_routings_round_trip() {
+        let matrix = ModulationMatrixHandle::default();
+        let routings = matrix.routings();
+        let other = ModulationMatrixHandle::default();
+        other.set_routings(&[]);
+        assert_ne!(other.routings(), routings);
+        other.set_routings(&routings);
+        assert_eq!(other.routings(), routings);
+    }
+}
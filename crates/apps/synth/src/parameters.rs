@@ -0,0 +1,301 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use augmented_adsr_envelope::MultiStageEnvelope;
+use augmented_atomics::{AtomicEnum, AtomicF32, AtomicValue};
+use augmented_oscillator::polyblep::PolyBlepWaveform;
+
+use crate::modulation::ModulationMatrixHandle;
+
+/// Maximum number of voices, the polyphony can be set to anything up to this
+pub const MAX_VOICES: usize = 16;
+/// Maximum number of oscillators stacked on each voice
+pub const MAX_UNISON: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum VoiceMode {
+    /// Each note gets its own voice
+    Poly = 0,
+    /// A single voice, every note retriggers the envelopes
+    Mono = 1,
+    /// A single voice, overlapping notes only change its pitch
+    Legato = 2,
+}
+
+/// Which voice is taken when a note is played and all voices are busy. Voices which were
+/// released are always preferred over ones which are still held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum VoiceStealingMode {
+    Oldest = 0,
+    Quietest = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum OscillatorWaveform {
+    Saw = 0,
+    Square = 1,
+    Triangle = 2,
+}
+
+impl From<OscillatorWaveform> for PolyBlepWaveform {
+    fn from(waveform: OscillatorWaveform) -> Self {
+        match waveform {
+            OscillatorWaveform::Saw => PolyBlepWaveform::Saw,
+            OscillatorWaveform::Square => PolyBlepWaveform::Square,
+            OscillatorWaveform::Triangle => PolyBlepWaveform::Triangle,
+        }
+    }
+}
+
+/// ADSR settings shared by the envelopes of all voices. Times are in seconds.
+pub struct EnvelopeHandle {
+    attack: AtomicF32,
+    decay: AtomicF32,
+    sustain: AtomicF32,
+    release: AtomicF32,
+}
+
+impl EnvelopeHandle {
+    fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            attack: attack.into(),
+            decay: decay.into(),
+            sustain: sustain.into(),
+            release: release.into(),
+        }
+    }
+
+    pub fn attack(&self) -> f32 {
+        self.attack.get()
+    }
+
+    pub fn set_attack(&self, value: f32) {
+        self.attack.set(value.max(0.0));
+    }
+
+    pub fn decay(&self) -> f32 {
+        self.decay.get()
+    }
+
+    pub fn set_decay(&self, value: f32) {
+        self.decay.set(value.max(0.0));
+    }
+
+    pub fn sustain(&self) -> f32 {
+        self.sustain.get()
+    }
+
+    pub fn set_sustain(&self, value: f32) {
+        self.sustain.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn release(&self) -> f32 {
+        self.release.get()
+    }
+
+    pub fn set_release(&self, value: f32) {
+        self.release.set(value.max(0.0));
+    }
+
+    /// Copy these settings onto a voice's envelope
+    pub(crate) fn apply(&self, envelope: &MultiStageEnvelope) {
+        envelope.set_attack(Duration::from_secs_f32(self.attack()));
+        envelope.set_decay(Duration::from_secs_f32(self.decay()));
+        envelope.set_sustain(self.sustain());
+        envelope.set_release(Duration::from_secs_f32(self.release()));
+    }
+}
+
+/// Parameters of the [`crate::Synthesizer`], shared with the audio-thread.
+///
+/// Velocity, aftertouch, envelopes & controllers reach the voices through the
+/// [`ModulationMatrixHandle`].
+pub struct SynthesizerHandle {
+    volume: AtomicF32,
+    polyphony: AtomicUsize,
+    voice_mode: AtomicEnum<VoiceMode>,
+    voice_stealing_mode: AtomicEnum<VoiceStealingMode>,
+    glide: AtomicF32,
+    waveform: AtomicEnum<OscillatorWaveform>,
+    pulse_width: AtomicF32,
+    unison_voices: AtomicUsize,
+    unison_detune: AtomicF32,
+    unison_spread: AtomicF32,
+    voice_pan_spread: AtomicF32,
+    filter_cutoff: AtomicF32,
+    filter_resonance: AtomicF32,
+    amp_envelope: EnvelopeHandle,
+    filter_envelope: EnvelopeHandle,
+    modulation_matrix: ModulationMatrixHandle,
+}
+
+impl Default for SynthesizerHandle {
+    fn default() -> Self {
+        Self {
+            volume: 0.25.into(),
+            polyphony: AtomicUsize::new(8),
+            voice_mode: VoiceMode::Poly.into(),
+            voice_stealing_mode: VoiceStealingMode::Oldest.into(),
+            glide: 0.0.into(),
+            waveform: OscillatorWaveform::Saw.into(),
+            pulse_width: 0.5.into(),
+            unison_voices: AtomicUsize::new(3),
+            unison_detune: 10.0.into(),
+            unison_spread: 0.5.into(),
+            voice_pan_spread: 0.0.into(),
+            filter_cutoff: 2000.0.into(),
+            filter_resonance: 0.707.into(),
+            amp_envelope: EnvelopeHandle::new(0.01, 0.3, 0.8, 0.3),
+            filter_envelope: EnvelopeHandle::new(0.01, 0.4, 0.3, 0.3),
+            modulation_matrix: ModulationMatrixHandle::default(),
+        }
+    }
+}
+
+impl SynthesizerHandle {
+    pub fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    pub fn set_volume(&self, value: f32) {
+        self.volume.set(value);
+    }
+
+    pub fn polyphony(&self) -> usize {
+        self.polyphony.get()
+    }
+
+    /// Set the number of voices, clamped between 1 and [`MAX_VOICES`]
+    pub fn set_polyphony(&self, value: usize) {
+        self.polyphony.set(value.clamp(1, MAX_VOICES));
+    }
+
+    pub fn voice_mode(&self) -> VoiceMode {
+        self.voice_mode.get()
+    }
+
+    pub fn set_voice_mode(&self, value: VoiceMode) {
+        self.voice_mode.set(value);
+    }
+
+    pub fn voice_stealing_mode(&self) -> VoiceStealingMode {
+        self.voice_stealing_mode.get()
+    }
+
+    pub fn set_voice_stealing_mode(&self, value: VoiceStealingMode) {
+        self.voice_stealing_mode.set(value);
+    }
+
+    /// Glide time in seconds, only used on mono & legato modes
+    pub fn glide(&self) -> f32 {
+        self.glide.get()
+    }
+
+    pub fn set_glide(&self, value: f32) {
+        self.glide.set(value.max(0.0));
+    }
+
+    pub fn waveform(&self) -> OscillatorWaveform {
+        self.waveform.get()
+    }
+
+    pub fn set_waveform(&self, value: OscillatorWaveform) {
+        self.waveform.set(value);
+    }
+
+    pub fn pulse_width(&self) -> f32 {
+        self.pulse_width.get()
+    }
+
+    pub fn set_pulse_width(&self, value: f32) {
+        self.pulse_width.set(value);
+    }
+
+    pub fn unison_voices(&self) -> usize {
+        self.unison_voices.get()
+    }
+
+    /// Set the number of oscillators per voice, clamped between 1 and [`MAX_UNISON`]
+    pub fn set_unison_voices(&self, value: usize) {
+        self.unison_voices.set(value.clamp(1, MAX_UNISON));
+    }
+
+    /// Detune between the outermost unison oscillators & the note, in cents
+    pub fn unison_detune(&self) -> f32 {
+        self.unison_detune.get()
+    }
+
+    pub fn set_unison_detune(&self, value: f32) {
+        self.unison_detune.set(value.max(0.0));
+    }
+
+    /// How far apart the unison oscillators are panned, between 0 and 1
+    pub fn unison_spread(&self) -> f32 {
+        self.unison_spread.get()
+    }
+
+    pub fn set_unison_spread(&self, value: f32) {
+        self.unison_spread.set(value.clamp(0.0, 1.0));
+    }
+
+    /// How far apart voices are panned, between 0 and 1
+    pub fn voice_pan_spread(&self) -> f32 {
+        self.voice_pan_spread.get()
+    }
+
+    pub fn set_voice_pan_spread(&self, value: f32) {
+        self.voice_pan_spread.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn filter_cutoff(&self) -> f32 {
+        self.filter_cutoff.get()
+    }
+
+    pub fn set_filter_cutoff(&self, value: f32) {
+        self.filter_cutoff.set(value);
+    }
+
+    pub fn filter_resonance(&self) -> f32 {
+        self.filter_resonance.get()
+    }
+
+    pub fn set_filter_resonance(&self, value: f32) {
+        self.filter_resonance.set(value.max(0.1));
+    }
+
+    pub fn amp_envelope(&self) -> &EnvelopeHandle {
+        &self.amp_envelope
+    }
+
+    pub fn filter_envelope(&self) -> &EnvelopeHandle {
+        &self.filter_envelope
+    }
+
+    pub fn modulation_matrix(&self) -> &ModulationMatrixHandle {
+        &self.modulation_matrix
+    }
+}
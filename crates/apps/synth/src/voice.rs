@@ -20,88 +20,414 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
-use augmented_adsr_envelope::Envelope;
+use audio_processor_traits::AudioBuffer;
+use augmented_adsr_envelope::MultiStageEnvelope;
+use augmented_dsp_filters::rbj::Filter;
+use augmented_dsp_filters::state::FilterState;
+use augmented_oscillator::polyblep::PolyBlepOscillator;
 use augmented_oscillator::Oscillator;
 
+use crate::modulation::{
+    ModulationSourceValues, CUTOFF_MODULATION_RANGE, NUM_LFOS, PITCH_MODULATION_RANGE,
+    PULSE_WIDTH_MODULATION_RANGE, RESONANCE_MODULATION_RANGE,
+};
+use crate::parameters::{SynthesizerHandle, MAX_UNISON};
+
+/// Modulation, and so the filter coefficients, is updated every this many samples
+const CONTROL_INTERVAL: usize = 16;
+
+fn frequency_for_pitch(pitch: f32) -> f32 {
+    440.0 * 2.0_f32.powf((pitch - 69.0) / 12.0)
+}
+
+/// Constant power gains for a pan position between -1 (left) and 1 (right)
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// How a note should start on a voice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteTrigger {
+    /// Jump to the note & retrigger the envelopes
+    Retrigger,
+    /// Glide to the note & retrigger the envelopes
+    GlideRetrigger,
+    /// Glide to the note without retriggering the envelopes
+    Legato,
+}
+
+/// A single synthesizer voice: a stack of unison oscillators going through a low-pass filter
+/// per channel, with amp & filter envelopes.
 pub struct Voice {
-    oscillators: [Oscillator<f32>; 3],
-    envelope: Envelope,
+    oscillators: [PolyBlepOscillator; MAX_UNISON],
+    unison_ratios: [f32; MAX_UNISON],
+    unison_gains: [(f32, f32); MAX_UNISON],
+    unison_voices: usize,
+    amp_envelope: MultiStageEnvelope,
+    filter_envelope: MultiStageEnvelope,
+    filters: [Filter<f32>; 2],
+    lfos: [Oscillator<f32>; NUM_LFOS],
     current_note: Option<u8>,
-    volume: f32,
+    is_held: bool,
+    velocity: f32,
+    pressure: f32,
+    /// Pan position between -1 and 1, relative to the voice pan spread
+    pan: f32,
+    /// Pitch in fractional MIDI notes, this follows the target pitch when gliding
+    pitch: f32,
+    target_pitch: f32,
+    glide_coefficient: f32,
+    /// Pitch offset from the modulation matrix, in semi-tones
+    pitch_modulation: f32,
+    /// Amp modulation at the start & end of the current control interval
+    amp_modulation: (f32, f32),
+    /// Set when a new note starts, so amp modulation doesn't ramp from the previous note
+    reset_modulation: bool,
+    /// Note-on counter value when this voice started, used to find the oldest voice
+    started_at: u64,
+    sample_rate: f32,
 }
 
 impl Voice {
-    pub fn new(sample_rate: f32) -> Self {
+    pub fn new(sample_rate: f32, pan: f32) -> Self {
         Voice {
-            oscillators: [
-                Oscillator::new_with_sample_rate(
-                    sample_rate,
-                    augmented_oscillator::generators::square_generator,
-                ),
-                Oscillator::new_with_sample_rate(
-                    sample_rate,
-                    augmented_oscillator::generators::square_generator,
-                ),
-                Oscillator::new_with_sample_rate(
-                    sample_rate,
-                    augmented_oscillator::generators::square_generator,
-                ),
-            ],
-            envelope: Envelope::new(),
+            oscillators: [(); MAX_UNISON].map(|_| PolyBlepOscillator::saw(sample_rate)),
+            unison_ratios: [1.0; MAX_UNISON],
+            unison_gains: [pan_gains(0.0); MAX_UNISON],
+            unison_voices: 1,
+            amp_envelope: MultiStageEnvelope::default(),
+            filter_envelope: MultiStageEnvelope::default(),
+            filters: [Filter::new(), Filter::new()],
+            lfos: [(); NUM_LFOS].map(|_| Oscillator::sine(sample_rate)),
             current_note: None,
-            volume: 0.25,
+            is_held: false,
+            velocity: 0.0,
+            pressure: 0.0,
+            pan,
+            pitch: 69.0,
+            target_pitch: 69.0,
+            glide_coefficient: 0.0,
+            pitch_modulation: 0.0,
+            amp_modulation: (1.0, 1.0),
+            reset_modulation: true,
+            started_at: 0,
+            sample_rate,
         }
     }
 
-    pub fn current_note(&self) -> &Option<u8> {
-        &self.current_note
+    pub fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        for oscillator in &mut self.oscillators {
+            oscillator.set_sample_rate(sample_rate);
+        }
+        for lfo in &mut self.lfos {
+            lfo.set_sample_rate(sample_rate);
+        }
+        self.amp_envelope.set_sample_rate(sample_rate);
+        self.filter_envelope.set_sample_rate(sample_rate);
+    }
+
+    /// The last note played on this voice, this is kept during the release stage
+    pub fn current_note(&self) -> Option<u8> {
+        self.current_note
+    }
+
+    /// Whether the note is still held, as opposed to released or idle
+    pub fn is_held(&self) -> bool {
+        self.is_held
+    }
+
+    /// Whether the voice is producing sound
+    pub fn is_active(&self) -> bool {
+        !self.amp_envelope.is_idle()
     }
 
-    pub fn note_on(&mut self, note: u8, _velocity: u8) {
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    /// Current amp envelope level, used to find the quietest voice
+    pub fn level(&self) -> f32 {
+        if self.is_active() {
+            self.amp_envelope.volume() * self.velocity
+        } else {
+            0.0
+        }
+    }
+
+    pub fn note_on(
+        &mut self,
+        handle: &SynthesizerHandle,
+        note: u8,
+        velocity: u8,
+        trigger: NoteTrigger,
+        started_at: u64,
+    ) {
+        let was_active = self.is_active();
         self.current_note = Some(note);
-        self.oscillators[0].set_frequency(pitch_calc::hz_from_step(note as f32));
-        self.oscillators[1].set_frequency(pitch_calc::hz_from_step(note as f32) * 1.005);
-        self.oscillators[2].set_frequency(pitch_calc::hz_from_step(note as f32) * 0.995);
-        self.envelope.note_on();
+        self.is_held = true;
+        self.target_pitch = note as f32;
+
+        let glide = handle.glide();
+        if trigger == NoteTrigger::Retrigger || !was_active || glide <= 0.0 {
+            self.pitch = self.target_pitch;
+            self.glide_coefficient = 0.0;
+        } else {
+            self.glide_coefficient = (-1.0 / (glide * self.sample_rate)).exp();
+        }
+
+        if trigger == NoteTrigger::Legato && was_active {
+            return;
+        }
+
+        self.velocity = velocity as f32 / 127.0;
+        self.pressure = 0.0;
+        self.started_at = started_at;
+        if !was_active {
+            self.reset_modulation = true;
+            // Spread the unison phases so the oscillators don't start in-phase
+            for (index, oscillator) in self.oscillators.iter_mut().enumerate() {
+                oscillator.set_phase(index as f32 * 0.618_034);
+            }
+        }
+        // LFOs are retriggered on every note
+        for (lfo, lfo_handle) in self.lfos.iter_mut().zip(handle.modulation_matrix().lfos()) {
+            *lfo =
+                Oscillator::new_with_sample_rate(self.sample_rate, lfo_handle.shape().generator());
+        }
+        self.update_parameters(handle);
+        self.amp_envelope.note_on();
+        self.filter_envelope.note_on();
     }
 
     pub fn note_off(&mut self) {
-        self.current_note = None;
-        self.envelope.note_off();
+        self.is_held = false;
+        self.amp_envelope.note_off();
+        self.filter_envelope.note_off();
+    }
+
+    /// Set the aftertouch pressure, between 0 and 127
+    pub fn set_pressure(&mut self, pressure: u8) {
+        self.pressure = pressure as f32 / 127.0;
     }
-}
 
-impl AudioProcessor for Voice {
-    type SampleType = f32;
+    /// Read the shared parameters into this voice
+    fn update_parameters(&mut self, handle: &SynthesizerHandle) {
+        handle.amp_envelope().apply(&self.amp_envelope);
+        handle.filter_envelope().apply(&self.filter_envelope);
 
-    fn prepare(&mut self, context: &mut AudioContext) {
-        let settings = context.settings;
+        let waveform = handle.waveform().into();
         for oscillator in &mut self.oscillators {
-            oscillator.set_sample_rate(settings.sample_rate());
+            oscillator.set_waveform(waveform);
+        }
+        for (lfo, lfo_handle) in self.lfos.iter_mut().zip(handle.modulation_matrix().lfos()) {
+            lfo.set_generator(lfo_handle.shape().generator());
+            lfo.set_frequency(lfo_handle.rate());
+        }
+
+        let unison_voices = handle.unison_voices();
+        let detune = handle.unison_detune();
+        let spread = handle.unison_spread();
+        let pan = self.pan * handle.voice_pan_spread();
+        let gain = 1.0 / (unison_voices as f32).sqrt();
+        for index in 0..unison_voices {
+            // Position between -1 and 1 of this oscillator in the stack
+            let position = if unison_voices > 1 {
+                index as f32 / (unison_voices - 1) as f32 * 2.0 - 1.0
+            } else {
+                0.0
+            };
+            self.unison_ratios[index] = 2.0_f32.powf(position * detune / 1200.0);
+            let (left, right) = pan_gains(pan + position * spread);
+            self.unison_gains[index] = (left * gain, right * gain);
+        }
+        if unison_voices != self.unison_voices {
+            self.unison_voices = unison_voices;
+            self.update_frequencies();
         }
-        self.envelope.set_sample_rate(settings.sample_rate());
     }
 
-    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
-        for sample_index in 0..data.num_samples() {
-            let mut oscillator_value = 0.0;
-            for oscillator in &self.oscillators {
-                oscillator_value += oscillator.get();
+    fn update_frequencies(&mut self) {
+        let frequency = frequency_for_pitch(self.pitch + self.pitch_modulation);
+        for (oscillator, ratio) in self
+            .oscillators
+            .iter_mut()
+            .zip(self.unison_ratios)
+            .take(self.unison_voices)
+        {
+            oscillator.set_frequency(frequency * ratio);
+        }
+    }
+
+    /// Evaluate the modulation matrix & apply it to the oscillators & filters, then move the
+    /// LFOs forward by `samples`
+    fn update_modulation(
+        &mut self,
+        handle: &SynthesizerHandle,
+        controllers: &[f32; 128],
+        samples: usize,
+    ) {
+        let matrix = handle.modulation_matrix();
+        let sources = ModulationSourceValues {
+            lfos: [self.lfos[0].get(), self.lfos[1].get()],
+            amp_envelope: self.amp_envelope.volume(),
+            filter_envelope: self.filter_envelope.volume(),
+            velocity: self.velocity,
+            key_tracking: (self.target_pitch - 60.0) / 60.0,
+            aftertouch: self.pressure,
+            controllers,
+        };
+        let modulation = matrix.evaluate(&sources);
+        for lfo in &mut self.lfos {
+            lfo.tick_n(samples as f32);
+        }
+
+        self.pitch_modulation = modulation.pitch * PITCH_MODULATION_RANGE;
+        self.update_frequencies();
+
+        let pulse_width =
+            handle.pulse_width() + modulation.pulse_width * PULSE_WIDTH_MODULATION_RANGE;
+        for oscillator in self.oscillators.iter_mut().take(self.unison_voices) {
+            oscillator.set_pulse_width(pulse_width);
+        }
+
+        let cutoff = (handle.filter_cutoff()
+            * 2.0_f32.powf(modulation.filter_cutoff * CUTOFF_MODULATION_RANGE))
+        .clamp(20.0, self.sample_rate * 0.45);
+        let resonance = handle.filter_resonance()
+            * 2.0_f32.powf(modulation.filter_resonance * RESONANCE_MODULATION_RANGE);
+        for filter in &mut self.filters {
+            filter.setup_low_pass(self.sample_rate, cutoff, resonance);
+        }
+
+        let amp_start = if self.reset_modulation {
+            modulation.amp
+        } else {
+            self.amp_modulation.1
+        };
+        self.amp_modulation = (amp_start, modulation.amp);
+        self.reset_modulation = false;
+    }
+
+    /// Add this voice's output onto `data`. `controllers` are the current CC values normalized
+    /// to 0-1.
+    pub fn process(
+        &mut self,
+        handle: &SynthesizerHandle,
+        controllers: &[f32; 128],
+        data: &mut AudioBuffer<f32>,
+    ) {
+        self.update_parameters(handle);
+        self.update_frequencies();
+
+        let gain = handle.volume();
+        let num_samples = data.num_samples();
+
+        for sample_index in 0..num_samples {
+            if !self.is_active() {
+                break;
             }
 
-            let envelope_volume = self.envelope.volume();
-            let output = self.volume * oscillator_value * envelope_volume;
+            let control_index = sample_index % CONTROL_INTERVAL;
+            if control_index == 0 {
+                let samples = CONTROL_INTERVAL.min(num_samples - sample_index);
+                self.update_modulation(handle, controllers, samples);
+            }
+            if self.pitch != self.target_pitch {
+                self.pitch =
+                    self.target_pitch + self.glide_coefficient * (self.pitch - self.target_pitch);
+                if (self.pitch - self.target_pitch).abs() < 0.001 {
+                    self.pitch = self.target_pitch;
+                }
+                self.update_frequencies();
+            }
 
-            for channel_index in 0..data.num_channels() {
-                let sample = data.get_mut(channel_index, sample_index);
-                *sample += output;
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for (oscillator, (left_gain, right_gain)) in self
+                .oscillators
+                .iter_mut()
+                .zip(self.unison_gains)
+                .take(self.unison_voices)
+            {
+                let value = oscillator.next_sample();
+                left += value * left_gain;
+                right += value * right_gain;
             }
 
-            self.envelope.tick();
-            for oscillator in &mut self.oscillators {
-                oscillator.tick();
+            let [left_filter, right_filter] = &mut self.filters;
+            let left = left_filter.state.process1(
+                &left_filter.coefficients,
+                left,
+                left_filter.denormal_prevention.alternating_current(),
+            );
+            let right = right_filter.state.process1(
+                &right_filter.coefficients,
+                right,
+                right_filter.denormal_prevention.alternating_current(),
+            );
+
+            // Ramp amp modulation over the control interval to avoid zipper noise
+            let (amp_start, amp_end) = self.amp_modulation;
+            let ramp = control_index as f32 / CONTROL_INTERVAL as f32;
+            let amp_modulation = amp_start + (amp_end - amp_start) * ramp;
+            let amp = self.amp_envelope.volume() * gain * amp_modulation;
+            let (left, right) = (left * amp, right * amp);
+
+            let num_channels = data.num_channels();
+            if num_channels == 1 {
+                *data.get_mut(0, sample_index) += (left + right) * 0.5;
+            } else {
+                for channel_index in 0..num_channels {
+                    let value = if channel_index % 2 == 0 { left } else { right };
+                    *data.get_mut(channel_index, sample_index) += value;
+                }
             }
+
+            self.amp_envelope.tick();
+            self.filter_envelope.tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pan_gains_are_constant_power() {
+        for pan in [-1.0, -0.5, 0.0, 0.3, 1.0] {
+            let (left, right) = pan_gains(pan);
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+        let (left, right) = pan_gains(-1.0);
+        assert!((left - 1.0).abs() < 1e-5 && right.abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_frequency_for_pitch() {
+        assert!((frequency_for_pitch(69.0) - 440.0).abs() < 1e-3);
+        assert!((frequency_for_pitch(81.0) - 880.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_glide_moves_pitch_gradually() {
+        let handle = SynthesizerHandle::default();
+        handle.set_glide(0.1);
+        let mut voice = Voice::new(1000.0, 0.0);
+        voice.prepare(1000.0);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 10);
+
+        voice.note_on(&handle, 60, 100, NoteTrigger::GlideRetrigger, 0);
+        assert_eq!(voice.pitch, 60.0);
+        voice.note_on(&handle, 72, 100, NoteTrigger::Legato, 1);
+        voice.process(&handle, &[0.0; 128], &mut buffer);
+        assert!(voice.pitch > 60.0 && voice.pitch < 72.0, "{}", voice.pitch);
+        for _ in 0..100 {
+            voice.process(&handle, &[0.0; 128], &mut buffer);
         }
+        assert_eq!(voice.pitch, 72.0);
     }
 }
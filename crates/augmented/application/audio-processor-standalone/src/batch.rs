@@ -0,0 +1,200 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Render folders of audio files through a processor, in parallel.
+//!
+//! Each file gets a fresh processor from a factory and goes through
+//! [`crate::offline::run_offline_render`]. Outputs mirror the input folder structure, see
+//! [`audio_processor_file::batch`].
+
+use std::path::PathBuf;
+
+use basedrop::Handle;
+
+pub use audio_processor_file::batch::{BatchError, BatchFileReport, BatchInput, BatchReport};
+
+use crate::offline::{run_offline_render, OfflineRenderOptions, OfflineRenderSettings};
+use crate::StandaloneProcessor;
+
+/// Report file name, written into the output directory unless a report path is set
+pub const DEFAULT_REPORT_FILE: &str = "report.json";
+
+pub struct BatchRenderOptions<'a> {
+    pub input: BatchInput,
+    pub output_directory: PathBuf,
+    /// Where to write the JSON report, defaults to [`DEFAULT_REPORT_FILE`] in the output directory
+    pub report_path: Option<PathBuf>,
+    /// Number of files rendered at once, defaults to the number of CPUs
+    pub jobs: Option<usize>,
+    /// GC handle, see <https://crates.io/crates/audio-garbage-collector>
+    pub handle: Option<&'a Handle>,
+    /// MIDI file passed through every processor
+    pub midi_input_path: Option<&'a str>,
+    pub settings: OfflineRenderSettings,
+}
+
+/// Render every input file into a WAV file in the output directory, then write the JSON report.
+///
+/// `factory` is called once per file. Failed files are recorded in the report and don't stop the
+/// batch; the returned error is only for failures to find inputs or write the report.
+pub fn run_batch_render<SP, F>(
+    options: BatchRenderOptions,
+    factory: F,
+) -> Result<BatchReport, BatchError>
+where
+    SP: StandaloneProcessor,
+    F: Fn() -> SP + Sync,
+{
+    let jobs =
+        audio_processor_file::batch::plan_batch(&options.input, &options.output_directory, "wav")?;
+    log::info!(
+        "Batch rendering {} files into {:?}",
+        jobs.len(),
+        options.output_directory
+    );
+
+    let handle = options.handle;
+    let midi_input_path = options.midi_input_path;
+    let settings = options.settings;
+    let report = audio_processor_file::batch::run_batch(jobs, options.jobs, |job| {
+        let input_path = job.input.to_str().ok_or("Invalid input path")?;
+        let output_path = job.output.to_str().ok_or("Invalid output path")?;
+        #[cfg(feature = "midi")]
+        let midi_input_file = midi_input_path.map(read_midi_file).transpose()?;
+        #[cfg(not(feature = "midi"))]
+        let _ = midi_input_path;
+
+        run_offline_render(OfflineRenderOptions {
+            app: factory(),
+            handle,
+            input_path: Some(input_path),
+            output_path,
+            #[cfg(feature = "midi")]
+            midi_input_file,
+            settings: settings.clone(),
+            on_progress: None,
+        })
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+    })?;
+
+    let output_directory = options.output_directory;
+    let report_path = options
+        .report_path
+        .unwrap_or_else(|| output_directory.join(DEFAULT_REPORT_FILE));
+    report.write_json(&report_path)?;
+    log::info!("Wrote batch report into {:?}", report_path);
+
+    Ok(report)
+}
+
+#[cfg(feature = "midi")]
+fn read_midi_file(path: &str) -> Result<augmented_midi::MIDIFile<String, Vec<u8>>, String> {
+    let file_contents = std::fs::read(path).map_err(|err| err.to_string())?;
+    let (_, midi_file) = augmented_midi::parse_midi_file::<String, Vec<u8>>(&file_contents)
+        .map_err(|_| format!("Failed to parse MIDI file {}", path))?;
+    Ok(midi_file)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use audio_processor_file::OutputAudioFileProcessor;
+    use audio_processor_traits::{
+        AudioBuffer, AudioContext, AudioProcessor, AudioProcessorSettings,
+    };
+
+    use crate::StandaloneAudioOnlyProcessor;
+
+    use super::*;
+
+    struct GainProcessor {
+        gain: f32,
+    }
+
+    impl AudioProcessor for GainProcessor {
+        type SampleType = f32;
+
+        fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<f32>) {
+            for sample in data.slice_mut() {
+                *sample *= self.gain;
+            }
+        }
+    }
+
+    fn write_constant(path: &Path, level: f32, num_frames: usize) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let settings = AudioProcessorSettings {
+            block_size: num_frames,
+            ..AudioProcessorSettings::default()
+        };
+        let mut output = OutputAudioFileProcessor::from_path(settings, path.to_str().unwrap());
+        output.prepare(settings).unwrap();
+        let mut buffer = AudioBuffer::new(vec![vec![level; num_frames]; 2]);
+        output.process(&mut buffer).unwrap();
+        output.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_render() {
+        let _ = wisual_logger::try_init_from_env();
+        let directory = tempdir::TempDir::new("audio_processor_standalone__batch").unwrap();
+        let input = directory.path().join("input");
+        write_constant(&input.join("drums/kick.wav"), 0.5, 4410);
+        write_constant(&input.join("pad.wav"), 0.5, 44100);
+        std::fs::create_dir_all(input.join("broken")).unwrap();
+        std::fs::write(input.join("broken/empty.wav"), "not audio").unwrap();
+
+        let output = directory.path().join("output");
+        let report = run_batch_render(
+            BatchRenderOptions {
+                input: BatchInput::Directory(input),
+                output_directory: output.clone(),
+                report_path: None,
+                jobs: Some(2),
+                handle: Some(audio_garbage_collector::handle()),
+                midi_input_path: None,
+                settings: Default::default(),
+            },
+            || StandaloneAudioOnlyProcessor::new(GainProcessor { gain: 0.5 }, Default::default()),
+        )
+        .unwrap();
+
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(report.num_failed(), 1);
+        assert!(report.files[0].error.is_some());
+
+        let kick = &report.files[1];
+        assert_eq!(kick.output, output.join("drums/kick.wav"));
+        // Input files are read through the sample rate converter, which isn't sample accurate
+        assert!((kick.duration_secs.unwrap() - 0.1).abs() < 0.01);
+        assert!((kick.loudness_db.unwrap() + 12.04).abs() < 0.1);
+        assert!(output.join("pad.wav").exists());
+
+        let written: BatchReport =
+            serde_json::from_slice(&std::fs::read(output.join(DEFAULT_REPORT_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(written.files.len(), 3);
+        assert_eq!(written.files[1].output, kick.output);
+    }
+}
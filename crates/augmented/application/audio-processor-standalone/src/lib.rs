@@ -33,6 +33,7 @@
 //! ## Navigating the documentation
 //! * Look at exported functions & macros; the structs/traits are for more advanced/internal usage.
 //! * Start with [`audio_processor_main`] and [`audio_processor_main_with_midi`]
+//! * Use [`audio_processor_main_with_factory`] to also batch render folders of files
 //! * There are plenty examples in the `augmented-audio` repository
 //!
 //! The gist of it is:
@@ -88,13 +89,26 @@
 //!
 //! FLAGS:
 //! -h, --help       Prints help information
+//! --tail           Keep rendering offline past the end until the output is silent
 //! -V, --version    Prints version information
 //!
 //! OPTIONS:
 //! -i, --input-file <INPUT_PATH>              An input audio file to process
 //! --midi-input-file <MIDI_INPUT_FILE>    If specified, this MIDI file will be passed through the processor
 //! -o, --output-file <OUTPUT_PATH>            If specified, will render offline into this file (WAV)
+//! --sample-rate <SAMPLE_RATE>            Sample rate of offline renders, defaults to 44100
+//! --block-size <BLOCK_SIZE>              Block size of offline renders, defaults to 16
+//! --channels <CHANNELS>                  Number of channels of offline renders, defaults to 2
+//! --duration <SECONDS>                   Length of offline renders, defaults to the input file length
+//! --tail-threshold <DB>                  Level below which the output is silent for --tail, defaults to -80
+//! --batch-input <INPUT>                  Render every audio file in this directory or glob offline
+//! --batch-output <OUTPUT_DIRECTORY>      Directory batch renders are written into, mirroring the input folders
+//! --batch-report <REPORT_PATH>           Where to write the batch JSON report, defaults to report.json in the output directory
+//! --jobs <JOBS>                          Number of files to batch render at once, defaults to the number of CPUs
 //! ```
+//!
+//! Batch rendering (`--batch-input`) needs a new processor per file, so it's only available
+//! through [`audio_processor_main_with_factory`] and [`audio_processor_main_with_midi_factory`].
 
 use basedrop::Handle;
 use cpal::traits::HostTrait;
@@ -124,6 +138,10 @@ pub mod standalone_processor;
 #[cfg(not(target_os = "ios"))]
 pub mod offline;
 
+/// Parallel offline rendering of folders of files
+#[cfg(not(target_os = "ios"))]
+pub mod batch;
+
 /// VST support (VST is not compiled for iOS)
 #[cfg(all(feature = "vst", not(target_os = "ios")))]
 pub mod standalone_vst;
@@ -176,6 +194,94 @@ pub fn audio_processor_main<Processor: AudioProcessor<SampleType = f32> + Send +
     standalone_main(app, None);
 }
 
+/// A default main function for an [`AudioProcessor`] that can be created more than once.
+///
+/// Same as `audio_processor_main`, but `factory` is also used to create a processor per file when
+/// batch rendering with `--batch-input` & `--batch-output`.
+pub fn audio_processor_main_with_factory<Processor, F>(factory: F)
+where
+    Processor: AudioProcessor<SampleType = f32> + Send + 'static,
+    F: Fn() -> Processor + Sync,
+{
+    let options = options::parse_options(ParseOptionsParams {
+        supports_midi: false,
+    });
+    let standalone_options = StandaloneOptions {
+        input_device: options.rendering().input_device(),
+        output_device: options.rendering().output_device(),
+        ..Default::default()
+    };
+    standalone_main_with_factory(
+        || StandaloneAudioOnlyProcessor::new(factory(), standalone_options.clone()),
+        None,
+    );
+}
+
+/// A default main function for an [`AudioProcessor`] and [`MidiEventHandler`] that can be created
+/// more than once.
+///
+/// Same as `audio_processor_main_with_midi`, but `factory` is also used to create a processor per
+/// file when batch rendering with `--batch-input` & `--batch-output`. The `--midi-input-file` is
+/// passed through every processor.
+pub fn audio_processor_main_with_midi_factory<Processor, F>(factory: F, handle: &Handle)
+where
+    Processor: AudioProcessor<SampleType = f32> + MidiEventHandler + Send + 'static,
+    F: Fn() -> Processor + Sync,
+{
+    standalone_main_with_factory(|| StandaloneProcessorImpl::new(factory()), Some(handle));
+}
+
+/// Internal main function used by the `_factory` main functions.
+fn standalone_main_with_factory<SP, F>(factory: F, handle: Option<&Handle>)
+where
+    SP: StandaloneProcessor,
+    F: Fn() -> SP + Sync,
+{
+    let mut app = factory();
+    let options = options::parse_options(ParseOptionsParams {
+        supports_midi: app.supports_midi(),
+    });
+
+    #[cfg(not(target_os = "ios"))]
+    if let RenderingOptions::Batch {
+        input,
+        output_directory,
+        report_file,
+        jobs,
+        options: offline_options,
+    } = options.rendering()
+    {
+        drop(app);
+        let result = batch::run_batch_render(
+            batch::BatchRenderOptions {
+                input: batch::BatchInput::parse(input),
+                output_directory: output_directory.into(),
+                report_path: report_file.as_ref().map(|report_file| report_file.into()),
+                jobs: *jobs,
+                handle,
+                midi_input_path: options.midi().input_file.as_deref(),
+                settings: offline::OfflineRenderSettings::from_options(offline_options),
+            },
+            factory,
+        );
+        match result {
+            Ok(report) => {
+                report.log_summary();
+                if report.num_failed() > 0 {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                log::error!("Batch rendering failed: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    standalone_main(app, handle);
+}
+
 /// Internal main function used by `audio_processor_main`.
 fn standalone_main<SP: StandaloneProcessor>(mut app: SP, handle: Option<&Handle>) {
     let options = options::parse_options(ParseOptionsParams {
@@ -202,6 +308,7 @@ fn standalone_main<SP: StandaloneProcessor>(mut app: SP, handle: Option<&Handle>
         RenderingOptions::Offline {
             input_file: input_path,
             output_file: output_path,
+            options: offline_options,
         } => {
             #[cfg(feature = "midi")]
             let midi_input_file = options.midi().input_file.as_ref().map(|midi_input_file| {
@@ -213,14 +320,38 @@ fn standalone_main<SP: StandaloneProcessor>(mut app: SP, handle: Option<&Handle>
                 midi_file
             });
 
-            offline::run_offline_render(offline::OfflineRenderOptions {
+            let result = offline::run_offline_render(offline::OfflineRenderOptions {
                 app,
                 handle,
-                input_path,
+                input_path: input_path.as_deref(),
                 output_path,
                 #[cfg(feature = "midi")]
                 midi_input_file,
+                settings: offline::OfflineRenderSettings::from_options(offline_options),
+                on_progress: Some(Box::new(|progress| {
+                    log::debug!("Rendering progress={:.2}", progress.fraction());
+                })),
             });
+            match result {
+                Ok(summary) => {
+                    log::info!(
+                        "Rendered {:?} into {} (tail_frames={})",
+                        summary.duration(),
+                        output_path,
+                        summary.tail_frames
+                    );
+                }
+                Err(err) => {
+                    log::error!("Offline rendering failed: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        RenderingOptions::Batch { .. } => {
+            log::error!(
+                "Batch rendering needs a processor per file, use `audio_processor_main_with_factory`"
+            );
+            std::process::exit(1);
         }
         #[cfg(target_os = "ios")]
         _ => {
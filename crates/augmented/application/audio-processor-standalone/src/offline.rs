@@ -20,7 +20,11 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use std::time::Duration;
+
 use audio_garbage_collector::Handle;
+use audio_processor_file::file_io::AudioFileError;
+use audio_processor_file::OutputFileError;
 use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor, AudioProcessorSettings};
 #[cfg(feature = "midi")]
 use audio_processor_traits::{MidiEventHandler, MidiMessageLike};
@@ -32,25 +36,218 @@ use augmented_midi::{
     MIDIFile, MIDIFileChunk, MIDIMessage, MIDIMessageNote, MIDITrackEvent, MIDITrackInner,
 };
 
+use crate::options::OfflineOptions;
 use crate::StandaloneProcessor;
 
+/// Tempo MIDI files are rendered at
+#[cfg(feature = "midi")]
+const MIDI_TEMPO: f32 = 120.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OfflineRenderError {
+    #[error("Failed to read the input file")]
+    InputFile(#[from] AudioFileError),
+    #[error("Failed to write the output file")]
+    OutputFile(#[from] OutputFileError),
+    #[error("Render duration is unknown, a duration is required without an input or MIDI file")]
+    MissingDuration,
+    #[error("Invalid render settings: {0}")]
+    InvalidSettings(&'static str),
+}
+
+/// Rendering past the end of the input until the output is silent, so reverb & delay tails
+/// aren't cut
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailSettings {
+    /// Output below this peak level counts as silence
+    pub threshold_db: f32,
+    /// The render stops once the output has been silent for this long
+    pub silence_duration: Duration,
+    /// Maximum length of the tail, for processors which never go silent
+    pub max_duration: Duration,
+}
+
+impl Default for TailSettings {
+    fn default() -> Self {
+        Self {
+            threshold_db: -80.0,
+            silence_duration: Duration::from_millis(100),
+            max_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Audio settings & length of an offline render
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineRenderSettings {
+    pub sample_rate: f32,
+    /// MIDI events are forwarded at the start of each block, so small blocks keep them closer
+    /// to their time
+    pub block_size: usize,
+    /// Number of channels of both the processor & the output file
+    pub channels: usize,
+    /// Length of the render before the tail. Defaults to the input file length, or to the MIDI
+    /// file length when there's no input.
+    pub duration: Option<Duration>,
+    pub tail: Option<TailSettings>,
+}
+
+impl Default for OfflineRenderSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            block_size: 16,
+            channels: 2,
+            duration: None,
+            tail: None,
+        }
+    }
+}
+
+impl OfflineRenderSettings {
+    /// Settings from the CLI options, missing options use the defaults
+    pub fn from_options(options: &OfflineOptions) -> Self {
+        let defaults = Self::default();
+        Self {
+            sample_rate: options.sample_rate.unwrap_or(defaults.sample_rate),
+            block_size: options.block_size.unwrap_or(defaults.block_size),
+            channels: options.channels.unwrap_or(defaults.channels),
+            duration: options.duration,
+            tail: options.tail.then(|| TailSettings {
+                threshold_db: options
+                    .tail_threshold_db
+                    .unwrap_or_else(|| TailSettings::default().threshold_db),
+                ..TailSettings::default()
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<(), OfflineRenderError> {
+        if !self.sample_rate.is_finite() || self.sample_rate <= 0.0 {
+            return Err(OfflineRenderError::InvalidSettings(
+                "sample rate must be a positive number",
+            ));
+        }
+        if self.block_size == 0 {
+            return Err(OfflineRenderError::InvalidSettings(
+                "block size must be positive",
+            ));
+        }
+        if self.channels == 0 {
+            return Err(OfflineRenderError::InvalidSettings(
+                "channels must be positive",
+            ));
+        }
+        Ok(())
+    }
+
+    fn frames(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64).round() as usize
+    }
+}
+
+/// Progress of an offline render, reported after each block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfflineRenderProgress {
+    pub rendered_frames: usize,
+    /// Frames before the tail, the total is unknown while rendering the tail
+    pub total_frames: usize,
+    pub is_tail: bool,
+}
+
+impl OfflineRenderProgress {
+    /// Progress between 0 and 1, 1 while rendering the tail
+    pub fn fraction(&self) -> f32 {
+        if self.total_frames == 0 {
+            return 1.0;
+        }
+        (self.rendered_frames as f32 / self.total_frames as f32).min(1.0)
+    }
+}
+
+/// Length of a finished render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OfflineRenderSummary {
+    pub sample_rate: f32,
+    /// Frames written, including the tail
+    pub num_frames: usize,
+    pub tail_frames: usize,
+}
+
+impl OfflineRenderSummary {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.num_frames as f64 / self.sample_rate as f64)
+    }
+}
+
 /// Offline rendering options
 pub struct OfflineRenderOptions<'a, Processor: StandaloneProcessor> {
     /// The audio/MIDI processor
     pub app: Processor,
     /// GC handle, see <https://crates.io/crates/audio-garbage-collector>
     pub handle: Option<&'a Handle>,
-    /// Input audio file path
-    pub input_path: &'a str,
+    /// Input audio file path, generators may render without an input
+    pub input_path: Option<&'a str>,
     /// Output audio file path
     pub output_path: &'a str,
     #[cfg(feature = "midi")]
     /// MIDI input file path
     pub midi_input_file: Option<MIDIFile<String, Vec<u8>>>,
+    pub settings: OfflineRenderSettings,
+    /// Called after each rendered block
+    pub on_progress: Option<Box<dyn FnMut(OfflineRenderProgress) + 'a>>,
+}
+
+/// Read the input file at the render sample rate. Input channels past the ones in the file
+/// repeat its channels.
+fn read_input_file(
+    handle: &Handle,
+    input_path: &str,
+    settings: AudioProcessorSettings,
+) -> Result<Vec<Vec<f32>>, OfflineRenderError> {
+    let audio_file_settings = audio_processor_file::InMemoryAudioFile::from_path(input_path)?;
+    let mut audio_file_processor =
+        audio_processor_file::AudioFileProcessor::new(handle, audio_file_settings, settings);
+    audio_file_processor.prepare(&mut AudioContext::from(settings));
+
+    let file_channels = audio_file_processor.buffer();
+    if file_channels.is_empty() {
+        return Err(AudioFileError::EmptyFileError.into());
+    }
+    Ok((0..settings.input_channels())
+        .map(|channel| file_channels[channel % file_channels.len()].clone())
+        .collect())
+}
+
+#[cfg(feature = "midi")]
+/// Length of a MIDI file, up to its last event
+fn midi_file_duration(midi_file: &MIDIFile<String, Vec<u8>>) -> Duration {
+    let ticks_per_quarter_note = midi_file.ticks_per_quarter_note().max(1) as f64;
+    let last_tick = midi_file
+        .chunks
+        .iter()
+        .filter_map(|chunk| match chunk {
+            MIDIFileChunk::Track { events } => Some(
+                events
+                    .iter()
+                    .map(|event| event.delta_time as u64)
+                    .sum::<u64>(),
+            ),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let beats = last_tick as f64 / ticks_per_quarter_note;
+    Duration::from_secs_f64(beats * 60.0 / MIDI_TEMPO as f64)
 }
 
 /// Render a processor offline into a file.
-pub fn run_offline_render<Processor>(options: OfflineRenderOptions<Processor>)
+///
+/// The render lasts for the settings duration, the input file or the MIDI file, in that order,
+/// then for the tail if enabled. The input is silent past its end.
+pub fn run_offline_render<Processor>(
+    options: OfflineRenderOptions<Processor>,
+) -> Result<OfflineRenderSummary, OfflineRenderError>
 where
     Processor: StandaloneProcessor,
 {
@@ -61,37 +258,55 @@ where
         output_path,
         #[cfg(feature = "midi")]
         midi_input_file,
+        settings,
+        mut on_progress,
     } = options;
 
     let _ = wisual_logger::try_init_from_env();
+    settings.validate()?;
 
     #[allow(clippy::redundant_closure)]
     let handle = handle.unwrap_or_else(|| audio_garbage_collector::handle());
 
     log::info!(
-        "Rendering offline input={} output={}",
+        "Rendering offline input={:?} output={} settings={:?}",
         input_path,
-        output_path
+        output_path,
+        settings
     );
 
-    let buffer_size = 16;
-    let sample_rate = 44100.0;
-    let audio_processor_settings = AudioProcessorSettings::new(sample_rate, 2, 2, buffer_size);
+    let block_size = settings.block_size;
+    let audio_processor_settings = AudioProcessorSettings::new(
+        settings.sample_rate,
+        settings.channels,
+        settings.channels,
+        block_size,
+    );
     let mut context = AudioContext::from(audio_processor_settings);
 
-    let audio_file_settings = audio_processor_file::InMemoryAudioFile::from_path(input_path)
-        .expect("Failed to read input file");
-
     // Set-up input file
-    log::info!("Loading input file");
-    let mut audio_file_processor = audio_processor_file::AudioFileProcessor::new(
-        handle,
-        audio_file_settings,
-        audio_processor_settings,
-    );
-    audio_file_processor.prepare(&mut context);
-    let audio_file_buffer = audio_file_processor.buffer();
-    let audio_file_total_samples = audio_file_buffer[0].len();
+    let input = match input_path {
+        Some(input_path) => {
+            log::info!("Loading input file");
+            Some(read_input_file(
+                handle,
+                input_path,
+                audio_processor_settings,
+            )?)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "midi")]
+    let midi_duration = midi_input_file.as_ref().map(midi_file_duration);
+    #[cfg(not(feature = "midi"))]
+    let midi_duration: Option<Duration> = None;
+    let total_frames = match (settings.duration, &input, midi_duration) {
+        (Some(duration), _, _) => settings.frames(duration),
+        (None, Some(input), _) => input[0].len(),
+        (None, None, Some(duration)) => settings.frames(duration),
+        (None, None, None) => return Err(OfflineRenderError::MissingDuration),
+    };
 
     // Set-up output file
     log::info!("Setting-up output buffers");
@@ -99,39 +314,75 @@ where
         audio_processor_settings,
         output_path,
     );
-    output_file_processor.prepare(audio_processor_settings);
+    output_file_processor.prepare(audio_processor_settings)?;
 
     // Set-up output buffer
-    let block_size = audio_processor_settings.block_size();
-    let total_blocks = audio_file_total_samples / block_size;
+    let total_blocks = total_frames.div_ceil(block_size);
     let mut buffer = AudioBuffer::empty();
-    buffer.resize(audio_processor_settings.input_channels(), block_size);
+    buffer.resize(settings.channels, block_size);
 
     log::info!("Setting-up audio processor");
     app.processor().prepare(&mut context);
 
+    // Events at the very end, like the last note-off when the length comes from the MIDI file,
+    // fall on the block after the last one, which is the start of the tail
     #[cfg(feature = "midi")]
     let midi_input_blocks = midi_input_file.map(|midi_input_file| {
-        build_midi_input_blocks(&audio_processor_settings, total_blocks, midi_input_file)
+        build_midi_input_blocks(&audio_processor_settings, total_blocks + 1, midi_input_file)
+    });
+
+    let tail = settings.tail.as_ref().map(|tail| {
+        (
+            10.0_f32.powf(tail.threshold_db / 20.0),
+            settings.frames(tail.silence_duration),
+            settings.frames(tail.max_duration),
+        )
     });
 
     log::info!(
-        "Rendering total_blocks={} block_size={} audio_file_total_samples={}",
+        "Rendering total_blocks={} block_size={} total_frames={}",
         total_blocks,
         block_size,
-        audio_file_total_samples
+        total_frames
     );
-    for block_num in 0..total_blocks {
+    let mut rendered_frames = 0;
+    let mut silent_frames = 0;
+    for block_num in 0.. {
+        let is_tail = rendered_frames >= total_frames;
+        let block_frames = if is_tail {
+            match tail {
+                Some((_, silence_frames, max_frames))
+                    if silent_frames < silence_frames
+                        && rendered_frames - total_frames < max_frames =>
+                {
+                    block_size.min(total_frames + max_frames - rendered_frames)
+                }
+                _ => break,
+            }
+        } else {
+            block_size.min(total_frames - rendered_frames)
+        };
+        if block_frames != buffer.num_samples() {
+            buffer.resize(settings.channels, block_frames);
+        }
+
         for sample in buffer.slice_mut() {
             *sample = 0.0;
         }
-
-        audio_file_processor.process(&mut context, &mut buffer);
+        if let Some(input) = &input {
+            for (channel, source) in buffer.channels_mut().iter_mut().zip(input) {
+                let start = rendered_frames.min(source.len());
+                let end = (rendered_frames + block_frames).min(source.len());
+                channel[..end - start].copy_from_slice(&source[start..end]);
+            }
+        }
 
         #[cfg(feature = "midi")]
         if let Some(midi) = app.midi() {
-            if let Some(midi_input_blocks) = &midi_input_blocks {
-                let midi_block = &midi_input_blocks[block_num];
+            if let Some(midi_block) = midi_input_blocks
+                .as_ref()
+                .and_then(|midi_input_blocks| midi_input_blocks.get(block_num))
+            {
                 if !midi_block.is_empty() {
                     log::debug!("Forwarding events {:?}", midi_block);
                     midi.process_midi_events(midi_block);
@@ -143,10 +394,38 @@ where
 
         app.processor().process(&mut context, &mut buffer);
 
-        output_file_processor
-            .process(&mut buffer)
-            .expect("Failed to write to WAV file");
+        output_file_processor.process(&mut buffer)?;
+        rendered_frames += block_frames;
+
+        if let Some((threshold, _, _)) = tail {
+            let is_silent = buffer
+                .channels()
+                .iter()
+                .flatten()
+                .all(|sample| sample.abs() < threshold);
+            if is_silent {
+                silent_frames += block_frames;
+            } else {
+                silent_frames = 0;
+            }
+        }
+
+        if let Some(on_progress) = &mut on_progress {
+            on_progress(OfflineRenderProgress {
+                rendered_frames,
+                total_frames,
+                is_tail,
+            });
+        }
     }
+
+    output_file_processor.finalize()?;
+
+    Ok(OfflineRenderSummary {
+        sample_rate: settings.sample_rate,
+        num_frames: rendered_frames,
+        tail_frames: rendered_frames.saturating_sub(total_frames),
+    })
 }
 
 #[cfg(feature = "midi")]
@@ -181,13 +460,13 @@ fn convert_to_absolute_time(
 
 #[cfg(feature = "midi")]
 /// Builds chunks containing MIDI messages over each block, aligned with their
-/// timing and a 120bpm tempo.
+/// timing and the `MIDI_TEMPO` tempo.
 fn build_midi_input_blocks(
     settings: &AudioProcessorSettings,
     total_blocks: usize,
     midi_input_file: MIDIFile<String, Vec<u8>>,
 ) -> Vec<Vec<MIDIBytes>> {
-    let tempo = 120_f32;
+    let tempo = MIDI_TEMPO;
     let ticks_per_quarter_note = midi_input_file.ticks_per_quarter_note() as f32;
     let chunks = midi_input_file.chunks;
     let track_events: Vec<MIDITrackEvent<Vec<u8>>> = chunks
@@ -217,12 +496,14 @@ fn build_midi_input_blocks(
         );
 
         log::debug!(
-            "Block - {} - ticks_per_beat={} - ticks={} input_len={} dt={}",
+            "Block - {} - ticks_per_beat={} - ticks={} input_len={} dt={:?}",
             i,
             ticks_per_quarter_note,
             delta_time_ticks,
             track_events.len(),
-            track_events[track_events_position].delta_time
+            track_events
+                .get(track_events_position)
+                .map(|event| event.delta_time)
         );
 
         let midi_track_events: Vec<&MIDITrackEvent<Vec<u8>>> = track_events
@@ -298,12 +579,247 @@ mod test {
         let options = OfflineRenderOptions {
             app: StandaloneAudioOnlyProcessor::new(NoopAudioProcessor::new(), Default::default()),
             handle: Some(audio_garbage_collector::handle()),
-            input_path: &input_path,
+            input_path: Some(&input_path),
             output_path: &output_path,
             #[cfg(feature = "midi")]
             midi_input_file: None,
+            settings: OfflineRenderSettings::default(),
+            on_progress: None,
+        };
+        let summary = run_offline_render(options).unwrap();
+        assert_eq!(summary.tail_frames, 0);
+        assert!((summary.duration().as_secs_f32() - 1.0).abs() < 0.1);
+    }
+
+    /// Outputs an impulse followed by an exponential decay, like a reverb tail
+    struct DecayProcessor {
+        level: f32,
+        decay: f32,
+    }
+
+    impl AudioProcessor for DecayProcessor {
+        type SampleType = f32;
+
+        fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<f32>) {
+            for sample_index in 0..data.num_samples() {
+                for channel in data.channels_mut() {
+                    channel[sample_index] = self.level;
+                }
+                self.level *= self.decay;
+            }
+        }
+    }
+
+    fn render_generator(
+        processor: DecayProcessor,
+        settings: OfflineRenderSettings,
+        on_progress: Option<Box<dyn FnMut(OfflineRenderProgress) + '_>>,
+    ) -> (
+        Result<OfflineRenderSummary, OfflineRenderError>,
+        Vec<Vec<f32>>,
+    ) {
+        use symphonia::core::audio::Signal;
+
+        let directory = tempdir::TempDir::new("offline_render").unwrap();
+        let output_path = directory.path().join("output.wav");
+        let result = run_offline_render(OfflineRenderOptions {
+            app: StandaloneAudioOnlyProcessor::new(processor, Default::default()),
+            handle: Some(audio_garbage_collector::handle()),
+            input_path: None,
+            output_path: output_path.to_str().unwrap(),
+            #[cfg(feature = "midi")]
+            midi_input_file: None,
+            settings,
+            on_progress,
+        });
+        if result.is_err() {
+            return (result, vec![]);
+        }
+
+        let mut output =
+            audio_processor_file::file_io::default_read_audio_file(output_path.to_str().unwrap())
+                .unwrap();
+        let output = audio_processor_file::file_io::read_file_contents(&mut output).unwrap();
+        let channels = (0..output.spec().channels.count())
+            .map(|channel| output.chan(channel).to_vec())
+            .collect();
+        (result, channels)
+    }
+
+    #[test]
+    fn test_render_generator_with_settings() {
+        let settings = OfflineRenderSettings {
+            sample_rate: 8000.0,
+            block_size: 100,
+            channels: 1,
+            duration: Some(Duration::from_millis(505)),
+            tail: None,
+        };
+        let processor = DecayProcessor {
+            level: 0.5,
+            decay: 1.0,
+        };
+        let (result, output) = render_generator(processor, settings, None);
+        let summary = result.unwrap();
+        assert_eq!(summary.num_frames, 4040);
+        assert_eq!(summary.tail_frames, 0);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].len(), 4040);
+        assert!(output[0].iter().all(|sample| (sample - 0.5).abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_render_tail_until_silence() {
+        let settings = OfflineRenderSettings {
+            sample_rate: 1000.0,
+            block_size: 10,
+            channels: 2,
+            duration: Some(Duration::from_millis(100)),
+            tail: Some(TailSettings {
+                threshold_db: -60.0,
+                silence_duration: Duration::from_millis(50),
+                max_duration: Duration::from_secs(10),
+            }),
+        };
+        // Goes below -60dB after ~690 samples
+        let processor = DecayProcessor {
+            level: 1.0,
+            decay: 0.99,
+        };
+        let (result, output) = render_generator(processor, settings, None);
+        let summary = result.unwrap();
+        assert_eq!(summary.num_frames, 740);
+        assert_eq!(summary.tail_frames, 640);
+        assert_eq!(output[0].len(), 740);
+        assert!(output[0][680] > 0.001);
+        assert!(output[0][700..].iter().all(|sample| *sample < 0.001));
+    }
+
+    #[test]
+    fn test_render_tail_stops_at_the_max_duration() {
+        let settings = OfflineRenderSettings {
+            sample_rate: 1000.0,
+            block_size: 16,
+            channels: 1,
+            duration: Some(Duration::from_millis(100)),
+            tail: Some(TailSettings {
+                max_duration: Duration::from_millis(200),
+                ..TailSettings::default()
+            }),
+        };
+        let processor = DecayProcessor {
+            level: 0.5,
+            decay: 1.0,
         };
-        run_offline_render(options);
+        let (result, _) = render_generator(processor, settings, None);
+        let summary = result.unwrap();
+        assert_eq!(summary.num_frames, 300);
+        assert_eq!(summary.tail_frames, 200);
+    }
+
+    #[test]
+    fn test_render_reports_progress() {
+        let settings = OfflineRenderSettings {
+            sample_rate: 1000.0,
+            block_size: 64,
+            channels: 1,
+            duration: Some(Duration::from_millis(200)),
+            tail: None,
+        };
+        let processor = DecayProcessor {
+            level: 0.5,
+            decay: 1.0,
+        };
+        let mut progress = vec![];
+        let on_progress = Box::new(|value: OfflineRenderProgress| progress.push(value));
+        let (result, _) = render_generator(processor, settings, Some(on_progress));
+        result.unwrap();
+
+        let rendered: Vec<usize> = progress.iter().map(|p| p.rendered_frames).collect();
+        assert_eq!(rendered, vec![64, 128, 192, 200]);
+        assert!(progress.iter().all(|p| p.total_frames == 200 && !p.is_tail));
+        assert_eq!(progress.last().unwrap().fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_render_without_input_requires_a_duration() {
+        let processor = DecayProcessor {
+            level: 0.5,
+            decay: 1.0,
+        };
+        let (result, _) = render_generator(processor, OfflineRenderSettings::default(), None);
+        assert!(matches!(result, Err(OfflineRenderError::MissingDuration)));
+
+        let settings = OfflineRenderSettings {
+            block_size: 0,
+            ..OfflineRenderSettings::default()
+        };
+        let processor = DecayProcessor {
+            level: 0.5,
+            decay: 1.0,
+        };
+        let (result, _) = render_generator(processor, settings, None);
+        assert!(matches!(
+            result,
+            Err(OfflineRenderError::InvalidSettings(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_rejects_non_finite_sample_rates() {
+        for sample_rate in [f32::NAN, f32::INFINITY, -44100.0] {
+            let settings = OfflineRenderSettings {
+                sample_rate,
+                duration: Some(Duration::from_secs(1)),
+                ..OfflineRenderSettings::default()
+            };
+            let processor = DecayProcessor {
+                level: 0.5,
+                decay: 1.0,
+            };
+            let (result, _) = render_generator(processor, settings, None);
+            assert!(
+                matches!(result, Err(OfflineRenderError::InvalidSettings(_))),
+                "{}",
+                sample_rate
+            );
+        }
+    }
+
+    #[cfg(feature = "midi")]
+    #[test]
+    fn test_midi_file_duration() {
+        let midi_file: MIDIFile<String, Vec<u8>> = MIDIFile::new(vec![
+            MIDIFileChunk::Header(MIDIFileHeader {
+                format: MIDIFileFormat::Single,
+                num_tracks: 1,
+                division: MIDIFileDivision::TicksPerQuarterNote {
+                    ticks_per_quarter_note: 100,
+                },
+            }),
+            MIDIFileChunk::Track {
+                events: vec![
+                    MIDITrackEvent {
+                        delta_time: 0,
+                        inner: MIDITrackInner::Message(MIDIMessage::NoteOn(MIDIMessageNote {
+                            channel: 0,
+                            note: 60,
+                            velocity: 100,
+                        })),
+                    },
+                    MIDITrackEvent {
+                        delta_time: 300,
+                        inner: MIDITrackInner::Message(MIDIMessage::NoteOff(MIDIMessageNote {
+                            channel: 0,
+                            note: 60,
+                            velocity: 0,
+                        })),
+                    },
+                ],
+            },
+        ]);
+        // 3 beats at 120bpm
+        assert_eq!(midi_file_duration(&midi_file), Duration::from_millis(1500));
     }
 
     #[cfg(feature = "midi")]
@@ -397,6 +913,39 @@ mod test {
         assert_eq!(result[20].len(), 1);
     }
 
+    #[cfg(feature = "midi")]
+    #[test]
+    fn test_build_midi_input_blocks_past_the_last_event() {
+        // Debug logs reference the next event, which doesn't exist once all were consumed
+        log::set_max_level(log::LevelFilter::Debug);
+        let chunks = vec![
+            MIDIFileChunk::Header(MIDIFileHeader {
+                format: MIDIFileFormat::Single,
+                num_tracks: 1,
+                division: MIDIFileDivision::TicksPerQuarterNote {
+                    ticks_per_quarter_note: 1,
+                },
+            }),
+            MIDIFileChunk::Track {
+                events: vec![MIDITrackEvent {
+                    delta_time: 0,
+                    inner: MIDITrackInner::Message(MIDIMessage::NoteOn(MIDIMessageNote {
+                        channel: 1,
+                        note: 120,
+                        velocity: 120,
+                    })),
+                }],
+            },
+        ];
+
+        let midi_file = MIDIFile::new(chunks);
+        let settings = AudioProcessorSettings::new(1000.0, 1, 1, 50);
+        let result = build_midi_input_blocks(&settings, 5, midi_file);
+        assert_eq!(result.len(), 5);
+        assert_eq!(result[0].len(), 1);
+        assert!(result[1..].iter().all(|block| block.is_empty()));
+    }
+
     #[cfg(feature = "midi")]
     #[test]
     fn test_get_delta_time_ticks() {
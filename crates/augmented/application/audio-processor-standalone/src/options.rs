@@ -21,6 +21,8 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 use std::ffi::OsString;
+use std::str::FromStr;
+use std::time::Duration;
 
 use clap::ArgMatches;
 
@@ -31,11 +33,32 @@ pub enum RenderingOptions {
         output_device: Option<String>,
     },
     Offline {
-        input_file: String,
+        input_file: Option<String>,
         output_file: String,
+        options: OfflineOptions,
+    },
+    /// Render every file in a directory or glob into an output directory
+    Batch {
+        input: String,
+        output_directory: String,
+        report_file: Option<String>,
+        jobs: Option<usize>,
+        options: OfflineOptions,
     },
 }
 
+/// Offline rendering settings, missing values use the `offline::OfflineRenderSettings` defaults
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OfflineOptions {
+    pub sample_rate: Option<f32>,
+    pub block_size: Option<usize>,
+    pub channels: Option<usize>,
+    pub duration: Option<Duration>,
+    /// Keep rendering past the end until the output is silent
+    pub tail: bool,
+    pub tail_threshold_db: Option<f32>,
+}
+
 impl RenderingOptions {
     pub fn input_device(&self) -> Option<String> {
         if let RenderingOptions::Online { input_device, .. } = &self {
@@ -111,6 +134,36 @@ where
         .arg(clap::Arg::from_usage(
             "-o, --output-file=[OUTPUT_PATH] 'If specified, will render offline into this file (WAV)'",
         ))
+        .arg(clap::Arg::from_usage(
+            "--sample-rate=[SAMPLE_RATE] 'Sample rate of offline renders, defaults to 44100'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--block-size=[BLOCK_SIZE] 'Block size of offline renders, defaults to 16'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--channels=[CHANNELS] 'Number of channels of offline renders, defaults to 2'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--duration=[SECONDS] 'Length of offline renders, defaults to the input file length'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--tail 'Keep rendering offline past the end until the output is silent'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--tail-threshold=[DB] 'Level below which the output is silent for --tail, defaults to -80'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--batch-input=[INPUT] 'Render every audio file in this directory or glob offline'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--batch-output=[OUTPUT_DIRECTORY] 'Directory batch renders are written into, mirroring the input folders'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--batch-report=[REPORT_PATH] 'Where to write the batch JSON report, defaults to report.json in the output directory'",
+        ))
+        .arg(clap::Arg::from_usage(
+            "--jobs=[JOBS] 'Number of files to batch render at once, defaults to the number of CPUs'",
+        ))
         .arg(clap::Arg::from_usage(
             "--input-device=[INPUT_DEVICE] 'The input device to use'",
         ))
@@ -154,19 +207,80 @@ fn parse_midi_options(matches: &ArgMatches) -> MidiOptions {
     }
 }
 
+/// Parse an option's value, exiting if it's invalid
+fn parse_value<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    let value = matches.value_of(name)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            log::error!("Invalid value for `--{}`: {}", name, value);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse `--duration` in seconds, exiting if it's negative, not a number or too long
+fn parse_duration(matches: &ArgMatches) -> Option<Duration> {
+    let secs: f32 = parse_value(matches, "duration")?;
+    match duration_from_secs(secs) {
+        Some(duration) => Some(duration),
+        None => {
+            log::error!("Invalid value for `--duration`: {}", secs);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn duration_from_secs(secs: f32) -> Option<Duration> {
+    Duration::try_from_secs_f32(secs).ok()
+}
+
+fn parse_offline_options(matches: &ArgMatches) -> OfflineOptions {
+    OfflineOptions {
+        sample_rate: parse_value(matches, "sample-rate"),
+        block_size: parse_value(matches, "block-size"),
+        channels: parse_value(matches, "channels"),
+        duration: parse_duration(matches),
+        tail: matches.is_present("tail"),
+        tail_threshold_db: parse_value(matches, "tail-threshold"),
+    }
+}
+
 fn parse_rendering_options(matches: &ArgMatches) -> RenderingOptions {
-    if matches.is_present("output-file") {
-        if !matches.is_present("input-file") {
-            log::error!("Please specify `--input-file`");
+    if let Some(input) = matches.value_of("batch-input") {
+        let output_directory = match matches.value_of("batch-output") {
+            Some(output_directory) => output_directory.into(),
+            None => {
+                log::error!("Please specify `--batch-output` for `--batch-input`");
+                std::process::exit(1);
+            }
+        };
+
+        RenderingOptions::Batch {
+            input: input.into(),
+            output_directory,
+            report_file: matches.value_of("batch-report").map(|s| s.into()),
+            jobs: parse_value(matches, "jobs"),
+            options: parse_offline_options(matches),
+        }
+    } else if matches.is_present("output-file") {
+        let options = parse_offline_options(matches);
+        // Generators render without an input, for a duration or the length of the MIDI file
+        let has_length = matches.is_present("input-file")
+            || options.duration.is_some()
+            || matches.is_present("midi-input-file");
+        if !has_length {
+            log::error!("Please specify `--input-file`, `--duration` or `--midi-input-file`");
             std::process::exit(1);
         }
 
-        let input_path = matches.value_of("input-file").map(|s| s.into()).unwrap();
+        let input_path = matches.value_of("input-file").map(|s| s.into());
         let output_path = matches.value_of("output-file").map(|s| s.into()).unwrap();
 
         RenderingOptions::Offline {
             input_file: input_path,
             output_file: output_path,
+            options,
         }
     } else {
         RenderingOptions::Online {
@@ -303,11 +417,106 @@ mod test {
             RenderingOptions::Offline {
                 input_file,
                 output_file,
+                options,
             } => {
-                assert_eq!(input_file, "test.mp3");
+                assert_eq!(input_file.as_ref().unwrap(), "test.mp3");
                 assert_eq!(output_file, "test.wav");
+                assert_eq!(options, &OfflineOptions::default());
             }
             _ => {}
         }
     }
+
+    #[test]
+    fn test_duration_from_secs() {
+        assert_eq!(duration_from_secs(1.5), Some(Duration::from_millis(1500)));
+        assert_eq!(duration_from_secs(0.0), Some(Duration::ZERO));
+        assert_eq!(duration_from_secs(-1.0), None);
+        assert_eq!(duration_from_secs(f32::NAN), None);
+        assert_eq!(duration_from_secs(f32::INFINITY), None);
+        assert_eq!(duration_from_secs(f32::MAX), None);
+    }
+
+    #[test]
+    fn test_parse_offline_render_settings() {
+        let options = parse_options_from::<Vec<String>, String>(
+            ParseOptionsParams {
+                supports_midi: false,
+            },
+            vec![
+                "program".into(),
+                "--output-file".into(),
+                "test.wav".into(),
+                "--sample-rate".into(),
+                "48000".into(),
+                "--block-size".into(),
+                "512".into(),
+                "--channels".into(),
+                "1".into(),
+                "--duration".into(),
+                "2.5".into(),
+                "--tail".into(),
+                "--tail-threshold=-60".into(),
+            ],
+        );
+        match options.rendering() {
+            RenderingOptions::Offline {
+                input_file,
+                options,
+                ..
+            } => {
+                assert!(input_file.is_none());
+                assert_eq!(
+                    options,
+                    &OfflineOptions {
+                        sample_rate: Some(48000.0),
+                        block_size: Some(512),
+                        channels: Some(1),
+                        duration: Some(Duration::from_secs_f32(2.5)),
+                        tail: true,
+                        tail_threshold_db: Some(-60.0),
+                    }
+                );
+            }
+            _ => panic!("Expected offline rendering options"),
+        }
+    }
+
+    #[test]
+    fn test_parse_batch_options() {
+        let options = parse_options_from::<Vec<String>, String>(
+            ParseOptionsParams {
+                supports_midi: false,
+            },
+            vec![
+                "program".into(),
+                "--batch-input".into(),
+                "samples/**/*.wav".into(),
+                "--batch-output".into(),
+                "rendered".into(),
+                "--jobs".into(),
+                "4".into(),
+                "--sample-rate".into(),
+                "48000".into(),
+                "--tail".into(),
+            ],
+        );
+        match options.rendering() {
+            RenderingOptions::Batch {
+                input,
+                output_directory,
+                report_file,
+                jobs,
+                options,
+            } => {
+                assert_eq!(input, "samples/**/*.wav");
+                assert_eq!(output_directory, "rendered");
+                assert!(report_file.is_none());
+                assert_eq!(*jobs, Some(4));
+                assert_eq!(options.sample_rate, Some(48000.0));
+                assert!(options.tail);
+            }
+            _ => panic!("Expected batch rendering options"),
+        }
+    }
 }
@@ -71,6 +71,10 @@
 //! | Release | 0.3  |
 //! ------------------
 //! ![](https://raw.githubusercontent.com/yamadapc/augmented-audio/master/crates/augmented/audio/adsr-envelope/src/__plots__/exp-envelope.png)
+//!
+//! # Multi-stage envelopes
+//! [`MultiStageEnvelope`] takes any number of breakpoints with per-segment curvature, loop &
+//! sustain points, tempo-synced durations and a retrigger mode. See the [`multi_stage`] module.
 
 use std::time::Duration;
 
@@ -78,6 +82,10 @@ use num_derive::{FromPrimitive, ToPrimitive};
 
 use augmented_atomics::{AtomicEnum, AtomicF32};
 
+pub use multi_stage::MultiStageEnvelope;
+
+pub mod multi_stage;
+
 #[derive(Debug, FromPrimitive, ToPrimitive)]
 enum EnvelopeStage {
     Idle,
@@ -300,6 +308,12 @@ impl Envelope {
         self.set_stage(EnvelopeStage::Release);
     }
 
+    /// Whether the envelope is idle, either because it was never triggered or because its
+    /// release stage has finished.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.stage.get(), EnvelopeStage::Idle)
+    }
+
     fn next_stage(&self) {
         match self.stage.get() {
             EnvelopeStage::Attack => {
@@ -357,6 +371,26 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_is_idle_after_release() {
+        let envelope = Envelope::default();
+        envelope.set_sample_rate(100.0);
+        assert!(envelope.is_idle());
+
+        envelope.note_on();
+        assert!(!envelope.is_idle());
+        for _ in 0..100 {
+            envelope.tick();
+        }
+        envelope.note_off();
+        assert!(!envelope.is_idle());
+        for _ in 0..100 {
+            envelope.volume();
+            envelope.tick();
+        }
+        assert!(envelope.is_idle());
+    }
+
     #[test]
     fn test_0_attack_envelope_with_decay() {
         let envelope = Envelope::default();
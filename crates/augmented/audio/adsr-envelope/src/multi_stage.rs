@@ -0,0 +1,649 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Multi-stage envelope with arbitrary breakpoints.
+//!
+//! A [`MultiStageEnvelope`] is a list of segments, each moving from the level the previous one
+//! ended at to its own target level, over a duration in seconds or beats & with a curvature.
+//!
+//! * A **sustain point** holds the envelope at the end of a segment while the note is held
+//! * A **loop** repeats a range of segments while the note is held, which turns the envelope
+//!   into an LFO
+//! * On note off, the envelope jumps to the segment after the loop or sustain point
+//!
+//! ```rust
+//! use augmented_adsr_envelope::multi_stage::{MultiStageEnvelope, SegmentDuration};
+//!
+//! let envelope = MultiStageEnvelope::default();
+//! envelope.set_sample_rate(1000.0);
+//! envelope.set_tempo(120.0);
+//! // Rise to 1 over 1/4 beat, fall to 0.2 over 1/4 beat with a curve, and loop both
+//! envelope.set_segments(&[
+//!     (1.0, SegmentDuration::Beats(0.25), 0.0),
+//!     (0.2, SegmentDuration::Beats(0.25), -0.5),
+//!     (0.0, SegmentDuration::Seconds(0.1), 0.0),
+//! ]);
+//! envelope.set_loop(Some((0, 1)));
+//!
+//! envelope.note_on();
+//! for _ in 0..1000 {
+//!     envelope.tick();
+//!     let _volume = envelope.volume();
+//! }
+//! envelope.note_off();
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::time::Duration;
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use augmented_atomics::{AtomicEnum, AtomicF32, AtomicOption, AtomicValue};
+
+/// Maximum number of segments on an envelope
+pub const MAX_SEGMENTS: usize = 16;
+
+/// Duration of a segment, tempo-synced durations use the tempo set with
+/// [`MultiStageEnvelope::set_tempo`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentDuration {
+    Seconds(f32),
+    Beats(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+enum DurationUnit {
+    Seconds,
+    Beats,
+}
+
+/// What happens when a note starts while the envelope is still running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum RetriggerMode {
+    /// Restart from the first segment, moving from the current level so there are no clicks
+    Reset,
+    /// Keep going if the previous note is still held, otherwise reset
+    Legato,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+enum Stage {
+    Idle,
+    Running,
+    Sustain,
+}
+
+/// Shape of a segment at position `t` (0-1).
+///
+/// `curve` is between -1 and 1. 0 is linear, positive values start slowly & speed up and
+/// negative values start fast & slow down.
+pub fn segment_curve(t: f32, curve: f32) -> f32 {
+    if curve.abs() < 0.001 {
+        return t;
+    }
+    let k = curve * 10.0;
+    (k * t).exp_m1() / k.exp_m1()
+}
+
+struct Segment {
+    level: AtomicF32,
+    duration: AtomicF32,
+    unit: AtomicEnum<DurationUnit>,
+    curve: AtomicF32,
+}
+
+impl Default for Segment {
+    fn default() -> Self {
+        Self {
+            level: 0.0.into(),
+            duration: 0.0.into(),
+            unit: DurationUnit::Seconds.into(),
+            curve: 0.0.into(),
+        }
+    }
+}
+
+impl Segment {
+    fn duration(&self) -> SegmentDuration {
+        match self.unit.get() {
+            DurationUnit::Seconds => SegmentDuration::Seconds(self.duration.get()),
+            DurationUnit::Beats => SegmentDuration::Beats(self.duration.get()),
+        }
+    }
+
+    fn set_duration(&self, duration: SegmentDuration) {
+        match duration {
+            SegmentDuration::Seconds(seconds) => {
+                self.unit.set(DurationUnit::Seconds);
+                self.duration.set(seconds.max(0.0));
+            }
+            SegmentDuration::Beats(beats) => {
+                self.unit.set(DurationUnit::Beats);
+                self.duration.set(beats.max(0.0));
+            }
+        }
+    }
+}
+
+/// An envelope made of up to [`MAX_SEGMENTS`] segments.
+///
+/// Like [`crate::Envelope`] both configuration & state use atomics, so the envelope can be
+/// shared between threads & modified through an immutable reference.
+pub struct MultiStageEnvelope {
+    segments: [Segment; MAX_SEGMENTS],
+    num_segments: AtomicUsize,
+    sustain_point: AtomicOption<AtomicUsize>,
+    loop_start: AtomicOption<AtomicUsize>,
+    loop_end: AtomicOption<AtomicUsize>,
+    retrigger_mode: AtomicEnum<RetriggerMode>,
+    sample_rate: AtomicF32,
+    tempo: AtomicF32,
+
+    stage: AtomicEnum<Stage>,
+    segment: AtomicUsize,
+    segment_position: AtomicF32,
+    segment_start_level: AtomicF32,
+    level: AtomicF32,
+    is_gate_on: AtomicBool,
+}
+
+impl Default for MultiStageEnvelope {
+    /// Same shape as the default [`crate::Envelope`]
+    fn default() -> Self {
+        Self::adsr(
+            Duration::from_secs_f32(0.2),
+            Duration::from_secs_f32(0.3),
+            0.8,
+            Duration::from_secs_f32(0.1),
+        )
+    }
+}
+
+impl MultiStageEnvelope {
+    /// Create an envelope with no segments
+    pub fn new() -> Self {
+        Self {
+            segments: Default::default(),
+            num_segments: AtomicUsize::new(0),
+            sustain_point: AtomicOption::empty(),
+            loop_start: AtomicOption::empty(),
+            loop_end: AtomicOption::empty(),
+            retrigger_mode: RetriggerMode::Reset.into(),
+            sample_rate: 44100.0.into(),
+            tempo: 120.0.into(),
+            stage: Stage::Idle.into(),
+            segment: AtomicUsize::new(0),
+            segment_position: 0.0.into(),
+            segment_start_level: 0.0.into(),
+            level: 0.0.into(),
+            is_gate_on: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a linear ADSR envelope: attack, decay & release segments with a sustain point at
+    /// the end of the decay.
+    ///
+    /// The [`MultiStageEnvelope::set_attack`], [`MultiStageEnvelope::set_decay`],
+    /// [`MultiStageEnvelope::set_sustain`] & [`MultiStageEnvelope::set_release`] helpers assume
+    /// this layout.
+    pub fn adsr(attack: Duration, decay: Duration, sustain: f32, release: Duration) -> Self {
+        let envelope = Self::new();
+        envelope.set_segments(&[
+            (1.0, SegmentDuration::Seconds(attack.as_secs_f32()), 0.0),
+            (sustain, SegmentDuration::Seconds(decay.as_secs_f32()), 0.0),
+            (0.0, SegmentDuration::Seconds(release.as_secs_f32()), 0.0),
+        ]);
+        envelope.set_sustain_point(Some(1));
+        envelope
+    }
+
+    /// Set the sample rate, required before playback
+    pub fn set_sample_rate(&self, sample_rate: f32) {
+        self.sample_rate.set(sample_rate);
+    }
+
+    /// Set the tempo in BPM, used by segments with durations in beats
+    pub fn set_tempo(&self, tempo: f32) {
+        self.tempo.set(tempo);
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo.get()
+    }
+
+    pub fn retrigger_mode(&self) -> RetriggerMode {
+        self.retrigger_mode.get()
+    }
+
+    pub fn set_retrigger_mode(&self, mode: RetriggerMode) {
+        self.retrigger_mode.set(mode);
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.num_segments.get()
+    }
+
+    /// Replace all segments with `(level, duration, curve)` breakpoints. Segments past
+    /// [`MAX_SEGMENTS`] are ignored.
+    pub fn set_segments(&self, segments: &[(f32, SegmentDuration, f32)]) {
+        let num_segments = segments.len().min(MAX_SEGMENTS);
+        for (index, (level, duration, curve)) in segments.iter().take(num_segments).enumerate() {
+            self.set_segment(index, *level, *duration, *curve);
+        }
+        self.num_segments.set(num_segments);
+    }
+
+    /// Set a segment's target level, duration & curve (see [`segment_curve`]). If `index` is
+    /// past the current segments, the envelope is extended with it.
+    pub fn set_segment(&self, index: usize, level: f32, duration: SegmentDuration, curve: f32) {
+        if let Some(segment) = self.segments.get(index) {
+            segment.level.set(level);
+            segment.set_duration(duration);
+            segment.curve.set(curve.clamp(-1.0, 1.0));
+            if index >= self.num_segments.get() {
+                self.num_segments.set(index + 1);
+            }
+        }
+    }
+
+    /// Get a segment's target level, duration & curve
+    pub fn segment(&self, index: usize) -> Option<(f32, SegmentDuration, f32)> {
+        if index >= self.num_segments.get() {
+            return None;
+        }
+        let segment = &self.segments[index];
+        Some((segment.level.get(), segment.duration(), segment.curve.get()))
+    }
+
+    pub fn set_segment_level(&self, index: usize, level: f32) {
+        if let Some(segment) = self.segments.get(index) {
+            segment.level.set(level);
+        }
+    }
+
+    pub fn set_segment_duration(&self, index: usize, duration: SegmentDuration) {
+        if let Some(segment) = self.segments.get(index) {
+            segment.set_duration(duration);
+        }
+    }
+
+    pub fn set_segment_curve(&self, index: usize, curve: f32) {
+        if let Some(segment) = self.segments.get(index) {
+            segment.curve.set(curve.clamp(-1.0, 1.0));
+        }
+    }
+
+    /// Hold the envelope at the end of this segment while the note is held
+    pub fn set_sustain_point(&self, segment: Option<usize>) {
+        self.sustain_point.set(segment);
+    }
+
+    pub fn sustain_point(&self) -> Option<usize> {
+        self.sustain_point.inner()
+    }
+
+    /// Loop between the start of the first segment & the end of the second one (inclusive)
+    /// while the note is held. A loop takes priority over the sustain point.
+    pub fn set_loop(&self, range: Option<(usize, usize)>) {
+        if let Some((start, end)) = range {
+            self.loop_start.set(Some(start.min(end)));
+            self.loop_end.set(Some(start.max(end)));
+        } else {
+            self.loop_start.set(None);
+            self.loop_end.set(None);
+        }
+    }
+
+    pub fn loop_range(&self) -> Option<(usize, usize)> {
+        self.loop_start.inner().zip(self.loop_end.inner())
+    }
+
+    /// Set the attack time of an [`MultiStageEnvelope::adsr`] envelope
+    pub fn set_attack(&self, duration: Duration) {
+        self.set_segment_duration(0, SegmentDuration::Seconds(duration.as_secs_f32()));
+    }
+
+    /// Set the decay time of an [`MultiStageEnvelope::adsr`] envelope
+    pub fn set_decay(&self, duration: Duration) {
+        self.set_segment_duration(1, SegmentDuration::Seconds(duration.as_secs_f32()));
+    }
+
+    /// Set the sustain level of an [`MultiStageEnvelope::adsr`] envelope
+    pub fn set_sustain(&self, sustain: f32) {
+        self.set_segment_level(1, sustain);
+    }
+
+    /// Set the release time of an [`MultiStageEnvelope::adsr`] envelope
+    pub fn set_release(&self, duration: Duration) {
+        self.set_segment_duration(2, SegmentDuration::Seconds(duration.as_secs_f32()));
+    }
+
+    /// Get the current volume multiplier
+    pub fn volume(&self) -> f32 {
+        self.level.get()
+    }
+
+    /// Whether the envelope is idle, either because it was never triggered or because it went
+    /// through all of its segments
+    pub fn is_idle(&self) -> bool {
+        self.stage.get() == Stage::Idle
+    }
+
+    /// Index of the segment currently playing, if the envelope isn't idle
+    pub fn current_segment(&self) -> Option<usize> {
+        if self.is_idle() {
+            None
+        } else {
+            Some(self.segment.get())
+        }
+    }
+
+    /// Start the envelope, see [`RetriggerMode`] for what happens if it's already running
+    pub fn note_on(&self) {
+        let is_legato = self.retrigger_mode.get() == RetriggerMode::Legato
+            && self.is_gate_on.get()
+            && !self.is_idle();
+        self.is_gate_on.set(true);
+        if is_legato {
+            return;
+        }
+        self.start_segment(0, 0);
+    }
+
+    /// Release the envelope, jumping to the segment after the loop or sustain point. If there
+    /// are no segments after it, the envelope goes silent.
+    pub fn note_off(&self) {
+        self.is_gate_on.set(false);
+        if self.is_idle() {
+            return;
+        }
+
+        let release_segment = self
+            .loop_range()
+            .map(|(_, end)| end)
+            .or_else(|| self.sustain_point())
+            .map(|segment| segment + 1);
+        if let Some(release_segment) = release_segment {
+            if self.segment.get() < release_segment {
+                if release_segment >= self.num_segments.get() {
+                    self.level.set(0.0);
+                }
+                self.start_segment(release_segment, 0);
+            }
+        }
+    }
+
+    /// Update the envelope, pushing its state forwards by 1 sample
+    pub fn tick(&self) {
+        if self.stage.get() != Stage::Running {
+            return;
+        }
+
+        let index = self.segment.get();
+        let segment = &self.segments[index];
+        let position = self.segment_position.get() + 1.0;
+        let duration = self.duration_samples(segment);
+        if position >= duration {
+            self.finish_segment(index, 0);
+            return;
+        }
+
+        self.segment_position.set(position);
+        let start = self.segment_start_level.get();
+        let target = segment.level.get();
+        let t = segment_curve(position / duration, segment.curve.get());
+        self.level.set(start + (target - start) * t);
+    }
+
+    fn duration_samples(&self, segment: &Segment) -> f32 {
+        let seconds = match segment.duration() {
+            SegmentDuration::Seconds(seconds) => seconds,
+            SegmentDuration::Beats(beats) => beats * 60.0 / self.tempo.get().max(f32::EPSILON),
+        };
+        seconds * self.sample_rate.get()
+    }
+
+    /// `depth` counts segments skipped without playing, so loops of zero length segments
+    /// can't recurse forever
+    fn start_segment(&self, index: usize, depth: usize) {
+        if index >= self.num_segments.get() {
+            self.stage.set(Stage::Idle);
+            return;
+        }
+
+        self.segment.set(index);
+        self.segment_position.set(0.0);
+        self.segment_start_level.set(self.level.get());
+        self.stage.set(Stage::Running);
+
+        if self.duration_samples(&self.segments[index]) < 1.0 {
+            self.finish_segment(index, depth + 1);
+        }
+    }
+
+    fn finish_segment(&self, index: usize, depth: usize) {
+        self.level.set(self.segments[index].level.get());
+        let is_gate_on = self.is_gate_on.get();
+
+        if let Some((loop_start, loop_end)) = self.loop_range() {
+            if is_gate_on && index == loop_end && depth <= MAX_SEGMENTS {
+                self.start_segment(loop_start, depth);
+                return;
+            }
+        } else if is_gate_on && self.sustain_point() == Some(index) {
+            self.stage.set(Stage::Sustain);
+            return;
+        }
+
+        if depth > MAX_SEGMENTS {
+            // A loop with no length, hold it
+            self.stage.set(Stage::Sustain);
+            return;
+        }
+        self.start_segment(index + 1, depth);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(envelope: &MultiStageEnvelope, samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|_| {
+                envelope.tick();
+                envelope.volume()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_curve() {
+        for curve in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            assert!(segment_curve(0.0, curve).abs() < 1e-6);
+            assert!((segment_curve(1.0, curve) - 1.0).abs() < 1e-5);
+        }
+        assert!((segment_curve(0.5, 0.0) - 0.5).abs() < 1e-6);
+        assert!(segment_curve(0.5, 0.5) < 0.5);
+        assert!(segment_curve(0.5, -0.5) > 0.5);
+    }
+
+    #[test]
+    fn test_adsr_layout() {
+        let envelope = MultiStageEnvelope::adsr(
+            Duration::from_secs_f32(0.01),
+            Duration::from_secs_f32(0.01),
+            0.5,
+            Duration::from_secs_f32(0.01),
+        );
+        envelope.set_sample_rate(1000.0);
+        assert!(envelope.is_idle());
+
+        envelope.note_on();
+        let attack = run(&envelope, 10);
+        assert!((attack[4] - 0.5).abs() < 1e-5, "{:?}", attack);
+        assert!((attack[9] - 1.0).abs() < 1e-5);
+        let decay = run(&envelope, 10);
+        assert!((decay[9] - 0.5).abs() < 1e-5);
+        let sustain = run(&envelope, 100);
+        assert!(sustain.iter().all(|v| (v - 0.5).abs() < 1e-5));
+        assert_eq!(envelope.current_segment(), Some(1));
+
+        envelope.note_off();
+        let release = run(&envelope, 10);
+        assert!(release[9].abs() < 1e-5);
+        assert!(envelope.is_idle());
+    }
+
+    #[test]
+    fn test_note_off_during_attack_releases_from_current_level() {
+        let envelope = MultiStageEnvelope::adsr(
+            Duration::from_secs_f32(0.1),
+            Duration::from_secs_f32(0.1),
+            0.5,
+            Duration::from_secs_f32(0.01),
+        );
+        envelope.set_sample_rate(1000.0);
+        envelope.note_on();
+        run(&envelope, 50);
+        envelope.note_off();
+        assert_eq!(envelope.current_segment(), Some(2));
+        let release = run(&envelope, 10);
+        assert!(release[0] < 0.5 && release[0] > 0.4, "{:?}", release);
+        assert!(envelope.is_idle());
+    }
+
+    #[test]
+    fn test_loop_repeats_while_held() {
+        let envelope = MultiStageEnvelope::new();
+        envelope.set_sample_rate(1000.0);
+        envelope.set_segments(&[
+            (1.0, SegmentDuration::Seconds(0.01), 0.0),
+            (0.0, SegmentDuration::Seconds(0.01), 0.0),
+            (0.0, SegmentDuration::Seconds(0.01), 0.0),
+        ]);
+        envelope.set_loop(Some((0, 1)));
+
+        envelope.note_on();
+        let output = run(&envelope, 200);
+        let peaks = output.iter().filter(|v| (**v - 1.0).abs() < 1e-5).count();
+        assert_eq!(peaks, 10);
+        assert!(!envelope.is_idle());
+
+        envelope.note_off();
+        assert_eq!(envelope.current_segment(), Some(2));
+        run(&envelope, 10);
+        assert!(envelope.is_idle());
+    }
+
+    #[test]
+    fn test_zero_length_loop_does_not_hang() {
+        let envelope = MultiStageEnvelope::new();
+        envelope.set_segments(&[
+            (1.0, SegmentDuration::Seconds(0.0), 0.0),
+            (0.5, SegmentDuration::Seconds(0.0), 0.0),
+        ]);
+        envelope.set_loop(Some((0, 1)));
+        envelope.note_on();
+        run(&envelope, 10);
+        assert!(!envelope.is_idle());
+        envelope.note_off();
+        assert!(envelope.is_idle());
+    }
+
+    #[test]
+    fn test_note_off_without_release_segment_goes_silent() {
+        let envelope = MultiStageEnvelope::new();
+        envelope.set_sample_rate(1000.0);
+        envelope.set_segments(&[
+            (1.0, SegmentDuration::Seconds(0.01), 0.0),
+            (0.5, SegmentDuration::Seconds(0.01), 0.0),
+        ]);
+
+        envelope.set_sustain_point(Some(1));
+        envelope.note_on();
+        run(&envelope, 100);
+        envelope.note_off();
+        assert!(envelope.is_idle());
+        assert_eq!(envelope.volume(), 0.0);
+
+        envelope.set_loop(Some((0, 1)));
+        envelope.note_on();
+        run(&envelope, 15);
+        envelope.note_off();
+        assert!(envelope.is_idle());
+        assert_eq!(envelope.volume(), 0.0);
+    }
+
+    #[test]
+    fn test_tempo_synced_segments() {
+        let envelope = MultiStageEnvelope::new();
+        envelope.set_sample_rate(1000.0);
+        envelope.set_tempo(120.0);
+        envelope.set_segments(&[(1.0, SegmentDuration::Beats(1.0), 0.0)]);
+        envelope.note_on();
+        let output = run(&envelope, 500);
+        assert!((output[249] - 0.5).abs() < 1e-5);
+        assert!(envelope.is_idle());
+
+        envelope.set_tempo(60.0);
+        envelope.set_segment_level(0, 0.0);
+        envelope.note_on();
+        let output = run(&envelope, 1000);
+        assert!((output[499] - 0.5).abs() < 1e-5, "{}", output[499]);
+    }
+
+    #[test]
+    fn test_retrigger_modes() {
+        let build = |mode| {
+            let envelope = MultiStageEnvelope::adsr(
+                Duration::from_secs_f32(0.01),
+                Duration::from_secs_f32(0.01),
+                0.5,
+                Duration::from_secs_f32(0.01),
+            );
+            envelope.set_sample_rate(1000.0);
+            envelope.set_retrigger_mode(mode);
+            envelope.note_on();
+            run(&envelope, 100);
+            envelope
+        };
+
+        // Reset restarts the attack from the current level
+        let envelope = build(RetriggerMode::Reset);
+        envelope.note_on();
+        assert_eq!(envelope.current_segment(), Some(0));
+        let output = run(&envelope, 2);
+        assert!(output[0] > 0.5 && output[1] > output[0], "{:?}", output);
+
+        // Legato keeps sustaining while the note is held
+        let envelope = build(RetriggerMode::Legato);
+        envelope.note_on();
+        assert_eq!(envelope.current_segment(), Some(1));
+        assert!((envelope.volume() - 0.5).abs() < 1e-5);
+
+        // But resets once it was released
+        envelope.note_off();
+        envelope.note_on();
+        assert_eq!(envelope.current_segment(), Some(0));
+    }
+}
@@ -449,7 +449,9 @@ mod test {
         };
         let mut output_processor =
             OutputAudioFileProcessor::from_path(settings, &transients_file_path);
-        output_processor.prepare(settings);
+        output_processor
+            .prepare(settings)
+            .expect("Failed to create output file");
         // match input signal
         let transients: Vec<f32> = transients.iter().map(|f| f * max_input).collect();
         let mut buffer = AudioBuffer::from_interleaved(1, &transients);
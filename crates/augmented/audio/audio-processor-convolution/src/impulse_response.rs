@@ -0,0 +1,304 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::f64::consts::PI;
+
+use symphonia::core::audio::Signal;
+use thiserror::Error;
+
+use audio_processor_file::file_io::{self, AudioFileError};
+
+/// Number of zero-crossings of the sinc kernel on each side of a sample, when resampling
+const SINC_HALF_WIDTH: f64 = 32.0;
+
+#[derive(Error, Debug)]
+pub enum ImpulseResponseError {
+    #[error("Failed to read impulse response file")]
+    AudioFileError(#[from] AudioFileError),
+    #[error("Impulse response has no samples")]
+    EmptyImpulseResponse,
+    #[error("Impulse response channels have different lengths")]
+    MismatchedChannelLengths,
+}
+
+/// An impulse response held in memory, non-interleaved.
+///
+/// Channels are interpreted depending on how many there are:
+///
+/// * 1 channel - The same response is used for every channel
+/// * 2 channels - Left and right responses
+/// * 4 channels - True-stereo responses, in `LL, LR, RL, RR` order (input to output)
+#[derive(Debug, Clone)]
+pub struct ImpulseResponse {
+    sample_rate: f32,
+    channels: Vec<Vec<f32>>,
+}
+
+impl ImpulseResponse {
+    /// Create an impulse response from non-interleaved channels. All channels must have the same
+    /// length.
+    pub fn new(sample_rate: f32, channels: Vec<Vec<f32>>) -> Result<Self, ImpulseResponseError> {
+        if channels.is_empty() || channels[0].is_empty() {
+            return Err(ImpulseResponseError::EmptyImpulseResponse);
+        }
+        if channels
+            .iter()
+            .any(|channel| channel.len() != channels[0].len())
+        {
+            return Err(ImpulseResponseError::MismatchedChannelLengths);
+        }
+
+        Ok(Self {
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Read an impulse response file onto memory. This does not perform sample rate conversion,
+    /// which only happens once the processing rate is known.
+    pub fn from_path(path: &str) -> Result<Self, ImpulseResponseError> {
+        let mut probe_result = file_io::default_read_audio_file(path)?;
+        let contents = file_io::read_file_contents(&mut probe_result)?;
+        let sample_rate = contents.spec().rate as f32;
+        let channels = (0..contents.spec().channels.count())
+            .map(|channel| contents.chan(channel).to_vec())
+            .collect();
+
+        log::info!(
+            "Read impulse response path={} sample_rate={} length={}",
+            path,
+            sample_rate,
+            contents.frames()
+        );
+
+        Self::new(sample_rate, channels)
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Length in samples
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn channel(&self, channel: usize) -> &[f32] {
+        &self.channels[channel]
+    }
+
+    /// Returns a copy of this impulse response converted into `sample_rate` with a windowed-sinc
+    /// interpolator. This is meant to run once, off the audio-thread.
+    pub fn resample(&self, sample_rate: f32) -> ImpulseResponse {
+        if (sample_rate - self.sample_rate).abs() < f32::EPSILON {
+            return self.clone();
+        }
+
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| resample_channel(channel, ratio))
+            .collect();
+
+        ImpulseResponse {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < f64::EPSILON {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman_window(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+    }
+}
+
+/// Band-limited interpolation of `input`, where `ratio` is the output rate over the input rate.
+/// When down-sampling the kernel is stretched so it also low-passes below the new nyquist.
+fn resample_channel(input: &[f32], ratio: f64) -> Vec<f32> {
+    let output_len = (input.len() as f64 * ratio).ceil() as usize;
+    let cutoff = ratio.min(1.0);
+    let half_width = SINC_HALF_WIDTH / cutoff;
+
+    (0..output_len)
+        .map(|output_index| {
+            let position = output_index as f64 / ratio;
+            let start = (position - half_width).ceil().max(0.0) as usize;
+            let end = ((position + half_width).floor() as usize).min(input.len() - 1);
+
+            let mut sum = 0.0;
+            for (input_index, sample) in input.iter().enumerate().take(end + 1).skip(start) {
+                let x = position - input_index as f64;
+                sum += *sample as f64 * cutoff * sinc(x * cutoff) * blackman_window(x / half_width);
+            }
+            sum as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use audio_processor_testing_helpers::sine_buffer;
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn write_test_file(tempdir: &TempDir, channels: &[Vec<f32>]) -> String {
+        let file_path = tempdir.path().join("impulse-response.wav");
+        let mut writer = hound::WavWriter::create(
+            &file_path,
+            hound::WavSpec {
+                channels: channels.len() as u16,
+                sample_rate: 44100,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            },
+        )
+        .unwrap();
+        for sample in 0..channels[0].len() {
+            for channel in channels {
+                writer.write_sample(channel[sample]).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+        file_path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_empty_impulse_response_is_an_error() {
+        assert!(ImpulseResponse::new(44100.0, vec![]).is_err());
+        assert!(ImpulseResponse::new(44100.0, vec![vec![]]).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_channel_lengths_are_an_error() {
+        let result = ImpulseResponse::new(44100.0, vec![vec![1.0, 0.5], vec![1.0]]);
+        assert!(matches!(
+            result,
+            Err(ImpulseResponseError::MismatchedChannelLengths)
+        ));
+    }
+
+    #[test]
+    fn test_read_mono_impulse_response_from_path() {
+        let tempdir = TempDir::new("test_read_mono_impulse_response").unwrap();
+        let mut channel = vec![0.0; 1000];
+        channel[10] = 1.0;
+        channel[500] = -0.5;
+        let path = write_test_file(&tempdir, &[channel.clone()]);
+
+        let impulse_response = ImpulseResponse::from_path(&path).unwrap();
+        assert_eq!(impulse_response.sample_rate(), 44100.0);
+        assert_eq!(impulse_response.num_channels(), 1);
+        assert_eq!(impulse_response.channel(0), &channel[..]);
+    }
+
+    #[test]
+    fn test_read_true_stereo_impulse_response_from_path() {
+        let tempdir = TempDir::new("test_read_true_stereo_impulse_response").unwrap();
+        let channels: Vec<Vec<f32>> = (0..4)
+            .map(|i| {
+                let mut channel = vec![0.0; 100];
+                channel[i] = 1.0;
+                channel
+            })
+            .collect();
+        let path = write_test_file(&tempdir, &channels);
+
+        let impulse_response = ImpulseResponse::from_path(&path).unwrap();
+        assert_eq!(impulse_response.num_channels(), 4);
+        for (i, channel) in channels.iter().enumerate() {
+            assert_eq!(impulse_response.channel(i), &channel[..]);
+        }
+    }
+
+    #[test]
+    fn test_resample_to_the_same_rate_is_a_copy() {
+        let impulse_response = ImpulseResponse::new(44100.0, vec![vec![1.0, 0.5, 0.25]]).unwrap();
+        let resampled = impulse_response.resample(44100.0);
+        assert_eq!(resampled.channel(0), impulse_response.channel(0));
+    }
+
+    #[test]
+    fn test_resample_keeps_frequency_content() {
+        let input = sine_buffer(44100.0, 440.0, Duration::from_millis(200));
+        let impulse_response = ImpulseResponse::new(44100.0, vec![input]).unwrap();
+        let resampled = impulse_response.resample(48000.0);
+        assert_eq!(resampled.sample_rate(), 48000.0);
+        assert_eq!(
+            resampled.len(),
+            (impulse_response.len() as f32 * 48000.0 / 44100.0).ceil() as usize
+        );
+
+        // Away from the edges the output should be the same sine sampled at the new rate
+        let expected = sine_buffer(48000.0, 440.0, Duration::from_millis(200));
+        for (i, (actual, expected)) in resampled
+            .channel(0)
+            .iter()
+            .zip(&expected)
+            .enumerate()
+            .take(8000)
+            .skip(1000)
+        {
+            assert!(
+                (actual - expected).abs() < 0.01,
+                "Sample {} differs {} != {}",
+                i,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_downsample_removes_content_above_nyquist() {
+        // 15kHz is above the nyquist frequency of 22050Hz / 2
+        let input = sine_buffer(44100.0, 15000.0, Duration::from_millis(200));
+        let impulse_response = ImpulseResponse::new(44100.0, vec![input]).unwrap();
+        let resampled = impulse_response.resample(22050.0);
+
+        let middle = &resampled.channel(0)[500..3500];
+        let peak = middle.iter().map(|s| s.abs()).fold(0.0, f32::max);
+        assert!(peak < 0.05, "Aliased content at {}", peak);
+    }
+}
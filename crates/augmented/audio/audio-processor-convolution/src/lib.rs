@@ -0,0 +1,369 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Convolution processor for impulse responses, such as room reverbs and guitar cabinets.
+//!
+//! [`ConvolutionProcessor`] uses uniformly partitioned FFT convolution, so its latency is a single
+//! partition (`block_size` samples) regardless of the impulse response length. Impulse responses
+//! are resampled to the session rate on `prepare` and nothing is allocated while processing.
+//!
+//! Mono, stereo and true-stereo (4 channel, `LL, LR, RL, RR`) impulse responses are supported.
+//!
+//! ```no_run
+//! use audio_processor_convolution::{ConvolutionProcessor, ImpulseResponse};
+//!
+//! let impulse_response = ImpulseResponse::from_path("./room.wav").unwrap();
+//! let processor = ConvolutionProcessor::new(impulse_response);
+//! processor.handle().set_dry_wet(0.3);
+//! ```
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::parameters::{
+    make_handle_ref, AudioProcessorHandleProvider, AudioProcessorHandleRef,
+};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor};
+use generic_handle::GenericHandle;
+use partitioned_convolution::{PartitionedConvolution, MAX_CHANNELS};
+
+pub use impulse_response::{ImpulseResponse, ImpulseResponseError};
+
+mod generic_handle;
+mod impulse_response;
+mod partitioned_convolution;
+#[cfg(all(test, debug_assertions))]
+mod test_allocator;
+
+pub struct ConvolutionProcessorHandle {
+    dry_wet: AtomicF32,
+    output_gain: AtomicF32,
+}
+
+impl Default for ConvolutionProcessorHandle {
+    fn default() -> Self {
+        Self {
+            dry_wet: AtomicF32::new(1.0),
+            output_gain: AtomicF32::new(1.0),
+        }
+    }
+}
+
+impl ConvolutionProcessorHandle {
+    pub fn dry_wet(&self) -> f32 {
+        self.dry_wet.get()
+    }
+
+    /// Set the dry/wet mix, `0.0` is fully dry and `1.0` fully wet
+    pub fn set_dry_wet(&self, value: f32) {
+        self.dry_wet.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn output_gain(&self) -> f32 {
+        self.output_gain.get()
+    }
+
+    /// Set the linear gain applied to the convolved signal
+    pub fn set_output_gain(&self, value: f32) {
+        self.output_gain.set(value.max(0.0));
+    }
+}
+
+pub struct ConvolutionProcessorOptions {
+    /// Partition size in samples, this is also the processor latency
+    pub block_size: usize,
+}
+
+impl Default for ConvolutionProcessorOptions {
+    fn default() -> Self {
+        Self { block_size: 256 }
+    }
+}
+
+pub struct ConvolutionProcessor {
+    handle: Shared<ConvolutionProcessorHandle>,
+    impulse_response: ImpulseResponse,
+    block_size: usize,
+    convolution: Option<PartitionedConvolution>,
+    /// Input samples waiting for the next block, per channel
+    input_block: Vec<Vec<f32>>,
+    /// Output samples of the last block, per channel
+    output_block: Vec<Vec<f32>>,
+    /// Position on the input and output blocks
+    cursor: usize,
+}
+
+impl AudioProcessorHandleProvider for ConvolutionProcessor {
+    fn generic_handle(&self) -> AudioProcessorHandleRef {
+        make_handle_ref(GenericHandle(self.handle.clone()))
+    }
+}
+
+impl ConvolutionProcessor {
+    pub fn new(impulse_response: ImpulseResponse) -> Self {
+        Self::new_with_options(impulse_response, Default::default())
+    }
+
+    pub fn new_with_options(
+        impulse_response: ImpulseResponse,
+        options: ConvolutionProcessorOptions,
+    ) -> Self {
+        assert!(options.block_size > 0, "Block size must be positive");
+        Self {
+            handle: make_shared(ConvolutionProcessorHandle::default()),
+            impulse_response,
+            block_size: options.block_size,
+            convolution: None,
+            input_block: vec![vec![0.0; options.block_size]; MAX_CHANNELS],
+            output_block: vec![vec![0.0; options.block_size]; MAX_CHANNELS],
+            cursor: 0,
+        }
+    }
+
+    /// Create a processor from an impulse response file
+    pub fn from_path(path: &str) -> Result<Self, ImpulseResponseError> {
+        Ok(Self::new(ImpulseResponse::from_path(path)?))
+    }
+
+    pub fn handle(&self) -> &Shared<ConvolutionProcessorHandle> {
+        &self.handle
+    }
+
+    /// Delay in samples between input and output, both wet and dry signals are delayed by it
+    pub fn latency_samples(&self) -> usize {
+        self.block_size
+    }
+}
+
+impl AudioProcessor for ConvolutionProcessor {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        let sample_rate = context.settings.sample_rate();
+        let impulse_response = self.impulse_response.resample(sample_rate);
+        self.convolution = Some(PartitionedConvolution::new(
+            &impulse_response,
+            self.block_size,
+        ));
+        for channel in self.input_block.iter_mut().chain(&mut self.output_block) {
+            channel.fill(0.0);
+        }
+        self.cursor = 0;
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+        let convolution = if let Some(convolution) = self.convolution.as_mut() {
+            convolution
+        } else {
+            return;
+        };
+        let dry_wet = self.handle.dry_wet();
+        let output_gain = self.handle.output_gain();
+        let num_channels = data.num_channels().min(MAX_CHANNELS);
+        if num_channels == 0 {
+            return;
+        }
+
+        for sample_num in 0..data.num_samples() {
+            for channel in 0..MAX_CHANNELS {
+                // Mono buffers feed the same signal onto both convolution inputs
+                let input = *data.get(channel.min(num_channels - 1), sample_num);
+                // Before being overwritten, the input block holds the dry signal delayed by the
+                // processor latency
+                let dry = self.input_block[channel][self.cursor];
+                self.input_block[channel][self.cursor] = input;
+
+                if channel < num_channels {
+                    let wet = self.output_block[channel][self.cursor] * output_gain;
+                    data.set(channel, sample_num, dry * (1.0 - dry_wet) + wet * dry_wet);
+                }
+            }
+
+            self.cursor += 1;
+            if self.cursor == self.block_size {
+                self.cursor = 0;
+                convolution.process_block(&self.input_block, &mut self.output_block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    fn make_context(sample_rate: f32) -> AudioContext {
+        let mut settings = AudioProcessorSettings::default();
+        settings.set_sample_rate(sample_rate);
+        AudioContext::from(settings)
+    }
+
+    fn impulse(len: usize) -> Vec<f32> {
+        let mut signal = vec![0.0; len];
+        signal[0] = 1.0;
+        signal
+    }
+
+    /// Run the processor over `input` in buffers of varying size, so blocks don't line up with
+    /// calls to `process`
+    fn run_processor(
+        processor: &mut ConvolutionProcessor,
+        context: &mut AudioContext,
+        input: Vec<Vec<f32>>,
+    ) -> Vec<Vec<f32>> {
+        let mut output = vec![vec![]; input.len()];
+        let buffer_sizes = [1, 17, 100, 3, 512, 64];
+        let mut start = 0;
+        let mut buffer_size_index = 0;
+        while start < input[0].len() {
+            let size =
+                buffer_sizes[buffer_size_index % buffer_sizes.len()].min(input[0].len() - start);
+            buffer_size_index += 1;
+            let mut buffer = AudioBuffer::new(
+                input
+                    .iter()
+                    .map(|channel| channel[start..start + size].to_vec())
+                    .collect(),
+            );
+            processor.process(context, &mut buffer);
+            for (channel, output) in output.iter_mut().enumerate() {
+                output.extend_from_slice(buffer.channel(channel));
+            }
+            start += size;
+        }
+        output
+    }
+
+    #[test]
+    fn test_wet_output_is_the_delayed_impulse_response() {
+        let impulse_response: Vec<f32> = (0..1000).map(|i| 0.999_f32.powi(i) * 0.5).collect();
+        let mut context = make_context(44100.0);
+        let mut processor = ConvolutionProcessor::new_with_options(
+            ImpulseResponse::new(44100.0, vec![impulse_response.clone()]).unwrap(),
+            ConvolutionProcessorOptions { block_size: 128 },
+        );
+        processor.prepare(&mut context);
+
+        let output = run_processor(&mut processor, &mut context, vec![impulse(2000); 2]);
+        let latency = processor.latency_samples();
+        assert_eq!(latency, 128);
+        for channel in output {
+            assert!(channel[..latency].iter().all(|s| *s == 0.0));
+            for (actual, expected) in channel[latency..].iter().zip(&impulse_response) {
+                assert!((actual - expected).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dry_signal_is_latency_aligned() {
+        let mut context = make_context(44100.0);
+        let mut processor = ConvolutionProcessor::new_with_options(
+            ImpulseResponse::new(44100.0, vec![vec![0.0, 0.0, 1.0]]).unwrap(),
+            ConvolutionProcessorOptions { block_size: 64 },
+        );
+        processor.handle().set_dry_wet(0.0);
+        processor.prepare(&mut context);
+
+        let input: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.1).sin()).collect();
+        let output = run_processor(&mut processor, &mut context, vec![input.clone()]);
+        assert_eq!(output[0][64..], input[..input.len() - 64]);
+    }
+
+    #[test]
+    fn test_mono_buffer() {
+        let mut context = make_context(44100.0);
+        let mut processor = ConvolutionProcessor::new_with_options(
+            ImpulseResponse::new(44100.0, vec![vec![0.0, 0.5]]).unwrap(),
+            ConvolutionProcessorOptions { block_size: 32 },
+        );
+        processor.prepare(&mut context);
+
+        let output = run_processor(&mut processor, &mut context, vec![impulse(100)]);
+        assert!((output[0][33] - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_empty_buffer() {
+        let mut context = make_context(44100.0);
+        let mut processor =
+            ConvolutionProcessor::new(ImpulseResponse::new(44100.0, vec![impulse(100)]).unwrap());
+        processor.prepare(&mut context);
+
+        let mut buffer = AudioBuffer::empty();
+        processor.process(&mut context, &mut buffer);
+        buffer.resize(0, 64);
+        processor.process(&mut context, &mut buffer);
+        assert_eq!(buffer.num_channels(), 0);
+    }
+
+    #[test]
+    fn test_impulse_response_is_resampled_on_prepare() {
+        let mut context = make_context(88200.0);
+        let mut impulse_response = vec![0.0; 200];
+        impulse_response[100] = 1.0;
+        let mut processor = ConvolutionProcessor::new_with_options(
+            ImpulseResponse::new(44100.0, vec![impulse_response]).unwrap(),
+            ConvolutionProcessorOptions { block_size: 64 },
+        );
+        processor.prepare(&mut context);
+
+        let output = run_processor(&mut processor, &mut context, vec![impulse(1000)]);
+        let (peak_index, _) = output[0]
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.abs().partial_cmp(&b.abs()).unwrap())
+            .unwrap();
+        assert_eq!(peak_index, 64 + 200);
+    }
+
+    #[test]
+    fn test_no_alloc_process() {
+        let mut context = make_context(44100.0);
+        let impulse_response: Vec<Vec<f32>> = (0..4).map(|_| impulse(44100)).collect();
+        let mut processor =
+            ConvolutionProcessor::new(ImpulseResponse::new(44100.0, impulse_response).unwrap());
+        processor.prepare(&mut context);
+
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 1024);
+        assert_no_alloc(|| {
+            for _ in 0..10 {
+                processor.process(&mut context, &mut buffer);
+            }
+        });
+    }
+
+    #[test]
+    fn test_generic_handle() {
+        let processor =
+            ConvolutionProcessor::new(ImpulseResponse::new(44100.0, vec![impulse(10)]).unwrap());
+        let handle = processor.generic_handle();
+        assert_eq!(handle.name(), "Convolution");
+        assert_eq!(handle.parameter_count(), 2);
+        handle.set_parameter(0, 0.25.into());
+        assert_eq!(processor.handle().dry_wet(), 0.25);
+        handle.set_parameter(1, 0.5.into());
+        assert_eq!(processor.handle().output_gain(), 0.5);
+    }
+}
@@ -0,0 +1,325 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Uniformly partitioned overlap-save convolution.
+//!
+//! The impulse response is split into partitions of `block_size` samples, each transformed with a
+//! `2 * block_size` FFT. Every input block is transformed once and kept on a frequency-domain delay
+//! line, so the convolution with the whole response is a sum of spectral products followed by a
+//! single inverse FFT.
+//!
+//! # References
+//! * "Efficient Convolution without Input-Output Delay: William G. Gardner"
+//! * "Partitioned convolution algorithms for real-time auralization: Frank Wefers"
+
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use crate::impulse_response::ImpulseResponse;
+
+/// Maximum number of input and output channels
+pub(crate) const MAX_CHANNELS: usize = 2;
+
+/// Convolution of one input channel with one impulse response channel, summed onto one output
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Route {
+    input: usize,
+    output: usize,
+    impulse_response_channel: usize,
+}
+
+/// Map impulse response channels to input/output pairs.
+///
+/// Mono responses are used on both channels, stereo responses are used as left/right and 4
+/// channel responses as true-stereo `LL, LR, RL, RR`.
+fn make_routes(num_impulse_response_channels: usize) -> Vec<Route> {
+    let route = |input, output, impulse_response_channel| Route {
+        input,
+        output,
+        impulse_response_channel,
+    };
+
+    match num_impulse_response_channels {
+        1 => vec![route(0, 0, 0), route(1, 1, 0)],
+        4 => vec![
+            route(0, 0, 0),
+            route(0, 1, 1),
+            route(1, 0, 2),
+            route(1, 1, 3),
+        ],
+        // Anything else uses its first two channels
+        _ => vec![route(0, 0, 0), route(1, 1, 1)],
+    }
+}
+
+pub(crate) struct PartitionedConvolution {
+    block_size: usize,
+    forward_fft: Arc<dyn Fft<f32>>,
+    inverse_fft: Arc<dyn Fft<f32>>,
+    routes: Vec<Route>,
+    /// Spectra of each impulse response partition, indexed by `[channel][partition]`
+    partitions: Vec<Vec<Vec<Complex<f32>>>>,
+    /// Frequency-domain delay line of input spectra, indexed by `[input][slot]`
+    input_spectra: Vec<Vec<Vec<Complex<f32>>>>,
+    /// Slot of the most recent input block on `input_spectra`
+    position: usize,
+    /// The previous and current input blocks, for each input
+    input_history: Vec<Vec<f32>>,
+    accumulators: Vec<Vec<Complex<f32>>>,
+    fft_buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl PartitionedConvolution {
+    /// Pre-compute the impulse response spectra. This allocates and should happen off the
+    /// audio-thread. `impulse_response` should already be at the processing rate.
+    pub(crate) fn new(impulse_response: &ImpulseResponse, block_size: usize) -> Self {
+        assert!(block_size > 0, "Block size must be positive");
+        let fft_size = block_size * 2;
+        let mut planner = FftPlanner::new();
+        let forward_fft = planner.plan_fft_forward(fft_size);
+        let inverse_fft = planner.plan_fft_inverse(fft_size);
+        let scratch_len = forward_fft
+            .get_inplace_scratch_len()
+            .max(inverse_fft.get_inplace_scratch_len());
+        let mut scratch = vec![Complex::default(); scratch_len];
+
+        let routes = make_routes(impulse_response.num_channels());
+        let num_partitions = impulse_response.len().div_ceil(block_size);
+        let num_channels = impulse_response.num_channels().min(4);
+
+        let partitions = (0..num_channels)
+            .map(|channel| {
+                impulse_response
+                    .channel(channel)
+                    .chunks(block_size)
+                    .map(|partition| {
+                        // Partitions are zero-padded to the FFT size and the 1/N inverse FFT
+                        // scaling is folded in here
+                        let mut spectrum = vec![Complex::default(); fft_size];
+                        for (bin, sample) in spectrum.iter_mut().zip(partition) {
+                            bin.re = *sample / fft_size as f32;
+                        }
+                        forward_fft.process_with_scratch(&mut spectrum, &mut scratch);
+                        spectrum
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            block_size,
+            forward_fft,
+            inverse_fft,
+            routes,
+            partitions,
+            input_spectra: vec![
+                vec![vec![Complex::default(); fft_size]; num_partitions];
+                MAX_CHANNELS
+            ],
+            position: 0,
+            input_history: vec![vec![0.0; fft_size]; MAX_CHANNELS],
+            accumulators: vec![vec![Complex::default(); fft_size]; MAX_CHANNELS],
+            fft_buffer: vec![Complex::default(); fft_size],
+            scratch,
+        }
+    }
+
+    /// Convolve one block of `block_size` samples per channel. `outputs` are overwritten.
+    pub(crate) fn process_block(&mut self, inputs: &[Vec<f32>], outputs: &mut [Vec<f32>]) {
+        let block_size = self.block_size;
+        let num_partitions = self.input_spectra[0].len();
+        self.position = (self.position + 1) % num_partitions;
+
+        for (input, (history, spectra)) in inputs
+            .iter()
+            .zip(self.input_history.iter_mut().zip(&mut self.input_spectra))
+        {
+            // Slide the last block into the first half of the window
+            history.copy_within(block_size.., 0);
+            history[block_size..].copy_from_slice(&input[..block_size]);
+
+            let spectrum = &mut spectra[self.position];
+            for (bin, sample) in spectrum.iter_mut().zip(history.iter()) {
+                *bin = Complex::new(*sample, 0.0);
+            }
+            self.forward_fft
+                .process_with_scratch(spectrum, &mut self.scratch);
+        }
+
+        for accumulator in &mut self.accumulators {
+            accumulator.fill(Complex::default());
+        }
+        for route in &self.routes {
+            let spectra = &self.input_spectra[route.input];
+            let accumulator = &mut self.accumulators[route.output];
+            for (partition_index, partition) in self.partitions[route.impulse_response_channel]
+                .iter()
+                .enumerate()
+            {
+                // Partition `k` multiplies the input block from `k` blocks ago
+                let slot = (self.position + num_partitions - partition_index) % num_partitions;
+                for ((acc, x), h) in accumulator.iter_mut().zip(&spectra[slot]).zip(partition) {
+                    *acc += x * h;
+                }
+            }
+        }
+
+        for (accumulator, output) in self.accumulators.iter().zip(outputs.iter_mut()) {
+            self.fft_buffer.copy_from_slice(accumulator);
+            self.inverse_fft
+                .process_with_scratch(&mut self.fft_buffer, &mut self.scratch);
+            // The first half is circularly aliased, the second half is the linear convolution
+            for (sample, bin) in output.iter_mut().zip(&self.fft_buffer[block_size..]) {
+                *sample = bin.re;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn direct_convolution(input: &[f32], impulse_response: &[f32]) -> Vec<f32> {
+        (0..input.len())
+            .map(|n| {
+                impulse_response
+                    .iter()
+                    .enumerate()
+                    .take(n + 1)
+                    .map(|(k, h)| h * input[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn random_signal(seed: u64, len: usize) -> Vec<f32> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect()
+    }
+
+    fn run(convolution: &mut PartitionedConvolution, inputs: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        let block_size = convolution.block_size;
+        let mut result = vec![vec![]; MAX_CHANNELS];
+        let mut block_inputs = vec![vec![0.0; block_size]; MAX_CHANNELS];
+        let mut block_outputs = vec![vec![0.0; block_size]; MAX_CHANNELS];
+        for block_start in (0..inputs[0].len()).step_by(block_size) {
+            for (block_input, input) in block_inputs.iter_mut().zip(inputs) {
+                block_input.copy_from_slice(&input[block_start..block_start + block_size]);
+            }
+            convolution.process_block(&block_inputs, &mut block_outputs);
+            for (result, output) in result.iter_mut().zip(&block_outputs) {
+                result.extend_from_slice(output);
+            }
+        }
+        result
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-3, "Sample {} differs {} != {}", i, a, e);
+        }
+    }
+
+    #[test]
+    fn test_matches_direct_convolution() {
+        let input = random_signal(0, 64 * 20);
+        let impulse_response = random_signal(1, 300);
+        let mut convolution = PartitionedConvolution::new(
+            &ImpulseResponse::new(44100.0, vec![impulse_response.clone()]).unwrap(),
+            64,
+        );
+
+        let output = run(&mut convolution, &[input.clone(), input.clone()]);
+        let expected = direct_convolution(&input, &impulse_response);
+        assert_close(&output[0], &expected);
+        assert_close(&output[1], &expected);
+    }
+
+    #[test]
+    fn test_impulse_response_shorter_than_a_block() {
+        let input = random_signal(0, 128 * 4);
+        let impulse_response = vec![0.5, 0.0, 0.25];
+        let mut convolution = PartitionedConvolution::new(
+            &ImpulseResponse::new(44100.0, vec![impulse_response.clone()]).unwrap(),
+            128,
+        );
+
+        let output = run(&mut convolution, &[input.clone(), input.clone()]);
+        assert_close(&output[0], &direct_convolution(&input, &impulse_response));
+    }
+
+    #[test]
+    fn test_stereo_routing() {
+        let left = random_signal(0, 32 * 10);
+        let right = random_signal(1, 32 * 10);
+        let left_response = random_signal(2, 100);
+        let right_response = random_signal(3, 70);
+        let mut right_response_padded = right_response.clone();
+        right_response_padded.resize(100, 0.0);
+        let mut convolution = PartitionedConvolution::new(
+            &ImpulseResponse::new(44100.0, vec![left_response.clone(), right_response_padded])
+                .unwrap(),
+            32,
+        );
+
+        let output = run(&mut convolution, &[left.clone(), right.clone()]);
+        assert_close(&output[0], &direct_convolution(&left, &left_response));
+        assert_close(&output[1], &direct_convolution(&right, &right_response));
+    }
+
+    #[test]
+    fn test_true_stereo_routing() {
+        let left = random_signal(0, 32 * 10);
+        let right = random_signal(1, 32 * 10);
+        let responses: Vec<Vec<f32>> = (2..6).map(|seed| random_signal(seed, 90)).collect();
+        let mut convolution = PartitionedConvolution::new(
+            &ImpulseResponse::new(44100.0, responses.clone()).unwrap(),
+            32,
+        );
+
+        let output = run(&mut convolution, &[left.clone(), right.clone()]);
+        let sum = |a: Vec<f32>, b: Vec<f32>| -> Vec<f32> {
+            a.iter().zip(&b).map(|(a, b)| a + b).collect()
+        };
+        assert_close(
+            &output[0],
+            &sum(
+                direct_convolution(&left, &responses[0]),
+                direct_convolution(&right, &responses[2]),
+            ),
+        );
+        assert_close(
+            &output[1],
+            &sum(
+                direct_convolution(&left, &responses[1]),
+                direct_convolution(&right, &responses[3]),
+            ),
+        );
+    }
+}
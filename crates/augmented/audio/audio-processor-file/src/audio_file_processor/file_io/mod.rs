@@ -22,7 +22,7 @@
 // THE SOFTWARE.
 
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use symphonia::core::audio::Signal;
 use symphonia::core::audio::{AudioBuffer as SymphoniaAudioBuffer, AudioBufferRef};
@@ -41,10 +41,35 @@ use crate::file_io::sample_rate_converter::BLOCK_SIZE;
 pub use self::audio_file_error::AudioFileError;
 
 mod audio_file_error;
-mod sample_rate_converter;
+pub(crate) mod sample_rate_converter;
 #[cfg(test)]
 mod test;
 
+/// File extensions recognised as audio files when walking directories, these are the formats
+/// [`default_read_audio_file`] can decode with the enabled `symphonia` features
+pub const AUDIO_FILE_EXTENSIONS: [&str; 6] = ["wav", "wave", "mp3", "flac", "m4a", "aac"];
+
+/// Whether `path` has one of the [`AUDIO_FILE_EXTENSIONS`], ignoring case
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| AUDIO_FILE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Recursively push every audio file under `directory` onto `paths`
+pub fn find_audio_files(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_audio_files(&path, paths)?;
+        } else if is_audio_file(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
 /// Opens an audio file with default options & trying to guess the format
 pub fn default_read_audio_file(input_audio_path: &str) -> Result<ProbeResult, AudioFileError> {
     log::info!(
@@ -328,7 +353,7 @@ pub fn convert_audio_file_sample_rate(
     channel
 }
 
-/// buffers must be non-empty and stereo
+/// buffers must be non-empty and have the same number of channels
 fn concat_buffers(buffers: Vec<SymphoniaAudioBuffer<f32>>) -> SymphoniaAudioBuffer<f32> {
     let duration = buffers
         .iter()
@@ -341,7 +366,7 @@ fn concat_buffers(buffers: Vec<SymphoniaAudioBuffer<f32>>) -> SymphoniaAudioBuff
     for buffer in buffers {
         let mut channel_size = 0;
 
-        for channel_num in 0..2 {
+        for channel_num in 0..buffer.spec().channels.count() {
             let mut cursor = output_cursor; // reading channels copy cursor to reset for each channel
 
             let output_channel = output.chan_mut(channel_num);
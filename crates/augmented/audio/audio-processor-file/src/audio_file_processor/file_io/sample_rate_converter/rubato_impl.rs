@@ -28,6 +28,8 @@ use super::BLOCK_SIZE;
 pub type Decoder = rubato::FftFixedIn<f32>;
 pub type DecoderError = rubato::ResampleError;
 pub type DecoderCreateError = rubato::ResamplerConstructionError;
+/// Number of output frames of delay introduced by the decoder
+pub const LATENCY: usize = 256;
 
 pub fn make_decoder(
     input_rate: u32,
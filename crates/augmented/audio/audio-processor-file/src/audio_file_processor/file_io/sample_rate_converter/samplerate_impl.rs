@@ -21,8 +21,20 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-pub type Decoder = samplerate::Samplerate;
+/// Sample-rate converter backed by `libsamplerate`
+pub struct Decoder(samplerate::Samplerate);
+
+// SAFETY: `samplerate::Samplerate` is `!Send` only because it holds a raw `*mut SRC_STATE`.
+// `libsamplerate` keeps no thread-local or global state for a converter, so its state may be
+// used from any thread as long as calls aren't concurrent. The pointer is owned exclusively by
+// this `Decoder`, which neither clones nor shares it, and every call goes through `&mut self`, so
+// after being moved to the streaming reader thread only that one thread uses it at a time.
+unsafe impl Send for Decoder {}
+
 pub type DecoderError = samplerate::Error;
+/// Number of output frames of delay introduced by the decoder, `libsamplerate` compensates for its
+/// own delay
+pub const LATENCY: usize = 0;
 
 pub fn make_decoder(
     input_rate: u32,
@@ -35,6 +47,7 @@ pub fn make_decoder(
         output_rate,
         channels,
     )
+    .map(Decoder)
 }
 
 pub fn process<T: AsRef<[f32]>>(
@@ -54,7 +67,7 @@ pub fn process<T: AsRef<[f32]>>(
         }
     }
 
-    let result = decoder.process(&interleaved_buffer)?;
+    let result = decoder.0.process(&interleaved_buffer)?;
 
     let mut deinterleaved_buffer = vec![];
     deinterleaved_buffer.resize(num_channels, vec![]);
@@ -186,6 +186,27 @@ fn test_concat_buffers() {
     assert_eq!(result.chan(1)[3], 3.0);
 }
 
+#[test]
+fn test_concat_mono_buffers() {
+    let mut buffer1 = symphonia::core::audio::AudioBuffer::new(
+        2,
+        symphonia::core::audio::SignalSpec::new(44100, Channels::FRONT_LEFT),
+    );
+    let mut buffer2 = symphonia::core::audio::AudioBuffer::new(
+        2,
+        symphonia::core::audio::SignalSpec::new(44100, Channels::FRONT_LEFT),
+    );
+
+    buffer1.fill(|_, _| Ok(())).unwrap();
+    buffer1.chan_mut(0).fill(1.0);
+    buffer2.fill(|_, _| Ok(())).unwrap();
+    buffer2.chan_mut(0).fill(3.0);
+
+    let result = concat_buffers(vec![buffer1, buffer2]);
+    assert_eq!(result.spec().channels.count(), 1);
+    assert_eq!(result.chan(0), &[1.0, 1.0, 3.0, 3.0]);
+}
+
 #[test]
 fn test_convert_audio_file_stream_sample_rate() {
     let tempdir = TempDir::new("test_file_contents_stream").unwrap();
@@ -260,3 +281,32 @@ fn test_file_frames_stream_if_input_is_not_a_multiple_of_file_frame_count() {
     assert_eq!(frame3.0[0].len(), block_size);
     assert_eq!(frame3.1, 3, "Frame 3 has wrong size");
 }
+
+#[test]
+fn test_find_audio_files() {
+    let tempdir = TempDir::new("audio_processor_file__find_audio_files").unwrap();
+    let nested = tempdir.path().join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    for path in [
+        "kick.WAV",
+        "notes.txt",
+        "nested/pad.aiff",
+        "nested/pad.m4a",
+        "nested/bass.flac",
+    ] {
+        std::fs::write(tempdir.path().join(path), []).unwrap();
+    }
+
+    let mut paths = vec![];
+    find_audio_files(tempdir.path(), &mut paths).unwrap();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            tempdir.path().join("kick.WAV"),
+            nested.join("bass.flac"),
+            nested.join("pad.m4a"),
+        ]
+    );
+    assert!(!is_audio_file(Path::new("wav")));
+}
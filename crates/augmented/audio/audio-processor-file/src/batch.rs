@@ -0,0 +1,544 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Render folders of audio files in parallel.
+//!
+//! [`BatchInput`] finds the files to process, either by walking a directory or by expanding a glob
+//! pattern. [`plan_batch`] maps each file onto a [`BatchJob`], with an output path that mirrors
+//! its location relative to the input root. [`run_batch`] then runs the jobs on a rayon thread pool
+//! and measures each output file into a [`BatchReport`].
+//!
+//! The actual rendering is up to the caller, so the same machinery works for standalone processors
+//! and for hosted plugins.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use audio_processor_file::batch::{plan_batch, run_batch, BatchInput};
+//!
+//! let input = BatchInput::parse("samples/**/*.wav");
+//! let jobs = plan_batch(&input, Path::new("rendered"), "wav").unwrap();
+//! let report = run_batch(jobs, None, |job| {
+//!     std::fs::copy(&job.input, &job.output).map(|_| ())
+//! })
+//! .unwrap();
+//! report.write_json(Path::new("rendered/report.json")).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Signal;
+use thiserror::Error;
+
+use crate::file_io::{
+    default_read_audio_file, find_audio_files, read_file_contents, AudioFileError,
+};
+use crate::levels::{measure_levels, SILENCE_DB};
+
+#[derive(Debug, Error)]
+pub enum BatchError {
+    #[error("Invalid glob pattern {0}")]
+    Pattern(#[from] glob::PatternError),
+    #[error("Failed to read glob match {0}")]
+    Glob(#[from] glob::GlobError),
+    #[error("IO error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to start the batch thread pool {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("Failed to write batch report {0}")]
+    Report(#[from] serde_json::Error),
+    #[error("No audio files found for {0}")]
+    NoInputFiles(String),
+    #[error("{first:?} and {second:?} would both be rendered into {output:?}")]
+    DuplicateOutput {
+        output: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+/// Where batch input files come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchInput {
+    /// Every audio file under this directory, recursively
+    Directory(PathBuf),
+    /// Every file matching this pattern, e.g. `samples/**/*.wav`
+    Glob(String),
+}
+
+impl BatchInput {
+    /// Patterns containing `*`, `?` or `[` are globs, everything else is a directory
+    pub fn parse(input: &str) -> Self {
+        if input.contains(['*', '?', '[']) {
+            BatchInput::Glob(input.to_string())
+        } else {
+            BatchInput::Directory(PathBuf::from(input))
+        }
+    }
+
+    /// The directory output paths are made relative to. For globs, this is the longest prefix
+    /// without wildcards.
+    pub fn root(&self) -> PathBuf {
+        match self {
+            BatchInput::Directory(directory) => directory.clone(),
+            BatchInput::Glob(pattern) => {
+                let mut root = PathBuf::new();
+                let path = Path::new(pattern);
+                let num_components = path.components().count();
+                for (index, component) in path.components().enumerate() {
+                    let is_last = index + 1 == num_components;
+                    let has_wildcard = component
+                        .as_os_str()
+                        .to_str()
+                        .map(|component| component.contains(['*', '?', '[']))
+                        .unwrap_or(false);
+                    if has_wildcard || is_last {
+                        break;
+                    }
+                    root.push(component);
+                }
+                root
+            }
+        }
+    }
+
+    /// Find input files, sorted by path
+    pub fn files(&self) -> Result<Vec<PathBuf>, BatchError> {
+        let mut paths = vec![];
+        match self {
+            BatchInput::Directory(directory) => find_audio_files(directory, &mut paths)?,
+            BatchInput::Glob(pattern) => {
+                for entry in glob::glob(pattern)? {
+                    let path = entry?;
+                    if path.is_file() {
+                        paths.push(path);
+                    }
+                }
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// A single input file & where its output should be written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchJob {
+    pub input: PathBuf,
+    /// Input path relative to [`BatchInput::root`]
+    pub relative_path: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Map every input file onto `output_directory`, keeping the folder structure and replacing the
+/// file extension with `extension`. Fails if two inputs, such as `a.wav` & `a.mp3`, would be
+/// rendered into the same output.
+pub fn plan_batch(
+    input: &BatchInput,
+    output_directory: &Path,
+    extension: &str,
+) -> Result<Vec<BatchJob>, BatchError> {
+    let root = input.root();
+    let files = input.files()?;
+    if files.is_empty() {
+        return Err(BatchError::NoInputFiles(format!("{:?}", input)));
+    }
+
+    let mut inputs_by_output: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut jobs = Vec::with_capacity(files.len());
+    for path in files {
+        let relative_path = relative_path(&root, &path);
+        let output = output_directory
+            .join(&relative_path)
+            .with_extension(extension);
+        // `a.wav` & `a.mp3` would overwrite each other's output
+        if let Some(first) = inputs_by_output.insert(output.clone(), path.clone()) {
+            return Err(BatchError::DuplicateOutput {
+                output,
+                first,
+                second: path,
+            });
+        }
+        jobs.push(BatchJob {
+            input: path,
+            relative_path,
+            output,
+        });
+    }
+    Ok(jobs)
+}
+
+/// The outcome of a single [`BatchJob`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchFileReport {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Wall-clock time spent rendering & measuring this file
+    pub render_time_secs: f64,
+    /// Duration of the rendered output, if it was written
+    pub duration_secs: Option<f64>,
+    /// Sample peak across all channels, in dBFS
+    pub peak_db: Option<f32>,
+    /// RMS level across all channels, in dBFS
+    pub loudness_db: Option<f32>,
+    pub error: Option<String>,
+}
+
+impl BatchFileReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Summary of a [`run_batch`] call, in input order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub files: Vec<BatchFileReport>,
+    pub total_time_secs: f64,
+}
+
+impl BatchReport {
+    pub fn num_failed(&self) -> usize {
+        self.files.iter().filter(|file| !file.is_ok()).count()
+    }
+
+    /// Total duration of rendered audio
+    pub fn rendered_duration(&self) -> Duration {
+        Duration::from_secs_f64(
+            self.files
+                .iter()
+                .filter_map(|file| file.duration_secs)
+                .sum(),
+        )
+    }
+
+    /// How many times faster than real-time the batch ran
+    pub fn speed_factor(&self) -> f64 {
+        if self.total_time_secs > 0.0 {
+            self.rendered_duration().as_secs_f64() / self.total_time_secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), BatchError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn log_summary(&self) {
+        for file in &self.files {
+            match &file.error {
+                None => log::info!(
+                    "Rendered {:?} duration={:.2}s peak={:.1}dB loudness={:.1}dB time={:.2}s",
+                    file.output,
+                    file.duration_secs.unwrap_or(0.0),
+                    file.peak_db.unwrap_or(SILENCE_DB),
+                    file.loudness_db.unwrap_or(SILENCE_DB),
+                    file.render_time_secs
+                ),
+                Some(error) => log::error!("Failed to render {:?}: {}", file.input, error),
+            }
+        }
+        log::info!(
+            "Batch finished files={} failed={} time={:.2}s speed={:.1}x",
+            self.files.len(),
+            self.num_failed(),
+            self.total_time_secs,
+            self.speed_factor()
+        );
+    }
+}
+
+/// Run `render` for every job on a rayon thread pool with `num_threads` threads (defaults to the
+/// number of CPUs).
+///
+/// Output directories are created before `render` is called. Failures are collected into the
+/// report rather than stopping the batch.
+pub fn run_batch<F, E>(
+    jobs: Vec<BatchJob>,
+    num_threads: Option<usize>,
+    render: F,
+) -> Result<BatchReport, BatchError>
+where
+    F: Fn(&BatchJob) -> Result<(), E> + Sync,
+    E: Display,
+{
+    let mut builder = rayon::ThreadPoolBuilder::new();
+    if let Some(num_threads) = num_threads {
+        builder = builder.num_threads(num_threads);
+    }
+    let pool = builder.build()?;
+
+    let start = Instant::now();
+    let files = pool.install(|| {
+        jobs.par_iter()
+            .map(|job| run_job(job, &render))
+            .collect::<Vec<_>>()
+    });
+
+    Ok(BatchReport {
+        files,
+        total_time_secs: start.elapsed().as_secs_f64(),
+    })
+}
+
+fn run_job<F, E>(job: &BatchJob, render: &F) -> BatchFileReport
+where
+    F: Fn(&BatchJob) -> Result<(), E>,
+    E: Display,
+{
+    log::info!("Rendering {:?} into {:?}", job.input, job.output);
+    let start = Instant::now();
+    let mut report = BatchFileReport {
+        input: job.input.clone(),
+        output: job.output.clone(),
+        render_time_secs: 0.0,
+        duration_secs: None,
+        peak_db: None,
+        loudness_db: None,
+        error: None,
+    };
+
+    let result = job
+        .output
+        .parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .map_err(|err| err.to_string())
+        .and_then(|_| render(job).map_err(|err| err.to_string()))
+        .and_then(|_| measure_file(&job.output).map_err(|err| err.to_string()));
+    match result {
+        Ok(levels) => {
+            report.duration_secs = Some(levels.duration_secs);
+            report.peak_db = Some(levels.peak_db);
+            report.loudness_db = Some(levels.loudness_db);
+        }
+        Err(error) => report.error = Some(error),
+    }
+
+    report.render_time_secs = start.elapsed().as_secs_f64();
+    report
+}
+
+struct FileLevels {
+    duration_secs: f64,
+    peak_db: f32,
+    loudness_db: f32,
+}
+
+fn measure_file(path: &Path) -> Result<FileLevels, AudioFileError> {
+    let path = path.to_str().ok_or(AudioFileError::OpenStreamError)?;
+    let mut file = default_read_audio_file(path)?;
+    let contents = read_file_contents(&mut file)?;
+
+    let num_channels = contents.spec().channels.count();
+    let levels = measure_levels((0..num_channels).map(|channel| contents.chan(channel)));
+
+    Ok(FileLevels {
+        duration_secs: contents.frames() as f64 / contents.spec().rate as f64,
+        peak_db: levels.peak_db,
+        loudness_db: levels.rms_db,
+    })
+}
+
+/// `path` relative to `root`, falling back to the file name when it isn't under `root`
+fn relative_path(root: &Path, path: &Path) -> PathBuf {
+    let relative = path
+        .strip_prefix(root)
+        .ok()
+        .filter(|relative| {
+            relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        })
+        .map(|relative| relative.to_path_buf());
+    relative.unwrap_or_else(|| path.file_name().map(PathBuf::from).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::levels::to_db;
+
+    use super::*;
+
+    fn write_wav(path: &Path, amplitude: f32, num_frames: usize) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..num_frames {
+            writer.write_sample(amplitude).unwrap();
+            writer.write_sample(-amplitude).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn setup() -> (tempdir::TempDir, PathBuf) {
+        let directory = tempdir::TempDir::new("audio_processor_file__batch").unwrap();
+        let root = directory.path().join("input");
+        write_wav(&root.join("drums/kick.wav"), 0.5, 4410);
+        write_wav(&root.join("drums/loops/break.wav"), 0.25, 44100);
+        write_wav(&root.join("pad.wav"), 1.0, 22050);
+        std::fs::write(root.join("notes.txt"), "not audio").unwrap();
+        (directory, root)
+    }
+
+    #[test]
+    fn test_parse_batch_input() {
+        assert_eq!(
+            BatchInput::parse("samples/drums"),
+            BatchInput::Directory(PathBuf::from("samples/drums"))
+        );
+        assert_eq!(
+            BatchInput::parse("samples/**/*.wav"),
+            BatchInput::Glob("samples/**/*.wav".to_string())
+        );
+        assert_eq!(
+            BatchInput::parse("samples/**/*.wav").root(),
+            PathBuf::from("samples")
+        );
+        assert_eq!(BatchInput::parse("*.wav").root(), PathBuf::new());
+    }
+
+    #[test]
+    fn test_plan_directory_mirrors_structure() {
+        let (directory, root) = setup();
+        let output = directory.path().join("output");
+        let jobs = plan_batch(&BatchInput::Directory(root.clone()), &output, "wav").unwrap();
+
+        let relative_paths: Vec<_> = jobs.iter().map(|job| job.relative_path.clone()).collect();
+        assert_eq!(
+            relative_paths,
+            vec![
+                PathBuf::from("drums/kick.wav"),
+                PathBuf::from("drums/loops/break.wav"),
+                PathBuf::from("pad.wav"),
+            ]
+        );
+        assert_eq!(jobs[1].input, root.join("drums/loops/break.wav"));
+        assert_eq!(jobs[1].output, output.join("drums/loops/break.wav"));
+    }
+
+    #[test]
+    fn test_plan_glob() {
+        let (directory, root) = setup();
+        let output = directory.path().join("output");
+        let pattern = format!("{}/drums/**/*.wav", root.to_str().unwrap());
+        let jobs = plan_batch(&BatchInput::parse(&pattern), &output, "flac").unwrap();
+
+        let outputs: Vec<_> = jobs.iter().map(|job| job.output.clone()).collect();
+        assert_eq!(
+            outputs,
+            vec![output.join("kick.flac"), output.join("loops/break.flac"),]
+        );
+    }
+
+    #[test]
+    fn test_plan_with_clashing_outputs_fails() {
+        let (directory, root) = setup();
+        std::fs::write(root.join("pad.mp3"), "not decoded while planning").unwrap();
+        let output = directory.path().join("output");
+        let result = plan_batch(&BatchInput::Directory(root.clone()), &output, "wav");
+        match result {
+            Err(BatchError::DuplicateOutput {
+                output: duplicate,
+                first,
+                second,
+            }) => {
+                assert_eq!(duplicate, output.join("pad.wav"));
+                let mut inputs = vec![first, second];
+                inputs.sort();
+                assert_eq!(inputs, vec![root.join("pad.mp3"), root.join("pad.wav")]);
+            }
+            _ => panic!("Expected a duplicate output error"),
+        }
+    }
+
+    #[test]
+    fn test_plan_without_files_fails() {
+        let (directory, root) = setup();
+        let pattern = format!("{}/**/*.mp3", root.to_str().unwrap());
+        let result = plan_batch(&BatchInput::parse(&pattern), directory.path(), "wav");
+        assert!(matches!(result, Err(BatchError::NoInputFiles(_))));
+    }
+
+    #[test]
+    fn test_run_batch_reports_levels_and_errors() {
+        let (directory, root) = setup();
+        let output = directory.path().join("output");
+        let jobs = plan_batch(&BatchInput::Directory(root), &output, "wav").unwrap();
+
+        let report = run_batch(jobs, Some(2), |job| {
+            if job.relative_path == Path::new("pad.wav") {
+                return Err("processor failed".to_string());
+            }
+            std::fs::copy(&job.input, &job.output)
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(report.num_failed(), 1);
+        assert!(output.join("drums/loops/break.wav").exists());
+
+        let kick = &report.files[0];
+        assert!(kick.is_ok());
+        assert!((kick.duration_secs.unwrap() - 0.1).abs() < 1e-6);
+        assert!((kick.peak_db.unwrap() - to_db(0.5)).abs() < 0.01);
+        assert!((kick.loudness_db.unwrap() - to_db(0.5)).abs() < 0.01);
+
+        let pad = &report.files[2];
+        assert_eq!(pad.error.as_deref(), Some("processor failed"));
+        assert_eq!(pad.duration_secs, None);
+        assert!((report.rendered_duration().as_secs_f64() - 1.1).abs() < 1e-6);
+
+        let report_path = output.join("report.json");
+        report.write_json(&report_path).unwrap();
+        let written: BatchReport =
+            serde_json::from_slice(&std::fs::read(report_path).unwrap()).unwrap();
+        assert_eq!(written.files.len(), 3);
+        assert_eq!(written.files[2].error, pad.error);
+        assert_eq!(written.files[0].peak_db, kick.peak_db);
+    }
+
+    #[test]
+    fn test_run_batch_reports_missing_output() {
+        let (directory, root) = setup();
+        let jobs = plan_batch(&BatchInput::Directory(root), directory.path(), "wav").unwrap();
+        let report = run_batch(jobs, Some(1), |_| Ok::<(), String>(())).unwrap();
+        assert_eq!(report.num_failed(), 3);
+    }
+}
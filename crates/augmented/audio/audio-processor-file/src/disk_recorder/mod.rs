@@ -0,0 +1,702 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Recording straight to disk.
+//!
+//! [`DiskRecorderProcessor`] passes audio through unchanged and, while armed or recording, copies
+//! each block into a pre-allocated block which is pushed onto a lock-free queue (see
+//! [`atomic_queue`]). A writer thread pops the blocks, encodes them and sends them back to be
+//! reused, so the audio thread never allocates or blocks on disk IO.
+//!
+//! * While armed, the writer keeps the last [`DiskRecorderSettings::pre_roll`] of audio, which is
+//!   written at the start of the next take
+//! * [`DiskRecorderHandle::punch_in`] & [`DiskRecorderHandle::punch_out`] start and stop takes,
+//!   [`DiskRecorderHandle::set_auto_punch`] does so at sample-accurate positions
+//! * In multi-track mode, each channel is written into its own mono file
+//! * If the writer falls behind, blocks are dropped, counted in
+//!   [`DiskRecorderHandle::dropped_blocks`] and replaced by silence so takes stay in sync
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use atomic_queue::Queue;
+use audio_garbage_collector::{Handle, Shared};
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
+
+use crate::encoder::EncodedFormat;
+use crate::OutputSampleFormat;
+
+use self::writer::RecorderWriter;
+
+mod writer;
+
+/// How often the writer thread polls for blocks when the queue is empty
+const WRITER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Marks that no auto-punch position is set
+const NO_PUNCH: u64 = u64::MAX;
+
+/// A block of interleaved frames, sent from the audio thread to the writer thread
+pub(crate) struct RecordedBlock {
+    /// Recorder clock position of the first frame
+    pub(crate) frame: u64,
+    pub(crate) num_frames: usize,
+    pub(crate) is_recording: bool,
+    pub(crate) samples: Vec<f32>,
+}
+
+/// A finished recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedTake {
+    /// One file, or one file per channel in multi-track mode
+    pub paths: Vec<PathBuf>,
+    /// Recorder clock position of the first frame, including pre-roll
+    pub start_frame: u64,
+    pub num_frames: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskRecorderSettings {
+    pub directory: PathBuf,
+    /// Takes are named `{file_prefix}-{take number}.{extension}`, with a `-track{N}` suffix in
+    /// multi-track mode
+    pub file_prefix: String,
+    pub format: EncodedFormat,
+    /// Write each channel into its own mono file
+    pub multi_track: bool,
+    /// Audio captured while armed, before punching in
+    pub pre_roll: Duration,
+    /// How much audio can be queued for the writer before blocks are dropped
+    pub buffer_duration: Duration,
+}
+
+impl DiskRecorderSettings {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            file_prefix: String::from("take"),
+            format: EncodedFormat::Wav(OutputSampleFormat::Int24),
+            multi_track: false,
+            pre_roll: Duration::from_secs(0),
+            buffer_duration: Duration::from_secs(2),
+        }
+    }
+}
+
+pub struct DiskRecorderHandle {
+    is_armed: AtomicBool,
+    is_recording: AtomicBool,
+    punch_in_frame: AtomicU64,
+    punch_out_frame: AtomicU64,
+    position: AtomicU64,
+    dropped_blocks: AtomicUsize,
+    /// Finished takes, only touched by the writer thread & the UI
+    takes: Mutex<Vec<RecordedTake>>,
+}
+
+impl Default for DiskRecorderHandle {
+    fn default() -> Self {
+        Self {
+            is_armed: AtomicBool::new(false),
+            is_recording: AtomicBool::new(false),
+            punch_in_frame: AtomicU64::new(NO_PUNCH),
+            punch_out_frame: AtomicU64::new(NO_PUNCH),
+            position: AtomicU64::new(0),
+            dropped_blocks: AtomicUsize::new(0),
+            takes: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl DiskRecorderHandle {
+    /// Start sending audio to the writer thread, which captures pre-roll
+    pub fn arm(&self) {
+        self.is_armed.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop capturing, finishing the current take if recording
+    pub fn disarm(&self) {
+        self.is_armed.store(false, Ordering::Relaxed);
+        self.punch_out();
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.is_armed.load(Ordering::Relaxed)
+    }
+
+    /// Start a take on the next block, arming the recorder if needed
+    pub fn punch_in(&self) {
+        self.arm();
+        self.is_recording.store(true, Ordering::Relaxed);
+    }
+
+    /// Finish the current take on the next block
+    pub fn punch_out(&self) {
+        self.is_recording.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.is_recording.load(Ordering::Relaxed)
+    }
+
+    /// Punch in and out at recorder clock positions, in frames. `punch_out` may be `None` to keep
+    /// recording until a manual punch-out.
+    pub fn set_auto_punch(&self, punch_in: u64, punch_out: Option<u64>) {
+        self.punch_out_frame
+            .store(punch_out.unwrap_or(NO_PUNCH), Ordering::Relaxed);
+        self.punch_in_frame.store(punch_in, Ordering::Relaxed);
+    }
+
+    pub fn clear_auto_punch(&self) {
+        self.punch_in_frame.store(NO_PUNCH, Ordering::Relaxed);
+        self.punch_out_frame.store(NO_PUNCH, Ordering::Relaxed);
+    }
+
+    /// Frames processed since the recorder was prepared
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    /// Blocks which couldn't be queued for the writer, because it fell behind
+    pub fn dropped_blocks(&self) -> usize {
+        self.dropped_blocks.load(Ordering::Relaxed)
+    }
+
+    /// Takes which have been finished and written to disk
+    pub fn takes(&self) -> Vec<RecordedTake> {
+        self.takes
+            .lock()
+            .map(|takes| takes.clone())
+            .unwrap_or_default()
+    }
+}
+
+struct WriterThread {
+    is_closed: Arc<AtomicBool>,
+    join_handle: JoinHandle<()>,
+}
+
+impl WriterThread {
+    /// Stop the writer thread after it writes everything queued so far
+    fn close(self) {
+        self.is_closed.store(true, Ordering::Relaxed);
+        self.join_handle.thread().unpark();
+        if self.join_handle.join().is_err() {
+            log::error!("Disk recorder writer thread panicked");
+        }
+    }
+}
+
+/// Pass-through processor that records its input to disk. See the [module docs](self).
+pub struct DiskRecorderProcessor {
+    handle: Shared<DiskRecorderHandle>,
+    settings: DiskRecorderSettings,
+    queue: Option<Arc<Queue<RecordedBlock>>>,
+    pool: Option<Arc<Queue<RecordedBlock>>>,
+    writer: Option<WriterThread>,
+    num_channels: usize,
+    block_frames: usize,
+    was_recording: bool,
+}
+
+impl DiskRecorderProcessor {
+    pub fn new(handle: &Handle, settings: DiskRecorderSettings) -> Self {
+        Self {
+            handle: Shared::new(handle, DiskRecorderHandle::default()),
+            settings,
+            queue: None,
+            pool: None,
+            writer: None,
+            num_channels: 0,
+            block_frames: 0,
+            was_recording: false,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<DiskRecorderHandle> {
+        &self.handle
+    }
+
+    pub fn settings(&self) -> &DiskRecorderSettings {
+        &self.settings
+    }
+
+    /// Finish the current take and wait for the writer thread to write everything queued
+    pub fn close(&mut self) {
+        self.handle.is_recording.store(false, Ordering::Relaxed);
+        if let Some(writer) = self.writer.take() {
+            writer.close();
+        }
+        self.queue = None;
+        self.pool = None;
+        self.was_recording = false;
+    }
+
+    fn start_writer(&mut self, context: &AudioContext) {
+        let settings = context.settings;
+        self.num_channels = settings.output_channels();
+        self.block_frames = settings.block_size().max(1);
+
+        let num_blocks = ((self.settings.buffer_duration.as_secs_f32() * settings.sample_rate())
+            as usize
+            / self.block_frames)
+            .max(2);
+        let pool = Arc::new(Queue::new(num_blocks));
+        for _ in 0..num_blocks {
+            pool.push(RecordedBlock {
+                frame: 0,
+                num_frames: 0,
+                is_recording: false,
+                samples: vec![0.0; self.block_frames * self.num_channels],
+            });
+        }
+        // Blocks only ever come from the pool, so pushes can't fail & drop a block on the audio
+        // thread
+        let queue = Arc::new(Queue::new(num_blocks + 1));
+
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let join_handle = std::thread::Builder::new()
+            .name(String::from("disk-recorder-writer"))
+            .spawn({
+                let is_closed = is_closed.clone();
+                let queue = queue.clone();
+                let pool = pool.clone();
+                // Encoders aren't `Send`, so the writer is created on its own thread
+                let handle = self.handle.clone();
+                let recorder_settings = self.settings.clone();
+                let sample_rate = settings.sample_rate();
+                let num_channels = self.num_channels;
+                move || {
+                    let mut writer =
+                        RecorderWriter::new(handle, recorder_settings, sample_rate, num_channels);
+                    loop {
+                        // Read the flag before draining, so blocks queued before closing are written
+                        let should_close = is_closed.load(Ordering::Relaxed);
+                        let mut num_blocks = 0;
+                        while let Some(block) = queue.pop() {
+                            writer.write_block(&block);
+                            pool.push(block);
+                            num_blocks += 1;
+                        }
+
+                        if should_close {
+                            writer.finish_take();
+                            break;
+                        }
+                        if num_blocks == 0 {
+                            writer.idle();
+                            std::thread::park_timeout(WRITER_POLL_INTERVAL);
+                        }
+                    }
+                }
+            });
+
+        match join_handle {
+            Ok(join_handle) => {
+                self.queue = Some(queue);
+                self.pool = Some(pool);
+                self.writer = Some(WriterThread {
+                    is_closed,
+                    join_handle,
+                });
+            }
+            Err(err) => {
+                log::error!("Failed to start disk recorder writer thread: {}", err);
+            }
+        }
+    }
+
+    /// Queue `frames` of `buffer`, counting a dropped block if there's no free block
+    fn send(
+        &self,
+        buffer: &AudioBuffer<f32>,
+        start: usize,
+        end: usize,
+        frame: u64,
+        is_recording: bool,
+    ) {
+        let (queue, pool) = match (self.queue.as_ref(), self.pool.as_ref()) {
+            (Some(queue), Some(pool)) => (queue, pool),
+            _ => return,
+        };
+        let mut block = match pool.pop() {
+            Some(block) => block,
+            None => {
+                self.handle.dropped_blocks.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+
+        let num_channels = self.num_channels;
+        block.frame = frame;
+        block.num_frames = end - start;
+        block.is_recording = is_recording;
+        for (index, sample_num) in (start..end).enumerate() {
+            for channel_num in 0..num_channels {
+                block.samples[index * num_channels + channel_num] =
+                    if channel_num < buffer.num_channels() {
+                        *buffer.get(channel_num, sample_num)
+                    } else {
+                        0.0
+                    };
+            }
+        }
+        queue.push(block);
+    }
+
+    /// Apply the auto-punch positions which are due at `frame`, returns the next position where
+    /// the recording state might change
+    fn apply_auto_punch(&self, frame: u64) -> u64 {
+        let punch_in = self.handle.punch_in_frame.load(Ordering::Relaxed);
+        if punch_in != NO_PUNCH && punch_in <= frame {
+            self.handle
+                .punch_in_frame
+                .store(NO_PUNCH, Ordering::Relaxed);
+            self.handle.punch_in();
+        }
+
+        let punch_out = self.handle.punch_out_frame.load(Ordering::Relaxed);
+        if self.handle.is_recording() && punch_out != NO_PUNCH && punch_out <= frame {
+            self.handle
+                .punch_out_frame
+                .store(NO_PUNCH, Ordering::Relaxed);
+            self.handle.punch_out();
+        }
+
+        let punch_in = self.handle.punch_in_frame.load(Ordering::Relaxed);
+        let punch_out = self.handle.punch_out_frame.load(Ordering::Relaxed);
+        punch_in.min(punch_out)
+    }
+}
+
+impl AudioProcessor for DiskRecorderProcessor {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        self.close();
+        self.handle.position.store(0, Ordering::Relaxed);
+        self.start_writer(context);
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<Self::SampleType>) {
+        if self.writer.is_none() {
+            return;
+        }
+
+        let position = self.handle.position();
+        let num_samples = buffer.num_samples();
+
+        let mut offset = 0;
+        while offset < num_samples {
+            let frame = position + offset as u64;
+            let next_punch = self.apply_auto_punch(frame);
+            let is_recording = self.handle.is_recording();
+
+            let mut end = (offset + self.block_frames).min(num_samples);
+            if next_punch > frame && next_punch < position + end as u64 {
+                end = (next_punch - position) as usize;
+            }
+
+            // Send a last block after punching out, so the writer finishes the take even if the
+            // recorder was disarmed
+            if self.handle.is_armed() || is_recording || self.was_recording {
+                self.send(buffer, offset, end, frame, is_recording);
+            }
+            self.was_recording = is_recording;
+            offset = end;
+        }
+
+        self.handle
+            .position
+            .store(position + num_samples as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for DiskRecorderProcessor {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use assert_no_alloc::assert_no_alloc;
+
+    use audio_garbage_collector::GarbageCollector;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    fn value(frame: u64, channel: usize) -> f32 {
+        ((frame % 1000) as f32 / 1000.0) * if channel == 0 { 1.0 } else { -1.0 }
+    }
+
+    fn setup(
+        garbage_collector: &GarbageCollector,
+        settings: DiskRecorderSettings,
+    ) -> (DiskRecorderProcessor, AudioContext) {
+        wisual_logger::init_from_env();
+        let mut processor = DiskRecorderProcessor::new(garbage_collector.handle(), settings);
+        let mut context = AudioContext::from(AudioProcessorSettings::default());
+        processor.prepare(&mut context);
+        (processor, context)
+    }
+
+    fn float_settings(directory: &Path) -> DiskRecorderSettings {
+        DiskRecorderSettings {
+            format: EncodedFormat::Wav(OutputSampleFormat::Float32),
+            ..DiskRecorderSettings::new(directory)
+        }
+    }
+
+    /// Process `num_frames` of the test signal in blocks of `block_size`
+    fn process(
+        processor: &mut DiskRecorderProcessor,
+        context: &mut AudioContext,
+        num_frames: usize,
+        block_size: usize,
+    ) {
+        let mut buffer = AudioBuffer::empty();
+        let mut processed = 0;
+        while processed < num_frames {
+            let position = processor.handle().position();
+            let frames = block_size.min(num_frames - processed);
+            buffer.resize(2, frames);
+            for channel in 0..2 {
+                for (index, sample) in buffer.channel_mut(channel).iter_mut().enumerate() {
+                    *sample = value(position + index as u64, channel);
+                }
+            }
+            processor.process(context, &mut buffer);
+            // Audio is passed through
+            assert_eq!(
+                *buffer.get(0, frames - 1),
+                value(position + frames as u64 - 1, 0)
+            );
+            processed += frames;
+        }
+    }
+
+    fn read_file(path: &Path) -> Vec<f32> {
+        hound::WavReader::open(path)
+            .unwrap()
+            .samples::<f32>()
+            .map(|sample| sample.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_punch_in_and_out() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(&garbage_collector, float_settings(dir.path()));
+
+        process(&mut processor, &mut context, 1024, 512);
+        processor.handle().punch_in();
+        process(&mut processor, &mut context, 5120, 512);
+        processor.handle().punch_out();
+        process(&mut processor, &mut context, 1024, 512);
+        processor.close();
+
+        let takes = processor.handle().takes();
+        assert_eq!(takes.len(), 1);
+        assert_eq!(takes[0].start_frame, 1024);
+        assert_eq!(takes[0].num_frames, 5120);
+        assert_eq!(takes[0].paths, vec![dir.path().join("take-001.wav")]);
+        assert_eq!(processor.handle().dropped_blocks(), 0);
+
+        let samples = read_file(&takes[0].paths[0]);
+        assert_eq!(samples.len(), 5120 * 2);
+        for (index, frame) in samples.chunks(2).enumerate() {
+            assert_eq!(frame[0], value(1024 + index as u64, 0));
+            assert_eq!(frame[1], value(1024 + index as u64, 1));
+        }
+    }
+
+    #[test]
+    fn test_takes_are_numbered() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(&garbage_collector, float_settings(dir.path()));
+
+        for _ in 0..2 {
+            processor.handle().punch_in();
+            process(&mut processor, &mut context, 1024, 512);
+            processor.handle().punch_out();
+            process(&mut processor, &mut context, 512, 512);
+            // Let the writer finish the take before the next one
+            while processor.handle().takes().is_empty() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        processor.close();
+
+        let takes = processor.handle().takes();
+        assert_eq!(takes.len(), 2);
+        assert_eq!(takes[1].paths, vec![dir.path().join("take-002.wav")]);
+        assert_eq!(takes[1].start_frame, 1536);
+    }
+
+    #[test]
+    fn test_pre_roll_is_written_at_the_start_of_the_take() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(
+            &garbage_collector,
+            DiskRecorderSettings {
+                pre_roll: Duration::from_secs(1),
+                buffer_duration: Duration::from_secs(10),
+                ..float_settings(dir.path())
+            },
+        );
+
+        processor.handle().arm();
+        process(&mut processor, &mut context, 51200, 512);
+        processor.handle().punch_in();
+        process(&mut processor, &mut context, 2048, 512);
+        processor.handle().disarm();
+        process(&mut processor, &mut context, 512, 512);
+        processor.close();
+
+        let takes = processor.handle().takes();
+        assert_eq!(takes.len(), 1);
+        assert_eq!(takes[0].start_frame, 51200 - 44100);
+        assert_eq!(takes[0].num_frames, 44100 + 2048);
+        let samples = read_file(&takes[0].paths[0]);
+        assert_eq!(samples[0], value(51200 - 44100, 0));
+        assert_eq!(samples[44100 * 2], value(51200, 0));
+    }
+
+    #[test]
+    fn test_auto_punch_is_sample_accurate() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(&garbage_collector, float_settings(dir.path()));
+
+        processor.handle().set_auto_punch(1000, Some(3000));
+        process(&mut processor, &mut context, 5120, 512);
+        processor.close();
+
+        let takes = processor.handle().takes();
+        assert_eq!(takes.len(), 1);
+        assert_eq!(takes[0].start_frame, 1000);
+        assert_eq!(takes[0].num_frames, 2000);
+        let samples = read_file(&takes[0].paths[0]);
+        assert_eq!(samples[0], value(1000, 0));
+        assert_eq!(samples[samples.len() - 2], value(2999, 0));
+    }
+
+    #[test]
+    fn test_multi_track_writes_a_file_per_channel() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(
+            &garbage_collector,
+            DiskRecorderSettings {
+                multi_track: true,
+                ..float_settings(dir.path())
+            },
+        );
+
+        processor.handle().punch_in();
+        process(&mut processor, &mut context, 2048, 512);
+        processor.close();
+
+        let takes = processor.handle().takes();
+        assert_eq!(
+            takes[0].paths,
+            vec![
+                dir.path().join("take-001-track1.wav"),
+                dir.path().join("take-001-track2.wav")
+            ]
+        );
+        for (channel, path) in takes[0].paths.iter().enumerate() {
+            let samples = read_file(path);
+            assert_eq!(samples.len(), 2048);
+            for (index, sample) in samples.iter().enumerate() {
+                assert_eq!(*sample, value(index as u64, channel));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dropped_blocks_are_reported_and_filled_with_silence() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(
+            &garbage_collector,
+            DiskRecorderSettings {
+                buffer_duration: Duration::from_millis(1),
+                ..float_settings(dir.path())
+            },
+        );
+
+        processor.handle().punch_in();
+        // Far more than the queue can hold, in one go
+        process(&mut processor, &mut context, 512 * 100, 512 * 100);
+        std::thread::sleep(Duration::from_millis(50));
+        process(&mut processor, &mut context, 512, 512);
+        processor.close();
+
+        assert!(processor.handle().dropped_blocks() > 0);
+        let takes = processor.handle().takes();
+        assert_eq!(takes[0].num_frames, 512 * 101);
+        let samples = read_file(&takes[0].paths[0]);
+        assert_eq!(samples.len(), 512 * 101 * 2);
+        assert_eq!(samples[512 * 100 * 2], value(512 * 100, 0));
+    }
+
+    #[test]
+    fn test_nothing_is_written_when_not_armed() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(&garbage_collector, float_settings(dir.path()));
+
+        process(&mut processor, &mut context, 5120, 512);
+        processor.close();
+
+        assert!(processor.handle().takes().is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_process_does_not_allocate() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("disk_recorder").unwrap();
+        let (mut processor, mut context) = setup(&garbage_collector, float_settings(dir.path()));
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 512);
+
+        processor.handle().punch_in();
+        assert_no_alloc(|| {
+            for _ in 0..10 {
+                processor.process(&mut context, &mut buffer);
+            }
+        });
+        processor.close();
+        assert_eq!(processor.handle().takes()[0].num_frames, 5120);
+    }
+}
@@ -0,0 +1,282 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Writer thread side of the disk recorder.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use audio_garbage_collector::Shared;
+use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
+
+use crate::encoder::{create_encoder, AudioFileEncoder, EncoderError};
+
+use super::{DiskRecorderHandle, DiskRecorderSettings, RecordedBlock, RecordedTake};
+
+/// Frames of silence written at once when filling in dropped blocks
+const GAP_CHUNK_FRAMES: usize = 1024;
+
+struct Take {
+    encoders: Vec<Box<dyn AudioFileEncoder>>,
+    paths: Vec<PathBuf>,
+    start_frame: u64,
+    next_frame: u64,
+    num_frames: u64,
+    is_failed: bool,
+}
+
+pub(crate) struct RecorderWriter {
+    handle: Shared<DiskRecorderHandle>,
+    settings: DiskRecorderSettings,
+    sample_rate: f32,
+    num_channels: usize,
+    /// Interleaved audio received while armed but not recording
+    pre_roll: VecDeque<f32>,
+    pre_roll_capacity: usize,
+    /// Recorder clock position after the last pre-roll frame
+    pre_roll_end: u64,
+    take: Option<Take>,
+    scratch: AudioBuffer<f32>,
+}
+
+impl RecorderWriter {
+    pub(crate) fn new(
+        handle: Shared<DiskRecorderHandle>,
+        settings: DiskRecorderSettings,
+        sample_rate: f32,
+        num_channels: usize,
+    ) -> Self {
+        let pre_roll_frames = (settings.pre_roll.as_secs_f32() * sample_rate) as usize;
+        let pre_roll_capacity = pre_roll_frames * num_channels;
+        Self {
+            handle,
+            settings,
+            sample_rate,
+            num_channels,
+            pre_roll: VecDeque::with_capacity(pre_roll_capacity),
+            pre_roll_capacity,
+            pre_roll_end: 0,
+            take: None,
+            scratch: AudioBuffer::empty(),
+        }
+    }
+
+    pub(crate) fn write_block(&mut self, block: &RecordedBlock) {
+        let samples = &block.samples[..block.num_frames * self.num_channels];
+        if !block.is_recording {
+            self.finish_take();
+            self.push_pre_roll(block.frame, samples);
+            return;
+        }
+
+        if self.take.is_none() {
+            self.start_take(block.frame);
+        }
+        if let Some(take) = self.take.as_mut() {
+            if take.next_frame < block.frame {
+                // Dropped blocks are filled with silence, so multi-track takes stay aligned
+                let mut gap = (block.frame - take.next_frame) as usize;
+                let silence = vec![0.0; GAP_CHUNK_FRAMES * self.num_channels];
+                while gap > 0 {
+                    let frames = gap.min(GAP_CHUNK_FRAMES);
+                    self.encode(&silence[..frames * self.num_channels]);
+                    gap -= frames;
+                }
+            }
+        }
+        self.encode(samples);
+        if let Some(take) = self.take.as_mut() {
+            take.next_frame = block.frame + block.num_frames as u64;
+        }
+    }
+
+    /// Called when there's nothing queued. Finishes the take if the block marking the punch-out
+    /// was dropped.
+    pub(crate) fn idle(&mut self) {
+        if !self.handle.is_recording() {
+            self.finish_take();
+        }
+    }
+
+    pub(crate) fn finish_take(&mut self) {
+        let mut take = match self.take.take() {
+            Some(take) => take,
+            None => return,
+        };
+
+        for encoder in &mut take.encoders {
+            if let Err(err) = encoder.finalize() {
+                log::error!("Failed to finalize recording {:?}: {}", take.paths, err);
+            }
+        }
+        log::info!(
+            "Recorded take {:?} with {} frames",
+            take.paths,
+            take.num_frames
+        );
+        if let Ok(mut takes) = self.handle.takes.lock() {
+            takes.push(RecordedTake {
+                paths: take.paths,
+                start_frame: take.start_frame,
+                num_frames: take.num_frames,
+            });
+        }
+    }
+
+    fn push_pre_roll(&mut self, frame: u64, samples: &[f32]) {
+        if self.pre_roll_capacity == 0 {
+            return;
+        }
+        if frame != self.pre_roll_end {
+            self.pre_roll.clear();
+        }
+        self.pre_roll.extend(samples);
+        while self.pre_roll.len() > self.pre_roll_capacity {
+            self.pre_roll.pop_front();
+        }
+        self.pre_roll_end = frame + (samples.len() / self.num_channels) as u64;
+    }
+
+    fn start_take(&mut self, frame: u64) {
+        let pre_roll: Vec<f32> = if self.pre_roll_end == frame {
+            self.pre_roll.drain(..).collect()
+        } else {
+            self.pre_roll.clear();
+            vec![]
+        };
+        let pre_roll_frames = (pre_roll.len() / self.num_channels) as u64;
+
+        let paths = self.next_take_paths();
+        let encoders = match self.create_encoders(&paths) {
+            Ok(encoders) => encoders,
+            Err(err) => {
+                log::error!("Failed to create recording {:?}: {}", paths, err);
+                vec![]
+            }
+        };
+        self.take = Some(Take {
+            is_failed: encoders.is_empty(),
+            encoders,
+            paths,
+            start_frame: frame - pre_roll_frames,
+            next_frame: frame,
+            num_frames: 0,
+        });
+
+        if !pre_roll.is_empty() {
+            self.encode(&pre_roll);
+        }
+    }
+
+    fn create_encoders(
+        &self,
+        paths: &[PathBuf],
+    ) -> Result<Vec<Box<dyn AudioFileEncoder>>, EncoderError> {
+        std::fs::create_dir_all(&self.settings.directory)?;
+        let channels_per_file = if self.settings.multi_track {
+            1
+        } else {
+            self.num_channels
+        };
+        let settings = AudioProcessorSettings {
+            sample_rate: self.sample_rate,
+            input_channels: channels_per_file,
+            output_channels: channels_per_file,
+            ..AudioProcessorSettings::default()
+        };
+        paths
+            .iter()
+            .map(|path| create_encoder(path, self.settings.format, settings))
+            .collect()
+    }
+
+    /// Paths for the next take, skipping take numbers which already have files
+    fn next_take_paths(&self) -> Vec<PathBuf> {
+        let extension = self.settings.format.extension();
+        let mut take_number = self.handle.takes().len() + 1;
+        loop {
+            let paths: Vec<PathBuf> = if self.settings.multi_track {
+                (0..self.num_channels)
+                    .map(|channel| {
+                        self.settings.directory.join(format!(
+                            "{}-{:03}-track{}.{}",
+                            self.settings.file_prefix,
+                            take_number,
+                            channel + 1,
+                            extension
+                        ))
+                    })
+                    .collect()
+            } else {
+                vec![self.settings.directory.join(format!(
+                    "{}-{:03}.{}",
+                    self.settings.file_prefix, take_number, extension
+                ))]
+            };
+
+            if paths.iter().all(|path| !path.exists()) {
+                return paths;
+            }
+            take_number += 1;
+        }
+    }
+
+    /// Encode interleaved samples into the current take
+    fn encode(&mut self, samples: &[f32]) {
+        let take = match self.take.as_mut() {
+            Some(take) if !take.is_failed => take,
+            _ => return,
+        };
+
+        let num_frames = samples.len() / self.num_channels;
+        let result = if self.settings.multi_track {
+            self.scratch.resize(1, num_frames);
+            take.encoders
+                .iter_mut()
+                .enumerate()
+                .try_for_each(|(channel, encoder)| {
+                    for (frame, sample) in self.scratch.channel_mut(0).iter_mut().enumerate() {
+                        *sample = samples[frame * self.num_channels + channel];
+                    }
+                    encoder.encode(&self.scratch)
+                })
+        } else {
+            self.scratch.resize(self.num_channels, num_frames);
+            for (frame, chunk) in samples.chunks(self.num_channels).enumerate() {
+                for (channel, sample) in chunk.iter().enumerate() {
+                    self.scratch.set(channel, frame, *sample);
+                }
+            }
+            take.encoders
+                .iter_mut()
+                .try_for_each(|encoder| encoder.encode(&self.scratch))
+        };
+
+        match result {
+            Ok(()) => take.num_frames += num_frames as u64,
+            Err(err) => {
+                log::error!("Failed to write recording {:?}: {}", take.paths, err);
+                take.is_failed = true;
+            }
+        }
+    }
+}
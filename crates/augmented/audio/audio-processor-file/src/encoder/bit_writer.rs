@@ -0,0 +1,146 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+/// MSB-first bit writer for the FLAC bitstream.
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    num_bits: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+            accumulator: 0,
+            num_bits: 0,
+        }
+    }
+
+    /// Write the low `bits` bits of `value`, `bits` must be at most 32
+    #[inline]
+    pub(crate) fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.num_bits += bits;
+        while self.num_bits >= 8 {
+            self.num_bits -= 8;
+            self.bytes.push((self.accumulator >> self.num_bits) as u8);
+        }
+        self.accumulator &= (1 << self.num_bits) - 1;
+    }
+
+    /// Write a two's complement signed value in `bits` bits
+    #[inline]
+    pub(crate) fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `zeros` zero bits followed by a one
+    #[inline]
+    pub(crate) fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Write a Rice coded residual with parameter `k`
+    #[inline]
+    pub(crate) fn write_rice(&mut self, value: i64, k: u32) {
+        let folded = fold(value);
+        self.write_unary(folded >> k);
+        self.write(folded, k);
+    }
+
+    /// Pad with zeros up to the next byte boundary
+    pub(crate) fn align(&mut self) {
+        if self.num_bits > 0 {
+            self.write(0, 8 - self.num_bits);
+        }
+    }
+
+    pub(crate) fn is_aligned(&self) -> bool {
+        self.num_bits == 0
+    }
+
+    /// Bytes written so far, excluding bits before the next byte boundary
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// Map signed residuals onto unsigned values, `0, -1, 1, -2, ...` onto `0, 1, 2, 3, ...`
+#[inline]
+pub(crate) fn fold(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_bits() {
+        let mut writer = BitWriter::with_capacity(4);
+        writer.write(0b101, 3);
+        writer.write(0b11111, 5);
+        writer.write(0xABCD, 16);
+        writer.write_signed(-1, 4);
+        assert_eq!(
+            writer.into_bytes(),
+            vec![0b1011_1111, 0xAB, 0xCD, 0b1111_0000]
+        );
+    }
+
+    #[test]
+    fn test_write_rice() {
+        let mut writer = BitWriter::with_capacity(4);
+        // -3 folds into 5, with k = 1 that's 2 zeros, a one and then bit 1
+        writer.write_rice(-3, 1);
+        assert_eq!(writer.into_bytes(), vec![0b0011_0000]);
+    }
+
+    #[test]
+    fn test_write_long_unary() {
+        let mut writer = BitWriter::with_capacity(8);
+        writer.write_unary(40);
+        assert_eq!(writer.into_bytes(), vec![0, 0, 0, 0, 0, 0b1000_0000]);
+    }
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold(0), 0);
+        assert_eq!(fold(-1), 1);
+        assert_eq!(fold(1), 2);
+        assert_eq!(fold(-2), 3);
+    }
+}
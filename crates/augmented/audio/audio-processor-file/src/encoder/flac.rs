@@ -0,0 +1,703 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Native FLAC encoder.
+//!
+//! Frames use a fixed block size, fixed (polynomial) predictors of order 0 to 4 and partitioned
+//! Rice coding of the residual. Stereo input picks the cheapest of left/right, left/side,
+//! side/right and mid/side for each frame.
+
+use std::io::{Seek, SeekFrom, Write};
+
+use audio_processor_traits::AudioBuffer;
+
+use super::bit_writer::{fold, BitWriter};
+use super::{AudioFileEncoder, EncoderError};
+use crate::output_file_processor::dither::Quantizer;
+use crate::DitherMode;
+
+/// Frames per FLAC frame, except for the last one
+pub const FLAC_BLOCK_SIZE: usize = 4096;
+const MAX_CHANNELS: usize = 8;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+const MAX_RICE_PARAMETER: u32 = 14;
+/// `fLaC` marker and the STREAMINFO metadata block header
+const STREAMINFO_OFFSET: u64 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FlacEncoderSettings {
+    pub sample_rate: u32,
+    pub num_channels: usize,
+    /// Either 16 or 24
+    pub bits_per_sample: u16,
+    pub dither: DitherMode,
+}
+
+impl Default for FlacEncoderSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            num_channels: 2,
+            bits_per_sample: 24,
+            dither: DitherMode::default(),
+        }
+    }
+}
+
+/// Stereo decorrelation mode, the values are the frame header channel assignments
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChannelAssignment {
+    Independent,
+    LeftSide = 0b1000,
+    SideRight = 0b1001,
+    MidSide = 0b1010,
+}
+
+pub struct FlacEncoder<W: Write + Seek> {
+    inner: W,
+    settings: FlacEncoderSettings,
+    quantizer: Quantizer,
+    /// Quantized samples of the frame being filled, per channel
+    block: Vec<Vec<i64>>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    md5: md5::Context,
+    is_finalized: bool,
+}
+
+impl<W: Write + Seek> FlacEncoder<W> {
+    pub fn new(mut inner: W, settings: FlacEncoderSettings) -> Result<Self, EncoderError> {
+        if settings.bits_per_sample != 16 && settings.bits_per_sample != 24 {
+            return Err(EncoderError::UnsupportedSettings(format!(
+                "FLAC bit depth must be 16 or 24, got {}",
+                settings.bits_per_sample
+            )));
+        }
+        if settings.num_channels == 0 || settings.num_channels > MAX_CHANNELS {
+            return Err(EncoderError::UnsupportedSettings(format!(
+                "FLAC supports 1 to {} channels, got {}",
+                MAX_CHANNELS, settings.num_channels
+            )));
+        }
+        if settings.sample_rate == 0 || settings.sample_rate >= 1 << 20 {
+            return Err(EncoderError::UnsupportedSettings(format!(
+                "Invalid FLAC sample rate {}",
+                settings.sample_rate
+            )));
+        }
+
+        inner.write_all(b"fLaC")?;
+        // Last metadata block, type 0 (STREAMINFO), 34 bytes
+        inner.write_all(&[0x80, 0, 0, 34])?;
+        let mut encoder = Self {
+            inner,
+            settings,
+            quantizer: Quantizer::new(
+                settings.bits_per_sample,
+                settings.dither,
+                settings.num_channels,
+            ),
+            block: vec![Vec::with_capacity(FLAC_BLOCK_SIZE); settings.num_channels],
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            md5: md5::Context::new(),
+            is_finalized: false,
+        };
+        let streaminfo = encoder.streaminfo();
+        encoder.inner.write_all(&streaminfo)?;
+        Ok(encoder)
+    }
+
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut writer = BitWriter::with_capacity(34);
+        writer.write(FLAC_BLOCK_SIZE as u64, 16);
+        writer.write(FLAC_BLOCK_SIZE as u64, 16);
+        if self.max_frame_size == 0 {
+            // Unknown
+            writer.write(0, 24);
+            writer.write(0, 24);
+        } else {
+            writer.write(self.min_frame_size as u64, 24);
+            writer.write(self.max_frame_size as u64, 24);
+        }
+        writer.write(self.settings.sample_rate as u64, 20);
+        writer.write(self.settings.num_channels as u64 - 1, 3);
+        writer.write(self.settings.bits_per_sample as u64 - 1, 5);
+        writer.write(self.total_samples >> 32, 4);
+        writer.write(self.total_samples, 32);
+        let mut bytes = writer.into_bytes();
+        if self.is_finalized {
+            bytes.extend_from_slice(&self.md5.clone().compute().0);
+        } else {
+            bytes.extend_from_slice(&[0; 16]);
+        }
+        bytes
+    }
+
+    fn write_frame(&mut self) -> Result<(), EncoderError> {
+        let block_size = self.block[0].len();
+        if block_size == 0 {
+            return Ok(());
+        }
+
+        let bytes_per_sample = self.settings.bits_per_sample as usize / 8;
+        let mut signature = Vec::with_capacity(block_size * self.block.len() * bytes_per_sample);
+        for frame in 0..block_size {
+            for channel in &self.block {
+                signature.extend_from_slice(&channel[frame].to_le_bytes()[..bytes_per_sample]);
+            }
+        }
+        self.md5.consume(&signature);
+
+        let bps = self.settings.bits_per_sample as u32;
+        let assignment = if self.block.len() == 2 {
+            choose_channel_assignment(&self.block[0], &self.block[1])
+        } else {
+            ChannelAssignment::Independent
+        };
+
+        let mut writer = BitWriter::with_capacity(block_size * self.block.len() * 3);
+        self.write_frame_header(&mut writer, block_size, assignment);
+
+        match assignment {
+            ChannelAssignment::Independent => {
+                for channel in &self.block {
+                    write_subframe(&mut writer, channel, bps);
+                }
+            }
+            _ => {
+                let (left, right) = (&self.block[0], &self.block[1]);
+                let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+                match assignment {
+                    ChannelAssignment::LeftSide => {
+                        write_subframe(&mut writer, left, bps);
+                        write_subframe(&mut writer, &side, bps + 1);
+                    }
+                    ChannelAssignment::SideRight => {
+                        write_subframe(&mut writer, &side, bps + 1);
+                        write_subframe(&mut writer, right, bps);
+                    }
+                    _ => {
+                        let mid: Vec<i64> =
+                            left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+                        write_subframe(&mut writer, &mid, bps);
+                        write_subframe(&mut writer, &side, bps + 1);
+                    }
+                }
+            }
+        }
+
+        writer.align();
+        let crc = crc16(writer.bytes());
+        writer.write(crc as u64, 16);
+        let frame = writer.into_bytes();
+        self.inner.write_all(&frame)?;
+
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        self.frame_number += 1;
+        self.total_samples += block_size as u64;
+        for channel in &mut self.block {
+            channel.clear();
+        }
+        Ok(())
+    }
+
+    fn write_frame_header(
+        &self,
+        writer: &mut BitWriter,
+        block_size: usize,
+        assignment: ChannelAssignment,
+    ) {
+        // Sync code, reserved bit and fixed block size strategy
+        writer.write(0b1111_1111_1111_1000, 16);
+
+        let (block_size_code, block_size_bits) = if block_size == FLAC_BLOCK_SIZE {
+            (0b1100, 0)
+        } else if block_size <= 256 {
+            (0b0110, 8)
+        } else {
+            (0b0111, 16)
+        };
+        writer.write(block_size_code, 4);
+
+        let sample_rate = self.settings.sample_rate;
+        let (sample_rate_code, sample_rate_bits, sample_rate_value) = match sample_rate {
+            88200 => (0b0001, 0, 0),
+            176400 => (0b0010, 0, 0),
+            192000 => (0b0011, 0, 0),
+            8000 => (0b0100, 0, 0),
+            16000 => (0b0101, 0, 0),
+            22050 => (0b0110, 0, 0),
+            24000 => (0b0111, 0, 0),
+            32000 => (0b1000, 0, 0),
+            44100 => (0b1001, 0, 0),
+            48000 => (0b1010, 0, 0),
+            96000 => (0b1011, 0, 0),
+            rate if rate % 1000 == 0 && rate / 1000 <= 255 => (0b1100, 8, rate / 1000),
+            rate if rate <= 65535 => (0b1101, 16, rate),
+            rate if rate % 10 == 0 && rate / 10 <= 65535 => (0b1110, 16, rate / 10),
+            // Read from STREAMINFO
+            _ => (0b0000, 0, 0),
+        };
+        writer.write(sample_rate_code, 4);
+
+        let channel_assignment = match assignment {
+            ChannelAssignment::Independent => self.block.len() as u64 - 1,
+            assignment => assignment as u64,
+        };
+        writer.write(channel_assignment, 4);
+        let sample_size_code = if self.settings.bits_per_sample == 16 {
+            0b100
+        } else {
+            0b110
+        };
+        writer.write(sample_size_code, 3);
+        writer.write(0, 1);
+
+        write_utf8(writer, self.frame_number);
+        writer.write(block_size as u64 - 1, block_size_bits);
+        writer.write(sample_rate_value as u64, sample_rate_bits);
+
+        debug_assert!(writer.is_aligned());
+        let crc = crc8(writer.bytes());
+        writer.write(crc as u64, 8);
+    }
+}
+
+impl<W: Write + Seek> AudioFileEncoder for FlacEncoder<W> {
+    fn encode(&mut self, buffer: &AudioBuffer<f32>) -> Result<(), EncoderError> {
+        if self.is_finalized {
+            return Ok(());
+        }
+
+        for sample_num in 0..buffer.num_samples() {
+            for channel_num in 0..self.settings.num_channels {
+                let sample = if channel_num < buffer.num_channels() {
+                    *buffer.get(channel_num, sample_num)
+                } else {
+                    0.0
+                };
+                let value = self.quantizer.quantize(channel_num, sample);
+                self.block[channel_num].push(value as i64);
+            }
+
+            if self.block[0].len() == FLAC_BLOCK_SIZE {
+                self.write_frame()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), EncoderError> {
+        if self.is_finalized {
+            return Ok(());
+        }
+        self.write_frame()?;
+        self.is_finalized = true;
+
+        let streaminfo = self.streaminfo();
+        self.inner.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.inner.write_all(&streaminfo)?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+fn choose_channel_assignment(left: &[i64], right: &[i64]) -> ChannelAssignment {
+    let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+    let mid: Vec<i64> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+    let left_cost = estimate_cost(left);
+    let right_cost = estimate_cost(right);
+    let side_cost = estimate_cost(&side);
+    let mid_cost = estimate_cost(&mid);
+
+    [
+        (ChannelAssignment::Independent, left_cost + right_cost),
+        (ChannelAssignment::LeftSide, left_cost + side_cost),
+        (ChannelAssignment::SideRight, side_cost + right_cost),
+        (ChannelAssignment::MidSide, mid_cost + side_cost),
+    ]
+    .into_iter()
+    .min_by_key(|(_, cost)| *cost)
+    .map(|(assignment, _)| assignment)
+    .unwrap_or(ChannelAssignment::Independent)
+}
+
+/// Sum of absolute residuals of the best fixed predictor, a proxy for the encoded size
+fn estimate_cost(samples: &[i64]) -> u64 {
+    let (_, residual) = best_fixed_residual(samples);
+    residual.iter().map(|r| r.unsigned_abs()).sum()
+}
+
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    samples
+        .windows(order + 1)
+        .map(|w| match order {
+            0 => w[0],
+            1 => w[1] - w[0],
+            2 => w[2] - 2 * w[1] + w[0],
+            3 => w[3] - 3 * w[2] + 3 * w[1] - w[0],
+            _ => w[4] - 4 * w[3] + 6 * w[2] - 4 * w[1] + w[0],
+        })
+        .collect()
+}
+
+fn best_fixed_residual(samples: &[i64]) -> (usize, Vec<i64>) {
+    let max_order = MAX_FIXED_ORDER.min(samples.len().saturating_sub(1));
+    (0..=max_order)
+        .map(|order| (order, fixed_residual(samples, order)))
+        .min_by_key(|(_, residual)| residual.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap_or_else(|| (0, samples.to_vec()))
+}
+
+/// Returns the Rice parameter and size in bits for a partition
+fn best_rice_parameter(residual: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| {
+            let bits = residual
+                .iter()
+                .map(|r| (fold(*r) >> k) + 1 + k as u64)
+                .sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap_or((MAX_RICE_PARAMETER, u64::MAX))
+}
+
+/// Picks the partition order with the smallest size, returns it with each partition's Rice
+/// parameter
+fn best_partitioning(residual: &[i64], block_size: usize, order: usize) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let num_partitions = 1 << partition_order;
+        if block_size.trailing_zeros() < partition_order || block_size / num_partitions <= order {
+            break;
+        }
+
+        let partition_size = block_size / num_partitions;
+        let mut parameters = Vec::with_capacity(num_partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for partition in 0..num_partitions {
+            let len = if partition == 0 {
+                partition_size - order
+            } else {
+                partition_size
+            };
+            let (parameter, partition_bits) = best_rice_parameter(&residual[start..start + len]);
+            parameters.push(parameter);
+            bits += 4 + partition_bits;
+            start += len;
+        }
+
+        if best.as_ref().map(|(_, _, b)| bits < *b).unwrap_or(true) {
+            best = Some((partition_order, parameters, bits));
+        }
+    }
+    best.unwrap_or_else(|| {
+        let (parameter, bits) = best_rice_parameter(residual);
+        (0, vec![parameter], 4 + bits)
+    })
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bps: u32) {
+    if samples.iter().all(|s| *s == samples[0]) {
+        // Constant subframe
+        writer.write(0b0000_0000, 8);
+        writer.write_signed(samples[0], bps);
+        return;
+    }
+
+    let (order, residual) = best_fixed_residual(samples);
+    let (partition_order, parameters, residual_bits) =
+        best_partitioning(&residual, samples.len(), order);
+    let fixed_bits = order as u64 * bps as u64 + 2 + 4 + residual_bits;
+    let verbatim_bits = samples.len() as u64 * bps as u64;
+
+    if fixed_bits >= verbatim_bits {
+        writer.write(0b0000_0010, 8);
+        for sample in samples {
+            writer.write_signed(*sample, bps);
+        }
+        return;
+    }
+
+    writer.write(0b0001_0000 | (order as u64) << 1, 8);
+    for sample in &samples[..order] {
+        writer.write_signed(*sample, bps);
+    }
+    // Rice coding with 4 bit parameters
+    writer.write(0b00, 2);
+    writer.write(partition_order as u64, 4);
+    let partition_size = samples.len() >> partition_order;
+    let mut start = 0;
+    for (partition, parameter) in parameters.iter().enumerate() {
+        let len = if partition == 0 {
+            partition_size - order
+        } else {
+            partition_size
+        };
+        writer.write(*parameter as u64, 4);
+        for r in &residual[start..start + len] {
+            writer.write_rice(*r, *parameter);
+        }
+        start += len;
+    }
+}
+
+/// Frame numbers are written with the UTF-8 variable length scheme, extended up to 36 bits
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+
+    let num_bytes = match value {
+        v if v < 0x800 => 2,
+        v if v < 0x10000 => 3,
+        v if v < 0x200000 => 4,
+        v if v < 0x4000000 => 5,
+        v if v < 0x80000000 => 6,
+        _ => 7,
+    };
+    let prefix = (0xFF00_u64 >> num_bytes) & 0xFF;
+    writer.write(prefix | (value >> (6 * (num_bytes - 1))), 8);
+    for index in (0..num_bytes - 1).rev() {
+        writer.write(0x80 | ((value >> (6 * index)) & 0x3F), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0_u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use symphonia::core::audio::Signal;
+
+    use crate::file_io;
+
+    use super::*;
+
+    /// A sine with some noise, as integers in the given bit depth
+    fn test_signal(num_channels: usize, num_samples: usize, bits: u16) -> Vec<Vec<i64>> {
+        let amplitude = (1 << (bits - 1)) as f64 * 0.6;
+        let mut seed = 1_u32;
+        (0..num_channels)
+            .map(|channel| {
+                (0..num_samples)
+                    .map(|i| {
+                        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                        let noise = (seed >> 24) as f64 / 255.0 - 0.5;
+                        let phase = i as f64 * 440.0 * (channel + 1) as f64 / 44100.0;
+                        let value = (phase * 2.0 * std::f64::consts::PI).sin() * amplitude;
+                        (value + noise * amplitude * 0.01).round() as i64
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn to_buffer(signal: &[Vec<i64>], bits: u16) -> AudioBuffer<f32> {
+        let scale = (1 << (bits - 1)) as f32;
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(signal.len(), signal[0].len());
+        for (channel, samples) in signal.iter().enumerate() {
+            for (index, sample) in samples.iter().enumerate() {
+                buffer.set(channel, index, *sample as f32 / scale);
+            }
+        }
+        buffer
+    }
+
+    fn encode(path: &Path, signal: &[Vec<i64>], bits: u16) {
+        let file = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+        let mut encoder = FlacEncoder::new(
+            file,
+            FlacEncoderSettings {
+                sample_rate: 44100,
+                num_channels: signal.len(),
+                bits_per_sample: bits,
+                dither: DitherMode::None,
+            },
+        )
+        .unwrap();
+        // Odd block sizes, so that FLAC frames straddle input blocks
+        let buffer = to_buffer(signal, bits);
+        let mut start = 0;
+        while start < buffer.num_samples() {
+            let end = (start + 1000).min(buffer.num_samples());
+            let mut block = AudioBuffer::empty();
+            block.resize(buffer.num_channels(), end - start);
+            for channel in 0..buffer.num_channels() {
+                for index in start..end {
+                    block.set(channel, index - start, *buffer.get(channel, index));
+                }
+            }
+            encoder.encode(&block).unwrap();
+            start = end;
+        }
+        encoder.finalize().unwrap();
+    }
+
+    fn assert_round_trip(num_channels: usize, num_samples: usize, bits: u16) {
+        let dir = tempdir::TempDir::new("flac").unwrap();
+        let path = dir.path().join("test.flac");
+        let signal = test_signal(num_channels, num_samples, bits);
+        encode(&path, &signal, bits);
+
+        let mut probe = file_io::default_read_audio_file(path.to_str().unwrap()).unwrap();
+        let decoded = file_io::read_file_contents(&mut probe).unwrap();
+        assert_eq!(decoded.spec().channels.count(), num_channels);
+        assert_eq!(decoded.frames(), num_samples);
+
+        let expected = to_buffer(&signal, bits);
+        for channel in 0..num_channels {
+            for (index, sample) in decoded.chan(channel).iter().enumerate() {
+                assert_eq!(
+                    *sample,
+                    *expected.get(channel, index),
+                    "{} {}",
+                    channel,
+                    index
+                );
+            }
+        }
+
+        let raw_size = num_channels * num_samples * bits as usize / 8;
+        let encoded_size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert!(encoded_size < raw_size);
+    }
+
+    #[test]
+    fn test_round_trip_16_bit_stereo() {
+        assert_round_trip(2, 3 * FLAC_BLOCK_SIZE + 123, 16);
+    }
+
+    #[test]
+    fn test_round_trip_24_bit_mono() {
+        assert_round_trip(1, FLAC_BLOCK_SIZE + 1, 24);
+    }
+
+    #[test]
+    fn test_round_trip_multi_channel() {
+        assert_round_trip(6, 2 * FLAC_BLOCK_SIZE, 24);
+    }
+
+    #[test]
+    fn test_round_trip_silence() {
+        let dir = tempdir::TempDir::new("flac").unwrap();
+        let path = dir.path().join("silence.flac");
+        let signal = vec![vec![0; 5000]; 2];
+        encode(&path, &signal, 16);
+
+        let mut probe = file_io::default_read_audio_file(path.to_str().unwrap()).unwrap();
+        let decoded = file_io::read_file_contents(&mut probe).unwrap();
+        assert_eq!(decoded.frames(), 5000);
+        assert!(decoded.chan(0).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_streaminfo_is_written_on_finalize() {
+        let dir = tempdir::TempDir::new("flac").unwrap();
+        let path = dir.path().join("streaminfo.flac");
+        let signal = test_signal(2, 10000, 16);
+        encode(&path, &signal, 16);
+
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(&contents[0..4], b"fLaC");
+        let streaminfo = &contents[8..42];
+        let total_samples = u64::from_be_bytes([
+            0,
+            0,
+            0,
+            streaminfo[13] & 0x0F,
+            streaminfo[14],
+            streaminfo[15],
+            streaminfo[16],
+            streaminfo[17],
+        ]);
+        assert_eq!(total_samples, 10000);
+
+        let mut signature = vec![];
+        for index in 0..10000 {
+            for channel in &signal {
+                signature.extend_from_slice(&(channel[index] as i16).to_le_bytes());
+            }
+        }
+        assert_eq!(&streaminfo[18..34], &md5::compute(&signature).0);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_bit_depths() {
+        let result = FlacEncoder::new(
+            std::io::Cursor::new(vec![]),
+            FlacEncoderSettings {
+                bits_per_sample: 8,
+                ..FlacEncoderSettings::default()
+            },
+        );
+        assert!(matches!(result, Err(EncoderError::UnsupportedSettings(_))));
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+}
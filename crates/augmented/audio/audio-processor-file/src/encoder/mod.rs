@@ -0,0 +1,202 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! In-process encoders that take [`AudioBuffer`]s and write compressed (or not) files.
+//!
+//! * `wav` goes through [`OutputAudioFileProcessor`]
+//! * `flac` is encoded natively by [`FlacEncoder`]
+//! * `mp3` is encoded by [`Mp3Encoder`] through LAME, behind the `mp3` feature
+//!
+//! Other formats can be added by implementing [`AudioFileEncoder`].
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
+
+pub use self::encoder_error::EncoderError;
+pub use self::flac::{FlacEncoder, FlacEncoderSettings, FLAC_BLOCK_SIZE};
+#[cfg(feature = "mp3")]
+pub use self::mp3::{Mp3Encoder, Mp3EncoderSettings};
+use crate::{DitherMode, OutputAudioFileProcessor, OutputFileSettings, OutputSampleFormat};
+
+mod bit_writer;
+mod encoder_error;
+mod flac;
+#[cfg(feature = "mp3")]
+mod mp3;
+
+/// Streaming encoder, blocks are written as they're passed in.
+pub trait AudioFileEncoder {
+    /// Encode a block. Channels missing from `buffer` are encoded as silence and extra channels
+    /// are ignored.
+    fn encode(&mut self, buffer: &AudioBuffer<f32>) -> Result<(), EncoderError>;
+
+    /// Flush buffered audio and finish the stream. Subsequent calls to `encode` are ignored.
+    fn finalize(&mut self) -> Result<(), EncoderError>;
+}
+
+impl AudioFileEncoder for OutputAudioFileProcessor {
+    fn encode(&mut self, buffer: &AudioBuffer<f32>) -> Result<(), EncoderError> {
+        Ok(self.write_buffer(buffer)?)
+    }
+
+    fn finalize(&mut self) -> Result<(), EncoderError> {
+        Ok(OutputAudioFileProcessor::finalize(self)?)
+    }
+}
+
+/// File format, with its encoding options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodedFormat {
+    Wav(OutputSampleFormat),
+    Flac {
+        bits_per_sample: u16,
+    },
+    #[cfg(feature = "mp3")]
+    Mp3 {
+        bitrate_kbps: u32,
+    },
+}
+
+impl EncodedFormat {
+    /// File extension for this format, without the leading dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EncodedFormat::Wav(_) => "wav",
+            EncodedFormat::Flac { .. } => "flac",
+            #[cfg(feature = "mp3")]
+            EncodedFormat::Mp3 { .. } => "mp3",
+        }
+    }
+
+    /// Guess the format from the file extension, with default options
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "wav" | "wave" => Some(EncodedFormat::Wav(OutputSampleFormat::default())),
+            "flac" => Some(EncodedFormat::Flac {
+                bits_per_sample: 24,
+            }),
+            #[cfg(feature = "mp3")]
+            "mp3" => Some(EncodedFormat::Mp3 { bitrate_kbps: 320 }),
+            _ => None,
+        }
+    }
+}
+
+/// Create a file at `path` and an encoder writing into it, using the sample rate and output
+/// channel count from `settings`.
+pub fn create_encoder(
+    path: &Path,
+    format: EncodedFormat,
+    settings: AudioProcessorSettings,
+) -> Result<Box<dyn AudioFileEncoder>, EncoderError> {
+    let sample_rate = settings.sample_rate() as u32;
+    let num_channels = settings.output_channels();
+    match format {
+        EncodedFormat::Wav(format) => {
+            let path = path.to_str().ok_or_else(|| {
+                EncoderError::UnsupportedSettings(format!("Invalid path {:?}", path))
+            })?;
+            let mut processor = OutputAudioFileProcessor::new(
+                settings,
+                OutputFileSettings {
+                    format,
+                    ..OutputFileSettings::new(path)
+                },
+            );
+            processor.prepare(settings)?;
+            Ok(Box::new(processor))
+        }
+        EncodedFormat::Flac { bits_per_sample } => {
+            let file = io::BufWriter::new(fs::File::create(path)?);
+            Ok(Box::new(FlacEncoder::new(
+                file,
+                FlacEncoderSettings {
+                    sample_rate,
+                    num_channels,
+                    bits_per_sample,
+                    dither: DitherMode::default(),
+                },
+            )?))
+        }
+        #[cfg(feature = "mp3")]
+        EncodedFormat::Mp3 { bitrate_kbps } => {
+            let file = io::BufWriter::new(fs::File::create(path)?);
+            Ok(Box::new(Mp3Encoder::new(
+                file,
+                Mp3EncoderSettings {
+                    sample_rate,
+                    num_channels,
+                    bitrate_kbps,
+                    dither: DitherMode::default(),
+                },
+            )?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use symphonia::core::audio::Signal;
+
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            EncodedFormat::from_path(Path::new("bounce.wav")),
+            Some(EncodedFormat::Wav(OutputSampleFormat::Float32))
+        );
+        assert_eq!(
+            EncodedFormat::from_path(Path::new("bounce.FLAC")),
+            Some(EncodedFormat::Flac {
+                bits_per_sample: 24
+            })
+        );
+        assert_eq!(EncodedFormat::from_path(Path::new("bounce.txt")), None);
+        assert_eq!(EncodedFormat::from_path(Path::new("bounce")), None);
+    }
+
+    #[test]
+    fn test_create_encoder_for_each_format() {
+        let dir = tempdir::TempDir::new("encoder").unwrap();
+        let settings = AudioProcessorSettings::default();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 512);
+
+        for name in ["bounce.wav", "bounce.flac"] {
+            let path = dir.path().join(name);
+            let format = EncodedFormat::from_path(&path).unwrap();
+            let mut encoder = create_encoder(&path, format, settings).unwrap();
+            encoder.encode(&buffer).unwrap();
+            encoder.finalize().unwrap();
+
+            let mut probe =
+                crate::file_io::default_read_audio_file(path.to_str().unwrap()).unwrap();
+            let contents = crate::file_io::read_file_contents(&mut probe).unwrap();
+            assert_eq!(contents.frames(), 512);
+        }
+    }
+}
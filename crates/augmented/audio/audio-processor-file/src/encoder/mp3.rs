@@ -0,0 +1,253 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! MP3 encoding through LAME, linked in-process by `mp3lame-encoder`.
+
+use std::io::Write;
+
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm, Quality};
+
+use audio_processor_traits::AudioBuffer;
+
+use super::{AudioFileEncoder, EncoderError};
+use crate::output_file_processor::dither::Quantizer;
+use crate::DitherMode;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mp3EncoderSettings {
+    pub sample_rate: u32,
+    /// Mono input is encoded as a stereo file with both channels equal
+    pub num_channels: usize,
+    /// Rounded down to the closest bitrate MP3 supports
+    pub bitrate_kbps: u32,
+    pub dither: DitherMode,
+}
+
+pub struct Mp3Encoder<W: Write> {
+    inner: W,
+    encoder: mp3lame_encoder::Encoder,
+    settings: Mp3EncoderSettings,
+    quantizer: Quantizer,
+    interleaved: Vec<i16>,
+    output: Vec<u8>,
+    is_finalized: bool,
+}
+
+impl<W: Write> Mp3Encoder<W> {
+    pub fn new(inner: W, settings: Mp3EncoderSettings) -> Result<Self, EncoderError> {
+        if settings.num_channels == 0 || settings.num_channels > 2 {
+            return Err(EncoderError::UnsupportedSettings(format!(
+                "MP3 supports 1 or 2 channels, got {}",
+                settings.num_channels
+            )));
+        }
+
+        let mut builder = Builder::new().ok_or_else(|| {
+            EncoderError::EncodeError("Failed to create LAME encoder".to_string())
+        })?;
+        builder.set_num_channels(2).map_err(build_error)?;
+        builder
+            .set_sample_rate(settings.sample_rate)
+            .map_err(build_error)?;
+        builder
+            .set_brate(bitrate(settings.bitrate_kbps))
+            .map_err(build_error)?;
+        builder.set_quality(Quality::Best).map_err(build_error)?;
+        let encoder = builder.build().map_err(build_error)?;
+
+        Ok(Self {
+            inner,
+            encoder,
+            settings,
+            quantizer: Quantizer::new(16, settings.dither, 2),
+            interleaved: vec![],
+            output: vec![],
+            is_finalized: false,
+        })
+    }
+}
+
+impl<W: Write> AudioFileEncoder for Mp3Encoder<W> {
+    fn encode(&mut self, buffer: &AudioBuffer<f32>) -> Result<(), EncoderError> {
+        if self.is_finalized || buffer.num_samples() == 0 {
+            return Ok(());
+        }
+
+        self.interleaved.clear();
+        for sample_num in 0..buffer.num_samples() {
+            for channel_num in 0..2 {
+                let source_channel = channel_num.min(self.settings.num_channels - 1);
+                let sample = if source_channel < buffer.num_channels() {
+                    *buffer.get(source_channel, sample_num)
+                } else {
+                    0.0
+                };
+                self.interleaved
+                    .push(self.quantizer.quantize(channel_num, sample) as i16);
+            }
+        }
+
+        self.output.clear();
+        self.output
+            .reserve(mp3lame_encoder::max_required_buffer_size(
+                buffer.num_samples(),
+            ));
+        let encoded_size = self
+            .encoder
+            .encode(
+                InterleavedPcm(&self.interleaved),
+                self.output.spare_capacity_mut(),
+            )
+            .map_err(|err| EncoderError::EncodeError(format!("{:?}", err)))?;
+        // SAFETY: `output` was cleared, so its spare capacity starts at index 0. `encode` writes
+        // the encoded frames to the start of that slice & returns how many bytes it wrote, which
+        // is never more than the slice's length, so the first `encoded_size` bytes are within
+        // capacity and initialized.
+        unsafe {
+            self.output.set_len(encoded_size);
+        }
+        self.inner.write_all(&self.output)?;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), EncoderError> {
+        if self.is_finalized {
+            return Ok(());
+        }
+        self.is_finalized = true;
+
+        self.output.clear();
+        self.output.reserve(7200);
+        let encoded_size = self
+            .encoder
+            .flush::<FlushNoGap>(self.output.spare_capacity_mut())
+            .map_err(|err| EncoderError::EncodeError(format!("{:?}", err)))?;
+        // SAFETY: As in `encode`, `flush` initializes the first `encoded_size` bytes of the spare
+        // capacity, which LAME requires to be at least 7200 bytes.
+        unsafe {
+            self.output.set_len(encoded_size);
+        }
+        self.inner.write_all(&self.output)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+fn build_error(err: mp3lame_encoder::BuildError) -> EncoderError {
+    EncoderError::EncodeError(format!("{:?}", err))
+}
+
+fn bitrate(kbps: u32) -> Bitrate {
+    match kbps {
+        0..=15 => Bitrate::Kbps8,
+        16..=23 => Bitrate::Kbps16,
+        24..=31 => Bitrate::Kbps24,
+        32..=39 => Bitrate::Kbps32,
+        40..=47 => Bitrate::Kbps40,
+        48..=63 => Bitrate::Kbps48,
+        64..=79 => Bitrate::Kbps64,
+        80..=95 => Bitrate::Kbps80,
+        96..=111 => Bitrate::Kbps96,
+        112..=127 => Bitrate::Kbps112,
+        128..=159 => Bitrate::Kbps128,
+        160..=191 => Bitrate::Kbps160,
+        192..=223 => Bitrate::Kbps192,
+        224..=255 => Bitrate::Kbps224,
+        256..=319 => Bitrate::Kbps256,
+        _ => Bitrate::Kbps320,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use audio_processor_testing_helpers::{rms_level, sine_buffer};
+    use symphonia::core::audio::Signal;
+
+    use crate::file_io;
+
+    use super::*;
+
+    fn encode(path: &Path, input: &[f32], num_channels: usize) {
+        let file = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+        let mut encoder = Mp3Encoder::new(
+            file,
+            Mp3EncoderSettings {
+                sample_rate: 44100,
+                num_channels,
+                bitrate_kbps: 192,
+                dither: DitherMode::None,
+            },
+        )
+        .unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(num_channels, input.len());
+        for channel in 0..num_channels {
+            for (index, sample) in input.iter().enumerate() {
+                buffer.set(channel, index, *sample);
+            }
+        }
+        encoder.encode(&buffer).unwrap();
+        encoder.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempdir::TempDir::new("mp3").unwrap();
+        let input = sine_buffer(44100.0, 440.0, Duration::from_secs(1));
+        for num_channels in [1, 2] {
+            let path = dir.path().join(format!("test{}.mp3", num_channels));
+            encode(&path, &input, num_channels);
+
+            let mut probe = file_io::default_read_audio_file(path.to_str().unwrap()).unwrap();
+            let decoded = file_io::read_file_contents(&mut probe).unwrap();
+            assert_eq!(decoded.spec().channels.count(), 2);
+            assert!(decoded.frames() >= input.len());
+            assert_eq!(decoded.chan(0), decoded.chan(1));
+
+            let input_level = rms_level(&input);
+            let decoded_level = rms_level(decoded.chan(0));
+            assert!(
+                (decoded_level - input_level).abs() < input_level * 0.1,
+                "input_level={} decoded_level={}",
+                input_level,
+                decoded_level
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_more_than_two_channels() {
+        let result = Mp3Encoder::new(
+            std::io::Cursor::new(vec![]),
+            Mp3EncoderSettings {
+                sample_rate: 44100,
+                num_channels: 3,
+                bitrate_kbps: 192,
+                dither: DitherMode::None,
+            },
+        );
+        assert!(matches!(result, Err(EncoderError::UnsupportedSettings(_))));
+    }
+}
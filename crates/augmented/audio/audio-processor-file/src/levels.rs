@@ -0,0 +1,88 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! RMS & peak levels of whole files or buffers, in dBFS.
+
+/// Level reported for digital silence
+pub const SILENCE_DB: f32 = -120.0;
+
+/// Levels across all channels of some audio, in dBFS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Levels {
+    pub rms_db: f32,
+    pub peak_db: f32,
+}
+
+/// Measure the RMS & peak levels of `channels`, every sample is weighted equally
+pub fn measure_levels<C: AsRef<[f32]>>(channels: impl IntoIterator<Item = C>) -> Levels {
+    let mut sum = 0.0;
+    let mut peak: f32 = 0.0;
+    let mut num_samples = 0;
+    for channel in channels {
+        let channel = channel.as_ref();
+        for sample in channel {
+            sum += (*sample as f64) * (*sample as f64);
+            peak = peak.max(sample.abs());
+        }
+        num_samples += channel.len();
+    }
+    let rms = (sum / num_samples.max(1) as f64).sqrt() as f32;
+
+    Levels {
+        rms_db: to_db(rms),
+        peak_db: to_db(peak),
+    }
+}
+
+/// Convert a linear level into dBFS, clamped at [`SILENCE_DB`]
+pub fn to_db(level: f32) -> f32 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_db() {
+        assert_eq!(to_db(0.0), SILENCE_DB);
+        assert!((to_db(1.0)).abs() < 1e-6);
+        assert!((to_db(0.5) + 6.0206).abs() < 0.001);
+        assert_eq!(to_db(1e-9), SILENCE_DB);
+    }
+
+    #[test]
+    fn test_measure_levels() {
+        let levels = measure_levels([vec![0.5, -0.5], vec![0.25, -0.25]]);
+        assert!((levels.peak_db - to_db(0.5)).abs() < 1e-6);
+        assert!((levels.rms_db - to_db(0.15625_f32.sqrt())).abs() < 1e-4);
+
+        let levels = measure_levels(Vec::<Vec<f32>>::new());
+        assert_eq!(levels.rms_db, SILENCE_DB);
+        assert_eq!(levels.peak_db, SILENCE_DB);
+    }
+}
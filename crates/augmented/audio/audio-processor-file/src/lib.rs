@@ -23,17 +23,46 @@
 
 //! [`audio_processor_traits::AudioProcessor`] implementations for audio file playback & writing.
 //!
-//! Currently two processors are provided:
+//! Currently four processors are provided:
 //!
 //! * [`AudioFileProcessor`] is an input file processor, its `prepare` method will *load the whole
 //!   file onto memory*. Both `wav` and `mp3` are supported via [`symphonia`]
-//!   - If streaming is a requirement this won't work
-//! * [`OutputAudioFileProcessor`] writes `wav` files
+//!   - If streaming is a requirement, use [`StreamingAudioFileProcessor`] instead
+//! * [`StreamingAudioFileProcessor`] plays files from disk, reading them on a background thread
+//!   with seeking, loop regions, prefetch and underrun reporting
+//! * [`OutputAudioFileProcessor`] writes 16/24/32-bit integer or 32-bit float `wav` files, with
+//!   dither, BWF/iXML metadata and cue markers
+//! * [`DiskRecorderProcessor`] records long takes straight to disk from the audio thread, with
+//!   arm/punch-in/punch-out, pre-roll and multi-track recording
+//!
+//! The [`encoder`] module writes `flac` (and `mp3` with the `mp3` feature) in-process, see
+//! [`encoder::AudioFileEncoder`].
+//!
+//! Loop points, cue markers, tempo & tags can be read with [`metadata::read_metadata`].
+//!
+//! Folders of files can be rendered in parallel with the [`batch`] module, and measured with
+//! [`levels::measure_levels`].
 
 pub use audio_file_processor::{
     file_io, AudioFileProcessor, AudioFileProcessorHandle, InMemoryAudioFile,
 };
-pub use output_file_processor::{OutputAudioFileProcessor, OutputFileSettings};
+pub use disk_recorder::{
+    DiskRecorderHandle, DiskRecorderProcessor, DiskRecorderSettings, RecordedTake,
+};
+pub use output_file_processor::{
+    BwfMetadata, CueMarker, DitherMode, IxmlMetadata, OutputAudioFileProcessor, OutputFileError,
+    OutputFileSettings, OutputSampleFormat,
+};
+pub use streaming_file_processor::{StreamingAudioFileHandle, StreamingAudioFileProcessor};
+
+pub mod batch;
+pub mod encoder;
+pub mod levels;
+pub mod metadata;
 
 mod audio_file_processor;
+mod disk_recorder;
 mod output_file_processor;
+mod streaming_file_processor;
+#[cfg(all(test, debug_assertions))]
+mod test_allocator;
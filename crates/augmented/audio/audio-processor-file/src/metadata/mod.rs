@@ -0,0 +1,552 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Reads the metadata symphonia doesn't surface when decoding: sampler loop points (`smpl`),
+//! cue markers, ACID tempo & beat information as well as ID3/Vorbis/RIFF INFO tags.
+//!
+//! ```no_run
+//! let metadata = audio_processor_file::metadata::read_metadata("loop.wav").unwrap();
+//! if let Some(tempo) = metadata.tempo() {
+//!     println!("{} BPM, slices at {:?}", tempo, metadata.slice_positions());
+//! }
+//! ```
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use symphonia::core::meta::{MetadataRevision, StandardTagKey};
+
+use crate::file_io::{default_read_audio_file, AudioFileError};
+use crate::CueMarker;
+
+use self::riff::read_riff_chunks;
+
+mod riff;
+
+/// Tag keys holding the musical key, across ID3 (`TKEY`) and Vorbis comments
+const KEY_TAGS: [&str; 3] = ["TKEY", "INITIALKEY", "KEY"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopType {
+    Forward,
+    PingPong,
+    Backward,
+    Other(u32),
+}
+
+impl From<u32> for LoopType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => LoopType::Forward,
+            1 => LoopType::PingPong,
+            2 => LoopType::Backward,
+            other => LoopType::Other(other),
+        }
+    }
+}
+
+/// A sampler loop, in frames. `end` is inclusive, as in the `smpl` chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleLoop {
+    pub cue_point_id: u32,
+    pub loop_type: LoopType,
+    pub start: usize,
+    pub end: usize,
+    /// Zero means the loop plays forever
+    pub play_count: u32,
+}
+
+/// Contents of a WAV `smpl` chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplerInfo {
+    pub midi_unity_note: u8,
+    /// Fraction of a semitone above `midi_unity_note`, between 0 and 1
+    pub midi_pitch_fraction: f32,
+    pub loops: Vec<SampleLoop>,
+}
+
+/// Contents of an ACID `acid` chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcidInfo {
+    pub is_one_shot: bool,
+    pub root_note: Option<u8>,
+    pub num_beats: u32,
+    pub meter_numerator: u16,
+    pub meter_denominator: u16,
+    pub tempo: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFileTag {
+    /// The key as found in the file, e.g. `TBPM` or `INAM`
+    pub key: String,
+    pub std_key: Option<StandardTagKey>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioFileMetadata {
+    pub sample_rate: Option<u32>,
+    pub num_channels: Option<usize>,
+    pub num_frames: Option<u64>,
+    pub tags: Vec<AudioFileTag>,
+    /// Cue markers, sorted by position
+    pub markers: Vec<CueMarker>,
+    pub sampler: Option<SamplerInfo>,
+    pub acid: Option<AcidInfo>,
+}
+
+impl AudioFileMetadata {
+    pub fn duration(&self) -> Option<Duration> {
+        let sample_rate = self.sample_rate.filter(|sample_rate| *sample_rate > 0)?;
+        Some(Duration::from_secs_f64(
+            self.num_frames? as f64 / sample_rate as f64,
+        ))
+    }
+
+    /// Value of the first tag matching `key`, ignoring case
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.key.eq_ignore_ascii_case(key))
+            .map(|tag| tag.value.as_str())
+    }
+
+    /// Value of the first tag symphonia mapped to `key`
+    pub fn standard_tag(&self, key: StandardTagKey) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.std_key == Some(key))
+            .map(|tag| tag.value.as_str())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.standard_tag(StandardTagKey::TrackTitle)
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.standard_tag(StandardTagKey::Artist)
+    }
+
+    /// Tempo from the ACID chunk, falling back to a BPM tag
+    pub fn tempo(&self) -> Option<f32> {
+        self.acid
+            .as_ref()
+            .filter(|acid| !acid.is_one_shot)
+            .map(|acid| acid.tempo)
+            .filter(|tempo| tempo.is_finite() && *tempo > 0.0)
+            .or_else(|| {
+                self.standard_tag(StandardTagKey::Bpm)
+                    .and_then(|bpm| bpm.trim().parse::<f32>().ok())
+                    .filter(|tempo| tempo.is_finite() && *tempo > 0.0)
+            })
+    }
+
+    /// The musical key, as written in the file's tags (e.g. `Am` or `8A`)
+    pub fn key(&self) -> Option<&str> {
+        KEY_TAGS.iter().find_map(|key| self.tag(key))
+    }
+
+    /// Number of beats from the ACID chunk, otherwise estimated from the tempo & duration
+    pub fn num_beats(&self) -> Option<u32> {
+        if let Some(acid) = self.acid.as_ref().filter(|acid| acid.num_beats > 0) {
+            return Some(acid.num_beats);
+        }
+        let beats = self.tempo()? * self.duration()?.as_secs_f32() / 60.0;
+        Some(beats.round() as u32).filter(|beats| *beats > 0)
+    }
+
+    /// Loop points from the `smpl` chunk, or from `LOOPSTART`/`LOOPLENGTH` tags as used in Ogg
+    /// files
+    pub fn loops(&self) -> Vec<SampleLoop> {
+        if let Some(sampler) = &self.sampler {
+            return sampler.loops.clone();
+        }
+
+        let parse = |key| {
+            self.tag(key)
+                .and_then(|value| value.trim().parse::<usize>().ok())
+        };
+        let start = parse("LOOPSTART");
+        let end = parse("LOOPEND")
+            .map(|end| end.saturating_sub(1))
+            .or_else(|| Some(start? + parse("LOOPLENGTH")?.checked_sub(1)?));
+        match (start, end) {
+            (Some(start), Some(end)) if end > start => vec![SampleLoop {
+                cue_point_id: 0,
+                loop_type: LoopType::Forward,
+                start,
+                end,
+                play_count: 0,
+            }],
+            _ => vec![],
+        }
+    }
+
+    /// Frame positions to slice the file at: the cue markers if there are any, otherwise the
+    /// beats, if the tempo is known
+    pub fn slice_positions(&self) -> Vec<usize> {
+        if !self.markers.is_empty() {
+            return self.markers.iter().map(|marker| marker.position).collect();
+        }
+
+        let (num_frames, num_beats) = match (self.num_frames, self.num_beats()) {
+            (Some(num_frames), Some(num_beats)) => (num_frames as f64, num_beats as usize),
+            _ => return vec![],
+        };
+        let beat_length = num_frames / num_beats as f64;
+        (0..num_beats)
+            .map(|beat| (beat as f64 * beat_length).round() as usize)
+            .collect()
+    }
+}
+
+/// Read the metadata of the file at `path`, without decoding any audio
+pub fn read_metadata(path: impl AsRef<Path>) -> Result<AudioFileMetadata, AudioFileError> {
+    let path = path.as_ref();
+    let mut probe = default_read_audio_file(&path.to_string_lossy())?;
+    let mut metadata = AudioFileMetadata::default();
+
+    if let Some(track) = probe.format.default_track() {
+        let codec_params = &track.codec_params;
+        metadata.sample_rate = codec_params.sample_rate;
+        metadata.num_channels = codec_params.channels.map(|channels| channels.count());
+        metadata.num_frames = codec_params.n_frames;
+    }
+
+    // ID3 tags in front of the stream are found by the probe, the rest by the format reader
+    if let Some(mut probed_metadata) = probe.metadata.get() {
+        if let Some(revision) = probed_metadata.skip_to_latest() {
+            push_tags(&mut metadata.tags, revision);
+        }
+    }
+    if let Some(revision) = probe.format.metadata().skip_to_latest() {
+        push_tags(&mut metadata.tags, revision);
+    }
+
+    // FLAC cue sheets
+    metadata.markers = probe
+        .format
+        .cues()
+        .iter()
+        .map(|cue| CueMarker {
+            position: cue.start_ts as usize,
+            label: cue
+                .tags
+                .first()
+                .map(|tag| tag.value.to_string())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    let mut reader = BufReader::new(File::open(path)?);
+    if let Some(chunks) = read_riff_chunks(&mut reader)? {
+        metadata.sampler = chunks.sampler;
+        metadata.acid = chunks.acid;
+        if !chunks.markers.is_empty() {
+            metadata.markers = chunks.markers;
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn push_tags(tags: &mut Vec<AudioFileTag>, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let tag = AudioFileTag {
+            key: tag.key.clone(),
+            std_key: tag.std_key,
+            // RIFF INFO values keep their NUL terminator
+            value: tag.value.to_string().trim_end_matches('\0').to_string(),
+        };
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
+
+    use crate::encoder::{create_encoder, EncodedFormat};
+    use crate::{OutputAudioFileProcessor, OutputFileSettings};
+
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::from(&id[..]);
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// A 16-bit mono WAV file with `num_frames` of silence
+    fn write_wav(
+        dir: &Path,
+        num_frames: usize,
+        before_data: &[Vec<u8>],
+        after_data: &[Vec<u8>],
+    ) -> PathBuf {
+        let mut fmt = vec![];
+        fmt.extend_from_slice(&1_u16.to_le_bytes());
+        fmt.extend_from_slice(&1_u16.to_le_bytes());
+        fmt.extend_from_slice(&44100_u32.to_le_bytes());
+        fmt.extend_from_slice(&(44100_u32 * 2).to_le_bytes());
+        fmt.extend_from_slice(&2_u16.to_le_bytes());
+        fmt.extend_from_slice(&16_u16.to_le_bytes());
+
+        let mut body = Vec::from(&b"WAVE"[..]);
+        body.extend(chunk(b"fmt ", &fmt));
+        before_data.iter().for_each(|chunk| body.extend(chunk));
+        body.extend(chunk(b"data", &vec![0; num_frames * 2]));
+        after_data.iter().for_each(|chunk| body.extend(chunk));
+
+        let path = dir.join("test.wav");
+        std::fs::write(&path, chunk(b"RIFF", &body)).unwrap();
+        path
+    }
+
+    fn smpl_chunk(unity_note: u32, loops: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut body = vec![];
+        for value in [
+            0,
+            0,
+            22675,
+            unity_note,
+            1 << 31,
+            0,
+            0,
+            loops.len() as u32,
+            0,
+        ] {
+            body.extend_from_slice(&value.to_le_bytes());
+        }
+        for (index, (loop_type, start, end)) in loops.iter().enumerate() {
+            for value in [index as u32, *loop_type, *start, *end, 0, 0] {
+                body.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        chunk(b"smpl", &body)
+    }
+
+    fn acid_chunk(flags: u32, root_note: u16, num_beats: u32, tempo: f32) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend_from_slice(&flags.to_le_bytes());
+        body.extend_from_slice(&root_note.to_le_bytes());
+        body.extend_from_slice(&0x8000_u16.to_le_bytes());
+        body.extend_from_slice(&0_f32.to_le_bytes());
+        body.extend_from_slice(&num_beats.to_le_bytes());
+        body.extend_from_slice(&4_u16.to_le_bytes());
+        body.extend_from_slice(&4_u16.to_le_bytes());
+        body.extend_from_slice(&tempo.to_le_bytes());
+        chunk(b"acid", &body)
+    }
+
+    fn info_chunk(tags: &[(&[u8; 4], &str)]) -> Vec<u8> {
+        let mut body = Vec::from(&b"INFO"[..]);
+        for (id, value) in tags {
+            let mut value = Vec::from(value.as_bytes());
+            value.push(0);
+            body.extend(chunk(id, &value));
+        }
+        chunk(b"LIST", &body)
+    }
+
+    #[test]
+    fn test_read_sampler_loops() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = write_wav(
+            dir.path(),
+            1000,
+            &[],
+            &[smpl_chunk(62, &[(0, 100, 899), (1, 10, 20)])],
+        );
+
+        let metadata = read_metadata(&path).unwrap();
+        let sampler = metadata.sampler.as_ref().unwrap();
+        assert_eq!(sampler.midi_unity_note, 62);
+        assert_eq!(sampler.midi_pitch_fraction, 0.5);
+        assert_eq!(
+            metadata.loops(),
+            vec![
+                SampleLoop {
+                    cue_point_id: 0,
+                    loop_type: LoopType::Forward,
+                    start: 100,
+                    end: 899,
+                    play_count: 0
+                },
+                SampleLoop {
+                    cue_point_id: 1,
+                    loop_type: LoopType::PingPong,
+                    start: 10,
+                    end: 20,
+                    play_count: 0
+                }
+            ]
+        );
+        assert_eq!(metadata.sample_rate, Some(44100));
+        assert_eq!(metadata.num_channels, Some(1));
+        assert_eq!(metadata.num_frames, Some(1000));
+    }
+
+    #[test]
+    fn test_read_acid_tempo_and_beats() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = write_wav(dir.path(), 88200, &[acid_chunk(0x02, 57, 4, 120.0)], &[]);
+
+        let metadata = read_metadata(&path).unwrap();
+        let acid = metadata.acid.as_ref().unwrap();
+        assert!(!acid.is_one_shot);
+        assert_eq!(acid.root_note, Some(57));
+        assert_eq!((acid.meter_numerator, acid.meter_denominator), (4, 4));
+        assert_eq!(metadata.tempo(), Some(120.0));
+        assert_eq!(metadata.num_beats(), Some(4));
+        assert_eq!(metadata.duration(), Some(Duration::from_secs(2)));
+        assert_eq!(metadata.slice_positions(), vec![0, 22050, 44100, 66150]);
+    }
+
+    #[test]
+    fn test_one_shots_have_no_tempo() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = write_wav(dir.path(), 100, &[acid_chunk(0x01, 60, 0, 120.0)], &[]);
+
+        let metadata = read_metadata(&path).unwrap();
+        assert!(metadata.acid.as_ref().unwrap().is_one_shot);
+        assert_eq!(metadata.acid.as_ref().unwrap().root_note, None);
+        assert_eq!(metadata.tempo(), None);
+        assert!(metadata.slice_positions().is_empty());
+    }
+
+    #[test]
+    fn test_read_info_tags() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = write_wav(
+            dir.path(),
+            100,
+            &[info_chunk(&[(b"INAM", "Drum loop"), (b"IART", "Someone")])],
+            &[],
+        );
+
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(metadata.title(), Some("Drum loop"));
+        assert_eq!(metadata.artist(), Some("Someone"));
+        assert_eq!(metadata.tag("inam"), Some("Drum loop"));
+    }
+
+    #[test]
+    fn test_markers_written_by_the_output_processor_are_read() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = dir.path().join("markers.wav");
+        let settings = AudioProcessorSettings::default();
+        let mut output = OutputAudioFileProcessor::new(
+            settings,
+            OutputFileSettings {
+                markers: vec![CueMarker::new(300, "Chorus"), CueMarker::new(100, "Verse")],
+                ..OutputFileSettings::new(path.to_str().unwrap())
+            },
+        );
+        output.prepare(settings).unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 512);
+        output.process(&mut buffer).unwrap();
+        output.finalize().unwrap();
+
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(
+            metadata.markers,
+            vec![CueMarker::new(100, "Verse"), CueMarker::new(300, "Chorus")]
+        );
+        assert_eq!(metadata.slice_positions(), vec![100, 300]);
+        assert_eq!(metadata.num_frames, Some(512));
+    }
+
+    #[test]
+    fn test_tempo_and_loops_from_tags() {
+        let metadata = AudioFileMetadata {
+            sample_rate: Some(48000),
+            num_frames: Some(48000 * 4),
+            tags: vec![
+                AudioFileTag {
+                    key: String::from("TBPM"),
+                    std_key: Some(StandardTagKey::Bpm),
+                    value: String::from("90"),
+                },
+                AudioFileTag {
+                    key: String::from("TKEY"),
+                    std_key: None,
+                    value: String::from("Am"),
+                },
+                AudioFileTag {
+                    key: String::from("LOOPSTART"),
+                    std_key: None,
+                    value: String::from("1000"),
+                },
+                AudioFileTag {
+                    key: String::from("LOOPLENGTH"),
+                    std_key: None,
+                    value: String::from("2000"),
+                },
+            ],
+            ..AudioFileMetadata::default()
+        };
+
+        assert_eq!(metadata.tempo(), Some(90.0));
+        assert_eq!(metadata.key(), Some("Am"));
+        assert_eq!(metadata.num_beats(), Some(6));
+        assert_eq!(metadata.slice_positions().len(), 6);
+        assert_eq!(metadata.loops()[0].start, 1000);
+        assert_eq!(metadata.loops()[0].end, 2999);
+    }
+
+    #[test]
+    fn test_non_wav_files_have_no_riff_metadata() {
+        let dir = tempdir::TempDir::new("metadata").unwrap();
+        let path = dir.path().join("test.flac");
+        let settings = AudioProcessorSettings::default();
+        let mut encoder =
+            create_encoder(&path, EncodedFormat::from_path(&path).unwrap(), settings).unwrap();
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 4096);
+        encoder.encode(&buffer).unwrap();
+        encoder.finalize().unwrap();
+
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(metadata.sample_rate, Some(44100));
+        assert_eq!(metadata.num_channels, Some(2));
+        assert_eq!(metadata.num_frames, Some(4096));
+        assert!(metadata.sampler.is_none());
+        assert!(metadata.acid.is_none());
+    }
+
+    #[test]
+    fn test_missing_files_are_an_error() {
+        assert!(read_metadata("/does/not/exist.wav").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::CueMarker;
+
+use super::{AcidInfo, LoopType, SampleLoop, SamplerInfo};
+
+/// Chunks larger than this are skipped rather than read into memory
+const MAX_METADATA_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// The `smpl`, `cue `, `adtl` & `acid` chunks of a RIFF/WAVE file, which symphonia skips
+#[derive(Debug, Default)]
+pub(crate) struct RiffChunks {
+    pub(crate) sampler: Option<SamplerInfo>,
+    pub(crate) acid: Option<AcidInfo>,
+    pub(crate) markers: Vec<CueMarker>,
+}
+
+/// Walk the top-level chunks of `reader`. Returns `Ok(None)` if it isn't a RIFF/WAVE file.
+pub(crate) fn read_riff_chunks(
+    reader: &mut (impl Read + Seek),
+) -> std::io::Result<Option<RiffChunks>> {
+    let mut header = [0; 12];
+    if reader.read_exact(&mut header).is_err()
+        || &header[0..4] != b"RIFF"
+        || &header[8..12] != b"WAVE"
+    {
+        return Ok(None);
+    }
+
+    let mut chunks = RiffChunks::default();
+    let mut cue_positions: Vec<(u32, usize)> = vec![];
+    let mut labels: HashMap<u32, String> = HashMap::new();
+
+    loop {
+        let mut chunk_header = [0; 8];
+        if reader.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+        let id = &chunk_header[0..4];
+        let size = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+        // Chunks are word aligned
+        let padded_size = size as i64 + (size % 2) as i64;
+
+        let is_metadata = matches!(id, b"smpl" | b"acid" | b"cue " | b"LIST");
+        if !is_metadata || size > MAX_METADATA_CHUNK_SIZE {
+            reader.seek(SeekFrom::Current(padded_size))?;
+            continue;
+        }
+
+        let mut body = vec![0; size as usize];
+        if reader.read_exact(&mut body).is_err() {
+            // Truncated file, keep what we have
+            break;
+        }
+        if size % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+
+        match id {
+            b"smpl" => chunks.sampler = parse_smpl(&body),
+            b"acid" => chunks.acid = parse_acid(&body),
+            b"cue " => cue_positions = parse_cue(&body),
+            b"LIST" if body.starts_with(b"adtl") => labels = parse_adtl(&body[4..]),
+            _ => {}
+        }
+    }
+
+    chunks.markers = cue_positions
+        .into_iter()
+        .map(|(id, position)| CueMarker {
+            position,
+            label: labels.remove(&id).unwrap_or_default(),
+        })
+        .collect();
+    chunks.markers.sort_by_key(|marker| marker.position);
+
+    Ok(Some(chunks))
+}
+
+fn u16_at(body: &[u8], offset: usize) -> Option<u16> {
+    let bytes = body.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(body: &[u8], offset: usize) -> Option<u32> {
+    let bytes = body.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn f32_at(body: &[u8], offset: usize) -> Option<f32> {
+    u32_at(body, offset).map(f32::from_bits)
+}
+
+/// `smpl` is 36 bytes of header followed by 24 byte loop records
+fn parse_smpl(body: &[u8]) -> Option<SamplerInfo> {
+    let midi_unity_note = u32_at(body, 12)?;
+    let midi_pitch_fraction = u32_at(body, 16)?;
+    let num_loops = u32_at(body, 28)? as usize;
+
+    let loops = (0..num_loops)
+        .map_while(|index| {
+            let offset = 36 + index * 24;
+            Some(SampleLoop {
+                cue_point_id: u32_at(body, offset)?,
+                loop_type: LoopType::from(u32_at(body, offset + 4)?),
+                start: u32_at(body, offset + 8)? as usize,
+                end: u32_at(body, offset + 12)? as usize,
+                play_count: u32_at(body, offset + 20)?,
+            })
+        })
+        .collect();
+
+    Some(SamplerInfo {
+        midi_unity_note: midi_unity_note.min(127) as u8,
+        midi_pitch_fraction: midi_pitch_fraction as f32 / (u32::MAX as f32 + 1.0),
+        loops,
+    })
+}
+
+/// `acid` chunks are 24 bytes, as written by ACID & most loop libraries
+fn parse_acid(body: &[u8]) -> Option<AcidInfo> {
+    let flags = u32_at(body, 0)?;
+    let root_note = u16_at(body, 4)?;
+    let num_beats = u32_at(body, 12)?;
+    let meter_denominator = u16_at(body, 16)?;
+    let meter_numerator = u16_at(body, 18)?;
+    let tempo = f32_at(body, 20)?;
+
+    Some(AcidInfo {
+        is_one_shot: flags & 0x01 != 0,
+        root_note: if flags & 0x02 != 0 {
+            Some(root_note.min(127) as u8)
+        } else {
+            None
+        },
+        num_beats,
+        meter_numerator,
+        meter_denominator,
+        tempo,
+    })
+}
+
+/// Returns the `(id, sample offset)` of each cue point
+fn parse_cue(body: &[u8]) -> Vec<(u32, usize)> {
+    let num_points = u32_at(body, 0).unwrap_or(0) as usize;
+    (0..num_points)
+        .map_while(|index| {
+            let offset = 4 + index * 24;
+            Some((u32_at(body, offset)?, u32_at(body, offset + 20)? as usize))
+        })
+        .collect()
+}
+
+/// Returns the `labl` sub-chunks of an `adtl` list, by cue point id
+fn parse_adtl(mut body: &[u8]) -> HashMap<u32, String> {
+    let mut labels = HashMap::new();
+    while body.len() >= 8 {
+        let size = u32_at(body, 4).unwrap_or(0) as usize;
+        let end = (8 + size).min(body.len());
+        if &body[0..4] == b"labl" && size >= 4 {
+            if let Some(id) = u32_at(body, 8) {
+                let text = &body[12.min(end)..end];
+                let text = text.split(|c| *c == 0).next().unwrap_or_default();
+                labels.insert(id, String::from_utf8_lossy(text).into_owned());
+            }
+        }
+        body = &body[(end + size % 2).min(body.len())..];
+    }
+    labels
+}
@@ -0,0 +1,187 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::str::FromStr;
+
+use rand::{Rng, SeedableRng};
+
+/// How samples are rounded when writing integer formats.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Plain rounding, quantization error is correlated with the signal
+    None,
+    /// Triangular (TPDF) dither of 1 LSB peak, decorrelates the error into flat white noise
+    #[default]
+    Tpdf,
+    /// TPDF dither with 2nd order error feedback, moves the noise floor towards Nyquist where the
+    /// ear is less sensitive
+    NoiseShaped,
+}
+
+impl FromStr for DitherMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DitherMode::None),
+            "tpdf" => Ok(DitherMode::Tpdf),
+            "noise-shaped" => Ok(DitherMode::NoiseShaped),
+            _ => Err(format!(
+                "Unknown dither mode '{}', expected one of none, tpdf or noise-shaped",
+                s
+            )),
+        }
+    }
+}
+
+/// Converts floats in the `-1.0..1.0` range into `bits` wide integers, keeping per-channel error
+/// feedback state for noise shaping.
+pub(crate) struct Quantizer {
+    mode: DitherMode,
+    scale: f64,
+    min: f64,
+    max: f64,
+    rng: rand::rngs::SmallRng,
+    /// The last two total errors for each channel, in LSBs
+    errors: Vec<[f64; 2]>,
+}
+
+impl Quantizer {
+    pub(crate) fn new(bits: u16, mode: DitherMode, num_channels: usize) -> Self {
+        Self::with_rng(
+            bits,
+            mode,
+            num_channels,
+            rand::rngs::SmallRng::from_entropy(),
+        )
+    }
+
+    fn with_rng(
+        bits: u16,
+        mode: DitherMode,
+        num_channels: usize,
+        rng: rand::rngs::SmallRng,
+    ) -> Self {
+        let scale = (1_u64 << (bits - 1)) as f64;
+        Self {
+            mode,
+            scale,
+            min: -scale,
+            max: scale - 1.0,
+            rng,
+            errors: vec![[0.0; 2]; num_channels],
+        }
+    }
+
+    pub(crate) fn quantize(&mut self, channel: usize, sample: f32) -> i32 {
+        let input = sample as f64 * self.scale;
+        match self.mode {
+            DitherMode::None => input.round().clamp(self.min, self.max) as i32,
+            DitherMode::Tpdf => {
+                let dither = self.tpdf();
+                (input + dither).round().clamp(self.min, self.max) as i32
+            }
+            DitherMode::NoiseShaped => {
+                // Noise transfer function is (1 - z^-1)^2
+                let [e1, e2] = self.errors[channel];
+                let shaped = input - 2.0 * e1 + e2;
+                let dither = self.tpdf();
+                let output = (shaped + dither).round().clamp(self.min, self.max);
+                // Clipping would otherwise feed back huge errors into the next samples
+                let error = (output - shaped).clamp(-2.0, 2.0);
+                self.errors[channel] = [error, e1];
+                output as i32
+            }
+        }
+    }
+
+    fn tpdf(&mut self) -> f64 {
+        self.rng.gen::<f64>() - self.rng.gen::<f64>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quantizer(mode: DitherMode) -> Quantizer {
+        Quantizer::with_rng(16, mode, 1, rand::rngs::SmallRng::seed_from_u64(42))
+    }
+
+    /// Quantize a quiet sine and return the error in LSBs
+    fn quantization_error(mode: DitherMode) -> Vec<f64> {
+        let mut quantizer = quantizer(mode);
+        (0..44100)
+            .map(|i| {
+                let sample =
+                    0.001 * (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 44100.0).sin();
+                let output = quantizer.quantize(0, sample) as f64;
+                output - sample as f64 * 32768.0
+            })
+            .collect()
+    }
+
+    /// Energy of the error after a crude low-pass (moving average), a stand-in for the audible
+    /// band
+    fn low_frequency_energy(error: &[f64]) -> f64 {
+        error
+            .windows(64)
+            .map(|window| {
+                let average = window.iter().sum::<f64>() / window.len() as f64;
+                average * average
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_rounding_without_dither() {
+        let mut quantizer = quantizer(DitherMode::None);
+        assert_eq!(quantizer.quantize(0, 0.0), 0);
+        assert_eq!(quantizer.quantize(0, 0.5), 16384);
+        assert_eq!(quantizer.quantize(0, -1.0), -32768);
+        assert_eq!(quantizer.quantize(0, 1.0), 32767);
+        assert_eq!(quantizer.quantize(0, 2.0), 32767);
+    }
+
+    #[test]
+    fn test_tpdf_error_is_bounded() {
+        let error = quantization_error(DitherMode::Tpdf);
+        assert!(error.iter().all(|e| e.abs() <= 1.5));
+        let mean = error.iter().sum::<f64>() / error.len() as f64;
+        assert!(mean.abs() < 0.05);
+    }
+
+    #[test]
+    fn test_noise_shaping_moves_noise_out_of_the_low_band() {
+        let tpdf = low_frequency_energy(&quantization_error(DitherMode::Tpdf));
+        let shaped = low_frequency_energy(&quantization_error(DitherMode::NoiseShaped));
+        assert!(shaped < tpdf / 4.0, "shaped={} tpdf={}", shaped, tpdf);
+    }
+
+    #[test]
+    fn test_parse_dither_mode() {
+        assert_eq!("none".parse(), Ok(DitherMode::None));
+        assert_eq!("tpdf".parse(), Ok(DitherMode::Tpdf));
+        assert_eq!("noise-shaped".parse(), Ok(DitherMode::NoiseShaped));
+        assert!("other".parse::<DitherMode>().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Metadata written alongside the audio data.
+//!
+//! * [`BwfMetadata`] is written as a Broadcast Wave `bext` chunk
+//! * [`IxmlMetadata`] is written as an `iXML` chunk
+//! * [`CueMarker`]s are written as a `cue ` chunk, with labels in a `LIST`/`adtl` chunk
+
+/// Broadcast Wave Format (EBU Tech 3285) description of a file.
+///
+/// Text fields are truncated to the sizes the spec allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BwfMetadata {
+    /// Free text description, up to 256 bytes
+    pub description: String,
+    /// Name of the originator, up to 32 bytes
+    pub originator: String,
+    /// Unique reference given by the originator, up to 32 bytes
+    pub originator_reference: String,
+    /// `yyyy-mm-dd`
+    pub origination_date: String,
+    /// `hh:mm:ss`
+    pub origination_time: String,
+    /// Position of the first sample, in samples since midnight
+    pub time_reference: u64,
+    /// Free text history of the coding processes applied to the audio
+    pub coding_history: String,
+}
+
+impl BwfMetadata {
+    pub(crate) fn to_chunk(&self) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(602 + self.coding_history.len());
+        push_fixed_str(&mut chunk, &self.description, 256);
+        push_fixed_str(&mut chunk, &self.originator, 32);
+        push_fixed_str(&mut chunk, &self.originator_reference, 32);
+        push_fixed_str(&mut chunk, &self.origination_date, 10);
+        push_fixed_str(&mut chunk, &self.origination_time, 8);
+        chunk.extend_from_slice(&self.time_reference.to_le_bytes());
+        // Version 1, loudness fields are left empty
+        chunk.extend_from_slice(&1_u16.to_le_bytes());
+        // UMID
+        chunk.extend_from_slice(&[0; 64]);
+        // Loudness values & reserved
+        chunk.extend_from_slice(&[0; 10 + 180]);
+        chunk.extend_from_slice(self.coding_history.as_bytes());
+        chunk
+    }
+}
+
+/// The subset of iXML production metadata we write.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IxmlMetadata {
+    pub project: String,
+    pub scene: String,
+    pub take: String,
+    pub tape: String,
+    pub note: String,
+    /// Track names, by channel. Missing names default to `Track N`
+    pub track_names: Vec<String>,
+}
+
+impl IxmlMetadata {
+    pub(crate) fn to_xml(&self, num_channels: usize) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n");
+        push_element(&mut xml, 1, "IXML_VERSION", "1.61");
+        push_element(&mut xml, 1, "PROJECT", &self.project);
+        push_element(&mut xml, 1, "SCENE", &self.scene);
+        push_element(&mut xml, 1, "TAKE", &self.take);
+        push_element(&mut xml, 1, "TAPE", &self.tape);
+        push_element(&mut xml, 1, "NOTE", &self.note);
+        xml.push_str("  <TRACK_LIST>\n");
+        push_element(&mut xml, 2, "TRACK_COUNT", &num_channels.to_string());
+        for channel in 0..num_channels {
+            let index = (channel + 1).to_string();
+            let name = self
+                .track_names
+                .get(channel)
+                .cloned()
+                .unwrap_or_else(|| format!("Track {}", index));
+            xml.push_str("    <TRACK>\n");
+            push_element(&mut xml, 3, "CHANNEL_INDEX", &index);
+            push_element(&mut xml, 3, "INTERLEAVE_INDEX", &index);
+            push_element(&mut xml, 3, "NAME", &name);
+            xml.push_str("    </TRACK>\n");
+        }
+        xml.push_str("  </TRACK_LIST>\n</BWFXML>\n");
+        xml
+    }
+}
+
+/// A named position in the file, in frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueMarker {
+    pub position: usize,
+    pub label: String,
+}
+
+impl CueMarker {
+    pub fn new(position: usize, label: impl Into<String>) -> Self {
+        Self {
+            position,
+            label: label.into(),
+        }
+    }
+}
+
+/// Build the `cue ` chunk and the `adtl` list body holding the marker labels
+pub(crate) fn cue_chunks(markers: &[CueMarker]) -> (Vec<u8>, Vec<u8>) {
+    let mut cue = Vec::with_capacity(4 + markers.len() * 24);
+    cue.extend_from_slice(&(markers.len() as u32).to_le_bytes());
+    let mut adtl = Vec::from(&b"adtl"[..]);
+
+    for (index, marker) in markers.iter().enumerate() {
+        let id = index as u32 + 1;
+        let position = marker.position.min(u32::MAX as usize) as u32;
+        cue.extend_from_slice(&id.to_le_bytes());
+        cue.extend_from_slice(&position.to_le_bytes());
+        cue.extend_from_slice(b"data");
+        // Chunk start & block start, zero for uncompressed files without a wave list
+        cue.extend_from_slice(&0_u32.to_le_bytes());
+        cue.extend_from_slice(&0_u32.to_le_bytes());
+        cue.extend_from_slice(&position.to_le_bytes());
+
+        let label_size = 4 + marker.label.len() + 1;
+        adtl.extend_from_slice(b"labl");
+        adtl.extend_from_slice(&(label_size as u32).to_le_bytes());
+        adtl.extend_from_slice(&id.to_le_bytes());
+        adtl.extend_from_slice(marker.label.as_bytes());
+        adtl.push(0);
+        if label_size % 2 == 1 {
+            adtl.push(0);
+        }
+    }
+
+    (cue, adtl)
+}
+
+fn push_fixed_str(chunk: &mut Vec<u8>, value: &str, size: usize) {
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(size);
+    chunk.extend_from_slice(&bytes[..len]);
+    chunk.resize(chunk.len() + size - len, 0);
+}
+
+fn push_element(xml: &mut String, depth: usize, name: &str, value: &str) {
+    for _ in 0..depth {
+        xml.push_str("  ");
+    }
+    xml.push('<');
+    xml.push_str(name);
+    xml.push('>');
+    for c in value.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            '\'' => xml.push_str("&apos;"),
+            c => xml.push(c),
+        }
+    }
+    xml.push_str("</");
+    xml.push_str(name);
+    xml.push_str(">\n");
+}
@@ -0,0 +1,497 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fs, io};
+
+use audio_processor_traits::{AudioBuffer, AudioProcessorSettings};
+
+pub use self::dither::DitherMode;
+use self::dither::Quantizer;
+pub use self::metadata::{BwfMetadata, CueMarker, IxmlMetadata};
+pub use self::output_file_error::OutputFileError;
+use self::wav_writer::{WavSpec, WavWriter};
+
+pub(crate) mod dither;
+mod metadata;
+mod output_file_error;
+mod wav_writer;
+
+/// Sample format of the written file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    #[default]
+    Float32,
+}
+
+impl OutputSampleFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            OutputSampleFormat::Int16 => 16,
+            OutputSampleFormat::Int24 => 24,
+            OutputSampleFormat::Int32 | OutputSampleFormat::Float32 => 32,
+        }
+    }
+}
+
+impl FromStr for OutputSampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16" => Ok(OutputSampleFormat::Int16),
+            "24" => Ok(OutputSampleFormat::Int24),
+            "32" => Ok(OutputSampleFormat::Int32),
+            "32f" => Ok(OutputSampleFormat::Float32),
+            _ => Err(format!(
+                "Unknown sample format '{}', expected one of 16, 24, 32 or 32f",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings for [`OutputAudioFileProcessor`]. Use [`OutputFileSettings::new`] for defaults of 32-bit
+/// float output without metadata.
+#[derive(Debug, Clone)]
+pub struct OutputFileSettings {
+    pub audio_file_path: String,
+    pub format: OutputSampleFormat,
+    /// Dither applied to 16 and 24-bit output
+    pub dither: DitherMode,
+    pub bwf: Option<BwfMetadata>,
+    pub ixml: Option<IxmlMetadata>,
+    pub markers: Vec<CueMarker>,
+    /// How often to patch the header sizes & flush to disk, so a crashed render still leaves a
+    /// readable file. `None` only writes the header on finalize
+    pub header_flush_interval: Option<Duration>,
+}
+
+impl OutputFileSettings {
+    pub fn new(audio_file_path: impl Into<String>) -> Self {
+        Self {
+            audio_file_path: audio_file_path.into(),
+            format: OutputSampleFormat::default(),
+            dither: DitherMode::default(),
+            bwf: None,
+            ixml: None,
+            markers: vec![],
+            header_flush_interval: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+/// Writes `wav` files.
+///
+/// Call [`OutputAudioFileProcessor::prepare`] to create the file, then
+/// [`OutputAudioFileProcessor::process`] with each block. The file is finalized on drop, but
+/// [`OutputAudioFileProcessor::finalize`] should be called to find out about errors.
+pub struct OutputAudioFileProcessor {
+    audio_settings: AudioProcessorSettings,
+    output_file_settings: OutputFileSettings,
+    writer: Option<WavWriter<io::BufWriter<fs::File>>>,
+    quantizer: Option<Quantizer>,
+    markers: Vec<CueMarker>,
+    position: usize,
+    frames_since_flush: usize,
+}
+
+impl OutputAudioFileProcessor {
+    pub fn from_path(audio_settings: AudioProcessorSettings, audio_file_path: &str) -> Self {
+        Self::new(audio_settings, OutputFileSettings::new(audio_file_path))
+    }
+
+    pub fn new(
+        audio_settings: AudioProcessorSettings,
+        output_file_settings: OutputFileSettings,
+    ) -> Self {
+        OutputAudioFileProcessor {
+            audio_settings,
+            output_file_settings,
+            writer: None,
+            quantizer: None,
+            markers: vec![],
+            position: 0,
+            frames_since_flush: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &OutputFileSettings {
+        &self.output_file_settings
+    }
+
+    /// Number of frames written so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Add a cue marker, written when the file is finalized
+    pub fn add_marker(&mut self, marker: CueMarker) {
+        self.markers.push(marker);
+    }
+}
+
+impl OutputAudioFileProcessor {
+    /// Create the output file, finalizing any file previously being written.
+    pub fn prepare(&mut self, settings: AudioProcessorSettings) -> Result<(), OutputFileError> {
+        self.finalize()?;
+
+        self.audio_settings = settings;
+        let sample_rate = settings.sample_rate() as u32;
+        let format = self.output_file_settings.format;
+        log::info!(
+            "Wav file will be written with sample rate: {} format: {:?}",
+            sample_rate,
+            format
+        );
+        let spec = WavSpec {
+            channels: settings.output_channels() as u16,
+            sample_rate,
+            format,
+        };
+
+        let ixml = self
+            .output_file_settings
+            .ixml
+            .as_ref()
+            .map(|ixml| ixml.to_xml(settings.output_channels()));
+        let file = fs::File::create(&self.output_file_settings.audio_file_path)?;
+        self.writer = Some(WavWriter::new(
+            io::BufWriter::new(file),
+            spec,
+            self.output_file_settings.bwf.as_ref(),
+            ixml.as_deref(),
+        )?);
+        self.quantizer = match format {
+            OutputSampleFormat::Int16 | OutputSampleFormat::Int24 => Some(Quantizer::new(
+                format.bits_per_sample(),
+                self.output_file_settings.dither,
+                settings.output_channels(),
+            )),
+            // f32 samples only carry 24 bits of precision, so there's nothing to dither
+            OutputSampleFormat::Int32 => Some(Quantizer::new(
+                format.bits_per_sample(),
+                DitherMode::None,
+                settings.output_channels(),
+            )),
+            OutputSampleFormat::Float32 => None,
+        };
+        self.markers = self.output_file_settings.markers.clone();
+        self.position = 0;
+        self.frames_since_flush = 0;
+
+        Ok(())
+    }
+
+    /// Write a block. Channels missing from `data` are written as silence and extra channels are
+    /// ignored.
+    pub fn process(&mut self, data: &mut AudioBuffer<f32>) -> Result<(), OutputFileError> {
+        self.write_buffer(data)
+    }
+
+    pub(crate) fn write_buffer(&mut self, data: &AudioBuffer<f32>) -> Result<(), OutputFileError> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) if !writer.is_finalized() => writer,
+            _ => return Ok(()),
+        };
+
+        let num_channels = writer.spec().channels as usize;
+        for sample_num in 0..data.num_samples() {
+            for channel_num in 0..num_channels {
+                let sample = if channel_num < data.num_channels() {
+                    *data.get(channel_num, sample_num)
+                } else {
+                    0.0
+                };
+
+                if let Some(quantizer) = self.quantizer.as_mut() {
+                    writer.write_int(quantizer.quantize(channel_num, sample))?;
+                } else {
+                    writer.write_float(sample)?;
+                }
+            }
+        }
+
+        self.position += data.num_samples();
+        self.frames_since_flush += data.num_samples();
+        if let Some(interval) = self.output_file_settings.header_flush_interval {
+            let interval_frames =
+                (interval.as_secs_f32() * self.audio_settings.sample_rate()) as usize;
+            if self.frames_since_flush >= interval_frames {
+                self.frames_since_flush = 0;
+                writer.flush_header()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the markers & final header. Subsequent calls to `process` are ignored until
+    /// `prepare` is called again.
+    pub fn finalize(&mut self) -> Result<(), OutputFileError> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.finalize(&self.markers)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputAudioFileProcessor {
+    fn drop(&mut self) {
+        if let Err(err) = self.finalize() {
+            log::error!("Failed to finalize output file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    fn settings(num_channels: usize) -> AudioProcessorSettings {
+        AudioProcessorSettings {
+            input_channels: num_channels,
+            output_channels: num_channels,
+            ..AudioProcessorSettings::default()
+        }
+    }
+
+    fn ramp(num_channels: usize, num_samples: usize) -> AudioBuffer<f32> {
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(num_channels, num_samples);
+        for channel in 0..num_channels {
+            for sample in 0..num_samples {
+                let value = (sample as f32 / num_samples as f32) * 2.0 - 1.0;
+                buffer.set(channel, sample, value * 0.5);
+            }
+        }
+        buffer
+    }
+
+    /// Read the top-level RIFF chunks of a file
+    fn read_chunks(path: &Path) -> Vec<([u8; 4], Vec<u8>)> {
+        let contents = fs::read(path).unwrap();
+        assert_eq!(&contents[0..4], b"RIFF");
+        let riff_size = u32::from_le_bytes(contents[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size, contents.len() - 8);
+        assert_eq!(&contents[8..12], b"WAVE");
+
+        let mut chunks = vec![];
+        let mut cursor = 12;
+        while cursor < contents.len() {
+            let id: [u8; 4] = contents[cursor..cursor + 4].try_into().unwrap();
+            let size =
+                u32::from_le_bytes(contents[cursor + 4..cursor + 8].try_into().unwrap()) as usize;
+            chunks.push((id, contents[cursor + 8..cursor + 8 + size].to_vec()));
+            cursor += 8 + size + size % 2;
+        }
+        chunks
+    }
+
+    fn write_file(path: &Path, output_file_settings: OutputFileSettings) {
+        let settings = settings(2);
+        let mut processor = OutputAudioFileProcessor::new(settings, output_file_settings);
+        processor.prepare(settings).unwrap();
+        processor.process(&mut ramp(2, 1000)).unwrap();
+        processor.finalize().unwrap();
+        assert_eq!(processor.position(), 1000);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_write_each_format() {
+        let dir = tempdir::TempDir::new("output_file").unwrap();
+        for (format, bits, sample_format) in [
+            (OutputSampleFormat::Int16, 16, hound::SampleFormat::Int),
+            (OutputSampleFormat::Int24, 24, hound::SampleFormat::Int),
+            (OutputSampleFormat::Int32, 32, hound::SampleFormat::Int),
+            (OutputSampleFormat::Float32, 32, hound::SampleFormat::Float),
+        ] {
+            let path = dir.path().join(format!("{:?}.wav", format));
+            write_file(
+                &path,
+                OutputFileSettings {
+                    format,
+                    ..OutputFileSettings::new(path.to_str().unwrap())
+                },
+            );
+
+            let mut reader = hound::WavReader::open(&path).unwrap();
+            let spec = reader.spec();
+            assert_eq!(spec.bits_per_sample, bits);
+            assert_eq!(spec.sample_format, sample_format);
+            assert_eq!(spec.channels, 2);
+            assert_eq!(reader.duration(), 1000);
+
+            let expected = ramp(2, 1000);
+            let samples: Vec<f32> = match sample_format {
+                hound::SampleFormat::Float => reader.samples::<f32>().map(|s| s.unwrap()).collect(),
+                hound::SampleFormat::Int => {
+                    let scale = (1_i64 << (bits - 1)) as f32;
+                    reader
+                        .samples::<i32>()
+                        .map(|s| s.unwrap() as f32 / scale)
+                        .collect()
+                }
+            };
+            let tolerance = 2.0 / (1_i64 << (bits - 1)) as f32;
+            for (index, sample) in samples.iter().enumerate() {
+                let expected = *expected.get(index % 2, index / 2);
+                assert!((sample - expected).abs() <= tolerance.max(1e-7));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_metadata_chunks() {
+        let dir = tempdir::TempDir::new("output_file").unwrap();
+        let path = dir.path().join("metadata.wav");
+        let bwf = BwfMetadata {
+            description: "Bounce".to_string(),
+            originator: "augmented".to_string(),
+            origination_date: "2022-01-01".to_string(),
+            origination_time: "10:00:00".to_string(),
+            time_reference: 44100,
+            ..BwfMetadata::default()
+        };
+        let ixml = IxmlMetadata {
+            project: "Test & Project".to_string(),
+            track_names: vec!["Kick".to_string()],
+            ..IxmlMetadata::default()
+        };
+        write_file(
+            &path,
+            OutputFileSettings {
+                format: OutputSampleFormat::Int24,
+                bwf: Some(bwf),
+                ixml: Some(ixml),
+                markers: vec![CueMarker::new(10, "Intro"), CueMarker::new(500, "Verse")],
+                ..OutputFileSettings::new(path.to_str().unwrap())
+            },
+        );
+
+        let chunks = read_chunks(&path);
+        let ids: Vec<&[u8; 4]> = chunks.iter().map(|(id, _)| id).collect();
+        assert_eq!(
+            ids,
+            vec![b"bext", b"iXML", b"fmt ", b"data", b"cue ", b"LIST"]
+        );
+
+        let bext = &chunks[0].1;
+        assert_eq!(bext.len(), 602);
+        assert_eq!(&bext[0..6], b"Bounce");
+        assert_eq!(&bext[256..265], b"augmented");
+        assert_eq!(&bext[320..330], b"2022-01-01");
+        assert_eq!(
+            u64::from_le_bytes(bext[338..346].try_into().unwrap()),
+            44100
+        );
+
+        let ixml = String::from_utf8(chunks[1].1.clone()).unwrap();
+        assert!(ixml.contains("<PROJECT>Test &amp; Project</PROJECT>"));
+        assert!(ixml.contains("<TRACK_COUNT>2</TRACK_COUNT>"));
+        assert!(ixml.contains("<NAME>Kick</NAME>"));
+        assert!(ixml.contains("<NAME>Track 2</NAME>"));
+
+        assert_eq!(chunks[3].1.len(), 1000 * 2 * 3);
+
+        let cue = &chunks[4].1;
+        assert_eq!(u32::from_le_bytes(cue[0..4].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(cue[8..12].try_into().unwrap()), 10);
+        assert_eq!(u32::from_le_bytes(cue[32..36].try_into().unwrap()), 500);
+
+        let adtl = &chunks[5].1;
+        assert_eq!(&adtl[0..4], b"adtl");
+        assert_eq!(&adtl[4..8], b"labl");
+        assert_eq!(&adtl[16..21], b"Intro");
+
+        // Other readers skip the extra chunks
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration(), 1000);
+    }
+
+    #[test]
+    fn test_added_markers_are_written() {
+        let dir = tempdir::TempDir::new("output_file").unwrap();
+        let path = dir.path().join("markers.wav");
+        let settings = settings(1);
+        let mut processor = OutputAudioFileProcessor::from_path(settings, path.to_str().unwrap());
+        processor.prepare(settings).unwrap();
+        processor.process(&mut ramp(1, 333)).unwrap();
+        processor.add_marker(CueMarker::new(processor.position(), "Drop"));
+        processor.process(&mut ramp(1, 100)).unwrap();
+        drop(processor);
+
+        let chunks = read_chunks(&path);
+        let (_, cue) = chunks.iter().find(|(id, _)| id == b"cue ").unwrap();
+        assert_eq!(u32::from_le_bytes(cue[8..12].try_into().unwrap()), 333);
+    }
+
+    #[test]
+    fn test_header_is_flushed_while_writing() {
+        let dir = tempdir::TempDir::new("output_file").unwrap();
+        let path = dir.path().join("flushed.wav");
+        let settings = settings(2);
+        let mut processor = OutputAudioFileProcessor::new(
+            settings,
+            OutputFileSettings {
+                header_flush_interval: Some(Duration::from_millis(10)),
+                ..OutputFileSettings::new(path.to_str().unwrap())
+            },
+        );
+        processor.prepare(settings).unwrap();
+        for _ in 0..10 {
+            processor.process(&mut ramp(2, 512)).unwrap();
+        }
+
+        // The file is readable before the processor is finalized
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration(), 10 * 512);
+    }
+
+    #[test]
+    fn test_prepare_reports_errors() {
+        let dir = tempdir::TempDir::new("output_file").unwrap();
+        let path = dir.path().join("missing").join("file.wav");
+        let settings = settings(2);
+        let mut processor = OutputAudioFileProcessor::from_path(settings, path.to_str().unwrap());
+        assert!(matches!(
+            processor.prepare(settings),
+            Err(OutputFileError::FileWriteError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_sample_format() {
+        assert_eq!("16".parse(), Ok(OutputSampleFormat::Int16));
+        assert_eq!("24".parse(), Ok(OutputSampleFormat::Int24));
+        assert_eq!("32".parse(), Ok(OutputSampleFormat::Int32));
+        assert_eq!("32f".parse(), Ok(OutputSampleFormat::Float32));
+        assert!("8".parse::<OutputSampleFormat>().is_err());
+    }
+}
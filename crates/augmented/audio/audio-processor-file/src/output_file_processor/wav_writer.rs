@@ -0,0 +1,191 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::io::{Seek, SeekFrom, Write};
+
+use super::metadata::{cue_chunks, BwfMetadata, CueMarker};
+use super::{OutputFileError, OutputSampleFormat};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WavSpec {
+    pub(crate) channels: u16,
+    pub(crate) sample_rate: u32,
+    pub(crate) format: OutputSampleFormat,
+}
+
+/// Minimal RIFF/WAVE writer that supports the extra chunks `hound` can't write.
+///
+/// The RIFF and `data` sizes are patched in by [`WavWriter::flush_header`], so a file is
+/// readable up to the last flush even if the process never gets to [`WavWriter::finalize`].
+pub(crate) struct WavWriter<W: Write + Seek> {
+    inner: W,
+    spec: WavSpec,
+    data_size_position: u64,
+    data_bytes: u64,
+    is_finalized: bool,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub(crate) fn new(
+        mut inner: W,
+        spec: WavSpec,
+        bwf: Option<&BwfMetadata>,
+        ixml: Option<&str>,
+    ) -> Result<Self, OutputFileError> {
+        inner.write_all(b"RIFF")?;
+        inner.write_all(&0_u32.to_le_bytes())?;
+        inner.write_all(b"WAVE")?;
+
+        if let Some(bwf) = bwf {
+            write_text_chunk(&mut inner, b"bext", bwf.to_chunk(), 0)?;
+        }
+        if let Some(ixml) = ixml {
+            write_text_chunk(&mut inner, b"iXML", ixml.as_bytes().to_vec(), b' ')?;
+        }
+
+        let bits_per_sample = spec.format.bits_per_sample();
+        let block_align = spec.channels * bits_per_sample / 8;
+        let format_tag = if spec.format == OutputSampleFormat::Float32 {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        let mut fmt = Vec::with_capacity(16);
+        fmt.extend_from_slice(&format_tag.to_le_bytes());
+        fmt.extend_from_slice(&spec.channels.to_le_bytes());
+        fmt.extend_from_slice(&spec.sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(spec.sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+        write_chunk(&mut inner, b"fmt ", &fmt)?;
+
+        inner.write_all(b"data")?;
+        inner.write_all(&0_u32.to_le_bytes())?;
+        let data_size_position = inner.stream_position()? - 4;
+
+        let mut writer = Self {
+            inner,
+            spec,
+            data_size_position,
+            data_bytes: 0,
+            is_finalized: false,
+        };
+        writer.flush_header()?;
+        Ok(writer)
+    }
+
+    pub(crate) fn spec(&self) -> &WavSpec {
+        &self.spec
+    }
+
+    /// Write one integer sample, `value` must fit in the format's bit depth
+    pub(crate) fn write_int(&mut self, value: i32) -> Result<(), OutputFileError> {
+        let bytes = value.to_le_bytes();
+        let num_bytes = self.spec.format.bits_per_sample() as usize / 8;
+        self.write_bytes(&bytes[..num_bytes])
+    }
+
+    pub(crate) fn write_float(&mut self, value: f32) -> Result<(), OutputFileError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), OutputFileError> {
+        if self.data_size_position + 4 + self.data_bytes + bytes.len() as u64 > u32::MAX as u64 {
+            return Err(OutputFileError::FileTooLargeError);
+        }
+        self.inner.write_all(bytes)?;
+        self.data_bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Patch the header sizes to cover everything written so far and flush to disk
+    pub(crate) fn flush_header(&mut self) -> Result<(), OutputFileError> {
+        let end = self.data_size_position + 4 + self.data_bytes;
+        self.patch_sizes(end)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// Write the trailing chunks and final sizes. Further writes are ignored.
+    pub(crate) fn finalize(&mut self, markers: &[CueMarker]) -> Result<(), OutputFileError> {
+        if self.is_finalized {
+            return Ok(());
+        }
+        self.is_finalized = true;
+
+        if self.data_bytes % 2 == 1 {
+            self.inner.write_all(&[0])?;
+        }
+        if !markers.is_empty() {
+            let (cue, adtl) = cue_chunks(markers);
+            write_chunk(&mut self.inner, b"cue ", &cue)?;
+            write_chunk(&mut self.inner, b"LIST", &adtl)?;
+        }
+
+        let end = self.inner.stream_position()?;
+        self.patch_sizes(end)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn is_finalized(&self) -> bool {
+        self.is_finalized
+    }
+
+    fn patch_sizes(&mut self, end: u64) -> Result<(), OutputFileError> {
+        let riff_size = u32::try_from(end - 8).map_err(|_| OutputFileError::FileTooLargeError)?;
+        self.inner.seek(SeekFrom::Start(4))?;
+        self.inner.write_all(&riff_size.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(self.data_size_position))?;
+        self.inner
+            .write_all(&(self.data_bytes as u32).to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+/// Text chunks are padded inside the body rather than with a RIFF pad byte, as some readers
+/// (`hound` among them) skip chunks without accounting for the pad byte
+fn write_text_chunk(
+    writer: &mut impl Write,
+    id: &[u8; 4],
+    mut body: Vec<u8>,
+    padding: u8,
+) -> std::io::Result<()> {
+    if body.len() % 2 == 1 {
+        body.push(padding);
+    }
+    write_chunk(writer, id, &body)
+}
+
+fn write_chunk(writer: &mut impl Write, id: &[u8; 4], body: &[u8]) -> std::io::Result<()> {
+    writer.write_all(id)?;
+    writer.write_all(&(body.len() as u32).to_le_bytes())?;
+    writer.write_all(body)?;
+    if body.len() % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+    Ok(())
+}
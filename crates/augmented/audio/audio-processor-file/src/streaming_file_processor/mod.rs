@@ -0,0 +1,583 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Disk-streaming file playback.
+//!
+//! [`StreamingAudioFileProcessor`] plays a file without loading it onto memory. A background
+//! thread decodes the file, converts it to the output sample rate and pushes it in small chunks
+//! onto a lock-free ring buffer (see [`augmented_streams`]). The audio thread only pops from the
+//! ring buffer, so it never blocks on disk IO.
+//!
+//! The ring buffer holds [`StreamingAudioFileProcessor::set_prefetch`] worth of audio. If the
+//! reader falls behind, the processor outputs silence and counts an underrun, which is available
+//! through [`StreamingAudioFileHandle::underrun_count`].
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use audio_garbage_collector::{Handle, Shared};
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
+use augmented_streams::ConsumerActor;
+
+use crate::file_io::{self, AudioFileError};
+
+use self::reader::{StreamReader, StreamingChunk};
+
+mod reader;
+
+/// Frames sent from the reader thread at once
+const CHUNK_FRAMES: usize = 256;
+/// Channels past this are dropped
+pub const MAX_CHANNELS: usize = 8;
+/// How often the reader thread checks for room on the ring buffer & seek requests
+const READER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Marks that no loop region is set
+const NO_LOOP_REGION: usize = usize::MAX;
+
+pub struct StreamingAudioFileHandle {
+    is_playing: AtomicBool,
+    should_loop: AtomicBool,
+    loop_start: AtomicUsize,
+    loop_end: AtomicUsize,
+    /// Bumped on each seek, so chunks read before it are dropped
+    seek_generation: AtomicUsize,
+    seek_position: AtomicUsize,
+    position: AtomicUsize,
+    num_frames: AtomicUsize,
+    underruns: AtomicUsize,
+}
+
+impl Default for StreamingAudioFileHandle {
+    fn default() -> Self {
+        Self {
+            is_playing: AtomicBool::new(true),
+            should_loop: AtomicBool::new(true),
+            loop_start: AtomicUsize::new(0),
+            loop_end: AtomicUsize::new(NO_LOOP_REGION),
+            seek_generation: AtomicUsize::new(0),
+            seek_position: AtomicUsize::new(0),
+            position: AtomicUsize::new(0),
+            num_frames: AtomicUsize::new(0),
+            underruns: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl StreamingAudioFileHandle {
+    /// Resume playback
+    pub fn play(&self) {
+        self.is_playing.store(true, Ordering::Relaxed);
+    }
+
+    /// Pause playback
+    pub fn pause(&self) {
+        self.is_playing.store(false, Ordering::Relaxed);
+    }
+
+    /// Stop playback and go back to the start of the file
+    pub fn stop(&self) {
+        self.is_playing.store(false, Ordering::Relaxed);
+        self.seek(0);
+    }
+
+    /// Whether the file is being played back
+    pub fn is_playing(&self) -> bool {
+        self.is_playing.load(Ordering::Relaxed)
+    }
+
+    pub fn set_should_loop(&self, should_loop: bool) {
+        self.should_loop.store(should_loop, Ordering::Relaxed);
+    }
+
+    pub fn should_loop(&self) -> bool {
+        self.should_loop.load(Ordering::Relaxed)
+    }
+
+    /// Loop between `start` and `end`, in frames at the output sample rate, when looping is on.
+    /// Regions where `start >= end` are ignored.
+    ///
+    /// Audio which has already been prefetched will still be played, so the new region takes
+    /// effect after up-to the prefetch duration.
+    pub fn set_loop_region(&self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        self.loop_end.store(NO_LOOP_REGION, Ordering::Relaxed);
+        self.loop_start.store(start, Ordering::Relaxed);
+        self.loop_end.store(end, Ordering::Relaxed);
+    }
+
+    /// Loop over the whole file
+    pub fn clear_loop_region(&self) {
+        self.loop_end.store(NO_LOOP_REGION, Ordering::Relaxed);
+        self.loop_start.store(0, Ordering::Relaxed);
+    }
+
+    pub fn loop_region(&self) -> Option<(usize, usize)> {
+        let end = self.loop_end.load(Ordering::Relaxed);
+        let start = self.loop_start.load(Ordering::Relaxed);
+        if end == NO_LOOP_REGION || start >= end {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+
+    /// Jump to `position`, in frames at the output sample rate. Playback is silent until the
+    /// reader thread has read audio from the new position.
+    pub fn seek(&self, position: usize) {
+        self.seek_position.store(position, Ordering::Relaxed);
+        self.position.store(position, Ordering::Relaxed);
+        self.seek_generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Playback position in frames at the output sample rate
+    pub fn position(&self) -> usize {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    /// Length of the file in frames at the output sample rate, if known
+    pub fn num_frames(&self) -> Option<usize> {
+        match self.num_frames.load(Ordering::Relaxed) {
+            0 => None,
+            num_frames => Some(num_frames),
+        }
+    }
+
+    /// Number of blocks where the reader thread didn't keep up with playback
+    pub fn underrun_count(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
+    }
+}
+
+struct ReaderThread {
+    is_closed: Arc<AtomicBool>,
+    join_handle: JoinHandle<()>,
+}
+
+impl ReaderThread {
+    fn close(&self) {
+        self.is_closed.store(true, Ordering::Relaxed);
+        self.join_handle.thread().unpark();
+    }
+}
+
+/// An audio processor which streams a file from disk
+pub struct StreamingAudioFileProcessor {
+    path: PathBuf,
+    prefetch: Duration,
+    handle: Shared<StreamingAudioFileHandle>,
+    reader: Option<ReaderThread>,
+    consumer: Option<ConsumerActor<StreamingChunk>>,
+    chunk: Option<StreamingChunk>,
+    chunk_offset: usize,
+    /// Whether audio has been received since the last seek, until then an empty ring buffer is
+    /// not an underrun
+    is_primed: bool,
+}
+
+impl StreamingAudioFileProcessor {
+    /// Create a processor for the file at `path`. The file is probed, but it's only read once the
+    /// processor is prepared.
+    pub fn from_path(handle: &Handle, path: impl AsRef<Path>) -> Result<Self, AudioFileError> {
+        let path = path.as_ref().to_path_buf();
+        file_io::default_read_audio_file(&path.to_string_lossy())?;
+
+        Ok(Self {
+            path,
+            prefetch: Duration::from_secs(2),
+            handle: Shared::new(handle, StreamingAudioFileHandle::default()),
+            reader: None,
+            consumer: None,
+            chunk: None,
+            chunk_offset: 0,
+            is_primed: false,
+        })
+    }
+
+    pub fn handle(&self) -> &Shared<StreamingAudioFileHandle> {
+        &self.handle
+    }
+
+    /// How much audio is read ahead of playback. Takes effect on the next `prepare` call.
+    pub fn set_prefetch(&mut self, prefetch: Duration) {
+        self.prefetch = prefetch;
+    }
+
+    pub fn prefetch(&self) -> Duration {
+        self.prefetch
+    }
+
+    /// Number of frames read ahead of playback and ready to be played
+    pub fn buffered_frames(&self) -> usize {
+        let current = self
+            .chunk
+            .as_ref()
+            .map(|chunk| chunk.num_frames - self.chunk_offset)
+            .unwrap_or(0);
+        let queued = self
+            .consumer
+            .as_ref()
+            .map(|consumer| consumer.len() * CHUNK_FRAMES)
+            .unwrap_or(0);
+        current + queued
+    }
+
+    fn close_reader(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            reader.close();
+        }
+    }
+
+    fn start_reader(&mut self, sample_rate: f32) -> Result<(), AudioFileError> {
+        let probe_result = file_io::default_read_audio_file(&self.path.to_string_lossy())?;
+        let stream_reader =
+            StreamReader::new(self.handle.clone(), probe_result, sample_rate as u32)?;
+
+        let capacity = ((self.prefetch.as_secs_f32() * sample_rate) as usize / CHUNK_FRAMES).max(2);
+        let (mut producer, consumer) = augmented_streams::actors(stream_reader, capacity);
+
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let join_handle = std::thread::Builder::new()
+            .name(String::from("streaming-file-reader"))
+            .spawn({
+                let is_closed = is_closed.clone();
+                move || {
+                    while !is_closed.load(Ordering::Relaxed) {
+                        if producer.fill() == 0 {
+                            std::thread::park_timeout(READER_POLL_INTERVAL);
+                        }
+                    }
+                }
+            })?;
+
+        self.consumer = Some(consumer);
+        self.reader = Some(ReaderThread {
+            is_closed,
+            join_handle,
+        });
+        Ok(())
+    }
+
+    /// Pop chunks until one for the current seek request is found
+    fn next_chunk(&mut self, generation: usize) -> Option<StreamingChunk> {
+        let consumer = self.consumer.as_mut()?;
+        while let Some(chunk) = consumer.pop() {
+            if chunk.generation == generation {
+                return Some(chunk);
+            }
+        }
+        None
+    }
+}
+
+impl Drop for StreamingAudioFileProcessor {
+    fn drop(&mut self) {
+        self.close_reader();
+    }
+}
+
+impl AudioProcessor for StreamingAudioFileProcessor {
+    type SampleType = f32;
+
+    /// Starts the reader thread, which will start filling the ring buffer.
+    fn prepare(&mut self, context: &mut AudioContext) {
+        self.close_reader();
+        self.consumer = None;
+        self.chunk = None;
+        self.is_primed = false;
+
+        if let Err(err) = self.start_reader(context.settings.sample_rate()) {
+            log::error!("Failed to start streaming input file {}", err);
+        }
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+        if !self.handle.is_playing() {
+            return;
+        }
+
+        let generation = self.handle.seek_generation.load(Ordering::Acquire);
+        if self
+            .chunk
+            .as_ref()
+            .map(|chunk| chunk.generation != generation)
+            .unwrap_or(false)
+        {
+            self.chunk = None;
+            self.is_primed = false;
+        }
+
+        let mut position = None;
+        for sample_num in 0..data.num_samples() {
+            if self.chunk.is_none() {
+                match self.next_chunk(generation) {
+                    Some(chunk) => {
+                        self.chunk = Some(chunk);
+                        self.chunk_offset = 0;
+                        self.is_primed = true;
+                    }
+                    None => {
+                        if self.is_primed {
+                            self.handle.underruns.fetch_add(1, Ordering::Relaxed);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            let chunk = self.chunk.as_ref().unwrap();
+            if chunk.is_end {
+                self.chunk = None;
+                self.is_primed = false;
+                self.handle.stop();
+                return;
+            }
+
+            for channel_index in 0..data.num_channels() {
+                data.channel_mut(channel_index)[sample_num] +=
+                    chunk.get(self.chunk_offset, channel_index);
+            }
+
+            self.chunk_offset += 1;
+            position = Some(chunk.position + self.chunk_offset);
+            if self.chunk_offset >= chunk.num_frames {
+                self.chunk = None;
+            }
+        }
+
+        // Don't overwrite the play-head if there was a seek while processing
+        if let Some(position) = position {
+            if self.handle.seek_generation.load(Ordering::Acquire) == generation {
+                self.handle.position.store(position, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use audio_garbage_collector::GarbageCollector;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    /// Write a mono file where each frame holds `(index + 1) / num_frames`, so that output samples
+    /// can be mapped back into file positions
+    fn ramp_file(dir: &Path, sample_rate: u32, num_frames: usize) -> PathBuf {
+        let path = dir.join("ramp.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for index in 0..num_frames {
+            writer.write_sample(ramp_value(index, num_frames)).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    fn ramp_value(index: usize, num_frames: usize) -> f32 {
+        (index + 1) as f32 / num_frames as f32
+    }
+
+    fn setup(
+        garbage_collector: &GarbageCollector,
+        path: &Path,
+    ) -> (StreamingAudioFileProcessor, AudioContext) {
+        wisual_logger::init_from_env();
+        let processor =
+            StreamingAudioFileProcessor::from_path(garbage_collector.handle(), path).unwrap();
+        let context = AudioContext::from(AudioProcessorSettings::default());
+        (processor, context)
+    }
+
+    /// Render `num_frames` of channel 0, skipping the silence before the reader catches up
+    fn render(
+        processor: &mut StreamingAudioFileProcessor,
+        context: &mut AudioContext,
+        num_frames: usize,
+    ) -> Vec<f32> {
+        let start = Instant::now();
+        let mut output = vec![];
+        while output.len() < num_frames {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            if !output.is_empty() {
+                while processor.buffered_frames() < 2 * CHUNK_FRAMES {
+                    assert!(start.elapsed() < Duration::from_secs(10));
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+
+            let mut buffer = AudioBuffer::empty();
+            buffer.resize(2, CHUNK_FRAMES);
+            processor.process(context, &mut buffer);
+
+            let channel = buffer.channel(0);
+            let skip = if output.is_empty() {
+                channel
+                    .iter()
+                    .position(|sample| *sample != 0.0)
+                    .unwrap_or(channel.len())
+            } else {
+                0
+            };
+            output.extend_from_slice(&channel[skip..]);
+            if output.is_empty() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        output.truncate(num_frames);
+        output
+    }
+
+    #[test]
+    fn test_streams_the_file_in_order() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let num_frames = 20000;
+        let path = ramp_file(dir.path(), 44100, num_frames);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.prepare(&mut context);
+
+        let output = render(&mut processor, &mut context, 4096);
+        for (index, sample) in output.iter().enumerate() {
+            assert!((sample - ramp_value(index, num_frames)).abs() < 1e-6);
+        }
+        assert_eq!(processor.handle().num_frames(), Some(num_frames));
+        assert_eq!(processor.handle().underrun_count(), 0);
+    }
+
+    #[test]
+    fn test_seek() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let num_frames = 20000;
+        let path = ramp_file(dir.path(), 44100, num_frames);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.prepare(&mut context);
+        render(&mut processor, &mut context, 512);
+
+        processor.handle().seek(10000);
+        let output = render(&mut processor, &mut context, 512);
+        for (index, sample) in output.iter().enumerate() {
+            assert!((sample - ramp_value(10000 + index, num_frames)).abs() < 1e-6);
+        }
+        assert_eq!(processor.handle().position(), 10512);
+    }
+
+    #[test]
+    fn test_loop_region() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let num_frames = 20000;
+        let path = ramp_file(dir.path(), 44100, num_frames);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.handle().set_loop_region(1000, 1300);
+        processor.handle().seek(1000);
+        processor.prepare(&mut context);
+
+        let output = render(&mut processor, &mut context, 900);
+        for (index, sample) in output.iter().enumerate() {
+            let expected = ramp_value(1000 + index % 300, num_frames);
+            assert!((sample - expected).abs() < 1e-6, "{} {}", index, sample);
+        }
+    }
+
+    #[test]
+    fn test_loops_the_whole_file() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let num_frames = 1000;
+        let path = ramp_file(dir.path(), 44100, num_frames);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.prepare(&mut context);
+
+        let output = render(&mut processor, &mut context, 2500);
+        for (index, sample) in output.iter().enumerate() {
+            let expected = ramp_value(index % num_frames, num_frames);
+            assert!((sample - expected).abs() < 1e-6, "{} {}", index, sample);
+        }
+    }
+
+    #[test]
+    fn test_stops_at_the_end_without_loop() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let path = ramp_file(dir.path(), 44100, 1000);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.handle().set_should_loop(false);
+        processor.prepare(&mut context);
+
+        render(&mut processor, &mut context, 1000);
+        let start = Instant::now();
+        while processor.handle().is_playing() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            let mut buffer = AudioBuffer::empty();
+            buffer.resize(2, CHUNK_FRAMES);
+            processor.process(&mut context, &mut buffer);
+        }
+        assert_eq!(processor.handle().position(), 0);
+    }
+
+    #[test]
+    fn test_underruns_are_reported() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let path = ramp_file(dir.path(), 44100, 88200);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.set_prefetch(Duration::from_millis(1));
+        processor.prepare(&mut context);
+        render(&mut processor, &mut context, 256);
+
+        // Much more than the ring buffer holds, in one go
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 44100);
+        processor.process(&mut context, &mut buffer);
+        assert!(processor.handle().underrun_count() > 0);
+    }
+
+    #[test]
+    fn test_converts_the_file_sample_rate() {
+        let garbage_collector = GarbageCollector::default();
+        let dir = tempdir::TempDir::new("streaming").unwrap();
+        let path = ramp_file(dir.path(), 22050, 22050);
+        let (mut processor, mut context) = setup(&garbage_collector, &path);
+        processor.prepare(&mut context);
+
+        assert_eq!(processor.handle().num_frames(), Some(44100));
+        let output = render(&mut processor, &mut context, 4096);
+        // The ramp is slow enough that it's still smooth after conversion
+        for (index, sample) in output.iter().enumerate().skip(1) {
+            assert!((sample - output[index - 1]).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Background side of the streaming processor. Decodes the file through `symphonia`, converts its
+//! sample rate and splits it into [`StreamingChunk`]s.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+
+use symphonia::core::audio::Signal;
+use symphonia::core::codecs::Decoder;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::probe::ProbeResult;
+
+use audio_garbage_collector::Shared;
+use augmented_streams::ProducerProcedure;
+
+use crate::file_io::sample_rate_converter;
+use crate::file_io::sample_rate_converter::BLOCK_SIZE;
+use crate::file_io::{convert_audio_buffer_sample_type, AudioFileError};
+
+use super::{StreamingAudioFileHandle, CHUNK_FRAMES, MAX_CHANNELS};
+
+/// A block of interleaved frames, as sent from the reader thread to the audio thread
+pub(crate) struct StreamingChunk {
+    /// The seek request this chunk was read for, chunks from older requests are dropped
+    pub(crate) generation: usize,
+    /// Frame (in the output sample rate) of the first frame in this chunk
+    pub(crate) position: usize,
+    pub(crate) num_frames: usize,
+    pub(crate) num_channels: usize,
+    /// Marks the end of the file, when not looping
+    pub(crate) is_end: bool,
+    pub(crate) samples: [f32; CHUNK_FRAMES * MAX_CHANNELS],
+}
+
+impl StreamingChunk {
+    fn new(generation: usize, position: usize, num_channels: usize) -> Self {
+        Self {
+            generation,
+            position,
+            num_frames: 0,
+            num_channels,
+            is_end: false,
+            samples: [0.0; CHUNK_FRAMES * MAX_CHANNELS],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, frame: usize, channel: usize) -> f32 {
+        self.samples[frame * self.num_channels + channel.min(self.num_channels - 1)]
+    }
+}
+
+pub(crate) struct StreamReader {
+    handle: Shared<StreamingAudioFileHandle>,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    source_rate: u32,
+    output_rate: u32,
+    num_channels: usize,
+
+    converter: Option<sample_rate_converter::Decoder>,
+    /// Decoded frames waiting for a full converter block
+    converter_input: Vec<Vec<f32>>,
+    /// Output frames to drop for the converter's latency
+    skip_output: usize,
+    /// Source frames to drop after an accurate seek lands before the requested frame
+    skip_source: u64,
+
+    /// Frames ready to be sent, per channel
+    output: Vec<VecDeque<f32>>,
+    /// Output frame of the next frame to be sent
+    position: usize,
+    generation: usize,
+    is_eof: bool,
+    sent_end: bool,
+}
+
+impl StreamReader {
+    pub(crate) fn new(
+        handle: Shared<StreamingAudioFileHandle>,
+        probe_result: ProbeResult,
+        output_rate: u32,
+    ) -> Result<Self, AudioFileError> {
+        let format = probe_result.format;
+        let track = format
+            .default_track()
+            .ok_or(AudioFileError::OpenStreamError)?;
+        let decoder =
+            symphonia::default::get_codecs().make(&track.codec_params, &Default::default())?;
+        let track_id = track.id;
+        let source_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or(AudioFileError::OpenStreamError)?;
+        let num_channels = track
+            .codec_params
+            .channels
+            .map(|channels| channels.count())
+            .unwrap_or(1)
+            .clamp(1, MAX_CHANNELS);
+        if let Some(num_frames) = track.codec_params.n_frames {
+            let num_frames = num_frames as f64 * output_rate as f64 / source_rate as f64;
+            handle
+                .num_frames
+                .store(num_frames as usize, Ordering::Relaxed);
+        }
+
+        let generation = handle.seek_generation.load(Ordering::Acquire);
+        let position = handle.position.load(Ordering::Relaxed);
+        let mut reader = Self {
+            handle,
+            format,
+            decoder,
+            track_id,
+            source_rate,
+            output_rate,
+            num_channels,
+            converter: None,
+            converter_input: vec![Vec::with_capacity(BLOCK_SIZE); num_channels],
+            skip_output: 0,
+            skip_source: 0,
+            output: vec![VecDeque::new(); num_channels],
+            position: 0,
+            generation,
+            is_eof: false,
+            sent_end: false,
+        };
+        reader.reset_converter();
+        if position != 0 {
+            reader.seek(position);
+        }
+
+        Ok(reader)
+    }
+
+    fn reset_converter(&mut self) {
+        for channel in self.converter_input.iter_mut() {
+            channel.clear();
+        }
+        self.converter = None;
+        self.skip_output = 0;
+        if self.source_rate == self.output_rate {
+            return;
+        }
+
+        match sample_rate_converter::make_decoder(
+            self.source_rate,
+            self.output_rate,
+            self.num_channels,
+        ) {
+            Ok(converter) => {
+                self.converter = Some(converter);
+                self.skip_output = sample_rate_converter::LATENCY;
+            }
+            Err(err) => {
+                log::error!("Failed to create sample rate converter {:?}", err);
+            }
+        }
+    }
+
+    /// Move the reader to `position`, in output frames
+    fn seek(&mut self, position: usize) {
+        for channel in self.output.iter_mut() {
+            channel.clear();
+        }
+        self.reset_converter();
+        self.position = position;
+        self.is_eof = false;
+        self.sent_end = false;
+        self.skip_source = 0;
+
+        let ts = (position as f64 * self.source_rate as f64 / self.output_rate as f64) as u64;
+        let result = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts,
+                track_id: self.track_id,
+            },
+        );
+        self.decoder.reset();
+        match result {
+            Ok(seeked_to) => {
+                self.skip_source = seeked_to.required_ts.saturating_sub(seeked_to.actual_ts);
+            }
+            Err(err) => {
+                log::warn!("Failed to seek to frame={} {}", position, err);
+                self.is_eof = true;
+            }
+        }
+    }
+
+    fn ready_frames(&self) -> usize {
+        self.output[0].len()
+    }
+
+    /// Decode a packet onto the output queue. Marks the reader as finished once there are no more
+    /// packets.
+    fn decode_next(&mut self) {
+        let packet = match self.format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::ResetRequired) => {
+                self.decoder.reset();
+                return;
+            }
+            Err(_) => {
+                self.is_eof = true;
+                self.flush_converter();
+                return;
+            }
+        };
+
+        if packet.track_id() != self.track_id {
+            return;
+        }
+
+        let audio_buffer = match self.decoder.decode(&packet) {
+            Ok(audio_buffer) => convert_audio_buffer_sample_type(audio_buffer),
+            Err(SymphoniaError::DecodeError(err)) => {
+                log::warn!("Skipping packet which failed to decode {}", err);
+                return;
+            }
+            Err(err) => {
+                log::error!("Failed to decode file {}", err);
+                self.is_eof = true;
+                return;
+            }
+        };
+
+        let skip = (self.skip_source as usize).min(audio_buffer.frames());
+        self.skip_source -= skip as u64;
+        let buffer_channels = audio_buffer.spec().channels.count();
+
+        for channel in 0..self.num_channels {
+            let source = &audio_buffer.chan(channel.min(buffer_channels - 1))[skip..];
+            if self.converter.is_some() {
+                self.converter_input[channel].extend_from_slice(source);
+            } else {
+                self.output[channel].extend(source.iter().copied());
+            }
+        }
+
+        while self.converter_input[0].len() >= BLOCK_SIZE {
+            self.convert_block(BLOCK_SIZE);
+        }
+    }
+
+    /// Run the converter on its first `num_frames` input frames, padding them into a full block
+    fn convert_block(&mut self, num_frames: usize) {
+        let converter = match &mut self.converter {
+            Some(converter) => converter,
+            None => return,
+        };
+
+        let block: Vec<Vec<f32>> = self
+            .converter_input
+            .iter_mut()
+            .map(|channel| {
+                let mut block: Vec<f32> = channel.drain(0..num_frames).collect();
+                block.resize(BLOCK_SIZE, 0.0);
+                block
+            })
+            .collect();
+
+        let mut converted = match sample_rate_converter::process(converter, &block) {
+            Ok(converted) => converted,
+            Err(err) => {
+                log::error!("Failed to convert sample rate {:?}", err);
+                return;
+            }
+        };
+
+        // The trailing block was padded, drop the padding
+        if num_frames != BLOCK_SIZE {
+            let expected = (num_frames as f64 * self.output_rate as f64 / self.source_rate as f64)
+                as usize
+                + self.skip_output;
+            for channel in converted.iter_mut() {
+                channel.truncate(expected);
+            }
+        }
+
+        let skip = self.skip_output.min(converted[0].len());
+        self.skip_output -= skip;
+        for (output, channel) in self.output.iter_mut().zip(converted) {
+            output.extend(channel.into_iter().skip(skip));
+        }
+    }
+
+    fn flush_converter(&mut self) {
+        let remaining = self.converter_input[0].len();
+        if remaining > 0 {
+            self.convert_block(remaining);
+        }
+    }
+
+    fn loop_bounds(&self) -> Option<(usize, usize)> {
+        if !self.handle.should_loop() {
+            return None;
+        }
+        Some(self.handle.loop_region().unwrap_or((0, usize::MAX)))
+    }
+
+    fn build_chunk(&mut self, num_frames: usize) -> StreamingChunk {
+        let mut chunk = StreamingChunk::new(self.generation, self.position, self.num_channels);
+        chunk.num_frames = num_frames;
+        for frame in 0..num_frames {
+            for (channel, output) in self.output.iter_mut().enumerate() {
+                chunk.samples[frame * self.num_channels + channel] =
+                    output.pop_front().unwrap_or(0.0);
+            }
+        }
+        self.position += num_frames;
+        chunk
+    }
+}
+
+impl ProducerProcedure for StreamReader {
+    type Item = StreamingChunk;
+
+    fn pull(&mut self) -> Option<StreamingChunk> {
+        let generation = self.handle.seek_generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.generation = generation;
+            self.seek(self.handle.seek_position.load(Ordering::Relaxed));
+        }
+
+        let loop_bounds = self.loop_bounds();
+        if let Some((start, end)) = loop_bounds {
+            if self.position >= end {
+                self.seek(start);
+            }
+        }
+
+        let mut num_frames = CHUNK_FRAMES;
+        if let Some((_, end)) = loop_bounds {
+            num_frames = num_frames.min(end - self.position);
+        }
+
+        while self.ready_frames() < num_frames && !self.is_eof {
+            self.decode_next();
+        }
+
+        if self.ready_frames() == 0 {
+            match loop_bounds {
+                Some((start, _)) if self.position != start => {
+                    // Reached the end of the file before the end of the loop
+                    self.seek(start);
+                    return self.pull();
+                }
+                Some(_) => return None,
+                None if !self.sent_end => {
+                    self.sent_end = true;
+                    let mut chunk =
+                        StreamingChunk::new(self.generation, self.position, self.num_channels);
+                    chunk.is_end = true;
+                    return Some(chunk);
+                }
+                None => return None,
+            }
+        }
+
+        let num_frames = num_frames.min(self.ready_frames());
+        Some(self.build_chunk(num_frames))
+    }
+}
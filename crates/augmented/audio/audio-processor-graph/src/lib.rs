@@ -225,6 +225,26 @@ impl<P: Send + 'static + AudioProcessor> AudioProcessorGraphImpl<P> {
     ) -> Result<ConnectionIndex, AudioProcessorGraphError> {
         self.handle.add_connection(source, destination)
     }
+
+    /// Copy the last block that went through `connection` into `output`, resizing it to match.
+    /// Returns `false` if the connection doesn't exist.
+    ///
+    /// Useful to tap into the output of a node, e.g. to render stems of a mix offline.
+    pub fn copy_connection_buffer(
+        &self,
+        connection: ConnectionIndex,
+        output: &mut AudioBuffer<f32>,
+    ) -> bool {
+        let buffers = self.handle.buffers.get();
+        if let Some(buffer_ref) = buffers.get(&connection) {
+            let buffer = unsafe { &*buffer_ref.deref().0.get() };
+            output.resize(buffer.num_channels(), buffer.num_samples());
+            output.copy_from(buffer);
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl<P: AudioProcessor<SampleType = f32>> AudioProcessor for AudioProcessorGraphImpl<P> {
@@ -935,6 +955,54 @@ mod test {
         assert_eq!(output, vec![3.0, 3.0, 3.0]);
     }
 
+    #[test]
+    fn test_copy_connection_buffer_reads_node_outputs() {
+        let settings = AudioProcessorSettings::default();
+        let mut context = AudioContext::from(settings);
+        context.settings.input_channels = 1;
+        context.settings.output_channels = 1;
+
+        struct AddProcessor(f32);
+        impl MonoAudioProcessor for AddProcessor {
+            type SampleType = f32;
+            fn m_process(
+                &mut self,
+                _context: &mut AudioContext,
+                sample: Self::SampleType,
+            ) -> Self::SampleType {
+                sample + self.0
+            }
+        }
+
+        let mut graph = AudioProcessorGraph::default();
+        graph.prepare(&mut context);
+
+        let input_idx = graph.input();
+        let output_idx = graph.output();
+        let node1_idx = graph.add_node(NodeType::Simple(Box::new(MonoCopyProcessor::new(
+            AddProcessor(1.0),
+        ))));
+        let node2_idx = graph.add_node(NodeType::Simple(Box::new(MonoCopyProcessor::new(
+            AddProcessor(2.0),
+        ))));
+        graph.add_connection(input_idx, node1_idx).unwrap();
+        graph.add_connection(input_idx, node2_idx).unwrap();
+        let node1_output = graph.add_connection(node1_idx, output_idx).unwrap();
+        let node2_output = graph.add_connection(node2_idx, output_idx).unwrap();
+
+        let mut process_buffer = AudioBuffer::empty();
+        process_buffer.resize(1, 3);
+        graph.process(&mut context, &mut process_buffer);
+
+        let mut tap = AudioBuffer::empty();
+        assert!(graph.copy_connection_buffer(node1_output, &mut tap));
+        assert_eq!(tap.channel(0), [1.0, 1.0, 1.0]);
+        assert!(graph.copy_connection_buffer(node2_output, &mut tap));
+        assert_eq!(tap.channel(0), [2.0, 2.0, 2.0]);
+        assert!(!graph.copy_connection_buffer(ConnectionIndex::new(100), &mut tap));
+        assert_eq!(process_buffer.channel(0), [3.0, 3.0, 3.0]);
+    }
+
     #[test]
     fn test_process_sine_generator_in_the_graph_produces_sine() {
         type BufferType = AudioBuffer<f32>;
@@ -0,0 +1,324 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! A harmonizer, mixing several pitch-shifted voices with the input.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::simple_processor::MonoAudioProcessor;
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor};
+use augmented_atomics::AtomicEnum;
+
+use crate::{PhaseProcessingStrategyVariants, PitchShifterProcessor};
+
+pub const MAX_HARMONIZER_VOICES: usize = 4;
+
+pub struct HarmonizerVoiceHandle {
+    ratio: AtomicF32,
+    level: AtomicF32,
+}
+
+impl HarmonizerVoiceHandle {
+    fn new(semitones: f32, level: f32) -> Self {
+        let voice = Self {
+            ratio: AtomicF32::new(1.0),
+            level: AtomicF32::new(level),
+        };
+        voice.set_semitones(semitones);
+        voice
+    }
+
+    pub fn ratio(&self) -> f32 {
+        self.ratio.get()
+    }
+
+    pub fn set_ratio(&self, ratio: f32) {
+        self.ratio.set(ratio);
+    }
+
+    /// Set the voice ratio as an interval in semitones
+    pub fn set_semitones(&self, semitones: f32) {
+        self.ratio.set(2.0_f32.powf(semitones / 12.0));
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level.get()
+    }
+
+    /// Set the voice volume. Voices with a level of 0 are muted but keep processing the input,
+    /// so they don't replay stale audio once they're brought back up.
+    pub fn set_level(&self, level: f32) {
+        self.level.set(level.max(0.0));
+    }
+}
+
+pub struct HarmonizerProcessorHandle {
+    voices: [HarmonizerVoiceHandle; MAX_HARMONIZER_VOICES],
+    dry_level: AtomicF32,
+    strategy: AtomicEnum<PhaseProcessingStrategyVariants>,
+    formant_preservation: AtomicBool,
+}
+
+impl Default for HarmonizerProcessorHandle {
+    /// A major third and a fifth above the input
+    fn default() -> Self {
+        Self {
+            voices: [
+                HarmonizerVoiceHandle::new(4.0, 0.5),
+                HarmonizerVoiceHandle::new(7.0, 0.5),
+                HarmonizerVoiceHandle::new(0.0, 0.0),
+                HarmonizerVoiceHandle::new(0.0, 0.0),
+            ],
+            dry_level: AtomicF32::new(1.0),
+            strategy: AtomicEnum::new(PhaseProcessingStrategyVariants::IdentityPhaseLocking),
+            formant_preservation: AtomicBool::new(false),
+        }
+    }
+}
+
+impl HarmonizerProcessorHandle {
+    pub fn voices(&self) -> &[HarmonizerVoiceHandle] {
+        &self.voices
+    }
+
+    pub fn voice(&self, index: usize) -> &HarmonizerVoiceHandle {
+        &self.voices[index]
+    }
+
+    pub fn dry_level(&self) -> f32 {
+        self.dry_level.get()
+    }
+
+    pub fn set_dry_level(&self, level: f32) {
+        self.dry_level.set(level.max(0.0));
+    }
+
+    pub fn strategy(&self) -> PhaseProcessingStrategyVariants {
+        self.strategy.get()
+    }
+
+    pub fn set_strategy(&self, strategy: PhaseProcessingStrategyVariants) {
+        self.strategy.set(strategy);
+    }
+
+    pub fn formant_preservation(&self) -> bool {
+        self.formant_preservation.load(Ordering::Relaxed)
+    }
+
+    pub fn set_formant_preservation(&self, enabled: bool) {
+        self.formant_preservation.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Mixes up to [`MAX_HARMONIZER_VOICES`] pitch-shifted copies of the input with the input. The dry
+/// signal is delayed to line-up with the shifted voices.
+pub struct HarmonizerProcessor {
+    handle: Shared<HarmonizerProcessorHandle>,
+    /// Pitch shifters for each channel and voice
+    shifters: Vec<Vec<PitchShifterProcessor>>,
+    /// Dry signal delay line for each channel
+    dry_delay: Vec<Vec<f32>>,
+    dry_delay_cursor: usize,
+}
+
+impl Default for HarmonizerProcessor {
+    fn default() -> Self {
+        Self::from_handle(make_shared(HarmonizerProcessorHandle::default()))
+    }
+}
+
+impl HarmonizerProcessor {
+    pub fn from_handle(handle: Shared<HarmonizerProcessorHandle>) -> Self {
+        Self {
+            handle,
+            shifters: vec![],
+            dry_delay: vec![],
+            dry_delay_cursor: 0,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<HarmonizerProcessorHandle> {
+        &self.handle
+    }
+}
+
+impl AudioProcessor for HarmonizerProcessor {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        let num_channels = context.settings.output_channels();
+        self.shifters = (0..num_channels)
+            .map(|_| {
+                (0..MAX_HARMONIZER_VOICES)
+                    .map(|_| PitchShifterProcessor::default())
+                    .collect()
+            })
+            .collect();
+        for shifter in self.shifters.iter_mut().flatten() {
+            shifter.m_prepare(context);
+        }
+
+        let latency = PitchShifterProcessor::default().latency_samples();
+        self.dry_delay = vec![vec![0.0; latency]; num_channels];
+        self.dry_delay_cursor = 0;
+    }
+
+    fn process(&mut self, context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+        if self.shifters.is_empty() {
+            return;
+        }
+
+        let strategy = self.handle.strategy();
+        let formant_preservation = self.handle.formant_preservation();
+        let dry_level = self.handle.dry_level();
+        let mut levels = [0.0; MAX_HARMONIZER_VOICES];
+        for (level, voice) in levels.iter_mut().zip(self.handle.voices()) {
+            *level = voice.level();
+        }
+        for channel_shifters in &mut self.shifters {
+            for (shifter, voice) in channel_shifters.iter_mut().zip(self.handle.voices()) {
+                shifter.set_ratio(voice.ratio());
+                shifter.set_strategy(strategy);
+                shifter.set_formant_preservation(formant_preservation);
+            }
+        }
+
+        let num_channels = data.num_channels().min(self.shifters.len());
+        for sample_num in 0..data.num_samples() {
+            for channel_num in 0..num_channels {
+                let input = *data.get(channel_num, sample_num);
+                let dry_delay = &mut self.dry_delay[channel_num];
+                let dry = dry_delay[self.dry_delay_cursor];
+                dry_delay[self.dry_delay_cursor] = input;
+
+                let mut output = dry * dry_level;
+                for (shifter, level) in self.shifters[channel_num].iter_mut().zip(levels) {
+                    output += level * shifter.m_process(context, input);
+                }
+                data.set(channel_num, sample_num, output);
+            }
+            self.dry_delay_cursor = (self.dry_delay_cursor + 1) % self.dry_delay[0].len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_testing_helpers::sine_buffer;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use crate::test::goertzel_power;
+
+    use super::*;
+
+    fn make_context() -> AudioContext {
+        AudioContext::from(AudioProcessorSettings::new(44100.0, 1, 1, 512))
+    }
+
+    #[test]
+    fn test_harmonizer_mixes_shifted_voices() {
+        let mut context = make_context();
+        let mut harmonizer = HarmonizerProcessor::default();
+        harmonizer.prepare(&mut context);
+
+        let input = sine_buffer(44100.0, 440.0, Duration::from_millis(1500));
+        let mut buffer = AudioBuffer::from_interleaved(1, &input);
+        harmonizer.process(&mut context, &mut buffer);
+
+        let tail = &buffer.channel(0)[44100..];
+        let off_frequency_power = goertzel_power(tail, 44100.0, 500.0);
+        for frequency in [440.0, 554.37, 659.26] {
+            let power = goertzel_power(tail, 44100.0, frequency);
+            assert!(
+                power > off_frequency_power * 100.0,
+                "Missing {}Hz power={} off_frequency_power={}",
+                frequency,
+                power,
+                off_frequency_power
+            );
+        }
+    }
+
+    #[test]
+    fn test_dry_signal_is_latency_aligned() {
+        let mut context = make_context();
+        let mut harmonizer = HarmonizerProcessor::default();
+        for voice in harmonizer.handle().voices() {
+            voice.set_level(0.0);
+        }
+        harmonizer.prepare(&mut context);
+        let latency = PitchShifterProcessor::default().latency_samples();
+
+        let input: Vec<f32> = (0..latency * 2).map(|i| (i as f32 * 0.01).sin()).collect();
+        let mut buffer = AudioBuffer::from_interleaved(1, &input);
+        harmonizer.process(&mut context, &mut buffer);
+        assert!(buffer.channel(0)[..latency].iter().all(|s| *s == 0.0));
+        assert_eq!(buffer.channel(0)[latency..], input[..latency]);
+    }
+
+    #[test]
+    fn test_unmuted_voice_does_not_replay_stale_audio() {
+        let mut context = make_context();
+        let mut harmonizer = HarmonizerProcessor::default();
+        harmonizer.handle().set_dry_level(0.0);
+        harmonizer.handle().voice(1).set_level(0.0);
+        harmonizer.prepare(&mut context);
+
+        let input = sine_buffer(44100.0, 440.0, Duration::from_millis(500));
+        let mut buffer = AudioBuffer::from_interleaved(1, &input);
+        harmonizer.process(&mut context, &mut buffer);
+
+        let handle = harmonizer.handle().clone();
+        let voice = handle.voice(0);
+        voice.set_level(0.0);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 44100);
+        harmonizer.process(&mut context, &mut buffer);
+
+        voice.set_level(1.0);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 22050);
+        harmonizer.process(&mut context, &mut buffer);
+        assert!(buffer.channel(0).iter().all(|s| s.abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_harmonizer_does_not_allocate() {
+        let mut context = make_context();
+        let mut harmonizer = HarmonizerProcessor::default();
+        harmonizer.prepare(&mut context);
+        harmonizer.handle().voice(2).set_level(0.3);
+        harmonizer.handle().set_formant_preservation(true);
+
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 512);
+        assert_no_alloc(|| {
+            for _ in 0..20 {
+                harmonizer.process(&mut context, &mut buffer);
+            }
+        });
+    }
+}
@@ -23,156 +23,56 @@
 
 //! This is a phase-vocoder pitch-shifter implementation.
 //!
-//! Currently, it implements only very old strategies.
+//! [`PitchShifterProcessor`] supports several phase processing strategies, see
+//! [`PhaseProcessingStrategyVariants`]. The Laroche & Dolson phase-locking strategies reduce the
+//! "phasiness" of the plain phase-vocoder on voices and polyphonic material. Formants may also be
+//! preserved, by estimating the spectral envelope of each frame and re-applying it after the
+//! shift.
+//!
+//! [`harmonizer::HarmonizerProcessor`] mixes several shifted voices with the dry signal.
+//!
+//! The [`time_stretch`] module implements the opposite, a WSOLA time-stretcher that changes
+//! playback speed without changing pitch.
 //!
 //! # References
 //! * "Audio Effects: Theory, Implementation and Application: Joshua D. Reiss and Andrew Mcpherson"
 //! * "Phase-locked Vocoder: Miller Puckette"
 //! * "New phase-vocoder techniques for pitch-shifting, harmonizing and other exotic effects: Jean Laroche and Mark Dolson"
 
-use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use audio_garbage_collector::{make_shared, Shared};
 use audio_processor_analysis::fft_processor::{FftDirection, FftProcessor, FftProcessorOptions};
 use audio_processor_analysis::window_functions::{make_hann_vec, WindowFunctionType};
-use audio_processor_traits::num::Complex;
 use audio_processor_traits::simple_processor::MonoAudioProcessor;
-use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor, Zero};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor};
+use augmented_atomics::AtomicEnum;
+use phase_vocoder::{PhaseCorrectionParams, PhaseVocoder};
+use spectral_envelope::SpectralEnvelope;
+
+pub use phase_vocoder::PhaseProcessingStrategyVariants;
+
+pub mod harmonizer;
+mod phase_vocoder;
+mod spectral_envelope;
+pub mod time_stretch;
 
 #[cfg(all(test, debug_assertions))]
 mod test_allocator;
 
+/// Cepstral coefficients kept when estimating the spectral envelope, this has to be short enough
+/// to exclude the pitch period of the input
+const SPECTRAL_ENVELOPE_LIFTER_LEN: usize = 40;
+/// Limit to how much formant preservation boosts a bin, so bins next to deep envelope notches
+/// don't blow up
+const MAX_FORMANT_GAIN: f32 = 8.0;
+
 fn make_vec(size: usize) -> Vec<f32> {
     let mut v = Vec::with_capacity(size);
     v.resize(size, 0.0);
     v
 }
 
-struct PhaseCorrectionParams<'a> {
-    /// The hop size of the FFT
-    step_len: usize,
-    fft_frequency_domain: &'a mut [Complex<f32>],
-    pitch_shift_ratio: f32,
-}
-
-struct NormalPhaseVocoder {
-    last_output_phase: Vec<f32>,
-    last_input_phase: Vec<f32>,
-}
-
-impl NormalPhaseVocoder {
-    fn new(fft_size: usize) -> Self {
-        Self {
-            last_output_phase: make_vec(fft_size),
-            last_input_phase: make_vec(fft_size),
-        }
-    }
-
-    fn update_phases(&mut self, params: PhaseCorrectionParams) {
-        let PhaseCorrectionParams {
-            step_len,
-            fft_frequency_domain,
-            pitch_shift_ratio,
-        } = params;
-
-        let step_len = step_len as f32;
-        let fft_size = fft_frequency_domain.len() as f32;
-        for (bin, value) in fft_frequency_domain.iter_mut().enumerate() {
-            if bin <= (fft_size as usize) / 2 {
-                let value: &mut Complex<f32> = value;
-                let (magnitude, phase) = value.to_polar();
-
-                let bin_frequency = 2.0 * PI * bin as f32 / fft_size;
-                let expected_bin_phase = bin_frequency * step_len;
-                let phase_delta = phase - self.last_input_phase[bin];
-                let bin_deviation = phase_delta - expected_bin_phase;
-                let bin_frequency = expected_bin_phase + princ_arg(bin_deviation);
-
-                let last_output_phase = self.last_output_phase[bin];
-                let new_phase =
-                    princ_arg(last_output_phase + bin_frequency * pitch_shift_ratio * step_len);
-
-                *value = Complex::from_polar(magnitude, new_phase);
-
-                self.last_output_phase[bin] = new_phase;
-                self.last_input_phase[bin] = phase;
-            } else {
-                *value = Complex::new(0.0, 0.0);
-            }
-        }
-    }
-}
-
-struct PhaseLockedVocoder {
-    last_output_phase: Vec<f32>,
-    last_input_phase: Vec<f32>,
-    scratch: Vec<Complex<f32>>,
-}
-
-impl PhaseLockedVocoder {
-    fn new(fft_size: usize) -> Self {
-        Self {
-            last_output_phase: make_vec(fft_size),
-            last_input_phase: make_vec(fft_size),
-            scratch: make_vec(fft_size).iter().map(|_| Complex::zero()).collect(),
-        }
-    }
-
-    fn update_phases(&mut self, params: PhaseCorrectionParams) {
-        let PhaseCorrectionParams {
-            step_len,
-            fft_frequency_domain,
-            pitch_shift_ratio,
-        } = params;
-
-        let step_len = step_len as f32;
-        let fft_size = fft_frequency_domain.len() as f32;
-        for bin in 0..fft_frequency_domain.len() {
-            if bin <= (fft_size as usize) / 2 {
-                let value = fft_frequency_domain[bin];
-                let (magnitude, partial_phase) = value.to_polar();
-                let phase = if bin == 0 {
-                    partial_phase
-                } else {
-                    let v1 = value;
-                    let v2 = fft_frequency_domain[bin - 1];
-                    let v3 = fft_frequency_domain[bin + 1];
-                    let (_, phase) = (v1 + v2 + v3).to_polar();
-                    phase
-                };
-
-                let bin_frequency = 2.0 * PI * bin as f32 / fft_size;
-                let expected_bin_phase = bin_frequency * step_len;
-                let phase_delta = phase - self.last_input_phase[bin];
-                let bin_deviation = phase_delta - expected_bin_phase;
-                let bin_frequency = expected_bin_phase + princ_arg(bin_deviation);
-
-                let last_output_phase = self.last_output_phase[bin];
-                let new_phase =
-                    princ_arg(last_output_phase + bin_frequency * pitch_shift_ratio * step_len);
-
-                self.last_input_phase[bin] = phase;
-                self.last_output_phase[bin] = new_phase;
-                self.scratch[bin] = Complex::from_polar(magnitude, new_phase);
-            } else {
-                fft_frequency_domain[bin] = Complex::new(0.0, 0.0);
-            }
-        }
-
-        fft_frequency_domain[..self.scratch.len()].clone_from_slice(&self.scratch[..]);
-    }
-}
-
-enum PhaseProcessingStrategy {
-    Normal(NormalPhaseVocoder),
-    PhaseLocking(PhaseLockedVocoder),
-}
-
-pub enum PhaseProcessingStrategyVariants {
-    Normal,
-    PhaseLocking,
-}
-
 pub struct PitchShifterProcessor {
     pitch_shift_ratio: f32,
     resample_buffer: Vec<f32>,
@@ -180,7 +80,10 @@ pub struct PitchShifterProcessor {
     output_buffer: Vec<f32>,
     output_read_cursor: usize,
     output_write_cursor: usize,
-    phase_processing_strategy: PhaseProcessingStrategy,
+    strategy: PhaseProcessingStrategyVariants,
+    phase_vocoder: PhaseVocoder,
+    formant_preservation: bool,
+    spectral_envelope: SpectralEnvelope,
     fft_processor: FftProcessor,
     inverse_fft_processor: FftProcessor,
     window_fn: Vec<f32>,
@@ -219,29 +122,32 @@ impl PitchShifterProcessor {
                 direction: FftDirection::Inverse,
                 ..Default::default()
             }),
-            phase_processing_strategy: PhaseProcessingStrategy::Normal(NormalPhaseVocoder::new(
-                fft_size,
-            )),
+            strategy: PhaseProcessingStrategyVariants::Normal,
+            phase_vocoder: PhaseVocoder::new(fft_size),
+            formant_preservation: false,
+            spectral_envelope: SpectralEnvelope::new(fft_size, SPECTRAL_ENVELOPE_LIFTER_LEN),
             window_fn: make_hann_vec(fft_size),
         }
     }
 
+    /// Change the phase processing strategy, this is real-time safe
     pub fn set_strategy(&mut self, strategy: PhaseProcessingStrategyVariants) {
-        match strategy {
-            PhaseProcessingStrategyVariants::Normal => {
-                self.phase_processing_strategy = PhaseProcessingStrategy::Normal(
-                    NormalPhaseVocoder::new(self.fft_processor.size()),
-                )
-            }
-            PhaseProcessingStrategyVariants::PhaseLocking => {
-                self.phase_processing_strategy = PhaseProcessingStrategy::PhaseLocking(
-                    PhaseLockedVocoder::new(self.fft_processor.size()),
-                )
-            }
-        }
+        self.strategy = strategy;
     }
 
-    fn set_ratio(&mut self, ratio: f32) {
+    /// If enabled, the spectral envelope of the input is kept in place, so formants don't move
+    /// with the pitch
+    pub fn set_formant_preservation(&mut self, enabled: bool) {
+        self.formant_preservation = enabled;
+    }
+
+    /// Delay in samples between the input and the shifted output
+    pub fn latency_samples(&self) -> usize {
+        self.fft_processor.size() - self.fft_processor.step_len()
+    }
+
+    /// Set the pitch shift ratio, clamped between 0.25 and 4
+    pub fn set_ratio(&mut self, ratio: f32) {
         let ratio = ratio.clamp(0.25, 4.0);
         let step_len = self.fft_processor.step_len() as f32;
         let fft_size = self.fft_processor.size();
@@ -253,6 +159,9 @@ impl PitchShifterProcessor {
 
     fn on_fft_frame(&mut self) {
         let input_power = self.fft_processor.input_buffer_sum();
+        if self.formant_preservation {
+            self.preserve_formants();
+        }
         self.update_phases();
         self.inverse_fft_processor
             .process_fft_buffer(self.fft_processor.buffer_mut());
@@ -272,16 +181,33 @@ impl PitchShifterProcessor {
             (self.output_write_cursor + self.fft_processor.step_len()) % self.output_buffer.len();
     }
 
+    /// Resampling moves the content of bin `k` onto bin `k * ratio`, so bins are re-weighted
+    /// by the envelope where they'll end up over the envelope where they are
+    fn preserve_formants(&mut self) {
+        self.spectral_envelope.estimate(self.fft_processor.buffer());
+
+        let ratio = self.pitch_shift_ratio;
+        let spectrum = self.fft_processor.buffer_mut();
+        let nyquist_bin = (spectrum.len() / 2) as f32;
+        for (bin, value) in spectrum.iter_mut().enumerate() {
+            let target_bin = bin as f32 * ratio;
+            let gain = if target_bin <= nyquist_bin && bin as f32 <= nyquist_bin {
+                self.spectral_envelope.value_at(target_bin)
+                    / self.spectral_envelope.value_at(bin as f32)
+            } else {
+                0.0
+            };
+            *value *= gain.min(MAX_FORMANT_GAIN);
+        }
+    }
+
     fn update_phases(&mut self) {
         let params = PhaseCorrectionParams {
             step_len: self.fft_processor.step_len(),
             fft_frequency_domain: self.fft_processor.buffer_mut(),
             pitch_shift_ratio: self.pitch_shift_ratio,
         };
-        match &mut self.phase_processing_strategy {
-            PhaseProcessingStrategy::Normal(normal) => normal.update_phases(params),
-            PhaseProcessingStrategy::PhaseLocking(strategy) => strategy.update_phases(params),
-        }
+        self.phase_vocoder.update_phases(self.strategy, params);
     }
 
     fn resample_fft(&mut self, input_power: f32) {
@@ -315,12 +241,44 @@ impl PitchShifterProcessor {
 
 pub struct MultiChannelPitchShifterProcessorHandle {
     ratio: AtomicF32,
+    strategy: AtomicEnum<PhaseProcessingStrategyVariants>,
+    formant_preservation: AtomicBool,
+}
+
+impl Default for MultiChannelPitchShifterProcessorHandle {
+    fn default() -> Self {
+        Self {
+            ratio: AtomicF32::new(1.0),
+            strategy: AtomicEnum::new(PhaseProcessingStrategyVariants::Normal),
+            formant_preservation: AtomicBool::new(false),
+        }
+    }
 }
 
 impl MultiChannelPitchShifterProcessorHandle {
+    pub fn ratio(&self) -> f32 {
+        self.ratio.get()
+    }
+
     pub fn set_ratio(&self, ratio: f32) {
         self.ratio.set(ratio);
     }
+
+    pub fn strategy(&self) -> PhaseProcessingStrategyVariants {
+        self.strategy.get()
+    }
+
+    pub fn set_strategy(&self, strategy: PhaseProcessingStrategyVariants) {
+        self.strategy.set(strategy);
+    }
+
+    pub fn formant_preservation(&self) -> bool {
+        self.formant_preservation.load(Ordering::Relaxed)
+    }
+
+    pub fn set_formant_preservation(&self, enabled: bool) {
+        self.formant_preservation.store(enabled, Ordering::Relaxed);
+    }
 }
 
 pub struct MultiChannelPitchShifterProcessor {
@@ -344,9 +302,7 @@ impl MultiChannelPitchShifterProcessor {
 impl Default for MultiChannelPitchShifterProcessor {
     fn default() -> Self {
         Self {
-            handle: make_shared(MultiChannelPitchShifterProcessorHandle {
-                ratio: AtomicF32::new(1.0),
-            }),
+            handle: make_shared(MultiChannelPitchShifterProcessorHandle::default()),
             processors: vec![
                 PitchShifterProcessor::default(),
                 PitchShifterProcessor::default(),
@@ -369,9 +325,13 @@ impl AudioProcessor for MultiChannelPitchShifterProcessor {
     }
 
     fn process(&mut self, context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
-        let ratio = self.handle.ratio.get();
+        let ratio = self.handle.ratio();
+        let strategy = self.handle.strategy();
+        let formant_preservation = self.handle.formant_preservation();
         for processor in &mut self.processors {
             processor.set_ratio(ratio);
+            processor.set_strategy(strategy);
+            processor.set_formant_preservation(formant_preservation);
         }
 
         if (ratio - 1.0).abs() < f32::EPSILON {
@@ -389,17 +349,6 @@ impl AudioProcessor for MultiChannelPitchShifterProcessor {
     }
 }
 
-#[inline]
-fn princ_arg(phase: f32) -> f32 {
-    const PI_2: f32 = 2.0 * PI;
-
-    if phase >= 0.0 {
-        (phase + PI) % (PI_2) - PI
-    } else {
-        (phase + PI) % (-PI_2) + PI
-    }
-}
-
 impl MonoAudioProcessor for PitchShifterProcessor {
     type SampleType = f32;
 
@@ -426,8 +375,10 @@ impl MonoAudioProcessor for PitchShifterProcessor {
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use assert_no_alloc::assert_no_alloc;
-    use audio_processor_testing_helpers::{relative_path, rms_level};
+    use audio_processor_testing_helpers::{relative_path, rms_level, sine_buffer};
 
     use audio_processor_file::{AudioFileProcessor, OutputAudioFileProcessor};
     use audio_processor_traits::simple_processor::MultiChannel;
@@ -435,6 +386,156 @@ mod test {
 
     use super::*;
 
+    /// Power of a single frequency in `signal`
+    pub(crate) fn goertzel_power(signal: &[f32], sample_rate: f32, frequency: f32) -> f32 {
+        let coefficient = 2.0 * (2.0 * std::f32::consts::PI * frequency / sample_rate).cos();
+        let (mut previous, mut previous2) = (0.0, 0.0);
+        for sample in signal {
+            let current = sample + coefficient * previous - previous2;
+            previous2 = previous;
+            previous = current;
+        }
+        (previous2 * previous2 + previous * previous - coefficient * previous * previous2)
+            / signal.len() as f32
+    }
+
+    fn shift(
+        input: &[f32],
+        ratio: f32,
+        strategy: PhaseProcessingStrategyVariants,
+        formant_preservation: bool,
+    ) -> Vec<f32> {
+        let mut context = AudioContext::from(AudioProcessorSettings::new(44100.0, 1, 1, 512));
+        let mut pitch_shifter = PitchShifterProcessor::default();
+        pitch_shifter.m_prepare(&mut context);
+        pitch_shifter.set_ratio(ratio);
+        pitch_shifter.set_strategy(strategy);
+        pitch_shifter.set_formant_preservation(formant_preservation);
+        input
+            .iter()
+            .map(|sample| pitch_shifter.m_process(&mut context, *sample))
+            .collect()
+    }
+
+    /// Power-weighted mean frequency over harmonics of `fundamental`
+    fn harmonic_centroid(signal: &[f32], fundamental: f32, num_harmonics: usize) -> f32 {
+        let (weighted_sum, total) = (1..=num_harmonics)
+            .map(|harmonic| {
+                let frequency = fundamental * harmonic as f32;
+                let power = goertzel_power(signal, 44100.0, frequency);
+                (frequency * power, power)
+            })
+            .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+        weighted_sum / total
+    }
+
+    const ALL_STRATEGIES: [PhaseProcessingStrategyVariants; 4] = [
+        PhaseProcessingStrategyVariants::Normal,
+        PhaseProcessingStrategyVariants::PhaseLocking,
+        PhaseProcessingStrategyVariants::IdentityPhaseLocking,
+        PhaseProcessingStrategyVariants::ScaledPhaseLocking,
+    ];
+
+    #[test]
+    fn test_all_strategies_shift_a_sine() {
+        let input = sine_buffer(44100.0, 440.0, Duration::from_millis(1500));
+        for strategy in ALL_STRATEGIES {
+            let output = shift(&input, 1.5, strategy, false);
+            let tail = &output[44100..];
+            let shifted_power = goertzel_power(tail, 44100.0, 660.0);
+            let original_power = goertzel_power(tail, 44100.0, 440.0);
+            assert!(
+                shifted_power > original_power * 100.0,
+                "{:?} didn't shift the input shifted={} original={}",
+                strategy,
+                shifted_power,
+                original_power
+            );
+        }
+    }
+
+    #[test]
+    fn test_latency() {
+        // A tone burst should come out of the shifter after the reported latency
+        let burst_len = 2048;
+        let mut input = sine_buffer(44100.0, 440.0, Duration::from_millis(1000));
+        for (i, sample) in input.iter_mut().enumerate() {
+            if i >= burst_len {
+                *sample = 0.0;
+            }
+        }
+        let output = shift(&input, 1.0, PhaseProcessingStrategyVariants::Normal, false);
+
+        let energy_centre = |signal: &[f32]| {
+            let (weighted, total) = signal
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(weighted, total), (i, s)| {
+                    (weighted + i as f32 * s * s, total + s * s)
+                });
+            weighted / total
+        };
+        let measured_latency = energy_centre(&output) - energy_centre(&input);
+        let latency = PitchShifterProcessor::default().latency_samples() as f32;
+        assert!(
+            (measured_latency - latency).abs() < 256.0,
+            "measured={} reported={}",
+            measured_latency,
+            latency
+        );
+    }
+
+    #[test]
+    fn test_formant_preservation_keeps_the_spectral_envelope() {
+        // A pulse train through a one-pole low-pass filter, its harmonics decay with frequency
+        let fundamental = 147.0;
+        let period = 44100.0 / fundamental;
+        let mut filter_state = 0.0;
+        let input: Vec<f32> = (0..66150)
+            .map(|i| {
+                let pulse = if (i as f32 % period) < 1.0 { 1.0 } else { 0.0 };
+                filter_state += 0.05 * (pulse - filter_state);
+                filter_state
+            })
+            .collect();
+        let input_centroid = harmonic_centroid(&input[44100..], fundamental, 40);
+
+        let strategy = PhaseProcessingStrategyVariants::IdentityPhaseLocking;
+        let shifted = shift(&input, 1.5, strategy, false);
+        let shifted_centroid = harmonic_centroid(&shifted[44100..], fundamental * 1.5, 26);
+        let preserved = shift(&input, 1.5, strategy, true);
+        let preserved_centroid = harmonic_centroid(&preserved[44100..], fundamental * 1.5, 26);
+
+        assert!(
+            (preserved_centroid - input_centroid).abs()
+                < (shifted_centroid - input_centroid).abs() / 2.0,
+            "input={} shifted={} preserved={}",
+            input_centroid,
+            shifted_centroid,
+            preserved_centroid
+        );
+    }
+
+    #[test]
+    fn test_changing_the_strategy_does_not_allocate() {
+        let mut context = AudioContext::from(AudioProcessorSettings::new(44100.0, 1, 1, 512));
+        let mut pitch_shifter = MultiChannelPitchShifterProcessor::default();
+        pitch_shifter.prepare(&mut context);
+        pitch_shifter.handle().set_ratio(1.5);
+
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, 512);
+        for strategy in ALL_STRATEGIES {
+            pitch_shifter.handle().set_strategy(strategy);
+            pitch_shifter.handle().set_formant_preservation(true);
+            assert_no_alloc(|| {
+                for _ in 0..20 {
+                    pitch_shifter.process(&mut context, &mut buffer);
+                }
+            });
+        }
+    }
+
     /// Read an input file for testing
     fn read_input_file(input_file_path: &str) -> AudioBuffer<f32> {
         let settings = AudioProcessorSettings::default();
@@ -492,7 +593,9 @@ mod test {
             stereo.set(1, sample_num, *input.get(0, sample_num));
         }
 
-        output_file_processor.prepare(AudioProcessorSettings::default());
+        output_file_processor
+            .prepare(AudioProcessorSettings::default())
+            .expect("Failed to create output file");
         output_file_processor
             .process(&mut stereo)
             .expect("Failed to write samples to wave file");
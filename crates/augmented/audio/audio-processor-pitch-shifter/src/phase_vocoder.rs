@@ -0,0 +1,354 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Phase correction strategies for the phase-vocoder.
+//!
+//! All strategies share their phase history, so the strategy may be switched while processing.
+
+use std::f32::consts::PI;
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use audio_processor_traits::num::Complex;
+use audio_processor_traits::Zero;
+
+/// Bins on each side a magnitude must be larger than to be considered a peak
+const PEAK_NEIGHBOURHOOD: usize = 2;
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum PhaseProcessingStrategyVariants {
+    /// Plain phase propagation on every bin
+    Normal = 0,
+    /// Puckette's phase-locking, every bin is locked to the sum of its neighbours
+    PhaseLocking = 1,
+    /// Laroche & Dolson identity phase-locking, bins around a spectral peak keep the phase
+    /// relationship they have with the peak on the input
+    IdentityPhaseLocking = 2,
+    /// Laroche & Dolson scaled phase-locking, like identity phase-locking but phase differences to
+    /// the peak are scaled by the shift ratio
+    ScaledPhaseLocking = 3,
+}
+
+pub(crate) struct PhaseCorrectionParams<'a> {
+    /// The hop size of the FFT
+    pub step_len: usize,
+    pub fft_frequency_domain: &'a mut [Complex<f32>],
+    pub pitch_shift_ratio: f32,
+}
+
+pub(crate) struct PhaseVocoder {
+    last_output_phase: Vec<f32>,
+    last_input_phase: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    phases: Vec<f32>,
+    peaks: Vec<usize>,
+}
+
+impl PhaseVocoder {
+    pub(crate) fn new(fft_size: usize) -> Self {
+        Self {
+            last_output_phase: vec![0.0; fft_size],
+            last_input_phase: vec![0.0; fft_size],
+            scratch: vec![Complex::zero(); fft_size],
+            magnitudes: vec![0.0; fft_size],
+            phases: vec![0.0; fft_size],
+            peaks: Vec::with_capacity(fft_size),
+        }
+    }
+
+    pub(crate) fn update_phases(
+        &mut self,
+        strategy: PhaseProcessingStrategyVariants,
+        params: PhaseCorrectionParams,
+    ) {
+        match strategy {
+            PhaseProcessingStrategyVariants::Normal => self.update_phases_normal(params),
+            PhaseProcessingStrategyVariants::PhaseLocking => {
+                self.update_phases_phase_locking(params)
+            }
+            PhaseProcessingStrategyVariants::IdentityPhaseLocking => {
+                self.update_phases_peak_locking(params, false)
+            }
+            PhaseProcessingStrategyVariants::ScaledPhaseLocking => {
+                self.update_phases_peak_locking(params, true)
+            }
+        }
+    }
+
+    fn update_phases_normal(&mut self, params: PhaseCorrectionParams) {
+        let PhaseCorrectionParams {
+            step_len,
+            fft_frequency_domain,
+            pitch_shift_ratio,
+        } = params;
+
+        let fft_size = fft_frequency_domain.len();
+        for (bin, value) in fft_frequency_domain.iter_mut().enumerate() {
+            if bin <= fft_size / 2 {
+                let value: &mut Complex<f32> = value;
+                let (magnitude, phase) = value.to_polar();
+
+                let bin_frequency =
+                    phase_advance(bin, fft_size, step_len, phase, self.last_input_phase[bin]);
+                let last_output_phase = self.last_output_phase[bin];
+                let new_phase = princ_arg(last_output_phase + bin_frequency * pitch_shift_ratio);
+
+                *value = Complex::from_polar(magnitude, new_phase);
+
+                self.last_output_phase[bin] = new_phase;
+                self.last_input_phase[bin] = phase;
+            } else {
+                *value = Complex::new(0.0, 0.0);
+            }
+        }
+    }
+
+    fn update_phases_phase_locking(&mut self, params: PhaseCorrectionParams) {
+        let PhaseCorrectionParams {
+            step_len,
+            fft_frequency_domain,
+            pitch_shift_ratio,
+        } = params;
+
+        let fft_size = fft_frequency_domain.len();
+        for bin in 0..fft_frequency_domain.len() {
+            if bin <= fft_size / 2 {
+                let value = fft_frequency_domain[bin];
+                let (magnitude, partial_phase) = value.to_polar();
+                let phase = if bin == 0 {
+                    partial_phase
+                } else {
+                    let v1 = value;
+                    let v2 = fft_frequency_domain[bin - 1];
+                    let v3 = fft_frequency_domain[bin + 1];
+                    let (_, phase) = (v1 + v2 + v3).to_polar();
+                    phase
+                };
+
+                let bin_frequency =
+                    phase_advance(bin, fft_size, step_len, phase, self.last_input_phase[bin]);
+                let last_output_phase = self.last_output_phase[bin];
+                let new_phase = princ_arg(last_output_phase + bin_frequency * pitch_shift_ratio);
+
+                self.last_input_phase[bin] = phase;
+                self.last_output_phase[bin] = new_phase;
+                self.scratch[bin] = Complex::from_polar(magnitude, new_phase);
+            } else {
+                fft_frequency_domain[bin] = Complex::new(0.0, 0.0);
+            }
+        }
+
+        let num_bins = fft_size / 2 + 1;
+        fft_frequency_domain[..num_bins].clone_from_slice(&self.scratch[..num_bins]);
+    }
+
+    /// Laroche & Dolson phase-locking. Only spectral peaks have their phases propagated, every
+    /// other bin is assigned to its closest peak and rotated along with it.
+    fn update_phases_peak_locking(&mut self, params: PhaseCorrectionParams, scaled: bool) {
+        let PhaseCorrectionParams {
+            step_len,
+            fft_frequency_domain,
+            pitch_shift_ratio,
+        } = params;
+
+        let fft_size = fft_frequency_domain.len();
+        let num_bins = fft_size / 2 + 1;
+        for (bin, value) in fft_frequency_domain.iter().enumerate().take(num_bins) {
+            let (magnitude, phase) = value.to_polar();
+            self.magnitudes[bin] = magnitude;
+            self.phases[bin] = phase;
+        }
+        find_peaks(&self.magnitudes[..num_bins], &mut self.peaks);
+
+        for &peak in &self.peaks {
+            let bin_frequency = phase_advance(
+                peak,
+                fft_size,
+                step_len,
+                self.phases[peak],
+                self.last_input_phase[peak],
+            );
+            self.last_output_phase[peak] =
+                princ_arg(self.last_output_phase[peak] + bin_frequency * pitch_shift_ratio);
+        }
+
+        let phase_difference_scale = if scaled { pitch_shift_ratio } else { 1.0 };
+        for (index, &peak) in self.peaks.iter().enumerate() {
+            // Each peak's region of influence ends half-way to its neighbouring peaks
+            let region_start = if index == 0 {
+                0
+            } else {
+                (self.peaks[index - 1] + peak) / 2 + 1
+            };
+            let region_end = self
+                .peaks
+                .get(index + 1)
+                .map(|next_peak| (peak + next_peak) / 2)
+                .unwrap_or(num_bins - 1);
+
+            let peak_output_phase = self.last_output_phase[peak];
+            let peak_input_phase = self.phases[peak];
+            for bin in (region_start..=region_end).filter(|bin| *bin != peak) {
+                self.last_output_phase[bin] = princ_arg(
+                    peak_output_phase
+                        + phase_difference_scale * (self.phases[bin] - peak_input_phase),
+                );
+            }
+        }
+
+        for (bin, value) in fft_frequency_domain.iter_mut().enumerate() {
+            if bin < num_bins {
+                // Without any peaks there's nothing to lock to, so the output is silent
+                let magnitude = if self.peaks.is_empty() {
+                    0.0
+                } else {
+                    self.magnitudes[bin]
+                };
+                *value = Complex::from_polar(magnitude, self.last_output_phase[bin]);
+                self.last_input_phase[bin] = self.phases[bin];
+            } else {
+                *value = Complex::new(0.0, 0.0);
+            }
+        }
+    }
+}
+
+/// Phase advance of `bin` over one hop, unwrapped around the bin's centre frequency
+#[inline]
+fn phase_advance(bin: usize, fft_size: usize, step_len: usize, phase: f32, last_phase: f32) -> f32 {
+    let bin_frequency = 2.0 * PI * bin as f32 / fft_size as f32;
+    let expected_bin_phase = bin_frequency * step_len as f32;
+    let phase_delta = phase - last_phase;
+    let bin_deviation = phase_delta - expected_bin_phase;
+    expected_bin_phase + princ_arg(bin_deviation)
+}
+
+/// Collect local magnitude maxima onto `peaks`. `peaks` must have enough capacity for all bins
+/// so this won't allocate.
+fn find_peaks(magnitudes: &[f32], peaks: &mut Vec<usize>) {
+    peaks.clear();
+    for (bin, magnitude) in magnitudes.iter().enumerate() {
+        if *magnitude <= f32::EPSILON {
+            continue;
+        }
+
+        let start = bin.saturating_sub(PEAK_NEIGHBOURHOOD);
+        let end = (bin + PEAK_NEIGHBOURHOOD).min(magnitudes.len() - 1);
+        let is_peak = (start..bin).all(|other| magnitudes[other] < *magnitude)
+            && (bin + 1..=end).all(|other| magnitudes[other] <= *magnitude);
+        if is_peak {
+            peaks.push(bin);
+        }
+    }
+}
+
+/// Wrap a phase onto the `[-PI, PI)` range
+#[inline]
+pub(crate) fn princ_arg(phase: f32) -> f32 {
+    const PI_2: f32 = 2.0 * PI;
+
+    phase - PI_2 * ((phase + PI) / PI_2).floor()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_princ_arg() {
+        for (phase, expected) in [
+            (0.0, 0.0),
+            (1.0, 1.0),
+            (-1.0, -1.0),
+            (3.0 * PI / 2.0, -PI / 2.0),
+            (-3.0 * PI / 2.0, PI / 2.0),
+            (5.0 * PI, -PI),
+        ] {
+            assert!((princ_arg(phase) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_find_peaks() {
+        let magnitudes = [0.0, 1.0, 3.0, 1.0, 0.5, 0.2, 0.4, 2.0, 2.0, 0.1, 0.0];
+        let mut peaks = Vec::with_capacity(magnitudes.len());
+        find_peaks(&magnitudes, &mut peaks);
+        assert_eq!(peaks, vec![2, 7]);
+    }
+
+    #[test]
+    fn test_find_peaks_on_silence() {
+        let mut peaks = Vec::with_capacity(10);
+        find_peaks(&[0.0; 10], &mut peaks);
+        assert!(peaks.is_empty());
+    }
+
+    #[test]
+    fn test_identity_phase_locking_keeps_phase_differences_to_the_peak() {
+        let fft_size = 16;
+        let mut vocoder = PhaseVocoder::new(fft_size);
+        let mut spectrum = vec![Complex::zero(); fft_size];
+        spectrum[3] = Complex::from_polar(0.5, 0.3);
+        spectrum[4] = Complex::from_polar(1.0, 1.0);
+        spectrum[5] = Complex::from_polar(0.5, 2.0);
+
+        vocoder.update_phases(
+            PhaseProcessingStrategyVariants::IdentityPhaseLocking,
+            PhaseCorrectionParams {
+                step_len: 4,
+                fft_frequency_domain: &mut spectrum,
+                pitch_shift_ratio: 1.5,
+            },
+        );
+
+        let (_, peak_phase) = spectrum[4].to_polar();
+        let (_, left_phase) = spectrum[3].to_polar();
+        let (_, right_phase) = spectrum[5].to_polar();
+        assert!((princ_arg(left_phase - peak_phase) - -0.7).abs() < 1e-4);
+        assert!((princ_arg(right_phase - peak_phase) - 1.0).abs() < 1e-4);
+        assert!((spectrum[4].norm() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_scaled_phase_locking_scales_phase_differences_to_the_peak() {
+        let fft_size = 16;
+        let mut vocoder = PhaseVocoder::new(fft_size);
+        let mut spectrum = vec![Complex::zero(); fft_size];
+        spectrum[4] = Complex::from_polar(1.0, 1.0);
+        spectrum[5] = Complex::from_polar(0.5, 1.4);
+
+        vocoder.update_phases(
+            PhaseProcessingStrategyVariants::ScaledPhaseLocking,
+            PhaseCorrectionParams {
+                step_len: 4,
+                fft_frequency_domain: &mut spectrum,
+                pitch_shift_ratio: 2.0,
+            },
+        );
+
+        let (_, peak_phase) = spectrum[4].to_polar();
+        let (_, right_phase) = spectrum[5].to_polar();
+        assert!((princ_arg(right_phase - peak_phase) - 0.8).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,152 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Spectral envelope estimation, used to preserve formants when pitch-shifting.
+//!
+//! The envelope is estimated with the cepstrum. The log-magnitude spectrum is transformed and only
+//! its low quefrency coefficients are kept, which removes harmonic detail and leaves the smooth
+//! shape of the spectrum.
+
+use audio_processor_analysis::fft_processor::{FftDirection, FftProcessor, FftProcessorOptions};
+use audio_processor_traits::num::Complex;
+use audio_processor_traits::Zero;
+
+/// Avoids the logarithm of zero on silent bins
+const MIN_MAGNITUDE: f32 = 1e-9;
+
+pub(crate) struct SpectralEnvelope {
+    forward_fft: FftProcessor,
+    inverse_fft: FftProcessor,
+    cepstrum: Vec<Complex<f32>>,
+    envelope: Vec<f32>,
+    /// Number of cepstral coefficients kept on each side
+    lifter_len: usize,
+}
+
+impl SpectralEnvelope {
+    pub(crate) fn new(fft_size: usize, lifter_len: usize) -> Self {
+        Self {
+            forward_fft: FftProcessor::new(FftProcessorOptions {
+                size: fft_size,
+                ..Default::default()
+            }),
+            inverse_fft: FftProcessor::new(FftProcessorOptions {
+                size: fft_size,
+                direction: FftDirection::Inverse,
+                ..Default::default()
+            }),
+            cepstrum: vec![Complex::zero(); fft_size],
+            envelope: vec![1.0; fft_size],
+            lifter_len,
+        }
+    }
+
+    /// Estimate the envelope of a full (both halves) spectrum
+    pub(crate) fn estimate(&mut self, spectrum: &[Complex<f32>]) {
+        let fft_size = self.cepstrum.len();
+        for (coefficient, bin) in self.cepstrum.iter_mut().zip(spectrum) {
+            *coefficient = Complex::new(bin.norm().max(MIN_MAGNITUDE).ln(), 0.0);
+        }
+
+        self.inverse_fft.process_fft_buffer(&mut self.cepstrum);
+        for (quefrency, coefficient) in self.cepstrum.iter_mut().enumerate() {
+            if quefrency < self.lifter_len || quefrency > fft_size - self.lifter_len {
+                *coefficient /= fft_size as f32;
+            } else {
+                *coefficient = Complex::zero();
+            }
+        }
+        self.forward_fft.process_fft_buffer(&mut self.cepstrum);
+
+        for (value, coefficient) in self.envelope.iter_mut().zip(&self.cepstrum) {
+            *value = coefficient.re.exp();
+        }
+    }
+
+    /// Linearly interpolated envelope magnitude at a fractional bin
+    #[inline]
+    pub(crate) fn value_at(&self, bin: f32) -> f32 {
+        let index = bin.floor() as usize;
+        let delta = bin - bin.floor();
+        let current = self.envelope[index.min(self.envelope.len() - 1)];
+        let next = self.envelope[(index + 1).min(self.envelope.len() - 1)];
+        current + delta * (next - current)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A harmonic comb with a smooth low-pass shape
+    fn shaped_comb(fft_size: usize, harmonic_spacing: usize) -> (Vec<Complex<f32>>, Vec<f32>) {
+        let shape: Vec<f32> = (0..fft_size)
+            .map(|bin| {
+                let bin = bin.min(fft_size - bin) as f32;
+                1.0 / (1.0 + (bin / 50.0).powi(2))
+            })
+            .collect();
+        let spectrum = shape
+            .iter()
+            .enumerate()
+            .map(|(bin, magnitude)| {
+                let bin = bin.min(fft_size - bin);
+                let harmonic = if bin % harmonic_spacing == 0 {
+                    1.0
+                } else {
+                    0.05
+                };
+                Complex::new(magnitude * harmonic, 0.0)
+            })
+            .collect();
+        (spectrum, shape)
+    }
+
+    #[test]
+    fn test_flat_spectrum_has_flat_envelope() {
+        let mut envelope = SpectralEnvelope::new(1024, 30);
+        envelope.estimate(&vec![Complex::new(0.5, 0.0); 1024]);
+        for bin in 0..512 {
+            assert!((envelope.value_at(bin as f32) - 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_envelope_follows_the_spectrum_shape_not_the_harmonics() {
+        let (spectrum, shape) = shaped_comb(1024, 16);
+        let mut envelope = SpectralEnvelope::new(1024, 30);
+        envelope.estimate(&spectrum);
+
+        // The log-domain average sits below the harmonic peaks, so compare the envelope shape
+        // relative to the first bin
+        let reference = envelope.value_at(0.0) / shape[0];
+        for bin in (8..400).step_by(8) {
+            let relative = envelope.value_at(bin as f32) / shape[bin] / reference;
+            assert!(
+                (0.6..1.6).contains(&relative),
+                "Bin {} envelope is off by {}",
+                bin,
+                relative
+            );
+        }
+    }
+}
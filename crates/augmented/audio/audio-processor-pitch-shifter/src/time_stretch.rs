@@ -0,0 +1,368 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Time-stretching with WSOLA (Waveform Similarity Overlap-Add).
+//!
+//! [`WsolaTimeStretcher`] plays a random-access source at any rate without changing its pitch.
+//! Output is built out of overlapping grains read at the original speed. Each new grain starts near
+//! the caller's playback position, shifted within a small search range to the offset that best
+//! continues the previous grain, which avoids phase cancellation and keeps transients intact.
+//!
+//! # References
+//! * "An overlap-add technique based on waveform similarity (WSOLA) for high quality time-scale
+//!   modification of speech: Werner Verhelst and Marc Roelands"
+
+use std::f32::consts::PI;
+
+/// Step between candidate offsets on the first pass of the similarity search
+const COARSE_SEARCH_STEP: usize = 4;
+/// Step between samples compared by the similarity search
+const CORRELATION_STEP: usize = 2;
+
+/// Audio that can be read at arbitrary positions by the time-stretcher.
+pub trait TimeStretchSource {
+    fn num_channels(&self) -> usize;
+
+    /// Read a sample at `position`. Positions outside of the source may be requested and should
+    /// either wrap around (for loops) or be silent.
+    fn sample(&self, channel: usize, position: i64) -> f32;
+}
+
+pub struct WsolaOptions {
+    /// Grain size in samples, grains overlap by half of this
+    pub grain_size: usize,
+    /// Maximum distance in samples a grain may be moved from the playback position to line-up
+    /// with the previous grain
+    pub search_range: usize,
+}
+
+impl WsolaOptions {
+    /// 40ms grains with a 10ms search range
+    pub fn from_sample_rate(sample_rate: f32) -> Self {
+        let grain_size = ((sample_rate * 0.04) as usize / 2).max(1) * 2;
+        Self {
+            grain_size,
+            search_range: (sample_rate * 0.01) as usize,
+        }
+    }
+}
+
+impl Default for WsolaOptions {
+    fn default() -> Self {
+        Self::from_sample_rate(44100.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Grain {
+    start: i64,
+    /// 1 for forward playback, -1 for reverse playback
+    direction: i64,
+}
+
+impl Grain {
+    fn position(&self, offset: usize) -> i64 {
+        self.start + self.direction * offset as i64
+    }
+}
+
+pub struct WsolaTimeStretcher {
+    /// Hann window over two hops, so overlapping grains sum to 1
+    window: Vec<f32>,
+    hop: usize,
+    search_range: usize,
+    current: Grain,
+    previous: Grain,
+    /// Output samples since the current grain started
+    grain_cursor: usize,
+}
+
+impl Default for WsolaTimeStretcher {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl WsolaTimeStretcher {
+    pub fn new(options: WsolaOptions) -> Self {
+        let hop = (options.grain_size / 2).max(1);
+        let grain_size = hop * 2;
+        let window = (0..grain_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / grain_size as f32).cos())
+            .collect();
+        let grain = Grain {
+            start: 0,
+            direction: 1,
+        };
+
+        Self {
+            window,
+            hop,
+            search_range: options.search_range,
+            current: grain,
+            previous: Grain {
+                start: -(hop as i64),
+                direction: 1,
+            },
+            grain_cursor: 0,
+        }
+    }
+
+    /// Restart playback at `position`. The output continues from there seamlessly, as if the
+    /// source had been played at its original rate.
+    pub fn reset(&mut self, position: f32, rate: f32) {
+        let direction = if rate < 0.0 { -1 } else { 1 };
+        let start = position.round() as i64;
+        self.current = Grain { start, direction };
+        self.previous = Grain {
+            start: start - direction * self.hop as i64,
+            direction,
+        };
+        self.grain_cursor = 0;
+    }
+
+    /// Output sample for the current frame. Call [`WsolaTimeStretcher::advance`] once all channels
+    /// are read.
+    #[inline]
+    pub fn sample(&self, source: &impl TimeStretchSource, channel: usize) -> f32 {
+        let current = source.sample(channel, self.current.position(self.grain_cursor));
+        let previous = source.sample(
+            channel,
+            self.previous.position(self.grain_cursor + self.hop),
+        );
+        self.window[self.grain_cursor] * current
+            + self.window[self.grain_cursor + self.hop] * previous
+    }
+
+    /// Move onto the next frame. `position` is where playback is on the source and `rate` the
+    /// speed it's moving at, which may be negative for reverse playback.
+    pub fn advance(&mut self, source: &impl TimeStretchSource, position: f32, rate: f32) {
+        self.grain_cursor += 1;
+        if self.grain_cursor < self.hop {
+            return;
+        }
+
+        self.grain_cursor = 0;
+        self.previous = self.current;
+        let direction = if rate < 0.0 { -1 } else { 1 };
+        let target = Grain {
+            start: position.round() as i64,
+            direction,
+        };
+        self.current = self.find_best_grain(source, target);
+    }
+
+    /// Find the grain within the search range of `target` which is most similar to the natural
+    /// continuation of the last grain
+    fn find_best_grain(&self, source: &impl TimeStretchSource, target: Grain) -> Grain {
+        let continuation = Grain {
+            start: self.previous.position(self.hop),
+            direction: self.previous.direction,
+        };
+        if continuation == target {
+            return target;
+        }
+
+        let range = self.search_range as i64;
+        let similarity = |offset: i64| {
+            self.similarity(
+                source,
+                &continuation,
+                &Grain {
+                    start: target.start + offset,
+                    direction: target.direction,
+                },
+            )
+        };
+
+        let mut best_offset = 0;
+        let mut best_similarity = similarity(0);
+        for offset in (-range..=range).step_by(COARSE_SEARCH_STEP) {
+            let value = similarity(offset);
+            if value > best_similarity {
+                best_offset = offset;
+                best_similarity = value;
+            }
+        }
+
+        let coarse_offset = best_offset;
+        let fine_range = COARSE_SEARCH_STEP as i64 - 1;
+        for offset in
+            (coarse_offset - fine_range).max(-range)..=(coarse_offset + fine_range).min(range)
+        {
+            let value = similarity(offset);
+            if value > best_similarity {
+                best_offset = offset;
+                best_similarity = value;
+            }
+        }
+
+        Grain {
+            start: target.start + best_offset,
+            direction: target.direction,
+        }
+    }
+
+    /// Normalized cross-correlation over one hop of the down-mixed source
+    fn similarity(
+        &self,
+        source: &impl TimeStretchSource,
+        reference: &Grain,
+        candidate: &Grain,
+    ) -> f32 {
+        let mut correlation = 0.0;
+        let mut energy = 0.0;
+        for offset in (0..self.hop).step_by(CORRELATION_STEP) {
+            let mut reference_sample = 0.0;
+            let mut candidate_sample = 0.0;
+            for channel in 0..source.num_channels() {
+                reference_sample += source.sample(channel, reference.position(offset));
+                candidate_sample += source.sample(channel, candidate.position(offset));
+            }
+            correlation += reference_sample * candidate_sample;
+            energy += candidate_sample * candidate_sample;
+        }
+        correlation / (energy + f32::EPSILON).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_testing_helpers::sine_buffer;
+
+    use super::*;
+
+    struct LoopedSource(Vec<Vec<f32>>);
+
+    impl TimeStretchSource for LoopedSource {
+        fn num_channels(&self) -> usize {
+            self.0.len()
+        }
+
+        fn sample(&self, channel: usize, position: i64) -> f32 {
+            let len = self.0[channel].len() as i64;
+            self.0[channel][position.rem_euclid(len) as usize]
+        }
+    }
+
+    fn stretch(source: &LoopedSource, rate: f32, num_samples: usize) -> Vec<f32> {
+        let mut stretcher = WsolaTimeStretcher::new(WsolaOptions::from_sample_rate(22050.0));
+        stretcher.reset(0.0, rate);
+        let mut position = 0.0;
+        (0..num_samples)
+            .map(|_| {
+                let output = stretcher.sample(source, 0);
+                position += rate;
+                stretcher.advance(source, position, rate);
+                output
+            })
+            .collect()
+    }
+
+    /// Estimate the frequency of a sine from its rising zero-crossings
+    fn estimate_frequency(signal: &[f32], sample_rate: f32) -> f32 {
+        let crossings: Vec<usize> = signal
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(i, _)| i)
+            .collect();
+        let periods = (crossings.len() - 1) as f32;
+        let duration = (crossings[crossings.len() - 1] - crossings[0]) as f32;
+        periods * sample_rate / duration
+    }
+
+    #[test]
+    fn test_rate_of_one_plays_the_source_back() {
+        let input = sine_buffer(22050.0, 440.0, Duration::from_millis(500));
+        let source = LoopedSource(vec![input.clone()]);
+        let output = stretch(&source, 1.0, input.len());
+        for (actual, expected) in output.iter().zip(&input) {
+            assert!((actual - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_stretching_keeps_the_pitch() {
+        let input = sine_buffer(22050.0, 440.0, Duration::from_secs(1));
+        let source = LoopedSource(vec![input]);
+        for rate in [0.5, 0.8, 1.25, 2.0, -1.0] {
+            let output = stretch(&source, rate, 22050);
+            let frequency = estimate_frequency(&output, 22050.0);
+            assert!(
+                (frequency - 440.0).abs() < 5.0,
+                "Rate {} changed the frequency to {}",
+                rate,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn test_stretching_has_no_discontinuities() {
+        let input = sine_buffer(22050.0, 220.0, Duration::from_secs(1));
+        let source = LoopedSource(vec![input]);
+        let max_step = 2.0 * PI * 220.0 / 22050.0;
+        for rate in [0.7, 1.5] {
+            let output = stretch(&source, rate, 22050);
+            for pair in output.windows(2) {
+                assert!((pair[1] - pair[0]).abs() < max_step * 1.5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reverse_playback_reads_the_source_backwards() {
+        let input: Vec<f32> = (0..2000).map(|i| i as f32).collect();
+        let source = LoopedSource(vec![input]);
+        let mut stretcher = WsolaTimeStretcher::new(WsolaOptions {
+            grain_size: 64,
+            search_range: 0,
+        });
+        stretcher.reset(1000.0, -1.0);
+        let mut position = 1000.0;
+        for i in 0..500 {
+            assert!((stretcher.sample(&source, 0) - (1000 - i) as f32).abs() < 1e-3);
+            position -= 1.0;
+            stretcher.advance(&source, position, -1.0);
+        }
+    }
+
+    #[test]
+    fn test_no_alloc_stretch() {
+        let input = sine_buffer(22050.0, 440.0, Duration::from_secs(1));
+        let source = LoopedSource(vec![input.clone(), input]);
+        let mut stretcher = WsolaTimeStretcher::new(WsolaOptions::from_sample_rate(22050.0));
+        let mut position = 0.0;
+        assert_no_alloc(|| {
+            for _ in 0..5000 {
+                stretcher.sample(&source, 0);
+                stretcher.sample(&source, 1);
+                position += 0.75;
+                stretcher.advance(&source, position, 0.75);
+            }
+        });
+    }
+}
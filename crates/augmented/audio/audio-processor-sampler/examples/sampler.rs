@@ -0,0 +1,44 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Plays an SFZ or JSON mapping, set with the `SAMPLER_MAPPING` environment variable.
+//!
+//! Offline renders with a MIDI file use the standalone CLI options, e.g.:
+//!
+//! ```shell
+//! SAMPLER_MAPPING=kit.sfz cargo run --example sampler -- \
+//!     --midi-input-file beat.mid --output-file out.wav --tail
+//! ```
+use audio_garbage_collector::GarbageCollector;
+use audio_processor_sampler::{loader, Sampler};
+use audio_processor_standalone::audio_processor_main_with_midi;
+
+fn main() {
+    wisual_logger::init_from_env();
+    let mapping_path = std::env::var("SAMPLER_MAPPING").expect("SAMPLER_MAPPING isn't set");
+    let instrument = loader::load_instrument(mapping_path).expect("Failed to load the mapping");
+
+    let garbage_collector = GarbageCollector::default();
+    let sampler = Sampler::default();
+    sampler.handle().set_instrument(instrument);
+    audio_processor_main_with_midi(sampler, garbage_collector.handle());
+}
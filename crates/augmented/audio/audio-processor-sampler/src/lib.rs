@@ -0,0 +1,771 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A sample-accurate, voice-based sampler instrument.
+//!
+//! A [`SamplerInstrument`] maps key & velocity ranges to samples with [`SampleZone`]s. Each zone
+//! has its root note & fine tune, loop points with an optional crossfade, an ADSR envelope and
+//! belongs to a [`ZoneGroup`], which may limit its polyphony. Zones in a group can take turns
+//! as round-robin sequences.
+//!
+//! [`Sampler`] is the [`audio_processor_traits::AudioProcessor`] playing an instrument. MIDI
+//! events may be scheduled at a frame offset in the next block with
+//! [`Sampler::schedule_midi_event`], so notes start on the exact sample.
+//!
+//! Instruments can be loaded from SFZ files, or from JSON mappings using the same opcodes, see
+//! [`loader`].
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use audio_garbage_collector::{make_shared, make_shared_cell, Shared, SharedCell};
+use audio_processor_traits::{
+    AtomicF32, AudioBuffer, AudioContext, AudioProcessor, MidiEventHandler, MidiMessageLike,
+};
+use augmented_midi::{parse_midi_event, MIDIMessage, MIDIMessageNote, ParserState};
+use voice::Voice;
+
+pub use zone::*;
+
+pub mod loader;
+#[cfg(all(test, debug_assertions))]
+mod test_allocator;
+mod voice;
+mod zone;
+
+/// Number of voice slots. Voices fading out after being stolen keep their slot until they
+/// finish, so this is higher than [`MAX_POLYPHONY`].
+pub const MAX_VOICES: usize = 64;
+pub const MAX_POLYPHONY: usize = 48;
+/// Events scheduled in advance which haven't been processed yet
+pub const MAX_SCHEDULED_EVENTS: usize = 512;
+/// Length of the fade-out of stolen voices
+const STEAL_FADE_SECONDS: f32 = 0.005;
+
+const CC_SUSTAIN_PEDAL: u8 = 64;
+const CC_ALL_SOUND_OFF: u8 = 120;
+const CC_ALL_NOTES_OFF: u8 = 123;
+
+pub struct SamplerHandle {
+    instrument: SharedCell<SamplerInstrument>,
+    polyphony: AtomicUsize,
+    volume: AtomicF32,
+}
+
+impl Default for SamplerHandle {
+    fn default() -> Self {
+        Self {
+            instrument: make_shared_cell(SamplerInstrument::default()),
+            polyphony: AtomicUsize::new(32),
+            volume: AtomicF32::new(1.0),
+        }
+    }
+}
+
+impl SamplerHandle {
+    pub fn instrument(&self) -> Shared<SamplerInstrument> {
+        self.instrument.get()
+    }
+
+    /// Replace the instrument. Voices playing the previous instrument are stopped.
+    pub fn set_instrument(&self, instrument: SamplerInstrument) {
+        self.instrument.set(make_shared(instrument));
+    }
+
+    /// Maximum number of voices playing at once, the oldest voice is faded out when a new note
+    /// goes over it
+    pub fn polyphony(&self) -> usize {
+        self.polyphony.load(Ordering::Relaxed)
+    }
+
+    pub fn set_polyphony(&self, value: usize) {
+        self.polyphony
+            .store(value.clamp(1, MAX_POLYPHONY), Ordering::Relaxed);
+    }
+
+    /// Output gain
+    pub fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    pub fn set_volume(&self, value: f32) {
+        self.volume.set(value.max(0.0));
+    }
+}
+
+/// A MIDI event waiting for its frame offset
+#[derive(Clone, Copy)]
+struct ScheduledEvent {
+    offset: usize,
+    bytes: [u8; 3],
+    len: usize,
+}
+
+/// Plays a [`SamplerInstrument`], see the crate documentation.
+pub struct Sampler {
+    handle: Shared<SamplerHandle>,
+    instrument: Shared<SamplerInstrument>,
+    voices: Vec<Voice>,
+    note_counter: u64,
+    is_sustain_pedal_down: bool,
+    scheduled_events: Vec<ScheduledEvent>,
+    midi_parser_state: ParserState,
+    sample_rate: f32,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new(make_shared(SamplerHandle::default()))
+    }
+}
+
+impl Sampler {
+    pub fn new(handle: Shared<SamplerHandle>) -> Self {
+        let sample_rate = 44100.0;
+        Self {
+            instrument: handle.instrument(),
+            handle,
+            voices: (0..MAX_VOICES).map(|_| Voice::new(sample_rate)).collect(),
+            note_counter: 0,
+            is_sustain_pedal_down: false,
+            scheduled_events: Vec::with_capacity(MAX_SCHEDULED_EVENTS),
+            midi_parser_state: ParserState::default(),
+            sample_rate,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<SamplerHandle> {
+        &self.handle
+    }
+
+    /// Number of voices producing sound, including voices fading out
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.is_active()).count()
+    }
+
+    /// Queue a MIDI message to be handled `offset` frames into the next processed block.
+    /// Offsets past the block carry over into the following blocks. Messages are dropped once
+    /// [`MAX_SCHEDULED_EVENTS`] are waiting.
+    pub fn schedule_midi_event(&mut self, offset: usize, message: &[u8]) {
+        if self.scheduled_events.len() >= MAX_SCHEDULED_EVENTS
+            || message.is_empty()
+            || message.len() > 3
+        {
+            return;
+        }
+
+        let mut bytes = [0; 3];
+        bytes[..message.len()].copy_from_slice(message);
+        let event = ScheduledEvent {
+            offset,
+            bytes,
+            len: message.len(),
+        };
+        // Events at the same offset keep the order they were scheduled in
+        let index = self
+            .scheduled_events
+            .iter()
+            .position(|other| other.offset > offset)
+            .unwrap_or(self.scheduled_events.len());
+        self.scheduled_events.insert(index, event);
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if velocity == 0 {
+            self.note_off(note);
+            return;
+        }
+
+        self.note_counter += 1;
+        let instrument = self.instrument.clone();
+        for (zone_index, zone) in instrument.zones.iter().enumerate() {
+            if !zone.matches(note, velocity) {
+                continue;
+            }
+            let round_robin_counter = instrument
+                .groups
+                .get(zone.group)
+                .map(|group| group.round_robin_counter())
+                .unwrap_or(0);
+            if !zone.matches_round_robin(round_robin_counter) {
+                continue;
+            }
+
+            let group_polyphony = instrument
+                .groups
+                .get(zone.group)
+                .and_then(|group| group.polyphony);
+            let voice_index = self.allocate_voice(zone.group, group_polyphony);
+            self.voices[voice_index].note_on(zone_index, zone, note, velocity, self.note_counter);
+        }
+
+        for (group_index, group) in instrument.groups.iter().enumerate() {
+            let has_round_robin = instrument.zones.iter().any(|zone| {
+                zone.group == group_index
+                    && zone.round_robin.is_some()
+                    && zone.matches(note, velocity)
+            });
+            if has_round_robin {
+                group.advance_round_robin();
+            }
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        for voice in &mut self.voices {
+            if voice.note() == Some(note) {
+                voice.note_off(self.is_sustain_pedal_down);
+            }
+        }
+    }
+
+    pub fn set_sustain_pedal(&mut self, is_down: bool) {
+        self.is_sustain_pedal_down = is_down;
+        if !is_down {
+            for voice in &mut self.voices {
+                voice.release_sustain();
+            }
+        }
+    }
+
+    /// Release all voices
+    pub fn all_notes_off(&mut self) {
+        self.is_sustain_pedal_down = false;
+        for voice in &mut self.voices {
+            voice.note_off(false);
+        }
+    }
+
+    /// Stop all voices immediately
+    pub fn all_sound_off(&mut self) {
+        for voice in &mut self.voices {
+            voice.stop();
+        }
+    }
+
+    /// Find a voice slot for a new note, fading out voices over the group & global polyphony
+    fn allocate_voice(&mut self, group: usize, group_polyphony: Option<usize>) -> usize {
+        let fade_frames = (STEAL_FADE_SECONDS * self.sample_rate) as usize;
+        if let Some(group_polyphony) = group_polyphony {
+            self.fade_out_voices(group_polyphony.max(1) - 1, Some(group), fade_frames);
+        }
+        self.fade_out_voices(self.handle.polyphony() - 1, None, fade_frames);
+
+        if let Some(index) = self.voices.iter().position(|voice| !voice.is_active()) {
+            return index;
+        }
+        // All slots are taken by fading voices, cut the oldest
+        self.voices
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, voice)| voice.started_at())
+            .map(|(index, _)| index)
+            .unwrap_or(0)
+    }
+
+    /// Fade out the oldest voices, released voices first, until at most `limit` are playing
+    fn fade_out_voices(&mut self, limit: usize, group: Option<usize>, fade_frames: usize) {
+        let is_candidate = |voice: &Voice| match group {
+            Some(group) => voice.is_playing() && voice.group() == group,
+            None => voice.is_playing(),
+        };
+        loop {
+            let playing = self
+                .voices
+                .iter()
+                .filter(|voice| is_candidate(voice))
+                .count();
+            if playing <= limit {
+                return;
+            }
+            let stolen = self
+                .voices
+                .iter()
+                .enumerate()
+                .filter(|(_, voice)| is_candidate(voice))
+                .min_by_key(|(_, voice)| (voice.is_held(), voice.started_at()))
+                .map(|(index, _)| index);
+            match stolen {
+                Some(index) => self.voices[index].fade_out(fade_frames),
+                None => return,
+            }
+        }
+    }
+
+    fn handle_midi_message(&mut self, message: &MIDIMessage<&[u8]>) {
+        match message {
+            MIDIMessage::NoteOn(MIDIMessageNote { note, velocity, .. }) => {
+                self.note_on(*note, *velocity);
+            }
+            MIDIMessage::NoteOff(MIDIMessageNote { note, .. }) => {
+                self.note_off(*note);
+            }
+            MIDIMessage::ControlChange {
+                controller_number,
+                value,
+                ..
+            } => match *controller_number {
+                CC_SUSTAIN_PEDAL => self.set_sustain_pedal(*value >= 64),
+                CC_ALL_SOUND_OFF => self.all_sound_off(),
+                CC_ALL_NOTES_OFF => self.all_notes_off(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn handle_midi_bytes(&mut self, bytes: &[u8]) {
+        if let Ok((_, message)) = parse_midi_event::<&[u8]>(bytes, &mut self.midi_parser_state) {
+            self.handle_midi_message(&message);
+        }
+    }
+
+    fn render_voices(&mut self, data: &mut AudioBuffer<f32>, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        for voice in &mut self.voices {
+            if !voice.is_active() {
+                continue;
+            }
+            match self.instrument.zones.get(voice.zone_index()) {
+                Some(zone) => voice.process(zone, data, start, end),
+                None => voice.stop(),
+            }
+        }
+    }
+}
+
+impl AudioProcessor for Sampler {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        self.sample_rate = context.settings.sample_rate();
+        for voice in &mut self.voices {
+            voice.prepare(self.sample_rate);
+        }
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+        let instrument = self.handle.instrument();
+        if !std::ptr::eq(&*instrument, &*self.instrument) {
+            self.all_sound_off();
+            self.instrument = instrument;
+        }
+
+        // Silence the input
+        for sample in data.slice_mut() {
+            *sample = 0.0;
+        }
+
+        let num_samples = data.num_samples();
+        let mut position = 0;
+        let mut num_handled = 0;
+        while let Some(event) = self.scheduled_events.get(num_handled).copied() {
+            if event.offset >= num_samples {
+                break;
+            }
+            self.render_voices(data, position, event.offset);
+            position = position.max(event.offset);
+            self.handle_midi_bytes(&event.bytes[..event.len]);
+            num_handled += 1;
+        }
+        self.render_voices(data, position, num_samples);
+
+        self.scheduled_events.drain(..num_handled);
+        for event in &mut self.scheduled_events {
+            event.offset -= num_samples;
+        }
+
+        let volume = self.handle.volume();
+        if volume != 1.0 {
+            for sample in data.slice_mut() {
+                *sample *= volume;
+            }
+        }
+    }
+}
+
+impl MidiEventHandler for Sampler {
+    fn process_midi_events<Message: MidiMessageLike>(&mut self, midi_messages: &[Message]) {
+        for message in midi_messages {
+            if let Some(bytes) = message.bytes() {
+                self.schedule_midi_event(0, bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    struct TestMessage(Vec<u8>);
+
+    impl MidiMessageLike for TestMessage {
+        fn is_midi(&self) -> bool {
+            true
+        }
+
+        fn bytes(&self) -> Option<&[u8]> {
+            Some(&self.0)
+        }
+    }
+
+    /// A zone playing a constant `value`, so the output level identifies which zone played
+    fn dc_zone(value: f32) -> SampleZone {
+        let mut zone = SampleZone::new(
+            make_shared(AudioBuffer::new(vec![vec![value; 1000]])),
+            1000.0,
+        );
+        zone.loop_mode = LoopMode::LoopContinuous;
+        zone
+    }
+
+    fn setup(instrument: SamplerInstrument) -> (Sampler, AudioContext, AudioBuffer<f32>) {
+        let settings = AudioProcessorSettings::new(1000.0, 2, 2, 64);
+        let mut context = AudioContext::from(settings);
+        let mut sampler = Sampler::default();
+        sampler.handle().set_instrument(instrument);
+        sampler.prepare(&mut context);
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(2, 64);
+        sampler.process(&mut context, &mut buffer);
+        (sampler, context, buffer)
+    }
+
+    fn play(
+        sampler: &mut Sampler,
+        context: &mut AudioContext,
+        buffer: &mut AudioBuffer<f32>,
+        message: Vec<u8>,
+    ) -> f32 {
+        sampler.process_midi_events(&[TestMessage(message)]);
+        sampler.process(context, buffer);
+        *buffer.get(0, 63)
+    }
+
+    #[test]
+    fn test_notes_play_their_key_zones() {
+        let mut low = dc_zone(0.25);
+        low.key_range = (0, 59);
+        let mut high = dc_zone(0.5);
+        high.key_range = (60, 127);
+        let (mut sampler, mut context, mut buffer) = setup(SamplerInstrument::new(vec![low, high]));
+
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x90, 40, 127]),
+            0.25
+        );
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x80, 40, 0]),
+            0.0
+        );
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x90, 72, 127]),
+            0.5
+        );
+        assert_eq!(*buffer.get(1, 63), 0.5);
+    }
+
+    #[test]
+    fn test_velocity_layers() {
+        let mut soft = dc_zone(0.25);
+        soft.velocity_range = (0, 63);
+        let mut loud = dc_zone(0.5);
+        loud.velocity_range = (64, 127);
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![soft, loud]));
+
+        let soft_level = play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 63]);
+        let velocity_gain = (63.0_f32 / 127.0).powi(2);
+        assert!((soft_level - 0.25 * velocity_gain).abs() < 1e-6);
+        play(&mut sampler, &mut context, &mut buffer, vec![0x80, 60, 0]);
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 127]),
+            0.5
+        );
+    }
+
+    #[test]
+    fn test_round_robin_zones_take_turns() {
+        let zones = (0..3)
+            .map(|position| {
+                let mut zone = dc_zone(0.1 * (position + 1) as f32);
+                zone.round_robin = Some(RoundRobin {
+                    position,
+                    length: 3,
+                });
+                zone
+            })
+            .collect();
+        let (mut sampler, mut context, mut buffer) = setup(SamplerInstrument::new(zones));
+
+        let mut levels = vec![];
+        for _ in 0..4 {
+            levels.push(play(
+                &mut sampler,
+                &mut context,
+                &mut buffer,
+                vec![0x90, 60, 127],
+            ));
+            play(&mut sampler, &mut context, &mut buffer, vec![0x80, 60, 0]);
+        }
+        let expected = [0.1, 0.2, 0.3, 0.1];
+        for (level, expected) in levels.iter().zip(expected) {
+            assert!((level - expected).abs() < 1e-6, "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn test_polyphony_limit_fades_the_oldest_voice() {
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![dc_zone(0.25)]));
+        sampler.handle().set_polyphony(2);
+
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 127]);
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 62, 127]);
+        let level = play(&mut sampler, &mut context, &mut buffer, vec![0x90, 64, 127]);
+        assert_eq!(level, 0.5);
+        assert_eq!(sampler.active_voices(), 2);
+        let playing: Vec<u8> = sampler
+            .voices
+            .iter()
+            .filter_map(|voice| voice.note())
+            .collect();
+        assert_eq!(playing, vec![62, 64]);
+    }
+
+    #[test]
+    fn test_group_polyphony_chokes_the_group() {
+        let mut closed_hat = dc_zone(0.25);
+        closed_hat.key_range = (42, 42);
+        let mut open_hat = dc_zone(0.5);
+        open_hat.key_range = (46, 46);
+        let mut kick = dc_zone(0.125);
+        kick.key_range = (36, 36);
+        kick.group = 1;
+        let instrument = SamplerInstrument::with_groups(
+            vec![closed_hat, open_hat, kick],
+            vec![ZoneGroup::new(Some(1))],
+        );
+        let (mut sampler, mut context, mut buffer) = setup(instrument);
+
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 36, 127]);
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 46, 127]);
+        let level = play(&mut sampler, &mut context, &mut buffer, vec![0x90, 42, 127]);
+        assert_eq!(level, 0.125 + 0.25);
+    }
+
+    #[test]
+    fn test_scheduled_events_are_sample_accurate() {
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![dc_zone(0.5)]));
+        sampler.schedule_midi_event(10, &[0x90, 60, 127]);
+        sampler.schedule_midi_event(40, &[0x80, 60, 0]);
+        sampler.process(&mut context, &mut buffer);
+
+        let output = buffer.channel(0);
+        assert!(output[..10].iter().all(|sample| *sample == 0.0));
+        assert!(output[10..40].iter().all(|sample| *sample == 0.5));
+        // Released with the default 10ms release
+        assert!(output[41] < 0.5 && output[41] > 0.0);
+        assert!(output[50..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn test_scheduled_events_carry_over_into_the_next_blocks() {
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![dc_zone(0.5)]));
+        sampler.schedule_midi_event(64 * 2 + 5, &[0x90, 60, 127]);
+        sampler.process(&mut context, &mut buffer);
+        sampler.process(&mut context, &mut buffer);
+        assert!(buffer.channel(0).iter().all(|sample| *sample == 0.0));
+
+        sampler.process(&mut context, &mut buffer);
+        let output = buffer.channel(0);
+        assert!(output[..5].iter().all(|sample| *sample == 0.0));
+        assert!(output[5..].iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn test_sustain_pedal_holds_released_notes() {
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![dc_zone(0.5)]));
+        play(&mut sampler, &mut context, &mut buffer, vec![0xB0, 64, 127]);
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 127]);
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x80, 60, 0]),
+            0.5
+        );
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0xB0, 64, 0]),
+            0.0
+        );
+        assert_eq!(sampler.active_voices(), 0);
+    }
+
+    #[test]
+    fn test_changing_the_instrument_stops_voices() {
+        let (mut sampler, mut context, mut buffer) =
+            setup(SamplerInstrument::new(vec![dc_zone(0.5)]));
+        play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 127]);
+        sampler
+            .handle()
+            .set_instrument(SamplerInstrument::new(vec![dc_zone(0.25)]));
+        sampler.process(&mut context, &mut buffer);
+        assert_eq!(sampler.active_voices(), 0);
+        assert_eq!(
+            play(&mut sampler, &mut context, &mut buffer, vec![0x90, 60, 127]),
+            0.25
+        );
+    }
+
+    #[test]
+    fn test_process_doesnt_allocate() {
+        let mut zone = dc_zone(0.5);
+        zone.round_robin = Some(RoundRobin {
+            position: 0,
+            length: 1,
+        });
+        let (mut sampler, mut context, mut buffer) = setup(SamplerInstrument::new(vec![zone]));
+        let messages: Vec<TestMessage> = (0..MAX_VOICES as u8)
+            .map(|note| TestMessage(vec![0x90, note, 100]))
+            .collect();
+
+        assert_no_alloc(|| {
+            for offset in 0..64 {
+                sampler.schedule_midi_event(offset, &[0x80, offset as u8, 0]);
+            }
+            sampler.process_midi_events(&messages);
+            for _ in 0..10 {
+                sampler.process(&mut context, &mut buffer);
+            }
+        });
+    }
+
+    #[test]
+    fn test_offline_render_with_a_midi_file() {
+        use audio_processor_file::file_io::{default_read_audio_file, read_file_contents};
+        use audio_processor_file::OutputAudioFileProcessor;
+        use audio_processor_standalone::offline::{
+            run_offline_render, OfflineRenderOptions, OfflineRenderSettings, TailSettings,
+        };
+        use audio_processor_standalone::StandaloneProcessorImpl;
+        use augmented_midi::{
+            MIDIFile, MIDIFileChunk, MIDIFileDivision, MIDIFileFormat, MIDIFileHeader,
+            MIDITrackEvent, MIDITrackInner,
+        };
+        use symphonia::core::audio::Signal;
+
+        let _ = wisual_logger::try_init_from_env();
+        let directory = tempdir::TempDir::new("sampler_offline_render").unwrap();
+        let write_wav = |name: &str, channels: Vec<Vec<f32>>| {
+            let path = directory.path().join(name);
+            let num_channels = channels.len();
+            let settings =
+                AudioProcessorSettings::new(44100.0, num_channels, num_channels, channels[0].len());
+            let mut output = OutputAudioFileProcessor::from_path(settings, path.to_str().unwrap());
+            output.prepare(settings).unwrap();
+            output.process(&mut AudioBuffer::new(channels)).unwrap();
+            output.finalize().unwrap();
+            path
+        };
+        let sample_path = write_wav("sample.wav", vec![vec![0.5; 44100]]);
+        let output_path = directory.path().join("output.wav");
+
+        let sampler = Sampler::default();
+        let instrument = loader::SamplerMapping {
+            zones: vec![loader::ZoneMapping {
+                sample: sample_path.to_str().unwrap().to_string(),
+                ..Default::default()
+            }],
+        }
+        .load(directory.path())
+        .unwrap();
+        sampler.handle().set_instrument(instrument);
+
+        // 120bpm, so a note-on at the start and a note-off at 0.5s
+        let note = |delta_time, message| MIDITrackEvent {
+            delta_time,
+            inner: MIDITrackInner::Message(message),
+        };
+        let midi_file = MIDIFile::new(vec![
+            MIDIFileChunk::Header(MIDIFileHeader {
+                format: MIDIFileFormat::Single,
+                num_tracks: 1,
+                division: MIDIFileDivision::TicksPerQuarterNote {
+                    ticks_per_quarter_note: 480,
+                },
+            }),
+            MIDIFileChunk::Track {
+                events: vec![
+                    note(
+                        0,
+                        MIDIMessage::NoteOn(MIDIMessageNote {
+                            channel: 0,
+                            note: 60,
+                            velocity: 127,
+                        }),
+                    ),
+                    note(
+                        480,
+                        MIDIMessage::NoteOff(MIDIMessageNote {
+                            channel: 0,
+                            note: 60,
+                            velocity: 0,
+                        }),
+                    ),
+                ],
+            },
+        ]);
+
+        // Without an input the render lasts for the MIDI file, then for the release tail
+        let summary = run_offline_render(OfflineRenderOptions {
+            app: StandaloneProcessorImpl::new(sampler),
+            handle: Some(audio_garbage_collector::handle()),
+            input_path: None,
+            output_path: output_path.to_str().unwrap(),
+            midi_input_file: Some(midi_file),
+            settings: OfflineRenderSettings {
+                tail: Some(TailSettings::default()),
+                ..OfflineRenderSettings::default()
+            },
+            on_progress: None,
+        })
+        .unwrap();
+        assert!(summary.tail_frames > 0);
+
+        let mut output = default_read_audio_file(output_path.to_str().unwrap()).unwrap();
+        let output = read_file_contents(&mut output).unwrap();
+        let left = output.chan(0);
+        let right = output.chan(1);
+        assert!(left[100..22000]
+            .iter()
+            .all(|sample| (sample - 0.5).abs() < 1e-3));
+        assert_eq!(&left[100..22000], &right[100..22000]);
+        assert!(left[23000..].iter().all(|sample| sample.abs() < 1e-3));
+    }
+}
@@ -0,0 +1,374 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Load a [`SamplerInstrument`] from an SFZ file or a JSON mapping.
+//!
+//! Both formats describe zones with the same subset of SFZ opcodes, see [`ZoneMapping`]. A JSON
+//! mapping looks like:
+//!
+//! ```json
+//! {
+//!   "zones": [
+//!     { "sample": "piano/c4.wav", "lokey": 0, "hikey": 62, "pitch_keycenter": 60 },
+//!     { "sample": "piano/e4.wav", "lokey": 63, "hikey": 127, "pitch_keycenter": 64 }
+//!   ]
+//! }
+//! ```
+//!
+//! Sample paths are relative to the mapping file. Zones without loop points use the ones
+//! stored on the sample file, if any.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_file::file_io::{default_read_audio_file, read_file_contents, AudioFileError};
+use audio_processor_file::metadata::{read_metadata, AudioFileMetadata};
+use audio_processor_traits::AudioBuffer;
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Signal;
+
+use crate::zone::{AmpEnvelope, LoopMode, RoundRobin, SampleZone, SamplerInstrument, ZoneGroup};
+
+pub use sfz::parse_sfz;
+
+mod sfz;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SamplerLoadError {
+    #[error("Failed to read the mapping file")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse the JSON mapping")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid SFZ on line {line}: {message}")]
+    Sfz { line: usize, message: String },
+    #[error("Failed to read sample {path:?}")]
+    Sample {
+        path: PathBuf,
+        #[source]
+        source: AudioFileError,
+    },
+    #[error("Unsupported mapping format, expected an .sfz or .json file")]
+    UnsupportedFormat,
+}
+
+/// A zone using SFZ opcode names. Missing opcodes use their SFZ defaults.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoneMapping {
+    pub sample: String,
+    pub lokey: Option<u8>,
+    pub hikey: Option<u8>,
+    /// Sets `lokey`, `hikey` & `pitch_keycenter`
+    pub key: Option<u8>,
+    pub lovel: Option<u8>,
+    pub hivel: Option<u8>,
+    /// Defaults to the sample file's unity note, or 60
+    pub pitch_keycenter: Option<u8>,
+    /// Fine tune in cents
+    pub tune: Option<f32>,
+    /// Gain in dB
+    pub volume: Option<f32>,
+    /// Defaults to `loop_continuous` if the sample file has loop points
+    pub loop_mode: Option<LoopMode>,
+    /// First frame of the loop
+    pub loop_start: Option<usize>,
+    /// Last frame of the loop, inclusive
+    pub loop_end: Option<usize>,
+    /// Crossfade length in seconds
+    pub loop_crossfade: Option<f32>,
+    pub ampeg_attack: Option<f32>,
+    pub ampeg_decay: Option<f32>,
+    /// Sustain level in percent
+    pub ampeg_sustain: Option<f32>,
+    pub ampeg_release: Option<f32>,
+    pub group: Option<usize>,
+    /// Voice limit of the zone's group
+    pub polyphony: Option<usize>,
+    pub seq_length: Option<usize>,
+    /// 1-based position on the round-robin sequence
+    pub seq_position: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplerMapping {
+    pub zones: Vec<ZoneMapping>,
+}
+
+struct LoadedSample {
+    buffer: Shared<AudioBuffer<f32>>,
+    sample_rate: f32,
+    metadata: Option<AudioFileMetadata>,
+}
+
+fn read_sample(path: &Path) -> Result<LoadedSample, SamplerLoadError> {
+    let sample_error = |source| SamplerLoadError::Sample {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut probe = default_read_audio_file(&path.to_string_lossy()).map_err(sample_error)?;
+    let contents = read_file_contents(&mut probe).map_err(sample_error)?;
+    let channels = (0..contents.spec().channels.count())
+        .map(|channel| contents.chan(channel).to_vec())
+        .collect();
+    let metadata = read_metadata(path)
+        .map_err(|err| log::warn!("Failed to read metadata of {:?}: {}", path, err))
+        .ok();
+
+    Ok(LoadedSample {
+        buffer: make_shared(AudioBuffer::new(channels)),
+        sample_rate: contents.spec().rate as f32,
+        metadata,
+    })
+}
+
+impl SamplerMapping {
+    /// Read the samples & build the instrument. Relative sample paths are resolved from
+    /// `base_directory`.
+    pub fn load(&self, base_directory: &Path) -> Result<SamplerInstrument, SamplerLoadError> {
+        let mut samples: HashMap<PathBuf, LoadedSample> = HashMap::new();
+        let mut zones = Vec::with_capacity(self.zones.len());
+        let mut groups: Vec<ZoneGroup> = vec![];
+
+        for mapping in &self.zones {
+            let path = base_directory.join(&mapping.sample);
+            if !samples.contains_key(&path) {
+                let sample = read_sample(&path)?;
+                samples.insert(path.clone(), sample);
+            }
+            let sample = &samples[&path];
+            let zone = mapping.build_zone(sample);
+
+            while groups.len() <= zone.group {
+                groups.push(ZoneGroup::default());
+            }
+            if groups[zone.group].polyphony.is_none() {
+                groups[zone.group].polyphony = mapping.polyphony;
+            }
+            zones.push(zone);
+        }
+
+        Ok(SamplerInstrument::with_groups(zones, groups))
+    }
+}
+
+impl ZoneMapping {
+    fn build_zone(&self, sample: &LoadedSample) -> SampleZone {
+        let mut zone = SampleZone::new(sample.buffer.clone(), sample.sample_rate);
+        let sampler_info = sample
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.sampler.as_ref());
+        let file_loop = sample
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.loops().into_iter().next());
+
+        zone.key_range = (
+            self.lokey.or(self.key).unwrap_or(0),
+            self.hikey.or(self.key).unwrap_or(127),
+        );
+        zone.velocity_range = (self.lovel.unwrap_or(0), self.hivel.unwrap_or(127));
+        zone.root_note = self
+            .pitch_keycenter
+            .or(self.key)
+            .or_else(|| sampler_info.map(|info| info.midi_unity_note))
+            .unwrap_or(60);
+        zone.fine_tune = self.tune.unwrap_or(0.0);
+        if self.pitch_keycenter.or(self.key).is_none() {
+            // The sample file is this fraction of a semitone above its unity note
+            zone.fine_tune -= sampler_info
+                .map(|info| info.midi_pitch_fraction * 100.0)
+                .unwrap_or(0.0);
+        }
+        zone.gain_db = self.volume.unwrap_or(0.0);
+
+        zone.loop_mode = self.loop_mode.unwrap_or(if file_loop.is_some() {
+            LoopMode::LoopContinuous
+        } else {
+            LoopMode::NoLoop
+        });
+        if let Some(file_loop) = &file_loop {
+            zone.loop_start = file_loop.start;
+            zone.loop_end = file_loop.end + 1;
+        }
+        if let Some(loop_start) = self.loop_start {
+            zone.loop_start = loop_start;
+        }
+        if let Some(loop_end) = self.loop_end {
+            zone.loop_end = loop_end + 1;
+        }
+        zone.loop_crossfade = (self.loop_crossfade.unwrap_or(0.0) * sample.sample_rate) as usize;
+
+        let defaults = AmpEnvelope::default();
+        zone.envelope = AmpEnvelope {
+            attack: self.ampeg_attack.unwrap_or(defaults.attack),
+            decay: self.ampeg_decay.unwrap_or(defaults.decay),
+            sustain: self
+                .ampeg_sustain
+                .map(|sustain| sustain / 100.0)
+                .unwrap_or(defaults.sustain),
+            release: self.ampeg_release.unwrap_or(defaults.release),
+        };
+
+        zone.group = self.group.unwrap_or(0);
+        zone.round_robin = self
+            .seq_length
+            .filter(|length| *length > 1)
+            .map(|length| RoundRobin {
+                position: self.seq_position.unwrap_or(1).clamp(1, length) - 1,
+                length,
+            });
+        zone
+    }
+}
+
+/// Parse a JSON mapping, see the module documentation
+pub fn parse_json(source: &str) -> Result<SamplerMapping, SamplerLoadError> {
+    Ok(serde_json::from_str(source)?)
+}
+
+/// Load an `.sfz` or `.json` mapping & its samples
+pub fn load_instrument(path: impl AsRef<Path>) -> Result<SamplerInstrument, SamplerLoadError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let source = std::fs::read_to_string(path)?;
+    let mapping = match extension.as_deref() {
+        Some("sfz") => parse_sfz(&source)?,
+        Some("json") => parse_json(&source)?,
+        _ => return Err(SamplerLoadError::UnsupportedFormat),
+    };
+    let base_directory = path.parent().unwrap_or_else(|| Path::new("."));
+    mapping.load(base_directory)
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_file::OutputAudioFileProcessor;
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    fn write_wav(path: &Path, channel: Vec<f32>) {
+        let settings = AudioProcessorSettings::new(44100.0, 1, 1, channel.len());
+        let mut output = OutputAudioFileProcessor::from_path(settings, path.to_str().unwrap());
+        output.prepare(settings).unwrap();
+        output
+            .process(&mut AudioBuffer::new(vec![channel]))
+            .unwrap();
+        output.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_parse_json_mapping() {
+        let mapping = parse_json(
+            r#"{
+                "zones": [
+                    { "sample": "c4.wav", "lokey": 0, "hikey": 62, "pitch_keycenter": 60 },
+                    { "sample": "e4.wav", "key": 64, "loop_mode": "loop_sustain", "seq_length": 2 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(mapping.zones.len(), 2);
+        assert_eq!(mapping.zones[0].hikey, Some(62));
+        assert_eq!(mapping.zones[1].key, Some(64));
+        assert_eq!(mapping.zones[1].loop_mode, Some(LoopMode::LoopSustain));
+        assert_eq!(mapping.zones[1].seq_length, Some(2));
+    }
+
+    #[test]
+    fn test_load_instrument_from_sfz() {
+        let directory = tempdir::TempDir::new("sampler_loader").unwrap();
+        std::fs::create_dir(directory.path().join("samples")).unwrap();
+        write_wav(&directory.path().join("samples/kick.wav"), vec![0.5; 100]);
+        write_wav(
+            &directory.path().join("samples/snare 1.wav"),
+            vec![0.25; 200],
+        );
+        let sfz_path = directory.path().join("kit.sfz");
+        std::fs::write(
+            &sfz_path,
+            "<control> default_path=samples/\n\
+             <group> polyphony=1 ampeg_release=0.5\n\
+             <region> sample=kick.wav key=c1\n\
+             <region> sample=snare 1.wav key=d1 seq_length=2 seq_position=2 loop_mode=one_shot\n\
+             <region> sample=kick.wav key=e1 volume=-6\n",
+        )
+        .unwrap();
+
+        let instrument = load_instrument(&sfz_path).unwrap();
+        assert_eq!(instrument.zones.len(), 3);
+        assert_eq!(instrument.groups.len(), 1);
+        assert_eq!(instrument.groups[0].polyphony, Some(1));
+
+        let kick = &instrument.zones[0];
+        assert_eq!(kick.key_range, (24, 24));
+        assert_eq!(kick.root_note, 24);
+        assert_eq!(kick.sample.num_samples(), 100);
+        assert_eq!(kick.sample_rate, 44100.0);
+        assert_eq!(kick.envelope.release, 0.5);
+        // Samples used by more than one zone are only loaded once
+        assert!(std::ptr::eq(&*kick.sample, &*instrument.zones[2].sample));
+
+        let snare = &instrument.zones[1];
+        assert_eq!(snare.sample.num_samples(), 200);
+        assert_eq!(snare.loop_mode, LoopMode::OneShot);
+        assert_eq!(
+            snare.round_robin,
+            Some(RoundRobin {
+                position: 1,
+                length: 2
+            })
+        );
+        assert_eq!(instrument.zones[2].gain_db, -6.0);
+    }
+
+    #[test]
+    fn test_load_missing_sample_fails() {
+        let directory = tempdir::TempDir::new("sampler_loader").unwrap();
+        let mapping = SamplerMapping {
+            zones: vec![ZoneMapping {
+                sample: "missing.wav".to_string(),
+                ..Default::default()
+            }],
+        };
+        assert!(matches!(
+            mapping.load(directory.path()),
+            Err(SamplerLoadError::Sample { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_instrument_rejects_unknown_formats() {
+        let directory = tempdir::TempDir::new("sampler_loader").unwrap();
+        let path = directory.path().join("mapping.txt");
+        std::fs::write(&path, "").unwrap();
+        assert!(matches!(
+            load_instrument(&path),
+            Err(SamplerLoadError::UnsupportedFormat)
+        ));
+    }
+}
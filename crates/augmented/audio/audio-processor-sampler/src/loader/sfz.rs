@@ -0,0 +1,370 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Parser for the subset of SFZ which maps to [`ZoneMapping`] opcodes.
+//!
+//! `<control>`, `<global>`, `<group>` & `<region>` headers are supported. Regions inherit the
+//! opcodes of the global & group headers before them & each `<group>` header starts a new
+//! [`ZoneMapping::group`]. Unknown headers & opcodes are skipped with a warning.
+
+use super::{SamplerLoadError, SamplerMapping, ZoneMapping};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Header {
+    Control,
+    Global,
+    Group,
+    Region,
+    Unknown,
+}
+
+/// Parse a note number or name, where middle C is `c4` (60)
+fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(note) = value.parse::<u8>() {
+        return (note <= 127).then_some(note);
+    }
+
+    let value = value.to_lowercase();
+    let mut chars = value.chars();
+    let pitch_class: i32 = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+    let note = (octave + 1) * 12 + pitch_class + accidental;
+    (0..=127).contains(&note).then_some(note as u8)
+}
+
+/// Index where the opcode value starting at `rest` ends: before the next `name=` or header.
+/// Values may contain spaces, which is common on sample paths.
+fn value_end(rest: &str) -> usize {
+    for (index, char) in rest.char_indices() {
+        if char == '<' {
+            return index;
+        }
+        if !char.is_whitespace() {
+            continue;
+        }
+        let next = rest[index..].trim_start();
+        let is_opcode = next
+            .split_once('=')
+            .map(|(name, _)| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|char| char.is_ascii_alphanumeric() || char == '_')
+            })
+            .unwrap_or(false);
+        if is_opcode {
+            return index;
+        }
+    }
+    rest.len()
+}
+
+struct SfzParser {
+    mapping: SamplerMapping,
+    header: Header,
+    default_path: String,
+    global: Vec<(String, String)>,
+    group: Vec<(String, String)>,
+    region: Option<Vec<(String, String)>>,
+    /// Line of the current region's header, for errors
+    region_line: usize,
+    group_index: usize,
+    has_group: bool,
+}
+
+impl SfzParser {
+    fn new() -> Self {
+        Self {
+            mapping: SamplerMapping::default(),
+            header: Header::Unknown,
+            default_path: String::new(),
+            global: vec![],
+            group: vec![],
+            region: None,
+            region_line: 0,
+            group_index: 0,
+            has_group: false,
+        }
+    }
+
+    fn start_header(&mut self, name: &str, line: usize) -> Result<(), SamplerLoadError> {
+        self.finish_region()?;
+        self.header = match name {
+            "control" => Header::Control,
+            "global" => {
+                self.global.clear();
+                self.group.clear();
+                Header::Global
+            }
+            "group" => {
+                // Regions before the first group header are on a group of their own
+                if self.has_group || !self.mapping.zones.is_empty() {
+                    self.group_index += 1;
+                }
+                self.has_group = true;
+                self.group.clear();
+                Header::Group
+            }
+            "region" => {
+                self.region = Some(vec![]);
+                self.region_line = line;
+                Header::Region
+            }
+            _ => {
+                log::warn!(
+                    "Skipping unsupported SFZ header <{}> on line {}",
+                    name,
+                    line
+                );
+                Header::Unknown
+            }
+        };
+        Ok(())
+    }
+
+    fn opcode(&mut self, name: &str, value: &str) {
+        let opcode = (name.to_string(), value.to_string());
+        match self.header {
+            Header::Control => {
+                if name == "default_path" {
+                    self.default_path = value.replace('\\', "/");
+                } else {
+                    log::warn!("Skipping unsupported SFZ control opcode {}", name);
+                }
+            }
+            Header::Global => self.global.push(opcode),
+            Header::Group => self.group.push(opcode),
+            Header::Region => {
+                if let Some(region) = &mut self.region {
+                    region.push(opcode);
+                }
+            }
+            Header::Unknown => {}
+        }
+    }
+
+    fn finish_region(&mut self) -> Result<(), SamplerLoadError> {
+        let line = self.region_line;
+        let region = match self.region.take() {
+            Some(region) => region,
+            None => return Ok(()),
+        };
+
+        let mut zone = ZoneMapping {
+            group: Some(self.group_index),
+            ..Default::default()
+        };
+        for (name, value) in self.global.iter().chain(&self.group).chain(&region) {
+            set_opcode(&mut zone, name, value).map_err(|message| SamplerLoadError::Sfz {
+                line,
+                message: format!("{}={}: {}", name, value, message),
+            })?;
+        }
+        if zone.sample.is_empty() {
+            return Err(SamplerLoadError::Sfz {
+                line,
+                message: "region has no sample".to_string(),
+            });
+        }
+        zone.sample = format!("{}{}", self.default_path, zone.sample.replace('\\', "/"));
+        self.mapping.zones.push(zone);
+        Ok(())
+    }
+}
+
+fn set_opcode(zone: &mut ZoneMapping, name: &str, value: &str) -> Result<(), String> {
+    fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+        value.parse().map_err(|_| "invalid number".to_string())
+    }
+    fn note(value: &str) -> Result<u8, String> {
+        parse_note(value).ok_or_else(|| "invalid note".to_string())
+    }
+
+    match name {
+        "sample" => zone.sample = value.to_string(),
+        "lokey" => zone.lokey = Some(note(value)?),
+        "hikey" => zone.hikey = Some(note(value)?),
+        "key" => zone.key = Some(note(value)?),
+        "lovel" => zone.lovel = Some(number(value)?),
+        "hivel" => zone.hivel = Some(number(value)?),
+        "pitch_keycenter" => zone.pitch_keycenter = Some(note(value)?),
+        "tune" => zone.tune = Some(number(value)?),
+        "volume" => zone.volume = Some(number(value)?),
+        "loop_mode" => {
+            zone.loop_mode = Some(
+                serde_json::from_value(serde_json::Value::String(value.to_string()))
+                    .map_err(|_| "invalid loop mode".to_string())?,
+            )
+        }
+        "loop_start" | "loopstart" => zone.loop_start = Some(number(value)?),
+        "loop_end" | "loopend" => zone.loop_end = Some(number(value)?),
+        "loop_crossfade" => zone.loop_crossfade = Some(number(value)?),
+        "ampeg_attack" => zone.ampeg_attack = Some(number(value)?),
+        "ampeg_decay" => zone.ampeg_decay = Some(number(value)?),
+        "ampeg_sustain" => zone.ampeg_sustain = Some(number(value)?),
+        "ampeg_release" => zone.ampeg_release = Some(number(value)?),
+        "polyphony" => zone.polyphony = Some(number(value)?),
+        "seq_length" => zone.seq_length = Some(number(value)?),
+        "seq_position" => zone.seq_position = Some(number(value)?),
+        _ => log::warn!("Skipping unsupported SFZ opcode {}", name),
+    }
+    Ok(())
+}
+
+/// Parse an SFZ file into a mapping, see the module documentation
+pub fn parse_sfz(source: &str) -> Result<SamplerMapping, SamplerLoadError> {
+    let mut parser = SfzParser::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut rest = match line.find("//") {
+            Some(index) => &line[..index],
+            None => line,
+        }
+        .trim();
+
+        while !rest.is_empty() {
+            if let Some(header) = rest.strip_prefix('<') {
+                let end = header.find('>').ok_or_else(|| SamplerLoadError::Sfz {
+                    line: line_number,
+                    message: "unterminated header".to_string(),
+                })?;
+                parser.start_header(header[..end].trim(), line_number)?;
+                rest = header[end + 1..].trim_start();
+                continue;
+            }
+
+            let (name, value) = rest.split_once('=').ok_or_else(|| SamplerLoadError::Sfz {
+                line: line_number,
+                message: format!("expected an opcode, found {:?}", rest),
+            })?;
+            let end = value_end(value);
+            parser.opcode(name.trim(), value[..end].trim());
+            rest = value[end..].trim_start();
+        }
+    }
+    parser.finish_region()?;
+
+    Ok(parser.mapping)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::zone::LoopMode;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_note_names() {
+        assert_eq!(parse_note("60"), Some(60));
+        assert_eq!(parse_note("c4"), Some(60));
+        assert_eq!(parse_note("C#4"), Some(61));
+        assert_eq!(parse_note("db4"), Some(61));
+        assert_eq!(parse_note("c-1"), Some(0));
+        assert_eq!(parse_note("g9"), Some(127));
+        assert_eq!(parse_note("a9"), None);
+        assert_eq!(parse_note("h4"), None);
+    }
+
+    #[test]
+    fn test_regions_inherit_global_and_group_opcodes() {
+        let mapping = parse_sfz(
+            "// A comment\n\
+             <global> ampeg_release=0.3\n\
+             <group> lovel=0 hivel=63 volume=-3\n\
+             <region> sample=soft.wav key=60\n\
+             <region> sample=soft.wav key=62 ampeg_release=1 // trailing comment\n\
+             <group> lovel=64 hivel=127\n\
+             <region>\n\
+             sample=loud.wav\n\
+             lokey=c4 hikey=d4 pitch_keycenter=c4 loop_mode=loop_continuous\n",
+        )
+        .unwrap();
+
+        assert_eq!(mapping.zones.len(), 3);
+        let soft = &mapping.zones[0];
+        assert_eq!(soft.sample, "soft.wav");
+        assert_eq!(soft.key, Some(60));
+        assert_eq!(soft.hivel, Some(63));
+        assert_eq!(soft.volume, Some(-3.0));
+        assert_eq!(soft.ampeg_release, Some(0.3));
+        assert_eq!(soft.group, Some(0));
+        assert_eq!(mapping.zones[1].ampeg_release, Some(1.0));
+
+        let loud = &mapping.zones[2];
+        assert_eq!(loud.lovel, Some(64));
+        assert_eq!(loud.volume, None);
+        assert_eq!((loud.lokey, loud.hikey), (Some(60), Some(62)));
+        assert_eq!(loud.loop_mode, Some(LoopMode::LoopContinuous));
+        assert_eq!(loud.group, Some(1));
+    }
+
+    #[test]
+    fn test_sample_paths_may_contain_spaces() {
+        let mapping = parse_sfz(
+            "<control> default_path=Drum Kit\\\n\
+             <region> sample=Kick Drum 1.wav key=36<region>sample=snare.wav key=38",
+        )
+        .unwrap();
+        assert_eq!(mapping.zones[0].sample, "Drum Kit/Kick Drum 1.wav");
+        assert_eq!(mapping.zones[0].key, Some(36));
+        assert_eq!(mapping.zones[1].sample, "Drum Kit/snare.wav");
+    }
+
+    #[test]
+    fn test_unknown_opcodes_are_skipped() {
+        let mapping = parse_sfz("<region> sample=a.wav cutoff=1000 <curve> v000=0").unwrap();
+        assert_eq!(mapping.zones.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_values_fail() {
+        assert!(matches!(
+            parse_sfz("<region> sample=a.wav\n<region> sample=b.wav\nlokey=x9\n<region>"),
+            Err(SamplerLoadError::Sfz { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse_sfz("<region> key=60"),
+            Err(SamplerLoadError::Sfz { .. })
+        ));
+        assert!(matches!(
+            parse_sfz("<region sample=a.wav"),
+            Err(SamplerLoadError::Sfz { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::time::Duration;
+
+use audio_processor_traits::AudioBuffer;
+use augmented_adsr_envelope::MultiStageEnvelope;
+
+use crate::zone::{LoopMode, SampleZone};
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Linear interpolation between frames. `wrap` is the loop `(start, end)`, reading the frame
+/// after the loop end goes back to its start.
+#[inline]
+fn read_frame(channel: &[f32], position: f64, wrap: Option<(usize, usize)>) -> f32 {
+    let index = position as usize;
+    let fraction = (position - index as f64) as f32;
+    let next_index = match wrap {
+        Some((start, end)) if index + 1 >= end => start,
+        _ => index + 1,
+    };
+    let current = channel.get(index).copied().unwrap_or(0.0);
+    let next = channel.get(next_index).copied().unwrap_or(0.0);
+    current + (next - current) * fraction
+}
+
+/// A voice playing a single zone
+pub struct Voice {
+    zone_index: usize,
+    group: usize,
+    note: u8,
+    is_active: bool,
+    is_held: bool,
+    /// Held by the sustain pedal after its note-off
+    is_sustained: bool,
+    loop_mode: LoopMode,
+    /// Position in frames of the sample
+    position: f64,
+    increment: f64,
+    gain: f32,
+    envelope: MultiStageEnvelope,
+    /// Remaining & total frames of a fade-out, for voices stolen by the polyphony limits
+    fade: Option<(usize, usize)>,
+    /// Note-on counter value when this voice started, used to find the oldest voice
+    started_at: u64,
+    sample_rate: f32,
+}
+
+impl Voice {
+    pub fn new(sample_rate: f32) -> Self {
+        let envelope = MultiStageEnvelope::adsr(
+            Duration::ZERO,
+            Duration::ZERO,
+            1.0,
+            Duration::from_millis(10),
+        );
+        envelope.set_sample_rate(sample_rate);
+        Voice {
+            zone_index: 0,
+            group: 0,
+            note: 0,
+            is_active: false,
+            is_held: false,
+            is_sustained: false,
+            loop_mode: LoopMode::NoLoop,
+            position: 0.0,
+            increment: 1.0,
+            gain: 1.0,
+            envelope,
+            fade: None,
+            started_at: 0,
+            sample_rate,
+        }
+    }
+
+    pub fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.envelope.set_sample_rate(sample_rate);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// Whether the voice is playing & not fading out, which is what counts against polyphony
+    pub fn is_playing(&self) -> bool {
+        self.is_active && self.fade.is_none()
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.is_active && (self.is_held || self.is_sustained)
+    }
+
+    pub fn note(&self) -> Option<u8> {
+        self.is_active.then_some(self.note)
+    }
+
+    pub fn group(&self) -> usize {
+        self.group
+    }
+
+    pub fn zone_index(&self) -> usize {
+        self.zone_index
+    }
+
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn note_on(
+        &mut self,
+        zone_index: usize,
+        zone: &SampleZone,
+        note: u8,
+        velocity: u8,
+        started_at: u64,
+    ) {
+        let semitones = note as f64 - zone.root_note as f64 + zone.fine_tune as f64 / 100.0;
+        self.increment =
+            2.0_f64.powf(semitones / 12.0) * zone.sample_rate as f64 / self.sample_rate as f64;
+        let velocity = velocity as f32 / 127.0;
+        self.gain = db_to_gain(zone.gain_db) * velocity * velocity;
+
+        let envelope = &zone.envelope;
+        self.envelope
+            .set_attack(Duration::from_secs_f32(envelope.attack.max(0.0)));
+        self.envelope
+            .set_decay(Duration::from_secs_f32(envelope.decay.max(0.0)));
+        self.envelope.set_sustain(envelope.sustain.clamp(0.0, 1.0));
+        self.envelope
+            .set_release(Duration::from_secs_f32(envelope.release.max(0.0)));
+        self.envelope.note_on();
+
+        self.zone_index = zone_index;
+        self.group = zone.group;
+        self.note = note;
+        self.loop_mode = zone.loop_mode;
+        self.position = 0.0;
+        self.fade = None;
+        self.is_active = true;
+        self.is_held = true;
+        self.is_sustained = false;
+        self.started_at = started_at;
+    }
+
+    /// Release the voice. If `sustain` is set the release waits for [`Voice::release_sustain`].
+    pub fn note_off(&mut self, sustain: bool) {
+        if !self.is_held {
+            return;
+        }
+        self.is_held = false;
+        if sustain {
+            self.is_sustained = true;
+        } else if self.loop_mode != LoopMode::OneShot {
+            self.envelope.note_off();
+        }
+    }
+
+    /// Release a voice held by the sustain pedal
+    pub fn release_sustain(&mut self) {
+        if self.is_sustained {
+            self.is_sustained = false;
+            if self.loop_mode != LoopMode::OneShot {
+                self.envelope.note_off();
+            }
+        }
+    }
+
+    /// Fade the voice out over `frames` output frames
+    pub fn fade_out(&mut self, frames: usize) {
+        if self.is_playing() {
+            self.fade = Some((frames.max(1), frames.max(1)));
+        }
+    }
+
+    /// Stop immediately
+    pub fn stop(&mut self) {
+        self.is_active = false;
+        self.is_held = false;
+        self.is_sustained = false;
+        self.fade = None;
+    }
+
+    fn is_looping(&self) -> bool {
+        match self.loop_mode {
+            LoopMode::LoopContinuous => true,
+            LoopMode::LoopSustain => self.is_held || self.is_sustained,
+            LoopMode::NoLoop | LoopMode::OneShot => false,
+        }
+    }
+
+    /// Add this voice's output to `data` between the `start` & `end` frames
+    pub fn process(
+        &mut self,
+        zone: &SampleZone,
+        data: &mut AudioBuffer<f32>,
+        start: usize,
+        end: usize,
+    ) {
+        let sample = &*zone.sample;
+        let sample_channels = sample.num_channels();
+        let sample_length = sample.num_samples();
+        if sample_channels == 0 {
+            self.stop();
+            return;
+        }
+        let loop_points = zone.loop_points();
+
+        for frame in start..end {
+            let fade_gain = match &mut self.fade {
+                Some((remaining, total)) => {
+                    let gain = *remaining as f32 / *total as f32;
+                    *remaining -= 1;
+                    gain
+                }
+                None => 1.0,
+            };
+            let looping = self.is_looping().then_some(loop_points).flatten();
+            let gain = self.gain * self.envelope.volume() * fade_gain;
+
+            for channel_index in 0..data.num_channels() {
+                let channel = sample.channel(channel_index % sample_channels);
+                let value = match looping {
+                    Some((loop_start, loop_end, crossfade))
+                        if crossfade > 0 && self.position >= (loop_end - crossfade) as f64 =>
+                    {
+                        // Fade the loop end into the frames before the loop start, so the
+                        // jump back is continuous
+                        let t = ((self.position - (loop_end - crossfade) as f64) / crossfade as f64)
+                            as f32;
+                        let tail = read_frame(channel, self.position, None);
+                        let head = read_frame(
+                            channel,
+                            self.position - (loop_end - loop_start) as f64,
+                            None,
+                        );
+                        tail * (1.0 - t) + head * t
+                    }
+                    Some((loop_start, loop_end, _)) => {
+                        read_frame(channel, self.position, Some((loop_start, loop_end)))
+                    }
+                    None => read_frame(channel, self.position, None),
+                };
+                *data.get_mut(channel_index, frame) += value * gain;
+            }
+
+            self.envelope.tick();
+            self.position += self.increment;
+            if let Some((loop_start, loop_end, _)) = looping {
+                while self.position >= loop_end as f64 {
+                    self.position -= (loop_end - loop_start) as f64;
+                }
+            }
+
+            let has_finished = self.position >= sample_length as f64
+                || self.envelope.is_idle()
+                || self
+                    .fade
+                    .map(|(remaining, _)| remaining == 0)
+                    .unwrap_or(false);
+            if has_finished {
+                self.stop();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_garbage_collector::make_shared;
+
+    use super::*;
+
+    fn zone(samples: Vec<f32>) -> SampleZone {
+        SampleZone::new(make_shared(AudioBuffer::new(vec![samples])), 1000.0)
+    }
+
+    fn render(voice: &mut Voice, zone: &SampleZone, frames: usize) -> Vec<f32> {
+        let mut buffer = AudioBuffer::empty();
+        buffer.resize(1, frames);
+        voice.process(zone, &mut buffer, 0, frames);
+        buffer.channel(0).to_vec()
+    }
+
+    #[test]
+    fn test_plays_the_sample_at_the_root_note() {
+        let zone = zone((0..10).map(|i| i as f32).collect());
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 20);
+        assert_eq!(
+            &output[..10],
+            &(0..10).map(|i| i as f32).collect::<Vec<_>>()
+        );
+        assert_eq!(&output[10..], &[0.0; 10]);
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn test_an_octave_up_plays_twice_as_fast() {
+        let zone = zone((0..100).map(|i| i as f32).collect());
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 72, 127, 0);
+        let output = render(&mut voice, &zone, 10);
+        for (index, value) in output.iter().enumerate() {
+            assert!((value - index as f32 * 2.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_sample_rate_is_converted_to_the_output_rate() {
+        let zone = zone((0..100).map(|i| i as f32).collect());
+        let mut voice = Voice::new(2000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 10);
+        for (index, value) in output.iter().enumerate() {
+            assert!((value - index as f32 * 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_continuous_loop_keeps_playing_after_the_sample_end() {
+        let mut zone = zone((0..10).map(|i| i as f32).collect());
+        zone.loop_mode = LoopMode::LoopContinuous;
+        zone.loop_start = 4;
+        zone.loop_end = 8;
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 16);
+        assert_eq!(
+            output,
+            vec![0., 1., 2., 3., 4., 5., 6., 7., 4., 5., 6., 7., 4., 5., 6., 7.]
+        );
+        assert!(voice.is_active());
+    }
+
+    #[test]
+    fn test_loop_crossfade_is_continuous() {
+        let ramp: Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0).collect();
+        let mut zone = zone(ramp);
+        zone.loop_mode = LoopMode::LoopContinuous;
+        zone.loop_start = 300;
+        zone.loop_end = 800;
+        let max_step = |output: &[f32]| {
+            output
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0.0_f32, f32::max)
+        };
+        let mut voice = Voice::new(1000.0);
+
+        // Without a crossfade the loop end jumps back
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 2000);
+        assert!(max_step(&output) > 0.4);
+
+        zone.loop_crossfade = 100;
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 2000);
+        assert!(max_step(&output) < 0.01);
+    }
+
+    #[test]
+    fn test_loop_sustain_plays_the_tail_after_note_off() {
+        let mut zone = zone((0..10).map(|i| i as f32).collect());
+        zone.loop_mode = LoopMode::LoopSustain;
+        zone.loop_start = 2;
+        zone.loop_end = 4;
+        zone.envelope.release = 1.0;
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        let output = render(&mut voice, &zone, 6);
+        assert_eq!(output, vec![0., 1., 2., 3., 2., 3.]);
+
+        voice.note_off(false);
+        let output = render(&mut voice, &zone, 10);
+        assert_eq!(output[0], 2.0);
+        assert!(output[7] > 8.9 && output[7] < 9.0);
+        assert_eq!(&output[8..], &[0.0; 2]);
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn test_one_shot_ignores_note_off() {
+        let mut zone = zone(vec![1.0; 10]);
+        zone.loop_mode = LoopMode::OneShot;
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        voice.note_off(false);
+        let output = render(&mut voice, &zone, 12);
+        assert_eq!(&output[..10], &[1.0; 10]);
+        assert!(!voice.is_active());
+    }
+
+    #[test]
+    fn test_fade_out_stops_the_voice() {
+        let mut zone = zone(vec![1.0; 100]);
+        zone.loop_mode = LoopMode::LoopContinuous;
+        let mut voice = Voice::new(1000.0);
+        voice.note_on(0, &zone, 60, 127, 0);
+        voice.fade_out(4);
+        assert!(!voice.is_playing());
+        let output = render(&mut voice, &zone, 6);
+        assert_eq!(output, vec![1.0, 0.75, 0.5, 0.25, 0.0, 0.0]);
+        assert!(!voice.is_active());
+    }
+}
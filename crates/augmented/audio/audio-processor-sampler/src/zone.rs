@@ -0,0 +1,256 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Zones map a key & velocity range to a sample, see [`SampleZone`].
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use audio_garbage_collector::Shared;
+use audio_processor_traits::AudioBuffer;
+use serde::{Deserialize, Serialize};
+
+/// How a zone's sample plays, using the SFZ `loop_mode` names
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// Play the sample once, stopping early on note-off after the release
+    #[default]
+    NoLoop,
+    /// Play the whole sample once, ignoring note-offs
+    OneShot,
+    /// Loop between the loop points until the voice is released & the release finishes
+    LoopContinuous,
+    /// Loop between the loop points while the note is held, then play the rest of the sample
+    LoopSustain,
+}
+
+/// Amplitude envelope of a zone, times are in seconds & sustain is between 0 and 1
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AmpEnvelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Default for AmpEnvelope {
+    fn default() -> Self {
+        Self {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            // A short release so note-offs don't click
+            release: 0.01,
+        }
+    }
+}
+
+/// Round-robin position of a zone. Zones with the same group & key/velocity range take turns,
+/// each note-on on the group moves to the next `position`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundRobin {
+    /// 0-based position on the sequence
+    pub position: usize,
+    pub length: usize,
+}
+
+/// A sample mapped to a range of keys & velocities
+#[derive(Clone)]
+pub struct SampleZone {
+    pub sample: Shared<AudioBuffer<f32>>,
+    /// Sample rate of `sample`, playback is resampled to the output rate
+    pub sample_rate: f32,
+    /// Inclusive range of MIDI notes
+    pub key_range: (u8, u8),
+    /// Inclusive range of velocities
+    pub velocity_range: (u8, u8),
+    /// MIDI note at which the sample plays at its original pitch
+    pub root_note: u8,
+    /// Fine tune in cents
+    pub fine_tune: f32,
+    pub gain_db: f32,
+    pub loop_mode: LoopMode,
+    /// First frame of the loop
+    pub loop_start: usize,
+    /// Frame after the end of the loop
+    pub loop_end: usize,
+    /// Length of the crossfade into the loop end, in frames of the sample
+    pub loop_crossfade: usize,
+    pub envelope: AmpEnvelope,
+    /// Index into [`SamplerInstrument::groups`]
+    pub group: usize,
+    pub round_robin: Option<RoundRobin>,
+}
+
+impl SampleZone {
+    /// Create a zone playing `sample` over all keys & velocities with middle C as its root note
+    pub fn new(sample: Shared<AudioBuffer<f32>>, sample_rate: f32) -> Self {
+        let num_samples = sample.num_samples();
+        Self {
+            sample,
+            sample_rate,
+            key_range: (0, 127),
+            velocity_range: (0, 127),
+            root_note: 60,
+            fine_tune: 0.0,
+            gain_db: 0.0,
+            loop_mode: LoopMode::NoLoop,
+            loop_start: 0,
+            loop_end: num_samples,
+            loop_crossfade: 0,
+            envelope: AmpEnvelope::default(),
+            group: 0,
+            round_robin: None,
+        }
+    }
+
+    pub fn matches(&self, note: u8, velocity: u8) -> bool {
+        (self.key_range.0..=self.key_range.1).contains(&note)
+            && (self.velocity_range.0..=self.velocity_range.1).contains(&velocity)
+    }
+
+    /// Whether this zone plays on the current round-robin `counter` of its group
+    pub fn matches_round_robin(&self, counter: usize) -> bool {
+        match self.round_robin {
+            Some(RoundRobin { position, length }) => counter % length.max(1) == position,
+            None => true,
+        }
+    }
+
+    /// Loop start, end & crossfade length clamped to the sample. `None` if the loop is empty.
+    ///
+    /// The crossfade reads from before the loop start, so it can't be longer than the loop nor
+    /// than the frames before it.
+    pub fn loop_points(&self) -> Option<(usize, usize, usize)> {
+        let end = self.loop_end.min(self.sample.num_samples());
+        let start = self.loop_start;
+        if start >= end {
+            return None;
+        }
+        let crossfade = self.loop_crossfade.min(start).min(end - start);
+        Some((start, end, crossfade))
+    }
+}
+
+/// Settings shared by the zones of a group
+#[derive(Debug, Default)]
+pub struct ZoneGroup {
+    /// Maximum number of voices the group may play at once, the oldest voice is faded out
+    /// when a new note goes over it
+    pub polyphony: Option<usize>,
+    round_robin_counter: AtomicUsize,
+}
+
+impl ZoneGroup {
+    pub fn new(polyphony: Option<usize>) -> Self {
+        Self {
+            polyphony,
+            round_robin_counter: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn round_robin_counter(&self) -> usize {
+        self.round_robin_counter.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn advance_round_robin(&self) {
+        self.round_robin_counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A set of zones, which is what the [`crate::Sampler`] plays
+#[derive(Default)]
+pub struct SamplerInstrument {
+    pub zones: Vec<SampleZone>,
+    pub groups: Vec<ZoneGroup>,
+}
+
+impl SamplerInstrument {
+    /// Create an instrument with enough groups with no polyphony limit for `zones`
+    pub fn new(zones: Vec<SampleZone>) -> Self {
+        let num_groups = zones.iter().map(|zone| zone.group + 1).max().unwrap_or(0);
+        Self {
+            zones,
+            groups: (0..num_groups).map(|_| ZoneGroup::default()).collect(),
+        }
+    }
+
+    pub fn with_groups(zones: Vec<SampleZone>, groups: Vec<ZoneGroup>) -> Self {
+        let mut instrument = Self { zones, groups };
+        let num_groups = instrument
+            .zones
+            .iter()
+            .map(|zone| zone.group + 1)
+            .max()
+            .unwrap_or(0);
+        while instrument.groups.len() < num_groups {
+            instrument.groups.push(ZoneGroup::default());
+        }
+        instrument
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_garbage_collector::make_shared;
+
+    use super::*;
+
+    fn zone(num_samples: usize) -> SampleZone {
+        SampleZone::new(
+            make_shared(AudioBuffer::new(vec![vec![0.0; num_samples]])),
+            1000.0,
+        )
+    }
+
+    #[test]
+    fn test_matches_key_and_velocity_ranges() {
+        let mut zone = zone(10);
+        zone.key_range = (60, 64);
+        zone.velocity_range = (64, 127);
+        assert!(zone.matches(60, 100));
+        assert!(zone.matches(64, 64));
+        assert!(!zone.matches(65, 100));
+        assert!(!zone.matches(62, 63));
+    }
+
+    #[test]
+    fn test_loop_points_are_clamped_to_the_sample() {
+        let mut zone = zone(100);
+        zone.loop_start = 20;
+        zone.loop_end = 1000;
+        zone.loop_crossfade = 50;
+        assert_eq!(zone.loop_points(), Some((20, 100, 20)));
+
+        zone.loop_start = 100;
+        assert_eq!(zone.loop_points(), None);
+    }
+
+    #[test]
+    fn test_instrument_creates_groups_for_its_zones() {
+        let mut second = zone(10);
+        second.group = 2;
+        let instrument = SamplerInstrument::new(vec![zone(10), second]);
+        assert_eq!(instrument.groups.len(), 3);
+    }
+}
@@ -26,9 +26,10 @@
 //! - - -
 //! Time-based effects.
 //!
-//! Contains a mono delay processor implementation and a version of "FreeVerb".
+//! Contains a mono delay processor implementation, a version of "FreeVerb" and a modulated
+//! diffused reverb with a feedback delay network ([`mod_reverb::ModReverbProcessor`]).
 //!
-//! Also WIP implementations of a chorus processor and a modulated diffused reverb.
+//! Also a WIP implementation of a chorus processor.
 //!
 //! # References
 //! * FreeVerb - https://ccrma.stanford.edu/~jos/pasp/Freeverb.html
@@ -21,26 +21,117 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 use audio_garbage_collector::Shared;
-use audio_processor_traits::parameters::{AudioProcessorHandle, ParameterSpec, ParameterValue};
+use audio_processor_traits::parameters::{
+    AudioProcessorHandle, FloatType, ParameterSpec, ParameterType, ParameterValue,
+};
 
-use crate::mod_reverb::ModReverbHandle;
+use crate::mod_reverb::{FeedbackMatrix, ModReverbHandle, MAX_DIFFUSION_STEPS};
 
 pub struct GenericHandle(pub Shared<ModReverbHandle>);
 
 impl AudioProcessorHandle for GenericHandle {
+    fn name(&self) -> String {
+        "Mod Reverb".to_string()
+    }
+
     fn parameter_count(&self) -> usize {
-        0
+        8
     }
 
-    fn get_parameter_spec(&self, _index: usize) -> ParameterSpec {
-        todo!("NOT IMPLEMENTED")
+    fn get_parameter_spec(&self, index: usize) -> ParameterSpec {
+        let specs: [ParameterSpec; 8] = [
+            ParameterSpec::new(
+                "Diffusion".into(),
+                ParameterType::Float(FloatType {
+                    range: (1.0, MAX_DIFFUSION_STEPS as f32),
+                    step: Some(1.0),
+                }),
+            ),
+            ParameterSpec::new(
+                "Matrix".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 1.0),
+                    step: Some(1.0),
+                }),
+            ),
+            ParameterSpec::new(
+                "Decay".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.1, 20.0),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Damping".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 0.99),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Pre-delay".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 0.5),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Modulation".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 1.0),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Early/Late".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 1.0),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Dry/Wet".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 1.0),
+                    step: None,
+                }),
+            ),
+        ];
+        specs[index].clone()
     }
 
-    fn get_parameter(&self, _index: usize) -> Option<ParameterValue> {
-        None
+    fn get_parameter(&self, index: usize) -> Option<ParameterValue> {
+        match index {
+            0 => Some((self.0.diffusion_steps() as f32).into()),
+            1 => Some((self.0.feedback_matrix() as usize as f32).into()),
+            2 => Some(self.0.decay_time_secs().into()),
+            3 => Some(self.0.damping().into()),
+            4 => Some(self.0.pre_delay_secs().into()),
+            5 => Some(self.0.modulation_depth().into()),
+            6 => Some(self.0.early_late_mix().into()),
+            7 => Some(self.0.dry_wet().into()),
+            _ => None,
+        }
     }
 
-    fn set_parameter(&self, _index: usize, _request: ParameterValue) {
-        todo!("NOT IMPLEMENTED")
+    fn set_parameter(&self, index: usize, request: ParameterValue) {
+        if let Ok(value) = request.try_into() {
+            let value: f32 = value;
+            match index {
+                0 => self.0.set_diffusion_steps(value.round() as usize),
+                1 => self.0.set_feedback_matrix(if value >= 0.5 {
+                    FeedbackMatrix::Hadamard
+                } else {
+                    FeedbackMatrix::Householder
+                }),
+                2 => self.0.set_decay_time_secs(value),
+                3 => self.0.set_damping(value),
+                4 => self.0.set_pre_delay_secs(value),
+                5 => self.0.set_modulation_depth(value),
+                6 => self.0.set_early_late_mix(value),
+                7 => self.0.set_dry_wet(value),
+                _ => {}
+            }
+        }
     }
 }
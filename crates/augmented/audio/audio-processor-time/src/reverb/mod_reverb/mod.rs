@@ -20,9 +20,10 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
+use num_derive::{FromPrimitive, ToPrimitive};
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
@@ -32,40 +33,178 @@ use audio_processor_traits::parameters::{
     make_handle_ref, AudioProcessorHandleProvider, AudioProcessorHandleRef,
 };
 use audio_processor_traits::simple_processor::MonoAudioProcessor;
-use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
-use augmented_dsp_filters::rbj::{FilterProcessor, FilterType};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor};
+use augmented_atomics::AtomicEnum;
 use augmented_oscillator::Oscillator;
 use generic_handle::GenericHandle;
 
-use crate::MonoDelayProcessor;
+use crate::reverb::utils::undenormalize;
+use crate::{MonoDelayProcessor, MonoDelayProcessorHandle};
 
 use self::mix_matrix::{apply_householder, HadamardMatrix};
 
 mod generic_handle;
 mod mix_matrix;
 
+/// Maximum number of diffusion steps the input goes through
+pub const MAX_DIFFUSION_STEPS: usize = 6;
+/// Number of channels in the diffuser & feedback delay network
+const CHANNELS: usize = 8;
+/// Total diffusion time across all diffusion steps
+const DIFFUSION_TIME_SECS: f32 = 0.15;
+/// Shortest delay line in the feedback delay network, others are spread exponentially above it
+const BASE_DELAY_TIME_SECS: f32 = 0.1;
+/// Relative delay time change at full modulation depth
+const MAX_DELAY_MODULATION: f32 = 0.002;
+const MAX_PRE_DELAY_SECS: f32 = 0.5;
+/// Sum of 4 decorrelated channels into one
+const STEREO_MIX_SCALE: f32 = 0.5;
+
 fn flip_polarities(frame: &mut [f32]) {
     for sample in frame {
         *sample = -*sample
     }
 }
 
-pub struct ModReverbHandle {}
+/// Feedback gain for a delay line of `delay_time_secs` so that the network decays by 60dB over
+/// `decay_time_secs`.
+fn decay_gain(delay_time_secs: f32, decay_time_secs: f32) -> f32 {
+    10.0_f32.powf(-3.0 * delay_time_secs / decay_time_secs)
+}
+
+fn make_delay_line(max_delay_time: Duration) -> MonoDelayProcessor<f32> {
+    MonoDelayProcessor::new(
+        max_delay_time,
+        make_shared(MonoDelayProcessorHandle::default()),
+    )
+}
+
+/// The matrix used to mix the feedback delay network channels back into each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum FeedbackMatrix {
+    Householder = 0,
+    Hadamard = 1,
+}
+
+pub struct ModReverbHandle {
+    diffusion_steps: AtomicUsize,
+    feedback_matrix: AtomicEnum<FeedbackMatrix>,
+    decay_time_secs: AtomicF32,
+    damping: AtomicF32,
+    pre_delay_secs: AtomicF32,
+    modulation_depth: AtomicF32,
+    early_late_mix: AtomicF32,
+    dry_wet: AtomicF32,
+}
+
+impl Default for ModReverbHandle {
+    fn default() -> Self {
+        Self {
+            diffusion_steps: AtomicUsize::new(MAX_DIFFUSION_STEPS),
+            feedback_matrix: AtomicEnum::new(FeedbackMatrix::Householder),
+            decay_time_secs: AtomicF32::new(2.0),
+            damping: AtomicF32::new(0.3),
+            pre_delay_secs: AtomicF32::new(0.0),
+            modulation_depth: AtomicF32::new(0.25),
+            early_late_mix: AtomicF32::new(0.7),
+            dry_wet: AtomicF32::new(0.5),
+        }
+    }
+}
+
+impl ModReverbHandle {
+    /// Number of diffusion steps, between 1 and [`MAX_DIFFUSION_STEPS`]
+    pub fn diffusion_steps(&self) -> usize {
+        self.diffusion_steps.load(Ordering::Relaxed)
+    }
+
+    pub fn set_diffusion_steps(&self, value: usize) {
+        self.diffusion_steps
+            .store(value.clamp(1, MAX_DIFFUSION_STEPS), Ordering::Relaxed);
+    }
+
+    pub fn feedback_matrix(&self) -> FeedbackMatrix {
+        self.feedback_matrix.get()
+    }
+
+    pub fn set_feedback_matrix(&self, value: FeedbackMatrix) {
+        self.feedback_matrix.set(value);
+    }
+
+    /// Time in seconds for the reverb tail to decay by 60dB (RT60)
+    pub fn decay_time_secs(&self) -> f32 {
+        self.decay_time_secs.get()
+    }
+
+    pub fn set_decay_time_secs(&self, value: f32) {
+        self.decay_time_secs.set(value.max(0.01));
+    }
+
+    /// High-frequency damping on the feedback loop, between 0 and 1
+    pub fn damping(&self) -> f32 {
+        self.damping.get()
+    }
+
+    pub fn set_damping(&self, value: f32) {
+        self.damping.set(value.clamp(0.0, 0.99));
+    }
+
+    pub fn pre_delay_secs(&self) -> f32 {
+        self.pre_delay_secs.get()
+    }
+
+    pub fn set_pre_delay_secs(&self, value: f32) {
+        self.pre_delay_secs
+            .set(value.clamp(0.0, MAX_PRE_DELAY_SECS));
+    }
+
+    /// Depth of delay time modulation on the feedback delay network, between 0 and 1
+    pub fn modulation_depth(&self) -> f32 {
+        self.modulation_depth.get()
+    }
+
+    pub fn set_modulation_depth(&self, value: f32) {
+        self.modulation_depth.set(value.clamp(0.0, 1.0));
+    }
+
+    /// Balance between early reflections (0) and the late reverb tail (1)
+    pub fn early_late_mix(&self) -> f32 {
+        self.early_late_mix.get()
+    }
+
+    pub fn set_early_late_mix(&self, value: f32) {
+        self.early_late_mix.set(value.clamp(0.0, 1.0));
+    }
+
+    pub fn dry_wet(&self) -> f32 {
+        self.dry_wet.get()
+    }
+
+    pub fn set_dry_wet(&self, value: f32) {
+        self.dry_wet.set(value.clamp(0.0, 1.0));
+    }
+}
 
 /// Implements the reverb described by Geraint Luff on:
 ///
 /// * "Let's write a Reverb - ADC21 - https://www.youtube.com/watch?v=6ZK2Goiyotk"
 ///
-/// This is a reverb based on a multi-channel diffuser and delay.
+/// This is a reverb based on a multi-channel diffuser and a feedback delay network.
 ///
-/// A low-pass filter is added at the end of the signal, delay times are modulated.
+/// The input goes through a pre-delay and a configurable number of diffusion steps, which
+/// generate the early reflections. The diffused signal then feeds an 8 channel feedback delay
+/// network, mixed through a Householder or Hadamard matrix, with damping on the feedback loop and
+/// modulated delay times. Feedback gains are derived from the decay time (RT60).
 pub struct ModReverbProcessor {
     handle: Shared<ModReverbHandle>,
-    diffusers: [Diffuser<8>; 6],
-    delay: [MonoDelayProcessor<f32>; 8],
-    filter: [FilterProcessor<f32>; 2],
-    diffuser_modulator: Oscillator<f32>,
+    pre_delay: [MonoDelayProcessor<f32>; 2],
+    diffusers: [Diffuser<CHANNELS>; MAX_DIFFUSION_STEPS],
+    delay: [MonoDelayProcessor<f32>; CHANNELS],
+    delay_times: [f32; CHANNELS],
+    damping_state: [f32; CHANNELS],
+    hadamard_matrix: HadamardMatrix<CHANNELS>,
     delay_modulator: Oscillator<f32>,
+    sample_rate: f32,
 }
 
 impl AudioProcessorHandleProvider for ModReverbProcessor {
@@ -77,135 +216,179 @@ impl AudioProcessorHandleProvider for ModReverbProcessor {
 impl Default for ModReverbProcessor {
     fn default() -> Self {
         Self {
-            handle: make_shared(ModReverbHandle {}),
-            diffusers: [
-                Diffuser::default(),
-                Diffuser::default(),
-                Diffuser::default(),
-                Diffuser::default(),
-                Diffuser::default(),
-                Diffuser::default(),
-            ],
-            delay: [
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-                MonoDelayProcessor::default(),
-            ],
-            filter: [
-                FilterProcessor::new(FilterType::LowPass),
-                FilterProcessor::new(FilterType::LowPass),
-            ],
-            diffuser_modulator: Oscillator::sine(44100.0),
+            handle: make_shared(ModReverbHandle::default()),
+            pre_delay: [(); 2].map(|_| make_delay_line(Duration::from_secs(1))),
+            diffusers: [(); MAX_DIFFUSION_STEPS].map(|_| Diffuser::default()),
+            delay: [(); CHANNELS].map(|_| make_delay_line(Duration::from_secs(1))),
+            delay_times: [0.0; CHANNELS],
+            damping_state: [0.0; CHANNELS],
+            hadamard_matrix: HadamardMatrix::new(),
             delay_modulator: Oscillator::sine(44100.0),
+            sample_rate: 44100.0,
         }
     }
 }
 
+impl ModReverbProcessor {
+    pub fn handle(&self) -> &Shared<ModReverbHandle> {
+        &self.handle
+    }
+}
+
 impl AudioProcessor for ModReverbProcessor {
     type SampleType = f32;
 
     fn prepare(&mut self, context: &mut AudioContext) {
-        let mut max_delay_time = 0.5 / (self.diffusers.len() as f32).powf(2.0);
+        self.sample_rate = context.settings.sample_rate();
+
+        for pre_delay in &mut self.pre_delay {
+            pre_delay.m_prepare(context);
+            pre_delay.handle().set_feedback(0.0);
+        }
+
+        // Each step doubles the maximum delay time of the last, up-to `DIFFUSION_TIME_SECS`
+        let mut max_delay_time =
+            DIFFUSION_TIME_SECS / (2.0_f32.powi(self.diffusers.len() as i32) - 1.0);
         for diffuser in self.diffusers.iter_mut() {
             diffuser.max_delay_time = Duration::from_secs_f32(max_delay_time);
             diffuser.prepare(context);
             max_delay_time *= 2.0;
         }
 
-        for delay in &mut self.delay {
+        for (i, (delay, delay_time)) in self.delay.iter_mut().zip(&mut self.delay_times).enumerate()
+        {
+            *delay_time = BASE_DELAY_TIME_SECS * 2.0_f32.powf(i as f32 / CHANNELS as f32);
             delay.m_prepare(context);
-            delay.handle().set_delay_time_secs(0.2);
+            delay.handle().set_feedback(0.0);
+            delay.handle().set_delay_time_secs(*delay_time);
         }
+        self.damping_state = [0.0; CHANNELS];
 
-        self.diffuser_modulator
-            .set_sample_rate(context.settings.sample_rate());
-        self.diffuser_modulator.set_frequency(1.0);
         self.delay_modulator
             .set_sample_rate(context.settings.sample_rate());
         self.delay_modulator.set_frequency(0.3);
-
-        for filter in &mut self.filter {
-            filter.m_prepare(context);
-            filter.set_q(1.0);
-            filter.set_cutoff(800.0);
-        }
     }
 
     fn process(&mut self, context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
-        // Last delay line feedback / volume
-        let delay_feedback = 0.9;
-        let delay_volume = 0.5;
-        let delay_time = 0.15;
-        // Reverb volume
-        let reverb_volume = 0.5;
-        // Modulation
-        let delay_modulated_amount = 0.0005;
-        let diffuser_modulated_amount = 0.0;
+        let diffusion_steps = self.handle.diffusion_steps();
+        let feedback_matrix = self.handle.feedback_matrix();
+        let damping = self.handle.damping();
+        let early_late_mix = self.handle.early_late_mix();
+        let dry_wet = self.handle.dry_wet();
+        let delay_modulated_amount = self.handle.modulation_depth() * MAX_DELAY_MODULATION;
+        let decay_time_secs = self.handle.decay_time_secs();
+        let feedback_gains = self
+            .delay_times
+            .map(|delay_time| decay_gain(delay_time, decay_time_secs));
+
+        // Anything under a sample of pre-delay is bypassed, since the delay line would read
+        // back a full buffer
+        let pre_delay_secs = self.handle.pre_delay_secs();
+        let has_pre_delay = pre_delay_secs * self.sample_rate >= 1.0;
+        if has_pre_delay {
+            for pre_delay in &mut self.pre_delay {
+                pre_delay.handle().set_delay_time_secs(pre_delay_secs);
+            }
+        }
+
+        let is_stereo = data.num_channels() > 1;
 
         // For each frame
         for sample_num in 0..data.num_samples() {
-            // Modulate diffusion delay times
-            let diffuser_modulation = self.diffuser_modulator.next_sample(); // -1.0..1.0
-            let diffuser_modulation = 1.0 + diffuser_modulation * diffuser_modulated_amount;
-            for diffuser in self.diffusers.iter_mut() {
-                diffuser.set_delay_mult(diffuser_modulation);
-            }
             // Modulate multi-channel delay times
-            let delay_modulation = self.delay_modulator.next_sample();
+            let delay_modulation = self.delay_modulator.next_sample(); // -1.0..1.0
             let delay_modulation = 1.0 + delay_modulation * delay_modulated_amount;
-            let delay_duration = delay_time * delay_modulation;
-            for delay in &mut self.delay {
-                delay.handle().set_delay_time_secs(delay_duration);
+            for (delay, delay_time) in self.delay.iter_mut().zip(&self.delay_times) {
+                delay
+                    .handle()
+                    .set_delay_time_secs(delay_time * delay_modulation);
             }
 
             let left = data.channel(0)[sample_num];
-            let right = data.channel(1)[sample_num];
+            let right = if is_stereo {
+                data.channel(1)[sample_num]
+            } else {
+                left
+            };
+
+            // The pre-delay lines are always written so they're up-to-date when enabled
+            let pre_delayed_left = self.pre_delay[0].m_process(context, left);
+            let pre_delayed_right = self.pre_delay[1].m_process(context, right);
+            let (input_left, input_right) = if has_pre_delay {
+                (pre_delayed_left, pre_delayed_right)
+            } else {
+                (left, right)
+            };
 
             // Generate a 8 channel input signal
-            let mut frame8 = [left, right, left, right, left, right, left, right];
+            let mut frame8 = [
+                input_left,
+                input_right,
+                input_left,
+                input_right,
+                input_left,
+                input_right,
+                input_left,
+                input_right,
+            ];
 
-            // Run it through a diffusion step
-            for diffuser in &mut self.diffusers {
+            // Run it through the diffusion steps
+            for diffuser in self.diffusers.iter_mut().take(diffusion_steps) {
                 diffuser.process(context, &mut frame8);
             }
-
-            // Run it through a multi-channel delay line
-            let mut delayed = [0.0; 8];
-            for (delay, delay_output) in self.delay.iter_mut().zip(&mut delayed) {
-                *delay_output = delay.read();
+            let early_output = mix_to_stereo(&frame8);
+
+            // Run it through a multi-channel delay line, damping the high-frequencies
+            let mut delayed = [0.0; CHANNELS];
+            for ((delay, delay_output), damping_state) in self
+                .delay
+                .iter_mut()
+                .zip(&mut delayed)
+                .zip(&mut self.damping_state)
+            {
+                *damping_state = undenormalize(
+                    *damping_state + (delay.read() - *damping_state) * (1.0 - damping),
+                );
+                *delay_output = *damping_state;
             }
+            let late_output = mix_to_stereo(&delayed);
 
             // Shuffle the channels together
-            apply_householder(&mut delayed);
+            match feedback_matrix {
+                FeedbackMatrix::Householder => apply_householder(&mut delayed),
+                FeedbackMatrix::Hadamard => self.hadamard_matrix.apply(&mut delayed),
+            }
 
-            // Write back into the multi-channel delay line and generate output
-            for ((sample, delay), delay_output) in
-                frame8.iter_mut().zip(&mut self.delay).zip(delayed)
+            // Write back into the multi-channel delay line
+            for ((sample, delay), (feedback, gain)) in frame8
+                .iter()
+                .zip(&mut self.delay)
+                .zip(delayed.iter().zip(&feedback_gains))
             {
-                delay.write(*sample + delay_output * delay_feedback);
-                *sample += delay_output * delay_volume;
+                delay.write(*sample + feedback * gain);
             }
 
-            // Mix the multi-channel output back into stereo
-            let scale = 1.0 / (self.diffusers.len() as f32);
-            let mut reverb_output = [
-                (frame8[0] + frame8[2] + frame8[4] + frame8[6]) * scale * reverb_volume,
-                (frame8[1] + frame8[3] + frame8[5] + frame8[7]) * scale * reverb_volume,
-            ];
-            reverb_output[0] = self.filter[0].m_process(context, reverb_output[0]);
-            reverb_output[1] = self.filter[1].m_process(context, reverb_output[1]);
+            let wet_left =
+                early_output[0] * (1.0 - early_late_mix) + late_output[0] * early_late_mix;
+            let wet_right =
+                early_output[1] * (1.0 - early_late_mix) + late_output[1] * early_late_mix;
 
-            data.channel_mut(0)[sample_num] = reverb_output[0] + left * (1.0 - reverb_volume);
-            data.channel_mut(1)[sample_num] = reverb_output[1] + right * (1.0 - reverb_volume);
+            data.channel_mut(0)[sample_num] = wet_left * dry_wet + left * (1.0 - dry_wet);
+            if is_stereo {
+                data.channel_mut(1)[sample_num] = wet_right * dry_wet + right * (1.0 - dry_wet);
+            }
         }
     }
 }
 
+/// Mix the multi-channel signal back into stereo
+fn mix_to_stereo(frame: &[f32; CHANNELS]) -> [f32; 2] {
+    [
+        (frame[0] + frame[2] + frame[4] + frame[6]) * STEREO_MIX_SCALE,
+        (frame[1] + frame[3] + frame[5] + frame[7]) * STEREO_MIX_SCALE,
+    ]
+}
+
 struct Diffuser<const CHANNELS: usize> {
     rng: SmallRng,
     max_delay_time: Duration,
@@ -237,7 +420,7 @@ where
         }
         shuffle_positions.shuffle(&mut rng);
 
-        let mono_delay_processors = [(); CHANNELS].map(|_| MonoDelayProcessor::default());
+        let mono_delay_processors = [(); CHANNELS].map(|_| make_delay_line(Duration::from_secs(1)));
 
         Self {
             rng,
@@ -269,12 +452,6 @@ where
         }
     }
 
-    fn set_delay_mult(&mut self, mult: f32) {
-        for (delay, delay_basis) in self.mono_delay_processors.iter_mut().zip(&self.delay_times) {
-            delay.handle().set_delay_time_secs(*delay_basis * mult);
-        }
-    }
-
     fn process(&mut self, context: &mut AudioContext, frame: &mut [f32; CHANNELS]) {
         for (sample, delay_processor) in frame.iter_mut().zip(&mut self.mono_delay_processors) {
             *sample = delay_processor.m_process(context, *sample);
@@ -305,4 +482,184 @@ mod test {
             diffuser.process(&mut context, &mut frame);
         });
     }
+
+    // Impulse responses are rendered at a lower rate so debug builds run these tests quickly
+    const TEST_SAMPLE_RATE: f32 = 22050.0;
+
+    fn impulse_response(reverb: &mut ModReverbProcessor, length: Duration) -> Vec<f32> {
+        let mut settings = AudioProcessorSettings::default();
+        settings.set_sample_rate(TEST_SAMPLE_RATE);
+        let mut context = AudioContext::from(settings);
+        reverb.prepare(&mut context);
+
+        let num_samples = (length.as_secs_f32() * context.settings.sample_rate()) as usize;
+        let mut impulse = vec![0.0; num_samples];
+        impulse[0] = 1.0;
+        let mut buffer = AudioBuffer::new(vec![impulse.clone(), impulse]);
+        reverb.process(&mut context, &mut buffer);
+        buffer.channel(0).to_vec()
+    }
+
+    fn rms_db(window: &[f32]) -> f32 {
+        let rms = (window.iter().map(|s| s * s).sum::<f32>() / window.len() as f32).sqrt();
+        20.0 * rms.log10()
+    }
+
+    fn window(response: &[f32], start_secs: f32, length_secs: f32) -> &[f32] {
+        let start = (start_secs * TEST_SAMPLE_RATE) as usize;
+        let end = start + (length_secs * TEST_SAMPLE_RATE) as usize;
+        &response[start..end]
+    }
+
+    #[test]
+    fn test_no_alloc_process() {
+        let mut reverb = ModReverbProcessor::default();
+        let mut context = AudioContext::from(AudioProcessorSettings::default());
+        reverb.prepare(&mut context);
+
+        let mut buffer = AudioBuffer::new(vec![vec![0.0; 512], vec![0.0; 512]]);
+        buffer.channel_mut(0)[0] = 1.0;
+        assert_no_alloc(|| {
+            reverb.process(&mut context, &mut buffer);
+        });
+    }
+
+    #[test]
+    fn test_dry_signal_is_untouched_without_wet() {
+        let mut reverb = ModReverbProcessor::default();
+        reverb.handle().set_dry_wet(0.0);
+        let response = impulse_response(&mut reverb, Duration::from_millis(500));
+        assert_eq!(response[0], 1.0);
+        assert!(response[1..].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_impulse_response_has_a_decaying_tail() {
+        let mut reverb = ModReverbProcessor::default();
+        reverb.handle().set_dry_wet(1.0);
+        reverb.handle().set_decay_time_secs(0.5);
+        let response = impulse_response(&mut reverb, Duration::from_millis(1500));
+
+        assert!(response.iter().all(|s| s.is_finite()));
+        let early = rms_db(window(&response, 0.1, 0.1));
+        let late = rms_db(window(&response, 0.6, 0.1));
+        let tail = rms_db(window(&response, 1.3, 0.1));
+        assert!(early > -60.0, "Reverb output is too quiet {}dB", early);
+        assert!(late < early);
+        assert!(tail < late);
+    }
+
+    #[test]
+    fn test_impulse_response_decays_according_to_rt60() {
+        for matrix in [FeedbackMatrix::Householder, FeedbackMatrix::Hadamard] {
+            let mut reverb = ModReverbProcessor::default();
+            reverb.handle().set_dry_wet(1.0);
+            reverb.handle().set_early_late_mix(1.0);
+            reverb.handle().set_damping(0.0);
+            reverb.handle().set_modulation_depth(0.0);
+            reverb.handle().set_decay_time_secs(0.5);
+            reverb.handle().set_feedback_matrix(matrix);
+            let response = impulse_response(&mut reverb, Duration::from_secs(1));
+
+            // Over half a second the tail should have dropped by roughly 60dB
+            let start = rms_db(window(&response, 0.25, 0.1));
+            let end = rms_db(window(&response, 0.75, 0.1));
+            let decay = start - end;
+            assert!(
+                (45.0..75.0).contains(&decay),
+                "Expected ~60dB of decay with {:?}, got {}dB",
+                matrix,
+                decay
+            );
+        }
+    }
+
+    #[test]
+    fn test_longer_decay_time_has_a_longer_tail() {
+        let mut short_reverb = ModReverbProcessor::default();
+        short_reverb.handle().set_dry_wet(1.0);
+        short_reverb.handle().set_decay_time_secs(0.3);
+        let short_response = impulse_response(&mut short_reverb, Duration::from_secs(1));
+
+        let mut long_reverb = ModReverbProcessor::default();
+        long_reverb.handle().set_dry_wet(1.0);
+        long_reverb.handle().set_decay_time_secs(3.0);
+        let long_response = impulse_response(&mut long_reverb, Duration::from_secs(1));
+
+        let short_tail = rms_db(window(&short_response, 0.8, 0.1));
+        let long_tail = rms_db(window(&long_response, 0.8, 0.1));
+        assert!(long_tail > short_tail + 20.0);
+    }
+
+    #[test]
+    fn test_pre_delay_delays_the_wet_signal() {
+        let mut reverb = ModReverbProcessor::default();
+        reverb.handle().set_dry_wet(1.0);
+        reverb.handle().set_pre_delay_secs(0.1);
+        let response = impulse_response(&mut reverb, Duration::from_millis(500));
+
+        let pre_delay_samples = (0.1 * TEST_SAMPLE_RATE) as usize;
+        assert!(response[..pre_delay_samples - 1].iter().all(|s| *s == 0.0));
+        assert!(response[pre_delay_samples..].iter().any(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn test_damping_reduces_high_frequencies() {
+        let zero_crossings = |response: &[f32]| {
+            response
+                .windows(2)
+                .filter(|w| w[0].signum() != w[1].signum())
+                .count()
+        };
+
+        let mut bright_reverb = ModReverbProcessor::default();
+        bright_reverb.handle().set_dry_wet(1.0);
+        bright_reverb.handle().set_early_late_mix(1.0);
+        bright_reverb.handle().set_damping(0.0);
+        let bright_response = impulse_response(&mut bright_reverb, Duration::from_millis(600));
+
+        let mut dark_reverb = ModReverbProcessor::default();
+        dark_reverb.handle().set_dry_wet(1.0);
+        dark_reverb.handle().set_early_late_mix(1.0);
+        dark_reverb.handle().set_damping(0.9);
+        let dark_response = impulse_response(&mut dark_reverb, Duration::from_millis(600));
+
+        assert!(
+            zero_crossings(window(&dark_response, 0.3, 0.3))
+                < zero_crossings(window(&bright_response, 0.3, 0.3))
+        );
+    }
+
+    #[test]
+    fn test_mono_input_is_supported() {
+        let mut reverb = ModReverbProcessor::default();
+        let mut context = AudioContext::from(AudioProcessorSettings::default());
+        reverb.prepare(&mut context);
+
+        let mut impulse = vec![0.0; 4410];
+        impulse[0] = 1.0;
+        let mut buffer = AudioBuffer::new(vec![impulse]);
+        reverb.process(&mut context, &mut buffer);
+        assert!(buffer.channel(0)[1..].iter().any(|s| *s != 0.0));
+    }
+
+    #[test]
+    fn test_generic_handle_parameters() {
+        let reverb = ModReverbProcessor::default();
+        let handle = reverb.generic_handle();
+        assert_eq!(handle.name(), "Mod Reverb");
+        assert_eq!(handle.parameter_count(), 8);
+
+        handle.set_parameter(0, 2.0.into());
+        assert_eq!(reverb.handle().diffusion_steps(), 2);
+        handle.set_parameter(0, 100.0.into());
+        assert_eq!(reverb.handle().diffusion_steps(), MAX_DIFFUSION_STEPS);
+        handle.set_parameter(1, 1.0.into());
+        assert_eq!(reverb.handle().feedback_matrix(), FeedbackMatrix::Hadamard);
+        handle.set_parameter(2, 3.5.into());
+        assert_eq!(handle.get_parameter(2), Some(3.5.into()));
+        handle.set_parameter(4, 0.25.into());
+        assert_eq!(reverb.handle().pre_delay_secs(), 0.25);
+        assert_eq!(handle.get_parameter(8), None);
+    }
 }
@@ -27,19 +27,34 @@
 //! * Gain processor
 //! * Stereo to mono
 //! * Mono to stereo
-//! * Panning
+//! * Panning, with pan laws, balance and surround (VBAP) panning
 //! * White noise
+//! * Oversampling
+//! * Mid/side encoding & decoding
+//! * Stereo width
+//! * Polarity inversion & channel swapping
+//! * N×M channel mixing matrix
 
 /// Apply gain to input
 pub mod gain;
+/// Mix N input channels into M output channels
+pub mod matrix;
+/// Convert left/right signals into mid/side and back
+pub mod mid_side;
 /// Convert stereo signals to mono
 pub mod mono;
 /// Generate noise
 pub mod noise;
+/// Run processors at a higher sample rate
+pub mod oversampling;
 /// Pan signals to left/right
 pub mod pan;
+/// Invert channel polarity and swap left/right
+pub mod polarity;
 /// Convert mono signals to stereo
 pub mod stereo;
+/// Change the stereo width of signals
+pub mod width;
 
 #[cfg(all(test, debug_assertions))]
 mod test_allocator;
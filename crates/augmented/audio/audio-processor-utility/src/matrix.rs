@@ -0,0 +1,211 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor, Float};
+
+/// Gains for mixing `inputs` channels into `outputs` channels
+pub struct ChannelMatrixHandle {
+    inputs: usize,
+    outputs: usize,
+    /// Row-major, one row per output channel
+    gains: Vec<AtomicF32>,
+}
+
+impl ChannelMatrixHandle {
+    /// Create a matrix with all gains set to 0
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        Self {
+            inputs,
+            outputs,
+            gains: (0..inputs * outputs).map(|_| AtomicF32::new(0.0)).collect(),
+        }
+    }
+
+    /// Create a matrix which routes each input channel into the output channel with the same index
+    pub fn identity(channels: usize) -> Self {
+        let matrix = Self::new(channels, channels);
+        for channel in 0..channels {
+            matrix.set_gain(channel, channel, 1.0);
+        }
+        matrix
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Gain from `input` into `output`, `None` if either is out of range
+    pub fn gain(&self, input: usize, output: usize) -> Option<f32> {
+        self.index(input, output)
+            .map(|index| self.gains[index].get())
+    }
+
+    /// Set the gain from `input` into `output`. Out of range channels are ignored.
+    pub fn set_gain(&self, input: usize, output: usize, gain: f32) {
+        if let Some(index) = self.index(input, output) {
+            self.gains[index].set(gain);
+        }
+    }
+
+    fn index(&self, input: usize, output: usize) -> Option<usize> {
+        if input < self.inputs && output < self.outputs {
+            Some(output * self.inputs + input)
+        } else {
+            None
+        }
+    }
+}
+
+/// An `AudioProcessor` which mixes N input channels into M output channels, where each output is
+/// the sum of all inputs weighted by a [`ChannelMatrixHandle`] gain.
+///
+/// Buffer channels past the matrix inputs are not read, buffer channels past the matrix outputs
+/// are silenced and matrix outputs past the buffer channels are dropped.
+pub struct ChannelMatrixProcessor<SampleType> {
+    handle: Shared<ChannelMatrixHandle>,
+    frame: Vec<SampleType>,
+}
+
+impl<SampleType: Float> ChannelMatrixProcessor<SampleType> {
+    /// Create a processor with all gains set to 0
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        Self::new_with_handle(make_shared(ChannelMatrixHandle::new(inputs, outputs)))
+    }
+
+    pub fn new_with_handle(handle: Shared<ChannelMatrixHandle>) -> Self {
+        Self {
+            frame: vec![SampleType::zero(); handle.inputs()],
+            handle,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<ChannelMatrixHandle> {
+        &self.handle
+    }
+}
+
+impl<SampleType> AudioProcessor for ChannelMatrixProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        let num_inputs = self.handle.inputs().min(buffer.num_channels());
+        let num_outputs = self.handle.outputs().min(buffer.num_channels());
+
+        for sample_num in 0..buffer.num_samples() {
+            for (input, value) in self.frame[..num_inputs].iter_mut().enumerate() {
+                *value = *buffer.get(input, sample_num);
+            }
+
+            for output in 0..buffer.num_channels() {
+                let mut sum = SampleType::zero();
+                if output < num_outputs {
+                    for (input, value) in self.frame[..num_inputs].iter().enumerate() {
+                        let gain = self.handle.gains[output * self.handle.inputs + input].get();
+                        sum = sum + *value * SampleType::from(gain).unwrap();
+                    }
+                }
+                buffer.set(output, sample_num, sum);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_identity_is_a_noop() {
+        let mut processor =
+            ChannelMatrixProcessor::new_with_handle(make_shared(ChannelMatrixHandle::identity(2)));
+        let mut input = AudioBuffer::from_interleaved(2, &[1.0, 0.5, 0.2, -0.2]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 0.2]);
+        assert_eq!(input.channel(1), &[0.5, -0.2]);
+    }
+
+    #[test]
+    fn test_sum_stereo_into_mono() {
+        let mut processor = ChannelMatrixProcessor::new(2, 1);
+        processor.handle().set_gain(0, 0, 0.5);
+        processor.handle().set_gain(1, 0, 0.5);
+        let mut input = AudioBuffer::from_interleaved(2, &[1.0, 0.5, 0.2, -0.2]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_f_eq!(*input.get(0, 0), 0.75);
+        assert_f_eq!(*input.get(0, 1), 0.0);
+        // Channels past the matrix outputs are silenced
+        assert_eq!(input.channel(1), &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_route_one_input_into_many_outputs() {
+        let mut processor = ChannelMatrixProcessor::new(1, 3);
+        processor.handle().set_gain(0, 0, 1.0);
+        processor.handle().set_gain(0, 1, -1.0);
+        processor.handle().set_gain(0, 2, 0.25);
+        let mut input = AudioBuffer::new(vec![vec![1.0, 0.5], vec![0.3, 0.3], vec![0.3, 0.3]]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 0.5]);
+        assert_eq!(input.channel(1), &[-1.0, -0.5]);
+        assert_eq!(input.channel(2), &[0.25, 0.125]);
+    }
+
+    #[test]
+    fn test_out_of_range_gains_are_ignored() {
+        let handle = ChannelMatrixHandle::new(2, 2);
+        handle.set_gain(2, 0, 1.0);
+        assert_eq!(handle.gain(2, 0), None);
+        assert_eq!(handle.gain(1, 1), Some(0.0));
+    }
+
+    #[test]
+    fn test_no_alloc_process() {
+        let mut processor = ChannelMatrixProcessor::new(2, 2);
+        let mut input = AudioBuffer::new(vec![vec![1.0; 512], vec![1.0; 512]]);
+        let mut context = AudioContext::default();
+
+        assert_no_alloc(|| {
+            processor.process(&mut context, &mut input);
+        });
+    }
+}
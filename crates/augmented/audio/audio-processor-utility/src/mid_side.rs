@@ -0,0 +1,179 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::marker::PhantomData;
+
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor, Float};
+
+#[inline]
+pub(crate) fn encode<SampleType: Float>(
+    left: SampleType,
+    right: SampleType,
+) -> (SampleType, SampleType) {
+    let half = SampleType::from(0.5).unwrap();
+    ((left + right) * half, (left - right) * half)
+}
+
+#[inline]
+pub(crate) fn decode<SampleType: Float>(
+    mid: SampleType,
+    side: SampleType,
+) -> (SampleType, SampleType) {
+    (mid + side, mid - side)
+}
+
+/// An `AudioProcessor` which converts a left/right signal into mid/side.
+///
+/// Channel 0 becomes `(L + R) / 2` and channel 1 becomes `(L - R) / 2`. Buffers with less than 2
+/// channels are left untouched.
+pub struct MidSideEncoderProcessor<SampleType> {
+    phantom: PhantomData<SampleType>,
+}
+
+impl<SampleType> Default for MidSideEncoderProcessor<SampleType> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SampleType> MidSideEncoderProcessor<SampleType> {
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<SampleType> AudioProcessor for MidSideEncoderProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        if buffer.num_channels() < 2 {
+            return;
+        }
+
+        for sample_num in 0..buffer.num_samples() {
+            let (mid, side) = encode(*buffer.get(0, sample_num), *buffer.get(1, sample_num));
+            buffer.set(0, sample_num, mid);
+            buffer.set(1, sample_num, side);
+        }
+    }
+}
+
+/// An `AudioProcessor` which converts a mid/side signal, as output by
+/// [`MidSideEncoderProcessor`], back into left/right.
+///
+/// Channel 0 becomes `M + S` and channel 1 becomes `M - S`. Buffers with less than 2 channels are
+/// left untouched.
+pub struct MidSideDecoderProcessor<SampleType> {
+    phantom: PhantomData<SampleType>,
+}
+
+impl<SampleType> Default for MidSideDecoderProcessor<SampleType> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SampleType> MidSideDecoderProcessor<SampleType> {
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<SampleType> AudioProcessor for MidSideDecoderProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        if buffer.num_channels() < 2 {
+            return;
+        }
+
+        for sample_num in 0..buffer.num_samples() {
+            let (left, right) = decode(*buffer.get(0, sample_num), *buffer.get(1, sample_num));
+            buffer.set(0, sample_num, left);
+            buffer.set(1, sample_num, right);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_mid_side() {
+        let mut encoder = MidSideEncoderProcessor::new();
+        let samples = [1.0, 0.5, 0.2, -0.2];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        encoder.process(&mut context, &mut input);
+
+        assert_f_eq!(*input.get(0, 0), 0.75);
+        assert_f_eq!(*input.get(1, 0), 0.25);
+        assert_f_eq!(*input.get(0, 1), 0.0);
+        assert_f_eq!(*input.get(1, 1), 0.2);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut encoder = MidSideEncoderProcessor::new();
+        let mut decoder = MidSideDecoderProcessor::new();
+        let samples = [1.0, 0.5, 0.2, -0.2, -0.7, 0.3];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        encoder.process(&mut context, &mut input);
+        decoder.process(&mut context, &mut input);
+
+        let expected = AudioBuffer::from_interleaved(2, &samples);
+        for channel in 0..2 {
+            for (sample, expected) in input.channel(channel).iter().zip(expected.channel(channel)) {
+                assert_f_eq!(*sample, *expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mono_input_is_untouched() {
+        let mut encoder = MidSideEncoderProcessor::new();
+        let samples = [1.0, 0.5];
+        let mut input = AudioBuffer::from_interleaved(1, &samples);
+        let mut context = AudioContext::default();
+
+        encoder.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 0.5]);
+    }
+}
@@ -0,0 +1,452 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::f64::consts::PI;
+
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor};
+
+/// Length of the half-band FIR. Every other tap is zero besides the center one, so only
+/// `HALF_BAND_EVEN_TAPS + 1` multiplications happen per output sample.
+const HALF_BAND_LENGTH: usize = 63;
+const HALF_BAND_CENTER: usize = (HALF_BAND_LENGTH - 1) / 2;
+const HALF_BAND_EVEN_TAPS: usize = HALF_BAND_CENTER + 1;
+
+/// How much faster the inner processor runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversamplingFactor {
+    X2,
+    X4,
+    X8,
+}
+
+impl OversamplingFactor {
+    pub fn factor(&self) -> usize {
+        1 << self.num_stages()
+    }
+
+    /// Each stage doubles the sample rate
+    fn num_stages(&self) -> usize {
+        match self {
+            OversamplingFactor::X2 => 1,
+            OversamplingFactor::X4 => 2,
+            OversamplingFactor::X8 => 3,
+        }
+    }
+}
+
+/// Blackman windowed-sinc half-band low-pass, the even taps of the filter
+fn half_band_even_coefficients() -> [f32; HALF_BAND_EVEN_TAPS] {
+    let mut coefficients = [0.0; HALF_BAND_EVEN_TAPS];
+    for (i, coefficient) in coefficients.iter_mut().enumerate() {
+        let n = (2 * i) as f64;
+        let x = n - HALF_BAND_CENTER as f64;
+        let sinc = (PI * x / 2.0).sin() / (PI * x);
+        let window = 0.42 - 0.5 * (2.0 * PI * n / (HALF_BAND_LENGTH - 1) as f64).cos()
+            + 0.08 * (4.0 * PI * n / (HALF_BAND_LENGTH - 1) as f64).cos();
+        *coefficient = (sinc * window) as f32;
+    }
+
+    // The even taps sum to 0.5, the center tap adds the other half for unity gain
+    let sum: f32 = coefficients.iter().sum();
+    for coefficient in coefficients.iter_mut() {
+        *coefficient *= 0.5 / sum;
+    }
+    coefficients
+}
+
+/// Polyphase 2x interpolator. Zero-stuffing means the even taps run on the input history while
+/// the odd output is a pure delay through the center tap.
+struct HalfBandUpsampler {
+    coefficients: [f32; HALF_BAND_EVEN_TAPS],
+    history: [f32; HALF_BAND_EVEN_TAPS * 2],
+    position: usize,
+}
+
+impl HalfBandUpsampler {
+    fn new(coefficients: [f32; HALF_BAND_EVEN_TAPS]) -> Self {
+        Self {
+            coefficients,
+            history: [0.0; HALF_BAND_EVEN_TAPS * 2],
+            position: 0,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> (f32, f32) {
+        self.history[self.position] = sample;
+        self.history[self.position + HALF_BAND_EVEN_TAPS] = sample;
+        self.position = (self.position + 1) % HALF_BAND_EVEN_TAPS;
+
+        let window = &self.history[self.position..self.position + HALF_BAND_EVEN_TAPS];
+        let filtered: f32 = window
+            .iter()
+            .zip(self.coefficients.iter())
+            .map(|(sample, coefficient)| sample * coefficient)
+            .sum();
+        let delayed = window[HALF_BAND_EVEN_TAPS / 2];
+        (2.0 * filtered, delayed)
+    }
+}
+
+/// Polyphase 2x decimator, only computes the kept output samples and skips the zero taps
+struct HalfBandDownsampler {
+    coefficients: [f32; HALF_BAND_EVEN_TAPS],
+    history: [f32; HALF_BAND_LENGTH * 2],
+    position: usize,
+}
+
+impl HalfBandDownsampler {
+    fn new(coefficients: [f32; HALF_BAND_EVEN_TAPS]) -> Self {
+        Self {
+            coefficients,
+            history: [0.0; HALF_BAND_LENGTH * 2],
+            position: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.history[self.position] = sample;
+        self.history[self.position + HALF_BAND_LENGTH] = sample;
+        self.position = (self.position + 1) % HALF_BAND_LENGTH;
+    }
+
+    /// Takes two input samples and returns the output aligned with the first one
+    fn process(&mut self, first: f32, second: f32) -> f32 {
+        self.push(first);
+
+        let window = &self.history[self.position..self.position + HALF_BAND_LENGTH];
+        let filtered: f32 = window
+            .iter()
+            .step_by(2)
+            .zip(self.coefficients.iter())
+            .map(|(sample, coefficient)| sample * coefficient)
+            .sum();
+        let output = filtered + 0.5 * window[HALF_BAND_CENTER];
+
+        self.push(second);
+        output
+    }
+}
+
+struct ChannelState {
+    upsamplers: Vec<HalfBandUpsampler>,
+    downsamplers: Vec<HalfBandDownsampler>,
+}
+
+impl ChannelState {
+    fn new(factor: OversamplingFactor) -> Self {
+        let coefficients = half_band_even_coefficients();
+        Self {
+            upsamplers: (0..factor.num_stages())
+                .map(|_| HalfBandUpsampler::new(coefficients))
+                .collect(),
+            downsamplers: (0..factor.num_stages())
+                .map(|_| HalfBandDownsampler::new(coefficients))
+                .collect(),
+        }
+    }
+
+    /// Expand one sample into `output`, which has `factor` samples
+    fn upsample(&mut self, sample: f32, output: &mut [f32]) {
+        let mut values = [0.0; 8];
+        let mut next_values = [0.0; 8];
+        values[0] = sample;
+        let mut count = 1;
+        for upsampler in self.upsamplers.iter_mut() {
+            for i in 0..count {
+                let (first, second) = upsampler.process(values[i]);
+                next_values[i * 2] = first;
+                next_values[i * 2 + 1] = second;
+            }
+            count *= 2;
+            values = next_values;
+        }
+        output.copy_from_slice(&values[..count]);
+    }
+
+    /// Reduce `factor` samples from `input` into one
+    fn downsample(&mut self, input: &[f32]) -> f32 {
+        let mut values = [0.0; 8];
+        values[..input.len()].copy_from_slice(input);
+        let mut count = input.len();
+        for downsampler in self.downsamplers.iter_mut().rev() {
+            count /= 2;
+            for i in 0..count {
+                values[i] = downsampler.process(values[i * 2], values[i * 2 + 1]);
+            }
+        }
+        values[0]
+    }
+}
+
+/// Runs a processor at 2x, 4x or 8x the sample rate, to reduce aliasing from non-linear
+/// processing such as saturation, distortion or bit-crushing.
+///
+/// The signal is upsampled with polyphase half-band filters, processed, then filtered and
+/// downsampled again. The inner processor is prepared with the higher sample rate and block size.
+/// Filtering adds [`Oversampled::latency`] samples of delay.
+///
+/// Nothing allocates after `prepare`.
+pub struct Oversampled<P> {
+    processor: P,
+    factor: OversamplingFactor,
+    channels: Vec<ChannelState>,
+    buffer: AudioBuffer<f32>,
+    inner_context: AudioContext,
+    block_size: usize,
+}
+
+impl<P: AudioProcessor<SampleType = f32>> Oversampled<P> {
+    pub fn new(processor: P, factor: OversamplingFactor) -> Self {
+        Self {
+            processor,
+            factor,
+            channels: Vec::new(),
+            buffer: AudioBuffer::empty(),
+            inner_context: AudioContext::default(),
+            block_size: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.processor
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.processor
+    }
+
+    pub fn into_inner(self) -> P {
+        self.processor
+    }
+
+    pub fn factor(&self) -> OversamplingFactor {
+        self.factor
+    }
+
+    /// Delay added by the filters, in samples at the base sample rate.
+    ///
+    /// Each stage adds the group delay of its interpolation and decimation filters at the rate it
+    /// runs at.
+    pub fn latency(&self) -> f32 {
+        (1..=self.factor.num_stages())
+            .map(|stage| (2 * HALF_BAND_CENTER) as f32 / (1 << stage) as f32)
+            .sum()
+    }
+}
+
+impl<P: AudioProcessor<SampleType = f32>> AudioProcessor for Oversampled<P> {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        let factor = self.factor.factor();
+        let mut settings = context.settings;
+        settings.sample_rate *= factor as f32;
+        settings.block_size *= factor;
+        self.inner_context = AudioContext::from(settings);
+        self.processor.prepare(&mut self.inner_context);
+
+        let num_channels = context
+            .settings
+            .input_channels()
+            .max(context.settings.output_channels());
+        self.block_size = context.settings.block_size().max(1);
+        self.channels = (0..num_channels)
+            .map(|_| ChannelState::new(self.factor))
+            .collect();
+        self.buffer.resize(num_channels, settings.block_size());
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<f32>) {
+        let factor = self.factor.factor();
+        let num_channels = data.num_channels().min(self.channels.len());
+        let num_samples = data.num_samples();
+
+        let mut start = 0;
+        while start < num_samples {
+            let end = (start + self.block_size).min(num_samples);
+            self.buffer
+                .resize(self.channels.len(), (end - start) * factor);
+
+            for channel in 0..num_channels {
+                let state = &mut self.channels[channel];
+                let input = &data.channel(channel)[start..end];
+                let output = self.buffer.channel_mut(channel);
+                for (sample, frame) in input.iter().zip(output.chunks_mut(factor)) {
+                    state.upsample(*sample, frame);
+                }
+            }
+
+            self.processor
+                .process(&mut self.inner_context, &mut self.buffer);
+
+            for channel in 0..num_channels {
+                let state = &mut self.channels[channel];
+                let input = self.buffer.channel(channel);
+                let output = &mut data.channel_mut(channel)[start..end];
+                for (sample, frame) in output.iter_mut().zip(input.chunks(factor)) {
+                    *sample = state.downsample(frame);
+                }
+            }
+
+            start = end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+
+    use audio_processor_traits::{AudioProcessorSettings, NoopAudioProcessor};
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn sine(frequency: f32, time: f32) -> f32 {
+        (2.0 * std::f32::consts::PI * frequency * time / SAMPLE_RATE).sin()
+    }
+
+    fn sine_buffer(frequency: f32, num_samples: usize) -> AudioBuffer<f32> {
+        let samples: Vec<f32> = (0..num_samples)
+            .map(|i| sine(frequency, i as f32))
+            .collect();
+        AudioBuffer::new(vec![samples])
+    }
+
+    fn context(block_size: usize) -> AudioContext {
+        AudioContext::from(AudioProcessorSettings::new(SAMPLE_RATE, 1, 1, block_size))
+    }
+
+    /// Magnitude of `frequency` in `samples`
+    fn goertzel(samples: &[f32], frequency: f32) -> f32 {
+        let coefficient = 2.0 * (2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = sample + coefficient * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        (s1 * s1 + s2 * s2 - coefficient * s1 * s2).sqrt() / samples.len() as f32
+    }
+
+    struct HardClipProcessor;
+
+    impl AudioProcessor for HardClipProcessor {
+        type SampleType = f32;
+
+        fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<f32>) {
+            for sample in data.slice_mut() {
+                *sample = (*sample * 4.0).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct SettingsRecorder {
+        prepared: Option<AudioProcessorSettings>,
+        block_sizes: Vec<usize>,
+    }
+
+    impl AudioProcessor for SettingsRecorder {
+        type SampleType = f32;
+
+        fn prepare(&mut self, context: &mut AudioContext) {
+            self.prepared = Some(context.settings);
+        }
+
+        fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<f32>) {
+            self.block_sizes.push(data.num_samples());
+        }
+    }
+
+    #[test]
+    fn test_inner_processor_runs_at_the_higher_rate() {
+        let mut oversampled = Oversampled::new(SettingsRecorder::default(), OversamplingFactor::X4);
+        oversampled.prepare(&mut context(64));
+        let settings = oversampled.inner().prepared.unwrap();
+        assert!((settings.sample_rate() - SAMPLE_RATE * 4.0).abs() < f32::EPSILON);
+        assert_eq!(settings.block_size(), 256);
+
+        let mut buffer = sine_buffer(440.0, 100);
+        oversampled.process(&mut context(64), &mut buffer);
+        assert_eq!(oversampled.inner().block_sizes, vec![256, 36 * 4]);
+    }
+
+    #[test]
+    fn test_passes_audio_through_delayed_by_latency() {
+        for factor in [
+            OversamplingFactor::X2,
+            OversamplingFactor::X4,
+            OversamplingFactor::X8,
+        ] {
+            let mut oversampled = Oversampled::new(NoopAudioProcessor::new(), factor);
+            oversampled.prepare(&mut context(512));
+            let mut buffer = sine_buffer(1000.0, 4096);
+            oversampled.process(&mut context(512), &mut buffer);
+
+            let latency = oversampled.latency();
+            for (i, sample) in buffer.channel(0).iter().enumerate().skip(100) {
+                let expected = sine(1000.0, i as f32 - latency);
+                assert!(
+                    (sample - expected).abs() < 0.001,
+                    "{:?} {} {} {}",
+                    factor,
+                    i,
+                    sample,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversampling_reduces_aliasing() {
+        // Clipping a 5kHz sine makes odd harmonics, the 7th (35kHz) aliases to 9.1kHz
+        let alias_frequency = SAMPLE_RATE - 35000.0;
+
+        let mut buffer = sine_buffer(5000.0, 8192);
+        let mut context = context(512);
+        HardClipProcessor.process(&mut context, &mut buffer);
+        let base_rate_alias = goertzel(&buffer.channel(0)[512..], alias_frequency);
+
+        let mut buffer = sine_buffer(5000.0, 8192);
+        let mut oversampled = Oversampled::new(HardClipProcessor, OversamplingFactor::X8);
+        oversampled.prepare(&mut context);
+        oversampled.process(&mut context, &mut buffer);
+        let oversampled_alias = goertzel(&buffer.channel(0)[512..], alias_frequency);
+
+        let reduction = 20.0 * (base_rate_alias / oversampled_alias).log10();
+        assert!(reduction > 20.0, "{}", reduction);
+    }
+
+    #[test]
+    fn test_process_does_not_allocate() {
+        let mut oversampled = Oversampled::new(HardClipProcessor, OversamplingFactor::X8);
+        let mut context = context(512);
+        oversampled.prepare(&mut context);
+        let mut buffer = sine_buffer(1000.0, 1000);
+        assert_no_alloc(|| {
+            oversampled.process(&mut context, &mut buffer);
+        });
+    }
+}
@@ -0,0 +1,132 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::f32::consts::FRAC_PI_2;
+
+/// How gain is distributed between the left and right channels as a source is panned.
+///
+/// Laws are named after the attenuation they apply to each side when the source is in the
+/// center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanLaw {
+    /// 0dB in the center, the opposite side is attenuated linearly
+    Linear,
+    /// -3dB in the center, keeps the total power constant across positions
+    ConstantPower,
+    /// -4.5dB in the center, a compromise between constant power and -6dB
+    Minus4_5Db,
+    /// -6dB in the center, gains cross-fade linearly and always sum to 1
+    Minus6Db,
+}
+
+impl PanLaw {
+    /// Left and right gains for a source at `position`, between -1 (left) and 1 (right)
+    pub fn gains(&self, position: f32) -> (f32, f32) {
+        let theta = (position.clamp(-1.0, 1.0) + 1.0) * 0.5;
+        match self {
+            PanLaw::Linear => ((2.0 * (1.0 - theta)).min(1.0), (2.0 * theta).min(1.0)),
+            PanLaw::ConstantPower => ((theta * FRAC_PI_2).cos(), (theta * FRAC_PI_2).sin()),
+            PanLaw::Minus4_5Db => (
+                ((1.0 - theta) * (theta * FRAC_PI_2).cos()).sqrt(),
+                (theta * (theta * FRAC_PI_2).sin()).sqrt(),
+            ),
+            PanLaw::Minus6Db => (1.0 - theta, theta),
+        }
+    }
+
+    /// Left and right gains for balancing a stereo source at `position`. The center is unity and
+    /// the opposite side is attenuated following the law's curve.
+    pub fn balance_gains(&self, position: f32) -> (f32, f32) {
+        let (center, _) = self.gains(0.0);
+        let (left, right) = self.gains(position);
+        ((left / center).min(1.0), (right / center).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    fn db(gain: f32) -> f32 {
+        20.0 * gain.log10()
+    }
+
+    #[test]
+    fn test_center_attenuation() {
+        let (left, right) = PanLaw::Linear.gains(0.0);
+        assert_f_eq!(left, 1.0);
+        assert_f_eq!(right, 1.0);
+        for (law, expected_db) in [
+            (PanLaw::ConstantPower, -3.0),
+            (PanLaw::Minus4_5Db, -4.5),
+            (PanLaw::Minus6Db, -6.0),
+        ] {
+            let (left, right) = law.gains(0.0);
+            assert_f_eq!(left, right);
+            assert!(
+                (db(left) - expected_db).abs() < 0.1,
+                "{:?} {}",
+                law,
+                db(left)
+            );
+        }
+    }
+
+    #[test]
+    fn test_hard_panned_sources_use_one_side() {
+        for law in [
+            PanLaw::Linear,
+            PanLaw::ConstantPower,
+            PanLaw::Minus4_5Db,
+            PanLaw::Minus6Db,
+        ] {
+            let (left, right) = law.gains(-1.0);
+            assert!((left - 1.0).abs() < 1e-6, "{:?}", law);
+            assert!(right.abs() < 1e-6, "{:?}", law);
+            let (left, right) = law.gains(1.0);
+            assert!(left.abs() < 1e-6, "{:?}", law);
+            assert!((right - 1.0).abs() < 1e-6, "{:?}", law);
+        }
+    }
+
+    #[test]
+    fn test_constant_power_keeps_power() {
+        for i in -10..=10 {
+            let (left, right) = PanLaw::ConstantPower.gains(i as f32 / 10.0);
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_balance_is_unity_in_the_center() {
+        let (left, right) = PanLaw::ConstantPower.balance_gains(0.0);
+        assert_f_eq!(left, 1.0);
+        assert_f_eq!(right, 1.0);
+
+        let (left, right) = PanLaw::ConstantPower.balance_gains(0.5);
+        assert_f_eq!(right, 1.0);
+        assert!(left < 1.0);
+    }
+}
@@ -0,0 +1,327 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use audio_processor_traits::{AudioBuffer, AudioProcessor};
+use audio_processor_traits::{AudioContext, Float};
+
+pub use law::PanLaw;
+pub use vbap::{vbap_gains, SpeakerLayout};
+
+mod law;
+mod vbap;
+
+/// How the [`PanProcessor`] positions its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanMode {
+    /// Attenuates the channel opposite to the panning, the input channels aren't mixed.
+    Balance,
+    /// Pans each input channel as a separate source. Panning left moves the right channel
+    /// towards the left speaker, while the left channel stays in place.
+    Stereo,
+    /// Pans channel 0 into the speakers of a multichannel layout, using vector based amplitude
+    /// panning. The position is set with [`PanProcessor::set_azimuth`].
+    Vbap(SpeakerLayout),
+}
+
+/// An `AudioProcessor` that applies panning on its input.
+///
+/// Stereo inputs are either balanced or panned as stereo sources, following a [`PanLaw`].
+/// Multichannel outputs are supported through [`PanMode::Vbap`].
+///
+/// By default, this does stereo panning with the -6dB law.
+///
+/// Does not perform any bounds checking.
+pub struct PanProcessor<SampleType> {
+    /// A number between -1 and 1
+    /// -1 represents using the left channel only, 1 represents using the right channel only.
+    panning: SampleType,
+    law: PanLaw,
+    mode: PanMode,
+    /// Source direction in degrees for [`PanMode::Vbap`]
+    azimuth: f32,
+}
+
+impl<SampleType: Float> Default for PanProcessor<SampleType> {
+    fn default() -> Self {
+        Self::new(SampleType::from(0.0).unwrap())
+    }
+}
+
+impl<SampleType: Float> PanProcessor<SampleType> {
+    /// Create a processor with panning.
+    /// -1 represents using the left channel only, 1 represents using the right channel only.
+    pub fn new(panning: SampleType) -> Self {
+        PanProcessor {
+            panning,
+            law: PanLaw::Minus6Db,
+            mode: PanMode::Stereo,
+            azimuth: 0.0,
+        }
+    }
+
+    /// -1 represents using the left channel only, 1 represents using the right channel only.
+    pub fn panning(&self) -> SampleType {
+        self.panning
+    }
+
+    /// Set the panning.
+    ///
+    /// -1 represents using the left channel only, 1 represents using the right channel only.
+    pub fn set_panning(&mut self, panning: SampleType) {
+        self.panning = panning;
+    }
+
+    pub fn law(&self) -> PanLaw {
+        self.law
+    }
+
+    pub fn set_law(&mut self, law: PanLaw) {
+        self.law = law;
+    }
+
+    pub fn mode(&self) -> PanMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PanMode) {
+        self.mode = mode;
+    }
+
+    /// Source direction in degrees for [`PanMode::Vbap`]. 0 is the front and positive angles go
+    /// to the right.
+    pub fn azimuth(&self) -> f32 {
+        self.azimuth
+    }
+
+    pub fn set_azimuth(&mut self, azimuth: f32) {
+        self.azimuth = azimuth;
+    }
+
+    fn process_balance(&self, buffer: &mut AudioBuffer<SampleType>) {
+        let (left_gain, right_gain) = self.law.balance_gains(self.panning.to_f32().unwrap());
+        let left_gain = SampleType::from(left_gain).unwrap();
+        let right_gain = SampleType::from(right_gain).unwrap();
+
+        for sample_num in 0..buffer.num_samples() {
+            let left_input = *buffer.get(0, sample_num);
+            let right_input = *buffer.get(1, sample_num);
+            buffer.set(0, sample_num, left_input * left_gain);
+            buffer.set(1, sample_num, right_input * right_gain);
+        }
+    }
+
+    fn process_stereo(&self, buffer: &mut AudioBuffer<SampleType>) {
+        let panning = self.panning.to_f32().unwrap().clamp(-1.0, 1.0);
+        // Panning moves the opposite channel across, the near channel stays on its side
+        let left_position = if panning > 0.0 {
+            2.0 * panning - 1.0
+        } else {
+            -1.0
+        };
+        let right_position = if panning < 0.0 {
+            2.0 * panning + 1.0
+        } else {
+            1.0
+        };
+        let (left_to_left, left_to_right) = self.law.gains(left_position);
+        let (right_to_left, right_to_right) = self.law.gains(right_position);
+        let [left_to_left, left_to_right, right_to_left, right_to_right] =
+            [left_to_left, left_to_right, right_to_left, right_to_right]
+                .map(|gain| SampleType::from(gain).unwrap());
+
+        for sample_num in 0..buffer.num_samples() {
+            let left_input = *buffer.get(0, sample_num);
+            let right_input = *buffer.get(1, sample_num);
+
+            let left_output = left_input * left_to_left + right_input * right_to_left;
+            let right_output = left_input * left_to_right + right_input * right_to_right;
+
+            buffer.set(0, sample_num, left_output);
+            buffer.set(1, sample_num, right_output);
+        }
+    }
+
+    fn process_vbap(&self, layout: SpeakerLayout, buffer: &mut AudioBuffer<SampleType>) {
+        let mut gains = [0.0; SpeakerLayout::MAX_CHANNELS];
+        let gains = &mut gains[..layout.channels()];
+        vbap_gains(layout, self.azimuth, gains);
+
+        for sample_num in 0..buffer.num_samples() {
+            let input = *buffer.get(0, sample_num);
+            for channel_num in 0..buffer.num_channels() {
+                let gain = gains.get(channel_num).copied().unwrap_or(0.0);
+                buffer.set(
+                    channel_num,
+                    sample_num,
+                    input * SampleType::from(gain).unwrap(),
+                );
+            }
+        }
+    }
+}
+
+impl<SampleType> AudioProcessor for PanProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        match self.mode {
+            PanMode::Vbap(layout) => self.process_vbap(layout, buffer),
+            _ if buffer.num_channels() < 2 => {}
+            PanMode::Balance => self.process_balance(buffer),
+            PanMode::Stereo => self.process_stereo(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_testing_helpers::assert_f_eq;
+    use audio_processor_traits::AudioBuffer;
+
+    use super::*;
+
+    #[test]
+    fn test_pan_noop() {
+        let mut pan = PanProcessor::default();
+        let samples = [1., 1., 1., 1., 1., 1.];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        for sample_num in 0..input.num_samples() {
+            for channel_num in 0..input.num_channels() {
+                let sample = input.get(channel_num, sample_num);
+                assert_f_eq!(*sample, 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hard_pan_to_left() {
+        let mut pan = PanProcessor::new(-1.0);
+        let samples = [1., 1., 1., 1., 1., 1.];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        for sample_index in 0..input.num_samples() {
+            let left = *input.get(0, sample_index);
+            let right = *input.get(1, sample_index);
+            assert_f_eq!(left, 2.0);
+            assert_f_eq!(right, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_hard_pan_to_right() {
+        let mut pan = PanProcessor::new(1.0);
+        let samples = [1., 1., 1., 1., 1., 1.];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        for sample_index in 0..input.num_samples() {
+            let left = *input.get(0, sample_index);
+            let right = *input.get(1, sample_index);
+            assert_f_eq!(right, 2.0);
+            assert_f_eq!(left, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_stereo_pan_moves_the_opposite_channel() {
+        let mut pan = PanProcessor::new(0.5);
+        let samples = [1., 0.5];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        // The left channel is half-way to the right with -6dB law
+        assert_f_eq!(*input.get(0, 0), 0.5);
+        assert_f_eq!(*input.get(1, 0), 1.0);
+    }
+
+    #[test]
+    fn test_constant_power_stereo_pan_in_the_center_is_a_noop() {
+        let mut pan = PanProcessor::new(0.0);
+        pan.set_law(PanLaw::ConstantPower);
+        let samples = [1., 0.5];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        assert!((*input.get(0, 0) - 1.0).abs() < 1e-6);
+        assert!((*input.get(1, 0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_balance_does_not_mix_channels() {
+        let mut pan = PanProcessor::new(-0.5);
+        pan.set_mode(PanMode::Balance);
+        pan.set_law(PanLaw::Linear);
+        let samples = [1., 1.];
+        let mut input = AudioBuffer::from_interleaved(2, &samples);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        assert_f_eq!(*input.get(0, 0), 1.0);
+        assert_f_eq!(*input.get(1, 0), 0.5);
+    }
+
+    #[test]
+    fn test_vbap_pans_into_surround_channels() {
+        let mut pan = PanProcessor::<f32>::default();
+        pan.set_mode(PanMode::Vbap(SpeakerLayout::Surround51));
+        pan.set_azimuth(110.0);
+        let mut input = AudioBuffer::new(vec![vec![1.0; 4]; 6]);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        for channel_num in 0..6 {
+            let expected = if channel_num == 5 { 1.0 } else { 0.0 };
+            for sample in input.channel(channel_num) {
+                assert!((sample - expected).abs() < 1e-4, "{}", channel_num);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mono_input_is_untouched() {
+        let mut pan = PanProcessor::new(1.0);
+        let mut input = AudioBuffer::from_interleaved(1, &[1.0, 1.0]);
+        let mut context = AudioContext::default();
+
+        pan.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 1.0]);
+    }
+}
@@ -0,0 +1,195 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Two dimensional vector based amplitude panning (VBAP), as described by Ville Pulkki in
+//! "Virtual Sound Source Positioning Using Vector Base Amplitude Panning" (1997).
+//!
+//! The source is placed between the two speakers around it, with power normalised gains.
+
+/// Speaker layouts supported by the panner. Azimuths are in degrees, 0 is the front and positive
+/// angles go to the right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeakerLayout {
+    /// L, R at ±30°
+    Stereo,
+    /// L, R at ±45° and Ls, Rs at ±135°
+    Quad,
+    /// L, R at ±30°, C, LFE and Ls, Rs at ±110°
+    Surround51,
+}
+
+impl SpeakerLayout {
+    /// Maximum number of channels of any layout
+    pub const MAX_CHANNELS: usize = 6;
+
+    pub fn channels(&self) -> usize {
+        self.azimuths().len()
+    }
+
+    /// Speaker azimuth of each channel, `None` for channels which aren't positioned (LFE)
+    pub fn azimuths(&self) -> &'static [Option<f32>] {
+        match self {
+            SpeakerLayout::Stereo => &[Some(-30.0), Some(30.0)],
+            SpeakerLayout::Quad => &[Some(-45.0), Some(45.0), Some(-135.0), Some(135.0)],
+            SpeakerLayout::Surround51 => &[
+                Some(-30.0),
+                Some(30.0),
+                Some(0.0),
+                None,
+                Some(-110.0),
+                Some(110.0),
+            ],
+        }
+    }
+}
+
+fn unit_vector(azimuth: f32) -> (f32, f32) {
+    let radians = azimuth.to_radians();
+    (radians.sin(), radians.cos())
+}
+
+/// Write the gain of each channel in `layout` for a source at `azimuth` (in degrees) into
+/// `gains`, which should have [`SpeakerLayout::channels`] elements.
+///
+/// Sources outside of the area covered by the speakers (behind a stereo pair) are sent to the
+/// closest speaker.
+pub fn vbap_gains(layout: SpeakerLayout, azimuth: f32, gains: &mut [f32]) {
+    for gain in gains.iter_mut() {
+        *gain = 0.0;
+    }
+
+    // Speakers sorted by azimuth, so that consecutive speakers make up the pairs
+    let mut speakers = [(0.0_f32, 0_usize); SpeakerLayout::MAX_CHANNELS];
+    let mut num_speakers = 0;
+    for (channel, speaker_azimuth) in layout.azimuths().iter().enumerate() {
+        if let Some(speaker_azimuth) = speaker_azimuth {
+            speakers[num_speakers] = (*speaker_azimuth, channel);
+            num_speakers += 1;
+        }
+    }
+    let speakers = &mut speakers[..num_speakers];
+    speakers.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+    let (px, py) = unit_vector(azimuth);
+    for i in 0..speakers.len() {
+        let (first_azimuth, first_channel) = speakers[i];
+        let (second_azimuth, second_channel) = speakers[(i + 1) % speakers.len()];
+        let (l1x, l1y) = unit_vector(first_azimuth);
+        let (l2x, l2y) = unit_vector(second_azimuth);
+
+        let determinant = l1x * l2y - l1y * l2x;
+        if determinant.abs() < 1e-6 {
+            continue;
+        }
+        let g1 = (px * l2y - py * l2x) / determinant;
+        let g2 = (l1x * py - l1y * px) / determinant;
+        if g1 < -1e-6 || g2 < -1e-6 {
+            continue;
+        }
+
+        let norm = (g1 * g1 + g2 * g2).sqrt();
+        if let Some(gain) = gains.get_mut(first_channel) {
+            *gain = g1.max(0.0) / norm;
+        }
+        if let Some(gain) = gains.get_mut(second_channel) {
+            *gain = g2.max(0.0) / norm;
+        }
+        return;
+    }
+
+    let closest = speakers
+        .iter()
+        .min_by(|a, b| angle_between(a.0, azimuth).total_cmp(&angle_between(b.0, azimuth)));
+    if let Some(gain) = closest.and_then(|(_, channel)| gains.get_mut(*channel)) {
+        *gain = 1.0;
+    }
+}
+
+fn angle_between(a: f32, b: f32) -> f32 {
+    let difference = (a - b).rem_euclid(360.0);
+    difference.min(360.0 - difference)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gains(layout: SpeakerLayout, azimuth: f32) -> Vec<f32> {
+        let mut gains = vec![0.0; layout.channels()];
+        vbap_gains(layout, azimuth, &mut gains);
+        gains
+    }
+
+    fn assert_gains(actual: &[f32], expected: &[f32]) {
+        for (actual_gain, expected_gain) in actual.iter().zip(expected) {
+            assert!(
+                (actual_gain - expected_gain).abs() < 1e-4,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_source_on_a_speaker_only_uses_that_speaker() {
+        assert_gains(
+            &gains(SpeakerLayout::Surround51, 0.0),
+            &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+        );
+        assert_gains(
+            &gains(SpeakerLayout::Surround51, 110.0),
+            &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+        );
+        assert_gains(&gains(SpeakerLayout::Quad, -135.0), &[0.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_source_between_speakers_is_power_normalised() {
+        let quad = gains(SpeakerLayout::Quad, 0.0);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_gains(&quad, &[half, half, 0.0, 0.0]);
+
+        let back = gains(SpeakerLayout::Quad, 180.0);
+        assert_gains(&back, &[0.0, 0.0, half, half]);
+
+        let surround = gains(SpeakerLayout::Surround51, 15.0);
+        assert_gains(&surround, &[0.0, half, half, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_lfe_is_never_used() {
+        for azimuth in (-180..180).step_by(5) {
+            let surround = gains(SpeakerLayout::Surround51, azimuth as f32);
+            assert_eq!(surround[3], 0.0);
+            let power: f32 = surround.iter().map(|g| g * g).sum();
+            assert!((power - 1.0).abs() < 1e-4, "{} {:?}", azimuth, surround);
+        }
+    }
+
+    #[test]
+    fn test_stereo_sources_behind_go_to_the_closest_speaker() {
+        assert_gains(&gains(SpeakerLayout::Stereo, 120.0), &[0.0, 1.0]);
+        assert_gains(&gains(SpeakerLayout::Stereo, -100.0), &[1.0, 0.0]);
+    }
+}
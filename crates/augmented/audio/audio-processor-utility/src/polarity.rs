@@ -0,0 +1,178 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::{AudioBuffer, AudioContext, AudioProcessor, Float};
+
+/// Polarity can be flipped on up-to this number of channels
+pub const MAX_POLARITY_CHANNELS: usize = 64;
+
+#[derive(Default)]
+pub struct PolarityProcessorHandle {
+    /// Bit-mask of inverted channels
+    inverted: AtomicU64,
+    swap_channels: AtomicBool,
+}
+
+impl PolarityProcessorHandle {
+    /// Whether `channel` has its polarity inverted
+    pub fn is_inverted(&self, channel: usize) -> bool {
+        channel < MAX_POLARITY_CHANNELS
+            && self.inverted.load(Ordering::Relaxed) & (1 << channel) != 0
+    }
+
+    /// Invert the polarity of `channel`. Channels past [`MAX_POLARITY_CHANNELS`] are ignored.
+    pub fn set_inverted(&self, channel: usize, inverted: bool) {
+        if channel >= MAX_POLARITY_CHANNELS {
+            return;
+        }
+
+        if inverted {
+            self.inverted.fetch_or(1 << channel, Ordering::Relaxed);
+        } else {
+            self.inverted.fetch_and(!(1 << channel), Ordering::Relaxed);
+        }
+    }
+
+    /// Whether channels 0 and 1 are swapped
+    pub fn swap_channels(&self) -> bool {
+        self.swap_channels.load(Ordering::Relaxed)
+    }
+
+    pub fn set_swap_channels(&self, value: bool) {
+        self.swap_channels.store(value, Ordering::Relaxed);
+    }
+}
+
+/// An `AudioProcessor` which inverts the polarity of any of its channels and can swap the left
+/// and right channels.
+///
+/// Polarity is inverted on the input channels, before they are swapped.
+pub struct PolarityProcessor<SampleType> {
+    handle: Shared<PolarityProcessorHandle>,
+    phantom: PhantomData<SampleType>,
+}
+
+impl<SampleType> Default for PolarityProcessor<SampleType> {
+    fn default() -> Self {
+        Self::new_with_handle(make_shared(PolarityProcessorHandle::default()))
+    }
+}
+
+impl<SampleType> PolarityProcessor<SampleType> {
+    pub fn new_with_handle(handle: Shared<PolarityProcessorHandle>) -> Self {
+        Self {
+            handle,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<PolarityProcessorHandle> {
+        &self.handle
+    }
+}
+
+impl<SampleType> AudioProcessor for PolarityProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        for channel_num in 0..buffer.num_channels() {
+            if self.handle.is_inverted(channel_num) {
+                for sample in buffer.channel_mut(channel_num) {
+                    *sample = -*sample;
+                }
+            }
+        }
+
+        if self.handle.swap_channels() && buffer.num_channels() >= 2 {
+            for sample_num in 0..buffer.num_samples() {
+                let left = *buffer.get(0, sample_num);
+                let right = *buffer.get(1, sample_num);
+                buffer.set(0, sample_num, right);
+                buffer.set(1, sample_num, left);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_default_is_a_noop() {
+        let mut processor = PolarityProcessor::default();
+        let mut input = AudioBuffer::from_interleaved(2, &[1.0, 0.5, 0.2, -0.2]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 0.2]);
+        assert_eq!(input.channel(1), &[0.5, -0.2]);
+    }
+
+    #[test]
+    fn test_invert_one_channel() {
+        let mut processor = PolarityProcessor::default();
+        processor.handle().set_inverted(1, true);
+        let mut input = AudioBuffer::from_interleaved(2, &[1.0, 0.5, 0.2, -0.2]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_eq!(input.channel(0), &[1.0, 0.2]);
+        assert_eq!(input.channel(1), &[-0.5, 0.2]);
+
+        processor.handle().set_inverted(1, false);
+        assert!(!processor.handle().is_inverted(1));
+    }
+
+    #[test]
+    fn test_swap_channels_after_inverting() {
+        let mut processor = PolarityProcessor::default();
+        processor.handle().set_inverted(0, true);
+        processor.handle().set_swap_channels(true);
+        let mut input = AudioBuffer::from_interleaved(2, &[1.0, 0.5]);
+        let mut context = AudioContext::default();
+
+        processor.process(&mut context, &mut input);
+
+        assert_f_eq!(*input.get(0, 0), 0.5);
+        assert_f_eq!(*input.get(1, 0), -1.0);
+    }
+
+    #[test]
+    fn test_out_of_range_channels_are_ignored() {
+        let handle = PolarityProcessorHandle::default();
+        handle.set_inverted(MAX_POLARITY_CHANNELS, true);
+        assert!(!handle.is_inverted(MAX_POLARITY_CHANNELS));
+    }
+}
@@ -0,0 +1,157 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::marker::PhantomData;
+
+use audio_garbage_collector::{make_shared, Shared};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor, Float};
+
+use crate::mid_side::{decode, encode};
+
+/// Maximum width, where the side signal is doubled
+pub const MAX_WIDTH: f32 = 2.0;
+
+pub struct StereoWidthProcessorHandle {
+    width: AtomicF32,
+}
+
+impl StereoWidthProcessorHandle {
+    fn new(width: f32) -> Self {
+        Self {
+            width: AtomicF32::new(width.clamp(0.0, MAX_WIDTH)),
+        }
+    }
+
+    /// Set the width, between 0 (mono) and [`MAX_WIDTH`]. 1 leaves the input unchanged.
+    pub fn set_width(&self, width: f32) {
+        self.width.set(width.clamp(0.0, MAX_WIDTH));
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width.get()
+    }
+}
+
+/// An `AudioProcessor` which changes the stereo width of its input by scaling its side signal.
+///
+/// A width of 0 collapses the input into mono, 1 leaves it unchanged and values above 1 widen
+/// it. Buffers with less than 2 channels are left untouched.
+pub struct StereoWidthProcessor<SampleType> {
+    handle: Shared<StereoWidthProcessorHandle>,
+    phantom: PhantomData<SampleType>,
+}
+
+impl<SampleType> Default for StereoWidthProcessor<SampleType> {
+    /// Construct a `StereoWidthProcessor` with 1.0 width
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl<SampleType> StereoWidthProcessor<SampleType> {
+    pub fn new(width: f32) -> Self {
+        Self::new_with_handle(make_shared(StereoWidthProcessorHandle::new(width)))
+    }
+
+    pub fn new_with_handle(handle: Shared<StereoWidthProcessorHandle>) -> Self {
+        Self {
+            handle,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<StereoWidthProcessorHandle> {
+        &self.handle
+    }
+}
+
+impl<SampleType> AudioProcessor for StereoWidthProcessor<SampleType>
+where
+    SampleType: Float + Sync + Send,
+{
+    type SampleType = SampleType;
+
+    fn process(&mut self, _context: &mut AudioContext, buffer: &mut AudioBuffer<SampleType>) {
+        if buffer.num_channels() < 2 {
+            return;
+        }
+
+        let width = SampleType::from(self.handle.width()).unwrap();
+        for sample_num in 0..buffer.num_samples() {
+            let (mid, side) = encode(*buffer.get(0, sample_num), *buffer.get(1, sample_num));
+            let (left, right) = decode(mid, side * width);
+            buffer.set(0, sample_num, left);
+            buffer.set(1, sample_num, right);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    fn process(width: f32, samples: &[f32]) -> AudioBuffer<f32> {
+        let mut processor = StereoWidthProcessor::new(width);
+        let mut input = AudioBuffer::from_interleaved(2, samples);
+        let mut context = AudioContext::default();
+        processor.process(&mut context, &mut input);
+        input
+    }
+
+    #[test]
+    fn test_unit_width_is_a_noop() {
+        let output = process(1.0, &[1.0, 0.2, -0.5, 0.5]);
+        assert_f_eq!(*output.get(0, 0), 1.0);
+        assert_f_eq!(*output.get(1, 0), 0.2);
+        assert_f_eq!(*output.get(0, 1), -0.5);
+        assert_f_eq!(*output.get(1, 1), 0.5);
+    }
+
+    #[test]
+    fn test_zero_width_is_mono() {
+        let output = process(0.0, &[1.0, 0.2, -0.5, 0.5]);
+        assert_f_eq!(*output.get(0, 0), 0.6);
+        assert_f_eq!(*output.get(1, 0), 0.6);
+        assert_f_eq!(*output.get(0, 1), 0.0);
+        assert_f_eq!(*output.get(1, 1), 0.0);
+    }
+
+    #[test]
+    fn test_wide_doubles_the_side_signal() {
+        let output = process(2.0, &[1.0, 0.5]);
+        // mid = 0.75, side = 0.25 * 2
+        assert_f_eq!(*output.get(0, 0), 1.25);
+        assert_f_eq!(*output.get(1, 0), 0.25);
+    }
+
+    #[test]
+    fn test_width_is_clamped() {
+        let processor = StereoWidthProcessor::<f32>::new(1.0);
+        processor.handle().set_width(10.0);
+        assert_f_eq!(processor.handle().width(), MAX_WIDTH);
+        processor.handle().set_width(-1.0);
+        assert_f_eq!(processor.handle().width(), 0.0);
+    }
+}
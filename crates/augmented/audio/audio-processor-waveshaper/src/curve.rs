@@ -0,0 +1,230 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Transfer curves used by the waveshaper.
+
+use num_derive::{FromPrimitive, ToPrimitive};
+
+/// Input offset for [`WaveshaperCurve::TubeAsymmetric`], shifts the operating point of the curve
+/// so positive and negative half-cycles clip differently.
+const TUBE_BIAS: f32 = 0.3;
+
+/// The shape applied to the (driven) input signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum WaveshaperCurve {
+    /// `tanh(x)`, smooth saturation
+    Tanh = 0,
+    /// Cubic soft-clipper, reaches ±1 at ±1.5 and is flat above that
+    SoftClip = 1,
+    /// Clamps the signal into `-1..1`
+    HardClip = 2,
+    /// Reflects the signal back when it goes past ±1
+    Foldback = 3,
+    /// A biased `tanh`, which clips positive and negative half-cycles differently and generates
+    /// even harmonics
+    TubeAsymmetric = 4,
+    /// A user-defined [`TransferTable`]
+    Table = 5,
+}
+
+impl WaveshaperCurve {
+    pub const COUNT: usize = 6;
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        num_traits::FromPrimitive::from_usize(index)
+    }
+
+    /// Whether the curve can add a DC offset to the signal
+    pub fn is_asymmetric(&self) -> bool {
+        matches!(
+            self,
+            WaveshaperCurve::TubeAsymmetric | WaveshaperCurve::Table
+        )
+    }
+
+    /// Shape a single sample. `table` is only used for [`WaveshaperCurve::Table`].
+    #[inline]
+    pub fn apply(&self, table: &TransferTable, x: f32) -> f32 {
+        match self {
+            WaveshaperCurve::Tanh => x.tanh(),
+            WaveshaperCurve::SoftClip => soft_clip(x),
+            WaveshaperCurve::HardClip => x.clamp(-1.0, 1.0),
+            WaveshaperCurve::Foldback => foldback(x),
+            WaveshaperCurve::TubeAsymmetric => tube(x),
+            WaveshaperCurve::Table => table.get(x),
+        }
+    }
+}
+
+#[inline]
+fn soft_clip(x: f32) -> f32 {
+    let x = (x / 1.5).clamp(-1.0, 1.0);
+    1.5 * (x - x * x * x / 3.0)
+}
+
+/// Biased `tanh`, scaled so the negative half-cycle saturates at -1 and the positive one lower
+#[inline]
+fn tube(x: f32) -> f32 {
+    let offset = TUBE_BIAS.tanh();
+    ((x + TUBE_BIAS).tanh() - offset) / (1.0 + offset)
+}
+
+/// Triangle-wave folding, maps `x` into `-1..1` by mirroring it at the edges
+#[inline]
+fn foldback(x: f32) -> f32 {
+    let phase = 0.25 * x + 0.25;
+    4.0 * (phase - phase.round()).abs() - 1.0
+}
+
+/// A user-defined transfer curve.
+///
+/// Holds the output values for inputs evenly spaced across `-1..1`. Inputs in between points are
+/// linearly interpolated and inputs outside of `-1..1` are clamped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferTable {
+    points: Vec<f32>,
+}
+
+impl Default for TransferTable {
+    /// The identity curve
+    fn default() -> Self {
+        Self {
+            points: vec![-1.0, 1.0],
+        }
+    }
+}
+
+impl TransferTable {
+    /// Create a table from its points. Returns `None` if there are less than 2 points.
+    pub fn new(points: Vec<f32>) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+        Some(Self { points })
+    }
+
+    /// Create a table with `size` points by sampling `f` over `-1..1`
+    pub fn from_fn(size: usize, f: impl Fn(f32) -> f32) -> Option<Self> {
+        if size < 2 {
+            return None;
+        }
+        let points = (0..size)
+            .map(|i| f(-1.0 + 2.0 * i as f32 / (size - 1) as f32))
+            .collect();
+        Self::new(points)
+    }
+
+    pub fn points(&self) -> &[f32] {
+        &self.points
+    }
+
+    #[inline]
+    pub fn get(&self, x: f32) -> f32 {
+        let last = self.points.len() - 1;
+        let position = (x.clamp(-1.0, 1.0) + 1.0) * 0.5 * last as f32;
+        let index = (position as usize).min(last - 1);
+        let fraction = position - index as f32;
+        let start = self.points[index];
+        let end = self.points[index + 1];
+        start + (end - start) * fraction
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(curve: WaveshaperCurve, x: f32) -> f32 {
+        curve.apply(&TransferTable::default(), x)
+    }
+
+    #[test]
+    fn test_curves_are_bounded() {
+        for index in 0..WaveshaperCurve::COUNT {
+            let curve = WaveshaperCurve::from_index(index).unwrap();
+            for i in -100..=100 {
+                let x = i as f32 / 10.0;
+                let y = apply(curve, x);
+                assert!(y.abs() <= 1.0 + f32::EPSILON, "{:?} {} {}", curve, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_curves_keep_silence_silent() {
+        for index in 0..WaveshaperCurve::COUNT {
+            let curve = WaveshaperCurve::from_index(index).unwrap();
+            assert!(apply(curve, 0.0).abs() < 1e-6, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn test_soft_clip_is_continuous_at_the_knee() {
+        assert!((apply(WaveshaperCurve::SoftClip, 1.5) - 1.0).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::SoftClip, 3.0) - 1.0).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::SoftClip, -1.5) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_foldback_mirrors_over_the_threshold() {
+        assert!((apply(WaveshaperCurve::Foldback, 0.5) - 0.5).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::Foldback, 1.0) - 1.0).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::Foldback, 1.25) - 0.75).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::Foldback, -1.25) + 0.75).abs() < 1e-6);
+        assert!((apply(WaveshaperCurve::Foldback, 3.0) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tube_is_asymmetric() {
+        let positive = apply(WaveshaperCurve::TubeAsymmetric, 2.0);
+        let negative = apply(WaveshaperCurve::TubeAsymmetric, -2.0);
+        assert!((positive + negative).abs() > 0.1);
+    }
+
+    #[test]
+    fn test_transfer_table_needs_two_points() {
+        assert!(TransferTable::new(vec![]).is_none());
+        assert!(TransferTable::new(vec![0.0]).is_none());
+        assert!(TransferTable::from_fn(1, |x| x).is_none());
+    }
+
+    #[test]
+    fn test_transfer_table_interpolates_and_clamps() {
+        let table = TransferTable::new(vec![-1.0, 0.0, 0.5]).unwrap();
+        assert_eq!(table.get(-1.0), -1.0);
+        assert_eq!(table.get(-0.5), -0.5);
+        assert_eq!(table.get(0.0), 0.0);
+        assert_eq!(table.get(0.5), 0.25);
+        assert_eq!(table.get(1.0), 0.5);
+        assert_eq!(table.get(4.0), 0.5);
+        assert_eq!(table.get(-4.0), -1.0);
+    }
+
+    #[test]
+    fn test_transfer_table_from_fn() {
+        let table = TransferTable::from_fn(129, |x| x * x).unwrap();
+        assert_eq!(table.points().len(), 129);
+        assert!((table.get(0.5) - 0.25).abs() < 1e-3);
+        assert!((table.get(-0.3) - 0.09).abs() < 1e-3);
+    }
+}
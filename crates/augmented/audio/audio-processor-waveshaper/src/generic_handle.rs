@@ -0,0 +1,146 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use audio_garbage_collector::Shared;
+use audio_processor_traits::parameters::{
+    AudioProcessorHandle, FloatType, ParameterSpec, ParameterType, ParameterValue,
+};
+
+use crate::{
+    WaveshaperCurve, WaveshaperHandle, MAX_DRIVE_DB, MAX_FILTER_CUTOFF, MIN_FILTER_CUTOFF,
+};
+
+pub struct GenericHandle(pub Shared<WaveshaperHandle>);
+
+impl AudioProcessorHandle for GenericHandle {
+    fn name(&self) -> String {
+        "Waveshaper".to_string()
+    }
+
+    fn parameter_count(&self) -> usize {
+        6
+    }
+
+    fn get_parameter_spec(&self, index: usize) -> ParameterSpec {
+        let specs: [ParameterSpec; 6] = [
+            ParameterSpec::new(
+                "Drive".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, MAX_DRIVE_DB),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Curve".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, (WaveshaperCurve::COUNT - 1) as f32),
+                    step: Some(1.0),
+                }),
+            ),
+            ParameterSpec::new(
+                "Pre-filter".into(),
+                ParameterType::Float(FloatType {
+                    range: (MIN_FILTER_CUTOFF, MAX_FILTER_CUTOFF),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Tone".into(),
+                ParameterType::Float(FloatType {
+                    range: (MIN_FILTER_CUTOFF, MAX_FILTER_CUTOFF),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Output".into(),
+                ParameterType::Float(FloatType {
+                    range: (-48.0, 12.0),
+                    step: None,
+                }),
+            ),
+            ParameterSpec::new(
+                "Dry/Wet".into(),
+                ParameterType::Float(FloatType {
+                    range: (0.0, 1.0),
+                    step: None,
+                }),
+            ),
+        ];
+        specs[index].clone()
+    }
+
+    fn get_parameter(&self, index: usize) -> Option<ParameterValue> {
+        match index {
+            0 => Some(self.0.drive_db().into()),
+            1 => Some((self.0.curve() as usize as f32).into()),
+            2 => Some(self.0.pre_filter_cutoff().into()),
+            3 => Some(self.0.tone_cutoff().into()),
+            4 => Some(self.0.output_gain_db().into()),
+            5 => Some(self.0.dry_wet().into()),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&self, index: usize, request: ParameterValue) {
+        if let Ok(value) = request.try_into() {
+            let value: f32 = value;
+            match index {
+                0 => self.0.set_drive_db(value),
+                1 => {
+                    if let Some(curve) =
+                        WaveshaperCurve::from_index(value.round().max(0.0) as usize)
+                    {
+                        self.0.set_curve(curve)
+                    }
+                }
+                2 => self.0.set_pre_filter_cutoff(value),
+                3 => self.0.set_tone_cutoff(value),
+                4 => self.0.set_output_gain_db(value),
+                5 => self.0.set_dry_wet(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio_processor_traits::parameters::AudioProcessorHandleProvider;
+
+    use crate::WaveshaperProcessor;
+
+    use super::*;
+
+    #[test]
+    fn test_set_curve_through_the_generic_handle() {
+        let processor = WaveshaperProcessor::default();
+        let handle = processor.generic_handle();
+        handle.set_parameter(1, 3.0.into());
+        assert_eq!(processor.handle().curve(), WaveshaperCurve::Foldback);
+        assert_eq!(handle.get_parameter(1), Some(3.0.into()));
+
+        // Out of range values are ignored
+        handle.set_parameter(1, 40.0.into());
+        assert_eq!(processor.handle().curve(), WaveshaperCurve::Foldback);
+    }
+}
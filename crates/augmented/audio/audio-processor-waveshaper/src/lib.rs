@@ -0,0 +1,484 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Saturation and waveshaping distortion.
+//!
+//! [`WaveshaperProcessor`] is the [`audio_processor_traits::AudioProcessor`] implementation. The
+//! signal goes through:
+//!
+//! * A pre-filter (high-pass), which controls how much low-end gets into the shaper
+//! * The drive gain and the [`WaveshaperCurve`]
+//! * A DC blocker, for curves which aren't symmetric
+//! * A tone filter (low-pass) and the output gain
+//! * The dry/wet mix
+//!
+//! Curves are either one of the built-in shapes or a user-defined [`TransferTable`].
+//!
+//! [`WaveshaperHandle`] is the handle with which to change parameters from any thread. A generic
+//! handle is implemented to generate generic GUIs.
+
+use std::time::Duration;
+
+use audio_garbage_collector::{make_shared, make_shared_cell, Shared, SharedCell};
+use audio_processor_traits::parameters::{
+    make_handle_ref, AudioProcessorHandleProvider, AudioProcessorHandleRef,
+};
+use audio_processor_traits::{AtomicF32, AudioBuffer, AudioContext, AudioProcessor};
+use augmented_atomics::AtomicEnum;
+use augmented_dsp_filters::rbj::Filter;
+use augmented_dsp_filters::state::FilterState;
+use augmented_smooth_value::InterpolatedValue;
+
+pub use curve::{TransferTable, WaveshaperCurve};
+pub use generic_handle::GenericHandle;
+
+mod curve;
+mod generic_handle;
+#[cfg(all(test, debug_assertions))]
+mod test_allocator;
+
+pub const MAX_DRIVE_DB: f32 = 48.0;
+pub const MIN_FILTER_CUTOFF: f32 = 20.0;
+pub const MAX_FILTER_CUTOFF: f32 = 20000.0;
+
+const SMOOTHING_DURATION: Duration = Duration::from_millis(20);
+const FILTER_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+/// Corner frequency of the DC blocker high-pass
+const DC_BLOCKER_CUTOFF: f32 = 10.0;
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+pub struct WaveshaperHandle {
+    curve: AtomicEnum<WaveshaperCurve>,
+    transfer_table: SharedCell<TransferTable>,
+    drive_db: AtomicF32,
+    pre_filter_cutoff: AtomicF32,
+    tone_cutoff: AtomicF32,
+    output_gain_db: AtomicF32,
+    dry_wet: AtomicF32,
+}
+
+impl Default for WaveshaperHandle {
+    fn default() -> Self {
+        Self {
+            curve: AtomicEnum::new(WaveshaperCurve::Tanh),
+            transfer_table: make_shared_cell(TransferTable::default()),
+            drive_db: AtomicF32::new(12.0),
+            pre_filter_cutoff: AtomicF32::new(MIN_FILTER_CUTOFF),
+            tone_cutoff: AtomicF32::new(MAX_FILTER_CUTOFF),
+            output_gain_db: AtomicF32::new(0.0),
+            dry_wet: AtomicF32::new(1.0),
+        }
+    }
+}
+
+impl WaveshaperHandle {
+    pub fn curve(&self) -> WaveshaperCurve {
+        self.curve.get()
+    }
+
+    pub fn set_curve(&self, value: WaveshaperCurve) {
+        self.curve.set(value);
+    }
+
+    pub fn transfer_table(&self) -> Shared<TransferTable> {
+        self.transfer_table.get()
+    }
+
+    /// Replace the table used by [`WaveshaperCurve::Table`]. This doesn't change the current
+    /// curve.
+    pub fn set_transfer_table(&self, value: TransferTable) {
+        self.transfer_table.set(make_shared(value));
+    }
+
+    /// Gain applied before the curve, in dB between 0 and [`MAX_DRIVE_DB`]
+    pub fn drive_db(&self) -> f32 {
+        self.drive_db.get()
+    }
+
+    pub fn set_drive_db(&self, value: f32) {
+        self.drive_db.set(value.clamp(0.0, MAX_DRIVE_DB));
+    }
+
+    /// Cut-off of the high-pass filter before the curve
+    pub fn pre_filter_cutoff(&self) -> f32 {
+        self.pre_filter_cutoff.get()
+    }
+
+    pub fn set_pre_filter_cutoff(&self, value: f32) {
+        self.pre_filter_cutoff
+            .set(value.clamp(MIN_FILTER_CUTOFF, MAX_FILTER_CUTOFF));
+    }
+
+    /// Cut-off of the low-pass filter after the curve
+    pub fn tone_cutoff(&self) -> f32 {
+        self.tone_cutoff.get()
+    }
+
+    pub fn set_tone_cutoff(&self, value: f32) {
+        self.tone_cutoff
+            .set(value.clamp(MIN_FILTER_CUTOFF, MAX_FILTER_CUTOFF));
+    }
+
+    /// Gain applied to the wet signal, in dB
+    pub fn output_gain_db(&self) -> f32 {
+        self.output_gain_db.get()
+    }
+
+    pub fn set_output_gain_db(&self, value: f32) {
+        self.output_gain_db.set(value.clamp(-48.0, 12.0));
+    }
+
+    pub fn dry_wet(&self) -> f32 {
+        self.dry_wet.get()
+    }
+
+    pub fn set_dry_wet(&self, value: f32) {
+        self.dry_wet.set(value.clamp(0.0, 1.0));
+    }
+}
+
+struct DCBlocker {
+    pole: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl DCBlocker {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            pole: Self::pole(sample_rate),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    /// Approximation of the pole for a `DC_BLOCKER_CUTOFF` corner, good while it is far below
+    /// the sample rate
+    fn pole(sample_rate: f32) -> f32 {
+        (1.0 - 2.0 * std::f32::consts::PI * DC_BLOCKER_CUTOFF / sample_rate).max(0.0)
+    }
+
+    #[inline]
+    fn process(&mut self, input: f32) -> f32 {
+        let output = input - self.last_input + self.pole * self.last_output;
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+}
+
+struct ChannelState {
+    pre_filter: Filter<f32>,
+    tone_filter: Filter<f32>,
+    dc_blocker: DCBlocker,
+}
+
+impl ChannelState {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            pre_filter: Filter::new(),
+            tone_filter: Filter::new(),
+            dc_blocker: DCBlocker::new(sample_rate),
+        }
+    }
+
+    fn setup(&mut self, sample_rate: f32, pre_filter_cutoff: f32, tone_cutoff: f32) {
+        // Cut-offs too close to nyquist make the filters unstable
+        let max_cutoff = sample_rate * 0.45;
+        self.pre_filter
+            .setup_high_pass(sample_rate, pre_filter_cutoff.min(max_cutoff), FILTER_Q);
+        self.tone_filter
+            .setup_low_pass(sample_rate, tone_cutoff.min(max_cutoff), FILTER_Q);
+    }
+
+    #[inline]
+    fn pre_filter(&mut self, input: f32) -> f32 {
+        let filter = &mut self.pre_filter;
+        let very_small_amount = filter.denormal_prevention.alternating_current();
+        filter
+            .state
+            .process1(&filter.coefficients, input, very_small_amount)
+    }
+
+    #[inline]
+    fn tone_filter(&mut self, input: f32) -> f32 {
+        let filter = &mut self.tone_filter;
+        let very_small_amount = filter.denormal_prevention.alternating_current();
+        filter
+            .state
+            .process1(&filter.coefficients, input, very_small_amount)
+    }
+}
+
+/// Waveshaping distortion, see the crate documentation for the signal flow.
+pub struct WaveshaperProcessor {
+    handle: Shared<WaveshaperHandle>,
+    channels: Vec<ChannelState>,
+    sample_rate: f32,
+    pre_filter_cutoff: f32,
+    tone_cutoff: f32,
+    drive: InterpolatedValue,
+    output_gain: InterpolatedValue,
+    dry_wet: InterpolatedValue,
+}
+
+impl AudioProcessorHandleProvider for WaveshaperProcessor {
+    fn generic_handle(&self) -> AudioProcessorHandleRef {
+        make_handle_ref(GenericHandle(self.handle.clone()))
+    }
+}
+
+impl Default for WaveshaperProcessor {
+    fn default() -> Self {
+        Self::new(make_shared(WaveshaperHandle::default()))
+    }
+}
+
+impl WaveshaperProcessor {
+    pub fn new(handle: Shared<WaveshaperHandle>) -> Self {
+        let sample_rate = 44100.0;
+        Self {
+            drive: InterpolatedValue::new(
+                sample_rate,
+                SMOOTHING_DURATION,
+                db_to_gain(handle.drive_db()),
+            ),
+            output_gain: InterpolatedValue::new(
+                sample_rate,
+                SMOOTHING_DURATION,
+                db_to_gain(handle.output_gain_db()),
+            ),
+            dry_wet: InterpolatedValue::new(sample_rate, SMOOTHING_DURATION, handle.dry_wet()),
+            handle,
+            channels: vec![],
+            sample_rate,
+            pre_filter_cutoff: 0.0,
+            tone_cutoff: 0.0,
+        }
+    }
+
+    pub fn handle(&self) -> &Shared<WaveshaperHandle> {
+        &self.handle
+    }
+
+    fn update_filters(&mut self) {
+        let pre_filter_cutoff = self.handle.pre_filter_cutoff();
+        let tone_cutoff = self.handle.tone_cutoff();
+        if pre_filter_cutoff == self.pre_filter_cutoff && tone_cutoff == self.tone_cutoff {
+            return;
+        }
+
+        self.pre_filter_cutoff = pre_filter_cutoff;
+        self.tone_cutoff = tone_cutoff;
+        for channel in &mut self.channels {
+            channel.setup(self.sample_rate, pre_filter_cutoff, tone_cutoff);
+        }
+    }
+}
+
+impl AudioProcessor for WaveshaperProcessor {
+    type SampleType = f32;
+
+    fn prepare(&mut self, context: &mut AudioContext) {
+        self.sample_rate = context.settings.sample_rate();
+        self.channels = (0..context.settings.output_channels())
+            .map(|_| ChannelState::new(self.sample_rate))
+            .collect();
+
+        for value in [&mut self.drive, &mut self.output_gain, &mut self.dry_wet] {
+            value.set_sample_rate(self.sample_rate);
+        }
+        self.drive.set_immediate(db_to_gain(self.handle.drive_db()));
+        self.output_gain
+            .set_immediate(db_to_gain(self.handle.output_gain_db()));
+        self.dry_wet.set_immediate(self.handle.dry_wet());
+
+        self.pre_filter_cutoff = 0.0;
+        self.tone_cutoff = 0.0;
+        self.update_filters();
+    }
+
+    fn process(&mut self, _context: &mut AudioContext, data: &mut AudioBuffer<Self::SampleType>) {
+        self.update_filters();
+        self.drive.set(db_to_gain(self.handle.drive_db()));
+        self.output_gain
+            .set(db_to_gain(self.handle.output_gain_db()));
+        self.dry_wet.set(self.handle.dry_wet());
+
+        let curve = self.handle.curve();
+        let transfer_table = self.handle.transfer_table.get();
+        let block_dc = curve.is_asymmetric();
+        let num_channels = data.num_channels().min(self.channels.len());
+
+        for sample_index in 0..data.num_samples() {
+            let drive = self.drive.next_sample();
+            let output_gain = self.output_gain.next_sample();
+            let dry_wet = self.dry_wet.next_sample();
+
+            for (channel_index, channel) in self.channels[..num_channels].iter_mut().enumerate() {
+                let dry = *data.get(channel_index, sample_index);
+
+                let mut wet = channel.pre_filter(dry);
+                wet = curve.apply(&transfer_table, wet * drive);
+                if block_dc {
+                    wet = channel.dc_blocker.process(wet);
+                }
+                wet = channel.tone_filter(wet) * output_gain;
+
+                data.set(
+                    channel_index,
+                    sample_index,
+                    dry * (1.0 - dry_wet) + wet * dry_wet,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+    use audio_processor_testing_helpers::{rms_level, sine_buffer};
+    use audio_processor_traits::AudioProcessorSettings;
+
+    use super::*;
+
+    fn sine(settings: AudioProcessorSettings, frequency: f32) -> AudioBuffer<f32> {
+        let samples = sine_buffer(
+            settings.sample_rate(),
+            frequency,
+            Duration::from_millis(200),
+        );
+        AudioBuffer::new(vec![samples.clone(), samples])
+    }
+
+    fn process(processor: &mut WaveshaperProcessor, buffer: &mut AudioBuffer<f32>) {
+        let mut context = AudioContext::from(AudioProcessorSettings::default());
+        processor.prepare(&mut context);
+        processor.process(&mut context, buffer);
+    }
+
+    #[test]
+    fn test_dry_signal_is_untouched_without_wet() {
+        let settings = AudioProcessorSettings::default();
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_dry_wet(0.0);
+        let input = sine(settings, 440.0);
+        let mut output = input.clone();
+        process(&mut processor, &mut output);
+        assert_eq!(input.channel(0), output.channel(0));
+        assert_eq!(input.channel(1), output.channel(1));
+    }
+
+    #[test]
+    fn test_hard_clip_limits_the_output() {
+        let settings = AudioProcessorSettings::default();
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_curve(WaveshaperCurve::HardClip);
+        processor.handle().set_drive_db(24.0);
+        let mut output = sine(settings, 440.0);
+        process(&mut processor, &mut output);
+
+        // Skip the filters settling in
+        let peak = output.channel(0)[4410..]
+            .iter()
+            .fold(0.0_f32, |acc, s| acc.max(s.abs()));
+        assert!(peak < 1.1, "{}", peak);
+        assert!(peak > 0.9, "{}", peak);
+    }
+
+    #[test]
+    fn test_drive_adds_level() {
+        let settings = AudioProcessorSettings::default();
+        let mut quiet = sine(settings, 440.0);
+        for sample in quiet.slice_mut() {
+            *sample *= 0.1;
+        }
+        let input_rms = rms_level(&quiet.channel(0)[4410..]);
+
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_drive_db(24.0);
+        process(&mut processor, &mut quiet);
+        let output_rms = rms_level(&quiet.channel(0)[4410..]);
+        assert!(output_rms > input_rms * 4.0, "{} {}", input_rms, output_rms);
+    }
+
+    #[test]
+    fn test_tone_filter_removes_highs() {
+        let settings = AudioProcessorSettings::default();
+        let mut bright = sine(settings, 8000.0);
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_drive_db(0.0);
+        processor.handle().set_tone_cutoff(500.0);
+        process(&mut processor, &mut bright);
+        assert!(rms_level(&bright.channel(0)[4410..]) < 0.05);
+    }
+
+    #[test]
+    fn test_user_transfer_table_is_used() {
+        let settings = AudioProcessorSettings::default();
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_curve(WaveshaperCurve::Table);
+        processor.handle().set_drive_db(0.0);
+        processor
+            .handle()
+            .set_transfer_table(TransferTable::new(vec![0.0, 0.0, 0.0]).unwrap());
+        let mut output = sine(settings, 440.0);
+        process(&mut processor, &mut output);
+        assert!(output.channels().iter().flatten().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_dc_blocker_corner_follows_the_sample_rate() {
+        for sample_rate in [22050.0_f32, 44100.0, 96000.0, 192000.0] {
+            let pole = DCBlocker::pole(sample_rate) as f64;
+            // |H| of (1 - z^-1) / (1 - pole * z^-1) at the corner frequency, in f64 as the
+            // terms nearly cancel out
+            let w = 2.0 * std::f64::consts::PI * DC_BLOCKER_CUTOFF as f64 / sample_rate as f64;
+            let numerator = 2.0 - 2.0 * w.cos();
+            let denominator = 1.0 + pole * pole - 2.0 * pole * w.cos();
+            let gain = (numerator / denominator).sqrt();
+            assert!(
+                (gain - std::f64::consts::FRAC_1_SQRT_2).abs() < 0.01,
+                "{}",
+                gain
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_alloc_process() {
+        let mut processor = WaveshaperProcessor::default();
+        processor.handle().set_curve(WaveshaperCurve::Table);
+        let mut context = AudioContext::from(AudioProcessorSettings::default());
+        processor.prepare(&mut context);
+
+        let mut buffer = AudioBuffer::new(vec![vec![0.5; 512], vec![0.5; 512]]);
+        assert_no_alloc(|| {
+            processor.process(&mut context, &mut buffer);
+        });
+    }
+}
@@ -20,29 +20,95 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
+
+//! Streaming abstractions for real-time audio processing.
+//!
+//! A [`ProducerActor`] pulls items out of a [`ProducerProcedure`] (for example, a file reader
+//! running on a background thread) and pushes them onto a lock-free ring buffer. The
+//! [`ConsumerActor`] pops them on the other end (for example, on the audio thread), without
+//! locking or allocating.
+
+pub use ringbuf;
+
 pub trait ProducerProcedure {
     type Item;
 
     fn pull(&mut self) -> Option<Self::Item>;
 }
 
+/// Create a [`ProducerActor`] and [`ConsumerActor`] pair, connected by a ring buffer with room for
+/// `capacity` items
+pub fn actors<T, B: ProducerProcedure<Item = T>>(
+    read_block: B,
+    capacity: usize,
+) -> (ProducerActor<T, B>, ConsumerActor<T>) {
+    let (tx, rx) = ringbuf::RingBuffer::new(capacity).split();
+    (ProducerActor::new(read_block, tx), ConsumerActor::new(rx))
+}
+
 pub struct ProducerActor<T, B: ProducerProcedure<Item = T>> {
     read_block: B,
     producer: ringbuf::Producer<T>,
+    /// An item which was pulled while the ring buffer was full
+    pending: Option<T>,
 }
 
-impl<T: std::fmt::Debug, B: ProducerProcedure<Item = T>> ProducerActor<T, B> {
+impl<T, B: ProducerProcedure<Item = T>> ProducerActor<T, B> {
     pub fn new(read_block: B, producer: ringbuf::Producer<T>) -> Self {
         Self {
             read_block,
             producer,
+            pending: None,
+        }
+    }
+
+    /// Pull a single item and push it. Returns false if nothing was pushed, either because the
+    /// procedure returned nothing or because the ring buffer is full. In the latter case, the item
+    /// is kept and pushed on the next call.
+    pub fn pull(&mut self) -> bool {
+        let item = match self.pending.take() {
+            Some(item) => item,
+            None => match self.read_block.pull() {
+                Some(item) => item,
+                None => return false,
+            },
+        };
+
+        match self.producer.push(item) {
+            Ok(()) => true,
+            Err(item) => {
+                self.pending = Some(item);
+                false
+            }
         }
     }
 
-    pub fn pull(&mut self) {
-        if let Some(v) = self.read_block.pull() {
-            self.producer.push(v).unwrap();
+    /// Pull items until the ring buffer is full or the procedure returns nothing. Returns the
+    /// number of items pushed.
+    pub fn fill(&mut self) -> usize {
+        let mut count = 0;
+        while !self.producer.is_full() && self.pull() {
+            count += 1;
         }
+        count
+    }
+
+    /// Whether the ring buffer is full
+    pub fn is_full(&self) -> bool {
+        self.producer.is_full()
+    }
+
+    pub fn procedure(&self) -> &B {
+        &self.read_block
+    }
+
+    pub fn procedure_mut(&mut self) -> &mut B {
+        &mut self.read_block
+    }
+
+    /// Drop the item pulled while the ring buffer was full, if any
+    pub fn clear_pending(&mut self) {
+        self.pending = None;
     }
 }
 
@@ -56,7 +122,7 @@ impl<F> FnProcedure<F> {
 
 impl<T, F> ProducerProcedure for FnProcedure<F>
 where
-    F: Fn() -> Option<T>,
+    F: FnMut() -> Option<T>,
 {
     type Item = T;
 
@@ -66,10 +132,33 @@ where
 }
 
 pub struct ConsumerActor<T> {
-    #[allow(dead_code)]
     rx: ringbuf::Consumer<T>,
 }
 
+impl<T> ConsumerActor<T> {
+    pub fn new(rx: ringbuf::Consumer<T>) -> Self {
+        Self { rx }
+    }
+
+    /// Pop the next item. This never blocks or allocates.
+    pub fn pop(&mut self) -> Option<T> {
+        self.rx.pop()
+    }
+
+    /// Number of items waiting to be popped
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rx.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rx.capacity()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -84,4 +173,40 @@ mod test {
         let value = rx.pop().unwrap();
         assert_eq!(value, 10);
     }
+
+    #[test]
+    fn test_fill_stops_when_full() {
+        let mut count = 0;
+        let read_block = FnProcedure::new(move || {
+            count += 1;
+            Some(count)
+        });
+        let (mut producer, mut consumer) = actors(read_block, 4);
+
+        assert_eq!(producer.fill(), 4);
+        assert!(producer.is_full());
+        assert_eq!(consumer.len(), 4);
+        assert!(!producer.pull());
+
+        // The item pulled while full is pushed once there's room
+        assert_eq!(consumer.pop(), Some(1));
+        assert!(producer.pull());
+        assert_eq!(
+            (0..4).filter_map(|_| consumer.pop()).collect::<Vec<_>>(),
+            vec![2, 3, 4, 5]
+        );
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_fill_stops_when_the_procedure_is_done() {
+        let mut items = vec![1, 2];
+        let read_block = FnProcedure::new(move || items.pop());
+        let (mut producer, mut consumer) = actors(read_block, 4);
+
+        assert_eq!(producer.fill(), 2);
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(consumer.pop(), None);
+    }
 }
@@ -23,6 +23,10 @@
 use std::process::Command;
 use std::process::ExitStatus;
 
+/// Runs the `lame` binary to convert a WAV file into MP3.
+#[deprecated(
+    note = "Requires `lame` to be installed, use `audio_processor_file::encoder` with the `mp3` feature instead"
+)]
 pub fn convert_wav_file_to_mp3(
     wav_file_path: &str,
     mp3_file_path: &str,
@@ -39,6 +43,7 @@ mod tests {
     use crate::convert_wav_file_to_mp3;
 
     #[test]
+    #[allow(deprecated)]
     fn it_can_encode_mp3() {
         let crate_dir = env!("CARGO_MANIFEST_DIR");
         let input_path = format!("{}/test-inputs/synth.wav", crate_dir);
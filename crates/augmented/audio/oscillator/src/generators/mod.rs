@@ -42,3 +42,11 @@ pub fn square_generator(phase: f32) -> f32 {
 pub fn saw_generator(phase: f32) -> f32 {
     (1.0 - (phase % 1.0)) * 2.0 - 1.0
 }
+
+pub fn saw_up_generator(phase: f32) -> f32 {
+    (phase % 1.0) * 2.0 - 1.0
+}
+
+pub fn triangle_generator(phase: f32) -> f32 {
+    1.0 - 4.0 * ((phase % 1.0) - 0.5).abs()
+}
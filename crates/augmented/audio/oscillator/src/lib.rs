@@ -40,12 +40,25 @@
 //! let sample_rate = 44100.0;
 //! // let mut osc = WaveTableOscillator::new(vec![/* your wave table data */]);
 //! // You can either ^^^^ provide your own table (and update it at runtime) or generate a table
-//! // of a certain length (100 sample here) from a function oscillator
+//! // of a certain length (100 sample here) from a function oscillator, which is band-limited
+//! // with one table per octave so it doesn't alias at high frequencies
 //! let mut osc = WaveTableOscillator::from_oscillator(Oscillator::sine(sample_rate), 100);
 //! osc.set_frequency(40.0);  // set freq. in Hz
 //! let _sample = osc.next_sample(); // tick the oscillator forward
 //! ```
 //!
+//! ## Band-limited saw, square & triangle
+//! The functions in [`generators`] alias at high frequencies, [`polyblep::PolyBlepOscillator`]
+//! generates the same shapes with PolyBLEP corrections applied.
+//! ```
+//! use augmented_oscillator::polyblep::PolyBlepOscillator;
+//!
+//! let mut osc = PolyBlepOscillator::square(44100.0);
+//! osc.set_frequency(4000.0);
+//! osc.set_pulse_width(0.3);
+//! let _sample = osc.next_sample();
+//! ```
+//!
 //! ## Custom oscillator generator function
 //! ```
 //! let sample_rate = 44100.0;
@@ -58,6 +71,7 @@
 //! ```
 
 pub mod generators;
+pub mod polyblep;
 pub mod wavetable;
 
 #[cfg(test)]
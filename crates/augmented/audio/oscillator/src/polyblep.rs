@@ -0,0 +1,357 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Band-limited oscillators using PolyBLEP (polynomial band-limited step) corrections.
+//!
+//! The naive generators in [`crate::generators`] have hard discontinuities which alias badly
+//! once the oscillator frequency goes up. [`PolyBlepOscillator`] smooths each discontinuity in
+//! the waveform (or in its derivative, for the triangle) with a 2-sample polynomial residual,
+//! which removes most of the audible aliasing at very little cost.
+//!
+//! ```
+//! use augmented_oscillator::polyblep::PolyBlepOscillator;
+//!
+//! let mut osc = PolyBlepOscillator::square(44100.0);
+//! osc.set_frequency(2000.0);
+//! osc.set_pulse_width(0.25);
+//! let _sample = osc.next_sample();
+//! ```
+
+/// The waveform a [`PolyBlepOscillator`] generates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolyBlepWaveform {
+    /// Descending saw, matching [`crate::generators::saw_generator`]
+    Saw,
+    /// Pulse wave, high for `pulse_width` of the period
+    Square,
+    /// Triangle wave, rising for `pulse_width` of the period
+    Triangle,
+}
+
+/// Residual of a unit step discontinuity at phase `0`, band-limited over one sample on each
+/// side.
+///
+/// `phase` is in the 0-1 range and `phase_step` is the phase increment per sample.
+pub fn poly_blep(phase: f32, phase_step: f32) -> f32 {
+    if phase < phase_step {
+        let x = phase / phase_step;
+        -0.5 * (1.0 - x) * (1.0 - x)
+    } else if phase > 1.0 - phase_step {
+        let x = (phase - 1.0) / phase_step;
+        0.5 * (1.0 + x) * (1.0 + x)
+    } else {
+        0.0
+    }
+}
+
+/// Residual of a unit slope discontinuity (a corner) at phase `0`, this is the integral of
+/// [`poly_blep`].
+///
+/// The result is in samples, so it should be scaled by the slope change per sample.
+pub fn poly_blamp(phase: f32, phase_step: f32) -> f32 {
+    if phase < phase_step {
+        let x = 1.0 - phase / phase_step;
+        x * x * x / 6.0
+    } else if phase > 1.0 - phase_step {
+        let x = 1.0 + (phase - 1.0) / phase_step;
+        x * x * x / 6.0
+    } else {
+        0.0
+    }
+}
+
+/// Wraps a phase value into the 0-1 range
+fn wrap_phase(phase: f32) -> f32 {
+    phase - phase.floor()
+}
+
+/// An oscillator generating band-limited saw, square & triangle waves.
+///
+/// Unlike [`crate::Oscillator`] the generator can't be an arbitrary function of the phase, as
+/// the corrections depend on where the discontinuities are & on the phase increment.
+#[derive(Debug, Clone)]
+pub struct PolyBlepOscillator {
+    waveform: PolyBlepWaveform,
+    /// Current phase of the oscillator
+    phase: f32,
+    phase_step: f32,
+    /// Fraction of the period where square is high / triangle is rising
+    pulse_width: f32,
+    sample_rate: f32,
+    frequency: f32,
+}
+
+impl PolyBlepOscillator {
+    /// Construct a new oscillator with a given sample rate and waveform
+    pub fn new(sample_rate: f32, waveform: PolyBlepWaveform) -> Self {
+        let frequency = 440.0;
+        PolyBlepOscillator {
+            waveform,
+            phase: 0.0,
+            phase_step: crate::get_phase_step(sample_rate, frequency),
+            pulse_width: 0.5,
+            sample_rate,
+            frequency,
+        }
+    }
+
+    /// Construct a band-limited saw generator
+    pub fn saw(sample_rate: f32) -> Self {
+        Self::new(sample_rate, PolyBlepWaveform::Saw)
+    }
+
+    /// Construct a band-limited square generator
+    pub fn square(sample_rate: f32) -> Self {
+        Self::new(sample_rate, PolyBlepWaveform::Square)
+    }
+
+    /// Construct a band-limited triangle generator
+    pub fn triangle(sample_rate: f32) -> Self {
+        Self::new(sample_rate, PolyBlepWaveform::Triangle)
+    }
+
+    pub fn waveform(&self) -> PolyBlepWaveform {
+        self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: PolyBlepWaveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Set the sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.phase_step = crate::get_phase_step(self.sample_rate, self.frequency);
+    }
+
+    /// Get the oscillator frequency
+    pub fn get_frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Set the oscillator frequency
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.phase_step = crate::get_phase_step(self.sample_rate, self.frequency);
+    }
+
+    pub fn pulse_width(&self) -> f32 {
+        self.pulse_width
+    }
+
+    /// Set the pulse width, this is clamped between 0.01 and 0.99.
+    ///
+    /// For the square this is the duty cycle, for the triangle it's the fraction of the period
+    /// spent rising (so 0.5 is symmetric and values near the edges approach a saw). It has no
+    /// effect on the saw.
+    pub fn set_pulse_width(&mut self, pulse_width: f32) {
+        self.pulse_width = pulse_width.clamp(0.01, 0.99);
+    }
+
+    /// Return the current phase as a number between 0-1
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Reset the phase, for example, to hard-sync or retrigger the oscillator
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = wrap_phase(phase);
+    }
+
+    /// Process a single sample & update the oscillator phase.
+    pub fn next_sample(&mut self) -> f32 {
+        let result = self.get();
+        self.tick();
+        result
+    }
+
+    pub fn tick(&mut self) {
+        self.phase += self.phase_step;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+    }
+
+    pub fn tick_n(&mut self, n: f32) {
+        self.phase = wrap_phase(self.phase + n * self.phase_step);
+    }
+
+    /// Get the band-limited value at the current phase
+    pub fn get(&self) -> f32 {
+        let phase = self.phase;
+        let phase_step = self.phase_step.abs().min(0.5);
+
+        match self.waveform {
+            PolyBlepWaveform::Saw => {
+                let naive = 1.0 - 2.0 * phase;
+                naive + 2.0 * poly_blep(phase, phase_step)
+            }
+            PolyBlepWaveform::Square => {
+                let pulse_width = self.pulse_width;
+                let naive = if phase < pulse_width { 1.0 } else { -1.0 };
+                naive + 2.0 * poly_blep(phase, phase_step)
+                    - 2.0 * poly_blep(wrap_phase(phase - pulse_width), phase_step)
+            }
+            PolyBlepWaveform::Triangle => {
+                let pulse_width = self.pulse_width;
+                let naive = if phase < pulse_width {
+                    -1.0 + 2.0 * phase / pulse_width
+                } else {
+                    1.0 - 2.0 * (phase - pulse_width) / (1.0 - pulse_width)
+                };
+                // Slope change at the corners, converted from per-phase to per-sample
+                let slope_change = 2.0 / (pulse_width * (1.0 - pulse_width)) * phase_step;
+                naive + slope_change * poly_blamp(phase, phase_step)
+                    - slope_change * poly_blamp(wrap_phase(phase - pulse_width), phase_step)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::generators;
+    use crate::test_utils::{aliasing_ratio_db, generate_plot};
+    use crate::Oscillator;
+
+    use super::*;
+
+    #[test]
+    fn test_generate_plots() {
+        let root_path = format!("{}/src/polyblep.rs", env!("CARGO_MANIFEST_DIR"));
+        for (name, waveform) in [
+            ("saw-wave", PolyBlepWaveform::Saw),
+            ("square-wave", PolyBlepWaveform::Square),
+            ("triangle-wave", PolyBlepWaveform::Triangle),
+        ] {
+            let mut oscillator = PolyBlepOscillator::new(44100.0, waveform);
+            oscillator.set_frequency(440.0);
+            generate_plot(&root_path, || oscillator.next_sample(), name);
+        }
+    }
+
+    #[test]
+    fn test_poly_blep_is_continuous_with_step() {
+        // Just before the step the residual approaches 0.5 & just after it approaches -0.5, so a
+        // unit step plus the residual is continuous
+        let phase_step = 0.1;
+        assert!((poly_blep(1.0 - 1e-6, phase_step) - 0.5).abs() < 1e-3);
+        assert!((poly_blep(0.0, phase_step) + 0.5).abs() < 1e-3);
+        assert!(poly_blep(0.5, phase_step).abs() < f32::EPSILON);
+        assert!((poly_blamp(1.0 - 1e-6, phase_step) - 1.0 / 6.0).abs() < 1e-3);
+        assert!((poly_blamp(0.0, phase_step) - 1.0 / 6.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_waveforms_stay_in_range_and_have_no_dc() {
+        for waveform in [
+            PolyBlepWaveform::Saw,
+            PolyBlepWaveform::Square,
+            PolyBlepWaveform::Triangle,
+        ] {
+            let mut oscillator = PolyBlepOscillator::new(44100.0, waveform);
+            oscillator.set_frequency(441.0);
+            let samples: Vec<f32> = (0..44100).map(|_| oscillator.next_sample()).collect();
+            let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+            assert!(mean.abs() < 0.01, "{:?} mean={}", waveform, mean);
+            for sample in samples {
+                assert!(sample.abs() <= 1.1, "{:?} sample={}", waveform, sample);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pulse_width_changes_duty_cycle() {
+        let mut oscillator = PolyBlepOscillator::square(44100.0);
+        oscillator.set_frequency(100.0);
+        oscillator.set_pulse_width(0.25);
+        let samples: Vec<f32> = (0..44100).map(|_| oscillator.next_sample()).collect();
+        let high = samples.iter().filter(|s| **s > 0.0).count() as f32 / samples.len() as f32;
+        assert!((high - 0.25).abs() < 0.01, "high={}", high);
+    }
+
+    #[test]
+    fn test_polyblep_saw_aliases_less_than_naive_saw() {
+        let sample_rate = 44100.0;
+        let frequency = 3520.0;
+        let mut naive = Oscillator::new_with_sample_rate(sample_rate, generators::saw_generator);
+        naive.set_frequency(frequency);
+        let mut polyblep = PolyBlepOscillator::saw(sample_rate);
+        polyblep.set_frequency(frequency);
+
+        let naive_aliasing = aliasing_ratio_db(sample_rate, frequency, || naive.next_sample());
+        let polyblep_aliasing =
+            aliasing_ratio_db(sample_rate, frequency, || polyblep.next_sample());
+        assert!(
+            polyblep_aliasing < naive_aliasing - 10.0,
+            "naive={}dB polyblep={}dB",
+            naive_aliasing,
+            polyblep_aliasing
+        );
+    }
+
+    #[test]
+    fn test_polyblep_square_aliases_less_than_naive_square() {
+        let sample_rate = 44100.0;
+        let frequency = 3520.0;
+        let mut naive = Oscillator::new_with_sample_rate(sample_rate, generators::square_generator);
+        naive.set_frequency(frequency);
+        let mut polyblep = PolyBlepOscillator::square(sample_rate);
+        polyblep.set_frequency(frequency);
+
+        let naive_aliasing = aliasing_ratio_db(sample_rate, frequency, || naive.next_sample());
+        let polyblep_aliasing =
+            aliasing_ratio_db(sample_rate, frequency, || polyblep.next_sample());
+        assert!(
+            polyblep_aliasing < naive_aliasing - 10.0,
+            "naive={}dB polyblep={}dB",
+            naive_aliasing,
+            polyblep_aliasing
+        );
+    }
+
+    #[test]
+    fn test_polyblep_triangle_aliases_less_than_naive_triangle() {
+        let sample_rate = 44100.0;
+        let frequency = 3520.0;
+        let mut naive = Oscillator::new_with_sample_rate(sample_rate, |phase: f32| {
+            1.0 - 4.0 * (phase - 0.5).abs()
+        });
+        naive.set_frequency(frequency);
+        let mut polyblep = PolyBlepOscillator::triangle(sample_rate);
+        polyblep.set_frequency(frequency);
+
+        let naive_aliasing = aliasing_ratio_db(sample_rate, frequency, || naive.next_sample());
+        let polyblep_aliasing =
+            aliasing_ratio_db(sample_rate, frequency, || polyblep.next_sample());
+        assert!(
+            polyblep_aliasing < naive_aliasing - 10.0,
+            "naive={}dB polyblep={}dB",
+            naive_aliasing,
+            polyblep_aliasing
+        );
+    }
+}
@@ -61,3 +61,46 @@ pub fn generate_plot(file: &str, mut generator: impl FnMut() -> f32, plot_name:
         .unwrap();
     drawing_area.present().unwrap();
 }
+
+/// Render one second of `generator` and measure how much of its energy falls outside of the
+/// harmonics of `frequency`, in dB relative to the harmonic energy.
+///
+/// For a band-limited periodic signal all energy is at multiples of the fundamental, so
+/// anything else is aliasing (folded harmonics) or noise.
+pub fn aliasing_ratio_db(
+    sample_rate: f32,
+    frequency: f32,
+    mut generator: impl FnMut() -> f32,
+) -> f32 {
+    use rustfft::num_complex::Complex;
+
+    let size = sample_rate as usize;
+    let mut buffer: Vec<Complex<f32>> = (0..size)
+        .map(|i| {
+            // Blackman-Harris window, so leakage doesn't count as aliasing
+            let x = 2.0 * std::f32::consts::PI * i as f32 / size as f32;
+            let window =
+                0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos();
+            Complex::new(generator() * window, 0.0)
+        })
+        .collect();
+    let mut planner = rustfft::FftPlanner::new();
+    planner.plan_fft_forward(size).process(&mut buffer);
+
+    let bin_width = sample_rate / size as f32;
+    let harmonic_tolerance = 8.0 * bin_width;
+    let mut harmonic_power = 0.0;
+    let mut aliased_power = 0.0;
+    for (bin, value) in buffer.iter().enumerate().take(size / 2).skip(8) {
+        let bin_frequency = bin as f32 * bin_width;
+        let harmonic = (bin_frequency / frequency).round();
+        let distance = (bin_frequency - harmonic * frequency).abs();
+        if harmonic >= 1.0 && distance <= harmonic_tolerance {
+            harmonic_power += value.norm_sqr();
+        } else {
+            aliased_power += value.norm_sqr();
+        }
+    }
+
+    10.0 * (aliased_power / harmonic_power).log10()
+}
@@ -20,6 +20,9 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
 use crate::Oscillator;
 
 /// Calculate the cursor step increment between samples.
@@ -44,16 +47,80 @@ pub fn get_interpolated(cursor: f32, table: &[f32]) -> f32 {
     v1 + diff * (v2 - v1)
 }
 
+/// Build band-limited copies of a single-cycle wave-table.
+///
+/// Returns one table per octave, the first holding every harmonic the table can represent and
+/// each following table holding half the harmonics of the previous one, down to a single
+/// harmonic. Harmonics are removed by zeroing their FFT bins, so every table has the same
+/// length as the input.
+///
+/// Returns the tables along with the highest harmonic kept on each of them.
+pub fn build_mip_maps(table: &[f32]) -> (Vec<Vec<f32>>, Vec<usize>) {
+    let table_len = table.len();
+    if table_len < 4 {
+        return (vec![table.to_vec()], vec![table_len / 2]);
+    }
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(table_len);
+    let inverse = planner.plan_fft_inverse(table_len);
+    let mut spectrum: Vec<Complex<f32>> = table.iter().map(|s| Complex::new(*s, 0.0)).collect();
+    forward.process(&mut spectrum);
+
+    let mut tables = vec![];
+    let mut max_harmonics = vec![];
+    // The Nyquist bin of the table is skipped, as its phase is ambiguous
+    let mut max_harmonic = (table_len - 1) / 2;
+    loop {
+        let mut level: Vec<Complex<f32>> = spectrum
+            .iter()
+            .enumerate()
+            .map(|(bin, value)| {
+                let harmonic = bin.min(table_len - bin);
+                if harmonic <= max_harmonic {
+                    *value
+                } else {
+                    Complex::new(0.0, 0.0)
+                }
+            })
+            .collect();
+        inverse.process(&mut level);
+        tables.push(
+            level
+                .iter()
+                .map(|value| value.re / table_len as f32)
+                .collect(),
+        );
+        max_harmonics.push(max_harmonic);
+
+        if max_harmonic <= 1 {
+            break;
+        }
+        max_harmonic /= 2;
+    }
+
+    (tables, max_harmonics)
+}
+
 pub struct WaveTableOscillator {
     cursor: f32,
     cursor_step: f32,
-    table: Vec<f32>,
+    /// Mip-map levels, the first is the full-band table
+    tables: Vec<Vec<f32>>,
+    /// Highest harmonic on each of the levels
+    max_harmonics: Vec<usize>,
+    /// Level currently used for playback
+    current_level: usize,
     table_len: f32,
     sample_rate: f32,
     frequency: f32,
 }
 
 impl WaveTableOscillator {
+    /// Sample a single cycle of `oscillator` into a table of `table_len` samples & build its
+    /// band-limited mip-maps, so it doesn't alias at high frequencies.
+    ///
+    /// The frequency & sample rate are copied from the oscillator.
     pub fn from_oscillator(mut oscillator: Oscillator<f32>, table_len: usize) -> Self {
         let frequency = oscillator.get_frequency();
         let sample_rate = oscillator.sample_rate;
@@ -62,23 +129,40 @@ impl WaveTableOscillator {
 
         let table: Vec<f32> = (0..table_len).map(|_| oscillator.next_sample()).collect();
 
-        let mut result = Self::new(table);
+        let mut result = Self::new_band_limited(table);
         result.set_sample_rate(sample_rate);
         result.set_frequency(frequency);
         result
     }
 
+    /// Create an oscillator playing back `table` as is, without band-limiting.
     pub fn new(table: Vec<f32>) -> Self {
+        let table_len = table.len();
+        Self::new_with_mip_maps(vec![table], vec![table_len / 2])
+    }
+
+    /// Create an oscillator over `table`, with band-limited mip-maps. See [`build_mip_maps`].
+    pub fn new_band_limited(table: Vec<f32>) -> Self {
+        let (tables, max_harmonics) = build_mip_maps(&table);
+        Self::new_with_mip_maps(tables, max_harmonics)
+    }
+
+    fn new_with_mip_maps(tables: Vec<Vec<f32>>, max_harmonics: Vec<usize>) -> Self {
         let frequency = 440.0;
         let sample_rate = 44100.0;
-        Self {
+        let table_len = tables[0].len() as f32;
+        let mut result = Self {
             cursor: 0.0,
-            cursor_step: get_cursor_step(frequency, sample_rate, table.len() as f32),
+            cursor_step: get_cursor_step(frequency, sample_rate, table_len),
+            tables,
+            max_harmonics,
+            current_level: 0,
             sample_rate,
             frequency,
-            table_len: table.len() as f32,
-            table,
-        }
+            table_len,
+        };
+        result.update();
+        result
     }
 
     pub fn frequency(&self) -> f32 {
@@ -89,12 +173,33 @@ impl WaveTableOscillator {
         self.sample_rate
     }
 
+    /// The full-band table
     pub fn table(&self) -> &[f32] {
-        &self.table
+        &self.tables[0]
     }
 
+    /// The full-band table. Changes are only heard on the other mip-map levels after
+    /// [`WaveTableOscillator::rebuild_mip_maps`] is called.
     pub fn table_mut(&mut self) -> &mut [f32] {
-        &mut self.table
+        &mut self.tables[0]
+    }
+
+    /// Number of band-limited tables, this is 1 if the oscillator isn't band-limited
+    pub fn mip_map_levels(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// The table currently used for playback, which depends on the frequency
+    pub fn current_table(&self) -> &[f32] {
+        &self.tables[self.current_level]
+    }
+
+    /// Rebuild the band-limited mip-maps from the full-band table. This allocates.
+    pub fn rebuild_mip_maps(&mut self) {
+        let (tables, max_harmonics) = build_mip_maps(&self.tables[0]);
+        self.tables = tables;
+        self.max_harmonics = max_harmonics;
+        self.update();
     }
 
     pub fn set_sample_rate(&mut self, value: f32) {
@@ -112,7 +217,15 @@ impl WaveTableOscillator {
         let sample_rate = self.sample_rate;
         let table_len = self.table_len;
         let cursor_step = get_cursor_step(frequency, sample_rate, table_len);
-        self.cursor_step = cursor_step
+        self.cursor_step = cursor_step;
+
+        // Use the first level with no harmonics above nyquist
+        let nyquist_harmonic = (sample_rate / 2.0 / frequency.abs()) as usize;
+        self.current_level = self
+            .max_harmonics
+            .iter()
+            .position(|max_harmonic| *max_harmonic <= nyquist_harmonic)
+            .unwrap_or(self.max_harmonics.len() - 1);
     }
 
     pub fn tick(&mut self) {
@@ -133,7 +246,7 @@ impl WaveTableOscillator {
 
     pub fn get(&self) -> f32 {
         let cursor = self.cursor;
-        let table = &self.table;
+        let table = &self.tables[self.current_level];
 
         get_interpolated(cursor, table)
     }
@@ -147,7 +260,8 @@ impl WaveTableOscillator {
 
 #[cfg(test)]
 mod test {
-    use crate::test_utils::generate_plot;
+    use crate::generators;
+    use crate::test_utils::{aliasing_ratio_db, generate_plot};
 
     use super::*;
 
@@ -200,4 +314,67 @@ mod test {
             assert!((o - w).abs() < 0.01)
         }
     }
+
+    #[test]
+    fn test_mip_map_levels_halve_harmonics() {
+        let (tables, max_harmonics) = build_mip_maps(&vec![0.0; 1024]);
+        assert_eq!(max_harmonics, vec![511, 255, 127, 63, 31, 15, 7, 3, 1]);
+        assert_eq!(tables.len(), max_harmonics.len());
+        for table in tables {
+            assert_eq!(table.len(), 1024);
+        }
+    }
+
+    #[test]
+    fn test_mip_maps_keep_low_harmonics() {
+        // A band-limited sine is the same sine on every level
+        let oscillator = Oscillator::sine(44100.0);
+        let wave_table = WaveTableOscillator::from_oscillator(oscillator, 512);
+        let (tables, _) = build_mip_maps(wave_table.table());
+        for level in tables {
+            for (expected, actual) in wave_table.table().iter().zip(level.iter()) {
+                assert!((expected - actual).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_level_is_selected_by_frequency() {
+        let oscillator = Oscillator::new_with_sample_rate(44100.0, generators::saw_generator);
+        let mut wave_table = WaveTableOscillator::from_oscillator(oscillator, 2048);
+        wave_table.set_frequency(20.0);
+        assert_eq!(wave_table.current_level, 0);
+        wave_table.set_frequency(5000.0);
+        // 22050 / 5000 = 4.41, so there can be no more than 4 harmonics
+        assert_eq!(wave_table.max_harmonics[wave_table.current_level], 3);
+        wave_table.set_frequency(20000.0);
+        assert_eq!(wave_table.current_level, wave_table.mip_map_levels() - 1);
+    }
+
+    #[test]
+    fn test_band_limited_saw_aliases_less_than_naive_table() {
+        let sample_rate = 44100.0;
+        let frequency = 3520.0;
+        let mut oscillator =
+            Oscillator::new_with_sample_rate(sample_rate, generators::saw_generator);
+        oscillator.set_frequency(frequency);
+
+        let mut band_limited = WaveTableOscillator::from_oscillator(oscillator.clone(), 2048);
+        oscillator.set_frequency(sample_rate / 2048.0);
+        let mut naive =
+            WaveTableOscillator::new((0..2048).map(|_| oscillator.next_sample()).collect());
+        naive.set_sample_rate(sample_rate);
+        naive.set_frequency(frequency);
+        assert_eq!(naive.mip_map_levels(), 1);
+
+        let naive_aliasing = aliasing_ratio_db(sample_rate, frequency, || naive.next_sample());
+        let band_limited_aliasing =
+            aliasing_ratio_db(sample_rate, frequency, || band_limited.next_sample());
+        assert!(
+            band_limited_aliasing < naive_aliasing - 20.0,
+            "naive={}dB band_limited={}dB",
+            naive_aliasing,
+            band_limited_aliasing
+        );
+    }
 }
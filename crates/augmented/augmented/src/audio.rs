@@ -34,6 +34,8 @@ pub mod processor {
     #[doc(inline)]
     pub use audio_processor_bitcrusher as bitcrusher;
     #[doc(inline)]
+    pub use audio_processor_convolution as convolution;
+    #[doc(inline)]
     pub use audio_processor_dynamics as dynamics;
     #[doc(inline)]
     pub use audio_processor_file as file;
@@ -44,9 +46,13 @@ pub mod processor {
     #[doc(inline)]
     pub use audio_processor_pitch_shifter as pitch_shifter;
     #[doc(inline)]
+    pub use audio_processor_sampler as sampler;
+    #[doc(inline)]
     pub use audio_processor_time as time;
     #[doc(inline)]
     pub use audio_processor_traits::*;
     #[doc(inline)]
     pub use audio_processor_utility as utility;
+    #[doc(inline)]
+    pub use audio_processor_waveshaper as waveshaper;
 }
@@ -20,8 +20,7 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-pub use smooth_value;
-
 pub use atomic_queue;
 pub use augmented_audio_volume as volume;
+pub use augmented_smooth_value as smooth_value;
 pub use circular_data_structures;
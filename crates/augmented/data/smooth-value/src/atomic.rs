@@ -0,0 +1,163 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+use std::ops::Deref;
+use std::time::Duration;
+
+use augmented_atomics::AtomicF32;
+
+use crate::{InterpolatedValue, SmoothingStrategy};
+
+/// An [`InterpolatedValue`] which follows a target set by other threads.
+///
+/// The target lives in an `AtomicF32` behind any pointer type (`Arc`, `Shared`, a reference, ...),
+/// so a GUI thread can write it while the audio thread reads it without locks. Changes are picked
+/// up whenever the audio thread calls [`AtomicInterpolatedValue::update`], `next_sample` or
+/// `fill`.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use augmented_atomics::AtomicF32;
+/// use augmented_smooth_value::{AtomicInterpolatedValue, SmoothingStrategy};
+///
+/// let gain = Arc::new(AtomicF32::new(1.0));
+/// let mut smoothed_gain = AtomicInterpolatedValue::new(
+///     gain.clone(),
+///     44100.0,
+///     Duration::from_millis(20),
+///     SmoothingStrategy::Multiplicative,
+/// );
+///
+/// // GUI thread
+/// gain.set(0.5);
+///
+/// // Audio thread
+/// let mut block = [0.0; 64];
+/// smoothed_gain.fill(&mut block);
+/// ```
+pub struct AtomicInterpolatedValue<T: Deref<Target = AtomicF32>> {
+    target: T,
+    value: InterpolatedValue,
+}
+
+impl<T: Deref<Target = AtomicF32>> AtomicInterpolatedValue<T> {
+    /// Create a new value starting at the current target
+    pub fn new(
+        target: T,
+        sample_rate: f32,
+        smoothing_duration: Duration,
+        strategy: SmoothingStrategy,
+    ) -> Self {
+        let value = InterpolatedValue::new_with_strategy(
+            sample_rate,
+            smoothing_duration,
+            target.get(),
+            strategy,
+        );
+        Self { target, value }
+    }
+
+    /// The shared target
+    pub fn target(&self) -> &AtomicF32 {
+        &self.target
+    }
+
+    /// The underlying interpolated value
+    pub fn value(&self) -> &InterpolatedValue {
+        &self.value
+    }
+
+    /// Mutable access to the underlying interpolated value, for changing duration or strategy
+    pub fn value_mut(&mut self) -> &mut InterpolatedValue {
+        &mut self.value
+    }
+
+    /// Modify the sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.value.set_sample_rate(sample_rate);
+    }
+
+    /// Read the shared target and start moving towards it if it changed
+    pub fn update(&mut self) {
+        self.value.set(self.target.get());
+    }
+
+    /// Return the current value
+    pub fn get(&self) -> f32 {
+        self.value.get()
+    }
+
+    /// Read the target, get the current value and tick the internal state
+    pub fn next_sample(&mut self) -> f32 {
+        self.update();
+        self.value.next_sample()
+    }
+
+    /// Read the target once, then fill `output` with the next values
+    pub fn fill(&mut self, output: &mut [f32]) {
+        self.update();
+        self.value.fill(output);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use audio_processor_testing_helpers::assert_f_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_starts_at_the_target() {
+        let target = AtomicF32::new(0.3);
+        let value = AtomicInterpolatedValue::new(
+            &target,
+            100.0,
+            Duration::from_secs(1),
+            SmoothingStrategy::Linear,
+        );
+        assert_f_eq!(value.get(), 0.3);
+    }
+
+    #[test]
+    fn test_follows_a_target_set_from_another_thread() {
+        let target = Arc::new(AtomicF32::new(0.0));
+        let mut value = AtomicInterpolatedValue::new(
+            target.clone(),
+            100.0,
+            Duration::from_secs(1),
+            SmoothingStrategy::Linear,
+        );
+
+        std::thread::spawn(move || target.set(1.0)).join().unwrap();
+
+        let mut block = [0.0; 50];
+        value.fill(&mut block);
+        assert_f_eq!(block[0], 0.0);
+        assert!((value.get() - 0.5).abs() < 0.001);
+        value.fill(&mut block);
+        assert_f_eq!(value.get(), 1.0);
+        assert!(!value.value().is_smoothing());
+    }
+}
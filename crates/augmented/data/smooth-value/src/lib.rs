@@ -20,18 +20,71 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
+//! Parameter smoothing for audio processors.
+//!
+//! [`InterpolatedValue`] moves towards a target value over a time window using one of several
+//! [`SmoothingStrategy`]s. [`AtomicInterpolatedValue`] follows a target written by another thread
+//! (for example a GUI) through an `AtomicF32`, without locks.
+//!
+//! ```
+//! use std::time::Duration;
+//! use augmented_smooth_value::{InterpolatedValue, SmoothingStrategy};
+//!
+//! let mut cutoff = InterpolatedValue::new_with_strategy(
+//!     44100.0,
+//!     Duration::from_millis(50),
+//!     440.0,
+//!     SmoothingStrategy::Multiplicative,
+//! );
+//! cutoff.set(880.0);
+//!
+//! let mut block = [0.0; 64];
+//! cutoff.fill(&mut block);
+//! ```
+
 use std::time::Duration;
 
+pub use atomic::AtomicInterpolatedValue;
+
+mod atomic;
+
+/// How an [`InterpolatedValue`] moves towards its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmoothingStrategy {
+    /// Constant increment per sample, reaches the target once the duration elapses
+    Linear,
+    /// Constant ratio per sample, reaches the target once the duration elapses.
+    ///
+    /// Changes are even on a logarithmic scale, which suits frequencies and gains. Falls back to
+    /// [`SmoothingStrategy::Linear`] when moving from/to zero or across zero.
+    Multiplicative,
+    /// Fast start and slow end. The remaining distance decays to -60dB over the duration and then
+    /// the value snaps to the target
+    Exponential,
+    /// One-pole low-pass with the duration as its time-constant, so ~63% of a change happens
+    /// over the duration. The value snaps to the target once it is very close.
+    OnePole,
+}
+
+/// Residual of an [`SmoothingStrategy::Exponential`] change once the duration elapses (-60dB)
+const EXPONENTIAL_RESIDUAL: f32 = 0.001;
+/// Relative distance at which [`SmoothingStrategy::OnePole`] snaps to the target
+const ONE_POLE_THRESHOLD: f32 = 1e-5;
+
 struct InterpolationState {
     /// The start value of this change
     start: f32,
     /// The value we're moving towards
     target: f32,
-    /// The increment over current value per tick
-    tick_increment: f32,
+    /// Strategy used for this change, may differ from the configured one if it had to fall back
+    strategy: SmoothingStrategy,
+    /// Per tick increment, ratio or coefficient depending on the strategy
+    step: f32,
+    /// Ticks until the target is reached, unused for one-pole smoothing
+    remaining_samples: f32,
 }
 
-/// Wraps a certain numeric value with linear interpolation.
+/// Wraps a certain numeric value with interpolation.
 ///
 /// Whenever a value change is requested, the change will be smoothed over a time window.
 pub struct InterpolatedValue {
@@ -45,11 +98,28 @@ pub struct InterpolatedValue {
     interpolation_state: Option<InterpolationState>,
     /// The duration of interpolation
     smoothing_duration: Duration,
+    /// How the value moves towards its target
+    strategy: SmoothingStrategy,
 }
 
 impl InterpolatedValue {
-    /// Create a new interpolated value
+    /// Create a new linearly interpolated value
     pub fn new(sample_rate: f32, smoothing_duration: Duration, initial_value: f32) -> Self {
+        Self::new_with_strategy(
+            sample_rate,
+            smoothing_duration,
+            initial_value,
+            SmoothingStrategy::Linear,
+        )
+    }
+
+    /// Create a new interpolated value with a certain smoothing strategy
+    pub fn new_with_strategy(
+        sample_rate: f32,
+        smoothing_duration: Duration,
+        initial_value: f32,
+        strategy: SmoothingStrategy,
+    ) -> Self {
         let smoothing_samples =
             InterpolatedValue::calculate_smoothing_samples(sample_rate, smoothing_duration);
 
@@ -59,6 +129,7 @@ impl InterpolatedValue {
             current_value: initial_value,
             interpolation_state: None,
             smoothing_duration,
+            strategy,
         }
     }
 
@@ -80,15 +151,47 @@ impl InterpolatedValue {
         self.on_parameter_update(reset);
     }
 
+    /// The current smoothing strategy
+    pub fn strategy(&self) -> SmoothingStrategy {
+        self.strategy
+    }
+
+    /// Modify the smoothing strategy, a running transition restarts from the current value
+    pub fn set_strategy(&mut self, strategy: SmoothingStrategy) {
+        self.strategy = strategy;
+        if let Some(target) = self.interpolation_state.as_ref().map(|s| s.target) {
+            self.start_interpolation(target);
+        }
+    }
+
     /// Modify the target value
+    ///
+    /// Setting the target a transition is already moving towards doesn't restart it, so this is
+    /// safe to call every block.
     pub fn set(&mut self, target: f32) {
-        let delta = target - self.current_value;
-        let tick_increment = delta / self.smoothing_samples;
-        self.interpolation_state = Some(InterpolationState {
-            start: self.current_value,
-            target,
-            tick_increment,
-        })
+        if target.to_bits() == self.target().to_bits() {
+            return;
+        }
+        self.start_interpolation(target);
+    }
+
+    /// Jump to a value without smoothing
+    pub fn set_immediate(&mut self, value: f32) {
+        self.current_value = value;
+        self.interpolation_state = None;
+    }
+
+    /// The value this is moving towards, or the current value if it isn't moving
+    pub fn target(&self) -> f32 {
+        self.interpolation_state
+            .as_ref()
+            .map(|state| state.target)
+            .unwrap_or(self.current_value)
+    }
+
+    /// Returns true while a transition is running
+    pub fn is_smoothing(&self) -> bool {
+        self.interpolation_state.is_some()
     }
 
     /// Get the current value and tick the internal state
@@ -98,6 +201,18 @@ impl InterpolatedValue {
         value
     }
 
+    /// Fill `output` with the next values, ticking once per sample
+    pub fn fill(&mut self, output: &mut [f32]) {
+        if self.interpolation_state.is_none() {
+            output.fill(self.current_value);
+            return;
+        }
+
+        for sample in output.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
     /// Return the current value
     pub fn get(&self) -> f32 {
         self.current_value
@@ -105,12 +220,30 @@ impl InterpolatedValue {
 
     /// Interpolates current value towards the target value
     pub fn tick(&mut self) {
-        if let Some(interpolation_state) = &self.interpolation_state {
-            self.current_value += interpolation_state.tick_increment;
+        if let Some(state) = &mut self.interpolation_state {
+            state.remaining_samples -= 1.0;
+            match state.strategy {
+                // Computed from the target rather than accumulated, so long ramps don't drift
+                SmoothingStrategy::Linear => {
+                    self.current_value = state.target - state.step * state.remaining_samples
+                }
+                SmoothingStrategy::Multiplicative => self.current_value *= state.step,
+                SmoothingStrategy::Exponential | SmoothingStrategy::OnePole => {
+                    self.current_value =
+                        state.target + (self.current_value - state.target) * state.step
+                }
+            }
+
+            let finished = if state.strategy == SmoothingStrategy::OnePole {
+                (self.current_value - state.target).abs()
+                    <= ONE_POLE_THRESHOLD * state.target.abs().max(1.0)
+            } else {
+                state.remaining_samples <= 0.0
+            };
 
             // Reset internal state & don't let the value exceed the target.
-            if self.current_value >= interpolation_state.target {
-                self.current_value = interpolation_state.target;
+            if finished {
+                self.current_value = state.target;
                 self.interpolation_state = None;
             }
         }
@@ -123,8 +256,50 @@ impl InterpolatedValue {
         smoothing_duration.as_secs_f32() * sample_rate
     }
 
+    fn start_interpolation(&mut self, target: f32) {
+        if self.smoothing_samples < 1.0 {
+            self.set_immediate(target);
+            return;
+        }
+
+        let start = self.current_value;
+        let strategy = match self.strategy {
+            SmoothingStrategy::Multiplicative if !Self::can_multiply(start, target) => {
+                SmoothingStrategy::Linear
+            }
+            strategy => strategy,
+        };
+        self.interpolation_state = Some(InterpolationState {
+            start,
+            target,
+            strategy,
+            step: Self::calculate_step(strategy, start, target, self.smoothing_samples),
+            remaining_samples: self.smoothing_samples.ceil(),
+        });
+    }
+
+    /// Multiplicative ramps need both ends to be non-zero and have the same sign
+    fn can_multiply(start: f32, target: f32) -> bool {
+        start != 0.0 && target != 0.0 && start.is_sign_positive() == target.is_sign_positive()
+    }
+
+    fn calculate_step(
+        strategy: SmoothingStrategy,
+        start: f32,
+        target: f32,
+        smoothing_samples: f32,
+    ) -> f32 {
+        match strategy {
+            SmoothingStrategy::Linear => (target - start) / smoothing_samples,
+            SmoothingStrategy::Multiplicative => (target / start).powf(1.0 / smoothing_samples),
+            SmoothingStrategy::Exponential => EXPONENTIAL_RESIDUAL.powf(1.0 / smoothing_samples),
+            SmoothingStrategy::OnePole => (-1.0 / smoothing_samples).exp(),
+        }
+    }
+
     /// Update internal state when sample rate or duration changes.
     fn on_parameter_update(&mut self, reset: bool) {
+        let previous_smoothing_samples = self.smoothing_samples;
         self.smoothing_samples = InterpolatedValue::calculate_smoothing_samples(
             self.sample_rate,
             self.smoothing_duration,
@@ -133,16 +308,28 @@ impl InterpolatedValue {
         // Reset currently running interpolation
         if reset {
             if let Some(target) = self.interpolation_state.as_ref().map(|s| s.target) {
-                self.set(target);
+                self.start_interpolation(target);
+            }
+            return;
+        }
+
+        if self.smoothing_samples < 1.0 {
+            if let Some(target) = self.interpolation_state.as_ref().map(|s| s.target) {
+                self.set_immediate(target);
             }
             return;
         }
 
-        // Update currently running interpolation
+        // Update currently running interpolation, keeping how far along it is
         if let Some(state) = &mut self.interpolation_state {
-            let delta = state.target - state.start;
-            let tick_increment = delta / self.smoothing_samples;
-            state.tick_increment = tick_increment;
+            let remaining_ratio = state.remaining_samples / previous_smoothing_samples.max(1.0);
+            state.step = Self::calculate_step(
+                state.strategy,
+                state.start,
+                state.target,
+                self.smoothing_samples,
+            );
+            state.remaining_samples = (remaining_ratio * self.smoothing_samples).ceil();
         }
     }
 }
@@ -228,14 +415,146 @@ mod tests {
         // Go back to 0
         value.set(0.0);
 
+        // Tick the value another 22.05k times, the current value should be 25.
+        for _ in 0..22050 {
+            value.tick();
+        }
+        assert_approx_equals(value.get(), 25.0);
+
         // Tick the value another 22.05k times, the current value should be 0.
         for _ in 0..22050 {
             value.tick();
         }
         assert_approx_equals(value.get(), 0.0);
+        assert!(!value.is_smoothing());
+    }
+
+    #[test]
+    fn test_setting_the_same_target_does_not_restart() {
+        let mut value = InterpolatedValue::new(100.0, Duration::from_secs(1), 0.0);
+        value.set(100.0);
+        for _ in 0..50 {
+            value.tick();
+            value.set(100.0);
+        }
+        assert_approx_equals(value.get(), 50.0);
+    }
+
+    #[test]
+    fn test_multiplicative_moves_evenly_on_a_log_scale() {
+        let mut value = InterpolatedValue::new_with_strategy(
+            100.0,
+            Duration::from_secs(1),
+            100.0,
+            SmoothingStrategy::Multiplicative,
+        );
+        value.set(400.0);
+        for _ in 0..50 {
+            value.tick();
+        }
+        assert!((value.get() - 200.0).abs() < 0.01);
+        for _ in 0..50 {
+            value.tick();
+        }
+        assert_f_eq!(value.get(), 400.0);
+        assert!(!value.is_smoothing());
+    }
+
+    #[test]
+    fn test_multiplicative_falls_back_to_linear_from_zero() {
+        let mut value = InterpolatedValue::new_with_strategy(
+            100.0,
+            Duration::from_secs(1),
+            0.0,
+            SmoothingStrategy::Multiplicative,
+        );
+        value.set(1.0);
+        for _ in 0..50 {
+            value.tick();
+        }
+        assert_approx_equals(value.get(), 0.5);
+    }
+
+    #[test]
+    fn test_exponential_settles_within_the_duration() {
+        let mut value = InterpolatedValue::new_with_strategy(
+            100.0,
+            Duration::from_secs(1),
+            0.0,
+            SmoothingStrategy::Exponential,
+        );
+        value.set(1.0);
+        let mut previous = value.get();
+        for i in 0..100 {
+            let current = value.next_sample();
+            assert!(current >= previous);
+            if i == 50 {
+                // halfway through, sqrt(0.001) of the change is left
+                assert!((value.get() - (1.0 - 0.001_f32.sqrt())).abs() < 0.01);
+            }
+            previous = current;
+        }
+        assert_f_eq!(value.get(), 1.0);
+        assert!(!value.is_smoothing());
+    }
+
+    #[test]
+    fn test_one_pole_uses_the_duration_as_time_constant() {
+        let mut value = InterpolatedValue::new_with_strategy(
+            1000.0,
+            Duration::from_millis(100),
+            0.0,
+            SmoothingStrategy::OnePole,
+        );
+        value.set(1.0);
+        for _ in 0..100 {
+            value.tick();
+        }
+        assert!((value.get() - (1.0 - (-1.0_f32).exp())).abs() < 0.001);
+        for _ in 0..2000 {
+            value.tick();
+        }
+        assert_f_eq!(value.get(), 1.0);
+        assert!(!value.is_smoothing());
+    }
+
+    #[test]
+    fn test_fill_matches_next_sample() {
+        for strategy in [
+            SmoothingStrategy::Linear,
+            SmoothingStrategy::Multiplicative,
+            SmoothingStrategy::Exponential,
+            SmoothingStrategy::OnePole,
+        ] {
+            let mut value =
+                InterpolatedValue::new_with_strategy(100.0, Duration::from_secs(1), 1.0, strategy);
+            let mut reference =
+                InterpolatedValue::new_with_strategy(100.0, Duration::from_secs(1), 1.0, strategy);
+            value.set(10.0);
+            reference.set(10.0);
+
+            let mut block = [0.0; 150];
+            value.fill(&mut block);
+            for sample in block {
+                assert_f_eq!(sample, reference.next_sample());
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_duration_jumps_to_target() {
+        let mut value = InterpolatedValue::new(44100.0, Duration::from_secs(0), 0.0);
+        value.set(3.0);
+        assert_f_eq!(value.get(), 3.0);
+        assert!(!value.is_smoothing());
     }
 
     fn assert_approx_equals(value: f32, target: f32) {
-        assert!(value - target < f32::EPSILON);
+        assert!(
+            (value - target).abs() < 0.001,
+            "{} is not close to {}",
+            value,
+            target
+        );
     }
 }
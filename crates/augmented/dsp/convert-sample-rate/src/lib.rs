@@ -20,8 +20,22 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
+//! Sample-rate conversion.
+//!
+//! * [`convert_sample_rate`] converts a whole slice at once, with high quality, delegating to
+//!   `samplerate`. It allocates so it shouldn't be used on the audio thread.
+//! * [`StreamingResampler`] converts block by block with selectable quality and a ratio which may
+//!   change over time, without allocating.
+
 use samplerate::ConverterType;
 
+pub use streaming::{ResampleResult, ResamplerQuality, StreamingResampler};
+
+pub mod streaming;
+#[cfg(test)]
+mod test_allocator;
+
+/// Convert `input` at `input_rate` into `output` at `output_rate`
 pub fn convert_sample_rate(input_rate: f32, input: &[f32], output_rate: f32, output: &mut [f32]) {
     if (input_rate - output_rate).abs() < f32::EPSILON {
         for (s, d) in input.iter().zip(output.iter_mut()) {
@@ -0,0 +1,557 @@
+// Augmented Audio: Audio libraries and applications
+// Copyright (c) 2022 Pedro Tacla Yamada
+//
+// The MIT License (MIT)
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+//! Streaming sample-rate conversion.
+//!
+//! [`StreamingResampler`] converts audio block by block, keeping its state across calls, so it can
+//! run on the audio thread. The ratio may change between blocks, which allows varispeed playback or
+//! bridging devices running at different (and drifting) rates.
+//!
+//! Nothing allocates after construction or [`StreamingResampler::prepare`].
+
+use std::f64::consts::PI;
+
+/// Interpolation used by [`StreamingResampler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// 2-point linear interpolation, cheapest and the most aliasing
+    Linear,
+    /// 4-point, 3rd-order Hermite interpolation
+    Cubic,
+    /// Blackman windowed-sinc with an anti-aliasing cutoff that follows the ratio
+    Sinc,
+}
+
+impl ResamplerQuality {
+    fn num_taps(&self) -> usize {
+        match self {
+            ResamplerQuality::Linear => 2,
+            ResamplerQuality::Cubic => 4,
+            ResamplerQuality::Sinc => SINC_TAPS,
+        }
+    }
+}
+
+const SINC_TAPS: usize = 32;
+/// Number of fractional positions the sinc kernel is tabulated for, values in between are
+/// linearly interpolated
+const SINC_PHASES: usize = 256;
+/// Keeps the transition band of the sinc filter under the nyquist frequency
+const SINC_ROLLOFF: f64 = 0.95;
+/// The downsampling ratio used for the sinc cutoff is rounded down to multiples of
+/// `1 / SINC_CUTOFF_STEPS`, so small ratio changes don't recompute the kernel
+const SINC_CUTOFF_STEPS: f64 = 128.0;
+
+fn is_valid_ratio(ratio: f64) -> bool {
+    ratio.is_finite() && ratio > 0.0
+}
+
+/// Frames consumed and produced by a [`StreamingResampler::process`] call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResampleResult {
+    pub input_frames: usize,
+    pub output_frames: usize,
+}
+
+/// Streaming resampler for any number of channels.
+///
+/// The ratio is `output_rate / input_rate`, so playing back at half speed means a ratio of `2.0`.
+///
+/// `process` reads as much input as is needed to fill the output, and stops early if input runs
+/// out. `required_input_frames` tells how much input is needed to produce a number of frames.
+pub struct StreamingResampler {
+    quality: ResamplerQuality,
+    ratio: f64,
+    /// Position of the next output frame past the oldest frame in the interpolation window, it is
+    /// always in `[0, 1)` when an output frame is computed
+    fraction: f64,
+    write_position: usize,
+    /// Per channel history, each holds two copies of the window so it is always contiguous
+    history: Vec<Vec<f32>>,
+    sinc_table: Vec<f32>,
+    sinc_cutoff: f64,
+}
+
+impl StreamingResampler {
+    /// # Panics
+    /// If `ratio` isn't a finite number above zero
+    pub fn new(quality: ResamplerQuality, num_channels: usize, ratio: f64) -> Self {
+        assert!(is_valid_ratio(ratio), "Invalid resampling ratio {}", ratio);
+        let mut resampler = Self {
+            quality,
+            ratio,
+            fraction: 1.0,
+            write_position: 0,
+            history: Vec::new(),
+            sinc_table: vec![0.0; (SINC_PHASES + 1) * SINC_TAPS],
+            sinc_cutoff: 0.0,
+        };
+        resampler.prepare(num_channels);
+        resampler.update_sinc_table();
+        resampler
+    }
+
+    /// Create a resampler converting from `input_rate` to `output_rate`
+    pub fn from_rates(
+        quality: ResamplerQuality,
+        num_channels: usize,
+        input_rate: f32,
+        output_rate: f32,
+    ) -> Self {
+        Self::new(
+            quality,
+            num_channels,
+            output_rate as f64 / input_rate as f64,
+        )
+    }
+
+    /// Allocate state for `num_channels` and reset it. This is the only method that allocates.
+    pub fn prepare(&mut self, num_channels: usize) {
+        self.history = (0..num_channels)
+            .map(|_| vec![0.0; SINC_TAPS * 2])
+            .collect();
+        self.reset();
+    }
+
+    /// Clear the history, as if the resampler was just created
+    pub fn reset(&mut self) {
+        for channel in self.history.iter_mut() {
+            channel.fill(0.0);
+        }
+        self.fraction = 1.0;
+        self.write_position = 0;
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    /// Change the interpolation, this resets the history
+    pub fn set_quality(&mut self, quality: ResamplerQuality) {
+        self.quality = quality;
+        self.reset();
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Change the ratio, takes effect from the next output frame. Ratios that aren't a finite
+    /// number above zero are ignored and `false` is returned.
+    ///
+    /// With `Sinc` quality, a downsampling ratio that moves the cutoff to another
+    /// `1 / 128` step recomputes the filter kernel. That doesn't allocate, but evaluates around
+    /// 8000 `sin`/`cos` pairs (32 taps for 257 phases), so continuously sweeping the ratio on the
+    /// audio thread will spend time there whenever a step is crossed.
+    pub fn set_ratio(&mut self, ratio: f64) -> bool {
+        if !is_valid_ratio(ratio) {
+            return false;
+        }
+        self.ratio = ratio;
+        self.update_sinc_table();
+        true
+    }
+
+    /// Same as `set_ratio`, from a pair of sample rates
+    pub fn set_rates(&mut self, input_rate: f32, output_rate: f32) -> bool {
+        self.set_ratio(output_rate as f64 / input_rate as f64)
+    }
+
+    /// Delay introduced by interpolation, in input frames
+    pub fn latency(&self) -> usize {
+        self.quality.num_taps() / 2
+    }
+
+    /// Number of input frames the next `process` call needs to produce `output_frames`
+    pub fn required_input_frames(&self, output_frames: usize) -> usize {
+        if output_frames == 0 {
+            return 0;
+        }
+        let step = 1.0 / self.ratio;
+        (self.fraction + (output_frames - 1) as f64 * step).floor() as usize
+    }
+
+    /// Resample `input` channels into `output` channels.
+    ///
+    /// Stops when either the output is full or the input is exhausted, returning how many frames
+    /// were read and written.
+    pub fn process<I: AsRef<[f32]>, O: AsMut<[f32]>>(
+        &mut self,
+        input: &[I],
+        output: &mut [O],
+    ) -> ResampleResult {
+        let num_channels = self.history.len().min(input.len()).min(output.len());
+        let input_frames = input[..num_channels]
+            .iter()
+            .map(|channel| channel.as_ref().len())
+            .min()
+            .unwrap_or(0);
+        let output_frames = output[..num_channels]
+            .iter_mut()
+            .map(|channel| channel.as_mut().len())
+            .min()
+            .unwrap_or(0);
+
+        let step = 1.0 / self.ratio;
+        let taps = self.quality.num_taps();
+        let mut input_index = 0;
+        let mut output_index = 0;
+
+        while output_index < output_frames {
+            while self.fraction >= 1.0 {
+                if input_index >= input_frames {
+                    return ResampleResult {
+                        input_frames: input_index,
+                        output_frames: output_index,
+                    };
+                }
+                for (history, channel) in self.history.iter_mut().zip(input) {
+                    let sample = channel.as_ref()[input_index];
+                    history[self.write_position] = sample;
+                    history[self.write_position + taps] = sample;
+                }
+                self.write_position = (self.write_position + 1) % taps;
+                input_index += 1;
+                self.fraction -= 1.0;
+            }
+
+            for (history, channel) in self.history.iter().zip(output.iter_mut()) {
+                let window = &history[self.write_position..self.write_position + taps];
+                channel.as_mut()[output_index] = self.interpolate(window, self.fraction as f32);
+            }
+            output_index += 1;
+            self.fraction += step;
+        }
+
+        ResampleResult {
+            input_frames: input_index,
+            output_frames: output_index,
+        }
+    }
+
+    /// Single channel version of `process`
+    pub fn process_mono(&mut self, input: &[f32], output: &mut [f32]) -> ResampleResult {
+        self.process(&[input], &mut [output])
+    }
+
+    /// Interpolate between the two middle samples of `window`
+    fn interpolate(&self, window: &[f32], fraction: f32) -> f32 {
+        match self.quality {
+            ResamplerQuality::Linear => window[0] + (window[1] - window[0]) * fraction,
+            ResamplerQuality::Cubic => {
+                let (x0, x1, x2, x3) = (window[0], window[1], window[2], window[3]);
+                let c1 = 0.5 * (x2 - x0);
+                let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+                let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+                ((c3 * fraction + c2) * fraction + c1) * fraction + x1
+            }
+            ResamplerQuality::Sinc => {
+                let position = fraction * SINC_PHASES as f32;
+                let phase = (position as usize).min(SINC_PHASES - 1);
+                let t = position - phase as f32;
+                let row = &self.sinc_table[phase * SINC_TAPS..(phase + 2) * SINC_TAPS];
+                let (current, next) = row.split_at(SINC_TAPS);
+                window
+                    .iter()
+                    .zip(current.iter().zip(next))
+                    .map(|(sample, (a, b))| sample * (a + (b - a) * t))
+                    .sum()
+            }
+        }
+    }
+
+    /// Tabulate the windowed-sinc for each fractional position. The cutoff is lowered when
+    /// downsampling so content above the new nyquist frequency is filtered out.
+    fn update_sinc_table(&mut self) {
+        let cutoff = sinc_cutoff(self.ratio);
+        if (cutoff - self.sinc_cutoff).abs() < 1e-6 {
+            return;
+        }
+        self.sinc_cutoff = cutoff;
+
+        let center = (SINC_TAPS / 2 - 1) as f64;
+        let half_width = SINC_TAPS as f64 / 2.0;
+        for phase in 0..=SINC_PHASES {
+            let fraction = phase as f64 / SINC_PHASES as f64;
+            let row = &mut self.sinc_table[phase * SINC_TAPS..(phase + 1) * SINC_TAPS];
+            for (tap, value) in row.iter_mut().enumerate() {
+                let x = tap as f64 - center - fraction;
+                let sinc = if x.abs() < 1e-9 {
+                    1.0
+                } else {
+                    (PI * cutoff * x).sin() / (PI * cutoff * x)
+                };
+                let window = 0.42
+                    + 0.5 * (PI * x / half_width).cos()
+                    + 0.08 * (2.0 * PI * x / half_width).cos();
+                *value = (cutoff * sinc * window) as f32;
+            }
+
+            // Unity gain at DC
+            let sum: f32 = row.iter().sum();
+            for value in row.iter_mut() {
+                *value /= sum;
+            }
+        }
+    }
+}
+
+/// Anti-aliasing cutoff for a ratio, relative to the input nyquist frequency. It's rounded down so
+/// the transition band stays under the output nyquist frequency.
+fn sinc_cutoff(ratio: f64) -> f64 {
+    let steps = (ratio.min(1.0) * SINC_CUTOFF_STEPS).floor().max(1.0);
+    steps / SINC_CUTOFF_STEPS * SINC_ROLLOFF
+}
+
+#[cfg(test)]
+mod test {
+    use assert_no_alloc::assert_no_alloc;
+
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: f64, time: f64) -> f32 {
+        (2.0 * PI * frequency * time / sample_rate).sin() as f32
+    }
+
+    fn sine_buffer(frequency: f64, sample_rate: f64, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| sine(frequency, sample_rate, i as f64))
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_ratio_of_one_delays_by_latency() {
+        for quality in [
+            ResamplerQuality::Linear,
+            ResamplerQuality::Cubic,
+            ResamplerQuality::Sinc,
+        ] {
+            let mut resampler = StreamingResampler::new(quality, 1, 1.0);
+            let input = sine_buffer(440.0, 44100.0, 512);
+            let mut output = vec![0.0; 512];
+            let result = resampler.process_mono(&input, &mut output);
+            assert_eq!(result.input_frames, 512);
+            assert_eq!(result.output_frames, 512);
+
+            let latency = resampler.latency();
+            for i in latency..512 {
+                assert!(
+                    (output[i] - input[i - latency]).abs() < 0.002,
+                    "{:?} {} {} {}",
+                    quality,
+                    i,
+                    output[i],
+                    input[i - latency]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_converts_a_sine_between_rates() {
+        let input_rate = 44100.0;
+        let output_rate = 48000.0;
+        for (quality, tolerance) in [
+            (ResamplerQuality::Linear, 0.005),
+            (ResamplerQuality::Cubic, 0.001),
+            (ResamplerQuality::Sinc, 0.002),
+        ] {
+            let mut resampler = StreamingResampler::from_rates(quality, 1, input_rate, output_rate);
+            let input = sine_buffer(1000.0, input_rate as f64, 4410);
+            let mut output = vec![0.0; 4800];
+            let result = resampler.process_mono(&input, &mut output);
+            assert!(result.output_frames >= 4790);
+
+            // output frame `j` is the input at `j / ratio - latency`
+            let step = input_rate as f64 / output_rate as f64;
+            for (j, sample) in output[..result.output_frames].iter().enumerate().skip(100) {
+                let time = j as f64 * step - resampler.latency() as f64;
+                let expected = sine(1000.0, input_rate as f64, time);
+                assert!(
+                    (sample - expected).abs() < tolerance,
+                    "{:?} {} {} {}",
+                    quality,
+                    j,
+                    sample,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_splitting_is_seamless() {
+        let input = sine_buffer(3000.0, 44100.0, 8192);
+        let ratio = 0.77;
+
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 1, ratio);
+        let mut expected = vec![0.0; 6000];
+        resampler.process_mono(&input, &mut expected);
+
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 1, ratio);
+        let mut output = vec![0.0; 6000];
+        let mut input_position = 0;
+        let mut output_position = 0;
+        for block_size in [1, 7, 64, 333, 512, 13].iter().cycle() {
+            if output_position >= output.len() {
+                break;
+            }
+            let output_end = (output_position + block_size).min(output.len());
+            let required = resampler.required_input_frames(output_end - output_position);
+            let result = resampler.process_mono(
+                &input[input_position..input_position + required],
+                &mut output[output_position..output_end],
+            );
+            assert_eq!(result.input_frames, required);
+            assert_eq!(result.output_frames, output_end - output_position);
+            input_position += result.input_frames;
+            output_position += result.output_frames;
+        }
+
+        for (sample, expected) in output.iter().zip(expected.iter()) {
+            assert!((sample - expected).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_stops_when_input_runs_out() {
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Cubic, 1, 2.0);
+        let input = [1.0; 10];
+        let mut output = [0.0; 100];
+        let result = resampler.process_mono(&input, &mut output);
+        assert_eq!(result.input_frames, 10);
+        assert_eq!(result.output_frames, 20);
+    }
+
+    #[test]
+    fn test_downsampling_filters_content_above_nyquist() {
+        // 18kHz is above the 12kHz nyquist frequency of the output
+        let input = sine_buffer(18000.0, 48000.0, 48000);
+        let mut output = vec![0.0; 24000];
+
+        let mut resampler =
+            StreamingResampler::from_rates(ResamplerQuality::Sinc, 1, 48000.0, 24000.0);
+        let result = resampler.process_mono(&input, &mut output);
+        let sinc_rms = rms(&output[100..result.output_frames]);
+
+        let mut resampler =
+            StreamingResampler::from_rates(ResamplerQuality::Linear, 1, 48000.0, 24000.0);
+        let result = resampler.process_mono(&input, &mut output);
+        let linear_rms = rms(&output[100..result.output_frames]);
+
+        assert!(sinc_rms < 0.01, "{}", sinc_rms);
+        assert!(linear_rms > 0.1, "{}", linear_rms);
+    }
+
+    #[test]
+    fn test_time_varying_ratio_is_continuous() {
+        let input = sine_buffer(200.0, 44100.0, 44100);
+        let mut output = vec![0.0; 64];
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Cubic, 1, 1.0);
+        let mut input_position = 0;
+        let mut previous = 0.0;
+        for block in 0..200 {
+            resampler.set_ratio(1.0 + 0.5 * (block as f64 / 20.0).sin());
+            let required = resampler.required_input_frames(output.len());
+            let result = resampler.process_mono(
+                &input[input_position..input_position + required],
+                &mut output,
+            );
+            assert_eq!(result.output_frames, output.len());
+            input_position += result.input_frames;
+
+            for sample in output.iter() {
+                // a 200Hz sine moves at most ~0.03 per input frame, and at most 2 input frames
+                // are read per output frame
+                assert!((sample - previous).abs() < 0.06);
+                previous = *sample;
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_ratios_are_rejected() {
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 1, 1.5);
+        for ratio in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(!resampler.set_ratio(ratio));
+            assert_eq!(resampler.ratio(), 1.5);
+        }
+        assert!(!resampler.set_rates(0.0, 44100.0));
+        assert!(resampler.set_ratio(0.5));
+        assert_eq!(resampler.ratio(), 0.5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_new_panics_on_invalid_ratio() {
+        StreamingResampler::new(ResamplerQuality::Linear, 1, 0.0);
+    }
+
+    #[test]
+    fn test_sinc_cutoff_is_quantised() {
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 1, 0.5);
+        let table = resampler.sinc_table.clone();
+        resampler.set_ratio(0.5 + 0.5 / SINC_CUTOFF_STEPS);
+        assert_eq!(resampler.sinc_cutoff, 0.5 * SINC_ROLLOFF);
+        assert_eq!(resampler.sinc_table, table);
+
+        resampler.set_ratio(0.5 + 1.0 / SINC_CUTOFF_STEPS);
+        assert!(resampler.sinc_cutoff > 0.5 * SINC_ROLLOFF);
+        assert_ne!(resampler.sinc_table, table);
+
+        resampler.set_ratio(0.001);
+        assert_eq!(resampler.sinc_cutoff, SINC_ROLLOFF / SINC_CUTOFF_STEPS);
+    }
+
+    #[test]
+    fn test_multiple_channels_are_independent() {
+        let left = sine_buffer(440.0, 44100.0, 1024);
+        let right: Vec<f32> = left.iter().map(|s| -s).collect();
+        let mut output = vec![vec![0.0; 1000], vec![0.0; 1000]];
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 2, 0.9);
+        resampler.process(&[&left, &right], &mut output);
+        for (l, r) in output[0].iter().zip(output[1].iter()) {
+            assert!((l + r).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_process_does_not_allocate() {
+        let input = vec![vec![0.5; 512]; 2];
+        let mut output = vec![vec![0.0; 512]; 2];
+        let mut resampler = StreamingResampler::new(ResamplerQuality::Sinc, 2, 1.0);
+        assert_no_alloc(|| {
+            resampler.set_ratio(0.5);
+            resampler.process(&input, &mut output);
+            resampler.set_ratio(1.3);
+            resampler.process(&input, &mut output);
+        });
+    }
+}
@@ -148,6 +148,46 @@ pub fn spawn_audio_drawer(mut samples: AudioBuffer<f32>) -> PathRendererHandle {
     }
 }
 
+/// Draw a waveform from `(min, max)` peaks, such as one pair per pixel column out of the looper's
+/// sample library peak cache. Unlike [`spawn_audio_drawer`] this doesn't need the samples, so it
+/// can draw synchronously.
+pub fn draw_peaks(canvas: &mut Canvas, peaks: &[(f32, f32)], size: (f32, f32)) {
+    if peaks.is_empty() {
+        return;
+    }
+
+    let mut paint = Paint::new(
+        Color4f::new(8.0 / 255.0, 178.0 / 255.0, 227.0 / 255.0, 1.0),
+        None,
+    );
+    paint.set_anti_alias(true);
+
+    let path = build_peaks_path(peaks);
+    canvas.save();
+    canvas.set_matrix(&M44::scale(size.0 as scalar, size.1 as scalar, 1.0));
+    canvas.draw_path(&path, &paint);
+    canvas.restore();
+}
+
+/// Closed path in a `1.0 x 1.0` space, along the maximums & back along the minimums
+fn build_peaks_path(peaks: &[(f32, f32)]) -> Path {
+    let mut path = Path::new();
+    let width = peaks.len() as f32;
+    let y = |sample: f32| 0.5 + sample.clamp(-1.0, 1.0) * 0.5;
+
+    path.move_to((0.0, y(peaks[0].1)));
+    for (i, (_, max)) in peaks.iter().enumerate() {
+        path.line_to(((i as f32 + 0.5) / width, y(*max)));
+    }
+    for (i, (min, _)) in peaks.iter().enumerate().rev() {
+        path.line_to(((i as f32 + 0.5) / width, y(*min)));
+    }
+    path.line_to((0.0, y(peaks[0].0)));
+    path.close();
+
+    path
+}
+
 pub struct DrawState {
     previous_point: (f32, f32),
 }